@@ -1305,6 +1305,11 @@ def external_crates_repository(name, cargo_lockfile, lockfile, sanitizers_enable
             ),
             "trust-dns-resolver": crate.spec(
                 version = "^0.22.0",
+                features = [
+                    "dns-over-https-rustls",
+                    "dns-over-rustls",
+                    "webpki-roots",
+                ],
             ),
             "turmoil": crate.spec(
                 version = "^0.6",
@@ -2,23 +2,77 @@ use std::time::Duration;
 
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
+use certificate_orchestrator_interface as ifc;
 use instant_acme::{
     Account, Authorization, Challenge, ChallengeType, Identifier, NewOrder, OrderStatus,
 };
 use mockall::automock;
-use rcgen::{Certificate, CertificateParams, DistinguishedName};
+use rcgen::{
+    Certificate, CertificateParams, DistinguishedName, DnType, KeyPair, PKCS_ECDSA_P256_SHA256,
+    PKCS_RSA_SHA256,
+};
+use rsa::{pkcs8::EncodePrivateKey, RsaPrivateKey};
+use serde::{Deserialize, Serialize};
 use tokio::time::sleep;
 
+pub const RATE_LIMITED_PROBLEM: &str = "urn:ietf:params:acme:error:rateLimited";
+pub const CAA_PROBLEM: &str = "urn:ietf:params:acme:error:caa";
+pub const UNAUTHORIZED_PROBLEM: &str = "urn:ietf:params:acme:error:unauthorized";
+pub const DNS_PROBLEM: &str = "urn:ietf:params:acme:error:dns";
+pub const CONNECTION_PROBLEM: &str = "urn:ietf:params:acme:error:connection";
+pub const BAD_NONCE_PROBLEM: &str = "urn:ietf:params:acme:error:badNonce";
+pub const SERVER_INTERNAL_PROBLEM: &str = "urn:ietf:params:acme:error:serverInternal";
+
+// Whether the error was caused by an ACME problem document of the given type
+pub fn has_problem(err: &Error, problem_type: &str) -> bool {
+    err.chain()
+        .any(|err| match err.downcast_ref::<instant_acme::Error>() {
+            Some(instant_acme::Error::Api(problem)) => {
+                format!("{problem:?}").contains(problem_type)
+            }
+            _ => false,
+        })
+}
+
+// Class of an error, e.g. to tell being rate-limited apart from being misconfigured
+pub fn error_class(err: &Error) -> &'static str {
+    for (problem_type, class) in [
+        (RATE_LIMITED_PROBLEM, "rate-limited"),
+        (UNAUTHORIZED_PROBLEM, "unauthorized"),
+        (DNS_PROBLEM, "dns"),
+        (CONNECTION_PROBLEM, "connection"),
+        (BAD_NONCE_PROBLEM, "bad-nonce"),
+        (SERVER_INTERNAL_PROBLEM, "server-internal"),
+    ] {
+        if has_problem(err, problem_type) {
+            return class;
+        }
+    }
+
+    // The ACME provider could not be reached at all
+    if err.chain().any(|err| {
+        matches!(
+            err.downcast_ref::<instant_acme::Error>(),
+            Some(instant_acme::Error::Http(_))
+        )
+    }) {
+        return "connection";
+    }
+
+    "other"
+}
+
 #[automock]
 #[async_trait]
 pub trait Order: Sync + Send {
-    async fn order(&self, name: &str) -> Result<String, Error>;
+    // Returns the dns challenge key authorization for each of the names
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error>;
 }
 
 #[automock]
 #[async_trait]
 pub trait Ready: Sync + Send {
-    async fn ready(&self, name: &str) -> Result<(), Error>;
+    async fn ready(&self, names: &[String]) -> Result<(), Error>;
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -26,14 +80,79 @@ pub enum FinalizeError {
     #[error("order not ready: {0}")]
     OrderNotReady(String),
 
+    #[error("order invalid")]
+    OrderInvalid,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+// Key algorithm of issued certificates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    #[default]
+    EcdsaP256,
+    Rsa2048,
+    Rsa4096,
+}
+
+impl From<ifc::KeyType> for KeyType {
+    fn from(t: ifc::KeyType) -> Self {
+        match t {
+            ifc::KeyType::EcdsaP256 => Self::EcdsaP256,
+            ifc::KeyType::Rsa2048 => Self::Rsa2048,
+            ifc::KeyType::Rsa4096 => Self::Rsa4096,
+        }
+    }
+}
+
+impl From<KeyType> for ifc::KeyType {
+    fn from(t: KeyType) -> Self {
+        match t {
+            KeyType::EcdsaP256 => Self::EcdsaP256,
+            KeyType::Rsa2048 => Self::Rsa2048,
+            KeyType::Rsa4096 => Self::Rsa4096,
+        }
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait Finalize: Sync + Send {
-    async fn finalize(&self, name: &str) -> Result<(String, String), FinalizeError>;
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: KeyType,
+    ) -> Result<(String, String), FinalizeError>;
+}
+
+// Revocation reason codes (RFC 5280, section 5.3.1) accepted by ACME providers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RevocationReason {
+    #[default]
+    Unspecified,
+    KeyCompromise,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl RevocationReason {
+    pub fn code(&self) -> u8 {
+        match self {
+            Self::Unspecified => 0,
+            Self::KeyCompromise => 1,
+            Self::Superseded => 4,
+            Self::CessationOfOperation => 5,
+        }
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait Revoke: Sync + Send {
+    async fn revoke(&self, cert_chain_pem: &[u8], reason: RevocationReason) -> Result<(), Error>;
 }
 
 #[derive(Clone)]
@@ -47,14 +166,18 @@ impl Acme {
     }
 }
 
+fn identifiers(names: &[String]) -> Vec<Identifier> {
+    names.iter().cloned().map(Identifier::Dns).collect()
+}
+
 #[async_trait]
 impl Order for Acme {
-    async fn order(&self, name: &str) -> Result<String, Error> {
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error> {
         // Get Order
         let mut order = self
             .account
             .new_order(&NewOrder {
-                identifiers: &[Identifier::Dns(name.to_string())],
+                identifiers: &identifiers(names),
             })
             .await
             .context("failed to create new order")?;
@@ -64,22 +187,32 @@ impl Order for Acme {
             .await
             .context("failed to retrieve order authorizations")?;
 
-        // Get Challenge Key
-        let challenge = get_dns_challenge(authorizations).context("failed to get dns challenge")?;
-        let key_auth = order.key_authorization(&challenge).dns_value();
+        // Get Challenge Keys
+        let challenges =
+            get_dns_challenges(authorizations).context("failed to get dns challenges")?;
 
-        return Ok(key_auth);
+        let key_auths = challenges
+            .iter()
+            .map(|(name, challenge)| {
+                (
+                    name.to_owned(),
+                    order.key_authorization(challenge).dns_value(),
+                )
+            })
+            .collect();
+
+        return Ok(key_auths);
     }
 }
 
 #[async_trait]
 impl Ready for Acme {
-    async fn ready(&self, name: &str) -> Result<(), Error> {
+    async fn ready(&self, names: &[String]) -> Result<(), Error> {
         // Get Order
         let mut order = self
             .account
             .new_order(&NewOrder {
-                identifiers: &[Identifier::Dns(name.to_string())],
+                identifiers: &identifiers(names),
             })
             .await
             .context("failed to create new order")?;
@@ -89,13 +222,16 @@ impl Ready for Acme {
             .await
             .context("failed to retrieve order authorizations")?;
 
-        // Set Challenge Ready
-        let challenge = get_dns_challenge(authorizations).context("failed to get dns challenge")?;
+        // Set Challenges Ready
+        let challenges =
+            get_dns_challenges(authorizations).context("failed to get dns challenges")?;
 
-        order
-            .set_challenge_ready(&challenge.url)
-            .await
-            .context("failed to set challenge ready")?;
+        for (_, challenge) in challenges {
+            order
+                .set_challenge_ready(&challenge.url)
+                .await
+                .context("failed to set challenge ready")?;
+        }
 
         Ok(())
     }
@@ -103,12 +239,16 @@ impl Ready for Acme {
 
 #[async_trait]
 impl Finalize for Acme {
-    async fn finalize(&self, name: &str) -> Result<(String, String), FinalizeError> {
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: KeyType,
+    ) -> Result<(String, String), FinalizeError> {
         // Get Order
         let mut order = self
             .account
             .new_order(&NewOrder {
-                identifiers: &[Identifier::Dns(name.to_string())],
+                identifiers: &identifiers(names),
             })
             .await
             .context("failed to create new order")?;
@@ -118,16 +258,35 @@ impl Finalize for Acme {
             .await
             .context("failed to refresh order state")?;
 
+        if state.status == OrderStatus::Invalid {
+            return Err(FinalizeError::OrderInvalid);
+        }
+
         if state.status != OrderStatus::Ready {
             return Err(FinalizeError::OrderNotReady(format!("{:?}", state.status)));
         }
 
-        let cert = Certificate::from_params({
-            let mut params = CertificateParams::new(vec![name.to_string()]);
-            params.distinguished_name = DistinguishedName::new();
-            params
-        })
-        .context("failed to generate certificate")?;
+        let mut params = CertificateParams::new(names.to_vec());
+        params.distinguished_name = DistinguishedName::new();
+
+        // The first name is the primary one, which the syncer matches against the common name
+        params
+            .distinguished_name
+            .push(DnType::CommonName, names[0].to_owned());
+
+        match key_type {
+            KeyType::EcdsaP256 => params.alg = &PKCS_ECDSA_P256_SHA256,
+            KeyType::Rsa2048 => {
+                params.alg = &PKCS_RSA_SHA256;
+                params.key_pair = Some(generate_rsa_key_pair(2048).await?);
+            }
+            KeyType::Rsa4096 => {
+                params.alg = &PKCS_RSA_SHA256;
+                params.key_pair = Some(generate_rsa_key_pair(4096).await?);
+            }
+        }
+
+        let cert = Certificate::from_params(params).context("failed to generate certificate")?;
 
         let csr = cert
             .serialize_request_der()
@@ -162,16 +321,41 @@ impl Finalize for Acme {
     }
 }
 
-fn get_dns_challenge(authorizations: Vec<Authorization>) -> Result<Challenge, Error> {
-    for authorization in authorizations {
-        for challenge in authorization.challenges {
-            if challenge.r#type != ChallengeType::Dns01 {
-                continue;
-            }
+// rcgen cannot generate RSA keys by itself
+async fn generate_rsa_key_pair(bits: usize) -> Result<KeyPair, Error> {
+    // Generating large RSA keys takes a while, so keep it off the async workers
+    let der = tokio::task::spawn_blocking(move || -> Result<Vec<u8>, Error> {
+        let key = RsaPrivateKey::new(&mut rand::thread_rng(), bits)
+            .context("failed to generate rsa key")?;
 
-            return Ok(challenge);
-        }
-    }
+        Ok(key
+            .to_pkcs8_der()
+            .context("failed to encode rsa key")?
+            .as_bytes()
+            .to_vec())
+    })
+    .await
+    .context("rsa key generation task failed")??;
+
+    KeyPair::from_der(&der).context("failed to load rsa key")
+}
 
-    Err(anyhow!("failed to find challenge"))
+// Each name of an order has its own authorization, and hence its own challenge
+fn get_dns_challenges(
+    authorizations: Vec<Authorization>,
+) -> Result<Vec<(String, Challenge)>, Error> {
+    authorizations
+        .into_iter()
+        .map(|authorization| {
+            let Identifier::Dns(name) = authorization.identifier;
+
+            let challenge = authorization
+                .challenges
+                .into_iter()
+                .find(|challenge| challenge.r#type == ChallengeType::Dns01)
+                .ok_or_else(|| anyhow!("failed to find challenge for {name}"))?;
+
+            Ok((name, challenge))
+        })
+        .collect()
 }
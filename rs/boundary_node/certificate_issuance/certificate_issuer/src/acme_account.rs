@@ -0,0 +1,181 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{Context, Error};
+use instant_acme::{Account, AccountCredentials, NewAccount};
+use tracing::info;
+
+use crate::encode::{Decode, Encode};
+
+// Keeps the credentials of automatically created ACME accounts, one file per provider,
+// encrypted with the same keys as certificates so they are never stored in the clear
+pub struct CredentialStore {
+    dir: PathBuf,
+    encoder: Arc<dyn Encode>,
+    decoder: Arc<dyn Decode>,
+}
+
+impl CredentialStore {
+    pub fn new(dir: &Path, encoder: Arc<dyn Encode>, decoder: Arc<dyn Decode>) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            encoder,
+            decoder,
+        }
+    }
+
+    fn path(&self, acme_provider_url: &str) -> PathBuf {
+        let name: String = acme_provider_url
+            .trim_start_matches("https://")
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+
+        self.dir.join(format!("{name}.acme"))
+    }
+
+    pub async fn load(&self, acme_provider_url: &str) -> Result<Option<Account>, Error> {
+        let path = self.path(acme_provider_url);
+
+        let bs = match fs::read(&path) {
+            Ok(bs) => bs,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(Error::from(err).context("failed to read acme credentials")),
+        };
+
+        let bs = self
+            .decoder
+            .decode(&bs)
+            .await
+            .context("failed to decrypt acme credentials")?;
+
+        let creds: AccountCredentials =
+            serde_json::from_slice(&bs).context("failed to parse acme credentials")?;
+
+        let account = Account::from_credentials(creds)
+            .context("failed to create acme account from credentials")?;
+
+        Ok(Some(account))
+    }
+
+    pub async fn store(&self, acme_provider_url: &str, account: &Account) -> Result<(), Error> {
+        let bs = serde_json::to_vec(&account.credentials())
+            .context("failed to serialize acme credentials")?;
+
+        let bs = self
+            .encoder
+            .encode(&bs)
+            .await
+            .context("failed to encrypt acme credentials")?;
+
+        fs::create_dir_all(&self.dir).context("failed to create acme credentials directory")?;
+
+        // Written to a temporary file first, so that a crash never leaves partial credentials behind
+        let path = self.path(acme_provider_url);
+        let tmp = path.with_extension("acme.tmp");
+
+        fs::write(&tmp, bs).context("failed to write acme credentials")?;
+        fs::rename(&tmp, &path).context("failed to write acme credentials")?;
+
+        Ok(())
+    }
+}
+
+// Loads the account created on a previous run, or creates a new one registered with the given
+// contact emails. Without a store, a new account is created on every run.
+pub async fn load_or_create(
+    acme_provider_url: &str,
+    contacts: &[String],
+    store: Option<&CredentialStore>,
+) -> Result<Account, Error> {
+    if let Some(store) = store {
+        if let Some(account) = store.load(acme_provider_url).await? {
+            return Ok(account);
+        }
+    }
+
+    let contacts: Vec<String> = contacts.iter().map(|c| format!("mailto:{c}")).collect();
+    let contacts: Vec<&str> = contacts.iter().map(String::as_str).collect();
+
+    let account = Account::create(
+        &NewAccount {
+            contact: &contacts,
+            terms_of_service_agreed: true,
+            only_return_existing: false,
+        },
+        acme_provider_url,
+        None,
+    )
+    .await
+    .context("failed to create acme account")?;
+
+    if let Some(store) = store {
+        store.store(acme_provider_url, &account).await?;
+        info!(acme_provider_url, "created acme account");
+    }
+
+    Ok(account)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::{
+        rand::SystemRandom,
+        signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+    };
+
+    use crate::encode::{Decoder, Encoder, Keyring};
+
+    #[tokio::test]
+    async fn store_and_load_credentials() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("acme-{}", rand::random::<u64>()));
+
+        let keys = Arc::new(Keyring::new(vec![[1u8; 32].to_vec()])?);
+        let store = CredentialStore::new(
+            &dir,
+            Arc::new(Encoder::new(keys.clone())),
+            Arc::new(Decoder::new(keys)),
+        );
+
+        let url = "https://acme-staging-v02.api.letsencrypt.org";
+        assert!(store.load(url).await?.is_none());
+
+        let key =
+            EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &SystemRandom::new())
+                .map_err(|_| anyhow::anyhow!("failed to generate key"))?;
+        let key = base64::encode_config(key.as_ref(), base64::URL_SAFE_NO_PAD);
+
+        let account = Account::from_credentials(serde_json::from_str(&format!(
+            r#"{{
+                "id": "{url}/acme/acct/1",
+                "key_pkcs8": "{key}",
+                "urls": {{
+                    "newNonce": "{url}/acme/new-nonce",
+                    "newAccount": "{url}/acme/new-acct",
+                    "newOrder": "{url}/acme/new-order"
+                }}
+            }}"#,
+        ))?)?;
+
+        store.store(url, &account).await?;
+
+        // Credentials are not stored in the clear
+        let bs = fs::read(store.path(url))?;
+        assert!(!String::from_utf8_lossy(&bs).contains(&key));
+
+        let loaded = store.load(url).await?.expect("missing account");
+        assert_eq!(
+            serde_json::to_value(loaded.credentials())?,
+            serde_json::to_value(account.credentials())?,
+        );
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
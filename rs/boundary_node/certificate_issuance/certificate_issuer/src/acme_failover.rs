@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use tracing::warn;
+
+use crate::acme::{
+    has_problem, Finalize, FinalizeError, KeyType, Order, Ready, RevocationReason, Revoke,
+    RATE_LIMITED_PROBLEM,
+};
+
+#[derive(Default)]
+struct ProviderState {
+    failures: u32,
+    tripped_at: Option<Instant>,
+}
+
+struct State {
+    providers: Vec<ProviderState>,
+
+    // Orders are bound to the provider they were created with,
+    // since subsequent phases have to be completed against the same directory.
+    // They are keyed by their primary name, which is unique across registrations
+    assignments: HashMap<String, usize>,
+}
+
+// Wrapper to fail over between an ordered list of ACME providers
+#[derive(Clone)]
+pub struct WithFailover<T> {
+    providers: Arc<Vec<(String, T)>>,
+    state: Arc<Mutex<State>>,
+
+    // configuration
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl<T> WithFailover<T> {
+    pub fn new(providers: Vec<(String, T)>, failure_threshold: u32, cooldown: Duration) -> Self {
+        let state = State {
+            providers: providers.iter().map(|_| ProviderState::default()).collect(),
+            assignments: HashMap::new(),
+        };
+
+        Self {
+            providers: Arc::new(providers),
+            state: Arc::new(Mutex::new(state)),
+            failure_threshold,
+            cooldown,
+        }
+    }
+
+    // Candidate providers in order of preference, healthy ones first
+    fn candidates(&self) -> Vec<usize> {
+        let state = self.state.lock().unwrap();
+
+        let (healthy, tripped): (Vec<usize>, Vec<usize>) =
+            (0..self.providers.len()).partition(|idx| match state.providers[*idx].tripped_at {
+                Some(t) => t.elapsed() >= self.cooldown,
+                None => true,
+            });
+
+        [healthy, tripped].concat()
+    }
+
+    fn assigned(&self, name: &str) -> usize {
+        let assigned = self.state.lock().unwrap().assignments.get(name).copied();
+
+        match assigned {
+            Some(idx) => idx,
+            None => self.candidates()[0],
+        }
+    }
+
+    fn assign(&self, name: &str, idx: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .assignments
+            .insert(name.to_string(), idx);
+    }
+
+    fn unassign(&self, name: &str) {
+        self.state.lock().unwrap().assignments.remove(name);
+    }
+
+    fn record_success(&self, idx: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.providers[idx] = ProviderState::default();
+    }
+
+    // Returns whether the provider is now considered unavailable
+    fn record_failure(&self, idx: usize, err: &Error) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let p = &mut state.providers[idx];
+
+        p.failures += 1;
+
+        let is_rate_limited = has_problem(err, RATE_LIMITED_PROBLEM);
+        if is_rate_limited || p.failures >= self.failure_threshold {
+            if p.tripped_at.is_none() {
+                warn!(
+                    provider = self.providers[idx].0.as_str(),
+                    failures = p.failures,
+                    is_rate_limited,
+                    "acme provider unavailable, failing over"
+                );
+            }
+
+            p.tripped_at = Some(Instant::now());
+            return true;
+        }
+
+        false
+    }
+}
+
+#[async_trait]
+impl<T: Order> Order for WithFailover<T> {
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error> {
+        let mut last_err = anyhow!("no acme providers configured");
+
+        for idx in self.candidates() {
+            match self.providers[idx].1.order(names).await {
+                Ok(out) => {
+                    self.record_success(idx);
+                    self.assign(&names[0], idx);
+                    return Ok(out);
+                }
+                Err(err) => {
+                    // Only move on to the next provider once this one is deemed unavailable
+                    let is_unavailable = self.record_failure(idx, &err);
+                    if !is_unavailable {
+                        return Err(err);
+                    }
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+#[async_trait]
+impl<T: Ready> Ready for WithFailover<T> {
+    async fn ready(&self, names: &[String]) -> Result<(), Error> {
+        let idx = self.assigned(&names[0]);
+
+        let out = self.providers[idx].1.ready(names).await;
+
+        match &out {
+            Ok(_) => self.record_success(idx),
+            Err(err) => {
+                self.record_failure(idx, err);
+            }
+        };
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: Finalize> Finalize for WithFailover<T> {
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: KeyType,
+    ) -> Result<(String, String), FinalizeError> {
+        let idx = self.assigned(&names[0]);
+
+        let out = self.providers[idx].1.finalize(names, key_type).await;
+
+        match &out {
+            Ok(_) => {
+                self.record_success(idx);
+                self.unassign(&names[0]);
+            }
+            Err(FinalizeError::OrderNotReady(_) | FinalizeError::OrderInvalid) => {}
+            Err(FinalizeError::UnexpectedError(err)) => {
+                self.record_failure(idx, err);
+            }
+        };
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: Revoke> Revoke for WithFailover<T> {
+    async fn revoke(&self, cert_chain_pem: &[u8], reason: RevocationReason) -> Result<(), Error> {
+        let mut last_err = anyhow!("no acme providers configured");
+
+        // Certificates can only be revoked by their issuer, which isn't tracked,
+        // so providers are tried in turn without affecting their health
+        for (name, p) in self.providers.iter() {
+            match p.revoke(cert_chain_pem, reason).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(provider = name.as_str(), error = ?err, "acme revocation failed");
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mockall::predicate;
+
+    use crate::acme::{MockFinalize, MockOrder, MockRevoke};
+
+    #[tokio::test]
+    async fn test_order_fails_over() {
+        let mut primary = MockOrder::new();
+        primary
+            .expect_order()
+            .times(2)
+            .returning(|_| Err(anyhow!("unavailable")));
+
+        let mut secondary = MockOrder::new();
+        secondary
+            .expect_order()
+            .times(1)
+            .withf(|names| names == ["name"])
+            .returning(|_| Ok(vec![("name".into(), "token".into())]));
+
+        let f = WithFailover::new(
+            vec![("primary".into(), primary), ("secondary".into(), secondary)],
+            2,                         // failure_threshold
+            Duration::from_secs(3600), // cooldown
+        );
+
+        let names = vec!["name".to_string()];
+
+        // The first failure is below the threshold and is returned as-is
+        assert!(f.order(&names).await.is_err());
+
+        // The second failure trips the primary and the order is placed with the secondary
+        assert_eq!(
+            f.order(&names).await.unwrap(),
+            vec![("name".to_string(), "token".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_finalize_uses_assigned_provider() {
+        let mut primary = MockOrder::new();
+        primary
+            .expect_order()
+            .times(1)
+            .returning(|_| Err(anyhow!("unavailable")));
+
+        let mut secondary = MockOrder::new();
+        secondary
+            .expect_order()
+            .times(1)
+            .returning(|_| Ok(vec![("name".into(), "token".into())]));
+
+        let f = WithFailover::new(
+            vec![("primary".into(), primary), ("secondary".into(), secondary)],
+            1,                         // failure_threshold
+            Duration::from_secs(3600), // cooldown
+        );
+
+        let names = vec!["name".to_string(), "www.name".to_string()];
+
+        f.order(&names).await.unwrap();
+        assert_eq!(f.assigned("name"), 1);
+
+        let mut primary = MockFinalize::new();
+        primary.expect_finalize().never();
+
+        let mut secondary = MockFinalize::new();
+        secondary
+            .expect_finalize()
+            .times(1)
+            .returning(|_, _| Ok(("cert".into(), "key".into())));
+
+        // Finalizers share the failover state with the orderers
+        let g = WithFailover {
+            providers: Arc::new(vec![
+                ("primary".into(), primary),
+                ("secondary".into(), secondary),
+            ]),
+            state: f.state.clone(),
+            failure_threshold: 1,
+            cooldown: Duration::from_secs(3600),
+        };
+
+        assert_eq!(
+            g.finalize(&names, KeyType::EcdsaP256).await.unwrap(),
+            ("cert".to_string(), "key".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_revoke_tries_all_providers() {
+        let mut primary = MockRevoke::new();
+        primary
+            .expect_revoke()
+            .times(1)
+            .returning(|_, _| Err(anyhow!("unauthorized")));
+
+        let mut secondary = MockRevoke::new();
+        secondary
+            .expect_revoke()
+            .times(1)
+            .with(
+                predicate::eq(&b"cert"[..]),
+                predicate::eq(RevocationReason::KeyCompromise),
+            )
+            .returning(|_, _| Ok(()));
+
+        let f = WithFailover::new(
+            vec![("primary".into(), primary), ("secondary".into(), secondary)],
+            1,                         // failure_threshold
+            Duration::from_secs(3600), // cooldown
+        );
+
+        f.revoke(b"cert", RevocationReason::KeyCompromise)
+            .await
+            .unwrap();
+
+        // Revocation failures don't trip providers
+        assert_eq!(f.candidates(), vec![0, 1]);
+    }
+}
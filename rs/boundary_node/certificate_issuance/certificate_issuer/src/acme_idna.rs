@@ -1,42 +1,50 @@
-use crate::acme::{Finalize, FinalizeError, Order, Ready};
+use crate::acme::{Finalize, FinalizeError, KeyType, Order, Ready};
 use anyhow::{Context, Error};
 use async_trait::async_trait;
 
 // Wrapper to convert names to A-label Internalized Domain Names
 pub struct WithIDNA<T>(pub T);
 
+// Convert names to A-label Internationalized Domain Names
+fn to_ascii(names: &[String]) -> Result<Vec<String>, Error> {
+    names
+        .iter()
+        .map(|name| idna::domain_to_ascii(name).context("failed to idna-encode domain"))
+        .collect()
+}
+
 #[async_trait]
 impl<T: Order> Order for WithIDNA<T> {
-    async fn order(&self, name: &str) -> Result<String, Error> {
-        // Convert name to A-label Internationalized Domain Name
-        let ascii_name = idna::domain_to_ascii(name).context("failed to idna-encode domain")?;
-        self.0.order(&ascii_name).await
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error> {
+        let ascii_names = to_ascii(names)?;
+        self.0.order(&ascii_names).await
     }
 }
 
 #[async_trait]
 impl<T: Ready> Ready for WithIDNA<T> {
-    async fn ready(&self, name: &str) -> Result<(), Error> {
-        // Convert name to A-label Internationalized Domain Name
-        let ascii_name = idna::domain_to_ascii(name).context("failed to idna-encode domain")?;
-        self.0.ready(&ascii_name).await
+    async fn ready(&self, names: &[String]) -> Result<(), Error> {
+        let ascii_names = to_ascii(names)?;
+        self.0.ready(&ascii_names).await
     }
 }
 
 #[async_trait]
 impl<T: Finalize> Finalize for WithIDNA<T> {
-    async fn finalize(&self, name: &str) -> Result<(String, String), FinalizeError> {
-        // Convert name to A-label Internationalized Domain Name
-        let ascii_name = idna::domain_to_ascii(name).context("failed to idna-encode domain")?;
-        self.0.finalize(&ascii_name).await
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: KeyType,
+    ) -> Result<(String, String), FinalizeError> {
+        let ascii_names = to_ascii(names)?;
+        self.0.finalize(&ascii_names, key_type).await
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::acme::{Finalize, MockFinalize, MockOrder, MockReady, Order, Ready};
+    use crate::acme::{Finalize, KeyType, MockFinalize, MockOrder, MockReady, Order, Ready};
     use crate::acme_idna::WithIDNA;
-    use mockall::predicate;
 
     /*
      * Check that the wrapper encodes the the parameter correctly and passes it
@@ -49,33 +57,41 @@ mod tests {
     #[tokio::test]
     async fn test_order_with_idna() {
         let mut mock = MockOrder::new();
-        mock.expect_order().returning(|x| Ok(x.to_string()));
+        mock.expect_order()
+            .returning(|xs| Ok(xs.iter().map(|x| (x.clone(), x.clone())).collect()));
 
         let mock = WithIDNA(mock);
-        assert_eq!(mock.order(DOMAIN).await.unwrap(), DOMAIN_ENCODED);
+        assert_eq!(
+            mock.order(&[DOMAIN.into()]).await.unwrap(),
+            vec![(DOMAIN_ENCODED.to_string(), DOMAIN_ENCODED.to_string())]
+        );
     }
 
     #[tokio::test]
     async fn test_ready_with_idna() {
         let mut mock = MockReady::new();
         mock.expect_ready()
-            .with(predicate::eq(DOMAIN_ENCODED))
+            .withf(|xs| xs == [DOMAIN_ENCODED, "example.com"])
             .times(1)
             .returning(|_x| Ok(()));
 
         let mock = WithIDNA(mock);
-        mock.ready(DOMAIN).await.unwrap();
+        mock.ready(&[DOMAIN.into(), "example.com".into()])
+            .await
+            .unwrap();
     }
 
     #[tokio::test]
     async fn test_finalize_with_idna() {
         let mut mock = MockFinalize::new();
         mock.expect_finalize()
-            .returning(|x| Ok((x.to_string(), x.to_string())));
+            .returning(|xs, _| Ok((xs[0].to_string(), xs[0].to_string())));
 
         let mock = WithIDNA(mock);
         assert_eq!(
-            mock.finalize(DOMAIN).await.unwrap(),
+            mock.finalize(&[DOMAIN.into()], KeyType::EcdsaP256)
+                .await
+                .unwrap(),
             (DOMAIN_ENCODED.to_string(), DOMAIN_ENCODED.to_string())
         );
     }
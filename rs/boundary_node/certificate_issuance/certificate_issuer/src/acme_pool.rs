@@ -0,0 +1,334 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::{
+    acme::{
+        has_problem, Finalize, FinalizeError, KeyType, Order, Ready, RevocationReason, Revoke,
+        RATE_LIMITED_PROBLEM,
+    },
+    work::extract_domain,
+};
+
+// An additional account with an ACME provider, see `WithAccountPool`
+#[derive(Clone, Debug, Deserialize)]
+pub struct PoolAccount {
+    pub provider_url: String,
+    pub id: String,
+    pub key_path: PathBuf,
+}
+
+impl PoolAccount {
+    // Loads a JSON list of `{"provider_url": .., "id": .., "key_path": ..}` entries
+    pub fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        let f = File::open(path).context("failed to open acme accounts file")?;
+        serde_json::from_reader(f).context("failed to parse acme accounts file")
+    }
+}
+
+struct State {
+    throttled_at: Vec<Option<Instant>>,
+
+    // Orders are bound to the account they were created with,
+    // keyed by their primary name like in `WithFailover`
+    assignments: HashMap<String, usize>,
+}
+
+// Wrapper to spread orders across a pool of accounts with the same ACME provider.
+// Orders for a registered domain always go to the same account (unless it is throttled),
+// so that the per-account rate limits are shared out and a compromised or throttled
+// account only affects the domains assigned to it. The pool must not be empty.
+#[derive(Clone)]
+pub struct WithAccountPool<T> {
+    accounts: Arc<Vec<(String, T)>>,
+    state: Arc<Mutex<State>>,
+
+    // configuration
+    cooldown: Duration,
+}
+
+impl<T> WithAccountPool<T> {
+    pub fn new(accounts: Vec<(String, T)>, cooldown: Duration) -> Self {
+        let state = State {
+            throttled_at: accounts.iter().map(|_| None).collect(),
+            assignments: HashMap::new(),
+        };
+
+        Self {
+            accounts: Arc::new(accounts),
+            state: Arc::new(Mutex::new(state)),
+            cooldown,
+        }
+    }
+
+    // The account a domain is assigned to, stable across restarts as long as the pool is unchanged
+    fn preferred(&self, name: &str) -> usize {
+        let h = Sha256::digest(extract_domain(name).as_bytes());
+        let h = u64::from_be_bytes(h[..8].try_into().unwrap());
+
+        (h % self.accounts.len() as u64) as usize
+    }
+
+    // Candidate accounts starting with the preferred one, throttled ones last
+    fn candidates(&self, name: &str) -> Vec<usize> {
+        let n = self.accounts.len();
+        let start = self.preferred(name);
+
+        let state = self.state.lock().unwrap();
+
+        let (available, throttled): (Vec<usize>, Vec<usize>) = (0..n)
+            .map(|k| (start + k) % n)
+            .partition(|idx| match state.throttled_at[*idx] {
+                Some(t) => t.elapsed() >= self.cooldown,
+                None => true,
+            });
+
+        [available, throttled].concat()
+    }
+
+    fn assigned(&self, name: &str) -> usize {
+        let assigned = self.state.lock().unwrap().assignments.get(name).copied();
+
+        match assigned {
+            Some(idx) => idx,
+            None => self.preferred(name),
+        }
+    }
+
+    fn assign(&self, name: &str, idx: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .assignments
+            .insert(name.to_string(), idx);
+    }
+
+    fn unassign(&self, name: &str) {
+        self.state.lock().unwrap().assignments.remove(name);
+    }
+
+    fn throttle(&self, idxs: &[usize]) {
+        let mut state = self.state.lock().unwrap();
+
+        for idx in idxs {
+            if state.throttled_at[*idx].is_none() {
+                warn!(
+                    account = self.accounts[*idx].0.as_str(),
+                    "acme account rate-limited, moving its orders to other accounts"
+                );
+            }
+
+            state.throttled_at[*idx] = Some(Instant::now());
+        }
+    }
+
+    fn unthrottle(&self, idx: usize) {
+        self.state.lock().unwrap().throttled_at[idx] = None;
+    }
+}
+
+#[async_trait]
+impl<T: Order> Order for WithAccountPool<T> {
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error> {
+        let mut last_err = anyhow!("no acme accounts configured");
+        let mut rate_limited = vec![];
+
+        for idx in self.candidates(&names[0]) {
+            match self.accounts[idx].1.order(names).await {
+                Ok(out) => {
+                    // Accounts are only throttled once another one succeeds, since a rate limit
+                    // hit by every account is more likely to be per domain than per account
+                    self.throttle(&rate_limited);
+                    self.unthrottle(idx);
+                    self.assign(&names[0], idx);
+
+                    info!(
+                        name = names[0].as_str(),
+                        account = self.accounts[idx].0.as_str(),
+                        "acme order placed"
+                    );
+
+                    return Ok(out);
+                }
+                Err(err) if has_problem(&err, RATE_LIMITED_PROBLEM) => {
+                    rate_limited.push(idx);
+                    last_err = err;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+#[async_trait]
+impl<T: Ready> Ready for WithAccountPool<T> {
+    async fn ready(&self, names: &[String]) -> Result<(), Error> {
+        let idx = self.assigned(&names[0]);
+        self.accounts[idx].1.ready(names).await
+    }
+}
+
+#[async_trait]
+impl<T: Finalize> Finalize for WithAccountPool<T> {
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: KeyType,
+    ) -> Result<(String, String), FinalizeError> {
+        let idx = self.assigned(&names[0]);
+
+        let out = self.accounts[idx].1.finalize(names, key_type).await;
+        if out.is_ok() {
+            self.unassign(&names[0]);
+        }
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: Revoke> Revoke for WithAccountPool<T> {
+    async fn revoke(&self, cert_chain_pem: &[u8], reason: RevocationReason) -> Result<(), Error> {
+        let mut last_err = anyhow!("no acme accounts configured");
+
+        // The account a certificate was issued with isn't tracked, so accounts are tried in turn
+        for (name, acct) in self.accounts.iter() {
+            match acct.revoke(cert_chain_pem, reason).await {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(account = name.as_str(), error = ?err, "acme revocation failed");
+                    last_err = err;
+                }
+            }
+        }
+
+        Err(last_err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::acme::{MockFinalize, MockOrder};
+
+    fn rate_limited() -> Error {
+        let problem: instant_acme::Problem = serde_json::from_value(serde_json::json!({
+            "type": RATE_LIMITED_PROBLEM,
+            "detail": "too many new orders recently",
+        }))
+        .unwrap();
+
+        instant_acme::Error::Api(problem).into()
+    }
+
+    fn pool(accounts: Vec<MockOrder>) -> WithAccountPool<MockOrder> {
+        WithAccountPool::new(
+            accounts
+                .into_iter()
+                .enumerate()
+                .map(|(idx, acct)| (format!("account-{idx}"), acct))
+                .collect(),
+            Duration::from_secs(3600), // cooldown
+        )
+    }
+
+    #[test]
+    fn test_sticky_per_domain() {
+        let p = pool(vec![MockOrder::new(), MockOrder::new(), MockOrder::new()]);
+
+        for name in ["a.example.com", "b.example.com", "example.com"] {
+            assert_eq!(p.preferred(name), p.preferred("example.com"), "{name}");
+        }
+
+        // Domains are spread across accounts
+        let used: std::collections::HashSet<usize> = (0..32)
+            .map(|i| p.preferred(&format!("domain-{i}.com")))
+            .collect();
+        assert_eq!(used.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_order_moves_off_rate_limited_account() {
+        let names = vec!["example.com".to_string()];
+        let preferred = pool(vec![MockOrder::new(), MockOrder::new()]).preferred(&names[0]);
+
+        let mut accounts = vec![MockOrder::new(), MockOrder::new()];
+        accounts[preferred]
+            .expect_order()
+            .times(1)
+            .returning(|_| Err(rate_limited()));
+        accounts[1 - preferred]
+            .expect_order()
+            .times(2)
+            .returning(|_| Ok(vec![("example.com".into(), "token".into())]));
+
+        let p = pool(accounts);
+
+        p.order(&names).await.unwrap();
+        assert_eq!(p.assigned(&names[0]), 1 - preferred);
+
+        // The throttled account is skipped until the cooldown has elapsed
+        assert_eq!(p.candidates(&names[0]), vec![1 - preferred, preferred]);
+        p.order(&names).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_order_rate_limited_by_all_accounts() {
+        let mut accounts = vec![MockOrder::new(), MockOrder::new()];
+        for acct in accounts.iter_mut() {
+            acct.expect_order()
+                .times(1)
+                .returning(|_| Err(rate_limited()));
+        }
+
+        let p = pool(accounts);
+        let names = vec!["example.com".to_string()];
+
+        let err = p.order(&names).await.unwrap_err();
+        assert!(has_problem(&err, RATE_LIMITED_PROBLEM));
+
+        // Likely a per-domain limit, so no account is throttled
+        assert_eq!(p.state.lock().unwrap().throttled_at, vec![None, None]);
+    }
+
+    #[tokio::test]
+    async fn test_finalize_uses_assigned_account() {
+        let names = vec!["example.com".to_string(), "www.example.com".to_string()];
+
+        let mut accounts = vec![MockFinalize::new(), MockFinalize::new()];
+        accounts[0].expect_finalize().never();
+        accounts[1]
+            .expect_finalize()
+            .times(1)
+            .returning(|_, _| Ok(("cert".into(), "key".into())));
+
+        let p = WithAccountPool::new(
+            accounts
+                .into_iter()
+                .enumerate()
+                .map(|(idx, acct)| (format!("account-{idx}"), acct))
+                .collect(),
+            Duration::from_secs(3600), // cooldown
+        );
+        p.assign(&names[0], 1);
+
+        assert_eq!(
+            p.finalize(&names, KeyType::EcdsaP256).await.unwrap(),
+            ("cert".to_string(), "key".to_string())
+        );
+        assert!(p.state.lock().unwrap().assignments.is_empty());
+    }
+}
@@ -0,0 +1,178 @@
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use instant_acme::Account;
+use ring::{
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use x509_parser::pem::parse_x509_pem;
+
+use crate::acme::{RevocationReason, Revoke};
+
+const ALREADY_REVOKED_PROBLEM: &str = "urn:ietf:params:acme:error:alreadyRevoked";
+const JOSE_CONTENT_TYPE: &str = "application/jose+json";
+const REPLAY_NONCE_HEADER: &str = "replay-nonce";
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Directory {
+    new_nonce: String,
+    revoke_cert: String,
+}
+
+// Subset of the credentials exported by `instant_acme`
+#[derive(Deserialize)]
+struct Credentials {
+    id: String,
+    key_pkcs8: String, // base64url-encoded
+}
+
+#[derive(Deserialize)]
+struct Problem {
+    r#type: String,
+    detail: Option<String>,
+}
+
+fn b64(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+// Revokes certificates using the ACME `revokeCert` endpoint (RFC 8555, section 7.6),
+// which `instant_acme` does not support. Requests are signed with the account key.
+pub struct AcmeRevoker {
+    http: reqwest::Client,
+    directory_url: String,
+    kid: String,
+    key: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AcmeRevoker {
+    pub fn new(
+        http: reqwest::Client,
+        acme_provider_url: &str,
+        account: &Account,
+    ) -> Result<Self, Error> {
+        let creds = serde_json::to_value(account.credentials())
+            .context("failed to serialize acme account credentials")?;
+
+        let Credentials { id, key_pkcs8 } =
+            serde_json::from_value(creds).context("failed to parse acme account credentials")?;
+
+        let key_pkcs8 = base64::decode_config(key_pkcs8, base64::URL_SAFE_NO_PAD)
+            .context("failed to decode acme account key")?;
+
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &key_pkcs8)
+            .map_err(|err| anyhow!("failed to load acme account key: {err}"))?;
+
+        Ok(Self {
+            http,
+            directory_url: format!("{acme_provider_url}/directory"),
+            kid: id,
+            key,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    async fn directory(&self) -> Result<Directory, Error> {
+        self.http
+            .get(&self.directory_url)
+            .send()
+            .await
+            .context("failed to fetch acme directory")?
+            .error_for_status()?
+            .json()
+            .await
+            .context("failed to parse acme directory")
+    }
+
+    async fn nonce(&self, url: &str) -> Result<String, Error> {
+        let resp = self
+            .http
+            .head(url)
+            .send()
+            .await
+            .context("failed to fetch acme nonce")?;
+
+        resp.headers()
+            .get(REPLAY_NONCE_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(String::from)
+            .ok_or_else(|| anyhow!("missing acme nonce"))
+    }
+
+    // Flattened JWS, identifying the signer by its account URL
+    fn sign(&self, url: &str, nonce: &str, payload: &Value) -> Result<Value, Error> {
+        let protected = b64(&serde_json::to_vec(&json!({
+            "alg": "ES256",
+            "kid": self.kid,
+            "nonce": nonce,
+            "url": url,
+        }))?);
+
+        let payload = b64(&serde_json::to_vec(payload)?);
+
+        let signature = self
+            .key
+            .sign(&self.rng, format!("{protected}.{payload}").as_bytes())
+            .map_err(|_| anyhow!("failed to sign acme request"))?;
+
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": b64(signature.as_ref()),
+        }))
+    }
+}
+
+#[async_trait]
+impl Revoke for AcmeRevoker {
+    async fn revoke(&self, cert_chain_pem: &[u8], reason: RevocationReason) -> Result<(), Error> {
+        // The leaf certificate comes first in the chain
+        let (_, pem) = parse_x509_pem(cert_chain_pem).context("failed to parse pem")?;
+
+        let dir = self.directory().await?;
+        let nonce = self.nonce(&dir.new_nonce).await?;
+
+        let body = self.sign(
+            &dir.revoke_cert,
+            &nonce,
+            &json!({
+                "certificate": b64(&pem.contents),
+                "reason": reason.code(),
+            }),
+        )?;
+
+        let resp = self
+            .http
+            .post(&dir.revoke_cert)
+            .header(reqwest::header::CONTENT_TYPE, JOSE_CONTENT_TYPE)
+            .body(serde_json::to_vec(&body)?)
+            .send()
+            .await
+            .context("failed to send acme revocation request")?;
+
+        if resp.status().is_success() {
+            return Ok(());
+        }
+
+        let status = resp.status();
+        let problem: Problem = resp
+            .json()
+            .await
+            .with_context(|| format!("acme revocation failed with status {status}"))?;
+
+        // Revocation is idempotent from our point of view
+        if problem.r#type == ALREADY_REVOKED_PROBLEM {
+            return Ok(());
+        }
+
+        Err(anyhow!(
+            "acme revocation failed: {} ({})",
+            problem.r#type,
+            problem.detail.unwrap_or_default(),
+        ))
+    }
+}
@@ -1,29 +1,68 @@
 use std::{
+    iter::once,
     sync::Arc,
     time::{SystemTime, UNIX_EPOCH},
 };
 
+use anyhow::Context;
 use axum::{
     body::Body,
-    extract::Path,
-    http::{Request, Response},
+    extract::{Path, Query},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, Request, Response,
+    },
     Extension, Json,
 };
+use candid::Principal;
+use certificate_orchestrator_interface::{self as ifc, ExportFilter};
+use futures::StreamExt;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use tracing::warn;
 
 use crate::{
-    certificate::Export,
-    check::{Check, CheckError},
+    acme::{self, KeyType, RevocationReason},
+    audit,
+    caa::CheckCaa,
+    certificate::{self, Export, GetCert, GetCertError, Pair, RevokeError, Upload, WithPagination},
+    check::{Check, CheckError, Delegation, OwnershipTokens, OWNERSHIP_TOKENS_PATH},
+    failure::ErrorCode,
+    history::{GetHistory, HistoryError, TaskHistory},
+    idn::{normalize_names, Normalize},
+    import::{self, Imported},
+    rate_limit::RequestRateLimit,
     registration::{
-        Create, CreateError, Get, GetError, Id, Remove, RemoveError, Update, UpdateError,
-        UpdateType,
+        Create, CreateError, Get, GetError, GetTimestamps, Id, List, Registration, Remove,
+        RemoveError, State, Timestamps, Update, UpdateError, UpdateType,
     },
-    work::Queue,
+    renewal::{expiry, RenewalPolicy},
+    validation::{name_field, names_field, principal_field, FieldError, Valid, Validate},
+    work::{Priority, Queue},
 };
 
+// Identity of an authenticated caller, attached to requests by the authentication layer
+#[derive(Clone, Debug)]
+pub struct CallerIdentity(pub String);
+
 #[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CreateHandlerRequest {
     pub name: Id,
+
+    #[serde(default)]
+    pub key_type: Option<KeyType>,
+
+    // Additional names to be covered by the same certificate, e.g `www.` of an apex domain
+    #[serde(default)]
+    pub alt_names: Vec<Id>,
+}
+
+impl Validate for CreateHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        name_field(body, "name", errors);
+        names_field(body, "alt_names", errors);
+    }
 }
 
 #[derive(Serialize)]
@@ -33,40 +72,297 @@ pub struct CreateHandlerResponse {
 
 #[allow(clippy::type_complexity)]
 pub async fn create_handler(
-    Extension((ck, c, q)): Extension<(Arc<dyn Check>, Arc<dyn Create>, Arc<dyn Queue>)>,
-    Json(CreateHandlerRequest { name }): Json<CreateHandlerRequest>,
+    Extension((n, ck, c, g, q)): Extension<(
+        Arc<dyn Normalize>,
+        Arc<dyn Check>,
+        Arc<dyn Create>,
+        Arc<dyn Get>,
+        Arc<dyn Queue>,
+    )>,
+    Valid(CreateHandlerRequest {
+        name,
+        key_type,
+        alt_names,
+    }): Valid<CreateHandlerRequest>,
 ) -> Response<Body> {
-    // Check request
-    let canister = match ck.check(&name).await {
-        Ok(canister) => canister,
-        Err(CheckError::UnexpectedError(_)) => {
+    if alt_names.len() > ifc::ALT_NAMES_MAX_LEN {
+        return Response::builder()
+            .status(400)
+            .body(Body::from(format!(
+                "at most {} alternative names are allowed",
+                ifc::ALT_NAMES_MAX_LEN
+            )))
+            .unwrap();
+    }
+
+    // Normalize names, so that registrations of the same names in another form are detected
+    let (name, alt_names) = match normalize_names(n.as_ref(), once(&name).chain(&alt_names)) {
+        Ok(mut names) => (names.remove(0), names),
+        Err(err) => {
             return Response::builder()
-                .status(500)
-                .body(Body::from("unexpected error"))
+                .status(400)
+                .body(Body::from(err.to_string()))
                 .unwrap()
         }
-        Err(err) => {
+    };
+
+    let id = match create(
+        ck.as_ref(), // checker
+        c.as_ref(),  // creator
+        g.as_ref(),  // getter
+        q.as_ref(),  // queuer
+        &name,       // name
+        key_type,    // key_type
+        &alt_names,  // alt_names
+        None,        // canister
+    )
+    .await
+    {
+        Ok(id) => id,
+        Err((status, msg)) => {
+            return Response::builder()
+                .status(status)
+                .body(Body::from(msg))
+                .unwrap()
+        }
+    };
+
+    let bs = match serde_json::ser::to_vec(&CreateHandlerResponse { id }) {
+        Ok(bs) => bs,
+        Err(_) => {
             return Response::builder()
                 .status(500)
-                .body(Body::from(err.to_string()))
+                .body(Body::from("unexpected error"))
                 .unwrap()
         }
     };
 
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+// Checks normalized names and creates a registration for them, queueing its first task.
+// With a canister given, the names have to point to it. Errors carry the status to respond with.
+async fn create(
+    ck: &dyn Check,
+    c: &dyn Create,
+    g: &dyn Get,
+    q: &dyn Queue,
+    name: &str,
+    key_type: Option<KeyType>,
+    alt_names: &[String],
+    expected: Option<&Principal>,
+) -> Result<Id, (u16, String)> {
+    // Check request
+    let canister = match ck.check(name).await {
+        Ok(canister) => canister,
+        Err(CheckError::UnexpectedError(_)) => return Err((500, "unexpected error".into())),
+        Err(err) if err.is_policy_violation() => return Err((403, err.to_string())),
+        Err(err) => return Err((500, err.to_string())),
+    };
+
+    if let Some(expected) = expected {
+        if &canister != expected {
+            return Err((
+                400,
+                format!("{name} points to canister {canister} instead of {expected}"),
+            ));
+        }
+    }
+
+    // Alternative names must be configured for the same canister
+    for alt_name in alt_names {
+        match ck.check(alt_name).await {
+            Ok(alt_canister) if alt_canister == canister => {}
+            Ok(_) => {
+                return Err((
+                    400,
+                    format!("alternative name {alt_name} points to a different canister"),
+                ))
+            }
+            Err(CheckError::UnexpectedError(_)) => return Err((500, "unexpected error".into())),
+            Err(err) if err.is_policy_violation() => return Err((403, err.to_string())),
+            Err(err) => return Err((500, err.to_string())),
+        }
+    }
+
     // Create registration
-    let (id, is_duplicate) = match c.create(&name, &canister).await {
+    let (id, is_duplicate) = match c.create(name, &canister, key_type, alt_names).await {
         Ok(id) => (id, false),
-        Err(CreateError::Duplicate(id)) => (id, true),
+
+        // A registration for exactly the same names and canister is merged with the new one (e.g a retried request),
+        // any other overlap would have both registrations contend for the same DNS records and certificates
+        Err(CreateError::Duplicate(id)) => match g.get(&id).await {
+            Ok(reg) if reg.canister == canister && same_names(&reg, name, alt_names) => (id, true),
+            Ok(_) => {
+                return Err((
+                    409,
+                    format!("names conflict with existing registration {id}"),
+                ))
+            }
+            Err(_) => return Err((500, "unexpected error".into())),
+        },
         Err(CreateError::RateLimited(domain)) => {
+            return Err((429, format!("rate limit exceeded for domain {}", domain)))
+        }
+        Err(CreateError::UnexpectedError(_)) => return Err((500, "unexpected error".into())),
+    };
+
+    // Queue task
+    if !is_duplicate {
+        let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(t) => t.as_nanos() as u64,
+            Err(_) => return Err((500, "unexpected error".into())),
+        };
+
+        if (q.queue(&id, t, Priority::Normal).await).is_err() {
+            return Err((500, "unexpected error".into()));
+        }
+    }
+
+    Ok(id)
+}
+
+// Whether a registration covers exactly the given names, in any order
+fn same_names(reg: &Registration, name: &str, alt_names: &[String]) -> bool {
+    let mut existing: Vec<&str> = once(&reg.name)
+        .chain(&reg.alt_names)
+        .map(String::as_str)
+        .collect();
+    let mut requested: Vec<&str> = once(name)
+        .chain(alt_names.iter().map(String::as_str))
+        .collect();
+
+    existing.sort_unstable();
+    requested.sort_unstable();
+
+    existing == requested
+}
+
+// Limits on batch creation, each name of a batch is checked and created like a single registration
+const BATCH_MAX_SIZE: usize = 50;
+const BATCH_CONCURRENCY: usize = 10;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchCreateHandlerRequest {
+    pub canister: Principal,
+    pub names: Vec<Id>,
+
+    #[serde(default)]
+    pub key_type: Option<KeyType>,
+}
+
+impl Validate for BatchCreateHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        principal_field(body, "canister", errors);
+        names_field(body, "names", errors);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchCreateResult {
+    pub name: String,
+    pub status: u16,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<Id>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct BatchCreateHandlerResponse {
+    pub results: Vec<BatchCreateResult>,
+}
+
+// Creates a registration per name, all of which have to point to the given canister.
+// Names are processed concurrently and independently, so the results report each name's outcome
+// in the order of the request, with the status a single creation would have responded with.
+#[allow(clippy::type_complexity)]
+pub async fn batch_create_handler(
+    Extension((n, ck, c, g, q)): Extension<(
+        Arc<dyn Normalize>,
+        Arc<dyn Check>,
+        Arc<dyn Create>,
+        Arc<dyn Get>,
+        Arc<dyn Queue>,
+    )>,
+    Extension(rate_limit): Extension<RequestRateLimit>,
+    Valid(BatchCreateHandlerRequest {
+        canister,
+        names,
+        key_type,
+    }): Valid<BatchCreateHandlerRequest>,
+) -> Response<Body> {
+    if names.is_empty() || names.len() > BATCH_MAX_SIZE {
+        return Response::builder()
+            .status(400)
+            .body(Body::from(format!(
+                "between 1 and {BATCH_MAX_SIZE} names are allowed"
+            )))
+            .unwrap();
+    }
+
+    // Names given more than once, possibly in different forms, would contend for the same registration
+    let names = match normalize_names(n.as_ref(), &names) {
+        Ok(names) => names,
+        Err(err) => {
             return Response::builder()
-                .status(429)
-                .body(Body::from(format!(
-                    "rate limit exceeded for domain {}",
-                    domain
-                )))
+                .status(400)
+                .body(Body::from(err.to_string()))
                 .unwrap()
         }
-        Err(CreateError::UnexpectedError(_)) => {
+    };
+
+    // Each name is charged like a single registration creation
+    if let Err(resp) = rate_limit.acquire(names.len() as u32) {
+        return resp;
+    }
+
+    let results: Vec<BatchCreateResult> = futures::stream::iter(names)
+        .map(|name| {
+            let (ck, c, g, q) = (ck.as_ref(), c.as_ref(), g.as_ref(), q.as_ref());
+
+            async move {
+                let out = create(
+                    ck,              // checker
+                    c,               // creator
+                    g,               // getter
+                    q,               // queuer
+                    &name,           // name
+                    key_type,        // key_type
+                    &[],             // alt_names
+                    Some(&canister), // canister
+                )
+                .await;
+
+                match out {
+                    Ok(id) => BatchCreateResult {
+                        name,
+                        status: 200,
+                        id: Some(id),
+                        error: None,
+                    },
+                    Err((status, msg)) => BatchCreateResult {
+                        name,
+                        status,
+                        id: None,
+                        error: Some(msg),
+                    },
+                }
+            }
+        })
+        .buffered(BATCH_CONCURRENCY)
+        .collect()
+        .await;
+
+    let bs = match serde_json::ser::to_vec(&BatchCreateHandlerResponse { results }) {
+        Ok(bs) => bs,
+        Err(_) => {
             return Response::builder()
                 .status(500)
                 .body(Body::from("unexpected error"))
@@ -74,27 +370,274 @@ pub async fn create_handler(
         }
     };
 
-    // Queue task
-    if !is_duplicate {
-        let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
-            Ok(t) => t.as_nanos() as u64,
-            Err(_) => {
-                return Response::builder()
-                    .status(500)
-                    .body(Body::from("unexpected error"))
-                    .unwrap()
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ValidateHandlerRequest {
+    pub name: Id,
+
+    #[serde(default)]
+    pub alt_names: Vec<Id>,
+}
+
+impl Validate for ValidateHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        name_field(body, "name", errors);
+        names_field(body, "alt_names", errors);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub check: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NameReport {
+    pub name: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canister: Option<Principal>,
+
+    pub checks: Vec<CheckReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValidateHandlerResponse {
+    pub valid: bool,
+    pub names: Vec<NameReport>,
+}
+
+// Runs every check for the given names, the first of which is the primary name
+async fn validate(
+    n: &dyn Normalize,
+    ck: &dyn Check,
+    caa: &dyn CheckCaa,
+    names: impl Iterator<Item = String>,
+) -> ValidateHandlerResponse {
+    let mut reports: Vec<NameReport> = vec![];
+
+    for name in names {
+        let mut checks = vec![];
+
+        let name = match n.normalize(&name) {
+            Ok(name) => name,
+            Err(err) => {
+                checks.push(CheckReport {
+                    check: "name",
+                    error: Some(err.to_string()),
+                });
+
+                reports.push(NameReport {
+                    name,
+                    canister: None,
+                    checks,
+                });
+
+                continue;
+            }
+        };
+
+        // Challenge delegation, canister mapping and known domains
+        let canister = match ck.check(&name).await {
+            Ok(canister) => {
+                checks.push(CheckReport {
+                    check: "dns",
+                    error: None,
+                });
+
+                Some(canister)
+            }
+            Err(err) => {
+                checks.push(CheckReport {
+                    check: "dns",
+                    error: Some(err.to_string()),
+                });
+
+                None
             }
         };
 
-        if (q.queue(&id, t).await).is_err() {
+        // Alternative names must be configured for the same canister
+        if let (Some(primary), Some(canister)) = (
+            reports.first().and_then(|r| r.canister.as_ref()),
+            canister.as_ref(),
+        ) {
+            checks.push(CheckReport {
+                check: "canister",
+                error: (primary != canister)
+                    .then(|| format!("points to canister {canister} instead of {primary}")),
+            });
+        }
+
+        checks.push(CheckReport {
+            check: "caa",
+            error: caa.check_caa(&name).await.err().map(|err| err.to_string()),
+        });
+
+        reports.push(NameReport {
+            name,
+            canister,
+            checks,
+        });
+    }
+
+    let valid = reports
+        .iter()
+        .flat_map(|r| r.checks.iter())
+        .all(|c| c.error.is_none());
+
+    ValidateHandlerResponse {
+        valid,
+        names: reports,
+    }
+}
+
+// Runs the checks performed before creating a registration and ordering its certificate,
+// without stopping at the first failure, to help diagnose a domain's configuration
+#[allow(clippy::type_complexity)]
+pub async fn validate_handler(
+    Extension((n, ck, caa)): Extension<(Arc<dyn Normalize>, Arc<dyn Check>, Arc<dyn CheckCaa>)>,
+    Valid(ValidateHandlerRequest { name, alt_names }): Valid<ValidateHandlerRequest>,
+) -> Response<Body> {
+    if alt_names.len() > ifc::ALT_NAMES_MAX_LEN {
+        return Response::builder()
+            .status(400)
+            .body(Body::from(format!(
+                "at most {} alternative names are allowed",
+                ifc::ALT_NAMES_MAX_LEN
+            )))
+            .unwrap();
+    }
+
+    let report = validate(
+        n.as_ref(),
+        ck.as_ref(),
+        caa.as_ref(),
+        once(name).chain(alt_names),
+    )
+    .await;
+
+    let bs = match serde_json::ser::to_vec(&report) {
+        Ok(bs) => bs,
+        Err(_) => {
             return Response::builder()
                 .status(500)
                 .body(Body::from("unexpected error"))
-                .unwrap();
+                .unwrap()
         }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OwnershipTokenHandlerRequest {
+    pub name: String,
+    pub canister: Principal,
+}
+
+impl Validate for OwnershipTokenHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        name_field(body, "name", errors);
+        principal_field(body, "canister", errors);
     }
+}
 
-    let bs = match serde_json::ser::to_vec(&CreateHandlerResponse { id }) {
+#[derive(Debug, Serialize)]
+pub struct OwnershipTokenHandlerResponse {
+    pub token: String,
+    pub path: &'static str, // where the canister has to serve the token
+}
+
+// Issues the token a canister has to serve to prove ownership of a domain before it can be registered
+pub async fn ownership_token_handler(
+    Extension((n, tokens)): Extension<(Arc<dyn Normalize>, Arc<OwnershipTokens>)>,
+    Valid(OwnershipTokenHandlerRequest { name, canister }): Valid<OwnershipTokenHandlerRequest>,
+) -> Response<Body> {
+    let name = match n.normalize(&name) {
+        Ok(name) => name,
+        Err(err) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from(err.to_string()))
+                .unwrap()
+        }
+    };
+
+    let bs = match serde_json::ser::to_vec(&OwnershipTokenHandlerResponse {
+        token: tokens.token(&name, &canister),
+        path: OWNERSHIP_TOKENS_PATH,
+    }) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DelegationHandlerRequest {
+    pub name: String,
+    pub canister: Principal,
+}
+
+impl Validate for DelegationHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        name_field(body, "name", errors);
+        principal_field(body, "canister", errors);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DelegationHandlerResponse {
+    pub src: String,
+    pub dst: String,
+}
+
+// Returns the CNAME record a domain needs to delegate its ACME challenge to the issuer when pointing to a canister
+pub async fn delegation_handler(
+    Extension((n, delegation)): Extension<(Arc<dyn Normalize>, Arc<Delegation>)>,
+    Valid(DelegationHandlerRequest { name, canister }): Valid<DelegationHandlerRequest>,
+) -> Response<Body> {
+    let name = match n.normalize(&name) {
+        Ok(name) => name,
+        Err(err) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from(err.to_string()))
+                .unwrap()
+        }
+    };
+
+    let bs = match serde_json::ser::to_vec(&DelegationHandlerResponse {
+        src: format!("_acme-challenge.{name}"),
+        dst: delegation
+            .target(&name, &canister)
+            .trim_end_matches('.')
+            .to_string(),
+    }) {
         Ok(bs) => bs,
         Err(_) => {
             return Response::builder()
@@ -110,8 +653,32 @@ pub async fn create_handler(
         .unwrap()
 }
 
+#[derive(Debug, Serialize)]
+pub struct ErrorDetail {
+    pub code: ErrorCode,
+    pub hint: &'static str,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub record: Option<String>,
+
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GetHandlerResponse {
+    #[serde(flatten)]
+    pub registration: Registration,
+
+    #[serde(flatten)]
+    pub timestamps: Timestamps,
+
+    // Set for failed or parked registrations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorDetail>,
+}
+
 pub async fn get_handler(
-    Extension(g): Extension<Arc<dyn Get>>,
+    Extension((g, gt)): Extension<(Arc<dyn Get>, Arc<dyn GetTimestamps>)>,
     Path(id): Path<Id>,
     _: Request<Body>,
 ) -> Response<Body> {
@@ -133,7 +700,24 @@ pub async fn get_handler(
         }
     };
 
-    let bs = match serde_json::ser::to_vec(&reg) {
+    // Timestamps are informational, so failing to get them does not fail the request
+    let timestamps = gt.get_timestamps(&id).await.unwrap_or_else(|err| {
+        warn!(id, error = ?err, "failed to get registration timestamps");
+        Timestamps::default()
+    });
+
+    let error = reg.state.failure().map(|f| ErrorDetail {
+        code: f.code,
+        hint: f.code.hint(),
+        record: f.record,
+        message: f.message,
+    });
+
+    let bs = match serde_json::ser::to_vec(&GetHandlerResponse {
+        registration: reg,
+        timestamps,
+        error,
+    }) {
         Ok(bs) => bs,
         Err(_) => {
             return Response::builder()
@@ -149,13 +733,25 @@ pub async fn get_handler(
         .unwrap()
 }
 
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateHandlerRequest {
+    pub canister: Option<Principal>,
+}
+
+impl Validate for UpdateHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        principal_field(body, "canister", errors);
+    }
+}
+
 #[allow(clippy::type_complexity)]
 pub async fn update_handler(
     Extension((ck, g, u)): Extension<(Arc<dyn Check>, Arc<dyn Get>, Arc<dyn Update>)>,
     Path(id): Path<Id>,
-    _: Request<Body>,
+    Valid(req): Valid<Option<UpdateHandlerRequest>>,
 ) -> Response<Body> {
-    let reg = match g.get(&id).await {
+    let mut reg = match g.get(&id).await {
         Ok(reg) => reg,
 
         Err(GetError::NotFound) => {
@@ -184,12 +780,28 @@ pub async fn update_handler(
         }
         Err(err) => {
             return Response::builder()
-                .status(500)
+                .status(400)
                 .body(Body::from(err.to_string()))
                 .unwrap()
         }
     };
 
+    // When the owner states the target canister, ensure the dns records already point to it
+    if let Some(UpdateHandlerRequest {
+        canister: Some(expected),
+    }) = req
+    {
+        if expected != canister {
+            return Response::builder()
+                .status(409)
+                .body(Body::from(format!(
+                    "canister mismatch: dns txt record points to {canister} instead of {expected}"
+                )))
+                .unwrap();
+        }
+    }
+
+    // Only the canister mapping is updated, the existing certificate is kept
     if reg.canister != canister {
         match u.update(&id, &UpdateType::Canister(canister)).await {
             Ok(()) => {}
@@ -208,10 +820,25 @@ pub async fn update_handler(
                     .unwrap()
             }
         };
-    }
 
-    Response::builder().status(200).body(Body::empty()).unwrap()
-}
+        reg.canister = canister;
+    }
+
+    let bs = match serde_json::ser::to_vec(&reg) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
 
 #[allow(clippy::type_complexity)]
 pub async fn remove_handler(
@@ -273,18 +900,46 @@ pub async fn remove_handler(
     Response::builder().status(200).body(Body::empty()).unwrap()
 }
 
-pub async fn export_handler(
-    Extension(e): Extension<Arc<dyn Export>>,
-    _: Request<Body>,
+#[derive(Default, Deserialize)]
+pub struct ListHandlerQuery {
+    pub after: Option<Id>,
+    pub limit: Option<u64>,
+    pub state: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ListHandlerEntry {
+    pub id: Id,
+
+    #[serde(flatten)]
+    pub registration: Registration,
+}
+
+const DEFAULT_LIST_LIMIT: u64 = 100;
+
+pub async fn list_handler(
+    Extension(l): Extension<Arc<dyn List>>,
+    Query(ListHandlerQuery {
+        after,
+        limit,
+        state,
+    }): Query<ListHandlerQuery>,
 ) -> Response<Body> {
-    let pkgs = match e
-        .export(
-            None,     // key
-            u64::MAX, // limit
-        )
-        .await
-    {
-        Ok((pkgs, _)) => pkgs,
+    let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT);
+
+    let state: Option<State> = match state.as_deref().map(parse_state) {
+        None => None,
+        Some(Some(state)) => Some(state.into()),
+        Some(None) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from("invalid state"))
+                .unwrap()
+        }
+    };
+
+    let regs = match l.list(after, limit, state).await {
+        Ok(regs) => regs,
         Err(_) => {
             return Response::builder()
                 .status(500)
@@ -293,7 +948,18 @@ pub async fn export_handler(
         }
     };
 
-    let bs = match serde_json::ser::to_vec(&pkgs) {
+    // A full page indicates there might be more entries
+    let next = match regs.len() as u64 == limit {
+        true => regs.last().map(|(id, _)| id.to_owned()),
+        false => None,
+    };
+
+    let regs: Vec<ListHandlerEntry> = regs
+        .into_iter()
+        .map(|(id, registration)| ListHandlerEntry { id, registration })
+        .collect();
+
+    let bs = match serde_json::ser::to_vec(&regs) {
         Ok(bs) => bs,
         Err(_) => {
             return Response::builder()
@@ -303,187 +969,2197 @@ pub async fn export_handler(
         }
     };
 
-    Response::builder()
-        .status(200)
-        .body(Body::from(bs))
-        .unwrap()
+    let mut resp = Response::builder().status(200);
+    if let Some(next) = next {
+        resp = resp.header(NEXT_PAGE_HEADER, next);
+    }
+
+    resp.body(Body::from(bs)).unwrap()
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[allow(clippy::type_complexity)]
+pub async fn retry_handler(
+    Extension((g, u, q)): Extension<(Arc<dyn Get>, Arc<dyn Update>, Arc<dyn Queue>)>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let reg = match g.get(&id).await {
+        Ok(reg) => reg,
 
-    use anyhow::Error;
-    use candid::Principal;
-    use mockall::predicate;
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
 
-    use crate::{
-        check::MockCheck,
-        registration::{MockGet, MockRemove, MockUpdate, Registration, State},
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
     };
 
-    #[tokio::test]
-    async fn update_ok() -> Result<(), Error> {
-        let mut getter = MockGet::new();
-        getter
-            .expect_get()
-            .times(1)
-            .with(predicate::eq(Id::from("id")))
-            .returning(|_| {
-                Ok(Registration {
-                    name: String::from("name"),
-                    canister: Principal::from_text("aaaaa-aa").unwrap(),
-                    state: State::PendingOrder,
-                })
-            });
+    // Only parked registrations are retried, others are still being processed
+    if !matches!(reg.state, State::Parked(_)) {
+        return Response::builder()
+            .status(409)
+            .body(Body::from("registration is not parked"))
+            .unwrap();
+    }
 
-        let mut checker = MockCheck::new();
-        checker
-            .expect_check()
-            .times(1)
-            .with(predicate::eq("name"))
-            .returning(|_| Ok(Principal::from_text("2ibo7-dia").unwrap()));
+    // Start over with a new order
+    match u.update(&id, &UpdateType::State(State::PendingOrder)).await {
+        Ok(()) => {}
 
-        let mut updater = MockUpdate::new();
-        updater
-            .expect_update()
-            .times(1)
-            .with(
-                predicate::eq(Id::from("id")),
-                predicate::eq(UpdateType::Canister(
-                    Principal::from_text("2ibo7-dia").unwrap(),
-                )),
-            )
-            .returning(|_, _| Ok(()));
+        Err(UpdateError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
 
-        let resp = update_handler(
-            Extension((Arc::new(checker), Arc::new(getter), Arc::new(updater))),
-            Path("id".into()),
-            Request::builder().body(Body::empty())?,
-        )
-        .await;
+        Err(UpdateError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
 
-        assert_eq!(resp.status(), 200);
+    let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_nanos() as u64,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
 
-        Ok(())
+    if (q.queue(&id, t, Priority::Normal).await).is_err() {
+        return Response::builder()
+            .status(500)
+            .body(Body::from("unexpected error"))
+            .unwrap();
     }
 
-    #[tokio::test]
-    async fn update_skip() -> Result<(), Error> {
-        let mut getter = MockGet::new();
-        getter
-            .expect_get()
-            .times(1)
-            .with(predicate::eq(Id::from("id")))
-            .returning(|_| {
-                Ok(Registration {
-                    name: String::from("name"),
-                    canister: Principal::from_text("aaaaa-aa").unwrap(),
-                    state: State::PendingOrder,
-                })
-            });
+    Response::builder().status(200).body(Body::empty()).unwrap()
+}
 
-        let mut checker = MockCheck::new();
-        checker
-            .expect_check()
-            .times(1)
-            .with(predicate::eq("name"))
-            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+// Queues a registration's renewal right away, instead of waiting for its certificate to near expiry
+pub async fn renew_handler(
+    Extension((g, q)): Extension<(Arc<dyn Get>, Arc<dyn Queue>)>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let reg = match g.get(&id).await {
+        Ok(reg) => reg,
 
-        let mut updater = MockUpdate::new();
-        updater.expect_update().never();
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
 
-        let resp = update_handler(
-            Extension((Arc::new(checker), Arc::new(getter), Arc::new(updater))),
-            Path("id".into()),
-            Request::builder().body(Body::empty())?,
-        )
-        .await;
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
 
-        assert_eq!(resp.status(), 200);
+    // Registrations without a certificate are still being processed
+    if reg.state != State::Available {
+        return Response::builder()
+            .status(409)
+            .body(Body::from("registration is not available"))
+            .unwrap();
+    }
 
-        Ok(())
+    let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_nanos() as u64,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    if (q.queue(&id, t, Priority::Normal).await).is_err() {
+        return Response::builder()
+            .status(500)
+            .body(Body::from("unexpected error"))
+            .unwrap();
     }
 
-    #[tokio::test]
-    async fn remove_ok() -> Result<(), Error> {
-        let mut getter = MockGet::new();
-        getter
-            .expect_get()
-            .times(1)
-            .with(predicate::eq(Id::from("id")))
-            .returning(|_| {
-                Ok(Registration {
-                    name: String::from("name"),
-                    canister: Principal::from_text("aaaaa-aa").unwrap(),
-                    state: State::PendingOrder,
-                })
-            });
+    Response::builder().status(200).body(Body::empty()).unwrap()
+}
 
-        let mut checker = MockCheck::new();
-        checker
-            .expect_check()
-            .times(1)
-            .with(predicate::eq("name"))
-            .returning(|_| {
-                Err(CheckError::MissingDnsCname {
-                    src: "src".into(),
-                    dst: "dst".into(),
-                })
-            });
+pub const PAUSED_REASON: &str = "paused by operator";
 
-        let mut remover = MockRemove::new();
-        remover
-            .expect_remove()
-            .times(1)
-            .with(predicate::eq(Id::from("id")))
-            .returning(|_| Ok(()));
+// Parks a registration, so its tasks are skipped until it is resumed
+pub async fn pause_handler(
+    Extension((g, u)): Extension<(Arc<dyn Get>, Arc<dyn Update>)>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let reg = match g.get(&id).await {
+        Ok(reg) => reg,
 
-        let resp = remove_handler(
-            Extension((Arc::new(checker), Arc::new(getter), Arc::new(remover))),
-            Path("id".into()),
-            Request::builder().body(Body::empty())?,
-        )
-        .await;
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
 
-        assert_eq!(resp.status(), 200);
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
 
-        Ok(())
+    if matches!(reg.state, State::Parked(_)) {
+        return Response::builder()
+            .status(409)
+            .body(Body::from("registration is already parked"))
+            .unwrap();
     }
 
-    #[tokio::test]
-    async fn remove_bad_request() -> Result<(), Error> {
-        let mut getter = MockGet::new();
-        getter
-            .expect_get()
-            .times(1)
-            .with(predicate::eq(Id::from("id")))
+    match u
+        .update(&id, &UpdateType::State(State::Parked(PAUSED_REASON.into())))
+        .await
+    {
+        Ok(()) => {}
+
+        Err(UpdateError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(UpdateError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder().status(200).body(Body::empty()).unwrap()
+}
+
+// Resumes a parked registration where it left off: registrations with a certificate
+// are scheduled for renewal, others start over with a new order
+#[allow(clippy::type_complexity)]
+pub async fn resume_handler(
+    Extension((g, cg, rp, u, q)): Extension<(
+        Arc<dyn Get>,
+        Arc<dyn GetCert>,
+        Arc<RenewalPolicy>,
+        Arc<dyn Update>,
+        Arc<dyn Queue>,
+    )>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let reg = match g.get(&id).await {
+        Ok(reg) => reg,
+
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    if !matches!(reg.state, State::Parked(_)) {
+        return Response::builder()
+            .status(409)
+            .body(Body::from("registration is not parked"))
+            .unwrap();
+    }
+
+    let now = SystemTime::now();
+
+    let (state, t) = match cg.get_cert(&id).await {
+        Ok(Pair(_, cert_chain_pem)) => match expiry(&cert_chain_pem) {
+            Ok(not_after) => (State::Available, rp.renewal_time(not_after, now)),
+            Err(_) => (State::Available, now),
+        },
+
+        Err(GetCertError::NotFound) => (State::PendingOrder, now),
+
+        Err(GetCertError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    match u.update(&id, &UpdateType::State(state)).await {
+        Ok(()) => {}
+
+        Err(UpdateError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(UpdateError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let t = match t.duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_nanos() as u64,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    if (q.queue(&id, t, Priority::Normal).await).is_err() {
+        return Response::builder()
+            .status(500)
+            .body(Body::from("unexpected error"))
+            .unwrap();
+    }
+
+    Response::builder().status(200).body(Body::empty()).unwrap()
+}
+
+// Re-runs the checks of an existing registration, see `validate_handler`
+#[allow(clippy::type_complexity)]
+pub async fn check_handler(
+    Extension((g, n, ck, caa)): Extension<(
+        Arc<dyn Get>,
+        Arc<dyn Normalize>,
+        Arc<dyn Check>,
+        Arc<dyn CheckCaa>,
+    )>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let reg = match g.get(&id).await {
+        Ok(reg) => reg,
+
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let report = validate(
+        n.as_ref(),
+        ck.as_ref(),
+        caa.as_ref(),
+        once(reg.name).chain(reg.alt_names),
+    )
+    .await;
+
+    let bs = match serde_json::ser::to_vec(&report) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+// Recent tasks of a registration processed by this issuer
+pub async fn history_handler(
+    Extension(h): Extension<Arc<TaskHistory>>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let bs = match serde_json::ser::to_vec(&h.get(&id)) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+// Persisted issuance history of a registration, e.g. for support engineers
+pub async fn issuance_history_handler(
+    Extension(h): Extension<Arc<dyn GetHistory>>,
+    Path(id): Path<Id>,
+    _: Request<Body>,
+) -> Response<Body> {
+    let history = match h.get_history(&id).await {
+        Ok(history) => history,
+
+        Err(HistoryError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(HistoryError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let bs = match serde_json::ser::to_vec(&history) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+#[derive(Default, Deserialize)]
+pub struct AuditHandlerQuery {
+    pub id: Option<Id>,
+    pub after: Option<u64>,
+    pub limit: Option<u64>,
+}
+
+pub async fn audit_handler(
+    Extension(aq): Extension<Arc<dyn audit::Query>>,
+    Query(AuditHandlerQuery { id, after, limit }): Query<AuditHandlerQuery>,
+) -> Response<Body> {
+    let limit = limit.unwrap_or(DEFAULT_LIST_LIMIT);
+
+    let rs = match aq.query(id, after, limit).await {
+        Ok(rs) => rs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    // A full page indicates there might be more entries
+    let next = match rs.len() as u64 == limit {
+        true => rs.last().map(|r| r.seq),
+        false => None,
+    };
+
+    let bs = match serde_json::ser::to_vec(&rs) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let mut resp = Response::builder().status(200);
+    if let Some(next) = next {
+        resp = resp.header(NEXT_PAGE_HEADER, next);
+    }
+
+    resp.body(Body::from(bs)).unwrap()
+}
+
+#[derive(Deserialize)]
+pub struct RevokeHandlerRequest {
+    #[serde(default)]
+    pub reason: RevocationReason,
+}
+
+#[allow(clippy::type_complexity)]
+pub async fn revoke_handler(
+    Extension((g, cg, ar, cr, u, q)): Extension<(
+        Arc<dyn Get>,
+        Arc<dyn GetCert>,
+        Arc<dyn acme::Revoke>,
+        Arc<dyn certificate::Revoke>,
+        Arc<dyn Update>,
+        Arc<dyn Queue>,
+    )>,
+    Path(id): Path<Id>,
+    req: Option<Json<RevokeHandlerRequest>>,
+) -> Response<Body> {
+    let reason = req.map(|Json(r)| r.reason).unwrap_or_default();
+
+    match g.get(&id).await {
+        Ok(_) => {}
+
+        Err(GetError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(GetError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let Pair(_, cert_chain_pem) = match cg.get_cert(&id).await {
+        Ok(pair) => pair,
+
+        Err(GetCertError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("no certificate issued"))
+                .unwrap()
+        }
+
+        Err(GetCertError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    if ar.revoke(&cert_chain_pem, reason).await.is_err() {
+        return Response::builder()
+            .status(500)
+            .body(Body::from("failed to revoke certificate"))
+            .unwrap();
+    }
+
+    // Stop boundary nodes from serving the revoked certificate
+    match cr.revoke(&id).await {
+        Ok(()) => {}
+
+        Err(RevokeError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("no certificate issued"))
+                .unwrap()
+        }
+
+        Err(RevokeError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    // Issue a replacement certificate
+    match u.update(&id, &UpdateType::State(State::PendingOrder)).await {
+        Ok(()) => {}
+
+        Err(UpdateError::NotFound) => {
+            return Response::builder()
+                .status(404)
+                .body(Body::from("not found"))
+                .unwrap()
+        }
+
+        Err(UpdateError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let t = match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(t) => t.as_nanos() as u64,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    if (q.queue(&id, t, Priority::Normal).await).is_err() {
+        return Response::builder()
+            .status(500)
+            .body(Body::from("unexpected error"))
+            .unwrap();
+    }
+
+    Response::builder().status(200).body(Body::empty()).unwrap()
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportHandlerRequest {
+    pub name: Id,
+
+    #[serde(default)]
+    pub alt_names: Vec<Id>,
+
+    pub key: String,         // PEM-encoded (PKCS#8) private key
+    pub certificate: String, // PEM-encoded certificate chain
+}
+
+impl Validate for ImportHandlerRequest {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        name_field(body, "name", errors);
+        names_field(body, "alt_names", errors);
+    }
+}
+
+// Imports an externally issued certificate, e.g. when migrating from another issuance pipeline.
+// The registration is created as available and its certificate is renewed as usual from then on.
+#[allow(clippy::type_complexity)]
+pub async fn import_handler(
+    Extension((n, ck, c, up, rp, u, rm, q)): Extension<(
+        Arc<dyn Normalize>,
+        Arc<dyn Check>,
+        Arc<dyn Create>,
+        Arc<dyn Upload>,
+        Arc<RenewalPolicy>,
+        Arc<dyn Update>,
+        Arc<dyn Remove>,
+        Arc<dyn Queue>,
+    )>,
+    Valid(ImportHandlerRequest {
+        name,
+        alt_names,
+        key,
+        certificate,
+    }): Valid<ImportHandlerRequest>,
+) -> Response<Body> {
+    if alt_names.len() > ifc::ALT_NAMES_MAX_LEN {
+        return Response::builder()
+            .status(400)
+            .body(Body::from(format!(
+                "at most {} alternative names are allowed",
+                ifc::ALT_NAMES_MAX_LEN
+            )))
+            .unwrap();
+    }
+
+    let names = match normalize_names(n.as_ref(), once(&name).chain(&alt_names)) {
+        Ok(names) => names,
+        Err(err) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from(err.to_string()))
+                .unwrap()
+        }
+    };
+
+    let now = SystemTime::now();
+
+    let Imported {
+        not_after,
+        key_type,
+    } = match import::validate(&names, key.as_bytes(), certificate.as_bytes(), now) {
+        Ok(imported) => imported,
+        Err(err) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from(err.to_string()))
+                .unwrap()
+        }
+    };
+
+    // All names must be configured for the same canister, as for new registrations
+    let mut canister = None;
+
+    for name in &names {
+        match ck.check(name).await {
+            Ok(c) if canister.map_or(true, |canister| canister == c) => canister = Some(c),
+            Ok(_) => {
+                return Response::builder()
+                    .status(400)
+                    .body(Body::from(format!(
+                        "alternative name {name} points to a different canister"
+                    )))
+                    .unwrap()
+            }
+            Err(CheckError::UnexpectedError(_)) => {
+                return Response::builder()
+                    .status(500)
+                    .body(Body::from("unexpected error"))
+                    .unwrap()
+            }
+            Err(err) => {
+                return Response::builder()
+                    .status(400)
+                    .body(Body::from(err.to_string()))
+                    .unwrap()
+            }
+        }
+    }
+
+    let canister = match canister {
+        Some(canister) => canister,
+        None => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let id = match c.create(&names[0], &canister, key_type, &names[1..]).await {
+        Ok(id) => id,
+        Err(CreateError::Duplicate(id)) => {
+            return Response::builder()
+                .status(409)
+                .body(Body::from(format!("registration {id} already exists")))
+                .unwrap()
+        }
+        Err(CreateError::RateLimited(domain)) => {
+            return Response::builder()
+                .status(429)
+                .body(Body::from(format!(
+                    "rate limit exceeded for domain {}",
+                    domain
+                )))
+                .unwrap()
+        }
+        Err(CreateError::UnexpectedError(_)) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let imported = async {
+        up.upload(&id, Pair(key.into_bytes(), certificate.into_bytes()))
+            .await
+            .context("failed to upload certificate")?;
+
+        u.update(&id, &UpdateType::State(State::Available))
+            .await
+            .context("failed to update registration")?;
+
+        let t = rp
+            .renewal_time(not_after, now)
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos() as u64;
+
+        q.queue(&id, t, Priority::Normal)
+            .await
+            .context("failed to queue renewal")?;
+
+        Ok::<_, anyhow::Error>(())
+    }
+    .await;
+
+    if imported.is_err() {
+        // Don't leave behind a registration that nothing is queued for
+        let _ = rm.remove(&id).await;
+
+        return Response::builder()
+            .status(500)
+            .body(Body::from("unexpected error"))
+            .unwrap();
+    }
+
+    let bs = match serde_json::ser::to_vec(&CreateHandlerResponse { id }) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    Response::builder()
+        .status(200)
+        .body(Body::from(bs))
+        .unwrap()
+}
+
+#[derive(Default, Deserialize)]
+pub struct ExportHandlerQuery {
+    pub after: Option<Id>,
+    pub limit: Option<u64>,
+
+    // filters
+    pub suffix: Option<String>,
+    pub state: Option<String>,
+    pub updated_since: Option<u64>, // nanoseconds since the unix epoch
+}
+
+fn parse_state(s: &str) -> Option<ifc::State> {
+    Some(match s {
+        // Matches any failure reason
+        "failed" => ifc::State::Failed("".into()),
+        "pendingOrder" => ifc::State::PendingOrder,
+        "pendingChallengeResponse" => ifc::State::PendingChallengeResponse,
+        "pendingAcmeApproval" => ifc::State::PendingAcmeApproval,
+        "available" => ifc::State::Available,
+        // Matches any parking reason
+        "parked" => ifc::State::Parked("".into()),
+        _ => return None,
+    })
+}
+
+// Header carrying the ID to pass as `after` to obtain the next page
+pub const NEXT_PAGE_HEADER: &str = "x-next-after";
+
+// Content type of the streamed export, one package per line
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+pub async fn export_handler(
+    Extension(e): Extension<Arc<WithPagination>>,
+    headers: HeaderMap,
+    Query(ExportHandlerQuery {
+        after,
+        limit,
+        suffix,
+        state,
+        updated_since,
+    }): Query<ExportHandlerQuery>,
+) -> Response<Body> {
+    let limit = limit.unwrap_or(u64::MAX);
+
+    let state = match state.as_deref().map(parse_state) {
+        None => None,
+        Some(Some(state)) => Some(state),
+        Some(None) => {
+            return Response::builder()
+                .status(400)
+                .body(Body::from("invalid state"))
+                .unwrap()
+        }
+    };
+
+    let filter = ExportFilter {
+        name_suffix: suffix,
+        state,
+        updated_since,
+    };
+
+    let is_ndjson = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map_or(false, |v| v.contains(NDJSON_CONTENT_TYPE));
+
+    // Stream packages as they are exported instead of buffering the entire export.
+    // Errors occurring mid-stream abort the response, so clients have to check for a complete last line.
+    if is_ndjson {
+        let pkgs = e.stream(after, limit, filter).map(|pkg| {
+            let mut bs = serde_json::ser::to_vec(&pkg?).context("failed to serialize package")?;
+            bs.push(b'\n');
+            Ok::<_, anyhow::Error>(bs)
+        });
+
+        return Response::builder()
+            .status(200)
+            .header(CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+            .body(Body::wrap_stream(pkgs))
+            .unwrap();
+    }
+
+    let (pkgs, next) = match e
+        .export(
+            after,   // key
+            limit,   // limit
+            &filter, // filter
+        )
+        .await
+    {
+        Ok((pkgs, _, next)) => (pkgs, next),
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let bs = match serde_json::ser::to_vec(&pkgs) {
+        Ok(bs) => bs,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Body::from("unexpected error"))
+                .unwrap()
+        }
+    };
+
+    let mut resp = Response::builder().status(200);
+    if let Some(next) = next {
+        resp = resp.header(NEXT_PAGE_HEADER, next);
+    }
+
+    resp.body(Body::from(bs)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use anyhow::Error;
+    use certificate_orchestrator_interface::IcCertificate;
+    use futures::TryStreamExt;
+    use mockall::predicate;
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    use crate::{
+        acme::MockRevoke,
+        caa::{CaaError, MockCheckCaa},
+        certificate::{
+            MockExport, MockGetCert, MockRevoke as MockCanisterRevoke, MockUpload, Package, Pair,
+            UploadError,
+        },
+        check::MockCheck,
+        failure::Failure,
+        history::{IssuanceHistory, IssuedCertificate, MockGetHistory},
+        idn::{IdnPolicy, Normalizer},
+        rate_limit::{RateLimitMiddlewareArgs, RateLimiter},
+        registration::{MockCreate, MockGet, MockGetTimestamps, MockList, MockRemove, MockUpdate},
+        work::{MockQueue, ProcessError, QueueError},
+    };
+
+    // Rate limits of a caller with `tokens` creations left
+    fn rate_limit(tokens: u32) -> RequestRateLimit {
+        RequestRateLimit::new(
+            RateLimitMiddlewareArgs {
+                by_ip: Arc::new(RateLimiter::new(tokens, Duration::from_secs(60))),
+                by_identity: Arc::new(RateLimiter::new(tokens, Duration::from_secs(60))),
+                ip_header: None,
+            },
+            Some("1.1.1.1".into()),
+            Some(CallerIdentity("caller".into())),
+        )
+    }
+
+    #[tokio::test]
+    async fn validate_reports_all_checks() -> Result<(), Error> {
+        use axum::body::HttpBody;
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("www.name"))
+            .returning(|_| Ok(Principal::from_text("2ibo7-dia").unwrap()));
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker
+            .expect_check_caa()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| {
+                Err(CaaError::Unauthorized {
+                    src: "name".into(),
+                    identities: "letsencrypt.org".into(),
+                })
+            });
+        caa_checker
+            .expect_check_caa()
+            .times(1)
+            .with(predicate::eq("www.name"))
+            .returning(|_| Ok(()));
+
+        let resp = validate_handler(
+            Extension((
+                Arc::new(Normalizer(IdnPolicy::Allow)),
+                Arc::new(checker),
+                Arc::new(caa_checker),
+            )),
+            Valid(ValidateHandlerRequest {
+                name: "name".into(),
+                alt_names: vec!["www.name".into()],
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let bs = resp.into_body().data().await.unwrap()?;
+        let report: serde_json::Value = serde_json::from_slice(&bs)?;
+
+        assert_eq!(report["valid"], false);
+        assert_eq!(report["names"][0]["checks"][1]["check"], "caa");
+        assert!(report["names"][0]["checks"][1]["error"].is_string());
+        assert_eq!(report["names"][1]["checks"][1]["check"], "canister");
+        assert!(report["names"][1]["checks"][1]["error"].is_string());
+        assert!(report["names"][1]["checks"][2]["error"].is_null());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn create_duplicate_merged_or_rejected() -> Result<(), Error> {
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        let mut creator = MockCreate::new();
+        creator
+            .expect_create()
+            .times(2)
+            .returning(|_, _, _, _| Err(CreateError::Duplicate("id".into())));
+
+        let mut getter = MockGet::new();
+        getter.expect_get().times(2).returning(|_| {
+            Ok(Registration {
+                name: "name".into(),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Available,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut queuer = MockQueue::new();
+        queuer.expect_queue().never();
+
+        let ext: (
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn Create>,
+            Arc<dyn Get>,
+            Arc<dyn Queue>,
+        ) = (
+            Arc::new(Normalizer(IdnPolicy::Allow)),
+            Arc::new(checker),
+            Arc::new(creator),
+            Arc::new(getter),
+            Arc::new(queuer),
+        );
+
+        // The same name in another form is merged with the existing registration
+        let resp = create_handler(
+            Extension(ext.clone()),
+            Valid(CreateHandlerRequest {
+                name: "NAME.".into(),
+                key_type: None,
+                alt_names: vec![],
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), 200);
+
+        // A registration only partially covering the same names is rejected
+        let resp = create_handler(
+            Extension(ext),
+            Valid(CreateHandlerRequest {
+                name: "name".into(),
+                key_type: None,
+                alt_names: vec!["www.name".into()],
+            }),
+        )
+        .await;
+        assert_eq!(resp.status(), 409);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn batch_create_reports_per_name() -> Result<(), Error> {
+        use axum::body::HttpBody;
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .with(predicate::eq("a.name"))
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+        checker
+            .expect_check()
+            .with(predicate::eq("b.name"))
+            .returning(|_| Ok(Principal::from_text("2ibo7-dia").unwrap()));
+
+        let mut creator = MockCreate::new();
+        creator
+            .expect_create()
+            .times(1)
+            .with(
+                predicate::eq("a.name"),
+                predicate::always(),
+                predicate::always(),
+                predicate::always(),
+            )
+            .returning(|_, _, _, _| Ok("id".into()));
+
+        let mut queuer = MockQueue::new();
+        queuer.expect_queue().times(1).returning(|_, _, _| Ok(()));
+
+        let ext: (
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn Create>,
+            Arc<dyn Get>,
+            Arc<dyn Queue>,
+        ) = (
+            Arc::new(Normalizer(IdnPolicy::Allow)),
+            Arc::new(checker),
+            Arc::new(creator),
+            Arc::new(MockGet::new()),
+            Arc::new(queuer),
+        );
+
+        let resp = batch_create_handler(
+            Extension(ext.clone()),
+            Extension(rate_limit(2)),
+            Valid(BatchCreateHandlerRequest {
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                names: vec!["a.name".into(), "b.name".into()],
+                key_type: None,
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let bs = resp.into_body().data().await.unwrap()?;
+        let out: serde_json::Value = serde_json::from_slice(&bs)?;
+
+        assert_eq!(out["results"][0]["status"], 200);
+        assert_eq!(out["results"][0]["id"], "id");
+        assert_eq!(out["results"][1]["name"], "b.name");
+        assert_eq!(out["results"][1]["status"], 400);
+        assert!(out["results"][1]["error"].is_string());
+
+        // Names given twice are rejected as a whole
+        let resp = batch_create_handler(
+            Extension(ext.clone()),
+            Extension(rate_limit(2)),
+            Valid(BatchCreateHandlerRequest {
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                names: vec!["a.name".into(), "A.name.".into()],
+                key_type: None,
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 400);
+
+        // Each name takes a token, so batches cannot exceed the creation rate limit
+        let resp = batch_create_handler(
+            Extension(ext),
+            Extension(rate_limit(1)),
+            Valid(BatchCreateHandlerRequest {
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                names: vec!["c.name".into(), "d.name".into()],
+                key_type: None,
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 429);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn get_failed_with_code() -> Result<(), Error> {
+        use axum::body::HttpBody;
+
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: "name".into(),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Failed(
+                    Failure::classify(&ProcessError::FailedCaaCheck(CaaError::Unauthorized {
+                        src: "name".into(),
+                        identities: "letsencrypt.org".into(),
+                    }))
+                    .to_string(),
+                ),
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut timestamps_getter = MockGetTimestamps::new();
+        timestamps_getter
+            .expect_get_timestamps()
+            .times(1)
+            .returning(|_| {
+                Ok(Timestamps {
+                    created_at: Some(1),
+                    last_state_change_at: Some(2),
+                    ..Default::default()
+                })
+            });
+
+        let ext: (Arc<dyn Get>, Arc<dyn GetTimestamps>) =
+            (Arc::new(getter), Arc::new(timestamps_getter));
+
+        let resp = get_handler(
+            Extension(ext),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let bs = resp.into_body().data().await.unwrap()?;
+        let reg: serde_json::Value = serde_json::from_slice(&bs)?;
+
+        assert_eq!(reg["name"], "name");
+        assert_eq!(reg["created_at"], 1);
+        assert_eq!(reg["last_state_change_at"], 2);
+        assert!(reg.get("next_scheduled_at").is_none());
+        assert_eq!(reg["error"]["code"], "CAA_FORBIDS_CA");
+        assert_eq!(reg["error"]["record"], "name");
+        assert!(reg["error"]["hint"].is_string());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_ok() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| {
+                Ok(Registration {
+                    name: String::from("name"),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: vec![],
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| Ok(Principal::from_text("2ibo7-dia").unwrap()));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::Canister(
+                    Principal::from_text("2ibo7-dia").unwrap(),
+                )),
+            )
+            .returning(|_, _| Ok(()));
+
+        let resp = update_handler(
+            Extension((Arc::new(checker), Arc::new(getter), Arc::new(updater))),
+            Path("id".into()),
+            Valid(None),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_skip() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| {
+                Ok(Registration {
+                    name: String::from("name"),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: vec![],
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        let mut updater = MockUpdate::new();
+        updater.expect_update().never();
+
+        let resp = update_handler(
+            Extension((Arc::new(checker), Arc::new(getter), Arc::new(updater))),
+            Path("id".into()),
+            Valid(None),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_canister_mismatch() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| {
+                Ok(Registration {
+                    name: String::from("name"),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::Available,
+                    key_type: None,
+                    alt_names: vec![],
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        let mut updater = MockUpdate::new();
+        updater.expect_update().never();
+
+        let resp = update_handler(
+            Extension((Arc::new(checker), Arc::new(getter), Arc::new(updater))),
+            Path("id".into()),
+            Valid(Some(UpdateHandlerRequest {
+                canister: Some(Principal::from_text("2ibo7-dia").unwrap()),
+            })),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 409);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_ok() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| {
+                Ok(Registration {
+                    name: String::from("name"),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: vec![],
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| {
+                Err(CheckError::MissingDnsCname {
+                    src: "src".into(),
+                    dst: "dst".into(),
+                })
+            });
+
+        let mut remover = MockRemove::new();
+        remover
+            .expect_remove()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| Ok(()));
+
+        let resp = remove_handler(
+            Extension((Arc::new(checker), Arc::new(getter), Arc::new(remover))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_bad_request() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
             .returning(|_| {
                 Ok(Registration {
                     name: String::from("name"),
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: vec![],
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        let mut remover = MockRemove::new();
+        remover.expect_remove().never();
+
+        let resp = remove_handler(
+            Extension((Arc::new(checker), Arc::new(getter), Arc::new(remover))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_page() -> Result<(), Error> {
+        let mut exporter = MockExport::new();
+        exporter
+            .expect_export()
+            .times(1)
+            .with(
+                predicate::eq(Some(Id::from("a"))),
+                predicate::eq(1), // page size
+                predicate::eq(ExportFilter::default()),
+            )
+            .returning(|_, _, _| {
+                Ok((
+                    vec![Package {
+                        id: "b".into(),
+                        name: "name".into(),
+                        alt_names: vec![],
+                        canister: Principal::from_text("aaaaa-aa").unwrap(),
+                        pair: Pair(vec![], vec![]),
+                    }],
+                    IcCertificate {
+                        cert: vec![],
+                        tree: vec![],
+                    },
+                    Some("b".into()),
+                ))
+            });
+
+        let resp = export_handler(
+            Extension(Arc::new(WithPagination(Arc::new(exporter), 1))),
+            HeaderMap::new(),
+            Query(ExportHandlerQuery {
+                after: Some("a".into()),
+                limit: Some(1),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(NEXT_PAGE_HEADER).unwrap(), "b");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_filtered() -> Result<(), Error> {
+        let mut exporter = MockExport::new();
+        exporter
+            .expect_export()
+            .times(1)
+            .with(
+                predicate::eq(None),
+                predicate::eq(50), // page size
+                predicate::eq(ExportFilter {
+                    name_suffix: Some(".example.com".into()),
+                    state: Some(ifc::State::Available),
+                    updated_since: Some(1),
+                }),
+            )
+            .returning(|_, _, _| {
+                Ok((
+                    vec![],
+                    IcCertificate {
+                        cert: vec![],
+                        tree: vec![],
+                    },
+                    None,
+                ))
+            });
+
+        let resp = export_handler(
+            Extension(Arc::new(WithPagination(Arc::new(exporter), 50))),
+            HeaderMap::new(),
+            Query(ExportHandlerQuery {
+                suffix: Some(".example.com".into()),
+                state: Some("available".into()),
+                updated_since: Some(1),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(NEXT_PAGE_HEADER).is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_invalid_state() -> Result<(), Error> {
+        let mut exporter = MockExport::new();
+        exporter.expect_export().never();
+
+        let resp = export_handler(
+            Extension(Arc::new(WithPagination(Arc::new(exporter), 50))),
+            HeaderMap::new(),
+            Query(ExportHandlerQuery {
+                state: Some("unknown".into()),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 400);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_parked() -> Result<(), Error> {
+        let mut lister = MockList::new();
+        lister
+            .expect_list()
+            .times(1)
+            .withf(|key, limit, state| {
+                key.is_none() && *limit == 1 && matches!(state, Some(State::Parked(_)))
+            })
+            .returning(|_, _, _| {
+                Ok(vec![(
+                    "id".into(),
+                    Registration {
+                        name: "name".into(),
+                        canister: Principal::from_text("aaaaa-aa").unwrap(),
+                        state: State::Parked("error".into()),
+                        key_type: None,
+                        alt_names: vec![],
+                    },
+                )])
+            });
+
+        let resp = list_handler(
+            Extension(Arc::new(lister)),
+            Query(ListHandlerQuery {
+                limit: Some(1),
+                state: Some("parked".into()),
+                ..Default::default()
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(NEXT_PAGE_HEADER).unwrap(), "id");
+
+        let bs = resp
+            .into_body()
+            .try_fold(Vec::new(), |mut acc, bs| async move {
+                acc.extend_from_slice(&bs);
+                Ok(acc)
+            })
+            .await?;
+        let regs: serde_json::Value = serde_json::from_slice(&bs)?;
+        assert_eq!(regs[0]["id"], "id");
+        assert_eq!(regs[0]["name"], "name");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn issuance_history() -> Result<(), Error> {
+        let mut getter = MockGetHistory::new();
+        getter
+            .expect_get_history()
+            .times(1)
+            .with(predicate::eq(String::from("id")))
+            .returning(|_| {
+                Ok(IssuanceHistory {
+                    attempts: vec![1],
+                    certificates: vec![IssuedCertificate {
+                        serial: "01ab".into(),
+                        not_before: 2,
+                        not_after: 3,
+                        issued_at: 4,
+                    }],
+                    ..Default::default()
                 })
             });
+        getter
+            .expect_get_history()
+            .times(1)
+            .with(predicate::eq(String::from("other")))
+            .returning(|_| Err(HistoryError::NotFound));
+
+        let getter: Arc<dyn GetHistory> = Arc::new(getter);
+
+        let resp = issuance_history_handler(
+            Extension(getter.clone()),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        let bs = resp
+            .into_body()
+            .try_fold(Vec::new(), |mut acc, bs| async move {
+                acc.extend_from_slice(&bs);
+                Ok(acc)
+            })
+            .await?;
+        let h: serde_json::Value = serde_json::from_slice(&bs)?;
+        assert_eq!(h["attempts"][0], 1);
+        assert_eq!(h["certificates"][0]["serial"], "01ab");
+        assert_eq!(h["certificates"][0]["notAfter"], 3);
+        assert!(h["failures"].as_array().unwrap().is_empty());
+
+        let resp = issuance_history_handler(
+            Extension(getter),
+            Path("other".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 404);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn audit_by_id() -> Result<(), Error> {
+        let mut querier = audit::MockQuery::new();
+        querier
+            .expect_query()
+            .times(1)
+            .withf(|id, after, limit| {
+                id.as_deref() == Some("id") && *after == Some(3) && *limit == 1
+            })
+            .returning(|_, _, _| {
+                Ok(vec![audit::AuditRecord {
+                    seq: 7,
+                    entry: audit::AuditEntry {
+                        timestamp: 0,
+                        actor: "operator".into(),
+                        id: "id".into(),
+                        action: audit::AuditAction::Remove,
+                        from_state: Some(State::Available),
+                        to_state: None,
+                        cause: "DELETE /registrations/:id".into(),
+                    },
+                }])
+            });
+
+        let resp = audit_handler(
+            Extension(Arc::new(querier)),
+            Query(AuditHandlerQuery {
+                id: Some("id".into()),
+                after: Some(3),
+                limit: Some(1),
+            }),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(NEXT_PAGE_HEADER).unwrap(), "7");
+
+        let bs = resp
+            .into_body()
+            .try_fold(Vec::new(), |mut acc, bs| async move {
+                acc.extend_from_slice(&bs);
+                Ok(acc)
+            })
+            .await?;
+        let rs: serde_json::Value = serde_json::from_slice(&bs)?;
+        assert_eq!(rs[0]["seq"], 7);
+        assert_eq!(rs[0]["action"], "remove");
+        assert_eq!(rs[0]["actor"], "operator");
+        assert!(rs[0].get("to_state").is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_parked() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Parked("error".into()),
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::State(State::PendingOrder)),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .returning(|_, _, _| Ok::<_, QueueError>(()));
+
+        let resp = retry_handler(
+            Extension((Arc::new(getter), Arc::new(updater), Arc::new(queuer))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retry_not_parked() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::PendingOrder,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut updater = MockUpdate::new();
+        updater.expect_update().never();
+
+        let mut queuer = MockQueue::new();
+        queuer.expect_queue().never();
+
+        let resp = retry_handler(
+            Extension((Arc::new(getter), Arc::new(updater), Arc::new(queuer))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 409);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pause_and_resume() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::PendingChallengeResponse,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::State(State::Parked(PAUSED_REASON.into()))),
+            )
+            .returning(|_, _| Ok(()));
+
+        let resp = pause_handler(
+            Extension((Arc::new(getter), Arc::new(updater))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        // Without a certificate, resuming starts over with a new order
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Parked(PAUSED_REASON.into()),
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut cert_getter = MockGetCert::new();
+        cert_getter
+            .expect_get_cert()
+            .times(1)
+            .returning(|_| Err(GetCertError::NotFound));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::State(State::PendingOrder)),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .returning(|_, _, _| Ok::<_, QueueError>(()));
+
+        let resp = resume_handler(
+            Extension((
+                Arc::new(getter),
+                Arc::new(cert_getter),
+                Arc::new(RenewalPolicy::new(Duration::ZERO, Duration::ZERO)),
+                Arc::new(updater),
+                Arc::new(queuer),
+            )),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn renew_not_available() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::PendingOrder,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut queuer = MockQueue::new();
+        queuer.expect_queue().never();
+
+        let resp = renew_handler(
+            Extension((Arc::new(getter), Arc::new(queuer))),
+            Path("id".into()),
+            Request::builder().body(Body::empty())?,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 409);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_key_compromise() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Available,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut cert_getter = MockGetCert::new();
+        cert_getter
+            .expect_get_cert()
+            .times(1)
+            .returning(|_| Ok(Pair(b"key".to_vec(), b"cert".to_vec())));
+
+        let mut acme_revoker = MockRevoke::new();
+        acme_revoker
+            .expect_revoke()
+            .times(1)
+            .with(
+                predicate::eq(&b"cert"[..]),
+                predicate::eq(RevocationReason::KeyCompromise),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut canister_revoker = MockCanisterRevoke::new();
+        canister_revoker
+            .expect_revoke()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| Ok(()));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::State(State::PendingOrder)),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .returning(|_, _, _| Ok::<_, QueueError>(()));
+
+        let resp = revoke_handler(
+            Extension((
+                Arc::new(getter),
+                Arc::new(cert_getter),
+                Arc::new(acme_revoker),
+                Arc::new(canister_revoker),
+                Arc::new(updater),
+                Arc::new(queuer),
+            )),
+            Path("id".into()),
+            Some(Json(serde_json::from_str(
+                r#"{"reason": "keyCompromise"}"#,
+            )?)),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn revoke_acme_failure() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Available,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut cert_getter = MockGetCert::new();
+        cert_getter
+            .expect_get_cert()
+            .times(1)
+            .returning(|_| Ok(Pair(b"key".to_vec(), b"cert".to_vec())));
+
+        let mut acme_revoker = MockRevoke::new();
+        acme_revoker
+            .expect_revoke()
+            .times(1)
+            .returning(|_, _| Err(anyhow::anyhow!("failed")));
+
+        // The certificate keeps being served until it's revoked by the provider
+        let mut canister_revoker = MockCanisterRevoke::new();
+        canister_revoker.expect_revoke().never();
+
+        let mut updater = MockUpdate::new();
+        updater.expect_update().never();
+
+        let mut queuer = MockQueue::new();
+        queuer.expect_queue().never();
+
+        let resp = revoke_handler(
+            Extension((
+                Arc::new(getter),
+                Arc::new(cert_getter),
+                Arc::new(acme_revoker),
+                Arc::new(canister_revoker),
+                Arc::new(updater),
+                Arc::new(queuer),
+            )),
+            Path("id".into()),
+            None,
+        )
+        .await;
+
+        assert_eq!(resp.status(), 500);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn export_ndjson() -> Result<(), Error> {
+        let mut exporter = MockExport::new();
+        exporter.expect_export().times(1).returning(|_, _, _| {
+            Ok((
+                ["a", "b"]
+                    .into_iter()
+                    .map(|id| Package {
+                        id: id.into(),
+                        name: "name".into(),
+                        alt_names: vec![],
+                        canister: Principal::from_text("aaaaa-aa").unwrap(),
+                        pair: Pair(vec![], vec![]),
+                    })
+                    .collect(),
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                None,
+            ))
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, NDJSON_CONTENT_TYPE.parse()?);
+
+        let resp = export_handler(
+            Extension(Arc::new(WithPagination(Arc::new(exporter), 50))),
+            headers,
+            Query(ExportHandlerQuery::default()),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            NDJSON_CONTENT_TYPE
+        );
+
+        let bs = resp
+            .into_body()
+            .try_fold(Vec::new(), |mut acc, bs| async move {
+                acc.extend_from_slice(&bs);
+                Ok(acc)
+            })
+            .await?;
+
+        let ids: Vec<String> = String::from_utf8(bs)?
+            .lines()
+            .map(|l| Ok(serde_json::from_str::<Package>(l)?.id))
+            .collect::<Result<_, Error>>()?;
+
+        assert_eq!(ids, vec!["a", "b"]);
+
+        Ok(())
+    }
+
+    fn import_request() -> Result<ImportHandlerRequest, Error> {
+        let mut params = CertificateParams::new(vec!["name.com".into(), "www.name.com".into()]);
+        params.not_before = date_time_ymd(2020, 1, 1);
+        params.not_after = date_time_ymd(2100, 1, 1);
+
+        let cert = Certificate::from_params(params)?;
+
+        Ok(ImportHandlerRequest {
+            name: "name.com".into(),
+            alt_names: vec!["www.name.com".into()],
+            key: cert.serialize_private_key_pem(),
+            certificate: cert.serialize_pem()?,
+        })
+    }
+
+    #[tokio::test]
+    async fn import_ok() -> Result<(), Error> {
+        let req = import_request()?;
 
         let mut checker = MockCheck::new();
         checker
             .expect_check()
+            .times(2)
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        let mut creator = MockCreate::new();
+        creator
+            .expect_create()
             .times(1)
-            .with(predicate::eq("name"))
+            .withf(|name, _, key_type, alt_names| {
+                name == "name.com"
+                    && *key_type == Some(KeyType::EcdsaP256)
+                    && alt_names == ["www.name.com"]
+            })
+            .returning(|_, _, _, _| Ok("id".into()));
+
+        let mut uploader = MockUpload::new();
+        uploader
+            .expect_upload()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(Pair(
+                    req.key.clone().into_bytes(),
+                    req.certificate.clone().into_bytes(),
+                )),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq(Id::from("id")),
+                predicate::eq(UpdateType::State(State::Available)),
+            )
+            .returning(|_, _| Ok(()));
+
+        // Renewal is scheduled ahead of the certificate's expiry
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .withf(|id, t, priority| {
+                id == "id"
+                    && *priority == Priority::Normal
+                    && *t > 4_000_000_000 * 1_000_000_000
+                    && *t < 4_102_444_800 * 1_000_000_000
+            })
+            .returning(|_, _, _| Ok::<_, QueueError>(()));
+
+        let resp = import_handler(
+            Extension((
+                Arc::new(Normalizer(IdnPolicy::Allow)),
+                Arc::new(checker),
+                Arc::new(creator),
+                Arc::new(uploader),
+                Arc::new(RenewalPolicy::new(
+                    Duration::from_secs(30 * 24 * 3600),
+                    Duration::ZERO,
+                )),
+                Arc::new(updater),
+                Arc::new(MockRemove::new()),
+                Arc::new(queuer),
+            )),
+            Valid(req),
+        )
+        .await;
+
+        assert_eq!(resp.status(), 200);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_upload_failure() -> Result<(), Error> {
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(2)
             .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
 
+        let mut creator = MockCreate::new();
+        creator
+            .expect_create()
+            .times(1)
+            .returning(|_, _, _, _| Ok("id".into()));
+
+        let mut uploader = MockUpload::new();
+        uploader
+            .expect_upload()
+            .times(1)
+            .returning(|_, _| Err(UploadError::UnexpectedError(anyhow::anyhow!("error"))));
+
+        // The registration is removed again, rather than left without a certificate
         let mut remover = MockRemove::new();
-        remover.expect_remove().never();
+        remover
+            .expect_remove()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| Ok(()));
 
-        let resp = remove_handler(
-            Extension((Arc::new(checker), Arc::new(getter), Arc::new(remover))),
-            Path("id".into()),
-            Request::builder().body(Body::empty())?,
+        let resp = import_handler(
+            Extension((
+                Arc::new(Normalizer(IdnPolicy::Allow)),
+                Arc::new(checker),
+                Arc::new(creator),
+                Arc::new(uploader),
+                Arc::new(RenewalPolicy::new(Duration::ZERO, Duration::ZERO)),
+                Arc::new(MockUpdate::new()),
+                Arc::new(remover),
+                Arc::new(MockQueue::new()),
+            )),
+            Valid(import_request()?),
         )
         .await;
 
-        assert_eq!(resp.status(), 400);
+        assert_eq!(resp.status(), 500);
 
         Ok(())
     }
@@ -0,0 +1,580 @@
+use std::{
+    fs::{File, OpenOptions},
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use axum::{extract::MatchedPath, http::Request, middleware::Next, response::IntoResponse};
+use candid::{Decode, Encode, Principal};
+use certificate_orchestrator_interface as ifc;
+use ic_agent::Agent;
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::{
+    acme::KeyType,
+    api::CallerIdentity,
+    registration::{
+        Create, CreateError, Get, Id, Remove, RemoveError, State, Update, UpdateError, UpdateType,
+    },
+};
+
+// Actor of mutations made outside of API requests, e.g. by the worker
+pub const ISSUER_ACTOR: &str = "issuer";
+
+const ANONYMOUS_ACTOR: &str = "anonymous";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Create,
+    Update,
+    Remove,
+}
+
+impl From<AuditAction> for ifc::AuditAction {
+    fn from(a: AuditAction) -> Self {
+        match a {
+            AuditAction::Create => ifc::AuditAction::Create,
+            AuditAction::Update => ifc::AuditAction::Update,
+            AuditAction::Remove => ifc::AuditAction::Remove,
+        }
+    }
+}
+
+impl From<ifc::AuditAction> for AuditAction {
+    fn from(a: ifc::AuditAction) -> Self {
+        match a {
+            ifc::AuditAction::Create => AuditAction::Create,
+            ifc::AuditAction::Update => AuditAction::Update,
+            ifc::AuditAction::Remove => AuditAction::Remove,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64, // nanoseconds since the unix epoch
+    pub actor: String,
+    pub id: Id,
+    pub action: AuditAction,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from_state: Option<State>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub to_state: Option<State>,
+
+    // e.g. the API route or the task that made the mutation
+    pub cause: String,
+}
+
+impl From<AuditEntry> for ifc::AuditEntry {
+    fn from(e: AuditEntry) -> Self {
+        ifc::AuditEntry {
+            timestamp: e.timestamp,
+            actor: e.actor.into(),
+            id: e.id,
+            action: e.action.into(),
+            from_state: e.from_state.map(Into::into),
+            to_state: e.to_state.map(Into::into),
+            cause: e.cause.into(),
+        }
+    }
+}
+
+impl From<ifc::AuditEntry> for AuditEntry {
+    fn from(e: ifc::AuditEntry) -> Self {
+        AuditEntry {
+            timestamp: e.timestamp,
+            actor: e.actor.into(),
+            id: e.id,
+            action: e.action.into(),
+            from_state: e.from_state.map(Into::into),
+            to_state: e.to_state.map(Into::into),
+            cause: e.cause.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub seq: u64,
+
+    #[serde(flatten)]
+    pub entry: AuditEntry,
+}
+
+// Who is making mutations and why, for the duration of a request or task
+#[derive(Clone, Debug)]
+pub struct AuditContext {
+    pub actor: String,
+    pub cause: String,
+}
+
+tokio::task_local! {
+    static CONTEXT: AuditContext;
+}
+
+pub async fn with_context<F: Future>(ctx: AuditContext, f: F) -> F::Output {
+    CONTEXT.scope(ctx, f).await
+}
+
+fn context() -> AuditContext {
+    CONTEXT
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| AuditContext {
+            actor: ISSUER_ACTOR.into(),
+            cause: "unknown".into(),
+        })
+}
+
+// Attributes mutations made by a request to its caller, needs to run after authentication
+pub async fn audit_mw<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let actor = req
+        .extensions()
+        .get::<CallerIdentity>()
+        .map_or(ANONYMOUS_ACTOR.into(), |CallerIdentity(name)| name.clone());
+
+    let path = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(req.uri().path(), |p| p.as_str());
+
+    let cause = format!("{} {path}", req.method());
+
+    with_context(AuditContext { actor, cause }, next.run(req)).await
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[automock]
+#[async_trait]
+pub trait Append: Send + Sync {
+    async fn append(&self, entry: AuditEntry) -> Result<u64, AuditError>;
+}
+
+#[async_trait]
+impl<T: Append + ?Sized> Append for Arc<T> {
+    async fn append(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        (**self).append(entry).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait Query: Send + Sync {
+    // Entries after the given sequence number, oldest first
+    async fn query(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<AuditRecord>, AuditError>;
+}
+
+#[async_trait]
+impl<T: Query + ?Sized> Query for Arc<T> {
+    async fn query(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<AuditRecord>, AuditError> {
+        (**self).query(id, after, limit).await
+    }
+}
+
+// Appends entries to a file, one JSON object per line
+pub struct FileAuditLog {
+    path: PathBuf,
+    file: Mutex<(File, u64)>, // next sequence number
+}
+
+impl FileAuditLog {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let next = match File::open(path) {
+            Ok(f) => match BufReader::new(f).lines().last() {
+                Some(line) => {
+                    let r: AuditRecord = serde_json::from_str(&line?)
+                        .context("failed to parse last audit log entry")?;
+                    r.seq + 1
+                }
+                None => 0,
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(Error::new(err).context("failed to open audit log")),
+        };
+
+        let f = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .context("failed to open audit log")?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            file: Mutex::new((f, next)),
+        })
+    }
+}
+
+#[async_trait]
+impl Append for FileAuditLog {
+    async fn append(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        let mut file = self.file.lock().unwrap();
+        let (f, next) = &mut *file;
+
+        let seq = *next;
+        let mut line = serde_json::to_vec(&AuditRecord { seq, entry })
+            .context("failed to serialize audit entry")?;
+        line.push(b'\n');
+
+        // A single write per entry, so that entries are never interleaved
+        f.write_all(&line)
+            .and_then(|_| f.flush())
+            .context("failed to write audit entry")?;
+
+        *next += 1;
+
+        Ok(seq)
+    }
+}
+
+#[async_trait]
+impl Query for FileAuditLog {
+    async fn query(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<AuditRecord>, AuditError> {
+        let f = File::open(&self.path).context("failed to open audit log")?;
+
+        let mut out = vec![];
+        for line in BufReader::new(f).lines() {
+            if out.len() as u64 >= limit {
+                break;
+            }
+
+            let r: AuditRecord = serde_json::from_str(&line.context("failed to read audit log")?)
+                .context("failed to parse audit entry")?;
+
+            if after.map_or(false, |after| r.seq <= after) {
+                continue;
+            }
+
+            if id.as_ref().map_or(false, |id| &r.entry.id != id) {
+                continue;
+            }
+
+            out.push(r);
+        }
+
+        Ok(out)
+    }
+}
+
+pub struct CanisterAuditLog(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl Append for CanisterAuditLog {
+    async fn append(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        use ifc::{RecordAuditEntryError as Error, RecordAuditEntryResponse as Response};
+
+        let entry: ifc::AuditEntry = entry.into();
+        let args = Encode!(&entry).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .update(&self.1, "recordAuditEntry")
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(seq) => Ok(seq),
+            Response::Err(err) => Err(match err {
+                Error::Unauthorized => AuditError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => AuditError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl Query for CanisterAuditLog {
+    async fn query(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<AuditRecord>, AuditError> {
+        use ifc::{ListAuditEntriesError as Error, ListAuditEntriesResponse as Response};
+
+        let args = Encode!(&id, &after, &limit).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .query(&self.1, "listAuditEntries")
+            .with_arg(args)
+            .call()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(entries) => Ok(entries
+                .into_iter()
+                .map(|(seq, entry)| AuditRecord {
+                    seq,
+                    entry: entry.into(),
+                })
+                .collect()),
+            Response::Err(err) => Err(match err {
+                Error::Unauthorized => AuditError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => AuditError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+// Records successful mutations of registrations. Recording is best-effort, as the
+// mutation has already happened by then, so failures are logged rather than returned.
+pub struct WithAudit<T> {
+    inner: T,
+    getter: Arc<dyn Get>,
+    appender: Arc<dyn Append>,
+}
+
+impl<T> WithAudit<T> {
+    pub fn new(inner: T, getter: Arc<dyn Get>, appender: Arc<dyn Append>) -> Self {
+        Self {
+            inner,
+            getter,
+            appender,
+        }
+    }
+
+    async fn state(&self, id: &Id) -> Option<State> {
+        match self.getter.get(id).await {
+            Ok(reg) => Some(reg.state),
+            Err(err) => {
+                warn!(%id, error = ?err, "failed to get registration state for audit log");
+                None
+            }
+        }
+    }
+
+    async fn record(
+        &self,
+        id: &Id,
+        action: AuditAction,
+        from_state: Option<State>,
+        to_state: Option<State>,
+    ) {
+        let AuditContext { actor, cause } = context();
+
+        let entry = AuditEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_nanos() as u64),
+            actor,
+            id: id.to_owned(),
+            action,
+            from_state,
+            to_state,
+            cause,
+        };
+
+        if let Err(err) = self.appender.append(entry.clone()).await {
+            warn!(?entry, error = ?err, "failed to record audit entry");
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Create> Create for WithAudit<T> {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        let id = self
+            .inner
+            .create(name, canister, key_type, alt_names)
+            .await?;
+
+        // Registrations are created pending an order
+        self.record(&id, AuditAction::Create, None, Some(State::PendingOrder))
+            .await;
+
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl<T: Update> Update for WithAudit<T> {
+    async fn update(&self, id: &Id, typ: &UpdateType) -> Result<(), UpdateError> {
+        let from_state = self.state(id).await;
+
+        self.inner.update(id, typ).await?;
+
+        let to_state = match typ {
+            UpdateType::State(state) => Some(state.to_owned()),
+            UpdateType::Canister(_) => from_state.clone(),
+        };
+
+        self.record(id, AuditAction::Update, from_state, to_state)
+            .await;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Remove> Remove for WithAudit<T> {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        let from_state = self.state(id).await;
+
+        self.inner.remove(id).await?;
+
+        self.record(id, AuditAction::Remove, from_state, None).await;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mockall::predicate;
+
+    use crate::registration::{MockGet, MockUpdate, Registration};
+
+    fn entry(id: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: 0,
+            actor: "operator".into(),
+            id: id.into(),
+            action: AuditAction::Update,
+            from_state: Some(State::PendingOrder),
+            to_state: Some(State::Available),
+            cause: "POST /admin/registrations/:id/retry".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn file_append_and_query() -> Result<(), Error> {
+        let path = std::env::temp_dir().join(format!("audit-{}.log", rand::random::<u64>()));
+
+        let log = FileAuditLog::open(&path)?;
+        for id in ["a", "b", "a"] {
+            log.append(entry(id)).await?;
+        }
+
+        let seqs = |rs: Vec<AuditRecord>| -> Vec<u64> { rs.iter().map(|r| r.seq).collect() };
+
+        assert_eq!(seqs(log.query(None, None, 10).await?), vec![0, 1, 2]);
+        assert_eq!(
+            seqs(log.query(Some("a".into()), None, 10).await?),
+            vec![0, 2]
+        );
+        assert_eq!(seqs(log.query(None, Some(0), 1).await?), vec![1]);
+
+        // Sequence numbers continue after reopening
+        let log = FileAuditLog::open(&path)?;
+        assert_eq!(log.append(entry("c")).await?, 3);
+
+        std::fs::remove_file(&path)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn update_is_recorded_with_context() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: "example.com".into(),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::Parked("paused".into()),
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut updater = MockUpdate::new();
+        updater.expect_update().times(1).returning(|_, _| Ok(()));
+
+        let mut appender = MockAppend::new();
+        appender
+            .expect_append()
+            .times(1)
+            .with(predicate::function(|e: &AuditEntry| {
+                e.actor == "operator"
+                    && e.id == "id"
+                    && e.action == AuditAction::Update
+                    && e.from_state == Some(State::Parked("paused".into()))
+                    && e.to_state == Some(State::PendingOrder)
+                    && e.cause == "POST /admin/registrations/:id/resume"
+            }))
+            .returning(|_| Ok(0));
+
+        let updater = WithAudit::new(updater, Arc::new(getter), Arc::new(appender));
+
+        with_context(
+            AuditContext {
+                actor: "operator".into(),
+                cause: "POST /admin/registrations/:id/resume".into(),
+            },
+            updater.update(&"id".into(), &UpdateType::State(State::PendingOrder)),
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failed_mutation_is_not_recorded() {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .returning(|_| Err(crate::registration::GetError::NotFound));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .returning(|_, _| Err(UpdateError::NotFound));
+
+        let mut appender = MockAppend::new();
+        appender.expect_append().never();
+
+        let updater = WithAudit::new(updater, Arc::new(getter), Arc::new(appender));
+
+        assert!(matches!(
+            updater
+                .update(&"id".into(), &UpdateType::State(State::Available))
+                .await,
+            Err(UpdateError::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, fs::File, path::Path, sync::Arc};
+
+use anyhow::{Context, Error};
+use axum::{
+    body::Body,
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        Request, Response,
+    },
+    middleware::Next,
+    response::IntoResponse,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::api::CallerIdentity;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    // Managing registrations
+    Create,
+    // Exporting certificates
+    Export,
+    // Operator actions, implies all other scopes
+    Admin,
+}
+
+#[derive(Deserialize)]
+struct TokenEntry {
+    name: String,
+    token: String,
+    scopes: Vec<Scope>,
+}
+
+struct Token {
+    name: String,
+    scopes: Vec<Scope>,
+}
+
+// Static bearer tokens, indexed by their digest so lookups don't leak timing information about the tokens
+pub struct Tokens(HashMap<[u8; 32], Token>);
+
+impl Tokens {
+    // Loads a JSON list of `{"name": .., "token": .., "scopes": [..]}` entries
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let f = File::open(path).context("failed to open tokens file")?;
+        let entries: Vec<TokenEntry> =
+            serde_json::from_reader(f).context("failed to parse tokens file")?;
+
+        Ok(Self::from_entries(entries))
+    }
+
+    fn from_entries(entries: Vec<TokenEntry>) -> Self {
+        Self(
+            entries
+                .into_iter()
+                .map(|e| {
+                    (
+                        Sha256::digest(e.token.as_bytes()).into(),
+                        Token {
+                            name: e.name,
+                            scopes: e.scopes,
+                        },
+                    )
+                })
+                .collect(),
+        )
+    }
+
+    fn get(&self, token: &str) -> Option<&Token> {
+        self.0
+            .get(&<[u8; 32]>::from(Sha256::digest(token.as_bytes())))
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthMiddlewareArgs {
+    // Disables authentication altogether when missing
+    pub tokens: Option<Arc<Tokens>>,
+
+    // Scopes granted to anonymous callers
+    pub public_scopes: Vec<Scope>,
+
+    // Scope required for the routes behind the middleware
+    pub scope: Scope,
+}
+
+fn unauthorized(status: u16, msg: &'static str) -> Response<Body> {
+    let mut resp = Response::builder().status(status);
+    if status == 401 {
+        resp = resp.header(WWW_AUTHENTICATE, "Bearer");
+    }
+
+    resp.body(Body::from(msg)).unwrap()
+}
+
+pub async fn auth_mw<B>(mut req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let AuthMiddlewareArgs {
+        tokens,
+        public_scopes,
+        scope,
+    } = req
+        .extensions()
+        .get::<AuthMiddlewareArgs>()
+        .expect("missing auth middleware args")
+        .to_owned();
+
+    let tokens = match tokens {
+        Some(tokens) => tokens,
+        None => return next.run(req).await,
+    };
+
+    let bearer = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.strip_prefix("Bearer ").map(str::trim));
+
+    match bearer {
+        // Anonymous caller
+        None => {
+            if !public_scopes.contains(&scope) {
+                return unauthorized(401, "unauthorized").into_response();
+            }
+        }
+
+        // Authenticated caller
+        Some(bearer) => {
+            let token = match bearer.and_then(|bearer| tokens.get(bearer)) {
+                Some(token) => token,
+                None => {
+                    warn!(?scope, "invalid bearer token");
+                    return unauthorized(401, "unauthorized").into_response();
+                }
+            };
+
+            if !token.scopes.contains(&scope) && !token.scopes.contains(&Scope::Admin) {
+                warn!(name = token.name.as_str(), ?scope, "missing scope");
+                return unauthorized(403, "forbidden").into_response();
+            }
+
+            req.extensions_mut()
+                .insert(CallerIdentity(token.name.clone()));
+        }
+    };
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_token() {
+        let tokens = Tokens::from_entries(vec![TokenEntry {
+            name: "syncer".into(),
+            token: "secret".into(),
+            scopes: vec![Scope::Export],
+        }]);
+
+        let token = tokens.get("secret").unwrap();
+        assert_eq!(token.name, "syncer");
+        assert_eq!(token.scopes, vec![Scope::Export]);
+
+        assert!(tokens.get("other").is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    http::{header::RETRY_AFTER, Request, Response},
+    middleware::Next,
+    response::IntoResponse,
+};
+use opentelemetry::{metrics::Counter, KeyValue};
+use tracing::warn;
+
+use crate::work::Count;
+
+// Clients are not asked to wait longer than this, as the estimate gets unreliable
+const MAX_RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+struct State {
+    pending: u64,
+    refreshed_at: Option<Instant>,
+
+    // Dispensed tasks as of the last refresh
+    dispensed: u64,
+
+    // Tasks dispensed per second, smoothed across refreshes
+    rate: Option<f64>,
+}
+
+// Rejects new registrations while more tasks are due than the issuer can process timely.
+// The number of due tasks is refreshed at most every `refresh_interval`, and clients are asked
+// to retry once the excess of due tasks is expected to be dispensed, at the rate observed so far.
+pub struct Backpressure {
+    counter: Arc<dyn Count>,
+    dispensed: AtomicU64,
+    state: Mutex<State>,
+
+    // configuration, without a threshold every registration is accepted
+    threshold: Option<u64>,
+    refresh_interval: Duration,
+}
+
+impl Backpressure {
+    pub fn new(
+        counter: Arc<dyn Count>,
+        threshold: Option<u64>,
+        refresh_interval: Duration,
+    ) -> Self {
+        Self {
+            counter,
+            dispensed: AtomicU64::new(0),
+            state: Mutex::new(State {
+                pending: 0,
+                refreshed_at: None,
+                dispensed: 0,
+                rate: None,
+            }),
+            threshold,
+            refresh_interval,
+        }
+    }
+
+    // Counts a task dispensed by this issuer
+    pub fn dispensed(&self) {
+        self.dispensed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Returns how long to wait before retrying if the issuer is overloaded
+    pub async fn check(&self) -> Result<(), Duration> {
+        self.check_at(Instant::now()).await
+    }
+
+    async fn check_at(&self, now: Instant) -> Result<(), Duration> {
+        let threshold = match self.threshold {
+            Some(threshold) => threshold,
+            None => return Ok(()),
+        };
+
+        self.refresh(now).await;
+
+        let st = self.state.lock().unwrap();
+
+        if st.pending <= threshold {
+            return Ok(());
+        }
+
+        let excess = (st.pending - threshold) as f64;
+
+        // There's no point in retrying before the count is refreshed
+        let retry_after = match st.rate {
+            Some(rate) if rate > 0.0 => {
+                Duration::from_secs_f64(excess / rate).clamp(self.refresh_interval, MAX_RETRY_AFTER)
+            }
+            _ => MAX_RETRY_AFTER,
+        };
+
+        Err(retry_after)
+    }
+
+    async fn refresh(&self, now: Instant) {
+        let dispensed = self.dispensed.load(Ordering::Relaxed);
+
+        // Claim the refresh, so that concurrent requests don't all count tasks
+        let (refreshed_at, prev_dispensed) = {
+            let mut st = self.state.lock().unwrap();
+
+            if let Some(t) = st.refreshed_at {
+                if now.saturating_duration_since(t) < self.refresh_interval {
+                    return;
+                }
+            }
+
+            let prev = (st.refreshed_at.replace(now), st.dispensed);
+            st.dispensed = dispensed;
+
+            prev
+        };
+
+        let out = self.counter.count().await;
+
+        let mut st = self.state.lock().unwrap();
+
+        if let Some(t) = refreshed_at {
+            let elapsed = now.saturating_duration_since(t).as_secs_f64();
+            if elapsed > 0.0 {
+                let sample = dispensed.saturating_sub(prev_dispensed) as f64 / elapsed;
+                st.rate = Some(st.rate.map_or(sample, |rate| (rate + sample) / 2.0));
+            }
+        }
+
+        // Registrations are accepted while the count is unknown
+        match out {
+            Ok(n) => st.pending = n,
+            Err(err) => {
+                warn!(error = ?err, "failed to count pending tasks");
+                st.pending = 0;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct BackpressureMiddlewareArgs {
+    pub backpressure: Arc<Backpressure>,
+
+    // Counts checked requests by whether they were accepted or rejected
+    pub counter: Counter<u64>,
+}
+
+pub async fn backpressure_mw<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let BackpressureMiddlewareArgs {
+        backpressure,
+        counter,
+    } = req
+        .extensions()
+        .get::<BackpressureMiddlewareArgs>()
+        .expect("missing backpressure middleware args")
+        .to_owned();
+
+    if let Err(retry_after) = backpressure.check().await {
+        warn!(?retry_after, "rejecting request, too many pending tasks");
+        counter.add(1, &[KeyValue::new("status", "rejected")]);
+
+        // Round up, like rate-limited requests
+        let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+        return Response::builder()
+            .status(503)
+            .header(RETRY_AFTER, retry_after)
+            .body(Body::from("too many pending tasks, please retry later"))
+            .unwrap()
+            .into_response();
+    }
+
+    counter.add(1, &[KeyValue::new("status", "accepted")]);
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::work::MockCount;
+
+    #[tokio::test]
+    async fn reject_above_threshold() {
+        let mut counter = MockCount::new();
+        let mut counts = vec![10, 30].into_iter();
+        counter
+            .expect_count()
+            .times(2)
+            .returning(move || Ok(counts.next().unwrap()));
+
+        let b = Backpressure::new(Arc::new(counter), Some(20), Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(b.check_at(now).await.is_ok());
+
+        // Cached until the refresh interval has elapsed
+        assert!(b.check_at(now + Duration::from_secs(5)).await.is_ok());
+
+        // 20 tasks dispensed in 20s, so the excess of 10 tasks takes about 10s
+        for _ in 0..20 {
+            b.dispensed();
+        }
+
+        assert_eq!(
+            b.check_at(now + Duration::from_secs(20)).await,
+            Err(Duration::from_secs(10))
+        );
+    }
+
+    #[tokio::test]
+    async fn accept_when_count_fails() {
+        let mut counter = MockCount::new();
+        counter
+            .expect_count()
+            .times(1)
+            .returning(|| Err(anyhow::anyhow!("unreachable").into()));
+
+        let b = Backpressure::new(Arc::new(counter), Some(0), Duration::from_secs(10));
+
+        assert!(b.check().await.is_ok());
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use tracing::info;
+
+use crate::{
+    registration::Id,
+    work::{extract_domain, Action, Process, ProcessError, Task},
+};
+
+const HOUR: Duration = Duration::from_secs(3600);
+const WEEK: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// At most `max` orders within a sliding `window`, 0 meaning unlimited
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limit {
+    pub max: usize,
+    pub window: Duration,
+}
+
+impl Limit {
+    // Time until another order fits, given the (ascending) times of matching orders
+    fn wait_time(&self, times: &[SystemTime], now: SystemTime) -> Option<Duration> {
+        if self.max == 0 || times.len() < self.max {
+            return None;
+        }
+
+        let expires_at = times[times.len() - self.max] + self.window;
+        expires_at.duration_since(now).ok()
+    }
+}
+
+// Let's Encrypt's rate limits, see https://letsencrypt.org/docs/rate-limits/
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    pub per_domain: Limit,  // certificates per registered domain
+    pub per_account: Limit, // new orders per account
+    pub duplicate: Limit,   // certificates for the exact same set of names
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            per_domain: Limit {
+                max: 50,
+                window: WEEK,
+            },
+            per_account: Limit {
+                max: 300,
+                window: 3 * HOUR,
+            },
+            duplicate: Limit {
+                max: 5,
+                window: WEEK,
+            },
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Exhausted {
+    pub limit: &'static str,
+    pub retry_in: Duration,
+}
+
+struct Order {
+    time: SystemTime,
+    names: BTreeSet<String>,
+    domains: BTreeSet<String>,
+}
+
+// Tracks orders placed with the ACME provider against its rate limits, so that orders
+// which would exceed them are delayed rather than rejected. Every order counts, whether
+// or not it results in a certificate, so the budget errs on the safe side. Like retry
+// attempts, orders are tracked in memory, per issuer.
+pub struct RateBudget {
+    limits: Limits,
+    orders: Mutex<VecDeque<Order>>,
+}
+
+impl RateBudget {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            orders: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    // Records an order of the given names, unless it would exceed a limit
+    pub fn reserve(&self, names: &[String], now: SystemTime) -> Result<(), Exhausted> {
+        let names: BTreeSet<String> = names.iter().cloned().collect();
+        let domains: BTreeSet<String> = names
+            .iter()
+            .map(|name| extract_domain(name).to_string())
+            .collect();
+
+        let mut orders = self.orders.lock().unwrap();
+
+        // Forget orders that no longer count towards any limit
+        let window = [
+            &self.limits.per_domain,
+            &self.limits.per_account,
+            &self.limits.duplicate,
+        ]
+        .iter()
+        .map(|l| l.window)
+        .max()
+        .unwrap_or_default();
+
+        while orders.front().map_or(false, |o| o.time + window <= now) {
+            orders.pop_front();
+        }
+
+        let times = |f: &dyn Fn(&Order) -> bool, window: Duration| -> Vec<SystemTime> {
+            orders
+                .iter()
+                .filter(|o| o.time + window > now && f(o))
+                .map(|o| o.time)
+                .collect()
+        };
+
+        let mut checks = vec![
+            (
+                "per-account",
+                &self.limits.per_account,
+                times(&|_| true, self.limits.per_account.window),
+            ),
+            (
+                "duplicate",
+                &self.limits.duplicate,
+                times(&|o| o.names == names, self.limits.duplicate.window),
+            ),
+        ];
+
+        for d in &domains {
+            checks.push((
+                "per-domain",
+                &self.limits.per_domain,
+                times(&|o| o.domains.contains(d), self.limits.per_domain.window),
+            ));
+        }
+
+        // Wait for the limit that takes longest to free up
+        let exhausted = checks
+            .into_iter()
+            .filter_map(|(limit, l, times)| {
+                l.wait_time(&times, now)
+                    .map(|retry_in| Exhausted { limit, retry_in })
+            })
+            .max_by_key(|e| e.retry_in);
+
+        if let Some(exhausted) = exhausted {
+            return Err(exhausted);
+        }
+
+        orders.push_back(Order {
+            time: now,
+            names,
+            domains,
+        });
+
+        Ok(())
+    }
+}
+
+pub struct WithBudget<T: Process> {
+    pub processor: T,
+    pub budget: Arc<RateBudget>,
+}
+
+impl<T: Process> WithBudget<T> {
+    pub fn new(processor: T, budget: Arc<RateBudget>) -> Self {
+        Self { processor, budget }
+    }
+}
+
+#[async_trait]
+impl<T: Process> Process for WithBudget<T> {
+    async fn process(&self, id: &Id, task: &Task) -> Result<(), ProcessError> {
+        // Only placing an order counts towards the rate limits
+        if let Action::Order = task.action {
+            if let Err(Exhausted { limit, retry_in }) =
+                self.budget.reserve(&task.names(), SystemTime::now())
+            {
+                info!(%id, limit, ?retry_in, "delaying order to stay within acme rate limits");
+                return Err(ProcessError::AwaitingRateLimitBudget(retry_in));
+            }
+        }
+
+        self.processor.process(id, task).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(ns: &[&str]) -> Vec<String> {
+        ns.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn reserve_within_limits() {
+        let b = RateBudget::new(Limits {
+            per_domain: Limit {
+                max: 3,
+                window: WEEK,
+            },
+            per_account: Limit {
+                max: 0, // unlimited
+                window: 3 * HOUR,
+            },
+            duplicate: Limit {
+                max: 1,
+                window: WEEK,
+            },
+        });
+
+        let t0 = SystemTime::UNIX_EPOCH + WEEK;
+
+        assert_eq!(b.reserve(&names(&["a.example.com"]), t0), Ok(()));
+
+        // Same set of names
+        assert_eq!(
+            b.reserve(&names(&["a.example.com"]), t0 + HOUR),
+            Err(Exhausted {
+                limit: "duplicate",
+                retry_in: WEEK - HOUR,
+            })
+        );
+
+        assert_eq!(b.reserve(&names(&["b.example.com"]), t0 + HOUR), Ok(()));
+        assert_eq!(
+            b.reserve(&names(&["c.example.com", "www.other.org"]), t0 + 2 * HOUR),
+            Ok(())
+        );
+
+        // Registered domain is exhausted, other domains are not
+        assert_eq!(
+            b.reserve(&names(&["d.example.com"]), t0 + 3 * HOUR),
+            Err(Exhausted {
+                limit: "per-domain",
+                retry_in: WEEK - 3 * HOUR,
+            })
+        );
+        assert_eq!(b.reserve(&names(&["other.net"]), t0 + 3 * HOUR), Ok(()));
+
+        // Budget frees up as orders leave the window
+        assert_eq!(b.reserve(&names(&["d.example.com"]), t0 + WEEK), Ok(()));
+    }
+}
@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use mockall::automock;
+use trust_dns_resolver::{
+    error::ResolveErrorKind,
+    proto::rr::{
+        rdata::caa::{Property, Value, CAA},
+        RData, RecordType,
+    },
+};
+
+use crate::dns::Resolve;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CaaError {
+    #[error("dns caa records at {src} do not authorize any of {identities}")]
+    Unauthorized { src: String, identities: String },
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[automock]
+#[async_trait]
+pub trait CheckCaa: Send + Sync {
+    async fn check_caa(&self, name: &str) -> Result<(), CaaError>;
+}
+
+#[async_trait]
+impl<T: CheckCaa + ?Sized> CheckCaa for Arc<T> {
+    async fn check_caa(&self, name: &str) -> Result<(), CaaError> {
+        (**self).check_caa(name).await
+    }
+}
+
+// Checks that the CA is allowed to issue certificates for a name (RFC 8659),
+// so that orders are not placed only to be rejected by the ACME provider
+pub struct CaaChecker {
+    // configuration
+    identities: Vec<String>, // CA domains, e.g. `letsencrypt.org`
+
+    // dependencies
+    resolver: Box<dyn Resolve>,
+}
+
+impl CaaChecker {
+    pub fn new(identities: Vec<String>, resolver: Box<dyn Resolve>) -> Self {
+        Self {
+            identities,
+            resolver,
+        }
+    }
+}
+
+// Whether a non-empty set of CAA records authorizes any of the identities
+fn is_authorized(records: &[CAA], identities: &[String]) -> bool {
+    // Unknown critical properties must not be ignored
+    if records
+        .iter()
+        .any(|r| r.issuer_critical() && matches!(r.tag(), Property::Unknown(_)))
+    {
+        return false;
+    }
+
+    let issuers: Vec<&Value> = records
+        .iter()
+        .filter(|r| r.tag() == &Property::Issue)
+        .map(|r| r.value())
+        .collect();
+
+    // Without issue properties, any CA is allowed
+    if issuers.is_empty() {
+        return true;
+    }
+
+    issuers.iter().any(|v| match v {
+        Value::Issuer(Some(issuer), _) => {
+            let issuer = issuer.to_ascii();
+            let issuer = issuer.trim_end_matches('.');
+
+            identities.iter().any(|id| id.eq_ignore_ascii_case(issuer))
+        }
+        _ => false,
+    })
+}
+
+#[async_trait]
+impl CheckCaa for CaaChecker {
+    async fn check_caa(&self, name: &str) -> Result<(), CaaError> {
+        // The relevant records are those of the closest name, starting with
+        // the name itself and climbing up its parents, that has any
+        let mut src = name.trim_end_matches('.');
+
+        loop {
+            let records: Vec<CAA> = match self
+                .resolver
+                .lookup(&format!("{src}."), RecordType::CAA)
+                .await
+            {
+                Ok(lookup) => lookup
+                    .iter()
+                    .filter_map(|r| match r {
+                        RData::CAA(caa) => Some(caa.to_owned()),
+                        _ => None,
+                    })
+                    .collect(),
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::NoRecordsFound { .. } => vec![],
+                    _ => {
+                        return Err(CaaError::UnexpectedError(anyhow!(
+                            "failed to resolve CAA: {err}"
+                        )))
+                    }
+                },
+            };
+
+            if !records.is_empty() {
+                if is_authorized(&records, &self.identities) {
+                    return Ok(());
+                }
+
+                return Err(CaaError::Unauthorized {
+                    src: src.to_string(),
+                    identities: self.identities.join(", "),
+                });
+            }
+
+            src = match src.split_once('.') {
+                Some((_, parent)) => parent,
+                None => return Ok(()),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Error;
+    use mockall::predicate;
+    use trust_dns_resolver::{
+        lookup::Lookup,
+        proto::{op::Query, rr::Record},
+        Name,
+    };
+
+    use crate::dns::MockResolve;
+
+    fn issue(issuer: &str) -> Result<CAA, Error> {
+        Ok(CAA::new_issue(
+            false,
+            Some(Name::from_utf8(issuer)?),
+            vec![],
+        ))
+    }
+
+    #[test]
+    fn authorization() -> Result<(), Error> {
+        let ids = vec!["letsencrypt.org".to_string()];
+
+        assert!(is_authorized(&[issue("letsencrypt.org")?], &ids));
+        assert!(is_authorized(
+            &[issue("sectigo.com")?, issue("LetsEncrypt.org")?],
+            &ids
+        ));
+        assert!(!is_authorized(&[issue("sectigo.com")?], &ids));
+
+        // `issue ";"` forbids issuance altogether
+        assert!(!is_authorized(&[CAA::new_issue(false, None, vec![])], &ids));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unauthorized() -> Result<(), Error> {
+        let mut resolver = MockResolve::new();
+        resolver
+            .expect_lookup()
+            .times(1)
+            .with(
+                predicate::eq("example.com."),
+                predicate::eq(RecordType::CAA),
+            )
+            .returning(|_, _| {
+                let name = Name::from_utf8("example.com.").unwrap();
+                let q = Query::query(name.clone(), RecordType::CAA);
+                let r = Record::from_rdata(name, 300, RData::CAA(issue("sectigo.com").unwrap()));
+
+                Ok(Lookup::new_with_max_ttl(q, Arc::new([r])))
+            });
+
+        let c = CaaChecker::new(vec!["letsencrypt.org".into()], Box::new(resolver));
+
+        match c.check_caa("example.com").await {
+            Err(CaaError::Unauthorized { src, .. }) if src == "example.com" => Ok(()),
+            other => Err(anyhow!("expected Unauthorized but got {:?}", other)),
+        }
+    }
+}
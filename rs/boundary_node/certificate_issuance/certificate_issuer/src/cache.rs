@@ -0,0 +1,581 @@
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use candid::Principal;
+use rusqlite::{params, Connection, OptionalExtension};
+use tracing::warn;
+
+use crate::{
+    acme::KeyType,
+    registration::{
+        Create, CreateError, Get, GetError, Id, Registration, Remove, RemoveError, State, Update,
+        UpdateError, UpdateType,
+    },
+    work::{Dispense, DispenseError, Peek, PeekError, Priority, Queue, QueueError, Task},
+};
+
+// A local copy of registrations and queued tasks, so that the issuer can keep serving
+// status reads and processing tasks while the orchestrator canister is unreachable.
+// Entries that are not yet known to the canister are marked as unsynced.
+pub struct Cache(Mutex<Connection>);
+
+impl Cache {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path).context("failed to open cache")?)
+    }
+
+    #[cfg(test)]
+    fn in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS registrations (
+                id           TEXT PRIMARY KEY,
+                registration TEXT NOT NULL,
+                synced       INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS tasks (
+                id     TEXT PRIMARY KEY,
+                t      INTEGER NOT NULL,
+                synced INTEGER NOT NULL
+            );",
+        )
+        .context("failed to create cache tables")?;
+
+        Ok(Self(Mutex::new(conn)))
+    }
+
+    fn get(&self, id: &Id) -> Result<Option<Registration>, Error> {
+        let reg: Option<String> = self
+            .0
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT registration FROM registrations WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        reg.map(|reg| serde_json::from_str(&reg).context("failed to decode registration"))
+            .transpose()
+    }
+
+    fn put(&self, id: &Id, reg: &Registration, synced: bool) -> Result<(), Error> {
+        let reg = serde_json::to_string(reg)?;
+
+        // Registrations from the canister do not replace local changes it has yet to see
+        let query = if synced {
+            "INSERT INTO registrations (id, registration, synced) VALUES (?1, ?2, 1)
+             ON CONFLICT(id) DO UPDATE SET registration = excluded.registration WHERE synced = 1"
+        } else {
+            "INSERT OR REPLACE INTO registrations (id, registration, synced) VALUES (?1, ?2, 0)"
+        };
+
+        self.0.lock().unwrap().execute(query, params![id, reg])?;
+
+        Ok(())
+    }
+
+    fn remove(&self, id: &Id) -> Result<(), Error> {
+        let conn = self.0.lock().unwrap();
+        conn.execute("DELETE FROM registrations WHERE id = ?1", params![id])?;
+        conn.execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+
+        Ok(())
+    }
+
+    fn put_task(&self, id: &Id, t: u64, synced: bool) -> Result<(), Error> {
+        self.0.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO tasks (id, t, synced) VALUES (?1, ?2, ?3)",
+            params![id, to_sql_time(t), synced],
+        )?;
+
+        Ok(())
+    }
+
+    // Takes the earliest task that is due, if any
+    fn take_task(&self, now: u64) -> Result<Option<Id>, Error> {
+        let conn = self.0.lock().unwrap();
+
+        let id: Option<Id> = conn
+            .query_row(
+                "SELECT id FROM tasks WHERE t <= ?1 ORDER BY t LIMIT 1",
+                params![to_sql_time(now)],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        if let Some(id) = &id {
+            conn.execute("DELETE FROM tasks WHERE id = ?1", params![id])?;
+        }
+
+        Ok(id)
+    }
+
+    // The earliest task that is due, if any
+    fn next_task(&self, now: u64) -> Result<Option<Id>, Error> {
+        Ok(self
+            .0
+            .lock()
+            .unwrap()
+            .query_row(
+                "SELECT id FROM tasks WHERE t <= ?1 ORDER BY t LIMIT 1",
+                params![to_sql_time(now)],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    // Drops a task dispensed by the canister, unless it was re-queued locally in the meantime
+    fn drop_synced_task(&self, id: &Id) -> Result<(), Error> {
+        self.0.lock().unwrap().execute(
+            "DELETE FROM tasks WHERE id = ?1 AND synced = 1",
+            params![id],
+        )?;
+
+        Ok(())
+    }
+
+    fn unsynced_registrations(&self) -> Result<Vec<(Id, Registration)>, Error> {
+        let conn = self.0.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT id, registration FROM registrations WHERE synced = 0")?;
+
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, String>(1)?)))?
+            .collect::<Result<Vec<(Id, String)>, _>>()?;
+
+        rows.into_iter()
+            .map(|(id, reg)| {
+                serde_json::from_str(&reg)
+                    .map(|reg| (id, reg))
+                    .context("failed to decode registration")
+            })
+            .collect()
+    }
+
+    fn unsynced_tasks(&self) -> Result<Vec<(Id, u64)>, Error> {
+        let conn = self.0.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id, t FROM tasks WHERE synced = 0")?;
+
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as u64)))?
+            .collect::<Result<Vec<(Id, u64)>, _>>()?;
+
+        Ok(rows)
+    }
+
+    // Marks a registration as synced, unless it changed again in the meantime
+    fn mark_registration_synced(&self, id: &Id, reg: &Registration) -> Result<(), Error> {
+        self.0.lock().unwrap().execute(
+            "UPDATE registrations SET synced = 1 WHERE id = ?1 AND registration = ?2",
+            params![id, serde_json::to_string(reg)?],
+        )?;
+
+        Ok(())
+    }
+
+    // Marks a task as synced, unless it was re-queued in the meantime
+    fn mark_task_synced(&self, id: &Id, t: u64) -> Result<(), Error> {
+        self.0.lock().unwrap().execute(
+            "UPDATE tasks SET synced = 1 WHERE id = ?1 AND t = ?2",
+            params![id, to_sql_time(t)],
+        )?;
+
+        Ok(())
+    }
+}
+
+// SQLite integers are signed
+fn to_sql_time(t: u64) -> i64 {
+    i64::try_from(t).unwrap_or(i64::MAX)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos() as u64)
+}
+
+pub struct WithCache<T>(pub T, pub Arc<Cache>);
+
+#[async_trait]
+impl<T: Create> Create for WithCache<T> {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        let id = self.0.create(name, canister, key_type, alt_names).await?;
+
+        let reg = Registration {
+            name: name.to_string(),
+            canister: canister.to_owned(),
+            state: State::PendingOrder,
+            key_type,
+            alt_names: alt_names.to_vec(),
+        };
+
+        if let Err(err) = self.1.put(&id, &reg, true) {
+            warn!(%id, error = ?err, "failed to cache registration");
+        }
+
+        Ok(id)
+    }
+}
+
+#[async_trait]
+impl<T: Get> Get for WithCache<T> {
+    async fn get(&self, id: &Id) -> Result<Registration, GetError> {
+        match self.0.get(id).await {
+            Ok(reg) => {
+                if let Err(err) = self.1.put(id, &reg, true) {
+                    warn!(%id, error = ?err, "failed to cache registration");
+                }
+
+                Ok(reg)
+            }
+
+            Err(GetError::NotFound) => {
+                if let Err(err) = self.1.remove(id) {
+                    warn!(%id, error = ?err, "failed to remove cached registration");
+                }
+
+                Err(GetError::NotFound)
+            }
+
+            // Fall back to the cached registration while the canister is unreachable
+            Err(GetError::UnexpectedError(err)) => match self.1.get(id) {
+                Ok(Some(reg)) => {
+                    warn!(%id, error = ?err, "serving cached registration");
+                    Ok(reg)
+                }
+                _ => Err(GetError::UnexpectedError(err)),
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Update> Update for WithCache<T> {
+    async fn update(&self, id: &Id, typ: &UpdateType) -> Result<(), UpdateError> {
+        let out = self.0.update(id, typ).await;
+
+        let reg = match self.1.get(id) {
+            Ok(Some(reg)) => reg,
+            _ => return out,
+        };
+
+        let reg = match typ {
+            UpdateType::Canister(canister) => Registration {
+                canister: canister.to_owned(),
+                ..reg
+            },
+            UpdateType::State(state) => Registration {
+                state: state.to_owned(),
+                ..reg
+            },
+        };
+
+        match out {
+            // Replaces any deferred change, which the canister now supersedes
+            Ok(()) => {
+                if let Err(err) = self
+                    .1
+                    .put(id, &reg, false)
+                    .and_then(|_| self.1.mark_registration_synced(id, &reg))
+                {
+                    warn!(%id, error = ?err, "failed to cache registration");
+                }
+
+                Ok(())
+            }
+
+            // State changes of processed tasks are kept until the canister is reachable again
+            Err(UpdateError::UnexpectedError(err)) if matches!(typ, UpdateType::State(_)) => {
+                self.1.put(id, &reg, false)?;
+
+                warn!(%id, error = ?err, "registration update deferred");
+                Ok(())
+            }
+
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Remove> Remove for WithCache<T> {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        self.0.remove(id).await?;
+
+        if let Err(err) = self.1.remove(id) {
+            warn!(%id, error = ?err, "failed to remove cached registration");
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<T: Queue> Queue for WithCache<T> {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
+        match self.0.queue(id, t, priority).await {
+            Ok(()) => {
+                if let Err(err) = self.1.put_task(id, t, true) {
+                    warn!(%id, error = ?err, "failed to cache task");
+                }
+
+                Ok(())
+            }
+
+            // Tasks are kept until the canister is reachable again
+            Err(QueueError::UnexpectedError(err)) => {
+                self.1.put_task(id, t, false)?;
+
+                warn!(%id, error = ?err, "task queueing deferred");
+                Ok(())
+            }
+
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Peek> Peek for WithCache<T> {
+    async fn peek(&self) -> Result<Id, PeekError> {
+        match self.0.peek().await {
+            Err(PeekError::UnexpectedError(err)) => match self.1.next_task(now()) {
+                Ok(Some(id)) => Ok(id),
+                _ => Err(PeekError::UnexpectedError(err)),
+            },
+            out => out,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Dispense> Dispense for WithCache<T> {
+    async fn dispense(&self) -> Result<(Id, Task), DispenseError> {
+        match self.0.dispense().await {
+            Ok((id, task)) => {
+                if let Err(err) = self.1.drop_synced_task(&id) {
+                    warn!(%id, error = ?err, "failed to remove cached task");
+                }
+
+                Ok((id, task))
+            }
+
+            // Dispense cached tasks while the canister is unreachable
+            Err(DispenseError::UnexpectedError(err)) => {
+                let id = match self.1.take_task(now())? {
+                    Some(id) => id,
+                    None => return Err(DispenseError::UnexpectedError(err)),
+                };
+
+                let reg = self
+                    .1
+                    .get(&id)?
+                    .ok_or_else(|| anyhow!("task {id} has no cached registration"))?;
+
+                warn!(%id, error = ?err, "dispensing cached task");
+
+                Ok((
+                    id,
+                    Task {
+                        name: reg.name,
+                        canister: reg.canister,
+                        action: reg.state.into(),
+                        key_type: reg.key_type,
+                        alt_names: reg.alt_names,
+
+                        // Priorities are not cached, as the cache only bridges short outages
+                        priority: Priority::Normal,
+                        lease: None,
+                    },
+                ))
+            }
+
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// Pushes changes made while the canister was unreachable
+pub struct Reconciler {
+    cache: Arc<Cache>,
+    queuer: Arc<dyn Queue>,
+    updater: Arc<dyn Update>,
+}
+
+impl Reconciler {
+    pub fn new(cache: Arc<Cache>, queuer: Arc<dyn Queue>, updater: Arc<dyn Update>) -> Self {
+        Self {
+            cache,
+            queuer,
+            updater,
+        }
+    }
+
+    pub async fn reconcile(&self) -> Result<(), Error> {
+        for (id, reg) in self.cache.unsynced_registrations()? {
+            match self
+                .updater
+                .update(&id, &UpdateType::State(reg.state.clone()))
+                .await
+            {
+                Ok(()) => self.cache.mark_registration_synced(&id, &reg)?,
+                Err(UpdateError::NotFound) => self.cache.remove(&id)?,
+                Err(UpdateError::UnexpectedError(err)) => {
+                    return Err(err.context(format!("failed to update registration {id}")))
+                }
+            }
+        }
+
+        for (id, t) in self.cache.unsynced_tasks()? {
+            match self.queuer.queue(&id, t, Priority::Normal).await {
+                Ok(()) => self.cache.mark_task_synced(&id, t)?,
+                Err(QueueError::NotFound) => self.cache.remove(&id)?,
+                Err(QueueError::UnexpectedError(err)) => {
+                    return Err(err.context(format!("failed to queue task {id}")))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mockall::predicate;
+
+    use crate::{
+        registration::{MockGet, MockUpdate},
+        work::{Action, MockQueue},
+    };
+
+    fn registration(state: State) -> Registration {
+        Registration {
+            name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
+            state,
+            key_type: None,
+            alt_names: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn get_falls_back_to_cache() -> Result<(), Error> {
+        let cache = Arc::new(Cache::in_memory()?);
+
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .returning(|_| Ok(registration(State::Available)));
+        getter
+            .expect_get()
+            .times(1)
+            .returning(|_| Err(GetError::UnexpectedError(anyhow!("unreachable"))));
+
+        let getter = WithCache(getter, cache);
+
+        getter.get(&"id".into()).await?;
+
+        match getter.get(&"id".into()).await {
+            Ok(reg) if reg.state == State::Available => Ok(()),
+            other => Err(anyhow!("expected cached registration but got {:?}", other)),
+        }
+    }
+
+    #[tokio::test]
+    async fn deferred_changes_are_reconciled() -> Result<(), Error> {
+        let cache = Arc::new(Cache::in_memory()?);
+        cache.put(
+            &"id".into(),
+            &registration(State::PendingChallengeResponse),
+            true,
+        )?;
+
+        // Canister is unreachable
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .returning(|_, _, _| Err(QueueError::UnexpectedError(anyhow!("unreachable"))));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .returning(|_, _| Err(UpdateError::UnexpectedError(anyhow!("unreachable"))));
+
+        WithCache(queuer, cache.clone())
+            .queue(&"id".into(), 0, Priority::High)
+            .await?;
+
+        WithCache(updater, cache.clone())
+            .update(&"id".into(), &UpdateType::State(State::PendingAcmeApproval))
+            .await?;
+
+        // Deferred task can be dispensed from the cache
+        struct Unreachable;
+
+        #[async_trait]
+        impl Dispense for Unreachable {
+            async fn dispense(&self) -> Result<(Id, Task), DispenseError> {
+                Err(DispenseError::UnexpectedError(anyhow!("unreachable")))
+            }
+        }
+
+        let (id, task) = WithCache(Unreachable, cache.clone()).dispense().await?;
+        assert_eq!(id, "id");
+        assert!(matches!(task.action, Action::Certificate));
+
+        // Canister is reachable again
+        cache.put_task(&"id".into(), 1, false)?;
+
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .with(
+                predicate::eq("id".to_string()),
+                predicate::eq(1),
+                predicate::eq(Priority::Normal),
+            )
+            .returning(|_, _, _| Ok(()));
+
+        let mut updater = MockUpdate::new();
+        updater
+            .expect_update()
+            .times(1)
+            .with(
+                predicate::eq("id".to_string()),
+                predicate::eq(UpdateType::State(State::PendingAcmeApproval)),
+            )
+            .returning(|_, _| Ok(()));
+
+        let r = Reconciler::new(cache.clone(), Arc::new(queuer), Arc::new(updater));
+        r.reconcile().await?;
+
+        assert!(cache.unsynced_registrations()?.is_empty());
+        assert!(cache.unsynced_tasks()?.is_empty());
+
+        Ok(())
+    }
+}
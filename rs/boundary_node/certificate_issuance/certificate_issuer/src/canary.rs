@@ -0,0 +1,220 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use candid::Principal;
+use opentelemetry::{
+    metrics::{Counter, Histogram},
+    KeyValue,
+};
+use tokio::time::sleep;
+use tracing::info;
+
+use crate::{
+    certificate::{Pair, Upload, UploadError},
+    registration::Id,
+    work::{Action, Priority, Process, ProcessError, Task},
+};
+
+// Canary tasks are not backed by a registration
+const CANARY_ID: &str = "canary";
+
+// Certificates issued by the canary only exercise the pipeline, so they are discarded rather than stored
+pub struct Discard;
+
+#[async_trait]
+impl Upload for Discard {
+    async fn upload(&self, _: &Id, _: Pair) -> Result<(), UploadError> {
+        Ok(())
+    }
+}
+
+// Periodically issues a certificate for a dedicated canary domain through the full pipeline,
+// against a staging ACME directory, so that broken credentials or DNS setup are noticed
+// before customers' registrations start failing.
+pub struct Canary {
+    processor: Box<dyn Process>,
+    name: String,
+    canister: Principal,
+
+    // How long to wait between steps of an issuance, e.g. for DNS records to propagate,
+    // and how long an issuance may take overall
+    poll_interval: Duration,
+    timeout: Duration,
+
+    counter: Counter<u64>,
+    recorder: Histogram<f64>,
+}
+
+impl Canary {
+    pub fn new(
+        processor: Box<dyn Process>,
+        name: String,
+        canister: Principal,
+        poll_interval: Duration,
+        timeout: Duration,
+        counter: Counter<u64>,
+        recorder: Histogram<f64>,
+    ) -> Self {
+        Self {
+            processor,
+            name,
+            canister,
+            poll_interval,
+            timeout,
+            counter,
+            recorder,
+        }
+    }
+
+    // Issues a canary certificate, returning how long it took
+    pub async fn run(&self) -> Result<Duration, Error> {
+        let start_time = Instant::now();
+        let out = self.issue(start_time).await;
+        let duration = start_time.elapsed();
+
+        let status = if out.is_ok() { "ok" } else { "fail" };
+        let labels = &[KeyValue::new("status", status)];
+
+        self.counter.add(1, labels);
+        self.recorder.record(duration.as_secs_f64(), labels);
+
+        out?;
+
+        info!(name = self.name, ?duration, "issued canary certificate");
+
+        Ok(duration)
+    }
+
+    async fn issue(&self, start_time: Instant) -> Result<(), Error> {
+        let mut task = Task {
+            name: self.name.clone(),
+            canister: self.canister,
+            action: Action::Order,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
+        };
+
+        loop {
+            task.action = match self.processor.process(&CANARY_ID.into(), &task).await {
+                Ok(()) => return Ok(()),
+                Err(ProcessError::AwaitingDnsPropagation) => Action::Ready,
+                Err(ProcessError::AwaitingAcmeOrderReady) => Action::Certificate,
+                Err(err) => {
+                    return Err(Error::from(err).context(format!(
+                        "failed to issue canary certificate at {} step",
+                        task.action
+                    )))
+                }
+            };
+
+            if start_time.elapsed() >= self.timeout {
+                return Err(anyhow!(
+                    "canary certificate not issued within {:?}, stuck at {} step",
+                    self.timeout,
+                    task.action
+                ));
+            }
+
+            sleep(self.poll_interval).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    // Answers each step with the next scripted outcome, remembering the steps taken
+    struct ScriptedProcessor {
+        outcomes: Mutex<Vec<Result<(), ProcessError>>>,
+        actions: Mutex<Vec<String>>,
+    }
+
+    impl ScriptedProcessor {
+        fn new(mut outcomes: Vec<Result<(), ProcessError>>) -> Self {
+            outcomes.reverse();
+
+            Self {
+                outcomes: Mutex::new(outcomes),
+                actions: Mutex::new(vec![]),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl Process for Arc<ScriptedProcessor> {
+        async fn process(&self, id: &Id, task: &Task) -> Result<(), ProcessError> {
+            assert_eq!(id, CANARY_ID);
+            assert_eq!(task.name, "canary.example.com");
+
+            self.actions.lock().unwrap().push(task.action.to_string());
+            self.outcomes.lock().unwrap().pop().unwrap()
+        }
+    }
+
+    fn canary(processor: Box<dyn Process>, timeout: Duration) -> Canary {
+        let meter = opentelemetry::global::meter("test");
+
+        Canary::new(
+            processor,                                 // processor
+            "canary.example.com".into(),               // name
+            Principal::from_text("aaaaa-aa").unwrap(), // canister
+            Duration::ZERO,                            // poll_interval
+            timeout,                                   // timeout
+            meter.u64_counter("canary").init(),        // counter
+            meter.f64_histogram("canary").init(),      // recorder
+        )
+    }
+
+    #[tokio::test]
+    async fn issue_through_all_steps() -> Result<(), Error> {
+        let processor = Arc::new(ScriptedProcessor::new(vec![
+            Err(ProcessError::AwaitingDnsPropagation), // order
+            Err(ProcessError::AwaitingDnsPropagation), // not propagated yet
+            Err(ProcessError::AwaitingAcmeOrderReady), // ready
+            Err(ProcessError::AwaitingAcmeOrderReady), // not finalized yet
+            Ok(()),                                    // certificate
+        ]));
+
+        canary(Box::new(processor.clone()), Duration::from_secs(60))
+            .run()
+            .await?;
+
+        assert_eq!(
+            *processor.actions.lock().unwrap(),
+            vec!["Order", "Ready", "Ready", "Certificate", "Certificate"],
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn fail_on_error_or_timeout() {
+        let processor = Arc::new(ScriptedProcessor::new(vec![
+            Err(ProcessError::AwaitingDnsPropagation),
+            Err(ProcessError::AcmeOrderInvalid),
+        ]));
+
+        assert!(canary(Box::new(processor), Duration::from_secs(60))
+            .run()
+            .await
+            .is_err());
+
+        // Without time to wait, issuance gives up after placing the order
+        let processor = Arc::new(ScriptedProcessor::new(vec![Err(
+            ProcessError::AwaitingDnsPropagation,
+        )]));
+
+        assert!(canary(Box::new(processor.clone()), Duration::ZERO)
+            .run()
+            .await
+            .is_err());
+
+        assert_eq!(*processor.actions.lock().unwrap(), vec!["Order"]);
+    }
+}
@@ -3,8 +3,13 @@ use std::sync::Arc;
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
 use candid::{CandidType, Decode, Deserialize, Encode, Principal};
-use certificate_orchestrator_interface::{self as ifc, EncryptedPair, IcCertificate, Id};
-use futures::{stream, StreamExt, TryStreamExt};
+use certificate_orchestrator_interface::{
+    self as ifc, EncryptedPair, ExportFilter, IcCertificate, Id,
+};
+use futures::{
+    stream::{self, BoxStream},
+    StreamExt, TryStreamExt,
+};
 use ic_agent::{hash_tree::HashTree, Agent, Certificate};
 use mockall::automock;
 use serde::Serialize;
@@ -34,6 +39,14 @@ pub trait GetCert: Send + Sync {
     async fn get_cert(&self, id: &Id) -> Result<Pair, GetCertError>;
 }
 
+// Lets the storage backend be chosen at runtime
+#[async_trait]
+impl<T: GetCert + ?Sized> GetCert for Arc<T> {
+    async fn get_cert(&self, id: &Id) -> Result<Pair, GetCertError> {
+        (**self).get_cert(id).await
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum UploadError {
     #[error("Not found")]
@@ -48,10 +61,44 @@ pub trait Upload: Sync + Send {
     async fn upload(&self, id: &Id, pair: Pair) -> Result<(), UploadError>;
 }
 
+#[async_trait]
+impl<T: Upload + ?Sized> Upload for Arc<T> {
+    async fn upload(&self, id: &Id, pair: Pair) -> Result<(), UploadError> {
+        (**self).upload(id, pair).await
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RevokeError {
+    #[error("Not found")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// Withdraws a certificate from the orchestrator, so boundary nodes stop serving it
+#[automock]
+#[async_trait]
+pub trait Revoke: Sync + Send {
+    async fn revoke(&self, id: &Id) -> Result<(), RevokeError>;
+}
+
+#[async_trait]
+impl<T: Revoke + ?Sized> Revoke for Arc<T> {
+    async fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        (**self).revoke(id).await
+    }
+}
+
 #[derive(Debug, CandidType, Clone, Deserialize, Serialize)]
 pub struct Package {
     pub id: String,
     pub name: String,
+
+    // Named like the canister's field, so that the encoding matches the certified one
+    #[serde(rename = "altNames")]
+    pub alt_names: Vec<String>,
+
     pub canister: Principal,
     pub pair: Pair,
 }
@@ -62,13 +109,29 @@ pub enum ExportError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+// Exporters return the key to continue the export from, or none once the end of the export was reached.
+// Pages can be shorter than `limit` and still be followed by more entries.
+#[automock]
 #[async_trait]
 pub trait Export: Sync + Send {
     async fn export(
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError>;
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError>;
+}
+
+#[async_trait]
+impl<T: Export + ?Sized> Export for Arc<T> {
+    async fn export(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
+        (**self).export(key, limit, filter).await
+    }
 }
 
 pub struct CanisterCertGetter {
@@ -167,6 +230,45 @@ impl Upload for CanisterUploader {
     }
 }
 
+pub struct CanisterRevoker {
+    agent: Arc<Agent>,
+    canister_id: Principal,
+}
+
+impl CanisterRevoker {
+    pub fn new(agent: Arc<Agent>, canister_id: Principal) -> Self {
+        Self { agent, canister_id }
+    }
+}
+
+#[async_trait]
+impl Revoke for CanisterRevoker {
+    async fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        use ifc::{RevokeCertificateError as Error, RevokeCertificateResponse as Response};
+
+        let args = Encode!(&id).context("failed to encode arg")?;
+
+        let resp = self
+            .agent
+            .update(&self.canister_id, "revokeCertificate")
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(()) => Ok(()),
+            Response::Err(err) => Err(match err {
+                Error::NotFound => RevokeError::NotFound,
+                Error::Unauthorized => RevokeError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => RevokeError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
 pub struct CanisterExporter {
     agent: Arc<Agent>,
     canister_id: Principal,
@@ -184,36 +286,71 @@ impl Export for CanisterExporter {
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError> {
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
         use ifc::{
             ExportCertificatesCertifiedResponse as Response, ExportCertificatesError as Error,
+            ExportCertificatesFilteredResponse as FilteredResponse,
         };
 
-        let args = Encode!(&key, &limit).context("failed to encode arg")?;
-
-        let resp = self
-            .agent
-            .query(&self.canister_id, "exportCertificatesCertified")
-            .with_arg(args)
-            .call()
-            .await
-            .context("failed to query canister")?;
-
-        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+        // Filters are pushed down to the canister, which only returns matching packages.
+        // Filtered pages can end before `limit` matches were found, so the canister returns the key to continue from.
+        let resp = match filter.is_empty() {
+            true => {
+                let resp = self
+                    .agent
+                    .query(&self.canister_id, "exportCertificatesCertified")
+                    .with_arg(Encode!(&key, &limit).context("failed to encode arg")?)
+                    .call()
+                    .await
+                    .context("failed to query canister")?;
+
+                match Decode!(&resp, Response).context("failed to decode canister response")? {
+                    Response::Ok((pkgs, iccert)) => {
+                        // A full page indicates there might be more entries
+                        let next_key = match pkgs.len() as u64 == limit {
+                            true => pkgs.last().map(|pkg| pkg.id.to_owned()),
+                            false => None,
+                        };
+
+                        Ok((pkgs, iccert, next_key))
+                    }
+                    Response::Err(err) => Err(err),
+                }
+            }
+            false => {
+                let resp = self
+                    .agent
+                    .query(&self.canister_id, "exportCertificatesFiltered")
+                    .with_arg(Encode!(&key, &limit, filter).context("failed to encode arg")?)
+                    .call()
+                    .await
+                    .context("failed to query canister")?;
+
+                match Decode!(&resp, FilteredResponse)
+                    .context("failed to decode canister response")?
+                {
+                    FilteredResponse::Ok(out) => Ok(out),
+                    FilteredResponse::Err(err) => Err(err),
+                }
+            }
+        };
 
         match resp {
-            Response::Ok((pkgs, iccert)) => Ok((
+            Ok((pkgs, iccert, next_key)) => Ok((
                 pkgs.iter()
                     .map(|p| Package {
                         id: p.id.clone(),
                         name: p.name.clone().into(),
+                        alt_names: p.alt_names.iter().cloned().map(Into::into).collect(),
                         canister: p.canister,
                         pair: Pair(p.pair.0.clone(), p.pair.1.clone()),
                     })
                     .collect(),
                 iccert,
+                next_key,
             )),
-            Response::Err(err) => {
+            Err(err) => {
                 return Err(match err {
                     Error::Unauthorized => ExportError::UnexpectedError(anyhow!("unauthorized")),
                     Error::UnexpectedError(err) => ExportError::UnexpectedError(anyhow!(err)),
@@ -231,8 +368,9 @@ impl<T: Export> Export for WithDecode<T> {
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError> {
-        let (pkgs, iccert) = self.0.export(key, limit).await?;
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
+        let (pkgs, iccert, next_key) = self.0.export(key, limit, filter).await?;
 
         // Decode certificate
         let pkgs: Vec<Package> = stream::iter(pkgs.into_iter())
@@ -240,6 +378,7 @@ impl<T: Export> Export for WithDecode<T> {
                 Ok::<_, ExportError>(Package {
                     id: pkg.id,
                     name: pkg.name,
+                    alt_names: pkg.alt_names,
                     canister: pkg.canister,
                     pair: Pair(
                         self.1.decode(&pkg.pair.0).await?,
@@ -251,7 +390,7 @@ impl<T: Export> Export for WithDecode<T> {
             .await
             .context("failed to decode certificates")?;
 
-        Ok((pkgs, iccert))
+        Ok((pkgs, iccert, next_key))
     }
 }
 
@@ -263,8 +402,9 @@ impl<T: Export> Export for WithVerify<T> {
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError> {
-        let (pkgs, iccert) = self.0.export(key.clone(), limit).await?;
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
+        let (pkgs, iccert, next_key) = self.0.export(key.clone(), limit, filter).await?;
 
         let (cert, tree): (Certificate, HashTree<Vec<u8>>) = (
             serde_cbor::from_slice(&iccert.cert).context("failed to cbor-decode ic certificate")?,
@@ -272,11 +412,11 @@ impl<T: Export> Export for WithVerify<T> {
         );
 
         self.1
-            .verify(key, limit, &pkgs, &cert, &tree)
+            .verify(key, limit, filter, &pkgs, &cert, &tree)
             .await
             .context("failed to verify certificate")?;
 
-        Ok((pkgs, iccert))
+        Ok((pkgs, iccert, next_key))
     }
 }
 
@@ -288,66 +428,249 @@ impl<T: Export> Export for WithRetries<T> {
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError> {
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
         let mut counter = 0;
         while counter < self.1 {
-            if let Ok(pkgs) = self.0.export(key.clone(), limit).await {
+            if let Ok(pkgs) = self.0.export(key.clone(), limit, filter).await {
                 return Ok(pkgs);
             }
             counter += 1;
         }
-        self.0.export(key.clone(), limit).await
+        self.0.export(key.clone(), limit, filter).await
     }
 }
 
-pub struct WithPagination<T>(pub T, pub u64);
+// Wrapper to page through the canister export, returning up to `limit` packages after `key`,
+// ordered by ID. The wrapped exporter is queried with the given page size.
+pub struct WithPagination(pub Arc<dyn Export>, pub u64);
 
-#[async_trait]
-impl<T: Export> Export for WithPagination<T> {
-    async fn export(
+impl WithPagination {
+    // Streams the packages page by page, so that only a single page is held in memory at a time
+    pub fn stream(
         &self,
-        _: Option<String>,
-        _: u64,
-    ) -> Result<(Vec<Package>, IcCertificate), ExportError> {
-        let mut out = Vec::new();
-
-        // Disregard given `key` and `limit`, pagination will just process the entire dataset
-        let mut key: Option<String> = None;
-
-        loop {
-            let (mut pkgs, _) = self
-                .0
-                .export(
-                    key,    // key
-                    self.1, // limit
-                )
-                .await?;
-
-            if pkgs.len() < self.1 as usize {
-                out.append(&mut pkgs);
-                break;
+        key: Option<String>,
+        limit: u64,
+        filter: ExportFilter,
+    ) -> BoxStream<'static, Result<Package, ExportError>> {
+        let (exporter, page_size) = (self.0.clone(), self.1);
+
+        stream::try_unfold((key, limit, false), move |(key, remaining, is_done)| {
+            let (exporter, filter) = (exporter.clone(), filter.clone());
+
+            async move {
+                if is_done || remaining == 0 {
+                    return Ok::<_, ExportError>(None);
+                }
+
+                let (pkgs, _, next_key) = exporter
+                    .export(
+                        key.clone(), // key
+                        page_size,   // limit
+                        &filter,     // filter
+                    )
+                    .await?;
+
+                // Unfiltered pages but the first include the entry at `key` as well (for certification purposes),
+                // so only entries strictly after `key` are kept
+                let mut pkgs: Vec<Package> = pkgs
+                    .into_iter()
+                    .filter(|pkg| key.as_ref().map_or(true, |key| &pkg.id > key))
+                    .collect();
+
+                pkgs.truncate(remaining.try_into().unwrap_or(usize::MAX));
+                let remaining = remaining - pkgs.len() as u64;
+
+                let is_done = next_key.is_none() || next_key <= key;
+
+                Ok(Some((pkgs, (next_key, remaining, is_done))))
             }
+        })
+        .map_ok(|pkgs| stream::iter(pkgs.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed()
+    }
+}
 
-            key = Some(
-                pkgs.last()
-                    .expect("missing last element from packages list")
-                    .id
-                    .to_owned(),
-            );
-
-            // For every page but the last, remove the last entry
-            // This is because the next page will include that entry as well (for certification purposes)
-            pkgs.truncate(self.1 as usize - 1);
+#[async_trait]
+impl Export for WithPagination {
+    async fn export(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
+        let pkgs: Vec<Package> = self
+            .stream(key, limit, filter.clone())
+            .try_collect()
+            .await?;
 
-            out.append(&mut pkgs);
-        }
+        // A full page indicates there might be more entries
+        let next_key = match pkgs.len() as u64 == limit {
+            true => pkgs.last().map(|pkg| pkg.id.to_owned()),
+            false => None,
+        };
 
         Ok((
-            out,
+            pkgs,
             IcCertificate {
                 cert: Vec::new(),
                 tree: Vec::new(),
             },
+            next_key,
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Error;
+    use mockall::predicate;
+
+    fn pkg(id: &str) -> Package {
+        Package {
+            id: id.into(),
+            name: format!("{id}.com"),
+            alt_names: vec![],
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
+            pair: Pair(vec![], vec![]),
+        }
+    }
+
+    // Mimics the canister, which starts each page at `key` (inclusive)
+    fn exporter(ids: &'static [&'static str]) -> MockExport {
+        let mut exporter = MockExport::new();
+        exporter.expect_export().returning(move |key, limit, _| {
+            let pkgs: Vec<Package> = ids
+                .iter()
+                .filter(|id| key.as_ref().map_or(true, |key| **id >= key.as_str()))
+                .take(limit as usize)
+                .map(|id| pkg(id))
+                .collect();
+
+            let next_key = match pkgs.len() as u64 == limit {
+                true => pkgs.last().map(|pkg| pkg.id.to_owned()),
+                false => None,
+            };
+
+            Ok((
+                pkgs,
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                next_key,
+            ))
+        });
+        exporter
+    }
+
+    const IDS: &[&str] = &["a", "b", "c", "d", "e"];
+
+    #[tokio::test]
+    async fn paginate_all() -> Result<(), Error> {
+        let (pkgs, _, _) = WithPagination(Arc::new(exporter(IDS)), 2)
+            .export(None, u64::MAX, &ExportFilter::default())
+            .await?;
+
+        let ids: Vec<String> = pkgs.into_iter().map(|pkg| pkg.id).collect();
+        assert_eq!(ids, vec!["a", "b", "c", "d", "e"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paginate_after_with_limit() -> Result<(), Error> {
+        let (pkgs, _, _) = WithPagination(Arc::new(exporter(IDS)), 2)
+            .export(Some("b".into()), 2, &ExportFilter::default())
+            .await?;
+
+        let ids: Vec<String> = pkgs.into_iter().map(|pkg| pkg.id).collect();
+        assert_eq!(ids, vec!["c", "d"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paginate_after_last() -> Result<(), Error> {
+        let (pkgs, _, _) = WithPagination(Arc::new(exporter(IDS)), 2)
+            .export(Some("e".into()), 10, &ExportFilter::default())
+            .await?;
+
+        assert!(pkgs.is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn paginate_short_filtered_pages() -> Result<(), Error> {
+        // Filtered pages end once the canister's scan limit is reached, even when empty
+        let mut pages = vec![
+            (vec![], Some("b")),
+            (vec![pkg("c")], Some("d")),
+            (vec![pkg("e")], None),
+        ]
+        .into_iter();
+
+        let mut exporter = MockExport::new();
+        exporter.expect_export().times(3).returning(move |_, _, _| {
+            let (pkgs, next_key) = pages.next().unwrap();
+            Ok((
+                pkgs,
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                next_key.map(Into::into),
+            ))
+        });
+
+        let filter = ExportFilter {
+            name_suffix: Some(".com".into()),
+            ..Default::default()
+        };
+
+        let (pkgs, _, _) = WithPagination(Arc::new(exporter), 2)
+            .export(None, u64::MAX, &filter)
+            .await?;
+
+        let ids: Vec<String> = pkgs.into_iter().map(|pkg| pkg.id).collect();
+        assert_eq!(ids, vec!["c", "e"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stream_pages_lazily() -> Result<(), Error> {
+        let mut exporter = MockExport::new();
+        exporter
+            .expect_export()
+            .times(1)
+            .with(
+                predicate::eq(None),
+                predicate::eq(2),
+                predicate::eq(ExportFilter::default()),
+            )
+            .returning(|_, _, _| {
+                Ok((
+                    vec![pkg("a"), pkg("b")],
+                    IcCertificate {
+                        cert: vec![],
+                        tree: vec![],
+                    },
+                    Some("b".into()),
+                ))
+            });
+
+        let mut pkgs =
+            WithPagination(Arc::new(exporter), 2).stream(None, u64::MAX, ExportFilter::default());
+
+        // Only the first page is fetched when consuming its entries
+        assert_eq!(pkgs.try_next().await?.unwrap().id, "a");
+        assert_eq!(pkgs.try_next().await?.unwrap().id, "b");
+
+        Ok(())
+    }
+}
@@ -10,6 +10,7 @@ use ic_utils::{
     interfaces::http_request::{HeaderField, HttpRequestCanister},
 };
 use mockall::automock;
+use ring::hmac;
 use std::{
     io::{BufRead, Read},
     sync::Arc,
@@ -19,6 +20,9 @@ use trust_dns_resolver::{error::ResolveErrorKind, proto::rr::RecordType};
 
 use crate::dns::Resolve;
 
+// Served by canisters to prove they consent to being registered for a domain, one token per line
+pub const OWNERSHIP_TOKENS_PATH: &str = "/.well-known/ic-domain-ownership";
+
 #[derive(Debug, thiserror::Error)]
 pub enum CheckError {
     #[error("existing dns txt challenge record at {src}")]
@@ -27,6 +31,9 @@ pub enum CheckError {
     #[error("missing dns cname record from {src} to {dst}")]
     MissingDnsCname { src: String, dst: String },
 
+    #[error("missing dns {record_type} record for {src}")]
+    MissingDnsAddress { src: String, record_type: String },
+
     #[error("missing dns txt record from {src} to a canister id")]
     MissingDnsTxtCanisterId { src: String },
 
@@ -42,19 +49,57 @@ pub enum CheckError {
     #[error("domain is missing from canister {id} list of known domains")]
     MissingKnownDomains { id: String },
 
+    #[error("ownership token is missing from canister {id} list of ownership tokens")]
+    MissingOwnershipToken { id: String },
+
+    #[error("domain is blocked by the {entry} blocklist entry")]
+    BlockedDomain { entry: String },
+
+    #[error("top-level domain {tld} is reserved")]
+    ReservedTld { tld: String },
+
+    #[error("canister {id} has reached its limit of {limit} registrations")]
+    RegistrationLimitReached { id: String, limit: u64 },
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl CheckError {
+    // Whether the name was rejected by an acceptable-use policy rather than for its DNS setup
+    pub fn is_policy_violation(&self) -> bool {
+        matches!(
+            self,
+            Self::BlockedDomain { .. }
+                | Self::ReservedTld { .. }
+                | Self::RegistrationLimitReached { .. }
+        )
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait Check: Send + Sync {
     async fn check(&self, name: &str) -> Result<Principal, CheckError>;
 }
 
+// Address records a domain is required to have, directly or through a CNAME, to be registered
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum AddressPolicy {
+    // Either A or AAAA records, so that IPv6-only domains are accepted
+    Any,
+
+    // A records, whether or not there are AAAA records as well
+    Ipv4,
+
+    // Both A and AAAA records
+    Dual,
+}
+
 pub struct Checker {
     // configuration
-    delegation_domain: String,
+    delegation: Arc<Delegation>,
+    address_policy: AddressPolicy,
 
     // dependencies
     resolver: Box<dyn Resolve>,
@@ -64,9 +109,15 @@ pub struct Checker {
 }
 
 impl Checker {
-    pub fn new(delegation_domain: String, resolver: Box<dyn Resolve>, agent: Arc<Agent>) -> Self {
+    pub fn new(
+        delegation: Arc<Delegation>,
+        address_policy: AddressPolicy,
+        resolver: Box<dyn Resolve>,
+        agent: Arc<Agent>,
+    ) -> Self {
         Self {
-            delegation_domain,
+            delegation,
+            address_policy,
             resolver,
             agent,
         }
@@ -86,7 +137,7 @@ impl Check for Checker {
                     !rec.name()
                         .to_string()
                         .trim_end_matches('.')
-                        .ends_with(&self.delegation_domain.trim_end_matches('.'))
+                        .ends_with(&self.delegation.domain().trim_end_matches('.'))
                 }) {
                     // There's an existing challenge response. Return error.
                     Err(CheckError::ExistingDnsTxtChallenge {
@@ -106,32 +157,7 @@ impl Check for Checker {
             },
         }?;
 
-        // Phase 2 - Ensure a challenge delegation CNAME record exists
-        let cname_src = format!("_acme-challenge.{}.", name);
-        let cname_dst = format!("_acme-challenge.{}.{}.", name, self.delegation_domain);
-
-        self.resolver
-            .lookup(&cname_src, RecordType::CNAME)
-            .await
-            .map_err(|err| match err.kind() {
-                ResolveErrorKind::NoRecordsFound { .. } => CheckError::MissingDnsCname {
-                    src: cname_src.to_owned(),
-                    dst: cname_dst.to_owned(),
-                },
-                _ => CheckError::UnexpectedError(anyhow!("failed to resolve CNAME: {err}")),
-            })
-            .and_then(|rs| {
-                if !rs.iter().any(|r| r.to_string().eq(&cname_dst)) {
-                    return Err(CheckError::MissingDnsCname {
-                        src: cname_src.to_owned(),
-                        dst: cname_dst.to_owned(),
-                    });
-                }
-
-                Ok(())
-            })?;
-
-        // Phase 3 - Ensure a TXT record for a canister mapping exists
+        // Phase 2 - Ensure a TXT record for a canister mapping exists
         let txt_src = format!("_canister-id.{}.", name);
 
         let canister_id = self
@@ -170,96 +196,203 @@ impl Check for Checker {
                 Ok(id)
             })?;
 
-        // Phase 4 - Ensure canister mentions known domain.
-        let request = HttpRequest {
-            method: String::from("GET"),
-            url: String::from("/.well-known/ic-domains"),
-            headers: vec![],
-            body: vec![],
-        };
+        // Phase 3 - Ensure a challenge delegation CNAME record exists, its target depending on the canister
+        let cname_src = format!("_acme-challenge.{}.", name);
+        let cname_dst = self.delegation.target(name, &canister_id);
 
-        let (response,) = HttpRequestCanister::create(&self.agent, canister_id)
-            .http_request(&request.method, &request.url, vec![], vec![], None)
-            .call()
+        self.resolver
+            .lookup(&cname_src, RecordType::CNAME)
             .await
-            .map_err(|_| CheckError::KnownDomainsUnavailable {
-                id: canister_id.to_string(),
+            .map_err(|err| match err.kind() {
+                ResolveErrorKind::NoRecordsFound { .. } => CheckError::MissingDnsCname {
+                    src: cname_src.to_owned(),
+                    dst: cname_dst.to_owned(),
+                },
+                _ => CheckError::UnexpectedError(anyhow!("failed to resolve CNAME: {err}")),
+            })
+            .and_then(|rs| {
+                if !rs.iter().any(|r| r.to_string().eq(&cname_dst)) {
+                    return Err(CheckError::MissingDnsCname {
+                        src: cname_src.to_owned(),
+                        dst: cname_dst.to_owned(),
+                    });
+                }
+
+                Ok(())
             })?;
 
-        match response.status_code {
-            200 => Ok(()),
-            404 => Err(CheckError::MissingKnownDomains {
+        // Phase 4 - Ensure the domain resolves to addresses, as required by the address policy
+        check_addresses(self.resolver.as_ref(), self.address_policy, name).await?;
+
+        // Phase 5 - Ensure canister mentions known domain.
+        let body = match fetch_certified(&self.agent, canister_id, "/.well-known/ic-domains").await
+        {
+            Ok(Some(body)) => Ok(body),
+            Ok(None) => Err(CheckError::MissingKnownDomains {
                 id: canister_id.to_string(),
             }),
-            _ => Err(CheckError::KnownDomainsUnavailable {
+            Err(FetchError::Unavailable) => Err(CheckError::KnownDomainsUnavailable {
                 id: canister_id.to_string(),
             }),
+            Err(FetchError::UnexpectedError(err)) => Err(CheckError::UnexpectedError(err)),
         }?;
 
-        // Check response certification
-        let response_for_verification = HttpResponse {
-            status_code: response.status_code,
-            headers: response
-                .headers
-                .iter()
-                .map(|field| (field.0.to_string(), field.1.to_string()))
-                .collect::<Vec<(String, String)>>(),
-            body: response.body.clone(),
-            upgrade: response.upgrade,
+        // Search for name in response body
+        if !body.lines().any(|ln| match ln {
+            Ok(ln) => ln.eq(name),
+            _ => false,
+        }) {
+            return Err(CheckError::MissingKnownDomains {
+                id: canister_id.to_string(),
+            });
+        }
+
+        Ok(canister_id)
+    }
+}
+
+// Resolves the A and AAAA records of a domain, following CNAMEs, and ensures those required by the policy exist
+async fn check_addresses(
+    resolver: &dyn Resolve,
+    policy: AddressPolicy,
+    name: &str,
+) -> Result<(), CheckError> {
+    let src = format!("{name}.");
+
+    let has_records = {
+        let src = &src;
+
+        move |record_type: RecordType| async move {
+            match resolver.lookup(src, record_type).await {
+                Ok(rs) => Ok(rs.iter().any(|r| r.to_record_type() == record_type)),
+                Err(err) => match err.kind() {
+                    ResolveErrorKind::NoRecordsFound { .. } => Ok(false),
+                    _ => Err(CheckError::UnexpectedError(anyhow!(
+                        "failed to resolve {record_type}: {err}"
+                    ))),
+                },
+            }
+        }
+    };
+
+    let (has_a, has_aaaa) =
+        futures::try_join!(has_records(RecordType::A), has_records(RecordType::AAAA))?;
+
+    let missing = match (policy, has_a, has_aaaa) {
+        (AddressPolicy::Any, false, false) => Some("A or AAAA"),
+        (AddressPolicy::Ipv4 | AddressPolicy::Dual, false, _) => Some("A"),
+        (AddressPolicy::Dual, true, false) => Some("AAAA"),
+        _ => None,
+    };
+
+    match missing {
+        Some(record_type) => Err(CheckError::MissingDnsAddress {
+            src,
+            record_type: record_type.into(),
+        }),
+        None => Ok(()),
+    }
+}
+
+// Issues ownership tokens for pairs of domain and canister. Tokens are derived from a secret,
+// so they don't need to be stored and cannot be guessed, e.g. by whoever points a domain at someone else's canister.
+pub struct OwnershipTokens(hmac::Key);
+
+impl OwnershipTokens {
+    pub fn new(secret: &[u8]) -> Self {
+        Self(hmac::Key::new(hmac::HMAC_SHA256, secret))
+    }
+
+    pub fn token(&self, name: &str, canister_id: &Principal) -> String {
+        let msg = [name.as_bytes(), &[0], canister_id.as_slice()].concat();
+        let tag = hmac::sign(&self.0, &msg);
+
+        tag.as_ref()[..16]
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
+}
+
+// Names of the challenge response records in the delegation domain. Without a secret, the challenge of a domain
+// is delegated to `_acme-challenge.<domain>.<delegation-domain>`, which anyone registering the domain can use.
+// With a secret, it is delegated to `<token>.<delegation-domain>`, with a token derived from the domain and
+// its canister, so that a delegation only validates registrations of that domain for that canister.
+pub struct Delegation {
+    domain: String,
+    key: Option<hmac::Key>,
+}
+
+impl Delegation {
+    pub fn new(domain: String, secret: Option<&[u8]>) -> Self {
+        Self {
+            domain,
+            key: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret)),
+        }
+    }
+
+    pub fn domain(&self) -> &str {
+        &self.domain
+    }
+
+    // Name of the challenge response record of a domain, relative to the delegation domain
+    pub fn record(&self, name: &str, canister_id: &Principal) -> String {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return format!("_acme-challenge.{name}"),
         };
-        let max_cert_time_offset_ns = 300_000_000_000;
-        let current_time_ns = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
-            .as_nanos();
-        let ic_public_key = &self.agent.read_root_key();
-        verify_request_response_pair(
-            request,
-            response_for_verification,
-            canister_id.as_slice(),
-            current_time_ns,
-            max_cert_time_offset_ns,
-            ic_public_key,
-            MIN_VERIFICATION_VERSION,
-        )
-        .map_err(|_| CheckError::KnownDomainsUnavailable {
-            id: canister_id.to_string(),
-        })?;
 
-        // Decode body
-        let enc = response
-            .headers
+        let msg = [name.as_bytes(), &[0], canister_id.as_slice()].concat();
+        let tag = hmac::sign(key, &msg);
+
+        tag.as_ref()[..16]
             .iter()
-            .find(|HeaderField(name, _)| name == "Content-Encoding")
-            .map(|HeaderField(_, value)| value.as_ref());
-
-        let body = match enc {
-            // Identity
-            None | Some("identity") => Ok(response.body),
-
-            // Gzip
-            Some("gzip") => {
-                let mut buf = Vec::new();
-                GzDecoder::new(response.body.as_ref())
-                    .read_to_end(&mut buf)
-                    .map_err(|err| {
-                        CheckError::UnexpectedError(anyhow!(
-                            "failed to decode gzipped response body: {err}"
-                        ))
-                    })?;
-                Ok(buf)
-            }
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    }
 
-            // Other
-            Some(enc) => Err(anyhow!("unsupported content-encoding: {}", enc)),
-        }?;
+    // Fully qualified target of the CNAME record delegating the challenge of a domain
+    pub fn target(&self, name: &str, canister_id: &Principal) -> String {
+        format!("{}.{}.", self.record(name, canister_id), self.domain)
+    }
+}
+
+// Requires the canister a domain points to to serve the domain's ownership token,
+// on top of the checks of the underlying checker
+pub struct WithOwnership {
+    checker: Arc<dyn Check>,
+    tokens: Arc<OwnershipTokens>,
+    agent: Arc<Agent>,
+}
+
+impl WithOwnership {
+    pub fn new(checker: Arc<dyn Check>, tokens: Arc<OwnershipTokens>, agent: Arc<Agent>) -> Self {
+        Self {
+            checker,
+            tokens,
+            agent,
+        }
+    }
+}
+
+#[async_trait]
+impl Check for WithOwnership {
+    async fn check(&self, name: &str) -> Result<Principal, CheckError> {
+        let canister_id = self.checker.check(name).await?;
+
+        let token = self.tokens.token(name, &canister_id);
+
+        let body = match fetch_certified(&self.agent, canister_id, OWNERSHIP_TOKENS_PATH).await {
+            Ok(Some(body)) => body,
+            Ok(None) | Err(FetchError::Unavailable) => vec![],
+            Err(FetchError::UnexpectedError(err)) => return Err(CheckError::UnexpectedError(err)),
+        };
 
-        // Search for name in response body
         if !body.lines().any(|ln| match ln {
-            Ok(ln) => ln.eq(name),
+            Ok(ln) => ln.trim().eq(&token),
             _ => false,
         }) {
-            return Err(CheckError::MissingKnownDomains {
+            return Err(CheckError::MissingOwnershipToken {
                 id: canister_id.to_string(),
             });
         }
@@ -267,3 +400,203 @@ impl Check for Checker {
         Ok(canister_id)
     }
 }
+
+#[derive(Debug)]
+enum FetchError {
+    Unavailable,
+    UnexpectedError(anyhow::Error),
+}
+
+// Fetches a certified asset from a canister, `None` meaning the canister does not serve it
+async fn fetch_certified(
+    agent: &Agent,
+    canister_id: Principal,
+    url: &str,
+) -> Result<Option<Vec<u8>>, FetchError> {
+    let request = HttpRequest {
+        method: String::from("GET"),
+        url: String::from(url),
+        headers: vec![],
+        body: vec![],
+    };
+
+    let (response,) = HttpRequestCanister::create(agent, canister_id)
+        .http_request(&request.method, &request.url, vec![], vec![], None)
+        .call()
+        .await
+        .map_err(|_| FetchError::Unavailable)?;
+
+    match response.status_code {
+        200 => {}
+        404 => return Ok(None),
+        _ => return Err(FetchError::Unavailable),
+    }
+
+    // Check response certification
+    let response_for_verification = HttpResponse {
+        status_code: response.status_code,
+        headers: response
+            .headers
+            .iter()
+            .map(|field| (field.0.to_string(), field.1.to_string()))
+            .collect::<Vec<(String, String)>>(),
+        body: response.body.clone(),
+        upgrade: response.upgrade,
+    };
+    let max_cert_time_offset_ns = 300_000_000_000;
+    let current_time_ns = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_nanos();
+    let ic_public_key = &agent.read_root_key();
+    verify_request_response_pair(
+        request,
+        response_for_verification,
+        canister_id.as_slice(),
+        current_time_ns,
+        max_cert_time_offset_ns,
+        ic_public_key,
+        MIN_VERIFICATION_VERSION,
+    )
+    .map_err(|_| FetchError::Unavailable)?;
+
+    // Decode body
+    let enc = response
+        .headers
+        .iter()
+        .find(|HeaderField(name, _)| name == "Content-Encoding")
+        .map(|HeaderField(_, value)| value.as_ref());
+
+    match enc {
+        // Identity
+        None | Some("identity") => Ok(Some(response.body)),
+
+        // Gzip
+        Some("gzip") => {
+            let mut buf = Vec::new();
+            GzDecoder::new(response.body.as_ref())
+                .read_to_end(&mut buf)
+                .map_err(|err| {
+                    FetchError::UnexpectedError(anyhow!(
+                        "failed to decode gzipped response body: {err}"
+                    ))
+                })?;
+            Ok(Some(buf))
+        }
+
+        // Other
+        Some(enc) => Err(FetchError::UnexpectedError(anyhow!(
+            "unsupported content-encoding: {}",
+            enc
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use anyhow::Error;
+    use trust_dns_resolver::{
+        error::ResolveError,
+        lookup::Lookup,
+        proto::{
+            op::{Query, ResponseCode},
+            rr::{RData, Record},
+        },
+        Name,
+    };
+
+    use crate::dns::MockResolve;
+
+    // Resolves the domain to the given addresses, any other lookup finding no records
+    fn resolver(a: bool, aaaa: bool) -> MockResolve {
+        let mut resolver = MockResolve::new();
+        resolver.expect_lookup().returning(move |name, typ| {
+            let name = Name::from_utf8(name).unwrap();
+            let q = Query::query(name.clone(), typ);
+
+            let rdata = match typ {
+                RecordType::A if a => RData::A(Ipv4Addr::new(192, 0, 2, 1)),
+                RecordType::AAAA if aaaa => {
+                    RData::AAAA(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+                }
+                _ => {
+                    return Err(ResolveError::from(ResolveErrorKind::NoRecordsFound {
+                        query: Box::new(q),
+                        soa: None,
+                        negative_ttl: None,
+                        response_code: ResponseCode::NoError,
+                        trusted: true,
+                    }))
+                }
+            };
+
+            Ok(Lookup::new_with_max_ttl(
+                q,
+                Arc::new([Record::from_rdata(name, 300, rdata)]),
+            ))
+        });
+
+        resolver
+    }
+
+    #[tokio::test]
+    async fn address_policies() -> Result<(), Error> {
+        use AddressPolicy::*;
+
+        for (policy, a, aaaa, missing) in [
+            (Any, true, true, None),
+            (Any, true, false, None),
+            (Any, false, true, None), // IPv6-only
+            (Any, false, false, Some("A or AAAA")),
+            (Ipv4, true, false, None),
+            (Ipv4, false, true, Some("A")),
+            (Dual, true, true, None),
+            (Dual, true, false, Some("AAAA")),
+            (Dual, false, true, Some("A")),
+        ] {
+            let out = check_addresses(&resolver(a, aaaa), policy, "example.com").await;
+
+            match (out, missing) {
+                (Ok(()), None) => {}
+                (Err(CheckError::MissingDnsAddress { src, record_type }), Some(missing))
+                    if src == "example.com." && record_type == missing => {}
+                (out, _) => {
+                    return Err(anyhow!(
+                        "unexpected outcome for {policy:?} (a: {a}, aaaa: {aaaa}): {out:?}"
+                    ))
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn delegation_records() {
+        let (a, b) = (
+            Principal::from_text("aaaaa-aa").unwrap(),
+            Principal::from_text("oa7fk-maaaa-aaaam-abgka-cai").unwrap(),
+        );
+
+        let d = Delegation::new("delegation".into(), None);
+        assert_eq!(d.record("example.com", &a), "_acme-challenge.example.com");
+        assert_eq!(
+            d.target("example.com", &a),
+            "_acme-challenge.example.com.delegation."
+        );
+
+        // Records are scoped to both the domain and the canister
+        let d = Delegation::new("delegation".into(), Some(b"secret"));
+        let r = d.record("example.com", &a);
+
+        assert_eq!(r.len(), 32);
+        assert_eq!(d.record("example.com", &a), r);
+        assert_ne!(d.record("example.com", &b), r);
+        assert_ne!(d.record("www.example.com", &a), r);
+        assert_eq!(d.target("example.com", &a), format!("{r}.delegation."));
+    }
+}
@@ -1,3 +1,12 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    iter::successors,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
 use cloudflare::{
@@ -15,6 +24,8 @@ use cloudflare::{
     },
 };
 
+use serde::Deserialize;
+
 use crate::dns::{Create, Delete, Record};
 
 impl TryFrom<DnsContent> for Record {
@@ -28,59 +39,206 @@ impl TryFrom<DnsContent> for Record {
     }
 }
 
-pub struct Cloudflare {
-    client: Client,
-}
+const ZONES_PAGE_SIZE: u32 = 50;
 
-impl Cloudflare {
-    pub fn new(url: &str, key: &str) -> Result<Self, Error> {
-        let credentials = Credentials::UserAuthToken {
-            token: key.to_owned(),
-        };
+// Zones are listed again when a record does not belong to any known zone, at most this often
+const ZONES_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+// An API token scoped to a zone, e.g. with the `Zone.DNS:Edit` permission. The ID of the zone
+// can be provided for tokens that are not allowed to look it up (`Zone.Zone:Read`).
+#[derive(Clone, Debug, Deserialize)]
+pub struct ZoneToken {
+    pub zone: String,
+    pub token: String,
 
-        let client = Client::new(
-            credentials,
-            HttpApiClientConfig::default(),
-            Environment::Custom(url.try_into().context("invalid api url")?),
-        )
-        .context("failed to initialize cloudflare api client")?;
+    #[serde(default)]
+    pub zone_id: Option<String>,
+}
 
-        Ok(Self { client })
+impl ZoneToken {
+    // Loads a JSON list of `{"zone": .., "token": .., "zone_id": ..}` entries
+    pub fn load(path: &Path) -> Result<Vec<Self>, Error> {
+        let f = File::open(path).context("failed to open tokens file")?;
+        serde_json::from_reader(f).context("failed to parse tokens file")
     }
 }
 
-#[async_trait]
-impl Create for Cloudflare {
-    async fn create(&self, zone: &str, name: &str, record: Record) -> Result<(), Error> {
-        // Search zone
-        let resp = self
-            .client
+#[derive(Default)]
+struct Zones {
+    ids: HashMap<String, String>, // by zone name
+    refreshed_at: Option<Instant>,
+}
+
+// The zone a record belongs to, i.e. the longest suffix of its name that is a zone
+fn find_zone<'a>(name: &'a str, is_zone: impl Fn(&str) -> bool) -> Option<&'a str> {
+    successors(Some(name), |name| {
+        name.split_once('.').map(|(_, parent)| parent)
+    })
+    .find(|name| is_zone(name))
+}
+
+fn client(url: &str, token: &str) -> Result<Client, Error> {
+    let credentials = Credentials::UserAuthToken {
+        token: token.to_owned(),
+    };
+
+    Client::new(
+        credentials,
+        HttpApiClientConfig::default(),
+        Environment::Custom(url.try_into().context("invalid api url")?),
+    )
+    .context("failed to initialize cloudflare api client")
+}
+
+// Lists the zones accessible to a client, optionally only those with the given name
+async fn list_zones(client: &Client, name: Option<&str>) -> Result<HashMap<String, String>, Error> {
+    let mut ids = HashMap::new();
+
+    for page in 1.. {
+        let resp = client
             .request(&ListZones {
                 params: ListZonesParams {
-                    name: Some(zone.into()),
+                    name: name.map(Into::into),
                     status: None,
-                    page: None,
-                    per_page: None,
+                    page: Some(page),
+                    per_page: Some(ZONES_PAGE_SIZE),
                     order: None,
                     direction: None,
                     search_match: None,
                 },
             })
-            .await?;
+            .await
+            .context("failed to list zones")?;
+
+        let n = resp.result.len();
+        ids.extend(
+            resp.result
+                .into_iter()
+                .map(|Zone { id, name, .. }| (name, id)),
+        );
+
+        if n < ZONES_PAGE_SIZE as usize {
+            break;
+        }
+    }
+
+    Ok(ids)
+}
+
+// Manages records using scoped API tokens. Zones with their own token take precedence, other zones
+// are discovered using the default token. The IDs of zones are cached, as they do not change.
+pub struct Cloudflare {
+    default_client: Option<Client>,
+    zone_clients: HashMap<String, Client>, // by zone name
+    zones: Mutex<Zones>,
+}
+
+impl Cloudflare {
+    pub fn new(url: &str, token: Option<&str>, zone_tokens: Vec<ZoneToken>) -> Result<Self, Error> {
+        if token.is_none() && zone_tokens.is_empty() {
+            return Err(anyhow!("at least one cloudflare api token is required"));
+        }
+
+        let default_client = token.map(|token| client(url, token)).transpose()?;
+
+        let mut zone_clients = HashMap::new();
+        let mut zones = Zones::default();
+
+        for ZoneToken {
+            zone,
+            token,
+            zone_id,
+        } in zone_tokens
+        {
+            zone_clients.insert(zone.clone(), client(url, &token)?);
+
+            if let Some(id) = zone_id {
+                zones.ids.insert(zone, id);
+            }
+        }
+
+        Ok(Self {
+            default_client,
+            zone_clients,
+            zones: Mutex::new(zones),
+        })
+    }
 
-        let zone_id = match resp.result.first() {
-            Some(Zone { id, .. }) => id,
-            None => return Err(anyhow!("missing zone")),
+    // The client and zone ID to manage a record with
+    async fn zone(&self, name: &str) -> Result<(&Client, String), Error> {
+        if let Some(zone) = find_zone(name, |z| self.zone_clients.contains_key(z)) {
+            let client = &self.zone_clients[zone];
+
+            if let Some(id) = self.zones.lock().unwrap().ids.get(zone) {
+                return Ok((client, id.clone()));
+            }
+
+            let id = list_zones(client, Some(zone))
+                .await?
+                .remove(zone)
+                .ok_or_else(|| anyhow!("missing zone {zone}"))?;
+
+            self.zones
+                .lock()
+                .unwrap()
+                .ids
+                .insert(zone.to_string(), id.clone());
+
+            return Ok((client, id));
+        }
+
+        let client = self
+            .default_client
+            .as_ref()
+            .ok_or_else(|| anyhow!("no api token for the zone of {name}"))?;
+
+        let lookup = |zones: &Zones| {
+            find_zone(name, |z| zones.ids.contains_key(z)).map(|z| zones.ids[z].clone())
         };
 
+        {
+            let zones = self.zones.lock().unwrap();
+
+            if let Some(id) = lookup(&zones) {
+                return Ok((client, id));
+            }
+
+            if zones
+                .refreshed_at
+                .map_or(false, |t| t.elapsed() < ZONES_REFRESH_INTERVAL)
+            {
+                return Err(anyhow!("missing zone for {name}"));
+            }
+        }
+
+        let ids = list_zones(client, None).await?;
+
+        let mut zones = self.zones.lock().unwrap();
+        zones.ids.extend(ids);
+        zones.refreshed_at = Some(Instant::now());
+
+        match lookup(&zones) {
+            Some(id) => Ok((client, id)),
+            None => Err(anyhow!("missing zone for {name}")),
+        }
+    }
+}
+
+#[async_trait]
+impl Create for Cloudflare {
+    async fn create(&self, zone: &str, name: &str, record: Record) -> Result<(), Error> {
+        // Records are named in full, as the delegation domain may span multiple zones
+        let name = format!("{name}.{zone}");
+        let (client, zone_id) = self.zone(&name).await?;
+        let zone_id = &zone_id;
+
         // Check for existence
-        let resp = self
-            .client
+        let resp = client
             .request(&ListDnsRecords {
                 zone_identifier: zone_id,
                 params: ListDnsRecordsParams {
                     record_type: None,
-                    name: Some(format!("{}.{}", name, zone)),
+                    name: Some(name.clone()),
                     page: None,
                     per_page: None,
                     order: None,
@@ -113,28 +271,28 @@ impl Create for Cloudflare {
 
         match cmd {
             Some(Command::Create) => {
-                self.client
+                client
                     .request(&CreateDnsRecord {
                         zone_identifier: zone_id,
                         params: CreateDnsRecordParams {
                             ttl: None,
                             priority: None,
                             proxied: None,
-                            name,
+                            name: &name,
                             content,
                         },
                     })
                     .await?
             }
             Some(Command::Update(id)) => {
-                self.client
+                client
                     .request(&UpdateDnsRecord {
                         zone_identifier: zone_id,
                         identifier: &id,
                         params: UpdateDnsRecordParams {
                             ttl: None,
                             proxied: None,
-                            name,
+                            name: &name,
                             content,
                         },
                     })
@@ -152,35 +310,17 @@ impl Create for Cloudflare {
 #[async_trait]
 impl Delete for Cloudflare {
     async fn delete(&self, zone: &str, name: &str) -> Result<(), Error> {
-        // Search zone
-        let resp = self
-            .client
-            .request(&ListZones {
-                params: ListZonesParams {
-                    name: Some(zone.into()),
-                    status: None,
-                    page: None,
-                    per_page: None,
-                    order: None,
-                    direction: None,
-                    search_match: None,
-                },
-            })
-            .await?;
-
-        let zone_id = match resp.result.first() {
-            Some(Zone { id, .. }) => id,
-            None => return Err(anyhow!("missing zone")),
-        };
+        let name = format!("{name}.{zone}");
+        let (client, zone_id) = self.zone(&name).await?;
+        let zone_id = &zone_id;
 
         // Check for existence
-        let resp = self
-            .client
+        let resp = client
             .request(&ListDnsRecords {
                 zone_identifier: zone_id,
                 params: ListDnsRecordsParams {
                     record_type: None,
-                    name: Some(format!("{}.{}", name, zone)),
+                    name: Some(name.clone()),
                     page: None,
                     per_page: None,
                     order: None,
@@ -196,7 +336,7 @@ impl Delete for Cloudflare {
         };
 
         // Delete
-        self.client
+        client
             .request(&DeleteDnsRecord {
                 zone_identifier: zone_id,
                 identifier: &record.id,
@@ -206,3 +346,25 @@ impl Delete for Cloudflare {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_longest_zone() {
+        let zones = ["example.com", "sub.example.com"];
+        let is_zone = |z: &str| zones.contains(&z);
+
+        assert_eq!(
+            find_zone("_acme-challenge.a.sub.example.com", is_zone),
+            Some("sub.example.com")
+        );
+        assert_eq!(
+            find_zone("_acme-challenge.a.example.com", is_zone),
+            Some("example.com")
+        );
+        assert_eq!(find_zone("example.com", is_zone), Some("example.com"));
+        assert_eq!(find_zone("example.org", is_zone), None);
+    }
+}
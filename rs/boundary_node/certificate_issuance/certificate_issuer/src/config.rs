@@ -0,0 +1,224 @@
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Error};
+use serde_yaml::{Mapping, Value};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore, SemaphorePermit};
+
+const CONFIG_PATH_FLAG: &str = "--config-path";
+
+// The config file given on the command line, if any
+fn config_path(args: &[String]) -> Option<PathBuf> {
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if arg == CONFIG_PATH_FLAG {
+            return args.next().map(PathBuf::from);
+        }
+
+        if let Some(path) = arg.strip_prefix(&format!("{CONFIG_PATH_FLAG}=")) {
+            return Some(path.into());
+        }
+    }
+
+    None
+}
+
+// Reads a YAML config file mapping flag names to values, e.g. `task-concurrency: 20`,
+// into flags and their values. Lists are given as a repeated flag, and `true` as a flag without value.
+fn load(path: &Path) -> Result<Vec<(String, Option<String>)>, Error> {
+    let bs = fs::read(path).context("failed to read config file")?;
+
+    let cfg: Mapping = match serde_yaml::from_slice(&bs).context("failed to parse config file")? {
+        Value::Null => Mapping::new(),
+        Value::Mapping(cfg) => cfg,
+        _ => return Err(anyhow!("config file must be a mapping of flags to values")),
+    };
+
+    let mut flags = vec![];
+
+    for (k, v) in cfg {
+        let k = match k {
+            Value::String(k) => k,
+            k => return Err(anyhow!("invalid config key {k:?}")),
+        };
+
+        // Keys are accepted in snake case as well
+        let flag = format!("--{}", k.replace('_', "-"));
+
+        let vs = match v {
+            Value::Sequence(vs) => vs,
+            v => vec![v],
+        };
+
+        for v in vs {
+            match v {
+                Value::Bool(true) => flags.push((flag.clone(), None)),
+                Value::Bool(false) | Value::Null => {}
+                Value::Number(v) => flags.push((flag.clone(), Some(v.to_string()))),
+                Value::String(v) => flags.push((flag.clone(), Some(v))),
+                _ => return Err(anyhow!("invalid value for config key {k}")),
+            }
+        }
+    }
+
+    Ok(flags)
+}
+
+// Command-line arguments completed with the config file they point to (if any),
+// where flags given on the command line take precedence over the config file
+pub fn args(args: &[String]) -> Result<Vec<String>, Error> {
+    let path = match config_path(args) {
+        Some(path) => path,
+        None => return Ok(args.to_vec()),
+    };
+
+    let flags = load(&path).with_context(|| format!("failed to load {}", path.display()))?;
+
+    let given: HashSet<&str> = args
+        .iter()
+        .filter(|arg| arg.starts_with("--"))
+        .map(|arg| arg.split('=').next().unwrap_or(arg))
+        .collect();
+
+    let mut out: Vec<String> = args.iter().take(1).cloned().collect();
+
+    for (flag, v) in flags {
+        if given.contains(flag.as_str()) {
+            continue;
+        }
+
+        out.push(flag);
+        out.extend(v);
+    }
+
+    out.extend(args.iter().skip(1).cloned());
+
+    Ok(out)
+}
+
+// Limits how many tasks are processed concurrently, with a limit that can be changed at runtime
+pub struct Concurrency {
+    sem: Arc<Semaphore>,
+    limit: Mutex<u32>,
+}
+
+impl Concurrency {
+    pub fn new(limit: u32) -> Self {
+        Self {
+            sem: Arc::new(Semaphore::new(limit as usize)),
+            limit: Mutex::new(limit),
+        }
+    }
+
+    pub async fn acquire(&self) -> Result<OwnedSemaphorePermit, AcquireError> {
+        self.sem.clone().acquire_owned().await
+    }
+
+    // Lowering the limit takes effect as tasks in progress complete
+    pub fn set_limit(&self, limit: u32) {
+        let mut cur = self.limit.lock().unwrap();
+
+        if limit > *cur {
+            self.sem.add_permits((limit - *cur) as usize);
+        }
+
+        if limit < *cur {
+            let sem = self.sem.clone();
+            let n = *cur - limit;
+
+            tokio::spawn(async move {
+                if let Ok(permits) = sem.acquire_many_owned(n).await {
+                    permits.forget();
+                }
+            });
+        }
+
+        *cur = limit;
+    }
+
+    // Waits for all tasks in progress to complete
+    pub async fn drain(&self) -> Result<SemaphorePermit<'_>, AcquireError> {
+        let limit = *self.limit.lock().unwrap();
+        self.sem.acquire_many(limit).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn args_from_config_file() -> Result<(), Error> {
+        let p = std::env::temp_dir().join(format!("config-{}.yaml", rand::random::<u64>()));
+        fs::write(
+            &p,
+            "task-concurrency: 20\ndelegation_domain: example.com\nname-servers: [1.1.1.1, 8.8.8.8]\nlog-level: debug\n",
+        )?;
+        let path = p.to_str().unwrap();
+
+        let out = args(&to_args(&[
+            "issuer",
+            "--config-path",
+            path,
+            "--log-level=warn",
+        ]))?;
+
+        assert_eq!(
+            out,
+            to_args(&[
+                "issuer",
+                "--task-concurrency",
+                "20",
+                "--delegation-domain",
+                "example.com",
+                "--name-servers",
+                "1.1.1.1",
+                "--name-servers",
+                "8.8.8.8",
+                "--config-path",
+                path,
+                "--log-level=warn",
+            ])
+        );
+
+        // Arguments are left as they are without a config file
+        let cli = to_args(&["issuer", "--log-level", "warn"]);
+        assert_eq!(args(&cli)?, cli);
+
+        fs::remove_file(&p)?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn concurrency_set_limit() {
+        let c = Concurrency::new(1);
+
+        let permit = c.acquire().await.unwrap();
+        c.set_limit(2);
+        let _other = c.acquire().await.unwrap();
+
+        // Tasks in progress are not interrupted when lowering the limit,
+        // a completed task's permit is withheld instead
+        c.set_limit(1);
+        tokio::task::yield_now().await;
+        drop(permit);
+
+        assert!(timeout(Duration::from_millis(10), c.acquire())
+            .await
+            .is_err());
+    }
+}
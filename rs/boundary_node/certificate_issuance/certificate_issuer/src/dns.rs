@@ -1,8 +1,18 @@
-use anyhow::Error;
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
 use mockall::automock;
 use trust_dns_resolver::{
-    error::ResolveError, lookup::Lookup, proto::rr::RecordType, TokioAsyncResolver,
+    config::{NameServerConfigGroup, Protocol},
+    error::ResolveError,
+    lookup::Lookup,
+    proto::rr::RecordType,
+    TokioAsyncResolver,
 };
 
 #[automock]
@@ -21,6 +31,88 @@ impl Resolve for Resolver {
     }
 }
 
+// An upstream resolver, e.g. `udp://8.8.8.8`, `tls://1.1.1.1:853#cloudflare-dns.com`
+// or `https://9.9.9.9/#dns.quad9.net`. Encrypted protocols require the name to verify the server's certificate against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Upstream {
+    pub protocol: Protocol,
+    pub addr: SocketAddr,
+    pub tls_name: Option<String>,
+}
+
+impl FromStr for Upstream {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (protocol, rest) = s
+            .split_once("://")
+            .ok_or_else(|| anyhow!("expected <protocol>://<ip>[:<port>][#<tls_name>]"))?;
+
+        let (protocol, default_port) = match protocol {
+            "udp" => (Protocol::Udp, 53),
+            "tcp" => (Protocol::Tcp, 53),
+            "tls" => (Protocol::Tls, 853),
+            "https" => (Protocol::Https, 443),
+            _ => return Err(anyhow!("unknown resolver protocol {protocol}")),
+        };
+
+        let (addr, tls_name) = match rest.split_once('#') {
+            Some((addr, name)) => (addr, Some(name.to_string())),
+            None => (rest, None),
+        };
+
+        // DoH endpoints are commonly written with their path, which is always `/dns-query`
+        let addr = addr.trim_end_matches("/dns-query").trim_end_matches('/');
+
+        let addr = match addr.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => SocketAddr::new(
+                addr.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .context("invalid resolver address")?,
+                default_port,
+            ),
+        };
+
+        if protocol.is_encrypted() && tls_name.is_none() {
+            return Err(anyhow!("{s} requires a tls name, e.g. #dns.example.com"));
+        }
+
+        Ok(Self {
+            protocol,
+            addr,
+            tls_name,
+        })
+    }
+}
+
+pub fn name_servers(upstreams: &[Upstream]) -> NameServerConfigGroup {
+    let mut group = NameServerConfigGroup::new();
+
+    for u in upstreams {
+        let ips = &[u.addr.ip()];
+        let tls_name = u.tls_name.clone().unwrap_or_default();
+
+        let mut ns = match u.protocol {
+            Protocol::Tls => {
+                NameServerConfigGroup::from_ips_tls(ips, u.addr.port(), tls_name, true)
+            }
+            Protocol::Https => {
+                NameServerConfigGroup::from_ips_https(ips, u.addr.port(), tls_name, true)
+            }
+            _ => NameServerConfigGroup::from_ips_clear(ips, u.addr.port(), true),
+        };
+
+        // Clear name servers are configured for both UDP and TCP
+        ns.retain(|ns| ns.protocol == u.protocol);
+
+        group.merge(ns);
+    }
+
+    group
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum Record {
     Txt(String),
@@ -32,8 +124,74 @@ pub trait Create: Sync + Send {
     async fn create(&self, zone: &str, name: &str, record: Record) -> Result<(), Error>;
 }
 
+// Lets a single client, and its caches, be shared
+#[async_trait]
+impl<T: Create + ?Sized> Create for Arc<T> {
+    async fn create(&self, zone: &str, name: &str, record: Record) -> Result<(), Error> {
+        (**self).create(zone, name, record).await
+    }
+}
+
 #[automock]
 #[async_trait]
 pub trait Delete: Sync + Send {
     async fn delete(&self, zone: &str, name: &str) -> Result<(), Error>;
 }
+
+#[async_trait]
+impl<T: Delete + ?Sized> Delete for Arc<T> {
+    async fn delete(&self, zone: &str, name: &str) -> Result<(), Error> {
+        (**self).delete(zone, name).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_upstream() -> Result<(), Error> {
+        for (s, protocol, addr, tls_name) in [
+            ("udp://8.8.8.8", Protocol::Udp, "8.8.8.8:53", None),
+            (
+                "tcp://[2001:4860:4860::8888]",
+                Protocol::Tcp,
+                "[2001:4860:4860::8888]:53",
+                None,
+            ),
+            (
+                "tls://1.1.1.1:853#cloudflare-dns.com",
+                Protocol::Tls,
+                "1.1.1.1:853",
+                Some("cloudflare-dns.com"),
+            ),
+            (
+                "https://9.9.9.9/dns-query#dns.quad9.net",
+                Protocol::Https,
+                "9.9.9.9:443",
+                Some("dns.quad9.net"),
+            ),
+        ] {
+            assert_eq!(
+                s.parse::<Upstream>()?,
+                Upstream {
+                    protocol,
+                    addr: addr.parse()?,
+                    tls_name: tls_name.map(String::from),
+                },
+                "{s}"
+            );
+        }
+
+        for s in [
+            "8.8.8.8",
+            "quic://8.8.8.8",
+            "tls://1.1.1.1",
+            "udp://dns.google",
+        ] {
+            assert!(s.parse::<Upstream>().is_err(), "{s}");
+        }
+
+        Ok(())
+    }
+}
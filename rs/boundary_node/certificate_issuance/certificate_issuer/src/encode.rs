@@ -1,18 +1,78 @@
-use std::{sync::Arc, time::Instant};
+use std::{collections::HashSet, sync::Arc, time::Instant};
 
 use anyhow::{anyhow, Error};
 use async_trait::async_trait;
 use chacha20poly1305::{
     aead::{rand_core::RngCore, Aead, OsRng},
-    XChaCha20Poly1305, XNonce,
+    KeyInit, XChaCha20Poly1305, XNonce,
 };
 use opentelemetry::KeyValue;
+use sha2::{Digest, Sha256};
 use tracing::info;
 
 use crate::metrics::{MetricParams, WithMetrics};
 
 const NONCE_LEN: usize = 24;
 
+// Ciphertexts are prefixed with the envelope version and the ID of the key they were encrypted with.
+// Legacy ciphertexts, which carry neither, are decrypted by trying every configured key.
+const ENVELOPE_VERSION: u8 = 1;
+const KEY_ID_LEN: usize = 4;
+const HEADER_LEN: usize = 1 + KEY_ID_LEN;
+
+pub type KeyId = [u8; KEY_ID_LEN];
+
+// Symmetric keys, newest first. Data is encrypted with the newest key and can be
+// decrypted with any of them, so that keys can be rotated without re-issuing certificates.
+pub struct Keyring(Vec<(KeyId, XChaCha20Poly1305)>);
+
+impl Keyring {
+    pub fn new(keys: Vec<Vec<u8>>) -> Result<Self, Error> {
+        if keys.is_empty() {
+            return Err(anyhow!("at least one key is required"));
+        }
+
+        let mut ids = HashSet::new();
+        let mut ring = vec![];
+
+        for key in keys {
+            // Key IDs are derived from the keys themselves, so they need no configuration
+            let id = key_id(&key);
+            if !ids.insert(id) {
+                return Err(anyhow!("duplicate key id {id:02x?}"));
+            }
+
+            let cipher = XChaCha20Poly1305::new_from_slice(&key)
+                .map_err(|_| anyhow!("failed to init symmetric key"))?;
+
+            ring.push((id, cipher));
+        }
+
+        Ok(Self(ring))
+    }
+
+    fn current(&self) -> &(KeyId, XChaCha20Poly1305) {
+        &self.0[0]
+    }
+
+    fn get(&self, id: &[u8]) -> Option<&XChaCha20Poly1305> {
+        self.0.iter().find(|(k, _)| k == id).map(|(_, c)| c)
+    }
+
+    // Whether the data was encrypted with the newest key
+    pub fn is_current(&self, data: &[u8]) -> bool {
+        data.len() >= HEADER_LEN
+            && data[0] == ENVELOPE_VERSION
+            && data[1..HEADER_LEN] == self.current().0
+    }
+}
+
+fn key_id(key: &[u8]) -> KeyId {
+    let mut id = KeyId::default();
+    id.copy_from_slice(&Sha256::digest(key)[..KEY_ID_LEN]);
+    id
+}
+
 #[async_trait]
 pub trait Encode: Sync + Send {
     async fn encode(&self, v: &[u8]) -> Result<Vec<u8>, Error>;
@@ -24,12 +84,12 @@ pub trait Decode: Sync + Send {
 }
 
 pub struct Encoder {
-    cipher: Arc<XChaCha20Poly1305>,
+    keys: Arc<Keyring>,
 }
 
 impl Encoder {
-    pub fn new(cipher: Arc<XChaCha20Poly1305>) -> Self {
-        Self { cipher }
+    pub fn new(keys: Arc<Keyring>) -> Self {
+        Self { keys }
     }
 }
 
@@ -44,14 +104,17 @@ impl Encode for Encoder {
             return Err(anyhow!("wrong nonce length"));
         }
 
-        let data_enc = self
-            .cipher
+        let (key_id, cipher) = self.keys.current();
+
+        let data_enc = cipher
             .encrypt(&nonce, data)
             .map_err(|err| anyhow!("failed to encrypt data: {err}"))?;
 
         Ok([
-            nonce.to_vec(), // non-encrypted nonce
-            data_enc,       // encrypted data
+            vec![ENVELOPE_VERSION], // envelope version
+            key_id.to_vec(),        // key id
+            nonce.to_vec(),         // non-encrypted nonce
+            data_enc,               // encrypted data
         ]
         .concat())
     }
@@ -85,27 +148,48 @@ impl<T: Encode> Encode for WithMetrics<T> {
 }
 
 pub struct Decoder {
-    cipher: Arc<XChaCha20Poly1305>,
+    keys: Arc<Keyring>,
 }
 
 impl Decoder {
-    pub fn new(cipher: Arc<XChaCha20Poly1305>) -> Self {
-        Self { cipher }
+    pub fn new(keys: Arc<Keyring>) -> Self {
+        Self { keys }
     }
 }
 
+fn decrypt(cipher: &XChaCha20Poly1305, data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < NONCE_LEN {
+        return None;
+    }
+
+    let (nonce, data_enc) = data.split_at(NONCE_LEN);
+    let nonce = XNonce::from_slice(nonce);
+
+    cipher.decrypt(nonce, data_enc).ok()
+}
+
 #[async_trait]
 impl Decode for Decoder {
     async fn decode(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
-        let (nonce, data_enc) = data.split_at(NONCE_LEN);
-        let nonce = XNonce::from_slice(nonce);
-
-        let data = self
-            .cipher
-            .decrypt(nonce, data_enc)
-            .map_err(|err| anyhow!("failed to decrypt data: {err}"))?;
+        // Versioned envelope
+        if data.len() >= HEADER_LEN && data[0] == ENVELOPE_VERSION {
+            let (key_id, data_enc) = data[1..].split_at(KEY_ID_LEN);
+
+            if let Some(data) = self
+                .keys
+                .get(key_id)
+                .and_then(|cipher| decrypt(cipher, data_enc))
+            {
+                return Ok(data);
+            }
+        }
 
-        Ok(data)
+        // Legacy ciphertext, the header above might just have been part of its nonce
+        self.keys
+            .0
+            .iter()
+            .find_map(|(_, cipher)| decrypt(cipher, data))
+            .ok_or_else(|| anyhow!("failed to decrypt data with any of the configured keys"))
     }
 }
 
@@ -135,3 +219,51 @@ impl<T: Decode> Decode for WithMetrics<T> {
         out
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD_KEY: [u8; 32] = [1; 32];
+    const NEW_KEY: [u8; 32] = [2; 32];
+
+    #[tokio::test]
+    async fn decode_after_rotation() -> Result<(), Error> {
+        let old = Arc::new(Keyring::new(vec![OLD_KEY.to_vec()])?);
+        let new = Arc::new(Keyring::new(vec![NEW_KEY.to_vec(), OLD_KEY.to_vec()])?);
+
+        let data = Encoder::new(old.clone()).encode(b"data").await?;
+        assert!(old.is_current(&data));
+        assert!(!new.is_current(&data));
+
+        // Data encrypted with a previous key can still be decrypted
+        assert_eq!(Decoder::new(new.clone()).decode(&data).await?, b"data");
+
+        let data = Encoder::new(new.clone()).encode(b"data").await?;
+        assert!(new.is_current(&data));
+
+        // But not once the key has been dropped
+        assert!(Decoder::new(old).decode(&data).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn decode_legacy() -> Result<(), Error> {
+        let keys = Arc::new(Keyring::new(vec![NEW_KEY.to_vec(), OLD_KEY.to_vec()])?);
+
+        // Ciphertexts used to consist of the nonce and the encrypted data only
+        let nonce = [0u8; NONCE_LEN];
+        let cipher = XChaCha20Poly1305::new_from_slice(&OLD_KEY)
+            .map_err(|_| anyhow!("failed to init symmetric key"))?;
+        let data_enc = cipher
+            .encrypt(XNonce::from_slice(&nonce), &b"data"[..])
+            .map_err(|err| anyhow!("failed to encrypt data: {err}"))?;
+        let data = [nonce.to_vec(), data_enc].concat();
+
+        assert!(!keys.is_current(&data));
+        assert_eq!(Decoder::new(keys).decode(&data).await?, b"data");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::{
+    acme::{has_problem, CAA_PROBLEM, RATE_LIMITED_PROBLEM},
+    caa::CaaError,
+    check::CheckError,
+    work::ProcessError,
+};
+
+// Machine-readable reasons for a registration to fail or be parked
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    DelegationCnameMissing,
+    ExistingChallengeRecord,
+    AddressRecordMissing,
+    CanisterIdRecordMissing,
+    CanisterIdRecordDuplicate,
+    CanisterIdRecordInvalid,
+    KnownDomainsUnavailable,
+    KnownDomainsMissing,
+    OwnershipTokenMissing,
+    DomainBlocked,
+    ReservedTld,
+    RegistrationLimitReached,
+    CaaForbidsCa,
+    ChallengeNotPropagated,
+    AcmeRateLimited,
+    AcmeOrderInvalid,
+    CertificateRejected,
+    PhaseTimeout,
+    Internal,
+}
+
+impl ErrorCode {
+    const ALL: [Self; 19] = [
+        Self::DelegationCnameMissing,
+        Self::ExistingChallengeRecord,
+        Self::AddressRecordMissing,
+        Self::CanisterIdRecordMissing,
+        Self::CanisterIdRecordDuplicate,
+        Self::CanisterIdRecordInvalid,
+        Self::KnownDomainsUnavailable,
+        Self::KnownDomainsMissing,
+        Self::OwnershipTokenMissing,
+        Self::DomainBlocked,
+        Self::ReservedTld,
+        Self::RegistrationLimitReached,
+        Self::CaaForbidsCa,
+        Self::ChallengeNotPropagated,
+        Self::AcmeRateLimited,
+        Self::AcmeOrderInvalid,
+        Self::CertificateRejected,
+        Self::PhaseTimeout,
+        Self::Internal,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::DelegationCnameMissing => "DELEGATION_CNAME_MISSING",
+            Self::ExistingChallengeRecord => "EXISTING_CHALLENGE_RECORD",
+            Self::AddressRecordMissing => "ADDRESS_RECORD_MISSING",
+            Self::CanisterIdRecordMissing => "CANISTER_ID_RECORD_MISSING",
+            Self::CanisterIdRecordDuplicate => "CANISTER_ID_RECORD_DUPLICATE",
+            Self::CanisterIdRecordInvalid => "CANISTER_ID_RECORD_INVALID",
+            Self::KnownDomainsUnavailable => "KNOWN_DOMAINS_UNAVAILABLE",
+            Self::KnownDomainsMissing => "KNOWN_DOMAINS_MISSING",
+            Self::OwnershipTokenMissing => "OWNERSHIP_TOKEN_MISSING",
+            Self::DomainBlocked => "DOMAIN_BLOCKED",
+            Self::ReservedTld => "RESERVED_TLD",
+            Self::RegistrationLimitReached => "REGISTRATION_LIMIT_REACHED",
+            Self::CaaForbidsCa => "CAA_FORBIDS_CA",
+            Self::ChallengeNotPropagated => "CHALLENGE_NOT_PROPAGATED",
+            Self::AcmeRateLimited => "ACME_RATE_LIMITED",
+            Self::AcmeOrderInvalid => "ACME_ORDER_INVALID",
+            Self::CertificateRejected => "CERTIFICATE_REJECTED",
+            Self::PhaseTimeout => "PHASE_TIMEOUT",
+            Self::Internal => "INTERNAL",
+        }
+    }
+
+    // What the domain owner (or operator) can do about it
+    pub fn hint(&self) -> &'static str {
+        match self {
+            Self::DelegationCnameMissing => "Add a CNAME record delegating _acme-challenge.<domain> to the target given in the message, also returned by /registrations/delegation",
+            Self::ExistingChallengeRecord => "Remove the existing TXT record at _acme-challenge.<domain>, the CNAME record delegating it must be the only record",
+            Self::AddressRecordMissing => "Point the domain at the boundary nodes with the A and/or AAAA records given in the message, or a CNAME record resolving to them",
+            Self::CanisterIdRecordMissing => "Add a TXT record at _canister-id.<domain> containing the ID of the canister",
+            Self::CanisterIdRecordDuplicate => "Keep a single TXT record at _canister-id.<domain>",
+            Self::CanisterIdRecordInvalid => "Fix the TXT record at _canister-id.<domain> to contain a valid canister ID",
+            Self::KnownDomainsUnavailable => "Make sure the canister serves /.well-known/ic-domains",
+            Self::KnownDomainsMissing => "Add the domain to the canister's /.well-known/ic-domains file",
+            Self::OwnershipTokenMissing => "Add the domain's ownership token to the canister's /.well-known/ic-domain-ownership file",
+            Self::DomainBlocked => "Contact the operator if the domain was blocked in error",
+            Self::ReservedTld => "Register a domain under a public top-level domain, reserved ones cannot be issued certificates",
+            Self::RegistrationLimitReached => "Remove registrations of the canister that are no longer needed, or contact the operator to raise the limit",
+            Self::CaaForbidsCa => "Add a CAA record authorizing the certificate authority, e.g. 0 issue \"letsencrypt.org\", or remove the offending records",
+            Self::ChallengeNotPropagated => "Make sure the CNAME record of _acme-challenge.<domain> is served by all of the domain's name servers",
+            Self::AcmeRateLimited => "No action needed, the registration is retried once the rate limit allows",
+            Self::AcmeOrderInvalid => "Check the domain's DNS setup, a new order is placed on retry",
+            Self::CertificateRejected => "No action needed, the certificate was not deployed and a new order is placed on retry",
+            Self::PhaseTimeout => "No action needed, the registration is retried",
+            Self::Internal => "Contact the operator if the error persists",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// A failure of a registration, along with the offending record if any.
+// Failures are stored as part of the registration's state, formatted as `<code>(<record>): <message>`
+// so they remain readable, and parsed back when the registration is queried.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Failure {
+    pub code: ErrorCode,
+    pub record: Option<String>,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn classify(err: &ProcessError) -> Self {
+        let (code, record) = match err {
+            ProcessError::FailedUserConfigurationCheck(err) => match err {
+                CheckError::ExistingDnsTxtChallenge { src } => {
+                    (ErrorCode::ExistingChallengeRecord, Some(src))
+                }
+                CheckError::MissingDnsCname { src, .. } => {
+                    (ErrorCode::DelegationCnameMissing, Some(src))
+                }
+                CheckError::MissingDnsAddress { src, .. } => {
+                    (ErrorCode::AddressRecordMissing, Some(src))
+                }
+                CheckError::MissingDnsTxtCanisterId { src } => {
+                    (ErrorCode::CanisterIdRecordMissing, Some(src))
+                }
+                CheckError::MultipleDnsTxtCanisterId { src } => {
+                    (ErrorCode::CanisterIdRecordDuplicate, Some(src))
+                }
+                CheckError::InvalidDnsTxtCanisterId { src, .. } => {
+                    (ErrorCode::CanisterIdRecordInvalid, Some(src))
+                }
+                CheckError::KnownDomainsUnavailable { .. } => {
+                    (ErrorCode::KnownDomainsUnavailable, None)
+                }
+                CheckError::MissingKnownDomains { .. } => (ErrorCode::KnownDomainsMissing, None),
+                CheckError::MissingOwnershipToken { .. } => {
+                    (ErrorCode::OwnershipTokenMissing, None)
+                }
+                CheckError::BlockedDomain { .. } => (ErrorCode::DomainBlocked, None),
+                CheckError::ReservedTld { .. } => (ErrorCode::ReservedTld, None),
+                CheckError::RegistrationLimitReached { .. } => {
+                    (ErrorCode::RegistrationLimitReached, None)
+                }
+                CheckError::UnexpectedError(_) => (ErrorCode::Internal, None),
+            },
+            ProcessError::FailedCaaCheck(CaaError::Unauthorized { src, .. }) => {
+                (ErrorCode::CaaForbidsCa, Some(src))
+            }
+            ProcessError::AwaitingDnsPropagation => (ErrorCode::ChallengeNotPropagated, None),
+            ProcessError::AwaitingRateLimitBudget(_) => (ErrorCode::AcmeRateLimited, None),
+            ProcessError::AcmeOrderInvalid => (ErrorCode::AcmeOrderInvalid, None),
+            ProcessError::InvalidCertificate(_) => (ErrorCode::CertificateRejected, None),
+            ProcessError::PhaseTimeout { .. } => (ErrorCode::PhaseTimeout, None),
+            ProcessError::UnexpectedError(err) if has_problem(err, RATE_LIMITED_PROBLEM) => {
+                (ErrorCode::AcmeRateLimited, None)
+            }
+            ProcessError::UnexpectedError(err) if has_problem(err, CAA_PROBLEM) => {
+                (ErrorCode::CaaForbidsCa, None)
+            }
+            _ => (ErrorCode::Internal, None),
+        };
+
+        Self {
+            code,
+            record: record.cloned(),
+            message: err.to_string(),
+        }
+    }
+
+    // Failures stored before they carried a code are not parsed
+    pub fn parse(s: &str) -> Option<Self> {
+        let (prefix, message) = s.split_once(": ")?;
+
+        let (code, record) = match prefix.split_once('(') {
+            Some((code, record)) => (code, Some(record.strip_suffix(')')?.to_string())),
+            None => (prefix, None),
+        };
+
+        Some(Self {
+            code: *ErrorCode::ALL.iter().find(|c| c.as_str() == code)?,
+            record,
+            message: message.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.record {
+            Some(record) => write!(f, "{}({}): {}", self.code, record, self.message),
+            None => write!(f, "{}: {}", self.code, self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    use anyhow::anyhow;
+
+    use crate::inspect::InspectError;
+
+    #[test]
+    fn failure_roundtrip() {
+        for (err, code, record) in [
+            (
+                ProcessError::FailedUserConfigurationCheck(CheckError::MissingDnsCname {
+                    src: "_acme-challenge.example.com".into(),
+                    dst: "_acme-challenge.example.com.delegation".into(),
+                }),
+                ErrorCode::DelegationCnameMissing,
+                Some("_acme-challenge.example.com"),
+            ),
+            (
+                ProcessError::FailedUserConfigurationCheck(CheckError::MissingDnsAddress {
+                    src: "example.com.".into(),
+                    record_type: "AAAA".into(),
+                }),
+                ErrorCode::AddressRecordMissing,
+                Some("example.com."),
+            ),
+            (
+                ProcessError::FailedCaaCheck(CaaError::Unauthorized {
+                    src: "example.com".into(),
+                    identities: "letsencrypt.org".into(),
+                }),
+                ErrorCode::CaaForbidsCa,
+                Some("example.com"),
+            ),
+            (
+                ProcessError::AcmeOrderInvalid,
+                ErrorCode::AcmeOrderInvalid,
+                None,
+            ),
+            (
+                ProcessError::InvalidCertificate(InspectError::NameNotCovered(
+                    "example.com".into(),
+                )),
+                ErrorCode::CertificateRejected,
+                None,
+            ),
+            (
+                ProcessError::PhaseTimeout {
+                    phase: "propagation-wait",
+                    timeout: Duration::from_secs(300),
+                },
+                ErrorCode::PhaseTimeout,
+                None,
+            ),
+            (
+                ProcessError::UnexpectedError(anyhow!("error")),
+                ErrorCode::Internal,
+                None,
+            ),
+        ] {
+            let f = Failure::classify(&err);
+            assert_eq!(f.code, code);
+            assert_eq!(f.record.as_deref(), record);
+            assert_eq!(f.message, err.to_string());
+
+            assert_eq!(Failure::parse(&f.to_string()), Some(f));
+        }
+
+        // Failures without a code
+        assert_eq!(Failure::parse("acme order became invalid"), None);
+        assert_eq!(Failure::parse("paused by operator"), None);
+    }
+}
@@ -0,0 +1,540 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use candid::{Decode, Encode, Principal};
+use certificate_orchestrator_interface as ifc;
+use ic_agent::Agent;
+use mockall::automock;
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+use x509_parser::pem::parse_x509_pem;
+
+use crate::{
+    certificate::GetCert,
+    registration::Id,
+    work::{Action, Process, ProcessError, Task},
+};
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TaskRecord {
+    pub action: Action,
+    pub started_at: u64, // seconds since the unix epoch
+    pub duration_ms: u64,
+    pub outcome: &'static str, // e.g. `completed`, `retried` or `parked`
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Recent tasks of each registration, for operators to inspect. Like retry
+// attempts, records are kept in memory, so they are per issuer and reset on restart.
+pub struct TaskHistory {
+    max_records: usize, // per registration
+    records: Mutex<HashMap<Id, VecDeque<TaskRecord>>>,
+}
+
+impl TaskHistory {
+    pub fn new(max_records: usize) -> Self {
+        Self {
+            max_records,
+            records: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, id: &Id, record: TaskRecord) {
+        if self.max_records == 0 {
+            return;
+        }
+
+        let mut records = self.records.lock().unwrap();
+        let rs = records.entry(id.to_owned()).or_default();
+
+        if rs.len() >= self.max_records {
+            rs.pop_front();
+        }
+        rs.push_back(record);
+    }
+
+    // Records of a registration, oldest first
+    pub fn get(&self, id: &Id) -> Vec<TaskRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|rs| rs.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IssuedCertificate {
+    pub serial: String, // hex-encoded
+
+    // Nanoseconds since the unix epoch, like all timestamps of the issuance history
+    #[serde(rename = "notBefore")]
+    pub not_before: u64,
+
+    #[serde(rename = "notAfter")]
+    pub not_after: u64,
+
+    #[serde(rename = "issuedAt")]
+    pub issued_at: u64,
+}
+
+impl IssuedCertificate {
+    // Reads the leaf certificate of a PEM-encoded certificate chain
+    pub fn parse(certificate_chain_pem: &[u8], issued_at: u64) -> Result<Self, Error> {
+        let (_, pem) = parse_x509_pem(certificate_chain_pem).context("failed to parse pem")?;
+        let cert = pem.parse_x509().context("failed to parse x509")?;
+
+        let ts = |t: i64| -> Result<u64, Error> {
+            u64::try_from(t)
+                .ok()
+                .and_then(|t| t.checked_mul(1_000_000_000))
+                .ok_or_else(|| anyhow!("invalid timestamp {t}"))
+        };
+
+        Ok(Self {
+            serial: cert
+                .raw_serial()
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            not_before: ts(cert.validity().not_before.timestamp())?,
+            not_after: ts(cert.validity().not_after.timestamp())?,
+            issued_at,
+        })
+    }
+}
+
+impl From<IssuedCertificate> for ifc::IssuedCertificate {
+    fn from(c: IssuedCertificate) -> Self {
+        Self {
+            serial: c.serial.into(),
+            not_before: c.not_before,
+            not_after: c.not_after,
+            issued_at: c.issued_at,
+        }
+    }
+}
+
+impl From<ifc::IssuedCertificate> for IssuedCertificate {
+    fn from(c: ifc::IssuedCertificate) -> Self {
+        Self {
+            serial: c.serial.into(),
+            not_before: c.not_before,
+            not_after: c.not_after,
+            issued_at: c.issued_at,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct IssuanceFailure {
+    pub timestamp: u64,
+    pub error: String,
+}
+
+impl From<IssuanceFailure> for ifc::IssuanceFailure {
+    fn from(f: IssuanceFailure) -> Self {
+        Self {
+            timestamp: f.timestamp,
+            error: f.error.into(),
+        }
+    }
+}
+
+impl From<ifc::IssuanceFailure> for IssuanceFailure {
+    fn from(f: ifc::IssuanceFailure) -> Self {
+        Self {
+            timestamp: f.timestamp,
+            error: f.error.into(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum IssuanceEvent {
+    Attempt(u64),
+    Certificate(IssuedCertificate),
+    Failure(IssuanceFailure),
+    Renewal(u64),
+}
+
+impl From<IssuanceEvent> for ifc::IssuanceEvent {
+    fn from(e: IssuanceEvent) -> Self {
+        match e {
+            IssuanceEvent::Attempt(t) => ifc::IssuanceEvent::Attempt(t),
+            IssuanceEvent::Certificate(c) => ifc::IssuanceEvent::Certificate(c.into()),
+            IssuanceEvent::Failure(f) => ifc::IssuanceEvent::Failure(f.into()),
+            IssuanceEvent::Renewal(t) => ifc::IssuanceEvent::Renewal(t),
+        }
+    }
+}
+
+// Issuance history of a registration, persisted with the registration unlike task records.
+// Only the most recent entries of each kind are kept, oldest first.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct IssuanceHistory {
+    pub attempts: Vec<u64>,
+    pub certificates: Vec<IssuedCertificate>,
+    pub failures: Vec<IssuanceFailure>,
+    pub renewals: Vec<u64>,
+}
+
+impl From<IssuanceHistory> for ifc::IssuanceHistory {
+    fn from(h: IssuanceHistory) -> Self {
+        Self {
+            attempts: h.attempts,
+            certificates: h.certificates.into_iter().map(Into::into).collect(),
+            failures: h.failures.into_iter().map(Into::into).collect(),
+            renewals: h.renewals,
+        }
+    }
+}
+
+impl From<ifc::IssuanceHistory> for IssuanceHistory {
+    fn from(h: ifc::IssuanceHistory) -> Self {
+        Self {
+            attempts: h.attempts,
+            certificates: h.certificates.into_iter().map(Into::into).collect(),
+            failures: h.failures.into_iter().map(Into::into).collect(),
+            renewals: h.renewals,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("Not found")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[automock]
+#[async_trait]
+pub trait RecordEvent: Send + Sync {
+    async fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError>;
+}
+
+#[async_trait]
+impl<T: RecordEvent + ?Sized> RecordEvent for Arc<T> {
+    async fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        (**self).record(id, event).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait GetHistory: Send + Sync {
+    async fn get_history(&self, id: &Id) -> Result<IssuanceHistory, HistoryError>;
+}
+
+#[async_trait]
+impl<T: GetHistory + ?Sized> GetHistory for Arc<T> {
+    async fn get_history(&self, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+        (**self).get_history(id).await
+    }
+}
+
+pub struct CanisterHistory(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl RecordEvent for CanisterHistory {
+    async fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        use ifc::{RecordIssuanceEventError as Error, RecordIssuanceEventResponse as Response};
+
+        let event: ifc::IssuanceEvent = event.into();
+        let args = Encode!(id, &event).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .update(&self.1, "recordIssuanceEvent")
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(()) => Ok(()),
+            Response::Err(err) => Err(match err {
+                Error::NotFound => HistoryError::NotFound,
+                Error::Unauthorized => HistoryError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => HistoryError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl GetHistory for CanisterHistory {
+    async fn get_history(&self, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+        use ifc::{GetIssuanceHistoryError as Error, GetIssuanceHistoryResponse as Response};
+
+        let args = Encode!(id).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .query(&self.1, "getIssuanceHistory")
+            .with_arg(args)
+            .call()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(history) => Ok(history.into()),
+            Response::Err(err) => Err(match err {
+                Error::NotFound => HistoryError::NotFound,
+                Error::Unauthorized => HistoryError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => HistoryError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos() as u64)
+}
+
+// Errors other than those of a registration progressing through its phases
+fn is_failure(err: &ProcessError) -> bool {
+    matches!(
+        err,
+        ProcessError::FailedUserConfigurationCheck(_)
+            | ProcessError::AcmeOrderInvalid
+            | ProcessError::FailedCaaCheck(_)
+            | ProcessError::InvalidCertificate(_)
+            | ProcessError::PhaseTimeout { .. }
+            | ProcessError::UnexpectedError(_)
+    )
+}
+
+// Records the outcome of processed tasks in the issuance history of their registration.
+// Recording is best-effort, so failures are logged rather than returned.
+pub struct WithHistory<T: Process> {
+    processor: T,
+    recorder: Arc<dyn RecordEvent>,
+    certificate_getter: Arc<dyn GetCert>,
+}
+
+impl<T: Process> WithHistory<T> {
+    pub fn new(
+        processor: T,
+        recorder: Arc<dyn RecordEvent>,
+        certificate_getter: Arc<dyn GetCert>,
+    ) -> Self {
+        Self {
+            processor,
+            recorder,
+            certificate_getter,
+        }
+    }
+
+    async fn issued(&self, id: &Id, issued_at: u64) -> Result<IssuedCertificate, Error> {
+        let pair = self.certificate_getter.get_cert(id).await?;
+        IssuedCertificate::parse(&pair.1, issued_at)
+    }
+}
+
+#[async_trait]
+impl<T: Process> Process for WithHistory<T> {
+    async fn process(&self, id: &Id, task: &Task) -> Result<(), ProcessError> {
+        let t = now();
+        let out = self.processor.process(id, task).await;
+
+        let mut events = vec![];
+
+        match (&task.action, &out) {
+            // No order was placed
+            (_, Err(ProcessError::AwaitingRateLimitBudget(_))) => {}
+
+            (Action::Order, _) => events.push(IssuanceEvent::Attempt(t)),
+
+            (Action::Renewal, Err(ProcessError::AwaitingAcmeOrderCreation)) => {
+                events.push(IssuanceEvent::Renewal(t))
+            }
+
+            (Action::Certificate, Ok(())) => match self.issued(id, now()).await {
+                Ok(cert) => events.push(IssuanceEvent::Certificate(cert)),
+                Err(err) => warn!(%id, error = ?err, "failed to read issued certificate"),
+            },
+
+            _ => {}
+        }
+
+        if let Err(err) = &out {
+            if is_failure(err) {
+                events.push(IssuanceEvent::Failure(IssuanceFailure {
+                    timestamp: t,
+                    error: err.to_string(),
+                }));
+            }
+        }
+
+        for event in events {
+            if let Err(err) = self.recorder.record(id, event).await {
+                warn!(%id, error = ?err, "failed to record issuance history");
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use mockall::predicate;
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    use crate::{
+        certificate::{MockGetCert, Pair},
+        work::Priority,
+    };
+
+    struct TestProcessor(fn() -> Result<(), ProcessError>);
+
+    #[async_trait]
+    impl Process for TestProcessor {
+        async fn process(&self, _: &Id, _: &Task) -> Result<(), ProcessError> {
+            (self.0)()
+        }
+    }
+
+    fn task(action: Action) -> Task {
+        Task {
+            name: "example.com".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
+            action,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
+        }
+    }
+
+    fn record(outcome: &'static str) -> TaskRecord {
+        TaskRecord {
+            action: Action::Order,
+            started_at: 0,
+            duration_ms: 0,
+            outcome,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn keeps_most_recent_records() {
+        let h = TaskHistory::new(2);
+
+        h.record(&"id".into(), record("retried"));
+        h.record(&"id".into(), record("retried"));
+        h.record(&"id".into(), record("completed"));
+
+        let rs = h.get(&"id".into());
+        assert_eq!(rs.len(), 2);
+        assert_eq!(rs[1].outcome, "completed");
+
+        assert!(h.get(&"other".into()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn records_issued_certificate() -> Result<(), Error> {
+        let mut params = CertificateParams::new(vec!["example.com".into()]);
+        params.not_before = date_time_ymd(2020, 1, 1);
+        params.not_after = date_time_ymd(2020, 4, 1);
+
+        let chain = Certificate::from_params(params)?.serialize_pem()?;
+
+        let mut getter = MockGetCert::new();
+        getter
+            .expect_get_cert()
+            .times(1)
+            .returning(move |_| Ok(Pair(vec![], chain.clone().into_bytes())));
+
+        let mut recorder = MockRecordEvent::new();
+        recorder
+            .expect_record()
+            .times(1)
+            .withf(|id, event| {
+                *id == "id"
+                    && matches!(event, IssuanceEvent::Certificate(c) if !c.serial.is_empty()
+                        && c.serial.chars().all(|c| c.is_ascii_hexdigit())
+                        && c.not_before == 1_577_836_800_000_000_000
+                        && c.not_after == 1_585_699_200_000_000_000)
+            })
+            .returning(|_, _| Ok(()));
+
+        let p = WithHistory::new(
+            TestProcessor(|| Ok(())),
+            Arc::new(recorder),
+            Arc::new(getter),
+        );
+
+        p.process(&"id".into(), &task(Action::Certificate)).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn records_attempts_and_failures() {
+        let mut recorder = MockRecordEvent::new();
+        recorder
+            .expect_record()
+            .times(1)
+            .with(
+                predicate::always(),
+                predicate::function(|e: &IssuanceEvent| matches!(e, IssuanceEvent::Attempt(_))),
+            )
+            .returning(|_, _| Ok(()));
+        recorder
+            .expect_record()
+            .times(1)
+            .with(
+                predicate::always(),
+                predicate::function(
+                    |e: &IssuanceEvent| matches!(e, IssuanceEvent::Failure(f) if f.error == "boom"),
+                ),
+            )
+            .returning(|_, _| Ok(()));
+
+        let recorder = Arc::new(recorder);
+
+        let mut getter = MockGetCert::new();
+        getter.expect_get_cert().never();
+        let getter = Arc::new(getter);
+
+        // A failed order is both an attempt and a failure
+        let p = WithHistory::new(
+            TestProcessor(|| Err(anyhow!("boom").into())),
+            recorder.clone(),
+            getter.clone(),
+        );
+        assert!(p.process(&"id".into(), &task(Action::Order)).await.is_err());
+
+        // Orders delayed by the budget are not attempts
+        let p = WithHistory::new(
+            TestProcessor(|| {
+                Err(ProcessError::AwaitingRateLimitBudget(
+                    std::time::Duration::from_secs(1),
+                ))
+            }),
+            recorder,
+            getter,
+        );
+        assert!(p.process(&"id".into(), &task(Action::Order)).await.is_err());
+    }
+}
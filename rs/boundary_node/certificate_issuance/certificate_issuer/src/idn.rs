@@ -0,0 +1,223 @@
+use std::collections::BTreeSet;
+
+use mockall::automock;
+
+#[derive(Debug, thiserror::Error)]
+pub enum NormalizeError {
+    #[error("invalid domain name: {0}")]
+    InvalidName(String),
+
+    #[error("internationalized domain names are not allowed")]
+    NotAllowed,
+
+    #[error("label '{0}' mixes scripts in a way that allows for confusion with other names")]
+    MixedScript(String),
+
+    #[error("name '{0}' is given more than once")]
+    Duplicate(String),
+}
+
+// Policy for internationalized domain names
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum IdnPolicy {
+    // Any valid name is accepted
+    Allow,
+
+    // Labels may only mix scripts commonly used together (UTS #39, section 5.2)
+    HighlyRestrictive,
+
+    // Only ASCII names are accepted
+    AsciiOnly,
+}
+
+#[automock]
+pub trait Normalize: Send + Sync {
+    // Normalizes a name to its lowercase A-label (punycode) form, without a trailing dot
+    fn normalize(&self, name: &str) -> Result<String, NormalizeError>;
+}
+
+// Normalizes the names of a registration, which must still be distinct afterwards,
+// e.g. `Example.com` and `example.com.` cannot be covered by the same registration
+pub fn normalize_names<'a>(
+    n: &dyn Normalize,
+    names: impl IntoIterator<Item = &'a String>,
+) -> Result<Vec<String>, NormalizeError> {
+    let mut out: Vec<String> = vec![];
+
+    for name in names {
+        let name = n.normalize(name)?;
+
+        if out.contains(&name) {
+            return Err(NormalizeError::Duplicate(name));
+        }
+
+        out.push(name);
+    }
+
+    Ok(out)
+}
+
+pub struct Normalizer(pub IdnPolicy);
+
+impl Normalize for Normalizer {
+    fn normalize(&self, name: &str) -> Result<String, NormalizeError> {
+        // A trailing dot only makes the name fully qualified, which all names are taken to be
+        let name = name.strip_suffix('.').unwrap_or(name);
+
+        let ascii_name = idna::domain_to_ascii(name)
+            .map_err(|err| NormalizeError::InvalidName(format!("{err:?}")))?;
+
+        // Labels are checked in their Unicode form, regardless of how they were submitted
+        let (unicode_name, out) = idna::domain_to_unicode(&ascii_name);
+        out.map_err(|err| NormalizeError::InvalidName(format!("{err:?}")))?;
+
+        match self.0 {
+            IdnPolicy::Allow => {}
+            IdnPolicy::AsciiOnly => {
+                if !unicode_name.is_ascii() {
+                    return Err(NormalizeError::NotAllowed);
+                }
+            }
+            IdnPolicy::HighlyRestrictive => {
+                if let Some(label) = unicode_name.split('.').find(|l| !is_highly_restrictive(l)) {
+                    return Err(NormalizeError::MixedScript(label.to_string()));
+                }
+            }
+        }
+
+        // Empty labels, e.g. of `example.com..`, are left to idna in its non-strict mode
+        if ascii_name.is_empty() || ascii_name.split('.').any(str::is_empty) {
+            return Err(NormalizeError::InvalidName(format!(
+                "'{name}' contains an empty label"
+            )));
+        }
+
+        Ok(ascii_name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Script {
+    Common, // digits and hyphens
+    Latin,
+    Greek,
+    Cyrillic,
+    Armenian,
+    Hebrew,
+    Arabic,
+    Devanagari,
+    Thai,
+    Hangul,
+    Hiragana,
+    Katakana,
+    Han,
+    Other,
+}
+
+// Approximates the Unicode script of a character by its block,
+// covering the scripts most prone to confusion
+fn script(c: char) -> Script {
+    match c as u32 {
+        0x30..=0x39 | 0x2D => Script::Common,
+        0x61..=0x7A | 0xC0..=0x24F | 0x1E00..=0x1EFF => Script::Latin,
+        0x370..=0x3FF | 0x1F00..=0x1FFF => Script::Greek,
+        0x400..=0x52F => Script::Cyrillic,
+        0x530..=0x58F => Script::Armenian,
+        0x590..=0x5FF => Script::Hebrew,
+        0x600..=0x6FF | 0x750..=0x77F => Script::Arabic,
+        0x900..=0x97F => Script::Devanagari,
+        0xE00..=0xE7F => Script::Thai,
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => Script::Hangul,
+        0x3040..=0x309F => Script::Hiragana,
+        0x30A0..=0x30FF => Script::Katakana,
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF => Script::Han,
+        _ => Script::Other,
+    }
+}
+
+// A label is highly restrictive if it uses a single script, or one of the
+// combinations of Latin with CJK scripts used for Japanese and Korean names
+fn is_highly_restrictive(label: &str) -> bool {
+    use Script::*;
+
+    let scripts: BTreeSet<Script> = label.chars().map(script).filter(|s| *s != Common).collect();
+
+    if scripts.len() <= 1 {
+        return true;
+    }
+
+    let japanese = BTreeSet::from([Latin, Han, Hiragana, Katakana]);
+    let korean = BTreeSet::from([Latin, Han, Hangul]);
+
+    !scripts.contains(&Other) && (scripts.is_subset(&japanese) || scripts.is_subset(&korean))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_highly_restrictive() {
+        let n = Normalizer(IdnPolicy::HighlyRestrictive);
+
+        assert_eq!(n.normalize("rüdi.com").unwrap(), "xn--rdi-hoa.com");
+        assert_eq!(n.normalize("xn--rdi-hoa.com").unwrap(), "xn--rdi-hoa.com");
+        assert_eq!(n.normalize("Example.com").unwrap(), "example.com");
+        assert!(n.normalize("ソニーstore.jp").is_ok());
+
+        // Cyrillic 'а' in an otherwise Latin label
+        assert!(matches!(
+            n.normalize("p\u{0430}ypal.com"),
+            Err(NormalizeError::MixedScript(_))
+        ));
+    }
+
+    #[test]
+    fn normalize_canonical_form() {
+        let n = Normalizer(IdnPolicy::Allow);
+
+        for name in ["example.com", "Example.COM", "example.com.", "EXAMPLE.com."] {
+            assert_eq!(n.normalize(name).unwrap(), "example.com", "{name}");
+        }
+        assert_eq!(n.normalize("RÜDI.com.").unwrap(), "xn--rdi-hoa.com");
+
+        for name in ["example.com..", ".example.com", "."] {
+            assert!(
+                matches!(n.normalize(name), Err(NormalizeError::InvalidName(_))),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn normalize_names_unique() {
+        let n = Normalizer(IdnPolicy::Allow);
+
+        let names = vec!["example.com".to_string(), "www.Example.com.".to_string()];
+        assert_eq!(
+            normalize_names(&n, &names).unwrap(),
+            vec!["example.com", "www.example.com"]
+        );
+
+        let names = vec!["rüdi.com".to_string(), "XN--RDI-HOA.com.".to_string()];
+        assert!(matches!(
+            normalize_names(&n, &names),
+            Err(NormalizeError::Duplicate(name)) if name == "xn--rdi-hoa.com"
+        ));
+    }
+
+    #[test]
+    fn normalize_ascii_only() {
+        let n = Normalizer(IdnPolicy::AsciiOnly);
+
+        assert_eq!(n.normalize("example.com").unwrap(), "example.com");
+        assert!(matches!(
+            n.normalize("rüdi.com"),
+            Err(NormalizeError::NotAllowed)
+        ));
+        assert!(matches!(
+            n.normalize("xn--rdi-hoa.com"),
+            Err(NormalizeError::NotAllowed)
+        ));
+    }
+}
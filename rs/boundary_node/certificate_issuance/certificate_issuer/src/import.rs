@@ -0,0 +1,207 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rcgen::{KeyPair, PKCS_ECDSA_P256_SHA256, PKCS_RSA_SHA256};
+use x509_parser::{extensions::GeneralName, pem::parse_x509_pem, public_key::PublicKey};
+
+use crate::acme::KeyType;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImportError {
+    #[error("invalid certificate: {0}")]
+    InvalidCertificate(String),
+
+    #[error("invalid private key: {0}")]
+    InvalidKey(String),
+
+    #[error("certificate is not valid at this time")]
+    NotValid,
+
+    #[error("certificate does not cover {0}")]
+    NameNotCovered(String),
+
+    #[error("private key does not match the certificate")]
+    KeyMismatch,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Imported {
+    pub not_after: SystemTime,
+
+    // Key type used for renewals, the default one is used for keys that cannot be issued
+    pub key_type: Option<KeyType>,
+}
+
+// Whether a name from a certificate covers the given name, either exactly or as a wildcard
+pub fn covers(san: &str, name: &str) -> bool {
+    if san.eq_ignore_ascii_case(name) {
+        return true;
+    }
+
+    match (san.strip_prefix("*."), name.split_once('.')) {
+        (Some(san), Some((_, parent))) => san.eq_ignore_ascii_case(parent),
+        _ => false,
+    }
+}
+
+// Validates an externally issued certificate before it is imported. The leaf certificate
+// must be currently valid, cover all of the given names and match the (PKCS#8) private key.
+pub fn validate(
+    names: &[String],
+    key_pem: &[u8],
+    certificate_chain_pem: &[u8],
+    now: SystemTime,
+) -> Result<Imported, ImportError> {
+    let (_, pem) = parse_x509_pem(certificate_chain_pem)
+        .map_err(|err| ImportError::InvalidCertificate(err.to_string()))?;
+    let cert = pem
+        .parse_x509()
+        .map_err(|err| ImportError::InvalidCertificate(err.to_string()))?;
+
+    // Validity
+    let (not_before, not_after) = (
+        cert.validity().not_before.timestamp(),
+        cert.validity().not_after.timestamp(),
+    );
+
+    let now: i64 = now
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| ImportError::NotValid)?
+        .as_secs()
+        .try_into()
+        .map_err(|_| ImportError::NotValid)?;
+
+    if now < not_before || now >= not_after {
+        return Err(ImportError::NotValid);
+    }
+
+    // Names
+    let sans: Vec<&str> = match cert.subject_alternative_name() {
+        Ok(Some(ext)) => ext
+            .value
+            .general_names
+            .iter()
+            .filter_map(|n| match n {
+                GeneralName::DNSName(n) => Some(*n),
+                _ => None,
+            })
+            .collect(),
+        Ok(None) => vec![],
+        Err(err) => return Err(ImportError::InvalidCertificate(err.to_string())),
+    };
+
+    if let Some(name) = names
+        .iter()
+        .find(|name| !sans.iter().any(|san| covers(san, name)))
+    {
+        return Err(ImportError::NameNotCovered(name.to_owned()));
+    }
+
+    // Key
+    let key_pem = std::str::from_utf8(key_pem)
+        .map_err(|_| ImportError::InvalidKey("not a pem-encoded key".into()))?;
+    let key = KeyPair::from_pem(key_pem).map_err(|err| ImportError::InvalidKey(err.to_string()))?;
+
+    if key.public_key_raw() != cert.public_key().subject_public_key.data.as_ref() {
+        return Err(ImportError::KeyMismatch);
+    }
+
+    let key_type = if key.is_compatible(&PKCS_ECDSA_P256_SHA256) {
+        Some(KeyType::EcdsaP256)
+    } else if key.is_compatible(&PKCS_RSA_SHA256) {
+        match cert.public_key().parsed() {
+            Ok(PublicKey::RSA(k)) if k.key_size() == 2048 => Some(KeyType::Rsa2048),
+            Ok(PublicKey::RSA(k)) if k.key_size() == 4096 => Some(KeyType::Rsa4096),
+            _ => None,
+        }
+    } else {
+        None
+    };
+
+    Ok(Imported {
+        not_after: UNIX_EPOCH + Duration::from_secs(not_after as u64),
+        key_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Error;
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    fn generate(names: &[&str]) -> Result<(String, String), Error> {
+        let mut params = CertificateParams::new(names.iter().map(|n| n.to_string()).collect());
+        params.not_before = date_time_ymd(2020, 1, 1);
+        params.not_after = date_time_ymd(2030, 1, 1);
+
+        let cert = Certificate::from_params(params)?;
+
+        Ok((cert.serialize_private_key_pem(), cert.serialize_pem()?))
+    }
+
+    fn names(ns: &[&str]) -> Vec<String> {
+        ns.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn validate_ok() -> Result<(), Error> {
+        let (key, cert) = generate(&["example.com", "*.example.com"])?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000); // 2023-11-14
+
+        assert_eq!(
+            validate(
+                &names(&["example.com", "www.example.com"]),
+                key.as_bytes(),
+                cert.as_bytes(),
+                now,
+            )?,
+            Imported {
+                not_after: UNIX_EPOCH + Duration::from_secs(1_893_456_000), // 2030-01-01
+                key_type: Some(KeyType::EcdsaP256),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn validate_rejected() -> Result<(), Error> {
+        let (key, cert) = generate(&["example.com", "*.example.com"])?;
+        let (other_key, _) = generate(&["example.com"])?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000); // 2023-11-14
+
+        // Wildcards only cover a single label
+        assert!(matches!(
+            validate(&names(&["a.b.example.com"]), key.as_bytes(), cert.as_bytes(), now),
+            Err(ImportError::NameNotCovered(name)) if name == "a.b.example.com"
+        ));
+
+        assert!(matches!(
+            validate(
+                &names(&["example.com"]),
+                key.as_bytes(),
+                cert.as_bytes(),
+                UNIX_EPOCH + Duration::from_secs(1_893_456_000), // 2030-01-01
+            ),
+            Err(ImportError::NotValid)
+        ));
+
+        assert!(matches!(
+            validate(
+                &names(&["example.com"]),
+                other_key.as_bytes(),
+                cert.as_bytes(),
+                now
+            ),
+            Err(ImportError::KeyMismatch)
+        ));
+
+        assert!(matches!(
+            validate(&names(&["example.com"]), key.as_bytes(), b"cert", now),
+            Err(ImportError::InvalidCertificate(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,401 @@
+use std::{
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Error};
+use mockall::automock;
+use ring::signature::{self, UnparsedPublicKey, VerificationAlgorithm};
+use x509_parser::{
+    certificate::X509Certificate,
+    extensions::{GeneralName, ParsedExtension},
+    parse_x509_certificate,
+    pem::Pem,
+};
+
+use crate::import::covers;
+
+// Tolerated clock difference with the ACME provider
+const CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+// Certificates expiring sooner than this are of no use, as they would be renewed right away
+const MIN_REMAINING_VALIDITY: Duration = Duration::from_secs(7 * 24 * 3600);
+
+// Longest lifetime of a publicly trusted certificate
+const MAX_LIFETIME: Duration = Duration::from_secs(398 * 24 * 3600);
+
+#[derive(Debug, thiserror::Error)]
+pub enum InspectError {
+    #[error("malformed certificate chain: {0}")]
+    Malformed(String),
+
+    #[error("certificate does not cover {0}")]
+    NameNotCovered(String),
+
+    #[error("unacceptable validity period: {0}")]
+    InvalidValidity(String),
+
+    #[error("untrusted certificate chain: {0}")]
+    UntrustedChain(String),
+
+    #[error("certificate has {found} signed certificate timestamps, {required} required")]
+    MissingScts { found: usize, required: usize },
+}
+
+// Verifies a newly issued certificate chain before it is distributed to boundary nodes
+#[automock]
+pub trait Inspect: Sync + Send {
+    fn inspect(&self, names: &[String], certificate_chain_pem: &[u8]) -> Result<(), InspectError>;
+}
+
+pub struct Inspector {
+    // DER-encoded roots the chain must build to, without them any root is accepted
+    roots: Vec<Vec<u8>>,
+
+    // Signed certificate timestamps the leaf must embed, proving it was submitted to CT logs
+    min_scts: usize,
+}
+
+impl Inspector {
+    pub fn new(roots: Vec<Vec<u8>>, min_scts: usize) -> Self {
+        Self { roots, min_scts }
+    }
+
+    fn inspect_at(
+        &self,
+        names: &[String],
+        certificate_chain_pem: &[u8],
+        now: SystemTime,
+    ) -> Result<(), InspectError> {
+        let ders = Pem::iter_from_buffer(certificate_chain_pem)
+            .map(|pem| pem.map(|pem| pem.contents))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| InspectError::Malformed(err.to_string()))?;
+
+        let chain = ders
+            .iter()
+            .map(|der| parse_x509_certificate(der).map(|(_, cert)| cert))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| InspectError::Malformed(err.to_string()))?;
+
+        let leaf = chain
+            .first()
+            .ok_or_else(|| InspectError::Malformed("no certificates found".into()))?;
+
+        // Names
+        let sans: Vec<&str> = match leaf.subject_alternative_name() {
+            Ok(Some(ext)) => ext
+                .value
+                .general_names
+                .iter()
+                .filter_map(|n| match n {
+                    GeneralName::DNSName(n) => Some(*n),
+                    _ => None,
+                })
+                .collect(),
+            Ok(None) => vec![],
+            Err(err) => return Err(InspectError::Malformed(err.to_string())),
+        };
+
+        if let Some(name) = names
+            .iter()
+            .find(|name| !sans.iter().any(|san| covers(san, name)))
+        {
+            return Err(InspectError::NameNotCovered(name.to_owned()));
+        }
+
+        // Validity
+        let now = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_secs() as i64);
+
+        for cert in &chain {
+            let (not_before, not_after) = (
+                cert.validity().not_before.timestamp(),
+                cert.validity().not_after.timestamp(),
+            );
+
+            if now + (CLOCK_SKEW.as_secs() as i64) < not_before || now >= not_after {
+                return Err(InspectError::InvalidValidity(format!(
+                    "{} is not valid at this time",
+                    cert.subject()
+                )));
+            }
+        }
+
+        let (not_before, not_after) = (
+            leaf.validity().not_before.timestamp(),
+            leaf.validity().not_after.timestamp(),
+        );
+
+        if not_after - now < MIN_REMAINING_VALIDITY.as_secs() as i64 {
+            return Err(InspectError::InvalidValidity(format!(
+                "expires in less than {} days",
+                MIN_REMAINING_VALIDITY.as_secs() / (24 * 3600)
+            )));
+        }
+
+        if not_after - not_before > MAX_LIFETIME.as_secs() as i64 {
+            return Err(InspectError::InvalidValidity(format!(
+                "lifetime exceeds {} days",
+                MAX_LIFETIME.as_secs() / (24 * 3600)
+            )));
+        }
+
+        // Chain, every certificate must be issued by the next one
+        for pair in chain.windows(2) {
+            let (cert, issuer) = (&pair[0], &pair[1]);
+
+            if cert.issuer().as_raw() != issuer.subject().as_raw() {
+                return Err(InspectError::UntrustedChain(format!(
+                    "{} is not issued by {}",
+                    cert.subject(),
+                    issuer.subject()
+                )));
+            }
+
+            let is_ca = matches!(issuer.basic_constraints(), Ok(Some(ext)) if ext.value.ca);
+            if !is_ca {
+                return Err(InspectError::UntrustedChain(format!(
+                    "{} is not a certificate authority",
+                    issuer.subject()
+                )));
+            }
+
+            verify_signature(cert, issuer)?;
+        }
+
+        // Roots
+        if !self.roots.is_empty() {
+            let last = chain.last().unwrap();
+
+            // The chain may or may not include the root itself
+            let is_trusted = self.roots.contains(ders.last().unwrap())
+                || self
+                    .roots
+                    .iter()
+                    .any(|der| match parse_x509_certificate(der) {
+                        Ok((_, root)) => {
+                            last.issuer().as_raw() == root.subject().as_raw()
+                                && verify_signature(last, &root).is_ok()
+                        }
+                        Err(_) => false,
+                    });
+
+            if !is_trusted {
+                return Err(InspectError::UntrustedChain(format!(
+                    "{} is not issued by a trusted root",
+                    last.subject()
+                )));
+            }
+        }
+
+        // Certificate transparency
+        if self.min_scts > 0 {
+            let found = leaf
+                .extensions()
+                .iter()
+                .find_map(|ext| match ext.parsed_extension() {
+                    ParsedExtension::SCT(scts) => Some(scts.len()),
+                    _ => None,
+                })
+                .unwrap_or(0);
+
+            if found < self.min_scts {
+                return Err(InspectError::MissingScts {
+                    found,
+                    required: self.min_scts,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for Inspector {
+    fn inspect(&self, names: &[String], certificate_chain_pem: &[u8]) -> Result<(), InspectError> {
+        self.inspect_at(names, certificate_chain_pem, SystemTime::now())
+    }
+}
+
+// Verifies the signature of a certificate with the key of its issuer,
+// for the algorithms used by publicly trusted certificate authorities
+fn verify_signature(cert: &X509Certificate, issuer: &X509Certificate) -> Result<(), InspectError> {
+    let spki = issuer.public_key();
+
+    let curve = spki
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|p| p.as_oid().ok())
+        .map(|oid| oid.to_id_string());
+
+    let alg: &'static dyn VerificationAlgorithm = match (
+        cert.signature_algorithm.algorithm.to_id_string().as_str(),
+        curve.as_deref(),
+    ) {
+        // sha{256,384,512}WithRSAEncryption
+        ("1.2.840.113549.1.1.11", _) => &signature::RSA_PKCS1_2048_8192_SHA256,
+        ("1.2.840.113549.1.1.12", _) => &signature::RSA_PKCS1_2048_8192_SHA384,
+        ("1.2.840.113549.1.1.13", _) => &signature::RSA_PKCS1_2048_8192_SHA512,
+
+        // ecdsa-with-SHA{256,384}, on the P-256 or P-384 curve of the issuer
+        ("1.2.840.10045.4.3.2", Some("1.2.840.10045.3.1.7")) => &signature::ECDSA_P256_SHA256_ASN1,
+        ("1.2.840.10045.4.3.2", Some("1.3.132.0.34")) => &signature::ECDSA_P384_SHA256_ASN1,
+        ("1.2.840.10045.4.3.3", Some("1.2.840.10045.3.1.7")) => &signature::ECDSA_P256_SHA384_ASN1,
+        ("1.2.840.10045.4.3.3", Some("1.3.132.0.34")) => &signature::ECDSA_P384_SHA384_ASN1,
+
+        (alg, _) => {
+            return Err(InspectError::UntrustedChain(format!(
+                "unsupported signature algorithm {alg}"
+            )))
+        }
+    };
+
+    UnparsedPublicKey::new(alg, spki.subject_public_key.data.as_ref())
+        .verify(
+            cert.tbs_certificate.as_ref(),
+            cert.signature_value.data.as_ref(),
+        )
+        .map_err(|_| {
+            InspectError::UntrustedChain(format!(
+                "invalid signature of {} by {}",
+                cert.subject(),
+                issuer.subject()
+            ))
+        })
+}
+
+// Reads a bundle of PEM-encoded root certificates
+pub fn load_roots(path: &Path) -> Result<Vec<Vec<u8>>, Error> {
+    let bs = std::fs::read(path).context("failed to read trusted roots")?;
+
+    let roots = Pem::iter_from_buffer(&bs)
+        .map(|pem| pem.map(|pem| pem.contents))
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to parse trusted roots")?;
+
+    for der in &roots {
+        parse_x509_certificate(der).context("failed to parse trusted root")?;
+    }
+
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::{
+        date_time_ymd, BasicConstraints, Certificate, CertificateParams, DistinguishedName, DnType,
+        IsCa,
+    };
+
+    // A root, an intermediate issued by the root and a leaf issued by the intermediate
+    fn generate(names: &[&str], lifetime: (i32, i32)) -> Result<(String, String), Error> {
+        let ca = |cn: &str| {
+            let mut params = CertificateParams::default();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params.distinguished_name = DistinguishedName::new();
+            params.distinguished_name.push(DnType::CommonName, cn);
+            params.not_before = date_time_ymd(2020, 1, 1);
+            params.not_after = date_time_ymd(2035, 1, 1);
+            Certificate::from_params(params)
+        };
+
+        let root = ca("root")?;
+        let intermediate = ca("intermediate")?;
+
+        let mut params = CertificateParams::new(names.iter().map(|n| n.to_string()).collect());
+        params.not_before = date_time_ymd(lifetime.0, 1, 1);
+        params.not_after = date_time_ymd(lifetime.1, 1, 1);
+        let leaf = Certificate::from_params(params)?;
+
+        let chain = [
+            leaf.serialize_pem_with_signer(&intermediate)?,
+            intermediate.serialize_pem_with_signer(&root)?,
+        ]
+        .concat();
+
+        Ok((chain, root.serialize_pem()?))
+    }
+
+    fn names(ns: &[&str]) -> Vec<String> {
+        ns.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn roots(pem: &str) -> Vec<Vec<u8>> {
+        Pem::iter_from_buffer(pem.as_bytes())
+            .map(|pem| pem.unwrap().contents)
+            .collect()
+    }
+
+    #[test]
+    fn inspect_ok() -> Result<(), Error> {
+        let (chain, root) = generate(&["example.com", "*.example.com"], (2023, 2024))?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000); // 2023-11-14
+
+        Inspector::new(roots(&root), 0).inspect_at(
+            &names(&["example.com", "www.example.com"]),
+            chain.as_bytes(),
+            now,
+        )?;
+
+        // Any root is accepted without trusted roots
+        Inspector::new(vec![], 0).inspect_at(&names(&["example.com"]), chain.as_bytes(), now)?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn inspect_rejected() -> Result<(), Error> {
+        let (chain, _) = generate(&["example.com"], (2023, 2024))?;
+        let (_, other_root) = generate(&["example.com"], (2023, 2024))?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000); // 2023-11-14
+
+        let inspector = Inspector::new(roots(&other_root), 0);
+
+        assert!(matches!(
+            inspector.inspect_at(&names(&["www.example.com"]), chain.as_bytes(), now),
+            Err(InspectError::NameNotCovered(name)) if name == "www.example.com"
+        ));
+
+        assert!(matches!(
+            inspector.inspect_at(&names(&["example.com"]), chain.as_bytes(), now),
+            Err(InspectError::UntrustedChain(_))
+        ));
+
+        assert!(matches!(
+            inspector.inspect_at(&names(&["example.com"]), b"not a certificate", now),
+            Err(InspectError::Malformed(_))
+        ));
+
+        // Expiring soon
+        let now = UNIX_EPOCH + Duration::from_secs(1_703_900_000); // 2023-12-30
+        assert!(matches!(
+            Inspector::new(vec![], 0).inspect_at(&names(&["example.com"]), chain.as_bytes(), now),
+            Err(InspectError::InvalidValidity(_))
+        ));
+
+        // Too long-lived
+        let (chain, _) = generate(&["example.com"], (2023, 2030))?;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000); // 2023-11-14
+        assert!(matches!(
+            Inspector::new(vec![], 0).inspect_at(&names(&["example.com"]), chain.as_bytes(), now),
+            Err(InspectError::InvalidValidity(_))
+        ));
+
+        // Not submitted to CT logs
+        let (chain, _) = generate(&["example.com"], (2023, 2024))?;
+        assert!(matches!(
+            Inspector::new(vec![], 2).inspect_at(&names(&["example.com"]), chain.as_bytes(), now),
+            Err(InspectError::MissingScts {
+                found: 0,
+                required: 2
+            })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1004 @@
+use std::{
+    iter::once,
+    mem::discriminant,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use async_trait::async_trait;
+use candid::Principal;
+use certificate_orchestrator_interface::{self as ifc, ExportFilter, IcCertificate};
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{
+    acme::KeyType,
+    certificate::{
+        Export, ExportError, GetCert, GetCertError, Package, Pair, Revoke, RevokeError, Upload,
+        UploadError,
+    },
+    encode::{Decode, Encode},
+    history::{GetHistory, HistoryError, IssuanceEvent, IssuanceHistory, RecordEvent},
+    registration::{
+        Create, CreateError, Get, GetError, GetTimestamps, Id, List, ListError, Registration,
+        Remove, RemoveError, State, Timestamps, Update, UpdateError, UpdateType,
+    },
+    work::{
+        Count, CountError, Dispense, DispenseError, Lease, LeaseError, Peek, PeekError, Priority,
+        Queue, QueueError, Release, Renew, Task,
+    },
+};
+
+// Same as the orchestrator's default time until an unreleased lease expires
+const LEASE_TTL: Duration = Duration::from_secs(10 * 60);
+
+// Stores registrations, certificates and tasks in a local SQLite database instead of
+// the orchestrator canister, for standalone and development deployments. Certificates
+// are stored encrypted, like in the canister, but exports are not certified.
+pub struct LocalStore(Arc<Mutex<Connection>>);
+
+impl LocalStore {
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        Self::init(Connection::open(path).context("failed to open local store")?)
+    }
+
+    #[cfg(test)]
+    fn in_memory() -> Result<Self, Error> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS registrations (
+                id           TEXT PRIMARY KEY,
+                registration TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS names (
+                name TEXT PRIMARY KEY,
+                id   TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS certificates (
+                id         TEXT PRIMARY KEY,
+                key        BLOB NOT NULL,
+                chain      BLOB NOT NULL,
+                updated_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS tasks (
+                id TEXT PRIMARY KEY,
+                t  INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS task_priorities (
+                id       TEXT PRIMARY KEY,
+                priority INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS leases (
+                id         TEXT PRIMARY KEY,
+                lease      TEXT NOT NULL,
+                expires_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS histories (
+                id      TEXT PRIMARY KEY,
+                history TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS timestamps (
+                id                   TEXT PRIMARY KEY,
+                created_at           INTEGER,
+                last_attempt_at      INTEGER,
+                last_state_change_at INTEGER
+            );",
+        )
+        .context("failed to create local store tables")?;
+
+        Ok(Self(Arc::new(Mutex::new(conn))))
+    }
+
+    // Runs queries on a blocking thread, so that slow ones (e.g. the full scans of exports and
+    // listings) do not hold up the async workers that also serve the API
+    async fn run<T, E>(
+        &self,
+        f: impl FnOnce(&mut Connection) -> Result<T, E> + Send + 'static,
+    ) -> Result<T, E>
+    where
+        T: Send + 'static,
+        E: From<Error> + Send + 'static,
+    {
+        let conn = Arc::clone(&self.0);
+
+        tokio::task::spawn_blocking(move || {
+            // A query that panicked poisons the connection for all later calls
+            let mut conn = conn
+                .lock()
+                .map_err(|_| anyhow!("local store connection is poisoned"))?;
+
+            f(&mut conn)
+        })
+        .await
+        .context("local store task failed")?
+    }
+}
+
+// SQLite integers are signed
+fn to_sql_time(t: u64) -> i64 {
+    i64::try_from(t).unwrap_or(i64::MAX)
+}
+
+// Tasks without a stored priority have the default one
+fn to_sql_priority(p: Priority) -> i64 {
+    match p {
+        Priority::Low => 0,
+        Priority::Normal => 1,
+        Priority::High => 2,
+    }
+}
+
+fn from_sql_priority(p: i64) -> Priority {
+    match p {
+        0 => Priority::Low,
+        2 => Priority::High,
+        _ => Priority::Normal,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |t| t.as_nanos() as u64)
+}
+
+fn get_registration(conn: &Connection, id: &Id) -> Result<Option<Registration>, Error> {
+    let reg: Option<String> = conn
+        .query_row(
+            "SELECT registration FROM registrations WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    reg.map(|reg| serde_json::from_str(&reg).context("failed to decode registration"))
+        .transpose()
+}
+
+fn put_registration(conn: &Connection, id: &Id, reg: &Registration) -> Result<(), Error> {
+    conn.execute(
+        "INSERT OR REPLACE INTO registrations (id, registration) VALUES (?1, ?2)",
+        params![id, serde_json::to_string(reg)?],
+    )?;
+
+    Ok(())
+}
+
+// Sets one of the timestamp columns, keeping the others
+fn set_timestamp(conn: &Connection, id: &Id, column: &str, t: u64) -> Result<(), Error> {
+    conn.execute(
+        &format!(
+            "INSERT INTO timestamps (id, {column}) VALUES (?1, ?2)
+            ON CONFLICT(id) DO UPDATE SET {column} = excluded.{column}"
+        ),
+        params![id, to_sql_time(t)],
+    )
+    .context("failed to set timestamp")?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Create for LocalStore {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        let (name, canister, alt_names) =
+            (name.to_string(), canister.to_owned(), alt_names.to_vec());
+
+        self.run(move |conn| {
+            let name = name.as_str();
+            let tx = conn.transaction().context("failed to start transaction")?;
+
+            // Check for duplicate
+            for n in once(name).chain(alt_names.iter().map(String::as_str)) {
+                let id: Option<Id> = tx
+                    .query_row("SELECT id FROM names WHERE name = ?1", params![n], |row| {
+                        row.get(0)
+                    })
+                    .optional()
+                    .context("failed to look up name")?;
+
+                if let Some(id) = id {
+                    return Err(CreateError::Duplicate(id));
+                }
+            }
+
+            let id = format!(
+                "{:032x}{:032x}",
+                rand::random::<u128>(),
+                rand::random::<u128>()
+            );
+
+            put_registration(
+                &tx,
+                &id,
+                &Registration {
+                    name: name.to_string(),
+                    canister,
+                    state: State::PendingOrder,
+                    key_type,
+                    alt_names: alt_names.clone(),
+                },
+            )?;
+
+            for n in once(name).chain(alt_names.iter().map(String::as_str)) {
+                tx.execute(
+                    "INSERT INTO names (name, id) VALUES (?1, ?2)",
+                    params![n, id],
+                )
+                .context("failed to insert name")?;
+            }
+
+            let t = now();
+            set_timestamp(&tx, &id, "created_at", t)?;
+            set_timestamp(&tx, &id, "last_state_change_at", t)?;
+
+            tx.commit().context("failed to commit transaction")?;
+
+            Ok(id)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Get for LocalStore {
+    async fn get(&self, id: &Id) -> Result<Registration, GetError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| get_registration(conn, &id)?.ok_or(GetError::NotFound))
+            .await
+    }
+}
+
+#[async_trait]
+impl Update for LocalStore {
+    async fn update(&self, id: &Id, typ: &UpdateType) -> Result<(), UpdateError> {
+        let (id, typ) = (id.to_owned(), typ.to_owned());
+
+        self.run(move |conn| {
+            let (id, typ) = (&id, &typ);
+            let reg = get_registration(conn, id)?.ok_or(UpdateError::NotFound)?;
+
+            if matches!(typ, UpdateType::State(state) if state != &reg.state) {
+                set_timestamp(conn, id, "last_state_change_at", now())?;
+            }
+
+            let reg = match typ {
+                UpdateType::Canister(canister) => Registration {
+                    canister: canister.to_owned(),
+                    ..reg
+                },
+                UpdateType::State(state) => Registration {
+                    state: state.to_owned(),
+                    ..reg
+                },
+            };
+
+            put_registration(conn, id, &reg)?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Remove for LocalStore {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| {
+            let tx = conn.transaction().context("failed to start transaction")?;
+
+            if tx
+                .execute("DELETE FROM registrations WHERE id = ?1", params![id])
+                .context("failed to remove registration")?
+                == 0
+            {
+                return Err(RemoveError::NotFound);
+            }
+
+            for table in [
+                "names",
+                "certificates",
+                "tasks",
+                "task_priorities",
+                "leases",
+                "histories",
+                "timestamps",
+            ] {
+                tx.execute(&format!("DELETE FROM {table} WHERE id = ?1"), params![id])
+                    .context("failed to remove registration")?;
+            }
+
+            tx.commit().context("failed to commit transaction")?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn get_history(conn: &Connection, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+    if get_registration(conn, id)?.is_none() {
+        return Err(HistoryError::NotFound);
+    }
+
+    let h: Option<String> = conn
+        .query_row(
+            "SELECT history FROM histories WHERE id = ?1",
+            params![id],
+            |row| row.get(0),
+        )
+        .optional()
+        .context("failed to get issuance history")?;
+
+    Ok(
+        h.map(|h| serde_json::from_str(&h).context("failed to decode issuance history"))
+            .transpose()?
+            .unwrap_or_default(),
+    )
+}
+
+#[async_trait]
+impl RecordEvent for LocalStore {
+    async fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| {
+            // Shares the canister's bounds on the number of entries
+            let mut h: ifc::IssuanceHistory = get_history(conn, &id)?.into();
+            h.record(event.into());
+            let h: IssuanceHistory = h.into();
+
+            conn.execute(
+                "INSERT OR REPLACE INTO histories (id, history) VALUES (?1, ?2)",
+                params![
+                    id,
+                    serde_json::to_string(&h).context("failed to encode issuance history")?
+                ],
+            )
+            .context("failed to store issuance history")?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl GetHistory for LocalStore {
+    async fn get_history(&self, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| get_history(conn, &id)).await
+    }
+}
+
+#[async_trait]
+impl GetTimestamps for LocalStore {
+    async fn get_timestamps(&self, id: &Id) -> Result<Timestamps, GetError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| {
+            get_registration(conn, &id)?.ok_or(GetError::NotFound)?;
+
+            let from_sql = |t: Option<i64>| t.map(|t| t as u64);
+
+            let t = conn
+                .query_row(
+                    "SELECT created_at, last_attempt_at, last_state_change_at
+                FROM timestamps WHERE id = ?1",
+                    params![id],
+                    |row| {
+                        Ok(Timestamps {
+                            created_at: from_sql(row.get(0)?),
+                            last_attempt_at: from_sql(row.get(1)?),
+                            last_state_change_at: from_sql(row.get(2)?),
+                            ..Default::default()
+                        })
+                    },
+                )
+                .optional()
+                .context("failed to get timestamps")?
+                .unwrap_or_default();
+
+            // Tasks are not queued while being processed
+            let next_scheduled_at: Option<i64> = conn
+                .query_row("SELECT t FROM tasks WHERE id = ?1", params![id], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .context("failed to get task")?;
+
+            Ok(Timestamps {
+                next_scheduled_at: from_sql(next_scheduled_at),
+                ..t
+            })
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl List for LocalStore {
+    async fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<(Id, Registration)>, ListError> {
+        self.run(move |conn| {
+            let mut stmt = conn
+                .prepare("SELECT id, registration FROM registrations WHERE id > ?1 ORDER BY id")
+                .context("failed to prepare query")?;
+
+            let rows = stmt
+                .query_map(params![key.unwrap_or_default()], |row| {
+                    Ok((row.get::<_, Id>(0)?, row.get::<_, String>(1)?))
+                })
+                .context("failed to list registrations")?;
+
+            let mut regs = vec![];
+
+            for row in rows {
+                if regs.len() as u64 >= limit {
+                    break;
+                }
+
+                let (id, reg) = row.context("failed to list registrations")?;
+                let reg: Registration =
+                    serde_json::from_str(&reg).context("failed to decode registration")?;
+
+                // Like the canister, states are matched on their variant only
+                if state
+                    .as_ref()
+                    .map_or(true, |s| discriminant(s) == discriminant(&reg.state))
+                {
+                    regs.push((id, reg));
+                }
+            }
+
+            Ok(regs)
+        })
+        .await
+    }
+}
+
+pub struct LocalCertGetter(pub Arc<LocalStore>, pub Arc<dyn Decode>);
+
+#[async_trait]
+impl GetCert for LocalCertGetter {
+    async fn get_cert(&self, id: &Id) -> Result<Pair, GetCertError> {
+        let id = id.to_owned();
+
+        let (key, chain) = self
+            .0
+            .run(move |conn| {
+                let pair: Option<(Vec<u8>, Vec<u8>)> = conn
+                    .query_row(
+                        "SELECT key, chain FROM certificates WHERE id = ?1",
+                        params![id],
+                        |row| Ok((row.get(0)?, row.get(1)?)),
+                    )
+                    .optional()
+                    .context("failed to get certificate")?;
+
+                pair.ok_or(GetCertError::NotFound)
+            })
+            .await?;
+
+        Ok(Pair(
+            self.1.decode(&key).await?,
+            self.1.decode(&chain).await?,
+        ))
+    }
+}
+
+pub struct LocalUploader(pub Arc<LocalStore>, pub Arc<dyn Encode>);
+
+#[async_trait]
+impl Upload for LocalUploader {
+    async fn upload(&self, id: &Id, pair: Pair) -> Result<(), UploadError> {
+        let (key, chain) = (self.1.encode(&pair.0).await?, self.1.encode(&pair.1).await?);
+
+        let id = id.to_owned();
+
+        self.0
+            .run(move |conn| {
+        if get_registration(conn, &id)?.is_none() {
+            return Err(UploadError::NotFound);
+        }
+
+        conn.execute(
+            "INSERT OR REPLACE INTO certificates (id, key, chain, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![id, key, chain, to_sql_time(now())],
+        )
+        .context("failed to upload certificate")?;
+
+        Ok(())
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl Revoke for LocalStore {
+    async fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| {
+            let n = conn
+                .execute("DELETE FROM certificates WHERE id = ?1", params![id])
+                .context("failed to revoke certificate")?;
+
+            match n {
+                0 => Err(RevokeError::NotFound),
+                _ => Ok(()),
+            }
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Export for LocalStore {
+    async fn export(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<Package>, IcCertificate, Option<String>), ExportError> {
+        let filter = filter.clone();
+
+        self.run(move |conn| {
+            let mut stmt = conn
+                .prepare(
+                    "SELECT c.id, r.registration, c.key, c.chain, c.updated_at
+                 FROM certificates c JOIN registrations r ON r.id = c.id
+                 WHERE c.id > ?1 ORDER BY c.id",
+                )
+                .context("failed to prepare query")?;
+
+            let rows = stmt
+                .query_map(params![key.unwrap_or_default()], |row| {
+                    Ok((
+                        row.get::<_, Id>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Vec<u8>>(2)?,
+                        row.get::<_, Vec<u8>>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                })
+                .context("failed to export certificates")?;
+
+            let state: Option<State> = filter.state.clone().map(Into::into);
+
+            let mut pkgs = vec![];
+
+            for row in rows {
+                if pkgs.len() as u64 >= limit {
+                    break;
+                }
+
+                let (id, reg, key, chain, updated_at) =
+                    row.context("failed to export certificates")?;
+                let reg: Registration =
+                    serde_json::from_str(&reg).context("failed to decode registration")?;
+
+                if let Some(suffix) = &filter.name_suffix {
+                    if !reg.name.ends_with(suffix.as_str()) {
+                        continue;
+                    }
+                }
+
+                if let Some(state) = &state {
+                    if discriminant(state) != discriminant(&reg.state) {
+                        continue;
+                    }
+                }
+
+                if let Some(updated_since) = filter.updated_since {
+                    if (updated_at as u64) < updated_since {
+                        continue;
+                    }
+                }
+
+                pkgs.push(Package {
+                    id,
+                    name: reg.name,
+                    alt_names: reg.alt_names,
+                    canister: reg.canister,
+                    pair: Pair(key, chain),
+                });
+            }
+
+            // A full page indicates there might be more entries
+            let next_key = match pkgs.len() as u64 == limit {
+                true => pkgs.last().map(|pkg| pkg.id.to_owned()),
+                false => None,
+            };
+
+            Ok((
+                pkgs,
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                next_key,
+            ))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Queue for LocalStore {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
+        let id = id.to_owned();
+
+        self.run(move |conn| {
+            let tx = conn.transaction().context("failed to start transaction")?;
+
+            if get_registration(&tx, &id)?.is_none() {
+                return Err(QueueError::NotFound);
+            }
+
+            tx.execute(
+                "INSERT OR REPLACE INTO tasks (id, t) VALUES (?1, ?2)",
+                params![id, to_sql_time(t)],
+            )
+            .context("failed to queue task")?;
+
+            // Priorities are kept apart from tasks, so that reclaimed tasks keep theirs
+            tx.execute(
+                "INSERT OR REPLACE INTO task_priorities (id, priority) VALUES (?1, ?2)",
+                params![id, to_sql_priority(priority)],
+            )
+            .context("failed to set task priority")?;
+
+            tx.commit().context("failed to commit transaction")?;
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+// Re-queues the tasks of expired leases, whose holders are presumed gone
+fn reclaim_tasks(conn: &Connection) -> Result<(), Error> {
+    let t = to_sql_time(now());
+
+    conn.execute(
+        "INSERT OR IGNORE INTO tasks (id, t) SELECT id, expires_at FROM leases WHERE expires_at <= ?1",
+        params![t],
+    )
+    .context("failed to reclaim tasks")?;
+
+    conn.execute("DELETE FROM leases WHERE expires_at <= ?1", params![t])
+        .context("failed to remove expired leases")?;
+
+    Ok(())
+}
+
+// The due task with the highest priority that is not leased, the earliest one among equals
+fn next_task(conn: &Connection) -> Result<Option<(Id, Priority)>, Error> {
+    reclaim_tasks(conn)?;
+
+    let next: Option<(Id, Option<i64>)> = conn
+        .query_row(
+            "SELECT tasks.id, task_priorities.priority FROM tasks
+                LEFT JOIN task_priorities ON task_priorities.id = tasks.id
+                WHERE tasks.t <= ?1 AND tasks.id NOT IN (SELECT id FROM leases)
+                ORDER BY COALESCE(task_priorities.priority, ?2) DESC, tasks.t LIMIT 1",
+            params![to_sql_time(now()), to_sql_priority(Priority::Normal)],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    Ok(next.map(|(id, p)| (id, p.map_or(Priority::Normal, from_sql_priority))))
+}
+
+#[async_trait]
+impl Peek for LocalStore {
+    async fn peek(&self) -> Result<Id, PeekError> {
+        self.run(|conn| {
+            next_task(conn)?
+                .map(|(id, _)| id)
+                .ok_or(PeekError::NoTasksAvailable)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Count for LocalStore {
+    async fn count(&self) -> Result<u64, CountError> {
+        self.run(|conn| {
+            reclaim_tasks(conn)?;
+
+            let n: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM tasks WHERE t <= ?1 AND id NOT IN (SELECT id FROM leases)",
+                params![to_sql_time(now())],
+                |row| row.get(0),
+            )
+            .context("failed to count tasks")?;
+
+            Ok(n as u64)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Dispense for LocalStore {
+    async fn dispense(&self) -> Result<(Id, Task), DispenseError> {
+        self.run(|conn| {
+            let (id, priority) = next_task(conn)?.ok_or(DispenseError::NoTasksAvailable)?;
+
+            conn.execute("DELETE FROM tasks WHERE id = ?1", params![id])
+                .context("failed to dispense task")?;
+
+            let lease = Lease {
+                id: format!("{:032x}", rand::random::<u128>()),
+                expires_at: now() + LEASE_TTL.as_nanos() as u64,
+            };
+
+            conn.execute(
+                "INSERT OR REPLACE INTO leases (id, lease, expires_at) VALUES (?1, ?2, ?3)",
+                params![id, lease.id, to_sql_time(lease.expires_at)],
+            )
+            .context("failed to lease task")?;
+
+            set_timestamp(conn, &id, "last_attempt_at", now())?;
+
+            let reg = get_registration(conn, &id)?
+                .ok_or_else(|| anyhow!("task {id} has no registration"))?;
+
+            Ok((
+                id,
+                Task {
+                    name: reg.name,
+                    canister: reg.canister,
+                    action: reg.state.into(),
+                    key_type: reg.key_type,
+                    alt_names: reg.alt_names,
+                    priority,
+                    lease: Some(lease),
+                },
+            ))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Renew for LocalStore {
+    async fn renew(&self, id: &Id, lease: &Lease) -> Result<Lease, LeaseError> {
+        let lease = Lease {
+            expires_at: now() + LEASE_TTL.as_nanos() as u64,
+            ..lease.clone()
+        };
+
+        let id = id.to_owned();
+
+        // Expired leases are held until reclaimed
+        self.run(move |conn| {
+            if conn
+                .execute(
+                    "UPDATE leases SET expires_at = ?3 WHERE id = ?1 AND lease = ?2",
+                    params![id, lease.id, to_sql_time(lease.expires_at)],
+                )
+                .context("failed to renew lease")?
+                == 0
+            {
+                return Err(LeaseError::NotHeld);
+            }
+
+            Ok(lease)
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Release for LocalStore {
+    async fn release(&self, id: &Id, lease: &Lease) -> Result<(), LeaseError> {
+        let (id, lease) = (id.to_owned(), lease.to_owned());
+
+        self.run(move |conn| {
+            if conn
+                .execute(
+                    "DELETE FROM leases WHERE id = ?1 AND lease = ?2",
+                    params![id, lease.id],
+                )
+                .context("failed to release lease")?
+                == 0
+            {
+                return Err(LeaseError::NotHeld);
+            }
+
+            Ok(())
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::work::Action;
+
+    #[tokio::test]
+    async fn registration_lifecycle() -> Result<(), Error> {
+        let s = LocalStore::in_memory()?;
+        let canister = Principal::from_text("aaaaa-aa")?;
+
+        let id = s
+            .create("name", &canister, None, &["www.name".into()])
+            .await?;
+
+        match s.create("www.name", &canister, None, &[]).await {
+            Err(CreateError::Duplicate(other)) if other == id => {}
+            other => return Err(anyhow!("expected Duplicate but got {:?}", other)),
+        }
+
+        let t = s.get_timestamps(&id).await?;
+        assert!(t.created_at.is_some());
+        assert_eq!(t.last_state_change_at, t.created_at);
+        assert_eq!(t.last_attempt_at, None);
+
+        s.queue(&id, 7, Priority::Normal).await?;
+        assert_eq!(s.peek().await?, id);
+        assert_eq!(s.get_timestamps(&id).await?.next_scheduled_at, Some(7));
+
+        let (other, task) = s.dispense().await?;
+        assert_eq!(other, id);
+        assert!(matches!(task.action, Action::Order));
+        assert!(matches!(
+            s.dispense().await,
+            Err(DispenseError::NoTasksAvailable)
+        ));
+
+        let t = s.get_timestamps(&id).await?;
+        assert!(t.last_attempt_at.is_some());
+        assert_eq!(t.next_scheduled_at, None);
+
+        s.update(&id, &UpdateType::State(State::Available)).await?;
+        assert_eq!(s.get(&id).await?.state, State::Available);
+        assert!(s.get_timestamps(&id).await?.last_state_change_at > t.last_state_change_at);
+
+        let regs = s.list(None, 10, Some(State::Parked("".into()))).await?;
+        assert!(regs.is_empty());
+
+        s.record(&id, IssuanceEvent::Attempt(1)).await?;
+        assert_eq!(s.get_history(&id).await?.attempts, vec![1]);
+
+        s.remove(&id).await?;
+        assert!(matches!(s.get(&id).await, Err(GetError::NotFound)));
+        assert!(matches!(
+            s.get_timestamps(&id).await,
+            Err(GetError::NotFound)
+        ));
+        assert!(matches!(
+            s.get_history(&id).await,
+            Err(HistoryError::NotFound)
+        ));
+
+        // Names are released with the registration
+        s.create("name", &canister, None, &[]).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn task_leases() -> Result<(), Error> {
+        let s = LocalStore::in_memory()?;
+        let canister = Principal::from_text("aaaaa-aa")?;
+
+        let id = s.create("name", &canister, None, &[]).await?;
+        s.queue(&id, 0, Priority::Normal).await?;
+
+        let (_, task) = s.dispense().await?;
+        let lease = task.lease.ok_or_else(|| anyhow!("task was not leased"))?;
+
+        // Queued again while leased
+        s.queue(&id, 0, Priority::Normal).await?;
+        assert!(matches!(s.peek().await, Err(PeekError::NoTasksAvailable)));
+
+        let lease = s.renew(&id, &lease).await?;
+
+        let other = Lease {
+            id: "other".into(),
+            ..lease.clone()
+        };
+        assert!(matches!(
+            s.release(&id, &other).await,
+            Err(LeaseError::NotHeld)
+        ));
+
+        s.release(&id, &lease).await?;
+        assert_eq!(s.peek().await?, id);
+
+        // Tasks of expired leases are reclaimed
+        let (_, task) = s.dispense().await?;
+        let lease = task.lease.ok_or_else(|| anyhow!("task was not leased"))?;
+
+        s.0.lock()
+            .unwrap()
+            .execute("UPDATE leases SET expires_at = 0", params![])?;
+
+        assert_eq!(s.peek().await?, id);
+        assert!(matches!(
+            s.renew(&id, &lease).await,
+            Err(LeaseError::NotHeld)
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn tasks_dispensed_by_priority() -> Result<(), Error> {
+        let s = LocalStore::in_memory()?;
+        let canister = Principal::from_text("aaaaa-aa")?;
+
+        let tasks = [
+            ("a.com", 0, Priority::Low),
+            ("b.com", 1, Priority::Normal),
+            ("c.com", 2, Priority::High),
+            ("d.com", 0, Priority::Normal),
+        ];
+
+        let mut ids = vec![];
+        for (name, t, priority) in tasks {
+            let id = s.create(name, &canister, None, &[]).await?;
+            s.queue(&id, t, priority).await?;
+            ids.push(id);
+        }
+
+        assert_eq!(s.count().await?, 4);
+
+        // Highest priority first, then oldest first
+        for idx in [2, 3, 1, 0] {
+            let (id, task) = s.dispense().await?;
+            assert_eq!(id, ids[idx]);
+            assert_eq!(task.priority, tasks[idx].2);
+        }
+
+        // Leased tasks are no longer waiting
+        assert_eq!(s.count().await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn poisoned_connection() -> Result<(), Error> {
+        let s = LocalStore::in_memory()?;
+
+        let conn = Arc::clone(&s.0);
+        let _ = std::thread::spawn(move || {
+            let _conn = conn.lock().unwrap();
+            panic!("query panicked");
+        })
+        .join();
+
+        assert!(matches!(
+            s.get(&"id".to_string()).await,
+            Err(GetError::UnexpectedError(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use std::{
+    collections::HashMap,
     fs::File,
     net::{IpAddr, SocketAddr},
     path::PathBuf,
     sync::{
         atomic::{AtomicU64, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
@@ -12,23 +13,21 @@ use std::{
 use anyhow::{anyhow, Context, Error};
 use axum::{
     body::Body,
-    extract::MatchedPath,
+    extract::{DefaultBodyLimit, MatchedPath},
     handler::Handler,
     http::{Request, Response, StatusCode, Uri},
     middleware::{self, Next},
     response::IntoResponse,
     routing::{delete, get, post, put},
-    Extension, Router, Server,
+    Extension, Router,
 };
 use candid::Principal;
-use chacha20poly1305::{KeyInit, XChaCha20Poly1305};
 use clap::Parser;
-use futures::future::TryFutureExt;
 use ic_agent::{
     agent::http_transport::reqwest_transport::ReqwestHttpReplicaV2Transport,
     identity::Secp256k1Identity, Agent,
 };
-use instant_acme::{Account, AccountCredentials, NewAccount};
+use instant_acme::{Account, AccountCredentials};
 use opentelemetry::{
     metrics::{Counter, Histogram, MeterProvider as _},
     sdk::metrics::MeterProvider,
@@ -36,44 +35,113 @@ use opentelemetry::{
 };
 use opentelemetry_prometheus::exporter;
 use prometheus::{labels, Encoder as PrometheusEncoder, Registry, TextEncoder};
-use tokio::{sync::Semaphore, task, time::sleep};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::Notify,
+    task::{self, AbortHandle},
+    time::{sleep, timeout},
+};
+use tokio_util::sync::CancellationToken;
 use tower::ServiceBuilder;
-use tracing::info;
+use tracing::{info, level_filters::LevelFilter, warn};
+use tracing_subscriber::{layer::SubscriberExt, reload};
 use trust_dns_resolver::{
     config::{NameServerConfigGroup, ResolverConfig, ResolverOpts, GOOGLE_IPS},
     TokioAsyncResolver,
 };
 
 use crate::{
-    acme::Acme,
+    acme::{self, Acme},
+    acme_account::CredentialStore,
+    acme_failover::WithFailover,
     acme_idna::WithIDNA,
+    acme_pool::{PoolAccount, WithAccountPool},
+    acme_revoke::AcmeRevoker,
+    audit::{
+        audit_mw, with_context, Append, AuditContext, CanisterAuditLog, FileAuditLog, WithAudit,
+        ISSUER_ACTOR,
+    },
+    auth::{auth_mw, AuthMiddlewareArgs, Scope, Tokens},
+    backpressure::{backpressure_mw, Backpressure, BackpressureMiddlewareArgs},
+    budget::{Limit, Limits, RateBudget, WithBudget},
+    caa::{CaaChecker, CheckCaa},
+    cache::{Cache, Reconciler, WithCache},
+    canary::{Canary, Discard},
     certificate::{
-        CanisterCertGetter, CanisterExporter, CanisterUploader, Export, WithDecode, WithPagination,
-        WithRetries, WithVerify,
+        self, CanisterCertGetter, CanisterExporter, CanisterRevoker, CanisterUploader, GetCert,
+        WithDecode, WithPagination, WithRetries, WithVerify,
     },
-    check::{Check, Checker},
-    cloudflare::Cloudflare,
-    dns::Resolver,
-    encode::{Decoder, Encoder},
-    metrics::{MetricParams, WithMetrics},
-    registration::{Create, Get, Remove, State, Update, UpdateType},
+    check::{AddressPolicy, Check, Checker, Delegation, OwnershipTokens, WithOwnership},
+    cloudflare::{Cloudflare, ZoneToken},
+    config::Concurrency,
+    dns::{self, Resolver, Upstream},
+    encode::{Decoder, Encoder, Keyring},
+    failure::Failure,
+    history::{CanisterHistory, GetHistory, RecordEvent, TaskHistory, TaskRecord, WithHistory},
+    idn::{IdnPolicy, Normalize, Normalizer},
+    inspect::{load_roots, Inspector},
+    local::{LocalCertGetter, LocalStore, LocalUploader},
+    metrics::{histogram_view, Boundaries, Buckets, MetricParams, WithMetrics, DOMAIN_LABELS},
+    monitor::{Expiries, ExpiryMonitor},
+    policy::{Blocklist, WithBlocklist, WithRegistrationLimit, WithReservedTlds},
+    propagation::{NameServer, PropagationChecker},
+    rate_limit::{rate_limit_mw, request_rate_limit_mw, RateLimitMiddlewareArgs, RateLimiter},
+    reencrypt::Reencrypter,
+    registration::{
+        Create, Get, GetTimestamps, Id, List, Remove, State, Update, UpdateType, WithCleanup,
+        WithRevocation,
+    },
+    renewal::{expiry, RenewalPolicy},
+    retry::{ClassBackoff, RetryDecision, RetryPolicy},
+    s3::{Credentials, S3Bucket, S3Exporter},
+    tls::{self, TlsPaths, TlsReloader},
+    validation::DEFAULT_MAX_BODY_SIZE,
     verification::CertificateVerifier,
     work::{
-        Dispense, DispenseError, Peek, PeekError, Process, Queue, WithDetectImportance,
-        WithDetectRenewal,
+        hold_lease, Action, Count, Dispense, DispenseError, Lease, Peek, PeekError, PhaseTimeout,
+        PhaseTimeouts, Priority, Process, Queue, Release, Renew, WithDetectImportance,
+        WithDetectRenewal, WithNotify,
     },
 };
 
 mod acme;
+mod acme_account;
+mod acme_failover;
 mod acme_idna;
+mod acme_pool;
+mod acme_revoke;
 mod api;
+mod audit;
+mod auth;
+mod backpressure;
+mod budget;
+mod caa;
+mod cache;
+mod canary;
 mod certificate;
 mod check;
 mod cloudflare;
+mod config;
 mod dns;
 mod encode;
+mod failure;
+mod history;
+mod idn;
+mod import;
+mod inspect;
+mod local;
 mod metrics;
+mod monitor;
+mod policy;
+mod propagation;
+mod rate_limit;
+mod reencrypt;
 mod registration;
+mod renewal;
+mod retry;
+mod s3;
+mod tls;
+mod validation;
 mod verification;
 mod work;
 
@@ -82,12 +150,52 @@ const SERVICE_NAME: &str = "certificate-issuer";
 pub(crate) static TASK_DELAY_SEC: AtomicU64 = AtomicU64::new(60);
 pub(crate) static TASK_ERROR_DELAY_SEC: AtomicU64 = AtomicU64::new(10 * 60);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum StorageBackend {
+    /// The certificate orchestrator canister, shared by all issuers and boundary nodes
+    Canister,
+    /// A local SQLite database, for standalone and development deployments
+    Local,
+}
+
+enum Storage {
+    Canister(Principal),
+    Local(Arc<LocalStore>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+enum AuditBackend {
+    /// Registration mutations are not recorded
+    None,
+    /// An append-only file of JSON lines
+    File,
+    /// The certificate orchestrator canister, requires the canister storage backend
+    Canister,
+}
+
 #[derive(Parser)]
 #[command(name = SERVICE_NAME)]
 struct Cli {
+    /// A YAML file of flags and their values, e.g. `task-concurrency: 20`, overridden by flags given on the command line.
+    /// Concurrency, rate limits, the renewal window and the log level are reloaded from it on SIGHUP
+    #[arg(long)]
+    config_path: Option<PathBuf>,
+
+    /// Minimum level of logged events (error, warn, info, debug or trace)
+    #[arg(long, default_value = "info")]
+    log_level: LevelFilter,
+
     #[arg(long, default_value = "127.0.0.1:3000")]
     api_addr: SocketAddr,
 
+    /// Certificate to serve the API over TLS with (PEM), requires --api-tls-key-path
+    #[arg(long)]
+    api_tls_cert_path: Option<PathBuf>,
+
+    /// Private key to serve the API over TLS with (PEM), requires --api-tls-cert-path
+    #[arg(long)]
+    api_tls_key_path: Option<PathBuf>,
+
     /// NNS public key
     #[clap(long)]
     root_key_path: Option<PathBuf>,
@@ -98,17 +206,55 @@ struct Cli {
     #[arg(long, default_value = "http://127.0.0.1:8080/")]
     orchestrator_uri: Uri,
 
+    /// Required with the canister storage backend
     #[arg(long)]
-    orchestrator_canister_id: Principal,
+    orchestrator_canister_id: Option<Principal>,
+
+    /// Where registrations, certificates and tasks are stored
+    #[arg(long, value_enum, default_value = "canister")]
+    storage_backend: StorageBackend,
+
+    /// A SQLite database used by the local storage backend
+    #[arg(long, default_value = "issuer.db")]
+    storage_path: PathBuf,
 
     /// A symmetric key used to encrypt and/or decrypt certificates
     #[clap(long, default_value = "key.pem")]
     key_path: PathBuf,
 
+    /// Previous symmetric keys, still used to decrypt certificates until they are re-encrypted with the current key
+    #[arg(long, value_delimiter = ',')]
+    previous_key_path: Vec<PathBuf>,
+
+    /// How often to re-encrypt certificates that are encrypted with a previous key
+    #[arg(long, default_value = "86400")] // 1 day
+    reencrypt_interval_sec: u64,
+
+    /// Where to keep track of re-encryption progress, so an interrupted pass is resumed after a restart
+    #[arg(long)]
+    reencrypt_checkpoint_path: Option<PathBuf>,
+
+    /// A local store mirroring registrations and queued tasks, so work can continue while the orchestrator is unreachable
+    #[arg(long)]
+    cache_path: Option<PathBuf>,
+
+    /// How often to push changes made while the orchestrator was unreachable
+    #[arg(long, default_value = "30")]
+    cache_reconcile_interval_sec: u64,
+
     /// A domain clients are required to delegate their DNS-01 challenge to.
     #[arg(long)]
     delegation_domain: String,
 
+    /// Policy for internationalized domain names, which are stored in their punycode form
+    #[arg(long, value_enum, default_value = "highly-restrictive")]
+    idn_policy: IdnPolicy,
+
+    /// Address records registered domains are required to have: `any` of A or AAAA (accepting IPv6-only domains),
+    /// `ipv4` requiring A records, or `dual` requiring both
+    #[arg(long, value_enum, default_value = "any")]
+    address_policy: AddressPolicy,
+
     /// A set of DNS name servers the issuer will use
     #[arg(long, value_delimiter = ',')]
     name_servers: Option<Vec<IpAddr>>,
@@ -116,24 +262,112 @@ struct Cli {
     #[arg(long, default_value = "53")]
     name_servers_port: u16,
 
+    /// Upstream resolvers the issuer will use instead of --name-servers, e.g. `udp://8.8.8.8`,
+    /// `tls://1.1.1.1#cloudflare-dns.com` or `https://9.9.9.9/dns-query#dns.quad9.net`
+    #[arg(long, value_delimiter = ',')]
+    dns_resolvers: Vec<Upstream>,
+
+    /// Timeout of a single DNS query
+    #[arg(long, default_value = "5")]
+    dns_timeout_sec: u64,
+
+    /// Number of attempts of a DNS query before it fails
+    #[arg(long, default_value = "2")]
+    dns_attempts: usize,
+
+    /// Public resolvers used, in addition to the authoritative name servers of the delegation domain,
+    /// to verify that challenge responses have propagated
+    #[arg(long, value_delimiter = ',', default_value = "1.1.1.1,8.8.8.8,9.9.9.9")]
+    dns_propagation_name_servers: Vec<IpAddr>,
+
+    /// Number of name servers that need to agree on a challenge response (default: all)
     #[arg(long)]
-    acme_account_id: Option<String>,
+    dns_propagation_required: Option<usize>,
+
+    /// ACME account IDs, one per provider
+    #[arg(long, value_delimiter = ',')]
+    acme_account_id: Vec<String>,
+
+    /// ACME account key paths, one per provider
+    #[arg(long, value_delimiter = ',')]
+    acme_account_key_path: Vec<PathBuf>,
+
+    /// An ordered list of ACME providers, the first one being the primary
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "https://acme-v02.api.letsencrypt.org"
+    )]
+    acme_provider_url: Vec<String>,
+
+    /// Number of consecutive failures after which an ACME provider is failed over
+    #[arg(long, default_value = "3")]
+    acme_failover_threshold: u32,
 
+    /// Duration after which a failed over ACME provider or a rate-limited ACME account is retried
+    #[arg(long, default_value = "3600")]
+    acme_failover_cooldown_sec: u64,
+
+    /// Contact emails of ACME accounts created by the issuer, e.g. for the provider to send expiry notices to
+    #[arg(long, value_delimiter = ',')]
+    acme_contact_email: Vec<String>,
+
+    /// A directory to keep the credentials of ACME accounts created by the issuer in, encrypted with the symmetric key,
+    /// so that an account is created on first run and re-used afterwards (default: a new account on every run)
     #[arg(long)]
-    acme_account_key_path: Option<PathBuf>,
+    acme_credentials_dir: Option<PathBuf>,
+
+    /// A JSON file of additional ACME accounts, new orders are spread across the accounts of a provider
+    #[arg(long)]
+    acme_accounts_path: Option<PathBuf>,
+
+    /// CA domains that must be authorized by a domain's CAA records (if any) before placing an order
+    #[arg(long, value_delimiter = ',', default_value = "letsencrypt.org")]
+    acme_caa_identities: Vec<String>,
+
+    /// Orders per registered domain per week the issuer budgets for, delaying further orders (0 meaning unlimited)
+    #[arg(long, default_value = "50")]
+    acme_budget_per_domain: usize,
 
-    #[arg(long, default_value = "https://acme-v02.api.letsencrypt.org")]
-    acme_provider_url: String,
+    /// Orders per 3 hours per ACME account the issuer budgets for across all domains (0 meaning unlimited)
+    #[arg(long, default_value = "300")]
+    acme_budget_per_account: usize,
+
+    /// Orders for the exact same set of names per week the issuer budgets for (0 meaning unlimited)
+    #[arg(long, default_value = "5")]
+    acme_budget_duplicate: usize,
+
+    /// A PEM bundle of root certificates that issued certificate chains must build to (default: any root)
+    #[arg(long)]
+    issued_trusted_roots_path: Option<PathBuf>,
+
+    /// Number of signed certificate timestamps an issued certificate must embed, proving it was submitted to CT logs
+    #[arg(long, default_value = "0")]
+    issued_min_scts: usize,
+
+    /// Key algorithm of issued certificates, unless set by the registration
+    #[arg(long, value_enum, default_value = "ecdsa-p256")]
+    key_type: acme::KeyType,
 
     #[arg(long, default_value = "https://api.cloudflare.com/client/v4/")]
     cloudflare_api_url: String,
 
+    /// An API token used for zones without their own token, which are discovered automatically
+    #[arg(long)]
+    cloudflare_api_key_path: Option<PathBuf>,
+
+    /// A JSON file of API tokens scoped to zones, e.g. when the delegation domain spans multiple zones
     #[arg(long)]
-    cloudflare_api_key_path: PathBuf,
+    cloudflare_api_tokens_path: Option<PathBuf>,
 
+    /// How often to poll the orchestrator for tasks while idle
     #[arg(long, default_value = "60")]
     peek_sleep_sec: u64,
 
+    /// How long to wait before polling again after failing to reach the orchestrator
+    #[arg(long, default_value = "10")]
+    peek_error_sleep_sec: u64,
+
     /// A set of important domains, to be used in metrics
     #[arg(long, default_value = "", value_delimiter = ',')]
     important_domains: Vec<String>,
@@ -141,22 +375,207 @@ struct Cli {
     #[arg(long, default_value = "127.0.0.1:9090")]
     metrics_addr: SocketAddr,
 
+    /// Bucket boundaries (in seconds) of duration histograms
+    #[arg(
+        long,
+        default_value = "0.01,0.05,0.1,0.25,0.5,1,2.5,5,10,30,60,120,300,600"
+    )]
+    metrics_buckets: Boundaries,
+
+    /// Bucket boundaries of the histograms matching a pattern, overriding --metrics-buckets,
+    /// e.g. `certificate_issuer.acme_*.duration_sec=1,5,30,60,300,900` (`*` matching any characters)
+    #[arg(long)]
+    metrics_buckets_override: Vec<Buckets>,
+
+    /// Leave out labels naming domains, e.g. the apex domain of important domains or the domain of expiring certificates
+    #[arg(long)]
+    metrics_omit_domain_labels: bool,
+
+    /// Certificate to serve metrics over TLS with (PEM), requires --metrics-tls-key-path
+    #[arg(long)]
+    metrics_tls_cert_path: Option<PathBuf>,
+
+    /// Private key to serve metrics over TLS with (PEM), requires --metrics-tls-cert-path
+    #[arg(long)]
+    metrics_tls_key_path: Option<PathBuf>,
+
+    /// How often to check TLS certificates and keys for changes, reloading them if needed
+    #[arg(long, default_value = "60")]
+    tls_reload_interval_sec: u64,
+
+    /// Number of registrations a single source IP can create per hour
+    #[arg(long, default_value = "30")]
+    create_rate_limit_per_ip: u32,
+
+    /// Number of registrations a single caller identity can create per hour
+    #[arg(long, default_value = "60")]
+    create_rate_limit_per_identity: u32,
+
+    /// Header to take the source IP from when running behind a reverse proxy (e.g. x-real-ip).
+    /// For lists such as x-forwarded-for, the last entry is used, i.e. the one added by the proxy
+    #[arg(long)]
+    rate_limit_ip_header: Option<String>,
+
+    /// A JSON file of static bearer tokens and their scopes, authentication is disabled if missing
+    #[arg(long)]
+    api_tokens_path: Option<PathBuf>,
+
+    /// A secret to derive domain ownership tokens from. If set, canisters must serve the token of a domain
+    /// before it can be registered
+    #[arg(long)]
+    ownership_secret_path: Option<PathBuf>,
+
+    /// A file of domains that cannot be registered along with their subdomains, one per line
+    #[arg(long)]
+    blocklist_path: Option<PathBuf>,
+
+    /// Top-level domains reserved for special use, which cannot be registered
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "example,invalid,local,localhost,onion,test"
+    )]
+    reserved_tlds: Vec<String>,
+
+    /// The maximum number of registrations a canister can have, unlimited if unset
+    #[arg(long)]
+    max_registrations_per_canister: Option<u64>,
+
+    /// A secret to derive delegation tokens from. If set, the challenge of a domain is delegated to a record
+    /// specific to the domain and its canister, `<token>.<delegation-domain>`, rather than `_acme-challenge.<domain>.<delegation-domain>`
+    #[arg(long)]
+    delegation_secret_path: Option<PathBuf>,
+
+    /// Scopes granted to unauthenticated callers
+    #[arg(long, value_delimiter = ',', default_value = "create")]
+    api_public_scopes: Vec<Scope>,
+
+    /// Maximum size of API request bodies, in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_BODY_SIZE)]
+    api_max_body_size: usize,
+
+    /// Number of tasks processed concurrently
+    #[arg(long, default_value = "10", value_parser = clap::value_parser!(u32).range(1..))]
+    task_concurrency: u32,
+
     #[arg(long)]
     task_delay_sec: Option<u64>,
 
     #[arg(long)]
     task_error_delay_sec: Option<u64>,
+
+    /// Backoff for a class of failures, as `<class>=<initial_sec>:<max_sec>:<max_attempts>` (0 attempts meaning unlimited).
+    /// Classes are dns-not-propagated, acme-rate-limited, caa-failure, order-invalid, certificate-rejected, user-configuration and unexpected
+    #[arg(long)]
+    retry_backoff: Vec<ClassBackoff>,
+
+    /// How long a phase of processing a task may take before the task fails (0 meaning no limit)
+    #[arg(long, default_value = "300")] // 5 minutes
+    phase_timeout_sec: u64,
+
+    /// Timeout of a specific phase, as `<phase>=<sec>`, e.g. `acme-finalize=600` (0 meaning no limit).
+    /// Phases are caa-check, acme-order, dns-create, propagation-wait, acme-ready, acme-finalize, dns-cleanup, verify, upload and check
+    #[arg(long)]
+    phase_timeout: Vec<PhaseTimeout>,
+
+    /// How long before a certificate expires to renew it
+    #[arg(long, default_value = "2592000")] // 30 days
+    renewal_lead_time_sec: u64,
+
+    /// Maximum amount by which renewals are moved earlier, to spread them out over the days with the fewest renewals
+    #[arg(long, default_value = "259200")] // 3 days
+    renewal_jitter_sec: u64,
+
+    /// How often to scan stored certificates for their expiry
+    #[arg(long, default_value = "3600")] // 1 hour
+    expiry_scan_interval_sec: u64,
+
+    /// Certificates expiring within this window are renewed right away, regardless of their scheduled renewal
+    #[arg(long, default_value = "604800")] // 7 days
+    expiry_danger_window_sec: u64,
+
+    /// Number of due tasks above which new registrations are rejected with 503 (unset meaning never)
+    #[arg(long)]
+    backpressure_pending_tasks: Option<u64>,
+
+    /// How often to count due tasks while checking for backpressure
+    #[arg(long, default_value = "10")]
+    backpressure_refresh_interval_sec: u64,
+
+    /// How long to wait for in-flight tasks when shutting down, before re-queueing them
+    #[arg(long, default_value = "60")]
+    shutdown_timeout_sec: u64,
+
+    /// How many of the most recent tasks to keep per registration for operators to inspect
+    #[arg(long, default_value = "20")]
+    task_history_len: usize,
+
+    /// Where to record mutations of registrations, i.e. who changed what, when and why
+    #[arg(long, value_enum, default_value = "none")]
+    audit_backend: AuditBackend,
+
+    /// Path of the audit log with the file audit backend
+    #[arg(long, default_value = "audit.log")]
+    audit_log_path: PathBuf,
+
+    /// A domain to periodically issue a certificate for through the full pipeline, to detect broken credentials
+    /// or DNS setup (unset meaning no canary). Its challenge must be delegated like that of any registered domain
+    #[arg(long)]
+    canary_domain: Option<String>,
+
+    /// Canister the canary domain is considered registered for
+    #[arg(long, default_value = "aaaaa-aa")]
+    canary_canister_id: Principal,
+
+    /// ACME provider of canary issuances, a staging directory so that they don't count against rate limits
+    #[arg(long, default_value = "https://acme-staging-v02.api.letsencrypt.org")]
+    canary_acme_provider_url: String,
+
+    /// How often to issue a canary certificate
+    #[arg(long, default_value = "21600")] // 6 hours
+    canary_interval_sec: u64,
+
+    /// How long a canary issuance may take before it is considered failed
+    #[arg(long, default_value = "900")] // 15 minutes
+    canary_timeout_sec: u64,
+
+    /// Endpoint of an S3-compatible service to push encrypted certificate packages to, e.g. https://s3.us-east-1.amazonaws.com
+    /// (unset meaning no push)
+    #[arg(long)]
+    s3_export_endpoint: Option<String>,
+
+    /// Bucket to push certificate packages to
+    #[arg(long, default_value = "certificates")]
+    s3_export_bucket: String,
+
+    /// Region of the bucket, used for signing requests
+    #[arg(long, default_value = "us-east-1")]
+    s3_export_region: String,
+
+    /// Prefix of the keys of pushed objects, e.g. `issuer/`
+    #[arg(long, default_value = "")]
+    s3_export_prefix: String,
+
+    /// Path of a JSON file with the `access_key_id` and `secret_access_key` of the bucket
+    #[arg(long, default_value = "s3_credentials.json")]
+    s3_export_credentials_path: PathBuf,
+
+    /// How often to push changed certificate packages
+    #[arg(long, default_value = "60")]
+    s3_export_interval_sec: u64,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let cli = Cli::parse();
+    let args: Vec<String> = std::env::args().collect();
+    let cli = Cli::parse_from(config::args(&args)?);
 
     // Logging
-    let subscriber = tracing_subscriber::fmt()
-        .json()
-        .flatten_event(true)
-        .finish();
+    let (log_filter, log_reload) = reload::Layer::new(cli.log_level);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(log_filter)
+        .with(tracing_subscriber::fmt::layer().json().flatten_event(true));
 
     tracing::subscriber::set_global_default(subscriber)
         .context("failed to set global subscriber")?;
@@ -168,7 +587,17 @@ async fn main() -> Result<(), Error> {
     )
     .unwrap();
     let exporter = exporter().with_registry(registry.clone()).build()?;
-    let provider = MeterProvider::builder().with_reader(exporter).build();
+    let provider = MeterProvider::builder()
+        .with_reader(exporter)
+        .with_view(histogram_view(
+            cli.metrics_buckets.0.clone(),
+            cli.metrics_buckets_override.clone(),
+        ))
+        .build();
+
+    if cli.metrics_omit_domain_labels {
+        DOMAIN_LABELS.store(false, Ordering::SeqCst);
+    }
     let meter = provider.meter(SERVICE_NAME);
 
     let metrics_handler = metrics_handler.layer(Extension(MetricsHandlerArgs { registry }));
@@ -215,50 +644,280 @@ async fn main() -> Result<(), Error> {
     };
 
     // DNS
-    let name_servers = cli.name_servers.unwrap_or_else(
-        || GOOGLE_IPS.to_owned(), // default
-    );
-
-    let resolver = {
+    let resolver_opts = {
         let mut opts = ResolverOpts::default();
 
         // Disable caching of DNS results
         opts.cache_size = 0;
 
-        Resolver(TokioAsyncResolver::tokio(
-            ResolverConfig::from_parts(
-                None,
-                vec![],
-                NameServerConfigGroup::from_ips_clear(
-                    &name_servers,         // ips
-                    cli.name_servers_port, // port
-                    true,                  // trust_nx_responses
-                ),
-            ),
-            opts,
-        )?)
+        opts.timeout = Duration::from_secs(cli.dns_timeout_sec);
+        opts.attempts = cli.dns_attempts;
+
+        opts
+    };
+
+    let name_servers = if cli.dns_resolvers.is_empty() {
+        let ips = cli.name_servers.unwrap_or_else(
+            || GOOGLE_IPS.to_owned(), // default
+        );
+
+        NameServerConfigGroup::from_ips_clear(
+            &ips,                  // ips
+            cli.name_servers_port, // port
+            true,                  // trust_nx_responses
+        )
+    } else {
+        dns::name_servers(&cli.dns_resolvers)
+    };
+
+    info!(
+        name_servers = ?name_servers
+            .iter()
+            .map(|ns| format!("{} ({:?})", ns.socket_addr, ns.protocol))
+            .collect::<Vec<_>>(),
+        "using upstream resolvers"
+    );
+
+    let resolver = new_resolver(name_servers, &resolver_opts)?;
+
+    // Name servers that need to agree on a challenge response before its order is marked as ready
+    let propagation_name_servers = {
+        let mut nss = vec![];
+
+        // Authoritative name servers of the delegation domain
+        let ns_lookup = resolver
+            .0
+            .ns_lookup(format!("{}.", cli.delegation_domain))
+            .await
+            .context("failed to lookup name servers of delegation domain")?;
+
+        for ns in ns_lookup.iter() {
+            let ips = resolver
+                .0
+                .lookup_ip(ns.to_string())
+                .await
+                .with_context(|| format!("failed to resolve name server {ns}"))?;
+
+            for ip in ips.iter().filter(IpAddr::is_ipv4) {
+                nss.push(NameServer {
+                    name: format!("{ns} ({ip})"),
+                    resolver: Box::new(new_resolver(
+                        NameServerConfigGroup::from_ips_clear(&[ip], 53, true),
+                        &resolver_opts,
+                    )?),
+                    recursive: false,
+                });
+            }
+        }
+
+        for ip in cli.dns_propagation_name_servers {
+            nss.push(NameServer {
+                name: ip.to_string(),
+                resolver: Box::new(new_resolver(
+                    NameServerConfigGroup::from_ips_clear(&[ip], 53, true),
+                    &resolver_opts,
+                )?),
+                recursive: true,
+            });
+        }
+
+        nss
     };
 
     let resolver = WithMetrics(resolver, MetricParams::new(&meter, SERVICE_NAME, "resolve"));
 
     // Encryption
-    let cipher = Arc::new({
-        let f = std::fs::read(cli.key_path).context("failed to open key file")?;
-        let p = pem::parse(f).context("failed to parse pem file")?;
-        XChaCha20Poly1305::new_from_slice(&p.contents).context("failed to init symmetric key")?
+    let keys = Arc::new({
+        let keys = [vec![cli.key_path], cli.previous_key_path]
+            .concat()
+            .iter()
+            .map(|path| {
+                let f = std::fs::read(path).context("failed to open key file")?;
+                let p = pem::parse(f).context("failed to parse pem file")?;
+                Ok(p.contents)
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        Keyring::new(keys).context("failed to init symmetric keys")?
     });
 
-    let encoder = Encoder::new(cipher.clone());
+    let encoder = Encoder::new(keys.clone());
     let encoder = WithMetrics(encoder, MetricParams::new(&meter, SERVICE_NAME, "encrypt"));
     let encoder = Arc::new(encoder);
 
-    let decoder = Decoder::new(cipher.clone());
+    let decoder = Decoder::new(keys.clone());
     let decoder = WithMetrics(decoder, MetricParams::new(&meter, SERVICE_NAME, "decrypt"));
     let decoder = Arc::new(decoder);
 
+    // ACME
+    let Cli {
+        acme_account_id,
+        acme_account_key_path,
+        acme_provider_url,
+        ..
+    } = cli;
+
+    if !acme_account_id.is_empty() && acme_account_id.len() != acme_provider_url.len() {
+        return Err(anyhow!(
+            "must provide an acme_account_id for every acme_provider_url"
+        ));
+    }
+
+    let mut acme_pool_accounts = cli
+        .acme_accounts_path
+        .as_ref()
+        .map(|p| PoolAccount::load(p).context("failed to load acme accounts"))
+        .transpose()?
+        .unwrap_or_default();
+
+    if let Some(acct) = acme_pool_accounts
+        .iter()
+        .find(|acct| !acme_provider_url.contains(&acct.provider_url))
+    {
+        return Err(anyhow!(
+            "acme account {} is for an unknown provider {}",
+            acct.id,
+            acct.provider_url
+        ));
+    }
+
+    let acme_credentials = cli
+        .acme_credentials_dir
+        .as_deref()
+        .map(|dir| CredentialStore::new(dir, encoder.clone(), decoder.clone()));
+
+    let acme_http_client = reqwest::Client::new();
+
+    // Smallest number of accounts with any provider, which the per-account budget is scaled by
+    let mut acme_pool_size = usize::MAX;
+
+    let mut acme_providers = vec![];
+    let mut acme_revokers = vec![];
+    for (idx, acme_provider_url) in acme_provider_url.into_iter().enumerate() {
+        let (pool, rest) = acme_pool_accounts
+            .into_iter()
+            .partition(|acct| acct.provider_url == acme_provider_url);
+        acme_pool_accounts = rest;
+
+        let mut acme_accounts: Vec<(Option<String>, Option<PathBuf>)> = pool
+            .into_iter()
+            .map(|acct: PoolAccount| (Some(acct.id), Some(acct.key_path)))
+            .collect();
+
+        // A new account is only created if none is configured
+        let (id, key_path) = (
+            acme_account_id.get(idx).cloned(),
+            acme_account_key_path.get(idx).cloned(),
+        );
+        if id.is_some() || key_path.is_some() || acme_accounts.is_empty() {
+            acme_accounts.insert(0, (id, key_path));
+        }
+
+        acme_pool_size = acme_pool_size.min(acme_accounts.len());
+
+        let mut accounts = vec![];
+        let mut revokers = vec![];
+        for (n, (id, key_path)) in acme_accounts.into_iter().enumerate() {
+            let acme_account = load_acme_account(
+                &acme_provider_url,
+                id,
+                key_path,
+                &cli.acme_contact_email,
+                acme_credentials.as_ref(),
+            )
+            .await
+            .context(format!(
+                "failed to load acme account #{n} for {acme_provider_url}"
+            ))?;
+
+            let acme_revoker =
+                AcmeRevoker::new(acme_http_client.clone(), &acme_provider_url, &acme_account)?;
+
+            let name = format!("{acme_provider_url}#{n}");
+            revokers.push((name.clone(), acme_revoker));
+            accounts.push((name, Acme::new(acme_account)));
+        }
+
+        let cooldown = Duration::from_secs(cli.acme_failover_cooldown_sec);
+
+        acme_revokers.push((
+            acme_provider_url.clone(),
+            WithAccountPool::new(revokers, cooldown),
+        ));
+        acme_providers.push((acme_provider_url, WithAccountPool::new(accounts, cooldown)));
+    }
+
+    let acme_client = WithFailover::new(
+        acme_providers,
+        cli.acme_failover_threshold,
+        Duration::from_secs(cli.acme_failover_cooldown_sec),
+    );
+
+    let acme_revoker = WithFailover::new(
+        acme_revokers,
+        cli.acme_failover_threshold,
+        Duration::from_secs(cli.acme_failover_cooldown_sec),
+    );
+    let acme_revoker = WithMetrics(
+        acme_revoker,
+        MetricParams::new(&meter, SERVICE_NAME, "acme_revoke_certificate"),
+    );
+    let acme_revoker = Arc::new(acme_revoker);
+
+    let acme_order = WithIDNA(acme_client.clone());
+    let acme_order = WithMetrics(
+        acme_order,
+        MetricParams::new(&meter, SERVICE_NAME, "acme_create_order"),
+    );
+
+    let acme_ready = WithIDNA(acme_client.clone());
+    let acme_ready = WithMetrics(
+        acme_ready,
+        MetricParams::new(&meter, SERVICE_NAME, "acme_ready_order"),
+    );
+
+    let acme_finalize = WithIDNA(acme_client.clone());
+    let acme_finalize = WithMetrics(
+        acme_finalize,
+        MetricParams::new(&meter, SERVICE_NAME, "acme_finalize_order"),
+    );
+
+    // Storage
+    let storage = match cli.storage_backend {
+        StorageBackend::Canister => Storage::Canister(cli.orchestrator_canister_id.context(
+            "an orchestrator canister id is required with the canister storage backend",
+        )?),
+        StorageBackend::Local => Storage::Local(Arc::new(LocalStore::open(&cli.storage_path)?)),
+    };
+
+    if matches!(storage, Storage::Local(_)) && cli.cache_path.is_some() {
+        return Err(anyhow!(
+            "a cache can only be used with the canister storage backend"
+        ));
+    }
+
+    // Cache
+    let cache = cli
+        .cache_path
+        .as_deref()
+        .map(Cache::open)
+        .transpose()?
+        .map(Arc::new);
+
+    // Delegation
+    let delegation = Arc::new(Delegation::new(
+        cli.delegation_domain.clone(),
+        cli.delegation_secret_path
+            .as_ref()
+            .map(|p| std::fs::read(p).context("failed to open delegation secret file"))
+            .transpose()?
+            .as_deref(),
+    ));
+
     // Registration
     let registration_checker = Checker::new(
-        cli.delegation_domain.clone(),
+        delegation.clone(),
+        cli.address_policy,
         Box::new(resolver.clone()),
         agent.clone(),
     );
@@ -268,58 +927,207 @@ async fn main() -> Result<(), Error> {
     );
     let registration_checker = Arc::new(registration_checker);
 
-    let registration_creator =
-        registration::CanisterCreator(agent.clone(), cli.orchestrator_canister_id);
+    let registration_creator: Arc<dyn Create> = match &storage {
+        Storage::Canister(id) => Arc::new(registration::CanisterCreator(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let registration_creator = WithMetrics(
         registration_creator,
         MetricParams::new(&meter, SERVICE_NAME, "create_registration"),
     );
-    let registration_creator = Arc::new(registration_creator);
+    let registration_creator: Arc<dyn Create> = match &cache {
+        Some(cache) => Arc::new(WithCache(registration_creator, cache.clone())),
+        None => Arc::new(registration_creator),
+    };
 
-    let registration_updater =
-        registration::CanisterUpdater(agent.clone(), cli.orchestrator_canister_id);
+    let registration_updater: Arc<dyn Update> = match &storage {
+        Storage::Canister(id) => Arc::new(registration::CanisterUpdater(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let registration_updater = WithMetrics(
         registration_updater,
         MetricParams::new(&meter, SERVICE_NAME, "update_registration"),
     );
-    let registration_updater = Arc::new(registration_updater);
-
-    let registration_remover =
-        registration::CanisterRemover(agent.clone(), cli.orchestrator_canister_id);
-    let registration_remover = WithMetrics(
-        registration_remover,
-        MetricParams::new(&meter, SERVICE_NAME, "remove_registration"),
-    );
-    let registration_remover = Arc::new(registration_remover);
+    let registration_updater: Arc<dyn Update> = match &cache {
+        Some(cache) => Arc::new(WithCache(registration_updater, cache.clone())),
+        None => Arc::new(registration_updater),
+    };
 
-    let registration_getter =
-        registration::CanisterGetter(agent.clone(), cli.orchestrator_canister_id);
+    let registration_getter: Arc<dyn Get> = match &storage {
+        Storage::Canister(id) => Arc::new(registration::CanisterGetter(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let registration_getter = WithMetrics(
         registration_getter,
         MetricParams::new(&meter, SERVICE_NAME, "get_registration"),
     );
-    let registration_getter = Arc::new(registration_getter);
+    let registration_getter: Arc<dyn Get> = match &cache {
+        Some(cache) => Arc::new(WithCache(registration_getter, cache.clone())),
+        None => Arc::new(registration_getter),
+    };
+
+    let registration_timestamps_getter: Arc<dyn GetTimestamps> = match &storage {
+        Storage::Canister(id) => {
+            Arc::new(registration::CanisterTimestampsGetter(agent.clone(), *id))
+        }
+        Storage::Local(store) => store.clone(),
+    };
 
-    // Verifier
-    let certificate_verifier =
-        CertificateVerifier::new(agent.clone(), cli.orchestrator_canister_id);
-    let certificate_verifier = WithMetrics(
-        certificate_verifier,
-        MetricParams::new(&meter, SERVICE_NAME, "verify_certificates"),
+    let registration_lister: Arc<dyn List> = match &storage {
+        Storage::Canister(id) => Arc::new(registration::CanisterLister(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let registration_lister = WithMetrics(
+        registration_lister,
+        MetricParams::new(&meter, SERVICE_NAME, "list_registrations"),
     );
-    let certificate_verifier = Arc::new(certificate_verifier);
+    let registration_lister = Arc::new(registration_lister);
 
-    // Certificates
-    let certificate_getter =
-        CanisterCertGetter::new(agent.clone(), cli.orchestrator_canister_id, decoder.clone());
+    // Certificates are revoked with the ACME provider when removing registrations
+    let certificate_getter: Arc<dyn GetCert> = match &storage {
+        Storage::Canister(id) => {
+            Arc::new(CanisterCertGetter::new(agent.clone(), *id, decoder.clone()))
+        }
+        Storage::Local(store) => Arc::new(LocalCertGetter(store.clone(), decoder.clone())),
+    };
     let certificate_getter = WithMetrics(
         certificate_getter,
         MetricParams::new(&meter, SERVICE_NAME, "get_certificate"),
     );
     let certificate_getter = Arc::new(certificate_getter);
 
-    let certificate_exporter = CanisterExporter::new(agent.clone(), cli.orchestrator_canister_id);
-    let certificate_exporter = WithVerify(certificate_exporter, certificate_verifier);
+    // Cloudflare, shared so that discovered zones are cached once
+    let cloudflare = {
+        let token = cli
+            .cloudflare_api_key_path
+            .as_ref()
+            .map(|p| std::fs::read_to_string(p).context("failed to open cloudflare api key file"))
+            .transpose()?;
+
+        let zone_tokens = cli
+            .cloudflare_api_tokens_path
+            .as_ref()
+            .map(|p| ZoneToken::load(p).context("failed to load cloudflare api tokens"))
+            .transpose()?
+            .unwrap_or_default();
+
+        Arc::new(Cloudflare::new(
+            &cli.cloudflare_api_url,
+            token.as_deref().map(str::trim),
+            zone_tokens,
+        )?)
+    };
+
+    let registration_remover: Arc<dyn Remove> = match &storage {
+        Storage::Canister(id) => Arc::new(registration::CanisterRemover(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let registration_remover = WithRevocation::new(
+        registration_remover,
+        certificate_getter.clone(),
+        acme_revoker.clone(),
+    );
+    let registration_remover = WithCleanup::new(
+        registration_remover,
+        registration_getter.clone(),
+        Box::new(cloudflare.clone()),
+        delegation.clone(),
+    );
+    let registration_remover = WithMetrics(
+        registration_remover,
+        MetricParams::new(&meter, SERVICE_NAME, "remove_registration"),
+    );
+    let registration_remover: Arc<dyn Remove> = match &cache {
+        Some(cache) => Arc::new(WithCache(registration_remover, cache.clone())),
+        None => Arc::new(registration_remover),
+    };
+
+    // Audit
+    let audit_log: Option<(Arc<dyn Append>, Arc<dyn audit::Query>)> = match cli.audit_backend {
+        AuditBackend::None => None,
+        AuditBackend::File => {
+            let log = Arc::new(FileAuditLog::open(&cli.audit_log_path)?);
+            Some((log.clone(), log))
+        }
+        AuditBackend::Canister => match &storage {
+            Storage::Canister(id) => {
+                let log = Arc::new(CanisterAuditLog(agent.clone(), *id));
+                Some((log.clone(), log))
+            }
+            Storage::Local(_) => {
+                return Err(anyhow!(
+                    "the canister audit backend requires the canister storage backend"
+                ))
+            }
+        },
+    };
+
+    // Mutations are recorded regardless of whether they are made through the API or by the worker
+    let (registration_creator, registration_updater, registration_remover) = match &audit_log {
+        Some((appender, _)) => {
+            let appender: Arc<dyn Append> = Arc::new(WithMetrics(
+                appender.clone(),
+                MetricParams::new(&meter, SERVICE_NAME, "record_audit_entry"),
+            ));
+
+            let creator: Arc<dyn Create> = Arc::new(WithAudit::new(
+                registration_creator,
+                registration_getter.clone(),
+                appender.clone(),
+            ));
+            let updater: Arc<dyn Update> = Arc::new(WithAudit::new(
+                registration_updater,
+                registration_getter.clone(),
+                appender.clone(),
+            ));
+            let remover: Arc<dyn Remove> = Arc::new(WithAudit::new(
+                registration_remover,
+                registration_getter.clone(),
+                appender,
+            ));
+
+            (creator, updater, remover)
+        }
+        None => (
+            registration_creator,
+            registration_updater,
+            registration_remover,
+        ),
+    };
+
+    // Issuance history
+    let issuance_history_getter: Arc<dyn GetHistory> = match &storage {
+        Storage::Canister(id) => Arc::new(CanisterHistory(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+
+    let issuance_history_recorder: Arc<dyn RecordEvent> = match &storage {
+        Storage::Canister(id) => Arc::new(CanisterHistory(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let issuance_history_recorder = WithMetrics(
+        issuance_history_recorder,
+        MetricParams::new(&meter, SERVICE_NAME, "record_issuance_event"),
+    );
+    let issuance_history_recorder = Arc::new(issuance_history_recorder);
+
+    // Certificates
+    // Only exports of the canister are certified, local ones are trusted as is
+    let certificate_exporter: Arc<dyn certificate::Export> = match &storage {
+        Storage::Canister(id) => {
+            let certificate_verifier = CertificateVerifier::new(agent.clone(), *id);
+            let certificate_verifier = WithMetrics(
+                certificate_verifier,
+                MetricParams::new(&meter, SERVICE_NAME, "verify_certificates"),
+            );
+
+            Arc::new(WithVerify(
+                CanisterExporter::new(agent.clone(), *id),
+                Arc::new(certificate_verifier),
+            ))
+        }
+        Storage::Local(store) => store.clone(),
+    };
     let certificate_exporter = WithRetries(
         certificate_exporter,
         20, // Number of retries
@@ -330,35 +1138,349 @@ async fn main() -> Result<(), Error> {
         MetricParams::new(&meter, SERVICE_NAME, "export_certificates"),
     );
     let certificate_exporter = WithPagination(
-        certificate_exporter,
+        Arc::new(certificate_exporter),
         50, // Page Size
     );
     let certificate_exporter = Arc::new(certificate_exporter);
 
-    let certificate_uploader =
-        CanisterUploader::new(agent.clone(), cli.orchestrator_canister_id, encoder);
+    let certificate_revoker: Arc<dyn certificate::Revoke> = match &storage {
+        Storage::Canister(id) => Arc::new(CanisterRevoker::new(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let certificate_revoker = WithMetrics(
+        certificate_revoker,
+        MetricParams::new(&meter, SERVICE_NAME, "revoke_certificate"),
+    );
+    let certificate_revoker = Arc::new(certificate_revoker);
+
+    let certificate_uploader: Arc<dyn certificate::Upload> = match &storage {
+        Storage::Canister(id) => {
+            Arc::new(CanisterUploader::new(agent.clone(), *id, encoder.clone()))
+        }
+        Storage::Local(store) => Arc::new(LocalUploader(store.clone(), encoder.clone())),
+    };
     let certificate_uploader = WithMetrics(
         certificate_uploader,
         MetricParams::new(&meter, SERVICE_NAME, "upload_certificate"),
     );
+    let certificate_uploader = Arc::new(certificate_uploader);
+
+    // Re-encryption
+    let (reencrypt_exporter, reencrypt_uploader): (
+        Arc<dyn certificate::Export>,
+        Arc<dyn certificate::Upload>,
+    ) = match &storage {
+        Storage::Canister(id) => (
+            Arc::new(CanisterExporter::new(agent.clone(), *id)),
+            Arc::new(CanisterUploader::new(agent.clone(), *id, encoder)),
+        ),
+        Storage::Local(store) => (
+            store.clone(),
+            Arc::new(LocalUploader(store.clone(), encoder)),
+        ),
+    };
+
+    let reencrypter = Reencrypter::new(
+        WithPagination(
+            Arc::new(WithRetries(
+                reencrypt_exporter,
+                20, // Number of retries
+            )),
+            50, // Page Size
+        ),
+        keys.clone(),
+        decoder.clone(),
+        certificate_getter.clone(),
+        Arc::new(WithMetrics(
+            reencrypt_uploader,
+            MetricParams::new(&meter, SERVICE_NAME, "reencrypt_certificate"),
+        )),
+        cli.reencrypt_checkpoint_path.clone(),
+        meter
+            .u64_counter(format!("{SERVICE_NAME}.reencrypt_progress"))
+            .with_description("Counts certificates processed by re-encryption, by status")
+            .init(),
+    );
+    let reencrypt_interval = Duration::from_secs(cli.reencrypt_interval_sec);
 
     // Work
-    let queuer = work::CanisterQueuer(agent.clone(), cli.orchestrator_canister_id);
+    let queuer: Arc<dyn Queue> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterQueuer(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let queuer = WithMetrics(queuer, MetricParams::new(&meter, SERVICE_NAME, "queue"));
-    let queuer = Arc::new(queuer);
+    let queuer: Arc<dyn Queue> = match &cache {
+        Some(cache) => Arc::new(WithCache(queuer, cache.clone())),
+        None => Arc::new(queuer),
+    };
+
+    // Changes made while the orchestrator was unreachable are pushed without going through the cache
+    let reconciler = match (&cache, &storage) {
+        (Some(cache), Storage::Canister(id)) => Some(Reconciler::new(
+            cache.clone(),
+            Arc::new(WithMetrics(
+                work::CanisterQueuer(agent.clone(), *id),
+                MetricParams::new(&meter, SERVICE_NAME, "reconcile_queue"),
+            )),
+            Arc::new(WithMetrics(
+                registration::CanisterUpdater(agent.clone(), *id),
+                MetricParams::new(&meter, SERVICE_NAME, "reconcile_update_registration"),
+            )),
+        )),
+        _ => None,
+    };
+    let cache_reconcile_interval = Duration::from_secs(cli.cache_reconcile_interval_sec);
+
+    let renewal_policy = Arc::new(RenewalPolicy::new(
+        Duration::from_secs(cli.renewal_lead_time_sec),
+        Duration::from_secs(cli.renewal_jitter_sec),
+    ));
+
+    let task_history = Arc::new(TaskHistory::new(cli.task_history_len));
+
+    // Notifies the worker loop of tasks queued by the API, so it doesn't have to wait for the next poll
+    let task_notify = Arc::new(Notify::new());
+
+    // Ownership
+    let ownership_tokens = cli
+        .ownership_secret_path
+        .as_ref()
+        .map(|p| {
+            let secret = std::fs::read(p).context("failed to open ownership secret file")?;
+            Ok::<_, Error>(Arc::new(OwnershipTokens::new(&secret)))
+        })
+        .transpose()?;
+
+    // Only new registrations need to prove ownership, renewals are checked as before
+    let creation_checker: Arc<dyn Check> = match &ownership_tokens {
+        Some(tokens) => Arc::new(WithMetrics(
+            WithOwnership::new(registration_checker.clone(), tokens.clone(), agent.clone()),
+            MetricParams::new(&meter, SERVICE_NAME, "check_ownership"),
+        )),
+        None => registration_checker.clone(),
+    };
+
+    // Acceptable-use policies, the cheaper ones running first
+    let creation_checker: Arc<dyn Check> = match cli.max_registrations_per_canister {
+        Some(limit) => Arc::new(WithRegistrationLimit::new(
+            creation_checker,
+            registration_lister.clone(),
+            limit,
+        )),
+        None => creation_checker,
+    };
+
+    let creation_checker: Arc<dyn Check> =
+        Arc::new(WithReservedTlds::new(creation_checker, &cli.reserved_tlds));
+
+    let creation_checker: Arc<dyn Check> = match &cli.blocklist_path {
+        Some(p) => Arc::new(WithBlocklist::new(
+            creation_checker,
+            Arc::new(Blocklist::load(p)?),
+        )),
+        None => creation_checker,
+    };
+
+    // Expiry monitoring
+    let monitor_exporter: Arc<dyn certificate::Export> = match &storage {
+        Storage::Canister(id) => Arc::new(CanisterExporter::new(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+
+    let expiries = Expiries::default();
+
+    meter
+        .i64_observable_gauge(format!("{SERVICE_NAME}.certificate_expiry_seconds"))
+        .with_description(
+            "Seconds until the certificate of a domain expires, as of the last expiry scan",
+        )
+        .with_callback({
+            let expiries = expiries.clone();
+
+            move |o| {
+                let now = SystemTime::now();
+
+                let secs = expiries
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(name, not_after)| {
+                        let secs = match not_after.duration_since(now) {
+                            Ok(d) => d.as_secs() as i64,
+                            Err(err) => -(err.duration().as_secs() as i64),
+                        };
+
+                        (name.clone(), secs)
+                    })
+                    .collect::<Vec<_>>();
+
+                // Without domain labels, only the certificate expiring first is reported
+                if !DOMAIN_LABELS.load(Ordering::SeqCst) {
+                    if let Some(secs) = secs.iter().map(|(_, secs)| *secs).min() {
+                        o.observe(secs, &[]);
+                    }
+                    return;
+                }
+
+                for (name, secs) in secs {
+                    o.observe(secs, &[KeyValue::new("domain", name)]);
+                }
+            }
+        })
+        .init();
+
+    let expiry_monitor = ExpiryMonitor::new(
+        WithPagination(
+            Arc::new(WithRetries(
+                monitor_exporter,
+                20, // Number of retries
+            )),
+            50, // Page Size
+        ),
+        decoder.clone(),
+        Arc::new(WithNotify(queuer.clone(), task_notify.clone())),
+        Duration::from_secs(cli.expiry_danger_window_sec),
+        expiries,
+        meter
+            .u64_counter(format!("{SERVICE_NAME}.expiry_scan"))
+            .with_description("Counts certificates processed by expiry scans, by status")
+            .init(),
+    );
+    let expiry_scan_interval = Duration::from_secs(cli.expiry_scan_interval_sec);
+
+    // Backpressure
+    let task_counter: Arc<dyn Count> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterCounter(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let task_counter = Arc::new(WithMetrics(
+        task_counter,
+        MetricParams::new(&meter, SERVICE_NAME, "count_tasks"),
+    ));
+
+    let backpressure = Arc::new(Backpressure::new(
+        task_counter,                                               // counter
+        cli.backpressure_pending_tasks,                             // threshold
+        Duration::from_secs(cli.backpressure_refresh_interval_sec), // refresh_interval
+    ));
+
+    let backpressure_args = BackpressureMiddlewareArgs {
+        backpressure: backpressure.clone(),
+        counter: meter
+            .u64_counter(format!("{SERVICE_NAME}.backpressure"))
+            .with_description(
+                "Counts registration requests accepted or rejected for too many pending tasks",
+            )
+            .init(),
+    };
 
     // API
     let create_registration_handler = api::create_handler.layer(Extension({
-        let v: (Arc<dyn Check>, Arc<dyn Create>, Arc<dyn Queue>) = (
-            registration_checker.clone(), // checker
-            registration_creator.clone(), // creator
-            queuer.clone(),               // queuer
+        let v: (
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn Create>,
+            Arc<dyn Get>,
+            Arc<dyn Queue>,
+        ) = (
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            creation_checker.clone(),             // checker
+            registration_creator.clone(),         // creator
+            registration_getter.clone(),          // getter
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    // Registration creation triggers expensive checks, so it is rate-limited
+    let create_rate_limit = RateLimitMiddlewareArgs {
+        by_ip: Arc::new(RateLimiter::new(
+            cli.create_rate_limit_per_ip,
+            per_hour(cli.create_rate_limit_per_ip),
+        )),
+        by_identity: Arc::new(RateLimiter::new(
+            cli.create_rate_limit_per_identity,
+            per_hour(cli.create_rate_limit_per_identity),
+        )),
+        ip_header: cli.rate_limit_ip_header.clone(),
+    };
+
+    let create_registration_handler = create_registration_handler.layer(
+        ServiceBuilder::new()
+            .layer(Extension(create_rate_limit.clone()))
+            .layer(middleware::from_fn(rate_limit_mw))
+            .layer(Extension(backpressure_args.clone()))
+            .layer(middleware::from_fn(backpressure_mw)),
+    );
+
+    // Batches share the rate limits of registration creation, each name counting as a single request
+    let batch_create_registration_handler = api::batch_create_handler.layer(Extension({
+        let v: (
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn Create>,
+            Arc<dyn Get>,
+            Arc<dyn Queue>,
+        ) = (
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            creation_checker.clone(),             // checker
+            registration_creator.clone(),         // creator
+            registration_getter.clone(),          // getter
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    let batch_create_registration_handler = batch_create_registration_handler.layer(
+        ServiceBuilder::new()
+            .layer(Extension(create_rate_limit.clone()))
+            .layer(middleware::from_fn(request_rate_limit_mw))
+            .layer(Extension(backpressure_args))
+            .layer(middleware::from_fn(backpressure_mw)),
+    );
+
+    // Validation runs the same checks as registration creation, so it shares its rate limits
+    let validate_registration_handler = api::validate_handler.layer(Extension({
+        let v: (Arc<dyn Normalize>, Arc<dyn Check>, Arc<dyn CheckCaa>) = (
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            creation_checker.clone(),             // checker
+            Arc::new(WithMetrics(
+                CaaChecker::new(cli.acme_caa_identities.clone(), Box::new(resolver.clone())),
+                MetricParams::new(&meter, SERVICE_NAME, "validate_caa"),
+            )), // caa checker
+        );
+        v
+    }));
+
+    let validate_registration_handler = validate_registration_handler.layer(
+        ServiceBuilder::new()
+            .layer(Extension(create_rate_limit.clone()))
+            .layer(middleware::from_fn(rate_limit_mw)),
+    );
+
+    let ownership_token_handler = ownership_tokens.map(|tokens| {
+        api::ownership_token_handler.layer(Extension({
+            let v: (Arc<dyn Normalize>, Arc<OwnershipTokens>) = (
+                Arc::new(Normalizer(cli.idn_policy)), // normalizer
+                tokens,                               // tokens
+            );
+            v
+        }))
+    });
+
+    let delegation_handler = api::delegation_handler.layer(Extension({
+        let v: (Arc<dyn Normalize>, Arc<Delegation>) = (
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            delegation.clone(),                   // delegation
         );
         v
     }));
 
     let get_registration_handler = api::get_handler.layer(Extension({
-        let v: Arc<dyn Get> = registration_getter.clone();
+        let v: (Arc<dyn Get>, Arc<dyn GetTimestamps>) = (
+            registration_getter.clone(),            // getter
+            registration_timestamps_getter.clone(), // timestamps getter
+        );
         v
     }));
 
@@ -380,17 +1502,216 @@ async fn main() -> Result<(), Error> {
         v
     }));
 
-    let export_handler = api::export_handler.layer(Extension({
-        let v: Arc<dyn Export> = certificate_exporter;
+    let export_handler = api::export_handler.layer(Extension(certificate_exporter));
+
+    let list_registrations_handler = api::list_handler.layer(Extension({
+        let v: Arc<dyn List> = registration_lister.clone();
         v
     }));
 
-    let api_router = Router::new()
+    let retry_registration_handler = api::retry_handler.layer(Extension({
+        let v: (Arc<dyn Get>, Arc<dyn Update>, Arc<dyn Queue>) = (
+            registration_getter.clone(),                               // getter
+            registration_updater.clone(),                              // updater
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    let renew_registration_handler = api::renew_handler.layer(Extension({
+        let v: (Arc<dyn Get>, Arc<dyn Queue>) = (
+            registration_getter.clone(),                               // getter
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    let pause_registration_handler = api::pause_handler.layer(Extension({
+        let v: (Arc<dyn Get>, Arc<dyn Update>) = (
+            registration_getter.clone(),  // getter
+            registration_updater.clone(), // updater
+        );
+        v
+    }));
+
+    let resume_registration_handler = api::resume_handler.layer(Extension({
+        let v: (
+            Arc<dyn Get>,
+            Arc<dyn GetCert>,
+            Arc<RenewalPolicy>,
+            Arc<dyn Update>,
+            Arc<dyn Queue>,
+        ) = (
+            registration_getter.clone(),                               // getter
+            certificate_getter.clone(),                                // cert getter
+            renewal_policy.clone(),                                    // renewal policy
+            registration_updater.clone(),                              // updater
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    let check_registration_handler = api::check_handler.layer(Extension({
+        let v: (
+            Arc<dyn Get>,
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn CheckCaa>,
+        ) = (
+            registration_getter.clone(),          // getter
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            registration_checker.clone(),         // checker
+            Arc::new(WithMetrics(
+                CaaChecker::new(cli.acme_caa_identities.clone(), Box::new(resolver.clone())),
+                MetricParams::new(&meter, SERVICE_NAME, "admin_check_caa"),
+            )), // caa checker
+        );
+        v
+    }));
+
+    let task_history_handler = api::history_handler.layer(Extension(task_history.clone()));
+
+    let issuance_history_handler =
+        api::issuance_history_handler.layer(Extension(issuance_history_getter));
+
+    let audit_handler = audit_log.map(|(_, querier)| api::audit_handler.layer(Extension(querier)));
+
+    let revoke_certificate_handler = api::revoke_handler.layer(Extension({
+        let v: (
+            Arc<dyn Get>,
+            Arc<dyn GetCert>,
+            Arc<dyn acme::Revoke>,
+            Arc<dyn certificate::Revoke>,
+            Arc<dyn Update>,
+            Arc<dyn Queue>,
+        ) = (
+            registration_getter.clone(),                               // getter
+            certificate_getter.clone(),                                // cert getter
+            acme_revoker.clone(),                                      // acme revoker
+            certificate_revoker.clone(),                               // canister revoker
+            registration_updater.clone(),                              // updater
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    let import_certificate_handler = api::import_handler.layer(Extension({
+        let v: (
+            Arc<dyn Normalize>,
+            Arc<dyn Check>,
+            Arc<dyn Create>,
+            Arc<dyn certificate::Upload>,
+            Arc<RenewalPolicy>,
+            Arc<dyn Update>,
+            Arc<dyn Remove>,
+            Arc<dyn Queue>,
+        ) = (
+            Arc::new(Normalizer(cli.idn_policy)), // normalizer
+            registration_checker.clone(),         // checker
+            registration_creator.clone(),         // creator
+            certificate_uploader.clone(),         // uploader
+            renewal_policy.clone(),               // renewal policy
+            registration_updater.clone(),         // updater
+            registration_remover.clone(),         // remover
+            Arc::new(WithNotify(queuer.clone(), task_notify.clone())), // queuer
+        );
+        v
+    }));
+
+    // API (Authentication)
+    let api_tokens = match &cli.api_tokens_path {
+        Some(p) => Some(Arc::new(
+            Tokens::load(p).context("failed to load api tokens")?,
+        )),
+        None => {
+            warn!("no api tokens provided, api authentication is disabled");
+            None
+        }
+    };
+
+    let auth_layer = |scope| {
+        ServiceBuilder::new()
+            .layer(Extension(AuthMiddlewareArgs {
+                tokens: api_tokens.clone(),
+                public_scopes: cli.api_public_scopes.clone(),
+                scope,
+            }))
+            .layer(middleware::from_fn(auth_mw))
+            .layer(middleware::from_fn(audit_mw))
+    };
+
+    let registrations_router = Router::new()
         .route("/registrations", post(create_registration_handler))
+        .route(
+            "/registrations/batch",
+            post(batch_create_registration_handler),
+        )
+        .route(
+            "/registrations/validate",
+            post(validate_registration_handler),
+        )
+        .route("/registrations/delegation", post(delegation_handler))
         .route("/registrations/:id", get(get_registration_handler))
         .route("/registrations/:id", put(update_registration_handler))
         .route("/registrations/:id", delete(remove_registration_handler))
-        .route("/certificates", get(export_handler));
+        .route("/registrations/:id/history", get(issuance_history_handler));
+
+    let registrations_router = match ownership_token_handler {
+        Some(h) => registrations_router.route("/registrations/ownership-token", post(h)),
+        None => registrations_router,
+    };
+
+    let registrations_router = registrations_router.route_layer(auth_layer(Scope::Create));
+
+    let certificates_router = Router::new()
+        .route("/certificates", get(export_handler))
+        .route_layer(auth_layer(Scope::Export));
+
+    // Operator actions, e.g. listing and retrying parked registrations or revoking certificates
+    let admin_router = Router::new()
+        .route("/admin/registrations", get(list_registrations_handler))
+        .route(
+            "/admin/registrations/:id/retry",
+            post(retry_registration_handler),
+        )
+        .route(
+            "/admin/registrations/:id/revoke",
+            post(revoke_certificate_handler),
+        )
+        .route(
+            "/admin/registrations/:id/renew",
+            post(renew_registration_handler),
+        )
+        .route(
+            "/admin/registrations/:id/pause",
+            post(pause_registration_handler),
+        )
+        .route(
+            "/admin/registrations/:id/resume",
+            post(resume_registration_handler),
+        )
+        .route(
+            "/admin/registrations/:id/check",
+            post(check_registration_handler),
+        )
+        .route(
+            "/admin/registrations/import",
+            post(import_certificate_handler),
+        )
+        .route("/admin/registrations/:id/tasks", get(task_history_handler));
+
+    let admin_router = match audit_handler {
+        Some(h) => admin_router.route("/admin/audit", get(h)),
+        None => admin_router,
+    };
+
+    let admin_router = admin_router.route_layer(auth_layer(Scope::Admin));
+
+    let api_router = Router::new()
+        .merge(registrations_router)
+        .merge(certificates_router)
+        .merge(admin_router)
+        .layer(DefaultBodyLimit::max(cli.api_max_body_size));
 
     // API (Instrument)
     let api_router = api_router.layer(
@@ -408,112 +1729,232 @@ async fn main() -> Result<(), Error> {
             .layer(middleware::from_fn(metrics_mw)),
     );
 
-    // ACME
-    let Cli {
-        acme_account_id,
-        acme_account_key_path,
-        acme_provider_url,
-        ..
-    } = cli;
-
-    let acme_account = match (acme_account_id, acme_account_key_path) {
-        // Re-use existing account
-        (Some(id), Some(path)) => {
-            let key =
-                std::fs::read_to_string(path).context("failed to open acme account key file")?;
-            let acme_credentials: AccountCredentials = serde_json::from_str(&format!(
-                r#"{{
-                    "id": "{acme_provider_url}/acme/acct/{id}",
-                    "key_pkcs8": "{key}",
-                    "urls": {{
-                        "newNonce": "{acme_provider_url}/acme/new-nonce",
-                        "newAccount": "{acme_provider_url}/acme/new-acct",
-                        "newOrder": "{acme_provider_url}/acme/new-order"
-                    }}
-                }}"#,
-            ))?;
-
-            Account::from_credentials(acme_credentials)
-                .context("failed to create acme account from credentials")
-        }
-        (Some(_), None) | (None, Some(_)) => Err(anyhow!(
-            "must provide both acme_account_id and acme_account_key"
-        )),
-
-        // Create new ACME cccount
-        _ => Account::create(
-            &NewAccount {
-                contact: &[],
-                terms_of_service_agreed: true,
-                only_return_existing: false,
-            },
-            &acme_provider_url,
-            None,
-        )
-        .await
-        .context("failed to create acme account"),
-    }?;
-
-    let acme_client = Acme::new(acme_account);
-
-    let acme_order = WithIDNA(acme_client.clone());
-    let acme_order = WithMetrics(
-        acme_order,
-        MetricParams::new(&meter, SERVICE_NAME, "acme_create_order"),
-    );
-
-    let acme_ready = WithIDNA(acme_client.clone());
-    let acme_ready = WithMetrics(
-        acme_ready,
-        MetricParams::new(&meter, SERVICE_NAME, "acme_ready_order"),
-    );
-
-    let acme_finalize = WithIDNA(acme_client.clone());
-    let acme_finalize = WithMetrics(
-        acme_finalize,
-        MetricParams::new(&meter, SERVICE_NAME, "acme_finalize_order"),
-    );
-
     // Cloudflare
-    let dns_creator = {
-        let cloudflare_api_key = std::fs::read_to_string(cli.cloudflare_api_key_path.clone())
-            .context("failed to open cloudflare api key file")?;
-        Cloudflare::new(&cli.cloudflare_api_url, &cloudflare_api_key)?
-    };
-    let dns_creator = WithMetrics(
-        dns_creator,
+    let dns_creator = Arc::new(WithMetrics(
+        cloudflare.clone(),
         MetricParams::new(&meter, SERVICE_NAME, "dns_create"),
-    );
+    ));
 
-    let dns_deleter = {
-        let cloudflare_api_key = std::fs::read_to_string(cli.cloudflare_api_key_path)?;
-        Cloudflare::new(&cli.cloudflare_api_url, &cloudflare_api_key)?
-    };
-    let dns_deleter = WithMetrics(
-        dns_deleter,
+    let dns_deleter = Arc::new(WithMetrics(
+        cloudflare,
         MetricParams::new(&meter, SERVICE_NAME, "dns_delete"),
-    );
+    ));
 
     // Work
-    let peeker = work::CanisterPeeker(agent.clone(), cli.orchestrator_canister_id);
+    let peeker: Arc<dyn Peek> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterPeeker(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let peeker = WithMetrics(peeker, MetricParams::new(&meter, SERVICE_NAME, "peek"));
+    let peeker: Box<dyn Peek> = match &cache {
+        Some(cache) => Box::new(WithCache(peeker, cache.clone())),
+        None => Box::new(peeker),
+    };
 
-    let dispenser = work::CanisterDispenser(agent.clone(), cli.orchestrator_canister_id);
+    let dispenser: Arc<dyn Dispense> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterDispenser(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
     let dispenser = WithMetrics(
         dispenser,
         MetricParams::new(&meter, SERVICE_NAME, "dispense"),
     );
+    let dispenser: Box<dyn Dispense> = match &cache {
+        Some(cache) => Box::new(WithCache(dispenser, cache.clone())),
+        None => Box::new(dispenser),
+    };
+
+    let renewer: Arc<dyn Renew> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterRenewer(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let renewer: Arc<dyn Renew> = Arc::new(WithMetrics(
+        renewer,
+        MetricParams::new(&meter, SERVICE_NAME, "renew_lease"),
+    ));
+
+    let releaser: Arc<dyn Release> = match &storage {
+        Storage::Canister(id) => Arc::new(work::CanisterReleaser(agent.clone(), *id)),
+        Storage::Local(store) => store.clone(),
+    };
+    let releaser: Arc<dyn Release> = Arc::new(WithMetrics(
+        releaser,
+        MetricParams::new(&meter, SERVICE_NAME, "release_lease"),
+    ));
+
+    let caa_checker = CaaChecker::new(cli.acme_caa_identities, Box::new(resolver));
+    let caa_checker = Arc::new(WithMetrics(
+        caa_checker,
+        MetricParams::new(&meter, SERVICE_NAME, "check_caa"),
+    ));
+
+    let propagation_required = cli
+        .dns_propagation_required
+        .unwrap_or(propagation_name_servers.len());
+
+    if propagation_required > propagation_name_servers.len() {
+        return Err(anyhow!(
+            "{propagation_required} name servers required to agree, but only {} available",
+            propagation_name_servers.len()
+        ));
+    }
+
+    let propagation_checker = PropagationChecker::new(
+        delegation.clone(),
+        propagation_required,
+        propagation_name_servers,
+    );
+    let propagation_checker = Arc::new(WithMetrics(
+        propagation_checker,
+        MetricParams::new(&meter, SERVICE_NAME, "check_propagation"),
+    ));
+
+    let certificate_inspector = Inspector::new(
+        match &cli.issued_trusted_roots_path {
+            Some(path) => load_roots(path)?,
+            None => vec![],
+        },
+        cli.issued_min_scts,
+    );
+
+    let phase_timeouts = PhaseTimeouts::new(
+        Duration::from_secs(cli.phase_timeout_sec),
+        cli.phase_timeout,
+    );
 
     let processor = work::Processor::new(
-        cli.delegation_domain,
+        delegation.clone(),
+        cli.key_type,
+        phase_timeouts.clone(),
         registration_checker.clone(),
-        Box::new(resolver),
+        Box::new(propagation_checker.clone()),
+        Box::new(caa_checker.clone()),
         Box::new(acme_order),
         Box::new(acme_ready),
         Box::new(acme_finalize),
-        Box::new(dns_creator),
-        Box::new(dns_deleter),
-        Box::new(certificate_uploader),
+        Box::new(dns_creator.clone()),
+        Box::new(dns_deleter.clone()),
+        Box::new(certificate_inspector),
+        Box::new(certificate_uploader.clone()),
+        meter
+            .f64_histogram(format!("{SERVICE_NAME}.process_phase.duration_sec"))
+            .with_description("Duration of the phases of processing a task")
+            .init(),
+    );
+
+    // Canary, issuing through the same pipeline as registrations, except for the ACME provider and storage
+    let canary = match cli.canary_domain {
+        Some(name) => {
+            let acme_client = Acme::new(
+                load_acme_account(
+                    &cli.canary_acme_provider_url,
+                    None,
+                    None,
+                    &cli.acme_contact_email,
+                    acme_credentials.as_ref(),
+                )
+                .await
+                .context("failed to create canary acme account")?,
+            );
+
+            let processor = work::Processor::new(
+                delegation.clone(),
+                cli.key_type,
+                phase_timeouts,
+                registration_checker.clone(),
+                Box::new(propagation_checker),
+                Box::new(caa_checker),
+                Box::new(WithIDNA(acme_client.clone())),
+                Box::new(WithIDNA(acme_client.clone())),
+                Box::new(WithIDNA(acme_client)),
+                Box::new(dns_creator),
+                Box::new(dns_deleter),
+                Box::new(Inspector::new(vec![], 0)), // staging certificates build to untrusted roots
+                Box::new(Discard),
+                meter
+                    .f64_histogram(format!("{SERVICE_NAME}.canary_phase.duration_sec"))
+                    .with_description("Duration of the phases of canary issuances")
+                    .init(),
+            );
+
+            Some(Canary::new(
+                Box::new(processor),
+                name,
+                cli.canary_canister_id,
+                Duration::from_secs(10), // poll interval
+                Duration::from_secs(cli.canary_timeout_sec),
+                meter
+                    .u64_counter(format!("{SERVICE_NAME}.canary"))
+                    .with_description("Counts canary issuances, by status")
+                    .init(),
+                meter
+                    .f64_histogram(format!("{SERVICE_NAME}.canary.duration_sec"))
+                    .with_description("Duration of canary issuances")
+                    .init(),
+            ))
+        }
+        None => None,
+    };
+    let canary_interval = Duration::from_secs(cli.canary_interval_sec);
+
+    // S3 export, for consumers that cannot reach the issuer
+    let s3_exporter = match &cli.s3_export_endpoint {
+        Some(endpoint) => {
+            let exporter: Arc<dyn certificate::Export> = match &storage {
+                Storage::Canister(id) => Arc::new(CanisterExporter::new(agent.clone(), *id)),
+                Storage::Local(store) => store.clone(),
+            };
+
+            let bucket = S3Bucket::new(
+                reqwest::Client::new(),
+                endpoint,
+                &cli.s3_export_bucket,
+                &cli.s3_export_region,
+                Credentials::load(&cli.s3_export_credentials_path)?,
+            )?;
+
+            Some(S3Exporter::new(
+                WithPagination(
+                    Arc::new(WithRetries(
+                        exporter, 20, // Number of retries
+                    )),
+                    50, // Page Size
+                ),
+                Arc::new(bucket),
+                cli.s3_export_prefix.clone(),
+                meter
+                    .u64_counter(format!("{SERVICE_NAME}.s3_export"))
+                    .with_description("Counts certificate packages pushed to s3, by status")
+                    .init(),
+            ))
+        }
+        None => None,
+    };
+    let s3_export_interval = Duration::from_secs(cli.s3_export_interval_sec);
+    let rate_budget = {
+        let limits = Limits::default();
+
+        RateBudget::new(Limits {
+            per_domain: Limit {
+                max: cli.acme_budget_per_domain,
+                ..limits.per_domain
+            },
+            per_account: Limit {
+                max: cli.acme_budget_per_account.saturating_mul(acme_pool_size),
+                ..limits.per_account
+            },
+            duplicate: Limit {
+                max: cli.acme_budget_duplicate,
+                ..limits.duplicate
+            },
+        })
+    };
+
+    let processor = WithBudget::new(processor, Arc::new(rate_budget));
+    let processor = WithHistory::new(
+        processor,
+        issuance_history_recorder,
+        certificate_getter.clone(),
     );
     let processor = WithMetrics(
         processor,
@@ -523,7 +1964,51 @@ async fn main() -> Result<(), Error> {
     let processor = WithDetectImportance::new(processor, cli.important_domains);
     let processor = Arc::new(processor);
 
-    let sem = Arc::new(Semaphore::new(10));
+    let retry_policy = Arc::new(RetryPolicy::new(cli.retry_backoff));
+
+    // Outcome of processed tasks, e.g. whether they were retried or parked
+    let task_outcomes = meter
+        .u64_counter(format!("{SERVICE_NAME}.task_outcome"))
+        .with_description("Counts the outcomes of processed tasks")
+        .init();
+
+    let concurrency = Arc::new(Concurrency::new(cli.task_concurrency));
+
+    // Tasks being processed, so they can be re-queued if they don't complete before shutting down
+    let in_flight: Arc<Mutex<HashMap<Id, (AbortHandle, Option<Lease>, Priority)>>> =
+        Arc::new(Mutex::new(HashMap::new()));
+
+    let shutdown = CancellationToken::new();
+    let shutdown_timeout = Duration::from_secs(cli.shutdown_timeout_sec);
+
+    let peek_sleep = Duration::from_secs(cli.peek_sleep_sec);
+    let peek_error_sleep = Duration::from_secs(cli.peek_error_sleep_sec);
+
+    // TLS
+    let api_tls = match TlsPaths::from_args(
+        "api",                         // name
+        cli.api_tls_cert_path.clone(), // cert
+        cli.api_tls_key_path.clone(),  // key
+    )? {
+        Some(paths) => Some(Arc::new(TlsReloader::new("api", paths).await?)),
+        None => None,
+    };
+
+    let metrics_tls = match TlsPaths::from_args(
+        "metrics",                         // name
+        cli.metrics_tls_cert_path.clone(), // cert
+        cli.metrics_tls_key_path.clone(),  // key
+    )? {
+        Some(paths) => Some(Arc::new(TlsReloader::new("metrics", paths).await?)),
+        None => None,
+    };
+
+    let tls_reloaders: Vec<Arc<TlsReloader>> = [api_tls.clone(), metrics_tls.clone()]
+        .into_iter()
+        .flatten()
+        .collect();
+
+    let tls_reload_interval = Duration::from_secs(cli.tls_reload_interval_sec);
 
     // Service
     info!(
@@ -532,98 +2017,566 @@ async fn main() -> Result<(), Error> {
     );
 
     let _ = tokio::try_join!(
-        task::spawn(async move {
-            loop {
-                let _permit = sem.clone().acquire_owned().await.unwrap();
-
-                let processor = processor.clone();
-                let queuer = queuer.clone();
-                let registration_updater = registration_updater.clone();
-
-                // First check with a query call if there's anything to dispense
-                if let Err(err) = peeker.peek().await {
-                    match err {
-                        PeekError::NoTasksAvailable => {
-                            sleep(Duration::from_secs(cli.peek_sleep_sec)).await;
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                shutdown_signal().await?;
+                info!("received shutdown signal");
+
+                shutdown.cancel();
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+            let concurrency = concurrency.clone();
+            let renewal_policy = renewal_policy.clone();
+
+            async move {
+                let mut sighup = signal(SignalKind::hangup()).context("failed to listen for SIGHUP")?;
+
+                loop {
+                    tokio::select! {
+                        _ = sighup.recv() => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+
+                    let cli = match config::args(&args)
+                        .and_then(|args| Cli::try_parse_from(args).map_err(Error::from))
+                    {
+                        Ok(cli) => cli,
+                        Err(err) => {
+                            warn!(error = ?err, "failed to reload configuration");
+                            continue;
+                        }
+                    };
+
+                    // Only settings that are safe to change at runtime are applied, others require a restart
+                    concurrency.set_limit(cli.task_concurrency);
+
+                    create_rate_limit.by_ip.set_limit(
+                        cli.create_rate_limit_per_ip,
+                        per_hour(cli.create_rate_limit_per_ip),
+                    );
+                    create_rate_limit.by_identity.set_limit(
+                        cli.create_rate_limit_per_identity,
+                        per_hour(cli.create_rate_limit_per_identity),
+                    );
+
+                    renewal_policy.set_window(
+                        Duration::from_secs(cli.renewal_lead_time_sec),
+                        Duration::from_secs(cli.renewal_jitter_sec),
+                    );
+
+                    if let Err(err) = log_reload.reload(cli.log_level) {
+                        warn!(error = ?err, "failed to reload log level");
+                    }
+
+                    info!(
+                        task_concurrency = cli.task_concurrency,
+                        log_level = %cli.log_level,
+                        "reloaded configuration"
+                    );
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                loop {
+                    // Stop dispensing new tasks once shutting down
+                    let _permit = tokio::select! {
+                        biased;
+                        _ = shutdown.cancelled() => break,
+                        permit = concurrency.acquire() => permit.unwrap(),
+                    };
+
+                    let processor = processor.clone();
+                    let queuer = queuer.clone();
+                    let registration_updater = registration_updater.clone();
+                    let certificate_getter = certificate_getter.clone();
+                    let renewal_policy = renewal_policy.clone();
+                    let retry_policy = retry_policy.clone();
+                    let task_outcomes = task_outcomes.clone();
+                    let task_history = task_history.clone();
+                    let renewer = renewer.clone();
+                    let releaser = releaser.clone();
+
+                    // First check with a query call if there's anything to dispense
+                    if let Err(err) = peeker.peek().await {
+                        match err {
+                            PeekError::NoTasksAvailable => {
+                                idle(&task_notify, &shutdown, peek_sleep).await;
+                                continue;
+                            }
+                            PeekError::UnexpectedError(_) => {
+                                sleep(peek_error_sleep).await;
+                                continue;
+                            }
+                        }
+                    };
+
+                    let (id, task) = match dispenser.dispense().await {
+                        Ok((id, task)) => {
+                            backpressure.dispensed();
+                            (id, task)
+                        }
+                        Err(DispenseError::NoTasksAvailable) => {
+                            idle(&task_notify, &shutdown, peek_sleep).await;
                             continue;
                         }
-                        PeekError::UnexpectedError(_) => {
-                            sleep(Duration::from_secs(10)).await;
+                        Err(DispenseError::UnexpectedError(_)) => {
+                            sleep(peek_error_sleep).await;
                             continue;
                         }
+                    };
+
+                    // Tasks of parked registrations are left to operators, e.g. when paused
+                    if matches!(task.action, Action::Parked) {
+                        info!(%id, "skipping task of parked registration");
+
+                        if let Some(lease) = &task.lease {
+                            if let Err(err) = releaser.release(&id, lease).await {
+                                warn!(%id, error = ?err, "failed to release lease");
+                            }
+                        }
+
+                        continue;
+                    }
+
+                    // Hold the lock while spawning, so the task cannot deregister itself before registering
+                    let mut tasks = in_flight.lock().unwrap();
+                    let key = id.clone();
+                    let lease = task.lease.clone();
+                    let priority = task.priority;
+                    let in_flight = in_flight.clone();
+
+                    let handle = task::spawn(async move {
+                        let _permit = _permit;
+                        let _guard = InFlightGuard(&in_flight, id.clone());
+
+                        // Mutations made while processing are attributed to the task
+                        let ctx = AuditContext {
+                            actor: ISSUER_ACTOR.into(),
+                            cause: format!("task {}", task.action),
+                        };
+
+                        let work = async {
+                            let started_at = SystemTime::now();
+                            let record = |outcome, error: Option<String>| {
+                                task_history.record(
+                                    &id,
+                                    TaskRecord {
+                                        action: task.action.clone(),
+                                        started_at: started_at
+                                            .duration_since(UNIX_EPOCH)
+                                            .map_or(0, |t| t.as_secs()),
+                                        duration_ms: started_at
+                                            .elapsed()
+                                            .map_or(0, |d| d.as_millis() as u64),
+                                        outcome,
+                                        error,
+                                    },
+                                )
+                            };
+
+                            match processor.process(&id, &task).await {
+                                Ok(()) => {
+                                    retry_policy.on_success(&id);
+                                    task_outcomes.add(1, &[KeyValue::new("outcome", "completed")]);
+                                    record("completed", None);
+
+                                    let now = SystemTime::now();
+
+                                    // Renew relative to the expiry of the issued certificate
+                                    let t = match certificate_getter
+                                        .get_cert(&id)
+                                        .await
+                                        .map_err(Error::from)
+                                        .and_then(|pair| expiry(&pair.1))
+                                    {
+                                        Ok(not_after) => renewal_policy.renewal_time(not_after, now),
+                                        Err(err) => {
+                                            warn!(%id, error = ?err, "failed to determine certificate expiry");
+                                            now + Duration::from_secs(60 * 24 * 3600) // 60 days
+                                        }
+                                    };
+                                    let t = t.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+                                    // Schedule renewal, which is only urgent once the certificate is about to expire
+                                    queuer
+                                        .queue(&id, t, Priority::Normal)
+                                        .await
+                                        .context("failed to queue task {id}")?;
+
+                                    registration_updater
+                                        .update(&id, &UpdateType::State(State::Available))
+                                        .await
+                                        .context("failed to update registration {id}")?;
+                                }
+                                Err(err) => {
+                                    let d = match retry_policy.on_error(&id, &err) {
+                                        RetryDecision::Retry(d) => {
+                                            task_outcomes.add(1, &[KeyValue::new("outcome", "retried")]);
+                                            record("retried", Some(err.to_string()));
+                                            d
+                                        }
+
+                                        // Park the task, leaving it to an operator to retry
+                                        RetryDecision::Park { attempts } => {
+                                            warn!(%id, attempts, error = ?err, "parking task after repeated failures");
+                                            record("parked", Some(err.to_string()));
+
+                                            registration_updater
+                                                .update(
+                                                    &id,
+                                                    &UpdateType::State(State::Parked(
+                                                        Failure {
+                                                            message: format!(
+                                                                "parked after {attempts} attempts: {err}"
+                                                            ),
+                                                            ..Failure::classify(&err)
+                                                        }
+                                                        .to_string(),
+                                                    )),
+                                                )
+                                                .await
+                                                .context("failed to update registration {id}")?;
+
+                                            task_outcomes.add(1, &[KeyValue::new("outcome", "parked")]);
+                                            return Ok(());
+                                        }
+                                    };
+
+                                    let t = SystemTime::now().duration_since(UNIX_EPOCH)? + d;
+                                    let t = t.as_nanos() as u64;
+
+                                    // Schedule retry
+                                    queuer
+                                        .queue(&id, t, task.priority)
+                                        .await
+                                        .context("failed to queue task {id}")?;
+
+                                    registration_updater
+                                        .update(&id, &UpdateType::State(err.into()))
+                                        .await
+                                        .context("failed to update registration {id}")?;
+                                }
+                            }
+
+                            Ok::<_, Error>(())
+                        };
+                        let work = with_context(ctx, work);
+
+                        let lease = match task.lease.clone() {
+                            Some(lease) => lease,
+                            None => return work.await,
+                        };
+
+                        // Give up on the task once its lease is lost, as another issuer may be processing it by now
+                        let out = tokio::select! {
+                            out = work => out,
+                            _ = hold_lease(renewer.as_ref(), &id, lease.clone()) => {
+                                warn!(%id, "lost lease of task, abandoning it");
+                                task_outcomes.add(1, &[KeyValue::new("outcome", "lease-lost")]);
+                                return Ok(());
+                            }
+                        };
+
+                        // Let the re-queued task be dispensed right away, rather than once the lease expires
+                        if let Err(err) = releaser.release(&id, &lease).await {
+                            warn!(%id, error = ?err, "failed to release lease");
+                        }
+
+                        out
+                    });
+
+                    tasks.insert(key, (handle.abort_handle(), lease, priority));
+                }
+
+                // Wait for in-flight tasks to complete
+                let drained = timeout(shutdown_timeout, concurrency.drain()).await;
+
+                if drained.is_err() {
+                    let tasks: Vec<(Id, (AbortHandle, Option<Lease>, Priority))> =
+                        in_flight.lock().unwrap().drain().collect();
+
+                    let t = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+                    // Re-queue unfinished tasks, so they are picked up again right away
+                    for (id, (handle, lease, priority)) in tasks {
+                        handle.abort();
+
+                        warn!(%id, "re-queueing unfinished task");
+                        task_outcomes.add(1, &[KeyValue::new("outcome", "requeued")]);
+                        if let Err(err) = queuer.queue(&id, t, priority).await {
+                            warn!(%id, error = ?err, "failed to re-queue unfinished task");
+                        }
+
+                        if let Some(lease) = lease {
+                            if let Err(err) = releaser.release(&id, &lease).await {
+                                warn!(%id, error = ?err, "failed to release lease");
+                            }
+                        }
+                    }
+                }
+
+                info!("worker stopped");
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                loop {
+                    tokio::select! {
+                        out = reencrypter.run() => {
+                            if let Err(err) = out {
+                                warn!(error = ?err, "failed to re-encrypt certificates");
+                            }
+                        }
+                        _ = shutdown.cancelled() => break,
+                    }
+
+                    tokio::select! {
+                        _ = sleep(reencrypt_interval) => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                loop {
+                    tokio::select! {
+                        out = expiry_monitor.run() => {
+                            if let Err(err) = out {
+                                warn!(error = ?err, "failed to scan certificate expiries");
+                            }
+                        }
+                        _ = shutdown.cancelled() => break,
+                    }
+
+                    tokio::select! {
+                        _ = sleep(expiry_scan_interval) => {}
+                        _ = shutdown.cancelled() => break,
                     }
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                let reconciler = match reconciler {
+                    Some(reconciler) => reconciler,
+                    None => return Ok(()),
                 };
 
-                let (id, task) = match dispenser.dispense().await {
-                    Ok((id, task)) => (id, task),
-                    Err(DispenseError::NoTasksAvailable) => {
-                        sleep(Duration::from_secs(cli.peek_sleep_sec)).await;
-                        continue;
+                loop {
+                    tokio::select! {
+                        out = reconciler.reconcile() => {
+                            if let Err(err) = out {
+                                warn!(error = ?err, "failed to reconcile cache");
+                            }
+                        }
+                        _ = shutdown.cancelled() => break,
                     }
-                    Err(DispenseError::UnexpectedError(_)) => {
-                        sleep(Duration::from_secs(10)).await;
-                        continue;
+
+                    tokio::select! {
+                        _ = sleep(cache_reconcile_interval) => {}
+                        _ = shutdown.cancelled() => break,
                     }
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                let canary = match canary {
+                    Some(canary) => canary,
+                    None => return Ok(()),
                 };
 
-                task::spawn(async move {
-                    let _permit = _permit;
-
-                    match processor.process(&id, &task).await {
-                        Ok(()) => {
-                            let d: Duration = Duration::from_secs(60 * 24 * 3600); // 60 days
-                            let t = SystemTime::now().duration_since(UNIX_EPOCH)? + d;
-                            let t = t.as_nanos() as u64;
-
-                            // Schedule renewal
-                            queuer
-                                .queue(&id, t)
-                                .await
-                                .context("failed to queue task {id}")?;
-
-                            registration_updater
-                                .update(&id, &UpdateType::State(State::Available))
-                                .await
-                                .context("failed to update registration {id}")?;
+                loop {
+                    tokio::select! {
+                        out = canary.run() => {
+                            if let Err(err) = out {
+                                warn!(error = ?err, "canary issuance failed");
+                            }
                         }
-                        Err(err) => {
-                            let d: Duration = (&err).into();
-                            let t = SystemTime::now().duration_since(UNIX_EPOCH)? + d;
-                            let t = t.as_nanos() as u64;
-
-                            // Schedule retry
-                            queuer
-                                .queue(&id, t)
-                                .await
-                                .context("failed to queue task {id}")?;
-
-                            registration_updater
-                                .update(&id, &UpdateType::State(err.into()))
-                                .await
-                                .context("failed to update registration {id}")?;
+                        _ = shutdown.cancelled() => break,
+                    }
+
+                    tokio::select! {
+                        _ = sleep(canary_interval) => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                let s3_exporter = match s3_exporter {
+                    Some(s3_exporter) => s3_exporter,
+                    None => return Ok(()),
+                };
+
+                loop {
+                    tokio::select! {
+                        out = s3_exporter.run() => {
+                            if let Err(err) = out {
+                                warn!(error = ?err, "failed to push certificates to s3");
+                            }
                         }
+                        _ = shutdown.cancelled() => break,
                     }
 
-                    Ok::<_, Error>(())
-                });
+                    tokio::select! {
+                        _ = sleep(s3_export_interval) => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+                }
+
+                Ok::<_, Error>(())
             }
         }),
-        task::spawn(
-            Server::bind(&cli.api_addr)
-                .serve(api_router.into_make_service())
-                .map_err(|err| anyhow!("server failed: {:?}", err))
-        ),
-        task::spawn(
-            Server::bind(&cli.metrics_addr)
-                .serve(metrics_router.into_make_service())
-                .map_err(|err| anyhow!("server failed: {:?}", err))
-        ),
+        task::spawn({
+            let shutdown = shutdown.clone();
+
+            async move {
+                if tls_reloaders.is_empty() {
+                    return Ok(());
+                }
+
+                loop {
+                    tokio::select! {
+                        _ = sleep(tls_reload_interval) => {}
+                        _ = shutdown.cancelled() => break,
+                    }
+
+                    for r in &tls_reloaders {
+                        if let Err(err) = r.reload().await {
+                            warn!(error = ?err, "failed to reload tls configuration");
+                        }
+                    }
+                }
+
+                Ok::<_, Error>(())
+            }
+        }),
+        task::spawn(tls::serve(
+            cli.api_addr,                   // addr
+            api_router,                     // router
+            api_tls.map(|r| r.config()),    // tls
+            shutdown.clone(),               // shutdown
+        )),
+        task::spawn(tls::serve(
+            cli.metrics_addr,                // addr
+            metrics_router,                  // router
+            metrics_tls.map(|r| r.config()), // tls
+            shutdown.clone(),                // shutdown
+        )),
     )
     .context(format!("{SERVICE_NAME} failed to run"))?;
 
     Ok(())
 }
 
+// Waits for the next poll, or until a task is queued for immediate processing
+async fn idle(notify: &Notify, shutdown: &CancellationToken, d: Duration) {
+    tokio::select! {
+        _ = sleep(d) => {}
+        _ = notify.notified() => {}
+        _ = shutdown.cancelled() => {}
+    }
+}
+
+// Refill interval of a rate limit given per hour
+fn per_hour(n: u32) -> Duration {
+    Duration::from_secs(3600) / n.max(1)
+}
+
+async fn shutdown_signal() -> Result<(), Error> {
+    let mut sigterm = signal(SignalKind::terminate()).context("failed to listen for SIGTERM")?;
+
+    tokio::select! {
+        _ = sigterm.recv() => {}
+        out = tokio::signal::ctrl_c() => out.context("failed to listen for SIGINT")?,
+    };
+
+    Ok(())
+}
+
+// Deregisters a task from the in-flight tasks once it completes
+struct InFlightGuard<'a>(
+    &'a Mutex<HashMap<Id, (AbortHandle, Option<Lease>, Priority)>>,
+    Id,
+);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.lock().unwrap().remove(&self.1);
+    }
+}
+
+async fn load_acme_account(
+    acme_provider_url: &str,
+    acme_account_id: Option<String>,
+    acme_account_key_path: Option<PathBuf>,
+    acme_contact_email: &[String],
+    acme_credentials: Option<&CredentialStore>,
+) -> Result<Account, Error> {
+    match (acme_account_id, acme_account_key_path) {
+        // Re-use existing account
+        (Some(id), Some(path)) => {
+            let key =
+                std::fs::read_to_string(path).context("failed to open acme account key file")?;
+            let acme_credentials: AccountCredentials = serde_json::from_str(&format!(
+                r#"{{
+                    "id": "{acme_provider_url}/acme/acct/{id}",
+                    "key_pkcs8": "{key}",
+                    "urls": {{
+                        "newNonce": "{acme_provider_url}/acme/new-nonce",
+                        "newAccount": "{acme_provider_url}/acme/new-acct",
+                        "newOrder": "{acme_provider_url}/acme/new-order"
+                    }}
+                }}"#,
+            ))?;
+
+            Account::from_credentials(acme_credentials)
+                .context("failed to create acme account from credentials")
+        }
+        (Some(_), None) | (None, Some(_)) => Err(anyhow!(
+            "must provide both acme_account_id and acme_account_key"
+        )),
+
+        // Re-use the account created on a previous run, or create a new one
+        _ => {
+            acme_account::load_or_create(acme_provider_url, acme_contact_email, acme_credentials)
+                .await
+        }
+    }
+}
+
 #[derive(Clone)]
 struct MetricsHandlerArgs {
     registry: Registry,
@@ -692,3 +2645,13 @@ async fn metrics_mw<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
 
     response
 }
+
+fn new_resolver(
+    name_servers: NameServerConfigGroup,
+    opts: &ResolverOpts,
+) -> Result<Resolver, Error> {
+    Ok(Resolver(TokioAsyncResolver::tokio(
+        ResolverConfig::from_parts(None, vec![], name_servers),
+        opts.to_owned(),
+    )?))
+}
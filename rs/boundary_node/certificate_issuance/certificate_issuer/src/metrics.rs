@@ -1,13 +1,18 @@
-use std::time::Instant;
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
 
-use anyhow::Error;
+use anyhow::{anyhow, Context as _, Error};
 use async_trait::async_trait;
 use candid::Principal;
-use certificate_orchestrator_interface::IcCertificate;
+use certificate_orchestrator_interface::{ExportFilter, IcCertificate};
 use ic_agent::{hash_tree::HashTree, Certificate};
 use opentelemetry::{
     baggage::BaggageExt,
     metrics::{Counter, Histogram, Meter},
+    sdk::metrics::{Aggregation, Instrument, InstrumentKind, Stream},
     Context, KeyValue,
 };
 use tracing::info;
@@ -15,17 +20,23 @@ use trust_dns_resolver::{error::ResolveError, lookup::Lookup, proto::rr::RecordT
 
 use crate::{
     acme,
-    certificate::{self, ExportError, GetCert, GetCertError, Package, Pair, UploadError},
+    audit::{self, AuditEntry, AuditError},
+    caa::{CaaError, CheckCaa},
+    certificate::{
+        self, ExportError, GetCert, GetCertError, Package, Pair, RevokeError, UploadError,
+    },
     check::{Check, CheckError},
     dns::{self, Record, Resolve},
+    history::{HistoryError, IssuanceEvent, RecordEvent},
+    propagation::{CheckPropagation, PropagationError},
     registration::{
-        Create, CreateError, Get, GetError, Id, Registration, Remove, RemoveError, Update,
-        UpdateError, UpdateType,
+        Create, CreateError, Get, GetError, Id, List, ListError, Registration, Remove, RemoveError,
+        State, Update, UpdateError, UpdateType,
     },
     verification::{Verify, VerifyError},
     work::{
-        extract_domain, Dispense, DispenseError, Peek, PeekError, Process, ProcessError, Queue,
-        QueueError, Task,
+        extract_domain, Count, CountError, Dispense, DispenseError, Lease, LeaseError, Peek,
+        PeekError, Priority, Process, ProcessError, Queue, QueueError, Release, Renew, Task,
     },
 };
 
@@ -52,15 +63,112 @@ impl MetricParams {
     }
 }
 
+// Whether metrics are labeled with the domains they relate to, which are of high cardinality
+pub(crate) static DOMAIN_LABELS: AtomicBool = AtomicBool::new(true);
+
+// Bucket boundaries of the histograms whose name matches a pattern, where `*` matches any characters,
+// e.g. `certificate_issuer.acme_*.duration_sec=1,5,30,60,300,900`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Buckets(pub String, pub Vec<f64>);
+
+impl FromStr for Buckets {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (pattern, boundaries) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <pattern>=<boundary>,<boundary>,..."))?;
+
+        Ok(Self(
+            pattern.to_string(),
+            boundaries.parse::<Boundaries>()?.0,
+        ))
+    }
+}
+
+// Ascending bucket boundaries, e.g. `0.1,1,10`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Boundaries(pub Vec<f64>);
+
+impl FromStr for Boundaries {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bs: Vec<f64> = s
+            .split(',')
+            .map(|b| b.trim().parse().context("invalid bucket boundary"))
+            .collect::<Result<_, _>>()?;
+
+        if bs.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(anyhow!("bucket boundaries must be ascending"));
+        }
+
+        Ok(Self(bs))
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(name) => name,
+                None => return false,
+            };
+
+            // Let the wildcard match as few characters as needed
+            (0..=name.len())
+                .filter(|i| name.is_char_boundary(*i))
+                .any(|i| matches_pattern(rest, &name[i..]))
+        }
+    }
+}
+
+// A view aggregating histograms with the boundaries of the first matching pattern, or the default ones.
+// The SDK's default boundaries are meant for milliseconds, while durations are recorded in seconds here.
+pub fn histogram_view(
+    default: Vec<f64>,
+    overrides: Vec<Buckets>,
+) -> impl Fn(&Instrument) -> Option<Stream> + Send + Sync + 'static {
+    move |i: &Instrument| {
+        if !matches!(i.kind, Some(InstrumentKind::Histogram)) {
+            return None;
+        }
+
+        let boundaries = overrides
+            .iter()
+            .find(|Buckets(pattern, _)| matches_pattern(pattern, &i.name))
+            .map_or(&default, |Buckets(_, bs)| bs)
+            .clone();
+
+        Some(
+            Stream::new()
+                .name(i.name.clone())
+                .description(i.description.clone())
+                .unit(i.unit.clone())
+                .aggregation(Aggregation::ExplicitBucketHistogram {
+                    boundaries,
+                    record_min_max: true,
+                }),
+        )
+    }
+}
+
 #[derive(Clone)]
 pub struct WithMetrics<T>(pub T, pub MetricParams);
 
 #[async_trait]
 impl<T: Create> Create for WithMetrics<T> {
-    async fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError> {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<acme::KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
         let start_time = Instant::now();
 
-        let out = self.0.create(name, canister).await;
+        let out = self.0.create(name, canister, key_type, alt_names).await;
 
         let status = match &out {
             Ok(_) => "ok",
@@ -201,6 +309,112 @@ impl<T: Get> Get for WithMetrics<T> {
     }
 }
 
+#[async_trait]
+impl<T: List> List for WithMetrics<T> {
+    async fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<(Id, Registration)>, ListError> {
+        let start_time = Instant::now();
+
+        let out = self.0.list(key.clone(), limit, state.clone()).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                ListError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), ?key, limit, ?state, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: audit::Append> audit::Append for WithMetrics<T> {
+    async fn append(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        let start_time = Instant::now();
+
+        let id = entry.id.clone();
+        let out = self.0.append(entry).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                AuditError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), %id, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: RecordEvent> RecordEvent for WithMetrics<T> {
+    async fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        let start_time = Instant::now();
+
+        let out = self.0.record(id, event).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                HistoryError::NotFound => "not-found",
+                HistoryError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), %id, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
 #[async_trait]
 impl<T: GetCert> GetCert for WithMetrics<T> {
     async fn get_cert(&self, id: &Id) -> Result<Pair, GetCertError> {
@@ -237,10 +451,10 @@ impl<T: GetCert> GetCert for WithMetrics<T> {
 
 #[async_trait]
 impl<T: Queue> Queue for WithMetrics<T> {
-    async fn queue(&self, id: &Id, t: u64) -> Result<(), QueueError> {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
         let start_time = Instant::now();
 
-        let out = self.0.queue(id, t).await;
+        let out = self.0.queue(id, t, priority).await;
 
         let status = match &out {
             Ok(_) => "ok",
@@ -263,7 +477,40 @@ impl<T: Queue> Queue for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), %id, t, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), %id, t, %priority, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: Count> Count for WithMetrics<T> {
+    async fn count(&self) -> Result<u64, CountError> {
+        let start_time = Instant::now();
+
+        let out = self.0.count().await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                CountError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), status, duration, error = ?out.as_ref().err(), count = ?out.as_ref().ok());
 
         out
     }
@@ -337,6 +584,74 @@ impl<T: Dispense> Dispense for WithMetrics<T> {
     }
 }
 
+#[async_trait]
+impl<T: Renew> Renew for WithMetrics<T> {
+    async fn renew(&self, id: &Id, lease: &Lease) -> Result<Lease, LeaseError> {
+        let start_time = Instant::now();
+
+        let out = self.0.renew(id, lease).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                LeaseError::NotHeld => "not-held",
+                LeaseError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), %id, lease = lease.id, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: Release> Release for WithMetrics<T> {
+    async fn release(&self, id: &Id, lease: &Lease) -> Result<(), LeaseError> {
+        let start_time = Instant::now();
+
+        let out = self.0.release(id, lease).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                LeaseError::NotHeld => "not-held",
+                LeaseError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), %id, lease = lease.id, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
 #[async_trait]
 impl<T: Process> Process for WithMetrics<T> {
     async fn process(&self, id: &Id, task: &Task) -> Result<(), ProcessError> {
@@ -350,7 +665,12 @@ impl<T: Process> Process for WithMetrics<T> {
                 ProcessError::AwaitingAcmeOrderCreation => "awaiting-acme-order-creation",
                 ProcessError::AwaitingDnsPropagation => "awaiting-dns-propagation",
                 ProcessError::AwaitingAcmeOrderReady => "awaiting-acme-order-ready",
-                ProcessError::FailedUserConfigurationCheck => "failed-user-configuration-check",
+                ProcessError::FailedUserConfigurationCheck(_) => "failed-user-configuration-check",
+                ProcessError::AcmeOrderInvalid => "acme-order-invalid",
+                ProcessError::AwaitingRateLimitBudget(_) => "awaiting-rate-limit-budget",
+                ProcessError::FailedCaaCheck(_) => "failed-caa-check",
+                ProcessError::InvalidCertificate(_) => "invalid-certificate",
+                ProcessError::PhaseTimeout { .. } => "phase-timeout",
                 ProcessError::UnexpectedError(_) => "fail",
             },
         };
@@ -367,12 +687,11 @@ impl<T: Process> Process for WithMetrics<T> {
             _ => "N/A",
         };
 
-        let labels = &[
+        let mut labels = vec![
             KeyValue::new("status", status),
             KeyValue::new("task", task.action.to_string()),
             KeyValue::new("is_renewal", is_renewal.clone()),
             KeyValue::new("is_important", is_important.clone()),
-            KeyValue::new("apex_domain", apex_domain.to_string()),
         ];
 
         let MetricParams {
@@ -381,6 +700,12 @@ impl<T: Process> Process for WithMetrics<T> {
             recorder,
         } = &self.1;
 
+        if DOMAIN_LABELS.load(Ordering::SeqCst) {
+            labels.push(KeyValue::new("apex_domain", apex_domain.to_string()));
+        }
+
+        let labels = labels.as_slice();
+
         counter.add(1, labels);
         recorder.record(duration, labels);
 
@@ -476,15 +801,19 @@ impl<T: dns::Delete> dns::Delete for WithMetrics<T> {
 
 #[async_trait]
 impl<T: acme::Order> acme::Order for WithMetrics<T> {
-    async fn order(&self, name: &str) -> Result<String, Error> {
+    async fn order(&self, names: &[String]) -> Result<Vec<(String, String)>, Error> {
         let start_time = Instant::now();
 
-        let out = self.0.order(name).await;
+        let out = self.0.order(names).await;
 
         let status = if out.is_ok() { "ok" } else { "fail" };
+        let error_class = out.as_ref().map_or_else(acme::error_class, |_| "none");
         let duration = start_time.elapsed().as_secs_f64();
 
-        let labels = &[KeyValue::new("status", status)];
+        let labels = &[
+            KeyValue::new("status", status),
+            KeyValue::new("error_class", error_class),
+        ];
 
         let MetricParams {
             action,
@@ -495,7 +824,7 @@ impl<T: acme::Order> acme::Order for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), name, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), ?names, status, error_class, duration, error = ?out.as_ref().err());
 
         out
     }
@@ -503,15 +832,19 @@ impl<T: acme::Order> acme::Order for WithMetrics<T> {
 
 #[async_trait]
 impl<T: acme::Ready> acme::Ready for WithMetrics<T> {
-    async fn ready(&self, name: &str) -> Result<(), Error> {
+    async fn ready(&self, names: &[String]) -> Result<(), Error> {
         let start_time = Instant::now();
 
-        let out = self.0.ready(name).await;
+        let out = self.0.ready(names).await;
 
         let status = if out.is_ok() { "ok" } else { "fail" };
+        let error_class = out.as_ref().map_or_else(acme::error_class, |_| "none");
         let duration = start_time.elapsed().as_secs_f64();
 
-        let labels = &[KeyValue::new("status", status)];
+        let labels = &[
+            KeyValue::new("status", status),
+            KeyValue::new("error_class", error_class),
+        ];
 
         let MetricParams {
             action,
@@ -522,7 +855,7 @@ impl<T: acme::Ready> acme::Ready for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), name, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), ?names, status, error_class, duration, error = ?out.as_ref().err());
 
         out
     }
@@ -530,10 +863,54 @@ impl<T: acme::Ready> acme::Ready for WithMetrics<T> {
 
 #[async_trait]
 impl<T: acme::Finalize> acme::Finalize for WithMetrics<T> {
-    async fn finalize(&self, name: &str) -> Result<(String, String), acme::FinalizeError> {
+    async fn finalize(
+        &self,
+        names: &[String],
+        key_type: acme::KeyType,
+    ) -> Result<(String, String), acme::FinalizeError> {
+        let start_time = Instant::now();
+
+        let out = self.0.finalize(names, key_type).await;
+
+        let status = if out.is_ok() { "ok" } else { "fail" };
+        let error_class = match &out {
+            Ok(_) => "none",
+            Err(acme::FinalizeError::OrderNotReady(_)) => "order-not-ready",
+            Err(acme::FinalizeError::OrderInvalid) => "order-invalid",
+            Err(acme::FinalizeError::UnexpectedError(err)) => acme::error_class(err),
+        };
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[
+            KeyValue::new("status", status),
+            KeyValue::new("error_class", error_class),
+        ];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), ?names, ?key_type, status, error_class, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: acme::Revoke> acme::Revoke for WithMetrics<T> {
+    async fn revoke(
+        &self,
+        cert_chain_pem: &[u8],
+        reason: acme::RevocationReason,
+    ) -> Result<(), Error> {
         let start_time = Instant::now();
 
-        let out = self.0.finalize(name).await;
+        let out = self.0.revoke(cert_chain_pem, reason).await;
 
         let status = if out.is_ok() { "ok" } else { "fail" };
         let duration = start_time.elapsed().as_secs_f64();
@@ -549,7 +926,7 @@ impl<T: acme::Finalize> acme::Finalize for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), name, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), ?reason, status, duration, error = ?out.as_ref().err());
 
         out
     }
@@ -589,19 +966,57 @@ impl<T: certificate::Upload> certificate::Upload for WithMetrics<T> {
     }
 }
 
+#[async_trait]
+impl<T: certificate::Revoke> certificate::Revoke for WithMetrics<T> {
+    async fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        let start_time = Instant::now();
+
+        let out = self.0.revoke(id).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                RevokeError::NotFound => "not-found",
+                RevokeError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), %id, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
 #[async_trait]
 impl<T: Verify> Verify for WithMetrics<T> {
     async fn verify(
         &self,
         key: Option<String>,
         limit: u64,
+        filter: &ExportFilter,
         pkgs: &[Package],
         cert: &Certificate,
         tree: &HashTree<Vec<u8>>,
     ) -> Result<(), VerifyError> {
         let start_time = Instant::now();
 
-        let out = self.0.verify(key.clone(), limit, pkgs, cert, tree).await;
+        let out = self
+            .0
+            .verify(key.clone(), limit, filter, pkgs, cert, tree)
+            .await;
 
         let status = if out.is_ok() { "ok" } else { "fail" };
         let duration = start_time.elapsed().as_secs_f64();
@@ -617,7 +1032,7 @@ impl<T: Verify> Verify for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), ?key, limit, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), ?key, limit, ?filter, status, duration, error = ?out.as_ref().err());
 
         out
     }
@@ -629,10 +1044,11 @@ impl<T: certificate::Export> certificate::Export for WithMetrics<T> {
         &self,
         key: Option<String>,
         limit: u64,
-    ) -> Result<(Vec<certificate::Package>, IcCertificate), ExportError> {
+        filter: &ExportFilter,
+    ) -> Result<(Vec<certificate::Package>, IcCertificate, Option<String>), ExportError> {
         let start_time = Instant::now();
 
-        let out = self.0.export(key.clone(), limit).await;
+        let out = self.0.export(key.clone(), limit, filter).await;
 
         let status = if out.is_ok() { "ok" } else { "fail" };
         let duration = start_time.elapsed().as_secs_f64();
@@ -648,7 +1064,7 @@ impl<T: certificate::Export> certificate::Export for WithMetrics<T> {
         counter.add(1, labels);
         recorder.record(duration, labels);
 
-        info!(action = action.as_str(), ?key, limit, status, duration, error = ?out.as_ref().err());
+        info!(action = action.as_str(), ?key, limit, ?filter, status, duration, error = ?out.as_ref().err());
 
         out
     }
@@ -666,11 +1082,16 @@ impl<T: Check> Check for WithMetrics<T> {
             Err(err) => match err {
                 CheckError::ExistingDnsTxtChallenge { .. } => "existing-dns-txt-challenge",
                 CheckError::MissingDnsCname { .. } => "missing-dns-cname",
+                CheckError::MissingDnsAddress { .. } => "missing-dns-address",
                 CheckError::MissingDnsTxtCanisterId { .. } => "missing-dns-txt-canister-id",
                 CheckError::MultipleDnsTxtCanisterId { .. } => "multiple-dns-txt-canister-id",
                 CheckError::InvalidDnsTxtCanisterId { .. } => "invalid-dns-txt-canister-id",
                 CheckError::KnownDomainsUnavailable { .. } => "known-domains-unavailable",
                 CheckError::MissingKnownDomains { .. } => "missing-known-domains",
+                CheckError::MissingOwnershipToken { .. } => "missing-ownership-token",
+                CheckError::BlockedDomain { .. } => "blocked-domain",
+                CheckError::ReservedTld { .. } => "reserved-tld",
+                CheckError::RegistrationLimitReached { .. } => "registration-limit-reached",
                 CheckError::UnexpectedError(_) => "fail",
             },
         };
@@ -693,3 +1114,121 @@ impl<T: Check> Check for WithMetrics<T> {
         out
     }
 }
+
+#[async_trait]
+impl<T: CheckPropagation> CheckPropagation for WithMetrics<T> {
+    async fn check_propagation(
+        &self,
+        name: &str,
+        canister_id: &Principal,
+    ) -> Result<(), PropagationError> {
+        let start_time = Instant::now();
+
+        let out = self.0.check_propagation(name, canister_id).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(PropagationError::NotPropagated { .. }) => "not-propagated",
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), name, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[async_trait]
+impl<T: CheckCaa> CheckCaa for WithMetrics<T> {
+    async fn check_caa(&self, name: &str) -> Result<(), CaaError> {
+        let start_time = Instant::now();
+
+        let out = self.0.check_caa(name).await;
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(err) => match err {
+                CaaError::Unauthorized { .. } => "unauthorized",
+                CaaError::UnexpectedError(_) => "fail",
+            },
+        };
+
+        let duration = start_time.elapsed().as_secs_f64();
+
+        let labels = &[KeyValue::new("status", status)];
+
+        let MetricParams {
+            action,
+            counter,
+            recorder,
+        } = &self.1;
+
+        counter.add(1, labels);
+        recorder.record(duration, labels);
+
+        info!(action = action.as_str(), name, status, duration, error = ?out.as_ref().err());
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_buckets() -> Result<(), Error> {
+        assert_eq!(
+            "certificate_issuer.acme_*.duration_sec=1,5,30".parse::<Buckets>()?,
+            Buckets(
+                "certificate_issuer.acme_*.duration_sec".into(),
+                vec![1.0, 5.0, 30.0]
+            )
+        );
+
+        assert!("request_duration".parse::<Buckets>().is_err());
+        assert!("request_duration=1,a".parse::<Buckets>().is_err());
+        assert!("request_duration=5,1".parse::<Buckets>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_patterns() {
+        for (pattern, name, expected) in [
+            ("request_duration", "request_duration", true),
+            ("request_duration", "request_duration_sec", false),
+            (
+                "*.duration_sec",
+                "certificate_issuer.process.duration_sec",
+                true,
+            ),
+            (
+                "*_order.duration_sec",
+                "a.acme_create_order.duration_sec",
+                true,
+            ),
+            (
+                "*.acme_*.duration_sec",
+                "a.acme_ready_order.duration_sec",
+                true,
+            ),
+            ("*.acme_*.duration_sec", "a.dns_create.duration_sec", false),
+            ("*", "anything", true),
+        ] {
+            assert_eq!(matches_pattern(pattern, name), expected, "{pattern} {name}");
+        }
+    }
+}
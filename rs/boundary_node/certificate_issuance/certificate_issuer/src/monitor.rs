@@ -0,0 +1,216 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Error};
+use certificate_orchestrator_interface::ExportFilter;
+use futures::TryStreamExt;
+use opentelemetry::{metrics::Counter, KeyValue};
+use tracing::{info, warn};
+
+use crate::{
+    certificate::{Pair, WithPagination},
+    encode::Decode,
+    renewal::expiry,
+    work::{Priority, Queue},
+};
+
+// Expiry of the certificate of each domain, as of the last scan
+pub type Expiries = Arc<Mutex<HashMap<String, SystemTime>>>;
+
+// Scans stored certificates for their expiry, independently of the renewal tasks.
+// Renewals are scheduled well ahead of expiry, so a certificate that is about to
+// expire means its task was lost or keeps failing, in which case a renewal is forced.
+pub struct ExpiryMonitor {
+    exporter: WithPagination, // exports encrypted packages
+    decoder: Arc<dyn Decode>,
+    queuer: Arc<dyn Queue>,
+
+    // Certificates expiring within this window are renewed right away
+    danger_window: Duration,
+
+    expiries: Expiries,
+    counter: Counter<u64>,
+}
+
+impl ExpiryMonitor {
+    pub fn new(
+        exporter: WithPagination,
+        decoder: Arc<dyn Decode>,
+        queuer: Arc<dyn Queue>,
+        danger_window: Duration,
+        expiries: Expiries,
+        counter: Counter<u64>,
+    ) -> Self {
+        Self {
+            exporter,
+            decoder,
+            queuer,
+            danger_window,
+            expiries,
+            counter,
+        }
+    }
+
+    async fn expiry(&self, pair: &Pair) -> Result<SystemTime, Error> {
+        let chain = self.decoder.decode(&pair.1).await?;
+        expiry(&chain)
+    }
+
+    // Returns the number of certificates whose renewal was forced
+    pub async fn run(&self) -> Result<u64, Error> {
+        let mut pkgs = self
+            .exporter
+            .stream(None, u64::MAX, ExportFilter::default());
+
+        let mut expiries = HashMap::new();
+        let (mut processed, mut forced, mut failed) = (0, 0, 0);
+
+        while let Some(pkg) = pkgs
+            .try_next()
+            .await
+            .context("failed to export certificates")?
+        {
+            processed += 1;
+
+            let not_after = match self.expiry(&pkg.pair).await {
+                Ok(not_after) => not_after,
+                Err(err) => {
+                    warn!(id = pkg.id, error = ?err, "failed to read certificate expiry");
+                    self.counter.add(1, &[KeyValue::new("status", "failed")]);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            expiries.insert(pkg.name.clone(), not_after);
+
+            let now = SystemTime::now();
+            if not_after > now + self.danger_window {
+                self.counter.add(1, &[KeyValue::new("status", "ok")]);
+                continue;
+            }
+
+            warn!(
+                id = pkg.id,
+                name = pkg.name,
+                ?not_after,
+                "certificate is about to expire, forcing renewal"
+            );
+
+            let t = now.duration_since(UNIX_EPOCH)?.as_nanos() as u64;
+
+            let status = match self.queuer.queue(&pkg.id, t, Priority::High).await {
+                Ok(()) => {
+                    forced += 1;
+                    "renewal-forced"
+                }
+                Err(err) => {
+                    warn!(id = pkg.id, error = ?err, "failed to force renewal");
+                    failed += 1;
+                    "failed"
+                }
+            };
+
+            self.counter.add(1, &[KeyValue::new("status", status)]);
+        }
+
+        // Replaced as a whole, so that removed registrations are no longer reported
+        *self.expiries.lock().unwrap() = expiries;
+
+        info!(processed, forced, failed, "scanned certificate expiries");
+
+        Ok(forced)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use candid::Principal;
+    use certificate_orchestrator_interface::IcCertificate;
+    use mockall::predicate;
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    use crate::{
+        certificate::{MockExport, Package},
+        encode::{Decoder, Encode, Encoder, Keyring},
+        work::{MockQueue, QueueError},
+    };
+
+    #[tokio::test]
+    async fn force_renewal_in_danger_window() -> Result<(), Error> {
+        let keys = Arc::new(Keyring::new(vec![[1u8; 32].to_vec()])?);
+        let encoder = Encoder::new(keys.clone());
+
+        let mut exported = vec![];
+        for (id, year) in [("a", 2100), ("b", 2020)] {
+            let mut params = CertificateParams::new(vec![format!("{id}.com")]);
+            params.not_after = date_time_ymd(year, 1, 1);
+
+            let cert = Certificate::from_params(params)?;
+
+            exported.push(Package {
+                id: id.into(),
+                name: format!("{id}.com"),
+                alt_names: vec![],
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                pair: Pair(
+                    encoder
+                        .encode(cert.serialize_private_key_pem().as_bytes())
+                        .await?,
+                    encoder.encode(cert.serialize_pem()?.as_bytes()).await?,
+                ),
+            });
+        }
+
+        let mut exporter = MockExport::new();
+        exporter.expect_export().times(1).returning(move |_, _, _| {
+            Ok((
+                exported.clone(),
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                None,
+            ))
+        });
+
+        // Only the expired certificate is renewed
+        let mut queuer = MockQueue::new();
+        queuer
+            .expect_queue()
+            .times(1)
+            .with(
+                predicate::eq(String::from("b")),
+                predicate::always(),
+                predicate::eq(Priority::High),
+            )
+            .returning(|_, _, _| Ok::<_, QueueError>(()));
+
+        let expiries = Expiries::default();
+
+        let m = ExpiryMonitor::new(
+            WithPagination(Arc::new(exporter), 50),
+            Arc::new(Decoder::new(keys)),
+            Arc::new(queuer),
+            Duration::from_secs(7 * 24 * 3600),
+            expiries.clone(),
+            opentelemetry::global::meter("test")
+                .u64_counter("expiry_scan")
+                .init(),
+        );
+
+        assert_eq!(m.run().await?, 1);
+
+        let expiries = expiries.lock().unwrap();
+        assert_eq!(expiries.len(), 2);
+        assert!(expiries["a.com"] > SystemTime::now());
+        assert!(expiries["b.com"] < SystemTime::now());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,314 @@
+use std::{fs, path::Path, sync::Arc};
+
+use anyhow::{Context, Error};
+use async_trait::async_trait;
+use candid::Principal;
+
+use crate::{
+    check::{Check, CheckError},
+    registration::List,
+};
+
+// Acceptable-use policies enforced when registering domains. Each policy wraps the checker it
+// applies on top of, so that the configured policies form a chain in front of the DNS checks.
+
+// Registrations are counted a page at a time
+const LIST_PAGE_SIZE: u64 = 1000;
+
+// Names are compared in their lowercase ASCII form, without a trailing dot
+fn normalize(name: &str) -> String {
+    let name = name.trim().trim_end_matches('.');
+    idna::domain_to_ascii(name).unwrap_or_else(|_| name.to_ascii_lowercase())
+}
+
+// Whether a normalized name is the given domain or one of its subdomains
+fn is_within(name: &str, domain: &str) -> bool {
+    name == domain
+        || name
+            .strip_suffix(domain)
+            .map_or(false, |prefix| prefix.ends_with('.'))
+}
+
+// Domains that cannot be registered, along with their subdomains
+pub struct Blocklist(Vec<String>);
+
+impl Blocklist {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let s = fs::read_to_string(path).context("failed to read blocklist")?;
+        Ok(Self::parse(&s))
+    }
+
+    // One domain per line, ignoring empty lines and comments starting with #
+    fn parse(s: &str) -> Self {
+        Self(
+            s.lines()
+                .map(|ln| ln.split('#').next().unwrap_or_default().trim())
+                .filter(|ln| !ln.is_empty())
+                .map(normalize)
+                .collect(),
+        )
+    }
+
+    // The blocklist entry covering the name, if any
+    fn entry(&self, name: &str) -> Option<&str> {
+        let name = normalize(name);
+
+        self.0
+            .iter()
+            .find(|domain| is_within(&name, domain))
+            .map(String::as_str)
+    }
+}
+
+pub struct WithBlocklist {
+    checker: Arc<dyn Check>,
+    blocklist: Arc<Blocklist>,
+}
+
+impl WithBlocklist {
+    pub fn new(checker: Arc<dyn Check>, blocklist: Arc<Blocklist>) -> Self {
+        Self { checker, blocklist }
+    }
+}
+
+#[async_trait]
+impl Check for WithBlocklist {
+    async fn check(&self, name: &str) -> Result<Principal, CheckError> {
+        if let Some(entry) = self.blocklist.entry(name) {
+            return Err(CheckError::BlockedDomain {
+                entry: entry.to_string(),
+            });
+        }
+
+        self.checker.check(name).await
+    }
+}
+
+// Rejects names under top-level domains reserved for special use, which are never issued
+// public certificates
+pub struct WithReservedTlds {
+    checker: Arc<dyn Check>,
+    tlds: Vec<String>,
+}
+
+impl WithReservedTlds {
+    pub fn new(checker: Arc<dyn Check>, tlds: &[String]) -> Self {
+        Self {
+            checker,
+            tlds: tlds
+                .iter()
+                .map(|tld| normalize(tld.trim_start_matches('.')))
+                .filter(|tld| !tld.is_empty())
+                .collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl Check for WithReservedTlds {
+    async fn check(&self, name: &str) -> Result<Principal, CheckError> {
+        let normalized = normalize(name);
+
+        if let Some(tld) = self.tlds.iter().find(|tld| is_within(&normalized, tld)) {
+            return Err(CheckError::ReservedTld {
+                tld: tld.to_string(),
+            });
+        }
+
+        self.checker.check(name).await
+    }
+}
+
+// Limits the number of registrations a canister can have. Registrations of the checked name
+// itself are not counted, so that existing registrations are still accepted, e.g. on retries.
+pub struct WithRegistrationLimit {
+    checker: Arc<dyn Check>,
+    lister: Arc<dyn List>,
+    limit: u64,
+}
+
+impl WithRegistrationLimit {
+    pub fn new(checker: Arc<dyn Check>, lister: Arc<dyn List>, limit: u64) -> Self {
+        Self {
+            checker,
+            lister,
+            limit,
+        }
+    }
+
+    async fn count(&self, name: &str, canister_id: &Principal) -> Result<u64, Error> {
+        let mut count = 0;
+        let mut key = None;
+
+        loop {
+            let regs = self
+                .lister
+                .list(key, LIST_PAGE_SIZE, None)
+                .await
+                .context("failed to list registrations")?;
+
+            let last = match regs.last() {
+                Some((id, _)) => id.clone(),
+                None => return Ok(count),
+            };
+
+            count += regs
+                .iter()
+                .filter(|(_, reg)| &reg.canister == canister_id && reg.name != name)
+                .count() as u64;
+
+            // No need to go on once the limit is reached
+            if count >= self.limit {
+                return Ok(count);
+            }
+
+            key = Some(last);
+        }
+    }
+}
+
+#[async_trait]
+impl Check for WithRegistrationLimit {
+    async fn check(&self, name: &str) -> Result<Principal, CheckError> {
+        let canister_id = self.checker.check(name).await?;
+
+        if self.count(name, &canister_id).await? >= self.limit {
+            return Err(CheckError::RegistrationLimitReached {
+                id: canister_id.to_string(),
+                limit: self.limit,
+            });
+        }
+
+        Ok(canister_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::anyhow;
+    use mockall::predicate;
+
+    use crate::{
+        check::MockCheck,
+        registration::{MockList, Registration, State},
+    };
+
+    fn checker(times: usize) -> Arc<dyn Check> {
+        let mut checker = MockCheck::new();
+        checker
+            .expect_check()
+            .times(times)
+            .returning(|_| Ok(Principal::from_text("aaaaa-aa").unwrap()));
+
+        Arc::new(checker)
+    }
+
+    #[tokio::test]
+    async fn blocklist_and_reserved_tlds() -> Result<(), Error> {
+        let blocklist = Arc::new(Blocklist::parse(
+            "# Blocked domains\n\nExample.ORG.\nblocked.com # abuse\n",
+        ));
+
+        let ck = WithBlocklist::new(checker(2), blocklist);
+
+        for (name, entry) in [
+            ("example.org", "example.org"),
+            ("www.example.org.", "example.org"),
+            ("a.b.blocked.com", "blocked.com"),
+        ] {
+            match ck.check(name).await {
+                Err(CheckError::BlockedDomain { entry: other }) if other == entry => {}
+                other => return Err(anyhow!("expected BlockedDomain but got {:?}", other)),
+            }
+        }
+
+        // Only whole labels match
+        ck.check("notexample.org").await?;
+        ck.check("blocked.com.au").await?;
+
+        let ck =
+            WithReservedTlds::new(checker(1), &[".test".into(), "LOCALHOST".into(), "".into()]);
+
+        for name in ["example.test", "app.localhost.", "test"] {
+            assert!(matches!(
+                ck.check(name).await,
+                Err(CheckError::ReservedTld { .. })
+            ));
+        }
+
+        ck.check("test.com").await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn registration_limit() -> Result<(), Error> {
+        let reg = |name: &str, canister: &str| Registration {
+            name: name.into(),
+            canister: Principal::from_text(canister).unwrap(),
+            state: State::Available,
+            key_type: None,
+            alt_names: vec![],
+        };
+
+        let mut lister = MockList::new();
+        lister
+            .expect_list()
+            .with(
+                predicate::eq(None),
+                predicate::always(),
+                predicate::eq(None),
+            )
+            .returning(move |_, _, _| {
+                Ok(vec![
+                    ("1".into(), reg("a.com", "aaaaa-aa")),
+                    ("2".into(), reg("b.com", "2ibo7-dia")),
+                ])
+            });
+        lister
+            .expect_list()
+            .with(
+                predicate::eq(Some("2".to_string())),
+                predicate::always(),
+                predicate::eq(None),
+            )
+            .returning(move |_, _, _| Ok(vec![("3".into(), reg("c.com", "aaaaa-aa"))]));
+        lister
+            .expect_list()
+            .with(
+                predicate::eq(Some("3".to_string())),
+                predicate::always(),
+                predicate::eq(None),
+            )
+            .returning(|_, _, _| Ok(vec![]));
+
+        let lister = Arc::new(lister);
+
+        // The canister has two registrations besides the checked name
+        WithRegistrationLimit::new(checker(1), lister.clone(), 3)
+            .check("d.com")
+            .await?;
+
+        match WithRegistrationLimit::new(checker(1), lister.clone(), 2)
+            .check("d.com")
+            .await
+        {
+            Err(CheckError::RegistrationLimitReached { id, limit: 2 }) if id == "aaaaa-aa" => {}
+            other => {
+                return Err(anyhow!(
+                    "expected RegistrationLimitReached but got {:?}",
+                    other
+                ))
+            }
+        }
+
+        // An existing registration does not count against the limit
+        WithRegistrationLimit::new(checker(1), lister, 2)
+            .check("c.com")
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use async_trait::async_trait;
+use candid::Principal;
+use futures::future::join_all;
+use mockall::automock;
+use tracing::warn;
+use trust_dns_resolver::proto::rr::{RData, RecordType};
+
+use crate::{check::Delegation, dns::Resolve};
+
+#[derive(Debug, thiserror::Error)]
+pub enum PropagationError {
+    #[error("dns records seen by {agreed} name servers, but {required} are required")]
+    NotPropagated { agreed: usize, required: usize },
+}
+
+#[automock]
+#[async_trait]
+pub trait CheckPropagation: Send + Sync {
+    async fn check_propagation(
+        &self,
+        name: &str,
+        canister_id: &Principal,
+    ) -> Result<(), PropagationError>;
+}
+
+#[async_trait]
+impl<T: CheckPropagation + ?Sized> CheckPropagation for Arc<T> {
+    async fn check_propagation(
+        &self,
+        name: &str,
+        canister_id: &Principal,
+    ) -> Result<(), PropagationError> {
+        (**self).check_propagation(name, canister_id).await
+    }
+}
+
+pub struct NameServer {
+    pub name: String,
+    pub resolver: Box<dyn Resolve>,
+
+    // Recursive resolvers follow the delegation CNAME from the registered domain,
+    // while authoritative name servers of the delegation domain can only be asked
+    // for the challenge TXT record itself
+    pub recursive: bool,
+}
+
+// Checks that the challenge response has propagated to several independent
+// name servers before the ACME provider is asked to validate it. Failing
+// name servers are treated as not having seen the records yet.
+pub struct PropagationChecker {
+    // configuration
+    delegation: Arc<Delegation>,
+    required: usize, // number of name servers that need to agree
+
+    // dependencies
+    name_servers: Vec<NameServer>,
+}
+
+impl PropagationChecker {
+    pub fn new(
+        delegation: Arc<Delegation>,
+        required: usize,
+        name_servers: Vec<NameServer>,
+    ) -> Self {
+        Self {
+            delegation,
+            required,
+            name_servers,
+        }
+    }
+
+    // The challenge response records seen by a name server, if the delegation is in place
+    async fn observe(
+        &self,
+        ns: &NameServer,
+        name: &str,
+        canister_id: &Principal,
+    ) -> Option<BTreeSet<String>> {
+        let src = format!("_acme-challenge.{name}.");
+        let dst = self.delegation.target(name, canister_id);
+
+        if ns.recursive {
+            let is_delegated = match ns.resolver.lookup(&src, RecordType::CNAME).await {
+                Ok(rs) => rs.iter().any(|r| r.to_string().eq(&dst)),
+                Err(err) => {
+                    warn!(
+                        ns = ns.name.as_str(),
+                        name = src.as_str(),
+                        error = ?err,
+                        "failed to resolve CNAME"
+                    );
+                    false
+                }
+            };
+
+            if !is_delegated {
+                return None;
+            }
+        }
+
+        // Recursive resolvers are asked through the delegation, like the ACME provider would
+        let txt_src = if ns.recursive { &src } else { &dst };
+
+        match ns.resolver.lookup(txt_src, RecordType::TXT).await {
+            Ok(rs) => {
+                // Lookups through a CNAME also contain the CNAME record itself
+                let vs: BTreeSet<String> = rs
+                    .iter()
+                    .filter(|r| matches!(r, RData::TXT(_)))
+                    .map(|r| r.to_string())
+                    .collect();
+
+                (!vs.is_empty()).then_some(vs)
+            }
+            Err(err) => {
+                warn!(
+                    ns = ns.name.as_str(),
+                    name = txt_src.as_str(),
+                    error = ?err,
+                    "failed to resolve TXT"
+                );
+                None
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CheckPropagation for PropagationChecker {
+    async fn check_propagation(
+        &self,
+        name: &str,
+        canister_id: &Principal,
+    ) -> Result<(), PropagationError> {
+        let observations = join_all(
+            self.name_servers
+                .iter()
+                .map(|ns| self.observe(ns, name, canister_id)),
+        )
+        .await;
+
+        // Name servers agree if they see the same challenge response,
+        // stale responses of previous orders do not count towards the majority
+        let mut counts: Vec<(BTreeSet<String>, usize)> = vec![];
+        for vs in observations.into_iter().flatten() {
+            match counts.iter_mut().find(|(other, _)| other == &vs) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((vs, 1)),
+            }
+        }
+
+        let agreed = counts.iter().map(|(_, n)| *n).max().unwrap_or(0);
+
+        if agreed < self.required {
+            return Err(PropagationError::NotPropagated {
+                agreed,
+                required: self.required,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::{anyhow, Error};
+    use trust_dns_resolver::{
+        error::ResolveError,
+        lookup::Lookup,
+        proto::{
+            op::Query,
+            rr::{rdata::TXT, Record},
+        },
+        Name,
+    };
+
+    use crate::dns::MockResolve;
+
+    fn lookup(name: &str, rdata: RData) -> Result<Lookup, ResolveError> {
+        let name = Name::from_utf8(name).unwrap();
+        let q = Query::query(name.clone(), rdata.to_record_type());
+
+        Ok(Lookup::new_with_max_ttl(
+            q,
+            Arc::new([Record::from_rdata(name, 300, rdata)]),
+        ))
+    }
+
+    fn name_server(token: &'static str, recursive: bool) -> NameServer {
+        let mut resolver = MockResolve::new();
+        resolver
+            .expect_lookup()
+            .returning(move |name, typ| match typ {
+                RecordType::CNAME => lookup(
+                    name,
+                    RData::CNAME(Name::from_utf8("_acme-challenge.name.delegation.").unwrap()),
+                ),
+                _ => lookup(name, RData::TXT(TXT::new(vec![token.into()]))),
+            });
+
+        NameServer {
+            name: token.into(),
+            resolver: Box::new(resolver),
+            recursive,
+        }
+    }
+
+    #[tokio::test]
+    async fn required_agreement() -> Result<(), Error> {
+        let c = PropagationChecker::new(
+            Arc::new(Delegation::new("delegation".into(), None)),
+            3,
+            vec![
+                name_server("new", false),
+                name_server("new", true),
+                name_server("old", true),
+            ],
+        );
+
+        match c.check_propagation("name", &Principal::anonymous()).await {
+            Err(PropagationError::NotPropagated { agreed: 2, .. }) => {}
+            other => return Err(anyhow!("expected NotPropagated but got {:?}", other)),
+        }
+
+        let c = PropagationChecker::new(
+            Arc::new(Delegation::new("delegation".into(), None)),
+            2,
+            vec![
+                name_server("new", false),
+                name_server("new", true),
+                name_server("old", true),
+            ],
+        );
+
+        c.check_propagation("name", &Principal::anonymous()).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,285 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header::RETRY_AFTER, Request, Response},
+    middleware::Next,
+    response::IntoResponse,
+};
+use tracing::warn;
+
+use crate::api::CallerIdentity;
+
+// Number of tracked keys above which fully replenished buckets are dropped
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Clone, Copy)]
+struct Limit {
+    capacity: f64,
+    refill_interval: Duration, // time to replenish a single token
+}
+
+impl Limit {
+    fn tokens_at(&self, b: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(b.updated_at);
+        let refilled = elapsed.as_secs_f64() / self.refill_interval.as_secs_f64();
+
+        (b.tokens + refilled).min(self.capacity)
+    }
+}
+
+// Token bucket rate limiter, keyed by an arbitrary string (e.g. a source IP)
+pub struct RateLimiter {
+    limit: Mutex<Limit>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(capacity: u32, refill_interval: Duration) -> Self {
+        Self {
+            limit: Mutex::new(Limit {
+                capacity: capacity as f64,
+                refill_interval,
+            }),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Changes the limit of all keys, e.g. on configuration reload
+    pub fn set_limit(&self, capacity: u32, refill_interval: Duration) {
+        *self.limit.lock().unwrap() = Limit {
+            capacity: capacity as f64,
+            refill_interval,
+        };
+    }
+
+    // Takes `cost` tokens for the given key, or returns how long to wait until they are available
+    pub fn acquire(&self, key: &str, cost: u32) -> Result<(), Duration> {
+        self.acquire_at(key, cost, Instant::now())
+    }
+
+    fn acquire_at(&self, key: &str, cost: u32, now: Instant) -> Result<(), Duration> {
+        let cost = cost as f64;
+        let limit = *self.limit.lock().unwrap();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        if buckets.len() >= MAX_TRACKED_KEYS {
+            buckets.retain(|_, b| limit.tokens_at(b, now) < limit.capacity);
+        }
+
+        let b = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: limit.capacity,
+            updated_at: now,
+        });
+
+        b.tokens = limit.tokens_at(b, now);
+        b.updated_at = now;
+
+        if b.tokens < cost {
+            return Err(limit.refill_interval.mul_f64(cost - b.tokens));
+        }
+
+        b.tokens -= cost;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitMiddlewareArgs {
+    pub by_ip: Arc<RateLimiter>,
+    pub by_identity: Arc<RateLimiter>,
+
+    // Header carrying the client IP when running behind a reverse proxy (e.g. `x-real-ip`).
+    // For comma-separated lists (e.g. `x-forwarded-for`) the rightmost entry is used, since it is
+    // the one appended by the proxy, whereas the preceding entries are controlled by the client
+    pub ip_header: Option<String>,
+}
+
+fn source_ip<B>(req: &Request<B>, ip_header: &Option<String>) -> Option<String> {
+    if let Some(h) = ip_header {
+        return req
+            .headers()
+            .get(h)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+    }
+
+    req.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string())
+}
+
+// Rate limits of a single request, for handlers whose cost depends on the request itself
+// (e.g. the number of names in a batch)
+#[derive(Clone)]
+pub struct RequestRateLimit {
+    args: RateLimitMiddlewareArgs,
+    ip: Option<String>,
+    identity: Option<CallerIdentity>,
+}
+
+impl RequestRateLimit {
+    pub fn new(
+        args: RateLimitMiddlewareArgs,
+        ip: Option<String>,
+        identity: Option<CallerIdentity>,
+    ) -> Self {
+        Self { args, ip, identity }
+    }
+
+    fn from_request<B>(req: &Request<B>) -> Self {
+        let args = req
+            .extensions()
+            .get::<RateLimitMiddlewareArgs>()
+            .expect("missing rate-limit middleware args")
+            .to_owned();
+
+        let ip = source_ip(req, &args.ip_header);
+        let identity = req.extensions().get::<CallerIdentity>().cloned();
+
+        Self::new(args, ip, identity)
+    }
+
+    // Takes `cost` tokens from the limits of the request, or returns a 429 response
+    pub fn acquire(&self, cost: u32) -> Result<(), Response<Body>> {
+        let RateLimitMiddlewareArgs {
+            by_ip, by_identity, ..
+        } = &self.args;
+
+        let out = match (&self.ip, &self.identity) {
+            (Some(ip), Some(CallerIdentity(id))) => by_ip
+                .acquire(ip, cost)
+                .and_then(|_| by_identity.acquire(id, cost)),
+            (Some(ip), None) => by_ip.acquire(ip, cost),
+            (None, Some(CallerIdentity(id))) => by_identity.acquire(id, cost),
+
+            // Requests that cannot be attributed share a single bucket
+            (None, None) => by_ip.acquire("unknown", cost),
+        };
+
+        out.map_err(|retry_after| {
+            warn!(?retry_after, cost, "rate limit exceeded");
+
+            // Round up, so clients retrying right away are not limited again
+            let retry_after = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+
+            Response::builder()
+                .status(429)
+                .header(RETRY_AFTER, retry_after)
+                .body(Body::from("rate limit exceeded"))
+                .unwrap()
+        })
+    }
+}
+
+pub async fn rate_limit_mw<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    if let Err(resp) = RequestRateLimit::from_request(&req).acquire(1) {
+        return resp.into_response();
+    }
+
+    next.run(req).await
+}
+
+// Leaves charging the rate limits to the handler, which finds them in the request extensions
+pub async fn request_rate_limit_mw<B>(mut req: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let rate_limit = RequestRateLimit::from_request(&req);
+    req.extensions_mut().insert(rate_limit);
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn acquire_until_exhausted() {
+        let r = RateLimiter::new(2, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(r.acquire_at("a", 1, now).is_ok());
+        assert!(r.acquire_at("a", 1, now).is_ok());
+        assert_eq!(r.acquire_at("a", 1, now), Err(Duration::from_secs(10)));
+
+        // Keys are limited independently
+        assert!(r.acquire_at("b", 1, now).is_ok());
+    }
+
+    #[test]
+    fn acquire_after_refill() {
+        let r = RateLimiter::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(r.acquire_at("a", 1, now).is_ok());
+        match r.acquire_at("a", 1, now + Duration::from_secs(4)) {
+            Err(retry_after) => assert_eq!(retry_after.as_secs_f64().round(), 6.0),
+            other => panic!("expected Err but got {other:?}"),
+        };
+        assert!(r.acquire_at("a", 1, now + Duration::from_secs(10)).is_ok());
+    }
+
+    #[test]
+    fn acquire_after_set_limit() {
+        let r = RateLimiter::new(1, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(r.acquire_at("a", 1, now).is_ok());
+        assert!(r.acquire_at("a", 1, now).is_err());
+
+        r.set_limit(2, Duration::from_secs(1));
+        assert!(r.acquire_at("a", 1, now + Duration::from_secs(1)).is_ok());
+
+        // Buckets are capped at the new capacity
+        r.set_limit(1, Duration::from_secs(1));
+        assert!(r.acquire_at("b", 1, now).is_ok());
+        assert!(r.acquire_at("b", 1, now).is_err());
+    }
+
+    #[test]
+    fn acquire_with_cost() {
+        let r = RateLimiter::new(3, Duration::from_secs(10));
+        let now = Instant::now();
+
+        assert!(r.acquire_at("a", 2, now).is_ok());
+        assert_eq!(r.acquire_at("a", 2, now), Err(Duration::from_secs(10)));
+        assert!(r.acquire_at("a", 1, now).is_ok());
+
+        // Costs above the capacity are never available
+        assert!(r.acquire_at("b", 4, now).is_err());
+    }
+
+    #[test]
+    fn source_ip_from_proxy_entry() {
+        let ip_header = Some("x-forwarded-for".to_string());
+        let req = |v: &str| {
+            Request::builder()
+                .header("x-forwarded-for", v)
+                .body(())
+                .unwrap()
+        };
+
+        // Entries before the last one are set by the client and ignored
+        assert_eq!(
+            source_ip(&req("1.1.1.1, 2.2.2.2"), &ip_header),
+            Some("2.2.2.2".to_string())
+        );
+        assert_eq!(
+            source_ip(&req("3.3.3.3"), &ip_header),
+            Some("3.3.3.3".to_string())
+        );
+        assert_eq!(source_ip(&req("1.1.1.1,"), &ip_header), None);
+    }
+}
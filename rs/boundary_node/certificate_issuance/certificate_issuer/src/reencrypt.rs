@@ -0,0 +1,255 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, Context, Error};
+use certificate_orchestrator_interface::ExportFilter;
+use futures::TryStreamExt;
+use opentelemetry::{metrics::Counter, KeyValue};
+use tracing::{info, warn};
+
+use crate::{
+    certificate::{GetCert, GetCertError, Pair, Upload, UploadError, WithPagination},
+    encode::{Decode, Keyring},
+    registration::Id,
+};
+
+// How often the checkpoint is persisted, in processed packages
+const CHECKPOINT_INTERVAL: u64 = 100;
+
+// Re-encrypts certificates that are not encrypted with the newest key yet,
+// so that previous keys can eventually be retired. Passes that fail midway
+// are resumed from the last processed package, which is optionally persisted
+// so that a restart of the issuer does not start over either.
+pub struct Reencrypter {
+    exporter: WithPagination, // exports encrypted packages
+    keys: Arc<Keyring>,
+    decoder: Arc<dyn Decode>,
+    getter: Arc<dyn GetCert>,
+    uploader: Arc<dyn Upload>, // encrypts with the newest key
+
+    // progress
+    checkpoint: Mutex<Option<Id>>,
+    checkpoint_path: Option<PathBuf>,
+    counter: Counter<u64>,
+}
+
+impl Reencrypter {
+    pub fn new(
+        exporter: WithPagination,
+        keys: Arc<Keyring>,
+        decoder: Arc<dyn Decode>,
+        getter: Arc<dyn GetCert>,
+        uploader: Arc<dyn Upload>,
+        checkpoint_path: Option<PathBuf>,
+        counter: Counter<u64>,
+    ) -> Self {
+        let checkpoint = checkpoint_path
+            .as_ref()
+            .and_then(|p| fs::read_to_string(p).ok())
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+
+        if let Some(id) = &checkpoint {
+            info!(%id, "resuming re-encryption");
+        }
+
+        Self {
+            exporter,
+            keys,
+            decoder,
+            getter,
+            uploader,
+            checkpoint: Mutex::new(checkpoint),
+            checkpoint_path,
+            counter,
+        }
+    }
+
+    fn set_checkpoint(&self, id: Option<Id>, persist: bool) {
+        *self.checkpoint.lock().unwrap() = id.clone();
+
+        if let (true, Some(p)) = (persist, &self.checkpoint_path) {
+            if let Err(err) = fs::write(p, id.unwrap_or_default()) {
+                warn!(error = ?err, "failed to persist re-encryption checkpoint");
+            }
+        }
+    }
+
+    // Re-encrypts a single package, verifying that it can be read back
+    async fn reencrypt(&self, id: &Id, pair: &Pair) -> Result<&'static str, Error> {
+        let Pair(key, chain) = pair;
+        if self.keys.is_current(key) && self.keys.is_current(chain) {
+            return Ok("current");
+        }
+
+        let pair = Pair(
+            self.decoder.decode(key).await?,
+            self.decoder.decode(chain).await?,
+        );
+
+        // Skip certificates that were renewed or removed in the meantime, rather than overwriting them
+        match self.getter.get_cert(id).await {
+            Ok(current) if current == pair => {}
+            Ok(_) | Err(GetCertError::NotFound) => return Ok("skipped"),
+            Err(err) => return Err(err.into()),
+        };
+
+        match self.uploader.upload(id, pair.clone()).await {
+            Ok(()) => {}
+            Err(UploadError::NotFound) => return Ok("skipped"),
+            Err(err) => return Err(err.into()),
+        };
+
+        match self.getter.get_cert(id).await {
+            Ok(stored) if stored == pair => Ok("reencrypted"),
+            Ok(_) => Err(anyhow!(
+                "re-encrypted certificate does not match the original"
+            )),
+            Err(err) => Err(Error::from(err).context("failed to read back certificate")),
+        }
+    }
+
+    // Returns the number of re-encrypted certificates
+    pub async fn run(&self) -> Result<u64, Error> {
+        let after = self.checkpoint.lock().unwrap().clone();
+
+        let mut pkgs = self
+            .exporter
+            .stream(after, u64::MAX, ExportFilter::default());
+
+        let (mut count, mut processed, mut failed) = (0, 0, 0);
+
+        loop {
+            let pkg = match pkgs.try_next().await {
+                Ok(Some(pkg)) => pkg,
+                Ok(None) => break,
+                Err(err) => {
+                    // Persist progress, so the next pass resumes where this one stopped
+                    let checkpoint = self.checkpoint.lock().unwrap().clone();
+                    self.set_checkpoint(checkpoint, true);
+
+                    return Err(Error::from(err).context("failed to export certificates"));
+                }
+            };
+
+            // Failures of single certificates do not hold up the rest, they are retried in the next pass
+            let status = match self.reencrypt(&pkg.id, &pkg.pair).await {
+                Ok(status) => status,
+                Err(err) => {
+                    warn!(id = pkg.id, error = ?err, "failed to re-encrypt certificate");
+                    failed += 1;
+                    "failed"
+                }
+            };
+
+            if status == "reencrypted" {
+                count += 1;
+            }
+
+            self.counter.add(1, &[KeyValue::new("status", status)]);
+
+            processed += 1;
+            self.set_checkpoint(Some(pkg.id), processed % CHECKPOINT_INTERVAL == 0);
+
+            if processed % CHECKPOINT_INTERVAL == 0 {
+                info!(processed, count, failed, "re-encryption in progress");
+            }
+        }
+
+        // Start the next pass from the beginning
+        self.set_checkpoint(None, true);
+
+        info!(processed, count, failed, "re-encrypted certificates");
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use candid::Principal;
+    use certificate_orchestrator_interface::IcCertificate;
+    use mockall::predicate;
+
+    use crate::{
+        certificate::{MockExport, MockGetCert, MockUpload, Package},
+        encode::{Decoder, Encode, Encoder},
+    };
+
+    #[tokio::test]
+    async fn reencrypt_with_previous_key() -> Result<(), Error> {
+        let old = Arc::new(Keyring::new(vec![[1u8; 32].to_vec()])?);
+        let new = Arc::new(Keyring::new(vec![[2u8; 32].to_vec(), [1u8; 32].to_vec()])?);
+
+        let pkgs = vec![
+            ("a", Encoder::new(old.clone())),
+            ("b", Encoder::new(new.clone())),
+        ];
+
+        let mut exported = vec![];
+        for (id, encoder) in pkgs {
+            exported.push(Package {
+                id: id.into(),
+                name: format!("{id}.com"),
+                alt_names: vec![],
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                pair: Pair(
+                    encoder.encode(b"key").await?,
+                    encoder.encode(b"chain").await?,
+                ),
+            });
+        }
+
+        let mut exporter = MockExport::new();
+        exporter.expect_export().times(1).returning(move |_, _, _| {
+            Ok((
+                exported.clone(),
+                IcCertificate {
+                    cert: vec![],
+                    tree: vec![],
+                },
+                None,
+            ))
+        });
+
+        // Read before uploading, and again to verify the upload
+        let mut getter = MockGetCert::new();
+        getter
+            .expect_get_cert()
+            .times(2)
+            .with(predicate::eq(String::from("a")))
+            .returning(|_| Ok(Pair(b"key".to_vec(), b"chain".to_vec())));
+
+        // Only the package encrypted with the previous key is re-encrypted
+        let mut uploader = MockUpload::new();
+        uploader
+            .expect_upload()
+            .times(1)
+            .with(
+                predicate::eq(String::from("a")),
+                predicate::eq(Pair(b"key".to_vec(), b"chain".to_vec())),
+            )
+            .returning(|_, _| Ok(()));
+
+        let r = Reencrypter::new(
+            WithPagination(Arc::new(exporter), 50),
+            new.clone(),
+            Arc::new(Decoder::new(new)),
+            Arc::new(getter),
+            Arc::new(uploader),
+            None,
+            opentelemetry::global::meter("test")
+                .u64_counter("reencrypt")
+                .init(),
+        );
+
+        assert_eq!(r.run().await?, 1);
+
+        Ok(())
+    }
+}
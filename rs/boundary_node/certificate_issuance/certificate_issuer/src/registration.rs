@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{iter::once, sync::Arc};
 
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
@@ -7,8 +7,16 @@ use certificate_orchestrator_interface as ifc;
 use ic_agent::Agent;
 use mockall::automock;
 use serde::{Deserialize, Serialize};
+use tracing::warn;
 
-use crate::work::ProcessError;
+use crate::{
+    acme::{self, KeyType, RevocationReason},
+    certificate::{GetCert, GetCertError, Pair},
+    check::Delegation,
+    dns,
+    failure::Failure,
+    work::ProcessError,
+};
 
 pub type Id = String;
 
@@ -19,6 +27,7 @@ pub enum State {
     PendingChallengeResponse,
     PendingAcmeApproval,
     Available,
+    Parked(String),
 }
 
 impl ToString for State {
@@ -27,14 +36,29 @@ impl ToString for State {
     }
 }
 
+impl State {
+    // Why a registration failed or was parked, unless the reason predates error codes
+    pub fn failure(&self) -> Option<Failure> {
+        match self {
+            State::Failed(reason) | State::Parked(reason) => Failure::parse(reason),
+            _ => None,
+        }
+    }
+}
+
 impl From<ProcessError> for State {
     fn from(e: ProcessError) -> Self {
         match e {
             ProcessError::AwaitingAcmeOrderCreation => State::PendingOrder,
             ProcessError::AwaitingDnsPropagation => State::PendingChallengeResponse,
             ProcessError::AwaitingAcmeOrderReady => State::PendingAcmeApproval,
-            ProcessError::FailedUserConfigurationCheck => State::PendingOrder,
-            ProcessError::UnexpectedError(_) => State::Failed(e.to_string()),
+            ProcessError::FailedUserConfigurationCheck(_) => State::PendingOrder,
+            ProcessError::AcmeOrderInvalid => State::Failed(Failure::classify(&e).to_string()),
+            ProcessError::AwaitingRateLimitBudget(_) => State::PendingOrder,
+            ProcessError::FailedCaaCheck(_) => State::Failed(Failure::classify(&e).to_string()),
+            ProcessError::InvalidCertificate(_) => State::Failed(Failure::classify(&e).to_string()),
+            ProcessError::PhaseTimeout { .. } => State::Failed(Failure::classify(&e).to_string()),
+            ProcessError::UnexpectedError(_) => State::Failed(Failure::classify(&e).to_string()),
         }
     }
 }
@@ -47,6 +71,7 @@ impl From<ifc::State> for State {
             ifc::State::PendingChallengeResponse => State::PendingChallengeResponse,
             ifc::State::PendingAcmeApproval => State::PendingAcmeApproval,
             ifc::State::Available => State::Available,
+            ifc::State::Parked(err) => State::Parked(err.into()),
         }
     }
 }
@@ -59,6 +84,7 @@ impl From<State> for ifc::State {
             State::PendingChallengeResponse => ifc::State::PendingChallengeResponse,
             State::PendingAcmeApproval => ifc::State::PendingAcmeApproval,
             State::Available => ifc::State::Available,
+            State::Parked(err) => ifc::State::Parked(err.into()),
         }
     }
 }
@@ -68,14 +94,63 @@ pub struct Registration {
     pub name: String,
     pub canister: Principal,
     pub state: State,
+
+    // The issuer's default is used when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_type: Option<KeyType>,
+
+    // Additional names covered by the same certificate
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alt_names: Vec<String>,
 }
 
 impl From<ifc::Registration> for Registration {
     fn from(reg: ifc::Registration) -> Self {
+        // Registrations created before names were normalized may hold U-labels
+        let normalize = |name: ifc::Name| {
+            let name: String = name.into();
+            idna::domain_to_ascii(&name).unwrap_or(name)
+        };
+
         Registration {
-            name: reg.name.into(),
+            name: normalize(reg.name),
             canister: reg.canister,
             state: reg.state.into(),
+            key_type: reg.key_type.map(Into::into),
+            alt_names: reg
+                .alt_names
+                .unwrap_or_default()
+                .into_iter()
+                .map(normalize)
+                .collect(),
+        }
+    }
+}
+
+// When a registration was created, last attempted and next scheduled for processing, and when
+// its state last changed, in nanoseconds since the epoch
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Timestamps {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_attempt_at: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_scheduled_at: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_state_change_at: Option<u64>,
+}
+
+impl From<ifc::RegistrationTimestamps> for Timestamps {
+    fn from(t: ifc::RegistrationTimestamps) -> Self {
+        Timestamps {
+            created_at: t.created_at,
+            last_attempt_at: t.last_attempt_at,
+            next_scheduled_at: t.next_scheduled_at,
+            last_state_change_at: t.last_state_change_at,
         }
     }
 }
@@ -105,9 +180,30 @@ pub enum CreateError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+#[automock]
 #[async_trait]
 pub trait Create: Send + Sync {
-    async fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError>;
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError>;
+}
+
+// Lets the storage backend be chosen at runtime, behind the same decorators
+#[async_trait]
+impl<T: Create + ?Sized> Create for Arc<T> {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        (**self).create(name, canister, key_type, alt_names).await
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -124,6 +220,13 @@ pub trait Update: Send + Sync {
     async fn update(&self, id: &Id, typ: &UpdateType) -> Result<(), UpdateError>;
 }
 
+#[async_trait]
+impl<T: Update + ?Sized> Update for Arc<T> {
+    async fn update(&self, id: &Id, typ: &UpdateType) -> Result<(), UpdateError> {
+        (**self).update(id, typ).await
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RemoveError {
     #[error("Not found")]
@@ -138,6 +241,13 @@ pub trait Remove: Send + Sync {
     async fn remove(&self, id: &Id) -> Result<(), RemoveError>;
 }
 
+#[async_trait]
+impl<T: Remove + ?Sized> Remove for Arc<T> {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        (**self).remove(id).await
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum GetError {
     #[error("Not found")]
@@ -152,6 +262,55 @@ pub trait Get: Send + Sync {
     async fn get(&self, id: &Id) -> Result<Registration, GetError>;
 }
 
+#[async_trait]
+impl<T: Get + ?Sized> Get for Arc<T> {
+    async fn get(&self, id: &Id) -> Result<Registration, GetError> {
+        (**self).get(id).await
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[automock]
+#[async_trait]
+pub trait List: Send + Sync {
+    async fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<(Id, Registration)>, ListError>;
+}
+
+#[async_trait]
+impl<T: List + ?Sized> List for Arc<T> {
+    async fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<(Id, Registration)>, ListError> {
+        (**self).list(key, limit, state).await
+    }
+}
+
+#[automock]
+#[async_trait]
+pub trait GetTimestamps: Send + Sync {
+    async fn get_timestamps(&self, id: &Id) -> Result<Timestamps, GetError>;
+}
+
+#[async_trait]
+impl<T: GetTimestamps + ?Sized> GetTimestamps for Arc<T> {
+    async fn get_timestamps(&self, id: &Id) -> Result<Timestamps, GetError> {
+        (**self).get_timestamps(id).await
+    }
+}
+
 pub struct CanisterGetter(pub Arc<Agent>, pub Principal);
 
 #[async_trait]
@@ -182,14 +341,91 @@ impl Get for CanisterGetter {
     }
 }
 
+pub struct CanisterTimestampsGetter(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl GetTimestamps for CanisterTimestampsGetter {
+    async fn get_timestamps(&self, id: &Id) -> Result<Timestamps, GetError> {
+        use ifc::{GetRegistrationError as Error, GetRegistrationTimestampsResponse as Response};
+
+        let args = Encode!(&id).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .query(&self.1, "getRegistrationTimestamps")
+            .with_arg(args)
+            .call()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(t) => Ok(t.into()),
+            Response::Err(err) => Err(match err {
+                Error::NotFound => GetError::NotFound,
+                Error::Unauthorized => GetError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => GetError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+pub struct CanisterLister(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl List for CanisterLister {
+    async fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<(Id, Registration)>, ListError> {
+        use ifc::{ListRegistrationsError as Error, ListRegistrationsResponse as Response};
+
+        let state: Option<ifc::State> = state.map(Into::into);
+        let args = Encode!(&key, &limit, &state).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .query(&self.1, "listRegistrations")
+            .with_arg(args)
+            .call()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(entries) => Ok(entries
+                .into_iter()
+                .map(|e| (e.id, e.registration.into()))
+                .collect()),
+            Response::Err(err) => Err(match err {
+                Error::Unauthorized => ListError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => ListError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
 pub struct CanisterCreator(pub Arc<Agent>, pub Principal);
 
 #[async_trait]
 impl Create for CanisterCreator {
-    async fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError> {
+    async fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
         use ifc::{CreateRegistrationError as Error, CreateRegistrationResponse as Response};
 
-        let args = Encode!(&name.to_string(), canister).context("failed to encode arg")?;
+        let key_type: Option<ifc::KeyType> = key_type.map(Into::into);
+        let alt_names: Option<Vec<String>> = (!alt_names.is_empty()).then(|| alt_names.to_vec());
+        let args = Encode!(&name.to_string(), canister, &key_type, &alt_names)
+            .context("failed to encode arg")?;
 
         let resp = self
             .0
@@ -274,3 +510,228 @@ impl Remove for CanisterRemover {
         }
     }
 }
+
+// Wrapper to clean up any leftover state of a registration outside of the orchestrator canister
+pub struct WithCleanup<T> {
+    remover: T,
+    getter: Arc<dyn Get>,
+    dns_deleter: Box<dyn dns::Delete>,
+    delegation: Arc<Delegation>,
+}
+
+impl<T: Remove> WithCleanup<T> {
+    pub fn new(
+        remover: T,
+        getter: Arc<dyn Get>,
+        dns_deleter: Box<dyn dns::Delete>,
+        delegation: Arc<Delegation>,
+    ) -> Self {
+        Self {
+            remover,
+            getter,
+            dns_deleter,
+            delegation,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Remove> Remove for WithCleanup<T> {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        let Registration {
+            name,
+            canister,
+            alt_names,
+            ..
+        } = self.getter.get(id).await.map_err(|err| match err {
+            GetError::NotFound => RemoveError::NotFound,
+            GetError::UnexpectedError(err) => RemoveError::UnexpectedError(err),
+        })?;
+
+        // Delete leftover challenge response records, e.g. from an interrupted order
+        for name in once(name).chain(alt_names) {
+            self.dns_deleter
+                .delete(
+                    self.delegation.domain(),
+                    &self.delegation.record(&name, &canister),
+                )
+                .await
+                .context("failed to delete dns record")?;
+        }
+
+        // Removing the registration also cancels any of its queued tasks
+        self.remover.remove(id).await
+    }
+}
+
+// Wrapper to revoke the certificate of a registration with its ACME provider before removing it
+pub struct WithRevocation<T> {
+    remover: T,
+    cert_getter: Arc<dyn GetCert>,
+    acme_revoker: Arc<dyn acme::Revoke>,
+}
+
+impl<T: Remove> WithRevocation<T> {
+    pub fn new(
+        remover: T,
+        cert_getter: Arc<dyn GetCert>,
+        acme_revoker: Arc<dyn acme::Revoke>,
+    ) -> Self {
+        Self {
+            remover,
+            cert_getter,
+            acme_revoker,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Remove> Remove for WithRevocation<T> {
+    async fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        // Revocation is best-effort, a failure doesn't prevent the removal
+        match self.cert_getter.get_cert(id).await {
+            Ok(Pair(_, cert_chain_pem)) => {
+                if let Err(err) = self
+                    .acme_revoker
+                    .revoke(&cert_chain_pem, RevocationReason::CessationOfOperation)
+                    .await
+                {
+                    warn!(%id, error = ?err, "failed to revoke certificate");
+                }
+            }
+
+            // No certificate was issued yet
+            Err(GetCertError::NotFound) => {}
+
+            Err(GetCertError::UnexpectedError(err)) => {
+                warn!(%id, error = ?err, "failed to get certificate for revocation");
+            }
+        };
+
+        self.remover.remove(id).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::{anyhow, Error};
+    use mockall::predicate;
+
+    use crate::{acme::MockRevoke, certificate::MockGetCert, dns::MockDelete};
+
+    #[tokio::test]
+    async fn remove_with_cleanup() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter
+            .expect_get()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| {
+                Ok(Registration {
+                    name: String::from("name"),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::Available,
+                    key_type: None,
+                    alt_names: vec!["www.name".into()],
+                })
+            });
+
+        let mut dns_deleter = MockDelete::new();
+        for name in ["_acme-challenge.name", "_acme-challenge.www.name"] {
+            dns_deleter
+                .expect_delete()
+                .times(1)
+                .with(predicate::eq("delegation"), predicate::eq(name))
+                .returning(|_, _| Ok(()));
+        }
+
+        let mut remover = MockRemove::new();
+        remover
+            .expect_remove()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| Ok(()));
+
+        let remover = WithCleanup::new(
+            remover,
+            Arc::new(getter),
+            Box::new(dns_deleter),
+            Arc::new(Delegation::new("delegation".into(), None)),
+        );
+
+        remover.remove(&Id::from("id")).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn remove_with_failed_cleanup() -> Result<(), Error> {
+        let mut getter = MockGet::new();
+        getter.expect_get().times(1).returning(|_| {
+            Ok(Registration {
+                name: String::from("name"),
+                canister: Principal::from_text("aaaaa-aa").unwrap(),
+                state: State::PendingChallengeResponse,
+                key_type: None,
+                alt_names: vec![],
+            })
+        });
+
+        let mut dns_deleter = MockDelete::new();
+        dns_deleter
+            .expect_delete()
+            .times(1)
+            .returning(|_, _| Err(anyhow!("failed")));
+
+        // The registration is kept around so the removal can be retried
+        let mut remover = MockRemove::new();
+        remover.expect_remove().never();
+
+        let remover = WithCleanup::new(
+            remover,
+            Arc::new(getter),
+            Box::new(dns_deleter),
+            Arc::new(Delegation::new("delegation".into(), None)),
+        );
+
+        match remover.remove(&Id::from("id")).await {
+            Err(RemoveError::UnexpectedError(_)) => Ok(()),
+            other => Err(anyhow!("expected UnexpectedError but got {:?}", other)),
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_with_failed_revocation() -> Result<(), Error> {
+        let mut cert_getter = MockGetCert::new();
+        cert_getter
+            .expect_get_cert()
+            .times(1)
+            .returning(|_| Ok(Pair(b"key".to_vec(), b"cert".to_vec())));
+
+        let mut acme_revoker = MockRevoke::new();
+        acme_revoker
+            .expect_revoke()
+            .times(1)
+            .with(
+                predicate::eq(&b"cert"[..]),
+                predicate::eq(RevocationReason::CessationOfOperation),
+            )
+            .returning(|_, _| Err(anyhow!("failed")));
+
+        // The registration is removed regardless
+        let mut remover = MockRemove::new();
+        remover
+            .expect_remove()
+            .times(1)
+            .with(predicate::eq(Id::from("id")))
+            .returning(|_| Ok(()));
+
+        let remover = WithRevocation::new(remover, Arc::new(cert_getter), Arc::new(acme_revoker));
+
+        remover.remove(&Id::from("id")).await?;
+
+        Ok(())
+    }
+}
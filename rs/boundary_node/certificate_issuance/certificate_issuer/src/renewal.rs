@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Mutex, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Context, Error};
+use x509_parser::pem::parse_x509_pem;
+
+// Expiry of the leaf certificate in a PEM-encoded certificate chain
+pub fn expiry(certificate_chain_pem: &[u8]) -> Result<SystemTime, Error> {
+    let (_, pem) = parse_x509_pem(certificate_chain_pem).context("failed to parse pem")?;
+    let cert = pem.parse_x509().context("failed to parse x509")?;
+
+    let not_after = cert.validity().not_after.timestamp();
+    let not_after: u64 = not_after
+        .try_into()
+        .map_err(|_| anyhow!("invalid expiry {not_after}"))?;
+
+    Ok(UNIX_EPOCH + Duration::from_secs(not_after))
+}
+
+const DAY_SECS: u64 = 24 * 3600;
+
+// How long before expiry a certificate is renewed
+#[derive(Clone, Copy)]
+struct Window {
+    lead_time: Duration,
+
+    // Renewals are moved earlier by up to this duration, so that
+    // certificates issued together are not all renewed at once
+    jitter: Duration,
+}
+
+// Decides when to renew a certificate, based on its expiry and on the renewals
+// already scheduled, so that renewals are spread over the days of the window
+pub struct RenewalPolicy {
+    window: RwLock<Window>,
+
+    // Renewals scheduled per day (since the epoch), from today on
+    load: Mutex<BTreeMap<u64, u64>>,
+}
+
+impl RenewalPolicy {
+    pub fn new(lead_time: Duration, jitter: Duration) -> Self {
+        Self {
+            window: RwLock::new(Window { lead_time, jitter }),
+            load: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    // Applies to renewals scheduled from now on, e.g. on configuration reload
+    pub fn set_window(&self, lead_time: Duration, jitter: Duration) {
+        *self.window.write().unwrap() = Window { lead_time, jitter };
+    }
+
+    pub fn renewal_time(&self, not_after: SystemTime, now: SystemTime) -> SystemTime {
+        let Window { lead_time, jitter } = *self.window.read().unwrap();
+
+        let secs = |t: SystemTime| t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let now_secs = secs(now);
+
+        // Certificates already within the renewal window are renewed right away
+        let latest = match not_after.checked_sub(lead_time) {
+            Some(t) if secs(t) > now_secs => secs(t),
+            _ => return now,
+        };
+        let earliest = not_after
+            .checked_sub(lead_time + jitter)
+            .map_or(now_secs, |t| secs(t).max(now_secs));
+
+        let mut load = self.load.lock().unwrap();
+
+        // Days already past are no longer relevant
+        *load = load.split_off(&(now_secs / DAY_SECS));
+
+        // Days are weighted by how much of the window they cover, and inversely by
+        // how many renewals are already scheduled on them
+        let days: Vec<(u64, u64, u64, f64)> = (earliest / DAY_SECS..=latest / DAY_SECS)
+            .map(|day| {
+                let start = earliest.max(day * DAY_SECS);
+                let end = latest.min((day + 1) * DAY_SECS);
+                let n = load.get(&day).copied().unwrap_or(0);
+
+                (day, start, end, (end - start) as f64 / (n + 1) as f64)
+            })
+            .filter(|(_, _, _, weight)| *weight > 0.0)
+            .collect();
+
+        let total: f64 = days.iter().map(|(_, _, _, weight)| weight).sum();
+        let mut r = rand::random::<f64>() * total;
+
+        let (day, start, end) = days
+            .iter()
+            .find(|(_, _, _, weight)| {
+                r -= weight;
+                r < 0.0
+            })
+            .or(days.last())
+            .map_or(
+                (latest / DAY_SECS, latest, latest),
+                |&(day, start, end, _)| (day, start, end),
+            );
+
+        *load.entry(day).or_default() += 1;
+
+        UNIX_EPOCH
+            + Duration::from_secs(start)
+            + Duration::from_secs(end - start).mul_f64(rand::random::<f64>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rcgen::{date_time_ymd, Certificate, CertificateParams};
+
+    const DAY: Duration = Duration::from_secs(24 * 3600);
+
+    #[test]
+    fn parse_expiry() -> Result<(), Error> {
+        let mut params = CertificateParams::new(vec!["example.com".into()]);
+        params.not_after = date_time_ymd(2030, 1, 1);
+
+        let pem = Certificate::from_params(params)?.serialize_pem()?;
+
+        assert_eq!(
+            expiry(pem.as_bytes())?,
+            UNIX_EPOCH + Duration::from_secs(1_893_456_000) // 2030-01-01
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn renewal_time_within_jitter() {
+        let p = RenewalPolicy::new(30 * DAY, 3 * DAY);
+
+        let now = UNIX_EPOCH + 1000 * DAY;
+        let not_after = now + 90 * DAY;
+
+        for _ in 0..100 {
+            let t = p.renewal_time(not_after, now);
+            assert!(t <= now + 60 * DAY);
+            assert!(t >= now + 57 * DAY);
+        }
+    }
+
+    #[test]
+    fn renewal_time_spread_by_load() {
+        let p = RenewalPolicy::new(30 * DAY, 3 * DAY);
+
+        let now = UNIX_EPOCH + 1000 * DAY;
+        let not_after = now + 90 * DAY;
+
+        // A day that is already busy with renewals of other certificates
+        p.load.lock().unwrap().insert(1057, 100);
+
+        let mut counts = BTreeMap::new();
+        for _ in 0..300 {
+            let t = p.renewal_time(not_after, now);
+            assert!(t >= now + 57 * DAY && t <= now + 60 * DAY);
+
+            let day = t.duration_since(UNIX_EPOCH).unwrap().as_secs() / DAY_SECS;
+            *counts.entry(day).or_insert(0) += 1;
+        }
+
+        // Renewals make up for the busy day, rather than piling on it
+        assert!(counts.get(&1057).copied().unwrap_or(0) < 100);
+        assert!(counts[&1058] > 80);
+        assert!(counts[&1059] > 80);
+
+        // Scheduled renewals count towards the load of their day
+        assert_eq!(p.load.lock().unwrap().values().sum::<u64>(), 400);
+    }
+
+    #[test]
+    fn renewal_time_already_due() {
+        let p = RenewalPolicy::new(30 * DAY, 3 * DAY);
+
+        let now = UNIX_EPOCH + 1000 * DAY;
+
+        assert_eq!(p.renewal_time(now + 10 * DAY, now), now);
+        assert_eq!(p.renewal_time(now - 10 * DAY, now), now);
+    }
+}
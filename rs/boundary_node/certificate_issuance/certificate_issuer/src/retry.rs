@@ -0,0 +1,337 @@
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{atomic::Ordering, Mutex},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error};
+
+use crate::{
+    acme::{has_problem, CAA_PROBLEM, RATE_LIMITED_PROBLEM},
+    registration::Id,
+    work::ProcessError,
+    TASK_DELAY_SEC, TASK_ERROR_DELAY_SEC,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    DnsNotPropagated,
+    AcmeRateLimited,
+    CaaFailure,
+    OrderInvalid,
+    CertificateRejected,
+    UserConfiguration,
+    Unexpected,
+}
+
+impl FailureClass {
+    // Failures of tasks that are still progressing are not classified,
+    // e.g. while waiting on the ACME provider to approve an order
+    pub fn classify(err: &ProcessError) -> Option<Self> {
+        match err {
+            ProcessError::AwaitingAcmeOrderCreation => None,
+            ProcessError::AwaitingAcmeOrderReady => None,
+            ProcessError::AwaitingDnsPropagation => Some(Self::DnsNotPropagated),
+            ProcessError::FailedUserConfigurationCheck(_) => Some(Self::UserConfiguration),
+            ProcessError::AcmeOrderInvalid => Some(Self::OrderInvalid),
+            ProcessError::AwaitingRateLimitBudget(_) => None,
+            ProcessError::FailedCaaCheck(_) => Some(Self::CaaFailure),
+            ProcessError::InvalidCertificate(_) => Some(Self::CertificateRejected),
+            ProcessError::PhaseTimeout { .. } => Some(Self::Unexpected),
+            ProcessError::UnexpectedError(err) => Some(if has_problem(err, RATE_LIMITED_PROBLEM) {
+                Self::AcmeRateLimited
+            } else if has_problem(err, CAA_PROBLEM) {
+                Self::CaaFailure
+            } else {
+                Self::Unexpected
+            }),
+        }
+    }
+}
+
+impl FromStr for FailureClass {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dns-not-propagated" => Ok(Self::DnsNotPropagated),
+            "acme-rate-limited" => Ok(Self::AcmeRateLimited),
+            "caa-failure" => Ok(Self::CaaFailure),
+            "order-invalid" => Ok(Self::OrderInvalid),
+            "certificate-rejected" => Ok(Self::CertificateRejected),
+            "user-configuration" => Ok(Self::UserConfiguration),
+            "unexpected" => Ok(Self::Unexpected),
+            _ => Err(anyhow!("unknown failure class {s}")),
+        }
+    }
+}
+
+// Exponential backoff, doubling the delay on every consecutive failure
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+
+    // Tasks are parked instead of retried after this many consecutive failures
+    pub max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial.saturating_mul(factor).min(self.max)
+    }
+}
+
+// A backoff override for a single failure class, e.g. `acme-rate-limited=3600:86400:10`
+// (initial delay and maximum delay in seconds, followed by the maximum number of attempts, 0 meaning unlimited)
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClassBackoff(pub FailureClass, pub Backoff);
+
+impl FromStr for ClassBackoff {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (class, params) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <class>=<initial_sec>:<max_sec>:<max_attempts>"))?;
+
+        let params: Vec<u64> = params
+            .split(':')
+            .map(|p| p.parse().context("invalid backoff parameter"))
+            .collect::<Result<_, _>>()?;
+
+        let (initial, max, max_attempts) = match params[..] {
+            [initial, max, max_attempts] => (initial, max, max_attempts),
+            _ => return Err(anyhow!("expected <initial_sec>:<max_sec>:<max_attempts>")),
+        };
+
+        Ok(Self(
+            class.parse()?,
+            Backoff {
+                initial: Duration::from_secs(initial),
+                max: Duration::from_secs(max),
+                max_attempts: match max_attempts {
+                    0 => None,
+                    n => Some(n.try_into().context("invalid max attempts")?),
+                },
+            },
+        ))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RetryDecision {
+    Retry(Duration),
+    Park { attempts: u32 },
+}
+
+// Decides when to retry a failed task, based on the class of its failure and
+// how many times in a row it has failed with that class. Attempts are tracked
+// in memory, so they are counted per issuer and reset on restart.
+pub struct RetryPolicy {
+    backoffs: HashMap<FailureClass, Backoff>,
+    attempts: Mutex<HashMap<Id, (FailureClass, u32)>>,
+}
+
+impl RetryPolicy {
+    pub fn new(overrides: Vec<ClassBackoff>) -> Self {
+        let task_delay = Duration::from_secs(TASK_DELAY_SEC.load(Ordering::SeqCst));
+        let task_error_delay = Duration::from_secs(TASK_ERROR_DELAY_SEC.load(Ordering::SeqCst));
+
+        const HOUR: Duration = Duration::from_secs(3600);
+
+        let mut backoffs = HashMap::from([
+            (
+                FailureClass::DnsNotPropagated,
+                Backoff {
+                    initial: task_delay,
+                    max: task_delay.max(HOUR / 6),
+                    max_attempts: Some(100),
+                },
+            ),
+            (
+                FailureClass::AcmeRateLimited,
+                Backoff {
+                    initial: HOUR,
+                    max: 24 * HOUR,
+                    max_attempts: None,
+                },
+            ),
+            (
+                FailureClass::CaaFailure,
+                Backoff {
+                    initial: task_error_delay,
+                    max: 24 * HOUR,
+                    max_attempts: Some(10),
+                },
+            ),
+            (
+                FailureClass::OrderInvalid,
+                Backoff {
+                    initial: task_error_delay,
+                    max: 6 * HOUR,
+                    max_attempts: Some(5),
+                },
+            ),
+            (
+                FailureClass::CertificateRejected,
+                Backoff {
+                    initial: task_error_delay,
+                    max: 6 * HOUR,
+                    max_attempts: Some(5),
+                },
+            ),
+            (
+                FailureClass::UserConfiguration,
+                Backoff {
+                    initial: task_error_delay,
+                    max: 24 * HOUR,
+                    max_attempts: Some(30),
+                },
+            ),
+            (
+                FailureClass::Unexpected,
+                Backoff {
+                    initial: task_error_delay,
+                    max: 6 * HOUR,
+                    max_attempts: None,
+                },
+            ),
+        ]);
+
+        for ClassBackoff(class, backoff) in overrides {
+            backoffs.insert(class, backoff);
+        }
+
+        Self {
+            backoffs,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn on_error(&self, id: &Id, err: &ProcessError) -> RetryDecision {
+        // Orders delayed to stay within rate limits are not failures, and are retried once the budget allows
+        if let ProcessError::AwaitingRateLimitBudget(d) = err {
+            return RetryDecision::Retry(*d);
+        }
+
+        let mut attempts = self.attempts.lock().unwrap();
+
+        let class = match FailureClass::classify(err) {
+            Some(class) => class,
+            None => {
+                attempts.remove(id);
+                return RetryDecision::Retry(Duration::from_secs(
+                    TASK_DELAY_SEC.load(Ordering::SeqCst),
+                ));
+            }
+        };
+
+        // Only consecutive failures of the same class are counted
+        let attempt = match attempts.get(id) {
+            Some((c, n)) if *c == class => n + 1,
+            _ => 1,
+        };
+
+        let backoff = &self.backoffs[&class];
+
+        if backoff.max_attempts.map_or(false, |max| attempt >= max) {
+            attempts.remove(id);
+            return RetryDecision::Park { attempts: attempt };
+        }
+
+        attempts.insert(id.to_owned(), (class, attempt));
+        RetryDecision::Retry(backoff.delay(attempt))
+    }
+
+    pub fn on_success(&self, id: &Id) {
+        self.attempts.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_class_backoff() -> Result<(), Error> {
+        assert_eq!(
+            "order-invalid=60:600:0".parse::<ClassBackoff>()?,
+            ClassBackoff(
+                FailureClass::OrderInvalid,
+                Backoff {
+                    initial: Duration::from_secs(60),
+                    max: Duration::from_secs(600),
+                    max_attempts: None,
+                }
+            ),
+        );
+
+        assert!("order-invalid=60:600".parse::<ClassBackoff>().is_err());
+        assert!("other=60:600:1".parse::<ClassBackoff>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn backoff_then_park() {
+        let p = RetryPolicy::new(vec![ClassBackoff(
+            FailureClass::Unexpected,
+            Backoff {
+                initial: Duration::from_secs(10),
+                max: Duration::from_secs(30),
+                max_attempts: Some(4),
+            },
+        )]);
+
+        let id: Id = "id".into();
+        let err = ProcessError::UnexpectedError(anyhow!("error"));
+
+        assert_eq!(
+            p.on_error(&id, &err),
+            RetryDecision::Retry(Duration::from_secs(10))
+        );
+        assert_eq!(
+            p.on_error(&id, &err),
+            RetryDecision::Retry(Duration::from_secs(20))
+        );
+        assert_eq!(
+            p.on_error(&id, &err),
+            RetryDecision::Retry(Duration::from_secs(30))
+        );
+        assert_eq!(p.on_error(&id, &err), RetryDecision::Park { attempts: 4 });
+    }
+
+    #[test]
+    fn progress_resets_attempts() {
+        let p = RetryPolicy::new(vec![ClassBackoff(
+            FailureClass::OrderInvalid,
+            Backoff {
+                initial: Duration::from_secs(10),
+                max: Duration::from_secs(600),
+                max_attempts: Some(2),
+            },
+        )]);
+
+        let id: Id = "id".into();
+
+        assert_eq!(
+            p.on_error(&id, &ProcessError::AcmeOrderInvalid),
+            RetryDecision::Retry(Duration::from_secs(10)),
+        );
+
+        // The task made progress in between
+        p.on_error(&id, &ProcessError::AwaitingAcmeOrderReady);
+
+        assert_eq!(
+            p.on_error(&id, &ProcessError::AcmeOrderInvalid),
+            RetryDecision::Retry(Duration::from_secs(10)),
+        );
+        assert_eq!(
+            p.on_error(&id, &ProcessError::AcmeOrderInvalid),
+            RetryDecision::Park { attempts: 2 },
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::{net::SocketAddr, path::PathBuf, sync::Mutex, time::SystemTime};
+
+use anyhow::{anyhow, Context, Error};
+use axum::{Router, Server};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use tokio::fs;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+// Certificate and key of a listener, both PEM-encoded
+#[derive(Clone, Debug)]
+pub struct TlsPaths {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl TlsPaths {
+    // TLS is enabled by providing both a certificate and a key
+    pub fn from_args(
+        name: &str,
+        cert: Option<PathBuf>,
+        key: Option<PathBuf>,
+    ) -> Result<Option<Self>, Error> {
+        match (cert, key) {
+            (Some(cert), Some(key)) => Ok(Some(Self { cert, key })),
+            (None, None) => Ok(None),
+            _ => Err(anyhow!(
+                "both a tls certificate and key must be provided for the {name} server"
+            )),
+        }
+    }
+
+    // Latest modification time of the certificate and key
+    async fn modified(&self) -> Result<SystemTime, Error> {
+        let mut ts = vec![];
+
+        for p in [&self.cert, &self.key] {
+            let t = fs::metadata(p)
+                .await
+                .and_then(|m| m.modified())
+                .with_context(|| format!("failed to stat {}", p.display()))?;
+
+            ts.push(t);
+        }
+
+        Ok(ts.into_iter().max().unwrap_or(SystemTime::UNIX_EPOCH))
+    }
+}
+
+// Reloads a listener's TLS configuration when its certificate or key change on disk,
+// e.g. after being renewed. Connections that are already established keep the old one.
+pub struct TlsReloader {
+    name: &'static str,
+    paths: TlsPaths,
+    config: RustlsConfig,
+    modified: Mutex<SystemTime>,
+}
+
+impl TlsReloader {
+    pub async fn new(name: &'static str, paths: TlsPaths) -> Result<Self, Error> {
+        let modified = paths.modified().await?;
+
+        let config = RustlsConfig::from_pem_file(&paths.cert, &paths.key)
+            .await
+            .with_context(|| format!("failed to load tls configuration for the {name} server"))?;
+
+        Ok(Self {
+            name,
+            paths,
+            config,
+            modified: Mutex::new(modified),
+        })
+    }
+
+    pub fn config(&self) -> RustlsConfig {
+        self.config.clone()
+    }
+
+    pub async fn reload(&self) -> Result<(), Error> {
+        let modified = self.paths.modified().await?;
+
+        if *self.modified.lock().unwrap() == modified {
+            return Ok(());
+        }
+
+        // A failed reload keeps serving the previous configuration and is retried
+        self.config
+            .reload_from_pem_file(&self.paths.cert, &self.paths.key)
+            .await
+            .with_context(|| {
+                format!(
+                    "failed to reload tls configuration for the {} server",
+                    self.name
+                )
+            })?;
+
+        *self.modified.lock().unwrap() = modified;
+        info!(name = self.name, "reloaded tls configuration");
+
+        Ok(())
+    }
+}
+
+// Serves the router over TLS if a configuration is given, and over plain HTTP otherwise
+pub async fn serve(
+    addr: SocketAddr,
+    router: Router,
+    tls: Option<RustlsConfig>,
+    shutdown: CancellationToken,
+) -> Result<(), Error> {
+    let svc = router.into_make_service_with_connect_info::<SocketAddr>();
+
+    let config = match tls {
+        Some(config) => config,
+        None => {
+            return Server::bind(&addr)
+                .serve(svc)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .map_err(|err| anyhow!("server failed: {:?}", err));
+        }
+    };
+
+    let handle = Handle::new();
+
+    tokio::spawn({
+        let handle = handle.clone();
+
+        async move {
+            shutdown.cancelled().await;
+            handle.graceful_shutdown(None);
+        }
+    });
+
+    axum_server::bind_rustls(addr, config)
+        .handle(handle)
+        .serve(svc)
+        .await
+        .map_err(|err| anyhow!("server failed: {:?}", err))
+}
@@ -0,0 +1,339 @@
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::FromRequest,
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use candid::Principal;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+// Limit on the size of request bodies, unless configured otherwise
+pub const DEFAULT_MAX_BODY_SIZE: usize = 64 * 1024;
+
+const MAX_NAME_LEN: usize = 253;
+const MAX_LABEL_LEN: usize = 63;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl ToString) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    // serde names the offending field for unknown and missing fields only
+    fn from_serde(err: serde_json::Error) -> Self {
+        let msg = err.to_string();
+
+        let field = ["unknown field `", "missing field `"]
+            .iter()
+            .find_map(|prefix| msg.strip_prefix(prefix))
+            .and_then(|rest| rest.split_once('`'))
+            .map_or("body", |(field, _)| field);
+
+        Self::new(field, &msg)
+    }
+}
+
+#[derive(Serialize)]
+struct ValidationErrors {
+    errors: Vec<FieldError>,
+}
+
+fn reject(errors: Vec<FieldError>) -> Response {
+    (
+        StatusCode::UNPROCESSABLE_ENTITY,
+        Json(ValidationErrors { errors }),
+    )
+        .into_response()
+}
+
+// Checks the fields of a request body before it is deserialized, so that errors are reported per field
+pub trait Validate {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>);
+}
+
+// Optional bodies are validated when present
+impl<T: Validate> Validate for Option<T> {
+    fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+        T::validate(body, errors)
+    }
+}
+
+// A JSON body that is validated and deserialized strictly, rejecting the request with
+// field-level errors otherwise. An empty body is treated as `null`, e.g. for optional bodies.
+pub struct Valid<T>(pub T);
+
+#[async_trait]
+impl<T, S, B> FromRequest<S, B> for Valid<T>
+where
+    T: DeserializeOwned + Validate,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map_or(false, |v| v.starts_with("application/json"));
+
+        // Enforces the body size limit
+        let bs = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let body = if bs.is_empty() {
+            Value::Null
+        } else if !is_json {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expected request with `Content-Type: application/json`",
+            )
+                .into_response());
+        } else {
+            serde_json::from_slice(&bs).map_err(|err| reject(vec![FieldError::new("body", err)]))?
+        };
+
+        if let Value::Object(fields) = &body {
+            let mut errors = vec![];
+            T::validate(fields, &mut errors);
+
+            if !errors.is_empty() {
+                return Err(reject(errors));
+            }
+        }
+
+        serde_json::from_value(body)
+            .map(Valid)
+            .map_err(|err| reject(vec![FieldError::from_serde(err)]))
+    }
+}
+
+// Checks the syntax of a domain name, given in either its Unicode or A-label form
+pub fn validate_name(name: &str) -> Result<(), String> {
+    // Fully qualified names are accepted and stored without the trailing dot
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    let name = idna::domain_to_ascii(name).map_err(|_| "invalid domain name".to_string())?;
+
+    if name.len() > MAX_NAME_LEN {
+        return Err(format!("domain name exceeds {MAX_NAME_LEN} characters"));
+    }
+
+    if name.split('.').count() < 2 {
+        return Err("domain name must have at least two labels".into());
+    }
+
+    for label in name.split('.') {
+        if label.is_empty() {
+            return Err("domain name has an empty label".into());
+        }
+
+        if label.len() > MAX_LABEL_LEN {
+            return Err(format!(
+                "label '{label}' exceeds {MAX_LABEL_LEN} characters"
+            ));
+        }
+
+        if !label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(format!("label '{label}' contains invalid characters"));
+        }
+
+        if label.starts_with('-') || label.ends_with('-') {
+            return Err(format!("label '{label}' starts or ends with a hyphen"));
+        }
+    }
+
+    Ok(())
+}
+
+// Field checks, missing fields are left to deserialization
+pub fn name_field(body: &Map<String, Value>, field: &str, errors: &mut Vec<FieldError>) {
+    match body.get(field) {
+        None | Some(Value::Null) => {}
+        Some(Value::String(name)) => {
+            if let Err(err) = validate_name(name) {
+                errors.push(FieldError::new(field, err));
+            }
+        }
+        Some(_) => errors.push(FieldError::new(field, "expected a string")),
+    }
+}
+
+pub fn names_field(body: &Map<String, Value>, field: &str, errors: &mut Vec<FieldError>) {
+    match body.get(field) {
+        None | Some(Value::Null) => {}
+        Some(Value::Array(names)) => {
+            for (i, name) in names.iter().enumerate() {
+                let field = format!("{field}[{i}]");
+
+                match name {
+                    Value::String(name) => {
+                        if let Err(err) = validate_name(name) {
+                            errors.push(FieldError::new(&field, err));
+                        }
+                    }
+                    _ => errors.push(FieldError::new(&field, "expected a string")),
+                }
+            }
+        }
+        Some(_) => errors.push(FieldError::new(field, "expected an array")),
+    }
+}
+
+pub fn principal_field(body: &Map<String, Value>, field: &str, errors: &mut Vec<FieldError>) {
+    match body.get(field) {
+        None | Some(Value::Null) => {}
+        Some(Value::String(id)) => {
+            if let Err(err) = Principal::from_text(id) {
+                errors.push(FieldError::new(
+                    field,
+                    format!("invalid canister id: {err}"),
+                ));
+            }
+        }
+        Some(_) => errors.push(FieldError::new(field, "expected a string")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use anyhow::Error;
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TestRequest {
+        name: String,
+
+        #[serde(default)]
+        alt_names: Vec<String>,
+
+        canister: Option<Principal>,
+    }
+
+    impl Validate for TestRequest {
+        fn validate(body: &Map<String, Value>, errors: &mut Vec<FieldError>) {
+            name_field(body, "name", errors);
+            names_field(body, "alt_names", errors);
+            principal_field(body, "canister", errors);
+        }
+    }
+
+    async fn extract<T: DeserializeOwned + Validate>(body: &str) -> Result<T, (u16, Value)> {
+        let req = Request::builder()
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+
+        match Valid::<T>::from_request(req, &()).await {
+            Ok(Valid(v)) => Ok(v),
+            Err(resp) => {
+                let status = resp.status().as_u16();
+                let body = match resp.into_body().data().await {
+                    Some(Ok(bs)) => serde_json::from_slice(&bs).unwrap_or(Value::Null),
+                    _ => Value::Null,
+                };
+
+                Err((status, body))
+            }
+        }
+    }
+
+    #[test]
+    fn validate_names() {
+        for name in [
+            "example.com",
+            "www.example.com",
+            "münchen.de",
+            "a-b.example.com",
+            "Example.com.",
+        ] {
+            assert_eq!(validate_name(name), Ok(()), "{name}");
+        }
+
+        for name in [
+            "example",
+            "example..com",
+            "example.com..",
+            "-a.example.com",
+            "a_b.example.com",
+            "a b.example.com",
+            format!("{}.com", "a".repeat(64)).as_str(),
+        ] {
+            assert!(validate_name(name).is_err(), "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_valid() -> Result<(), Error> {
+        let req: TestRequest = extract(
+            r#"{"name": "example.com", "alt_names": ["www.example.com"], "canister": "aaaaa-aa"}"#,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(req.name, "example.com");
+        assert_eq!(req.alt_names, vec!["www.example.com"]);
+        assert_eq!(req.canister, Some(Principal::from_text("aaaaa-aa")?));
+
+        // Optional bodies may be empty
+        assert!(extract::<Option<TestRequest>>("").await.unwrap().is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn extract_field_errors() {
+        let (status, body) = extract::<TestRequest>(
+            r#"{"name": "example..com", "alt_names": ["ok.example.com", 1], "canister": "not-a-principal"}"#,
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(status, 422);
+
+        let fields: Vec<&str> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["field"].as_str().unwrap())
+            .collect();
+        assert_eq!(fields, vec!["name", "alt_names[1]", "canister"]);
+
+        // Schema violations
+        let (status, body) = extract::<TestRequest>(r#"{"name": "example.com", "other": 1}"#)
+            .await
+            .unwrap_err();
+
+        assert_eq!(status, 422);
+        assert_eq!(body["errors"][0]["field"], "other");
+
+        let (status, body) = extract::<TestRequest>(r#"{"alt_names": []}"#)
+            .await
+            .unwrap_err();
+
+        assert_eq!(status, 422);
+        assert_eq!(body["errors"][0]["field"], "name");
+
+        let (status, _) = extract::<TestRequest>("{").await.unwrap_err();
+        assert_eq!(status, 422);
+    }
+}
@@ -3,7 +3,7 @@ use std::{sync::Arc, time::SystemTime};
 use anyhow::{anyhow, Context, Error};
 use async_trait::async_trait;
 use candid::{Encode, Principal};
-use certificate_orchestrator_interface::{LABEL_DOMAINS, LEFT_GUARD, RIGHT_GUARD};
+use certificate_orchestrator_interface::{ExportFilter, LABEL_DOMAINS, LEFT_GUARD, RIGHT_GUARD};
 use ic_agent::{
     hash_tree::{HashTree, HashTreeNode, LookupResult},
     lookup_value, Agent, Certificate,
@@ -26,6 +26,7 @@ pub trait Verify: Sync + Send {
         &self,
         key: Option<String>,
         limit: u64,
+        filter: &ExportFilter,
         pkgs: &[Package],
         cert: &Certificate,
         tree: &HashTree<Vec<u8>>,
@@ -49,6 +50,7 @@ impl Verify for CertificateVerifier {
         &self,
         key: Option<String>,
         limit: u64,
+        filter: &ExportFilter,
         pkgs: &[Package],
         cert: &Certificate,
         tree: &HashTree<Vec<u8>>,
@@ -85,6 +87,33 @@ impl Verify for CertificateVerifier {
         )
         .context("failed to validate shared")?;
 
+        // Ensure package IDs are in ascending sorted order
+        if !pkgs.windows(2).all(|pair| pair[0].id < pair[1].id) {
+            return Err(anyhow!("packages are not sorted in ascending order").into());
+        }
+
+        // Ensure all packages pass verification
+        for pkg in pkgs {
+            validate_package(
+                tree,                                                // tree
+                &pkg.id,                                             // key
+                &Encode!(&pkg).context("failed to encode package")?, // val
+            )
+            .context("package failed validation")?;
+        }
+
+        // Filtered pages skip non-matching entries, so only the authenticity of the returned packages
+        // can be verified, but not the completeness of the page
+        if !filter.is_empty() {
+            if let (Some(key), Some(pkg)) = (key, pkgs.first()) {
+                if pkg.id <= key {
+                    return Err(anyhow!("first item lower/equal than key").into());
+                }
+            }
+
+            return Ok(());
+        }
+
         // Check that: (exactly one id <= key) xor (left guard is included in the certificate, no id <= key)
         let left_guard_present = validate_package(tree, LEFT_GUARD, &Vec::new()).is_ok();
 
@@ -122,21 +151,6 @@ impl Verify for CertificateVerifier {
             return Err(anyhow!("missing right guard").into());
         }
 
-        // Ensure package IDs are in ascending sorted order
-        if !pkgs.windows(2).all(|pair| pair[0].id < pair[1].id) {
-            return Err(anyhow!("packages are not sorted in ascending order").into());
-        }
-
-        // Ensure all packages pass verification
-        for pkg in pkgs {
-            validate_package(
-                tree,                                                // tree
-                &pkg.id,                                             // key
-                &Encode!(&pkg).context("failed to encode package")?, // val
-            )
-            .context("package failed validation")?;
-        }
-
         // Verify that no leaves were pruned in the provided hash tree
         let bool_vector = indicator_vector(tree.as_ref());
 
@@ -1,23 +1,37 @@
 use std::{
-    collections::HashSet, fmt, iter::once, sync::atomic::Ordering, sync::Arc, time::Duration,
+    collections::{HashMap, HashSet},
+    fmt,
+    future::Future,
+    iter::once,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, Context};
 use async_trait::async_trait;
 use candid::{Decode, Encode, Principal};
 use certificate_orchestrator_interface as ifc;
+use futures::future::try_join_all;
 use ic_agent::Agent;
-use opentelemetry::{baggage::BaggageExt, trace::FutureExt, KeyValue};
+use mockall::automock;
+use opentelemetry::{baggage::BaggageExt, metrics::Histogram, trace::FutureExt, KeyValue};
 use serde::Serialize;
-use trust_dns_resolver::{error::ResolveErrorKind, proto::rr::RecordType};
+use tokio::{
+    sync::Notify,
+    time::{sleep, timeout},
+};
+use tracing::warn;
 
 use crate::{
-    acme::{self, FinalizeError},
+    acme::{self, FinalizeError, KeyType},
+    caa::{CaaError, CheckCaa},
     certificate::{self, GetCert, GetCertError, Pair},
-    check::Check,
-    dns::{self, Resolve},
+    check::{Check, CheckError, Delegation},
+    dns,
+    inspect::{Inspect, InspectError},
+    propagation::{CheckPropagation, PropagationError},
     registration::{Id, Registration, State},
-    TASK_DELAY_SEC, TASK_ERROR_DELAY_SEC,
 };
 
 #[derive(Debug, Clone, Serialize)]
@@ -26,6 +40,9 @@ pub enum Action {
     Ready,
     Certificate,
     Renewal,
+
+    // Parked registrations are left alone until an operator retries or resumes them
+    Parked,
 }
 
 impl fmt::Display for Action {
@@ -41,6 +58,7 @@ impl From<State> for Action {
             State::PendingChallengeResponse => Action::Ready,
             State::PendingAcmeApproval => Action::Certificate,
             State::Available => Action::Renewal,
+            State::Parked(_) => Action::Parked,
         }
     }
 }
@@ -48,7 +66,61 @@ impl From<State> for Action {
 #[derive(Debug, Clone)]
 pub struct Task {
     pub name: String,
+    pub canister: Principal,
     pub action: Action,
+    pub key_type: Option<KeyType>,
+    pub alt_names: Vec<String>,
+
+    // Priority the task was queued with, kept by the tasks that follow up on it
+    pub priority: Priority,
+
+    // Held while the task is processed, unset for tasks dispensed from the cache
+    pub lease: Option<Lease>,
+}
+
+impl Task {
+    // All names covered by the certificate, starting with its primary name
+    pub fn names(&self) -> Vec<String> {
+        once(self.name.to_owned())
+            .chain(self.alt_names.iter().cloned())
+            .collect()
+    }
+}
+
+// Order in which due tasks are dispensed while there are more of them than workers,
+// e.g. so that renewals of certificates about to expire don't wait behind new registrations
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+impl From<ifc::TaskPriority> for Priority {
+    fn from(p: ifc::TaskPriority) -> Self {
+        match p {
+            ifc::TaskPriority::Low => Priority::Low,
+            ifc::TaskPriority::Normal => Priority::Normal,
+            ifc::TaskPriority::High => Priority::High,
+        }
+    }
+}
+
+impl From<Priority> for ifc::TaskPriority {
+    fn from(p: Priority) -> Self {
+        match p {
+            Priority::Low => ifc::TaskPriority::Low,
+            Priority::Normal => ifc::TaskPriority::Normal,
+            Priority::High => ifc::TaskPriority::High,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -59,9 +131,39 @@ pub enum QueueError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+#[automock]
 #[async_trait]
 pub trait Queue: Sync + Send {
-    async fn queue(&self, id: &Id, t: u64) -> Result<(), QueueError>;
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError>;
+}
+
+// Allows queuers to be shared, e.g. between decorators
+#[async_trait]
+impl<T: Queue + ?Sized> Queue for Arc<T> {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
+        (**self).queue(id, t, priority).await
+    }
+}
+
+// Wakes up the worker loop when a task is queued for immediate processing,
+// so newly created registrations don't have to wait for the next poll
+pub struct WithNotify<T>(pub T, pub Arc<Notify>);
+
+#[async_trait]
+impl<T: Queue> Queue for WithNotify<T> {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
+        self.0.queue(id, t, priority).await?;
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+
+        if t <= now {
+            self.1.notify_one();
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -77,6 +179,33 @@ pub trait Peek: Sync + Send {
     async fn peek(&self) -> Result<Id, PeekError>;
 }
 
+#[async_trait]
+impl<T: Peek + ?Sized> Peek for Arc<T> {
+    async fn peek(&self) -> Result<Id, PeekError> {
+        (**self).peek().await
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum CountError {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// Counts the tasks that are due, i.e. waiting to be dispensed
+#[automock]
+#[async_trait]
+pub trait Count: Sync + Send {
+    async fn count(&self) -> Result<u64, CountError>;
+}
+
+#[async_trait]
+impl<T: Count + ?Sized> Count for Arc<T> {
+    async fn count(&self) -> Result<u64, CountError> {
+        (**self).count().await
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DispenseError {
     #[error("No tasks available")]
@@ -90,6 +219,83 @@ pub trait Dispense: Sync + Send {
     async fn dispense(&self) -> Result<(Id, Task), DispenseError>;
 }
 
+#[async_trait]
+impl<T: Dispense + ?Sized> Dispense for Arc<T> {
+    async fn dispense(&self) -> Result<(Id, Task), DispenseError> {
+        (**self).dispense().await
+    }
+}
+
+// Exclusive hold of a dispensed task, so that no other issuer processes the registration
+// at the same time. Leases expire unless renewed, so the work of a crashed issuer is reclaimed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub id: String,
+    pub expires_at: u64,
+}
+
+impl From<ifc::Lease> for Lease {
+    fn from(lease: ifc::Lease) -> Self {
+        Self {
+            id: lease.id,
+            expires_at: lease.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LeaseError {
+    #[error("lease is no longer held")]
+    NotHeld,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+#[async_trait]
+pub trait Renew: Sync + Send {
+    async fn renew(&self, id: &Id, lease: &Lease) -> Result<Lease, LeaseError>;
+}
+
+#[async_trait]
+impl<T: Renew + ?Sized> Renew for Arc<T> {
+    async fn renew(&self, id: &Id, lease: &Lease) -> Result<Lease, LeaseError> {
+        (**self).renew(id, lease).await
+    }
+}
+
+#[async_trait]
+pub trait Release: Sync + Send {
+    async fn release(&self, id: &Id, lease: &Lease) -> Result<(), LeaseError>;
+}
+
+#[async_trait]
+impl<T: Release + ?Sized> Release for Arc<T> {
+    async fn release(&self, id: &Id, lease: &Lease) -> Result<(), LeaseError> {
+        (**self).release(id, lease).await
+    }
+}
+
+// Renews a lease ahead of its expiry for as long as it is held, returning once it is lost
+pub async fn hold_lease(renewer: &dyn Renew, id: &Id, mut lease: Lease) {
+    loop {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos() as u64);
+
+        // Leave room for a few failed attempts before the lease expires
+        let d = Duration::from_nanos(lease.expires_at.saturating_sub(now) / 3);
+        sleep(d.max(Duration::from_secs(1))).await;
+
+        match renewer.renew(id, &lease).await {
+            Ok(renewed) => lease = renewed,
+            Err(LeaseError::NotHeld) => return,
+            Err(LeaseError::UnexpectedError(err)) => {
+                warn!(%id, error = ?err, "failed to renew lease");
+            }
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ProcessError {
     #[error("awaiting creation of an acme order")]
@@ -101,33 +307,29 @@ pub enum ProcessError {
     #[error("awaiting acme approval for certificate order")]
     AwaitingAcmeOrderReady,
 
-    #[error("user configured configuration")]
-    FailedUserConfigurationCheck,
+    #[error("failed user configuration check: {0}")]
+    FailedUserConfigurationCheck(CheckError),
+
+    #[error("acme order became invalid")]
+    AcmeOrderInvalid,
+
+    #[error("awaiting acme rate limit budget, retrying in {0:?}")]
+    AwaitingRateLimitBudget(Duration),
 
     #[error(transparent)]
-    UnexpectedError(#[from] anyhow::Error),
-}
+    FailedCaaCheck(CaaError),
 
-impl From<&ProcessError> for Duration {
-    fn from(error: &ProcessError) -> Self {
-        match error {
-            ProcessError::AwaitingAcmeOrderCreation => {
-                Duration::from_secs(TASK_DELAY_SEC.load(Ordering::SeqCst))
-            }
-            ProcessError::AwaitingDnsPropagation => {
-                Duration::from_secs(TASK_DELAY_SEC.load(Ordering::SeqCst))
-            }
-            ProcessError::AwaitingAcmeOrderReady => {
-                Duration::from_secs(TASK_DELAY_SEC.load(Ordering::SeqCst))
-            }
-            ProcessError::FailedUserConfigurationCheck => {
-                Duration::from_secs(TASK_ERROR_DELAY_SEC.load(Ordering::SeqCst))
-            }
-            ProcessError::UnexpectedError(_) => {
-                Duration::from_secs(TASK_ERROR_DELAY_SEC.load(Ordering::SeqCst))
-            }
-        }
-    }
+    #[error("issued certificate failed verification: {0}")]
+    InvalidCertificate(InspectError),
+
+    #[error("{phase} phase timed out after {timeout:?}")]
+    PhaseTimeout {
+        phase: &'static str,
+        timeout: Duration,
+    },
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
 }
 
 #[async_trait]
@@ -139,10 +341,11 @@ pub struct CanisterQueuer(pub Arc<Agent>, pub Principal);
 
 #[async_trait]
 impl Queue for CanisterQueuer {
-    async fn queue(&self, id: &Id, t: u64) -> Result<(), QueueError> {
+    async fn queue(&self, id: &Id, t: u64, priority: Priority) -> Result<(), QueueError> {
         use ifc::{QueueTaskError as Error, QueueTaskResponse as Response};
 
-        let args = Encode!(id, &t).context("failed to encode arg")?;
+        let priority: Option<ifc::TaskPriority> = Some(priority.into());
+        let args = Encode!(id, &t, &priority).context("failed to encode arg")?;
 
         let resp = self
             .0
@@ -195,19 +398,48 @@ impl Peek for CanisterPeeker {
     }
 }
 
+pub struct CanisterCounter(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl Count for CanisterCounter {
+    async fn count(&self) -> Result<u64, CountError> {
+        use ifc::{CountTasksError as Error, CountTasksResponse as Response};
+
+        let args = Encode!().context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .query(&self.1, "countTasks")
+            .with_arg(args)
+            .call()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(n) => Ok(n),
+            Response::Err(err) => Err(match err {
+                Error::Unauthorized => CountError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => CountError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
 pub struct CanisterDispenser(pub Arc<Agent>, pub Principal);
 
 #[async_trait]
 impl Dispense for CanisterDispenser {
     async fn dispense(&self) -> Result<(Id, Task), DispenseError> {
-        let id = {
-            use ifc::{DispenseTaskError as Error, DispenseTaskResponse as Response};
+        let (id, priority, lease) = {
+            use ifc::{DispenseTaskError as Error, LeaseTaskResponse as Response};
 
             let args = Encode!().context("failed to encode arg")?;
 
             let resp = self
                 .0
-                .update(&self.1, "dispenseTask")
+                .update(&self.1, "leaseTask")
                 .with_arg(args)
                 .call_and_wait()
                 .await
@@ -216,7 +448,9 @@ impl Dispense for CanisterDispenser {
             let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
 
             match resp {
-                Response::Ok(id) => Ok(id),
+                Response::Ok((id, lease)) => {
+                    Ok((id, lease.priority.unwrap_or_default(), lease.into()))
+                }
                 Response::Err(err) => Err(match err {
                     Error::NoTasksAvailable => DispenseError::NoTasksAvailable,
                     Error::Unauthorized => DispenseError::UnexpectedError(anyhow!("unauthorized")),
@@ -254,97 +488,319 @@ impl Dispense for CanisterDispenser {
             id,
             Task {
                 name: reg.name,
+                canister: reg.canister,
                 action: reg.state.into(),
+                key_type: reg.key_type,
+                alt_names: reg.alt_names,
+                priority: priority.into(),
+                lease: Some(lease),
             },
         ))
     }
 }
 
+pub struct CanisterRenewer(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl Renew for CanisterRenewer {
+    async fn renew(&self, id: &Id, lease: &Lease) -> Result<Lease, LeaseError> {
+        use ifc::{RenewLeaseError as Error, RenewLeaseResponse as Response};
+
+        let args = Encode!(id, &lease.id).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .update(&self.1, "renewLease")
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(lease) => Ok(lease.into()),
+            Response::Err(err) => Err(match err {
+                Error::NotHeld => LeaseError::NotHeld,
+                Error::Unauthorized => LeaseError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => LeaseError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+pub struct CanisterReleaser(pub Arc<Agent>, pub Principal);
+
+#[async_trait]
+impl Release for CanisterReleaser {
+    async fn release(&self, id: &Id, lease: &Lease) -> Result<(), LeaseError> {
+        use ifc::{ReleaseLeaseError as Error, ReleaseLeaseResponse as Response};
+
+        let args = Encode!(id, &lease.id).context("failed to encode arg")?;
+
+        let resp = self
+            .0
+            .update(&self.1, "releaseLease")
+            .with_arg(args)
+            .call_and_wait()
+            .await
+            .context("failed to query canister")?;
+
+        let resp = Decode!(&resp, Response).context("failed to decode canister response")?;
+
+        match resp {
+            Response::Ok(()) => Ok(()),
+            Response::Err(err) => Err(match err {
+                Error::NotHeld => LeaseError::NotHeld,
+                Error::Unauthorized => LeaseError::UnexpectedError(anyhow!("unauthorized")),
+                Error::UnexpectedError(err) => LeaseError::UnexpectedError(anyhow!(err)),
+            }),
+        }
+    }
+}
+
+// Phases of processing a task, as timed by the Processor
+const PHASES: [&str; 10] = [
+    "caa-check",
+    "acme-order",
+    "dns-create",
+    "propagation-wait",
+    "acme-ready",
+    "acme-finalize",
+    "dns-cleanup",
+    "verify",
+    "upload",
+    "check",
+];
+
+// Timeout of a single phase, as `<phase>=<sec>`
+#[derive(Clone, Debug, PartialEq)]
+pub struct PhaseTimeout(pub String, pub Duration);
+
+impl FromStr for PhaseTimeout {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (phase, secs) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected <phase>=<sec>"))?;
+
+        if !PHASES.contains(&phase) {
+            return Err(anyhow!("unknown phase {phase}"));
+        }
+
+        let secs: u64 = secs.parse().context("invalid timeout")?;
+
+        Ok(Self(phase.into(), Duration::from_secs(secs)))
+    }
+}
+
+// How long each phase may take before the task fails, so that a stuck phase
+// doesn't hold on to a worker indefinitely. A zero timeout means no limit.
+#[derive(Clone, Debug, Default)]
+pub struct PhaseTimeouts {
+    default: Duration,
+    overrides: HashMap<String, Duration>,
+}
+
+impl PhaseTimeouts {
+    pub fn new(default: Duration, overrides: Vec<PhaseTimeout>) -> Self {
+        Self {
+            default,
+            overrides: overrides
+                .into_iter()
+                .map(|PhaseTimeout(phase, timeout)| (phase, timeout))
+                .collect(),
+        }
+    }
+
+    fn get(&self, phase: &str) -> Option<Duration> {
+        let d = self.overrides.get(phase).copied().unwrap_or(self.default);
+        (!d.is_zero()).then_some(d)
+    }
+}
+
 pub struct Processor {
     // configuration
-    delegation_domain: String,
+    delegation: Arc<Delegation>,
+    key_type: KeyType, // used for registrations without a key type
+    phase_timeouts: PhaseTimeouts,
 
     // dependencies
     checker: Arc<dyn Check>,
-    resolver: Box<dyn Resolve>,
+    propagation_checker: Box<dyn CheckPropagation>,
+    caa_checker: Box<dyn CheckCaa>,
     acme_order: Box<dyn acme::Order>,
     acme_ready: Box<dyn acme::Ready>,
     acme_finalize: Box<dyn acme::Finalize>,
     dns_creator: Box<dyn dns::Create>,
     dns_deleter: Box<dyn dns::Delete>,
+    certificate_inspector: Box<dyn Inspect>,
     certificate_uploader: Box<dyn certificate::Upload>,
+
+    // Records how long each phase of processing a task takes
+    phase_recorder: Histogram<f64>,
 }
 
 impl Processor {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        delegation_domain: String,
+        delegation: Arc<Delegation>,
+        key_type: KeyType,
+        phase_timeouts: PhaseTimeouts,
         checker: Arc<dyn Check>,
-        resolver: Box<dyn Resolve>,
+        propagation_checker: Box<dyn CheckPropagation>,
+        caa_checker: Box<dyn CheckCaa>,
         acme_order: Box<dyn acme::Order>,
         acme_ready: Box<dyn acme::Ready>,
         acme_finalize: Box<dyn acme::Finalize>,
         dns_creator: Box<dyn dns::Create>,
         dns_deleter: Box<dyn dns::Delete>,
+        certificate_inspector: Box<dyn Inspect>,
         certificate_uploader: Box<dyn certificate::Upload>,
+        phase_recorder: Histogram<f64>,
     ) -> Self {
         Self {
-            delegation_domain,
+            delegation,
+            key_type,
+            phase_timeouts,
             checker,
-            resolver,
+            propagation_checker,
+            caa_checker,
             acme_order,
             acme_ready,
             acme_finalize,
             dns_creator,
             dns_deleter,
+            certificate_inspector,
             certificate_uploader,
+            phase_recorder,
         }
     }
+
+    // Times a phase of processing a task, so that latency regressions can be attributed to a phase,
+    // and fails it once it exceeds its timeout
+    async fn phase<T>(
+        &self,
+        phase: &'static str,
+        f: impl Future<Output = Result<T, ProcessError>>,
+    ) -> Result<T, ProcessError> {
+        let start_time = Instant::now();
+
+        let out = match self.phase_timeouts.get(phase) {
+            Some(d) => timeout(d, f)
+                .await
+                .unwrap_or(Err(ProcessError::PhaseTimeout { phase, timeout: d })),
+            None => f.await,
+        };
+
+        let status = match &out {
+            Ok(_) => "ok",
+            Err(ProcessError::AwaitingDnsPropagation | ProcessError::AwaitingAcmeOrderReady) => {
+                "pending"
+            }
+            Err(ProcessError::PhaseTimeout { .. }) => "timeout",
+            Err(_) => "fail",
+        };
+
+        self.phase_recorder.record(
+            start_time.elapsed().as_secs_f64(),
+            &[
+                KeyValue::new("phase", phase),
+                KeyValue::new("status", status),
+            ],
+        );
+
+        out
+    }
 }
 
 #[async_trait]
 impl Process for Processor {
     async fn process(&self, id: &Id, task: &Task) -> Result<(), ProcessError> {
+        let names = task.names();
+
         match task.action {
             Action::Order => {
+                // Phase 4 - Ensure the ACME provider is allowed to issue a certificate
+                self.phase("caa-check", async {
+                    for name in &names {
+                        self.caa_checker
+                            .check_caa(name)
+                            .await
+                            .map_err(|err| match err {
+                                CaaError::UnexpectedError(err) => {
+                                    ProcessError::UnexpectedError(err)
+                                }
+                                err => ProcessError::FailedCaaCheck(err),
+                            })?;
+                    }
+
+                    Ok(())
+                })
+                .await?;
+
                 // Phase 5 - Initiate certificate generation via ACME provider
-                let challenge_key = self
-                    .acme_order
-                    .order(&task.name)
-                    .await
-                    .context("failed to create acme order")?;
-
-                // Phase 6 - Create DNS record with challenge response
-                self.dns_creator
-                    .create(
-                        &self.delegation_domain,
-                        &format!("_acme-challenge.{}", task.name),
-                        dns::Record::Txt(challenge_key),
-                    )
-                    .await
-                    .context("failed to create dns record")?;
+                let challenge_keys = self
+                    .phase("acme-order", async {
+                        Ok(self
+                            .acme_order
+                            .order(&names)
+                            .await
+                            .context("failed to create acme order")?)
+                    })
+                    .await?;
+
+                // Phase 6 - Create DNS records with challenge responses, concurrently for orders with multiple names
+                self.phase("dns-create", async {
+                    try_join_all(challenge_keys.into_iter().map(
+                        |(name, challenge_key)| async move {
+                            self.dns_creator
+                                .create(
+                                    self.delegation.domain(),
+                                    &self.delegation.record(&name, &task.canister),
+                                    dns::Record::Txt(challenge_key),
+                                )
+                                .await
+                                .context("failed to create dns record")
+                        },
+                    ))
+                    .await?;
+
+                    Ok(())
+                })
+                .await?;
 
                 Err(ProcessError::AwaitingDnsPropagation)
             }
 
             Action::Ready => {
-                // Phase 7 - Ensure DNS TXT record has propagated
-                self.resolver
-                    .lookup(&format!("_acme-challenge.{}", task.name), RecordType::TXT)
+                // Phase 7 - Ensure DNS records have propagated, checking all names in parallel
+                self.phase("propagation-wait", async {
+                    try_join_all(names.iter().map(|name| {
+                        self.propagation_checker
+                            .check_propagation(name, &task.canister)
+                    }))
                     .await
-                    .map_err(|err| match err.kind() {
-                        ResolveErrorKind::NoRecordsFound { .. } => {
+                    .map_err(|err| match err {
+                        PropagationError::NotPropagated { .. } => {
                             ProcessError::AwaitingDnsPropagation
                         }
-                        _ => ProcessError::UnexpectedError(anyhow!(
-                            "failed to resolve TXT record: {err}"
-                        )),
                     })?;
 
+                    Ok(())
+                })
+                .await?;
+
                 // Phase 8 - Mark ACME order as ready
-                self.acme_ready
-                    .ready(&task.name)
-                    .await
-                    .context("failed to mark acme order as ready")?;
+                self.phase("acme-ready", async {
+                    Ok(self
+                        .acme_ready
+                        .ready(&names)
+                        .await
+                        .context("failed to mark acme order as ready")?)
+                })
+                .await?;
 
                 Err(ProcessError::AwaitingAcmeOrderReady)
             }
@@ -352,46 +808,86 @@ impl Process for Processor {
             Action::Certificate => {
                 // Phase 9 - Obtain the certificate once the order is finalized
                 let (certificate_chain_pem, private_key_pem) = self
-                    .acme_finalize
-                    .finalize(&task.name)
-                    .await
-                    .map_err(|err| match err {
-                        FinalizeError::OrderNotReady(_) => ProcessError::AwaitingAcmeOrderReady,
-                        FinalizeError::UnexpectedError(err) => err.into(),
-                    })?;
+                    .phase("acme-finalize", async {
+                        self.acme_finalize
+                            .finalize(&names, task.key_type.unwrap_or(self.key_type))
+                            .await
+                            .map_err(|err| match err {
+                                FinalizeError::OrderNotReady(_) => {
+                                    ProcessError::AwaitingAcmeOrderReady
+                                }
+                                FinalizeError::OrderInvalid => ProcessError::AcmeOrderInvalid,
+                                FinalizeError::UnexpectedError(err) => err.into(),
+                            })
+                    })
+                    .await?;
+
+                // Phase 10 - Remove DNS records with challenge responses
+                self.phase("dns-cleanup", async {
+                    for name in &names {
+                        self.dns_deleter
+                            .delete(
+                                self.delegation.domain(),
+                                &self.delegation.record(name, &task.canister),
+                            )
+                            .await
+                            .context("failed to delete dns record")?;
+                    }
+
+                    Ok(())
+                })
+                .await?;
 
-                // Phase 10 - Remove DNS record with challenge response
-                self.dns_deleter
-                    .delete(
-                        &self.delegation_domain,
-                        &format!("_acme-challenge.{}", task.name),
-                    )
-                    .await
-                    .context("failed to delete dns record")?;
-
-                // Phase 11 - Upload certificates
-                self.certificate_uploader
-                    .upload(
-                        id,
-                        Pair(
-                            private_key_pem.into_bytes(),
-                            certificate_chain_pem.into_bytes(),
-                        ),
-                    )
-                    .await
-                    .context("failed to upload certificates")?;
+                // Phase 11 - Verify the certificate chain, so that a faulty certificate is never distributed
+                self.phase("verify", async {
+                    self.certificate_inspector
+                        .inspect(&names, certificate_chain_pem.as_bytes())
+                        .map_err(ProcessError::InvalidCertificate)
+                })
+                .await?;
+
+                // Phase 12 - Upload certificates
+                self.phase("upload", async {
+                    Ok(self
+                        .certificate_uploader
+                        .upload(
+                            id,
+                            Pair(
+                                private_key_pem.into_bytes(),
+                                certificate_chain_pem.into_bytes(),
+                            ),
+                        )
+                        .await
+                        .context("failed to upload certificates")?)
+                })
+                .await?;
 
                 Ok(())
             }
 
-            Action::Renewal => match self.checker.check(&task.name).await {
+            Action::Renewal => {
                 // Renewal - Before trying to renew the certificate of a domain,
                 // the issuer needs to check whether the domain and canister
                 // is still correctly configured (e.g., the DNS records are in place
                 // to delegate the ACME challenge to the delegation domain).
-                Ok(_) => Err(ProcessError::AwaitingAcmeOrderCreation),
-                Err(_err) => Err(ProcessError::FailedUserConfigurationCheck),
-            },
+                self.phase("check", async {
+                    for name in &names {
+                        if let Err(err) = self.checker.check(name).await {
+                            return Err(ProcessError::FailedUserConfigurationCheck(err));
+                        }
+                    }
+
+                    Ok(())
+                })
+                .await?;
+
+                Err(ProcessError::AwaitingAcmeOrderCreation)
+            }
+
+            // Tasks of parked registrations are skipped by the worker
+            Action::Parked => Err(ProcessError::UnexpectedError(anyhow!(
+                "registration is parked"
+            ))),
         }
     }
 }
@@ -472,30 +968,49 @@ mod tests {
 
     use anyhow::Error;
     use mockall::predicate;
-    use trust_dns_resolver::{
-        lookup::Lookup,
-        proto::{op::Query, rr::Record as TrustRecord},
-        Name,
-    };
 
     use crate::{
         acme::{MockFinalize, MockOrder, MockReady},
+        caa::MockCheckCaa,
         certificate::MockUpload,
         check::{CheckError, MockCheck},
-        dns::{MockCreate, MockDelete, MockResolve, Record},
+        dns::{MockCreate, MockDelete, Record},
+        inspect::MockInspect,
+        propagation::MockCheckPropagation,
     };
 
+    // Phase durations are not asserted on, so they are recorded by a no-op meter
+    fn phase_recorder() -> Histogram<f64> {
+        opentelemetry::global::meter("test")
+            .f64_histogram("phase")
+            .init()
+    }
+
     #[tokio::test]
     async fn test_process_order() -> Result<(), Error> {
         let id: String = "id".into();
 
         let task = Task {
             name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
             action: Action::Order,
+            key_type: None,
+            alt_names: vec!["www.name".into()],
+            priority: Priority::Normal,
+            lease: None,
         };
 
-        let mut resolver = MockResolve::new();
-        resolver.expect_lookup().never();
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        for name in ["name", "www.name"] {
+            caa_checker
+                .expect_check_caa()
+                .times(1)
+                .with(predicate::eq(name))
+                .returning(|_| Ok(()));
+        }
 
         let mut checker = MockCheck::new();
         checker.expect_check().never();
@@ -504,8 +1019,13 @@ mod tests {
         acme_order
             .expect_order()
             .times(1)
-            .with(predicate::eq("name"))
-            .returning(|_| Ok("token".into()));
+            .withf(|names| names == ["name", "www.name"])
+            .returning(|_| {
+                Ok(vec![
+                    ("name".into(), "token".into()),
+                    ("www.name".into(), "www-token".into()),
+                ])
+            });
 
         let mut acme_ready = MockReady::new();
         acme_ready.expect_ready().never();
@@ -514,32 +1034,45 @@ mod tests {
         acme_finalize.expect_finalize().never();
 
         let mut dns_creator = MockCreate::new();
-        dns_creator
-            .expect_create()
-            .times(1)
-            .with(
-                predicate::eq("delegation"),
-                predicate::eq("_acme-challenge.name"),
-                predicate::eq(Record::Txt("token".into())),
-            )
-            .returning(|_, _, _| Ok(()));
+        for (name, token) in [
+            ("_acme-challenge.name", "token"),
+            ("_acme-challenge.www.name", "www-token"),
+        ] {
+            dns_creator
+                .expect_create()
+                .times(1)
+                .with(
+                    predicate::eq("delegation"),
+                    predicate::eq(name),
+                    predicate::eq(Record::Txt(token.into())),
+                )
+                .returning(|_, _, _| Ok(()));
+        }
 
         let mut dns_deleter = MockDelete::new();
         dns_deleter.expect_delete().never();
 
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector.expect_inspect().never();
+
         let mut certificate_uploader = MockUpload::new();
         certificate_uploader.expect_upload().never();
 
         let processor = Processor::new(
-            "delegation".into(),            // delegation_domain
-            Arc::new(checker),              // checker
-            Box::new(resolver),             // resolver
-            Box::new(acme_order),           // acme_order
-            Box::new(acme_ready),           // acme_ready
-            Box::new(acme_finalize),        // acme_finalize
-            Box::new(dns_creator),          // dns_creator
-            Box::new(dns_deleter),          // dns_deleter
-            Box::new(certificate_uploader), // certificate_uploader
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
         );
 
         match processor.process(&id, &task).await {
@@ -551,34 +1084,108 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_process_order_caa_unauthorized() -> Result<(), Error> {
+        let id: String = "id".into();
+
+        let task = Task {
+            name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
+            action: Action::Order,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
+        };
+
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker
+            .expect_check_caa()
+            .times(1)
+            .with(predicate::eq("name"))
+            .returning(|_| {
+                Err(CaaError::Unauthorized {
+                    src: "name".into(),
+                    identities: "letsencrypt.org".into(),
+                })
+            });
+
+        let mut checker = MockCheck::new();
+        checker.expect_check().never();
+
+        let mut acme_order = MockOrder::new();
+        acme_order.expect_order().never();
+
+        let mut acme_ready = MockReady::new();
+        acme_ready.expect_ready().never();
+
+        let mut acme_finalize = MockFinalize::new();
+        acme_finalize.expect_finalize().never();
+
+        let mut dns_creator = MockCreate::new();
+        dns_creator.expect_create().never();
+
+        let mut dns_deleter = MockDelete::new();
+        dns_deleter.expect_delete().never();
+
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector.expect_inspect().never();
+
+        let mut certificate_uploader = MockUpload::new();
+        certificate_uploader.expect_upload().never();
+
+        let processor = Processor::new(
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
+        );
+
+        match processor.process(&id, &task).await {
+            Err(ProcessError::FailedCaaCheck(_)) => Ok(()),
+            other => Err(anyhow!("expected FailedCaaCheck but got {:?}", other)),
+        }
+    }
+
     #[tokio::test]
     async fn test_process_ready() -> Result<(), Error> {
         let id: String = "id".into();
 
         let task = Task {
             name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
             action: Action::Ready,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
         };
 
-        let mut resolver = MockResolve::new();
-        resolver
-            .expect_lookup()
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker
+            .expect_check_propagation()
             .times(1)
             .with(
-                predicate::eq("_acme-challenge.name"),
-                predicate::eq(RecordType::TXT),
+                predicate::eq("name"),
+                predicate::eq(Principal::from_text("aaaaa-aa").unwrap()),
             )
-            .returning(|_, _| {
-                Ok({
-                    let q = Query::new();
-
-                    let mut r = TrustRecord::new();
-                    r.set_name(Name::from_utf8("token")?);
-                    r.set_record_type(RecordType::TXT);
+            .returning(|_, _| Ok(()));
 
-                    Lookup::new_with_max_ttl(q, Arc::new([r]))
-                })
-            });
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker.expect_check_caa().never();
 
         let mut checker = MockCheck::new();
         checker.expect_check().never();
@@ -590,7 +1197,7 @@ mod tests {
         acme_ready
             .expect_ready()
             .times(1)
-            .with(predicate::eq("name"))
+            .withf(|names| names == ["name"])
             .returning(|_| Ok(()));
 
         let mut acme_finalize = MockFinalize::new();
@@ -602,19 +1209,27 @@ mod tests {
         let mut dns_deleter = MockDelete::new();
         dns_deleter.expect_delete().never();
 
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector.expect_inspect().never();
+
         let mut certificate_uploader = MockUpload::new();
         certificate_uploader.expect_upload().never();
 
         let processor = Processor::new(
-            "delegation".into(),            // delegation_domain
-            Arc::new(checker),              // checker
-            Box::new(resolver),             // resolver
-            Box::new(acme_order),           // acme_order
-            Box::new(acme_ready),           // acme_ready
-            Box::new(acme_finalize),        // acme_finalize
-            Box::new(dns_creator),          // dns_creator
-            Box::new(dns_deleter),          // dns_deleter
-            Box::new(certificate_uploader), // certificate_uploader
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
         );
 
         match processor.process(&id, &task).await {
@@ -632,11 +1247,19 @@ mod tests {
 
         let task = Task {
             name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
             action: Action::Certificate,
+            key_type: Some(KeyType::Rsa4096),
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
         };
 
-        let mut resolver = MockResolve::new();
-        resolver.expect_lookup().never();
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker.expect_check_caa().never();
 
         let mut checker = MockCheck::new();
         checker.expect_check().never();
@@ -651,8 +1274,8 @@ mod tests {
         acme_finalize
             .expect_finalize()
             .times(1)
-            .with(predicate::eq("name"))
-            .returning(|_| Ok(("cert".into(), "key".into())));
+            .withf(|names, key_type| names == ["name"] && *key_type == KeyType::Rsa4096)
+            .returning(|_, _| Ok(("cert".into(), "key".into())));
 
         let mut dns_creator = MockCreate::new();
         dns_creator.expect_create().never();
@@ -667,6 +1290,15 @@ mod tests {
             )
             .returning(|_, _| Ok(()));
 
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector
+            .expect_inspect()
+            .times(1)
+            .withf(|names, certificate_chain_pem| {
+                names == ["name"] && certificate_chain_pem == b"cert"
+            })
+            .returning(|_, _| Ok(()));
+
         let mut certificate_uploader = MockUpload::new();
         certificate_uploader
             .expect_upload()
@@ -678,15 +1310,20 @@ mod tests {
             .returning(|_, _| Ok(()));
 
         let processor = Processor::new(
-            "delegation".into(),            // delegation_domain
-            Arc::new(checker),              // checker
-            Box::new(resolver),             // resolver
-            Box::new(acme_order),           // acme_order
-            Box::new(acme_ready),           // acme_ready
-            Box::new(acme_finalize),        // acme_finalize
-            Box::new(dns_creator),          // dns_creator
-            Box::new(dns_deleter),          // dns_deleter
-            Box::new(certificate_uploader), // certificate_uploader
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
         );
 
         match processor.process(&id, &task).await {
@@ -695,17 +1332,149 @@ mod tests {
         }
     }
 
+    #[tokio::test]
+    async fn test_process_invalid_certificate() -> Result<(), Error> {
+        let id: String = "id".into();
+
+        let task = Task {
+            name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
+            action: Action::Certificate,
+            key_type: Some(KeyType::Rsa4096),
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
+        };
+
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker.expect_check_caa().never();
+
+        let mut checker = MockCheck::new();
+        checker.expect_check().never();
+
+        let mut acme_order = MockOrder::new();
+        acme_order.expect_order().never();
+
+        let mut acme_ready = MockReady::new();
+        acme_ready.expect_ready().never();
+
+        let mut acme_finalize = MockFinalize::new();
+        acme_finalize
+            .expect_finalize()
+            .times(1)
+            .withf(|names, key_type| names == ["name"] && *key_type == KeyType::Rsa4096)
+            .returning(|_, _| Ok(("cert".into(), "key".into())));
+
+        let mut dns_creator = MockCreate::new();
+        dns_creator.expect_create().never();
+
+        let mut dns_deleter = MockDelete::new();
+        dns_deleter
+            .expect_delete()
+            .times(1)
+            .with(
+                predicate::eq("delegation"),
+                predicate::eq("_acme-challenge.name"),
+            )
+            .returning(|_, _| Ok(()));
+
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector
+            .expect_inspect()
+            .times(1)
+            .returning(|_, _| Err(InspectError::NameNotCovered("name".into())));
+
+        let mut certificate_uploader = MockUpload::new();
+        certificate_uploader.expect_upload().never();
+
+        let processor = Processor::new(
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
+        );
+
+        match processor.process(&id, &task).await {
+            Err(ProcessError::InvalidCertificate(_)) => Ok(()),
+            other => Err(anyhow!("expected InvalidCertificate but got {:?}", other)),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_phase_timeout() -> Result<(), Error> {
+        let phase_timeouts =
+            PhaseTimeouts::new(Duration::from_millis(10), vec!["upload=60".parse()?]);
+
+        let processor = Processor::new(
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            phase_timeouts,                                       // phase_timeouts
+            Arc::new(MockCheck::new()),                           // checker
+            Box::new(MockCheckPropagation::new()),                // propagation_checker
+            Box::new(MockCheckCaa::new()),                        // caa_checker
+            Box::new(MockOrder::new()),                           // acme_order
+            Box::new(MockReady::new()),                           // acme_ready
+            Box::new(MockFinalize::new()),                        // acme_finalize
+            Box::new(MockCreate::new()),                          // dns_creator
+            Box::new(MockDelete::new()),                          // dns_deleter
+            Box::new(MockInspect::new()),                         // certificate_inspector
+            Box::new(MockUpload::new()),                          // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
+        );
+
+        let stuck = || async {
+            sleep(Duration::from_millis(50)).await;
+            Ok(())
+        };
+
+        match processor.phase("propagation-wait", stuck()).await {
+            Err(ProcessError::PhaseTimeout {
+                phase: "propagation-wait",
+                timeout,
+            }) => assert_eq!(timeout, Duration::from_millis(10)),
+            other => return Err(anyhow!("expected PhaseTimeout but got {:?}", other)),
+        }
+
+        // Phases with a timeout of their own are not subject to the default
+        processor.phase("upload", stuck()).await?;
+
+        assert!("unknown=60".parse::<PhaseTimeout>().is_err());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_failed_renewal_check() -> Result<(), Error> {
         let id: String = "id".into();
 
         let task = Task {
             name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
             action: Action::Renewal,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
         };
 
-        let mut resolver = MockResolve::new();
-        resolver.expect_lookup().never();
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker.expect_check_caa().never();
 
         let mut checker = MockCheck::new();
         checker
@@ -733,23 +1502,31 @@ mod tests {
         let mut dns_deleter = MockDelete::new();
         dns_deleter.expect_delete().never();
 
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector.expect_inspect().never();
+
         let mut certificate_uploader = MockUpload::new();
         certificate_uploader.expect_upload().never();
 
         let processor = Processor::new(
-            "delegation".into(),            // delegation_domain
-            Arc::new(checker),              // checker
-            Box::new(resolver),             // resolver
-            Box::new(acme_order),           // acme_order
-            Box::new(acme_ready),           // acme_ready
-            Box::new(acme_finalize),        // acme_finalize
-            Box::new(dns_creator),          // dns_creator
-            Box::new(dns_deleter),          // dns_deleter
-            Box::new(certificate_uploader), // certificate_uploader
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
         );
 
         match processor.process(&id, &task).await {
-            Err(ProcessError::FailedUserConfigurationCheck) => Ok(()),
+            Err(ProcessError::FailedUserConfigurationCheck(_)) => Ok(()),
             other => Err(anyhow!(
                 "expected FailedUserConfigurationCheck but got {:?}",
                 other
@@ -763,11 +1540,19 @@ mod tests {
 
         let task = Task {
             name: "name".into(),
+            canister: Principal::from_text("aaaaa-aa").unwrap(),
             action: Action::Renewal,
+            key_type: None,
+            alt_names: vec![],
+            priority: Priority::Normal,
+            lease: None,
         };
 
-        let mut resolver = MockResolve::new();
-        resolver.expect_lookup().never();
+        let mut propagation_checker = MockCheckPropagation::new();
+        propagation_checker.expect_check_propagation().never();
+
+        let mut caa_checker = MockCheckCaa::new();
+        caa_checker.expect_check_caa().never();
 
         let mut checker = MockCheck::new();
         checker
@@ -791,19 +1576,27 @@ mod tests {
         let mut dns_deleter = MockDelete::new();
         dns_deleter.expect_delete().never();
 
+        let mut certificate_inspector = MockInspect::new();
+        certificate_inspector.expect_inspect().never();
+
         let mut certificate_uploader = MockUpload::new();
         certificate_uploader.expect_upload().never();
 
         let processor = Processor::new(
-            "delegation".into(),            // delegation_domain
-            Arc::new(checker),              // checker
-            Box::new(resolver),             // resolver
-            Box::new(acme_order),           // acme_order
-            Box::new(acme_ready),           // acme_ready
-            Box::new(acme_finalize),        // acme_finalize
-            Box::new(dns_creator),          // dns_creator
-            Box::new(dns_deleter),          // dns_deleter
-            Box::new(certificate_uploader), // certificate_uploader
+            Arc::new(Delegation::new("delegation".into(), None)), // delegation
+            KeyType::EcdsaP256,                                   // key_type
+            PhaseTimeouts::default(),                             // phase_timeouts
+            Arc::new(checker),                                    // checker
+            Box::new(propagation_checker),                        // propagation_checker
+            Box::new(caa_checker),                                // caa_checker
+            Box::new(acme_order),                                 // acme_order
+            Box::new(acme_ready),                                 // acme_ready
+            Box::new(acme_finalize),                              // acme_finalize
+            Box::new(dns_creator),                                // dns_creator
+            Box::new(dns_deleter),                                // dns_deleter
+            Box::new(certificate_inspector),                      // certificate_inspector
+            Box::new(certificate_uploader),                       // certificate_uploader
+            phase_recorder(),                                     // phase_recorder
         );
 
         match processor.process(&id, &task).await {
@@ -828,4 +1621,43 @@ mod tests {
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_notify_on_immediate_task() -> Result<(), Error> {
+        struct NopQueuer;
+
+        #[async_trait]
+        impl Queue for NopQueuer {
+            async fn queue(
+                &self,
+                _id: &Id,
+                _t: u64,
+                _priority: Priority,
+            ) -> Result<(), QueueError> {
+                Ok(())
+            }
+        }
+
+        let notify = Arc::new(Notify::new());
+        let queuer = WithNotify(NopQueuer, notify.clone());
+
+        // Tasks scheduled in the future don't wake up the worker
+        queuer
+            .queue(&"id".into(), u64::MAX, Priority::Normal)
+            .await?;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), notify.notified())
+                .await
+                .is_err()
+        );
+
+        queuer.queue(&"id".into(), 0, Priority::Normal).await?;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), notify.notified())
+                .await
+                .is_ok()
+        );
+
+        Ok(())
+    }
 }
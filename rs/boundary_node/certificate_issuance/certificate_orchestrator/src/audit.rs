@@ -0,0 +1,204 @@
+use anyhow::anyhow;
+use certificate_orchestrator_interface::{AuditEntry, Id};
+use ic_cdk::caller;
+use prometheus::labels;
+
+use crate::{
+    acl::{Authorize, AuthorizeError, WithAuthorize},
+    LocalRef, StableMap, WithMetrics,
+};
+
+// Same bound as a stored registration id
+const MAX_ID_LEN: usize = 64;
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditError {
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub trait Record {
+    fn record(&self, entry: AuditEntry) -> Result<u64, AuditError>;
+}
+
+// Appends entries to the audit log, which is never modified otherwise
+pub struct Recorder {
+    entries: LocalRef<StableMap<u64, AuditEntry>>,
+}
+
+impl Recorder {
+    pub fn new(entries: LocalRef<StableMap<u64, AuditEntry>>) -> Self {
+        Self { entries }
+    }
+}
+
+impl Record for Recorder {
+    fn record(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        if entry.id.len() > MAX_ID_LEN {
+            return Err(anyhow!("id exceeds {MAX_ID_LEN} characters").into());
+        }
+
+        self.entries.with(|entries| {
+            let mut entries = entries.borrow_mut();
+
+            let seq = entries.last_key_value().map_or(0, |(seq, _)| seq + 1);
+            entries.insert(seq, entry);
+
+            Ok(seq)
+        })
+    }
+}
+
+impl<T: Record, A: Authorize> Record for WithAuthorize<T, A> {
+    fn record(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => AuditError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => AuditError::UnexpectedError(err),
+            });
+        };
+
+        self.0.record(entry)
+    }
+}
+
+impl<T: Record> Record for WithMetrics<T> {
+    fn record(&self, entry: AuditEntry) -> Result<u64, AuditError> {
+        let out = self.0.record(entry);
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            AuditError::Unauthorized => "unauthorized",
+                            AuditError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
+pub trait ListAudit {
+    fn list(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<(u64, AuditEntry)>, AuditError>;
+}
+
+pub struct AuditLister {
+    entries: LocalRef<StableMap<u64, AuditEntry>>,
+}
+
+impl AuditLister {
+    pub fn new(entries: LocalRef<StableMap<u64, AuditEntry>>) -> Self {
+        Self { entries }
+    }
+}
+
+impl ListAudit for AuditLister {
+    fn list(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<(u64, AuditEntry)>, AuditError> {
+        let start = match after {
+            Some(after) => match after.checked_add(1) {
+                Some(start) => start,
+                None => return Ok(vec![]),
+            },
+            None => 0,
+        };
+
+        Ok(self.entries.with(|entries| {
+            entries
+                .borrow()
+                .range(start..)
+                .filter(|(_, entry)| id.as_ref().map_or(true, |id| &entry.id == id))
+                .take(limit as usize)
+                .collect()
+        }))
+    }
+}
+
+impl<T: ListAudit, A: Authorize> ListAudit for WithAuthorize<T, A> {
+    fn list(
+        &self,
+        id: Option<Id>,
+        after: Option<u64>,
+        limit: u64,
+    ) -> Result<Vec<(u64, AuditEntry)>, AuditError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => AuditError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => AuditError::UnexpectedError(err),
+            });
+        };
+
+        self.0.list(id, after, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use certificate_orchestrator_interface::{AuditAction, State};
+
+    use crate::AUDIT_LOG;
+
+    fn entry(id: &str) -> AuditEntry {
+        AuditEntry {
+            timestamp: 0,
+            actor: "issuer".into(),
+            id: id.into(),
+            action: AuditAction::Update,
+            from_state: Some(State::PendingOrder),
+            to_state: Some(State::Available),
+            cause: "task Order".into(),
+        }
+    }
+
+    #[test]
+    fn record_and_list() {
+        let r = Recorder::new(&AUDIT_LOG);
+
+        for id in ["a", "b", "a"] {
+            r.record(entry(id)).unwrap();
+        }
+
+        let l = AuditLister::new(&AUDIT_LOG);
+
+        let seqs = |out: Vec<(u64, AuditEntry)>| -> Vec<u64> {
+            out.into_iter().map(|(seq, _)| seq).collect()
+        };
+
+        assert_eq!(seqs(l.list(None, None, 10).unwrap()), vec![0, 1, 2]);
+        assert_eq!(
+            seqs(l.list(Some("a".into()), None, 10).unwrap()),
+            vec![0, 2]
+        );
+        assert_eq!(seqs(l.list(None, Some(0), 1).unwrap()), vec![1]);
+        assert!(l.list(None, Some(u64::MAX), 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn record_rejects_long_id() {
+        let r = Recorder::new(&AUDIT_LOG);
+
+        match r.record(entry(&"a".repeat(65))) {
+            Err(AuditError::UnexpectedError(_)) => {}
+            other => panic!("expected UnexpectedError but got {other:?}"),
+        }
+    }
+}
@@ -2,14 +2,15 @@ use std::ops::Bound;
 
 use anyhow::anyhow;
 use certificate_orchestrator_interface::{
-    EncryptedPair, ExportPackage, IcCertificate, Id, Registration, LEFT_GUARD, RIGHT_GUARD,
+    EncryptedPair, ExportFilter, ExportPackage, IcCertificate, Id, Registration, LEFT_GUARD,
+    RIGHT_GUARD,
 };
-use ic_cdk::caller;
+use ic_cdk::{api::time, caller};
 use prometheus::labels;
 
 use crate::{
     acl::{Authorize, AuthorizeError, WithAuthorize},
-    ic_certification::{add_cert, get_cert_for_range, set_root_hash},
+    ic_certification::{add_cert, get_cert_for_range, remove_cert, set_root_hash},
     LocalRef, StableMap, StorableId, WithMetrics,
 };
 
@@ -103,13 +104,19 @@ impl Upload for Uploader {
 pub struct UploadWithIcCertification<T> {
     uploader: T,
     registrations: LocalRef<StableMap<StorableId, Registration>>,
+    updated_at: LocalRef<StableMap<StorableId, u64>>,
 }
 
 impl<T: Upload> UploadWithIcCertification<T> {
-    pub fn new(uploader: T, registrations: LocalRef<StableMap<StorableId, Registration>>) -> Self {
+    pub fn new(
+        uploader: T,
+        registrations: LocalRef<StableMap<StorableId, Registration>>,
+        updated_at: LocalRef<StableMap<StorableId, u64>>,
+    ) -> Self {
         Self {
             uploader,
             registrations,
+            updated_at,
         }
     }
 }
@@ -122,12 +129,15 @@ impl<T: Upload> Upload for UploadWithIcCertification<T> {
             ExportPackage {
                 id: id.into(),
                 name: reg.name,
+                alt_names: reg.alt_names.unwrap_or_default(),
                 canister: reg.canister,
                 pair,
             }
         });
         add_cert(id.into(), &package_to_certify);
         set_root_hash();
+        self.updated_at
+            .with(|ts| ts.borrow_mut().insert(id.into(), time()));
         Ok(())
     }
 }
@@ -168,6 +178,88 @@ impl<T: Upload> Upload for WithMetrics<T> {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum RevokeError {
+    #[error("Not found")]
+    NotFound,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub trait Revoke {
+    fn revoke(&self, id: &Id) -> Result<(), RevokeError>;
+}
+
+// Withdraws a revoked certificate from exports, so that boundary nodes stop serving it,
+// while keeping the registration so that a new certificate can be issued
+pub struct Revoker {
+    pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
+    updated_at: LocalRef<StableMap<StorableId, u64>>,
+}
+
+impl Revoker {
+    pub fn new(
+        pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
+        updated_at: LocalRef<StableMap<StorableId, u64>>,
+    ) -> Self {
+        Self { pairs, updated_at }
+    }
+}
+
+impl Revoke for Revoker {
+    fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        self.pairs
+            .with(|pairs| pairs.borrow_mut().remove(&id.into()))
+            .ok_or(RevokeError::NotFound)?;
+
+        remove_cert(id.into());
+        set_root_hash();
+
+        self.updated_at
+            .with(|ts| ts.borrow_mut().insert(id.into(), time()));
+
+        Ok(())
+    }
+}
+
+impl<T: Revoke, A: Authorize> Revoke for WithAuthorize<T, A> {
+    fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => RevokeError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => RevokeError::UnexpectedError(err),
+            });
+        };
+
+        self.0.revoke(id)
+    }
+}
+
+impl<T: Revoke> Revoke for WithMetrics<T> {
+    fn revoke(&self, id: &Id) -> Result<(), RevokeError> {
+        let out = self.0.revoke(id);
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            RevokeError::NotFound => "not-found",
+                            RevokeError::Unauthorized => "unauthorized",
+                            RevokeError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ExportError {
     #[error("Unauthorized")]
@@ -183,23 +275,59 @@ pub trait Export {
         key: Option<String>,
         limit: u64,
     ) -> Result<(Vec<ExportPackage>, IcCertificate), ExportError>;
+    fn export_filtered(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<ExportPackage>, IcCertificate, Option<Id>), ExportError>;
 }
 
+// Upper bound of entries a filtered export scans per call, whether they match or not. Sparse filters
+// would otherwise scan the whole map, exceeding the instruction limit and certifying every scanned entry.
+const EXPORT_FILTERED_SCAN_LIMIT: usize = 1000;
+
 pub struct Exporter {
     pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
     registrations: LocalRef<StableMap<StorableId, Registration>>,
+    updated_at: LocalRef<StableMap<StorableId, u64>>,
 }
 
 impl Exporter {
     pub fn new(
         pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
         registrations: LocalRef<StableMap<StorableId, Registration>>,
+        updated_at: LocalRef<StableMap<StorableId, u64>>,
     ) -> Self {
         Self {
             pairs,
             registrations,
+            updated_at,
+        }
+    }
+}
+
+fn matches(filter: &ExportFilter, reg: &Registration, updated_at: Option<u64>) -> bool {
+    if let Some(suffix) = &filter.name_suffix {
+        if !String::from(reg.name.clone()).ends_with(suffix.as_str()) {
+            return false;
+        }
+    }
+
+    if let Some(state) = &filter.state {
+        if std::mem::discriminant(state) != std::mem::discriminant(&reg.state) {
+            return false;
         }
     }
+
+    if let Some(updated_since) = filter.updated_since {
+        // Entries without a timestamp are kept, so that incremental exports never miss an update
+        if updated_at.map_or(false, |t| t < updated_since) {
+            return false;
+        }
+    }
+
+    true
 }
 
 impl Export for Exporter {
@@ -220,9 +348,15 @@ impl Export for Exporter {
                         None => Err(ExportError::UnexpectedError(anyhow!(
                             "registration {id} is missing",
                         ))),
-                        Some(Registration { name, canister, .. }) => Ok(ExportPackage {
+                        Some(Registration {
+                            name,
+                            alt_names,
+                            canister,
+                            ..
+                        }) => Ok(ExportPackage {
                             id: id.into(),
                             name,
+                            alt_names: alt_names.unwrap_or_default(),
                             canister,
                             pair,
                         }),
@@ -261,9 +395,15 @@ impl Export for Exporter {
                         None => Err(ExportError::UnexpectedError(anyhow!(
                             "registration {id} is missing",
                         ))),
-                        Some(Registration { name, canister, .. }) => Ok(ExportPackage {
+                        Some(Registration {
+                            name,
+                            alt_names,
+                            canister,
+                            ..
+                        }) => Ok(ExportPackage {
                             id: id.into(),
                             name,
+                            alt_names: alt_names.unwrap_or_default(),
                             canister,
                             pair,
                         }),
@@ -295,6 +435,87 @@ impl Export for Exporter {
             }
         }
     }
+
+    // Unlike `export_certified`, the page starts strictly after `key` and only contains matching entries.
+    // A page ends early once `EXPORT_FILTERED_SCAN_LIMIT` entries were scanned, and the returned key tells
+    // where to continue. The witness covers the scanned range, i.e. at most `EXPORT_FILTERED_SCAN_LIMIT`
+    // entries plus the guards, so each package can be verified individually, but the completeness of the
+    // page cannot be proven.
+    fn export_filtered(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<ExportPackage>, IcCertificate, Option<Id>), ExportError> {
+        let (pkgs, next_key) =
+            self.scan_filtered(key.clone(), limit, EXPORT_FILTERED_SCAN_LIMIT, filter)?;
+
+        let first = key.unwrap_or_else(|| LEFT_GUARD.to_string());
+        let last = next_key.clone().unwrap_or_else(|| RIGHT_GUARD.to_string());
+        let cert = get_cert_for_range(&first, &last);
+
+        Ok((pkgs, cert, next_key))
+    }
+}
+
+impl Exporter {
+    // Scans at most `scan_limit` entries after `key` for up to `limit` matching ones.
+    // Returns the last scanned key as well, unless all entries were scanned.
+    fn scan_filtered(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        scan_limit: usize,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<ExportPackage>, Option<Id>), ExportError> {
+        self.pairs.with(|pairs| {
+            self.registrations.with(|regs| {
+                self.updated_at.with(|ts| {
+                    let (pairs, regs, ts) = (pairs.borrow(), regs.borrow(), ts.borrow());
+
+                    let mut entries = pairs
+                        .range((
+                            match key.clone() {
+                                Some(key) => Bound::Excluded(StorableId::from(key)),
+                                None => Bound::Unbounded,
+                            },
+                            Bound::Unbounded,
+                        ))
+                        .peekable();
+
+                    let (mut pkgs, mut scanned, mut last) = (vec![], 0, key);
+
+                    while (pkgs.len() as u64) < limit && scanned < scan_limit {
+                        let Some((id, pair)) = entries.next() else {
+                            break;
+                        };
+                        scanned += 1;
+
+                        let reg = regs.get(&id).ok_or_else(|| {
+                            ExportError::UnexpectedError(anyhow!("registration {id} is missing"))
+                        })?;
+
+                        if matches(filter, &reg, ts.get(&id)) {
+                            pkgs.push(ExportPackage {
+                                id: id.clone().into(),
+                                name: reg.name,
+                                alt_names: reg.alt_names.unwrap_or_default(),
+                                canister: reg.canister,
+                                pair,
+                            });
+                        }
+
+                        last = Some(id.into());
+                    }
+
+                    // Nothing is left to continue from once all entries were scanned
+                    let next_key = entries.peek().and(last);
+
+                    Ok((pkgs, next_key))
+                })
+            })
+        })
+    }
 }
 
 impl<T: Export, A: Authorize> Export for WithAuthorize<T, A> {
@@ -323,4 +544,98 @@ impl<T: Export, A: Authorize> Export for WithAuthorize<T, A> {
 
         self.0.export_certified(key, limit)
     }
+
+    fn export_filtered(
+        &self,
+        key: Option<String>,
+        limit: u64,
+        filter: &ExportFilter,
+    ) -> Result<(Vec<ExportPackage>, IcCertificate, Option<Id>), ExportError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => ExportError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => ExportError::UnexpectedError(err),
+            });
+        };
+
+        self.0.export_filtered(key, limit, filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use candid::Principal;
+    use certificate_orchestrator_interface::{Name, State};
+
+    use crate::{ENCRYPTED_CERTIFICATES, REGISTRATIONS, UPDATED_AT};
+
+    #[test]
+    fn export_filtered_bounds_scanned_entries() {
+        // Only every tenth entry matches the filter
+        for i in 0..25 {
+            let id: StorableId = format!("id-{i:02}").into();
+            let name = if i % 10 == 0 {
+                "a.example.com"
+            } else {
+                "b.example.org"
+            };
+
+            REGISTRATIONS.with(|regs| {
+                regs.borrow_mut().insert(
+                    id.clone(),
+                    Registration {
+                        name: Name::try_from(name).unwrap(),
+                        canister: Principal::from_text("aaaaa-aa").unwrap(),
+                        state: State::Available,
+                        key_type: None,
+                        alt_names: None,
+                    },
+                )
+            });
+            ENCRYPTED_CERTIFICATES
+                .with(|pairs| pairs.borrow_mut().insert(id, EncryptedPair(vec![], vec![])));
+        }
+
+        let e = Exporter::new(&ENCRYPTED_CERTIFICATES, &REGISTRATIONS, &UPDATED_AT);
+        let filter = ExportFilter {
+            name_suffix: Some(".example.com".into()),
+            ..Default::default()
+        };
+
+        let (mut key, mut ids) = (None, vec![]);
+        loop {
+            let (pkgs, next_key) = e.scan_filtered(key.clone(), 10, 8, &filter).unwrap();
+            ids.extend(pkgs.into_iter().map(|pkg| pkg.id));
+
+            let Some(next_key) = next_key else {
+                break;
+            };
+
+            // The witnessed range (key, next_key] covers at most the scan limit of entries
+            let scanned = ENCRYPTED_CERTIFICATES.with(|pairs| {
+                pairs
+                    .borrow()
+                    .range((
+                        match key {
+                            Some(key) => Bound::Excluded(StorableId::from(key)),
+                            None => Bound::Unbounded,
+                        },
+                        Bound::Included(StorableId::from(next_key.clone())),
+                    ))
+                    .count()
+            });
+            assert_eq!(scanned, 8);
+
+            key = Some(next_key);
+        }
+
+        assert_eq!(ids, vec!["id-00", "id-10", "id-20"]);
+
+        // A full page is continued from its last match
+        let (pkgs, next_key) = e.scan_filtered(None, 1, 8, &filter).unwrap();
+        assert_eq!(pkgs.len(), 1);
+        assert_eq!(next_key, Some("id-00".into()));
+    }
 }
@@ -0,0 +1,197 @@
+use certificate_orchestrator_interface::{Id, IssuanceEvent, IssuanceHistory, Registration};
+use ic_cdk::caller;
+use prometheus::labels;
+
+use crate::{
+    acl::{Authorize, AuthorizeError, WithAuthorize},
+    LocalRef, StableMap, StorableId, WithMetrics,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("Not found")]
+    NotFound,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub trait RecordEvent {
+    fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError>;
+}
+
+pub struct EventRecorder {
+    registrations: LocalRef<StableMap<StorableId, Registration>>,
+    histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
+}
+
+impl EventRecorder {
+    pub fn new(
+        registrations: LocalRef<StableMap<StorableId, Registration>>,
+        histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
+    ) -> Self {
+        Self {
+            registrations,
+            histories,
+        }
+    }
+}
+
+impl RecordEvent for EventRecorder {
+    fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        // Histories are removed along with their registration
+        if !self
+            .registrations
+            .with(|regs| regs.borrow().contains_key(&id.into()))
+        {
+            return Err(HistoryError::NotFound);
+        }
+
+        self.histories.with(|hs| {
+            let mut hs = hs.borrow_mut();
+
+            let mut h = hs.get(&id.into()).unwrap_or_default();
+            h.record(event);
+            hs.insert(id.into(), h);
+        });
+
+        Ok(())
+    }
+}
+
+impl<T: RecordEvent, A: Authorize> RecordEvent for WithAuthorize<T, A> {
+    fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => HistoryError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => HistoryError::UnexpectedError(err),
+            });
+        };
+
+        self.0.record(id, event)
+    }
+}
+
+impl<T: RecordEvent> RecordEvent for WithMetrics<T> {
+    fn record(&self, id: &Id, event: IssuanceEvent) -> Result<(), HistoryError> {
+        let out = self.0.record(id, event);
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            HistoryError::NotFound => "not-found",
+                            HistoryError::Unauthorized => "unauthorized",
+                            HistoryError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
+pub trait GetHistory {
+    fn get(&self, id: &Id) -> Result<IssuanceHistory, HistoryError>;
+}
+
+pub struct HistoryGetter {
+    registrations: LocalRef<StableMap<StorableId, Registration>>,
+    histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
+}
+
+impl HistoryGetter {
+    pub fn new(
+        registrations: LocalRef<StableMap<StorableId, Registration>>,
+        histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
+    ) -> Self {
+        Self {
+            registrations,
+            histories,
+        }
+    }
+}
+
+impl GetHistory for HistoryGetter {
+    fn get(&self, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+        if !self
+            .registrations
+            .with(|regs| regs.borrow().contains_key(&id.into()))
+        {
+            return Err(HistoryError::NotFound);
+        }
+
+        Ok(self
+            .histories
+            .with(|hs| hs.borrow().get(&id.into()).unwrap_or_default()))
+    }
+}
+
+impl<T: GetHistory, A: Authorize> GetHistory for WithAuthorize<T, A> {
+    fn get(&self, id: &Id) -> Result<IssuanceHistory, HistoryError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => HistoryError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => HistoryError::UnexpectedError(err),
+            });
+        };
+
+        self.0.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use candid::Principal;
+    use certificate_orchestrator_interface::{Name, State, ISSUANCE_HISTORY_MAX_LEN};
+
+    use crate::{HISTORIES, REGISTRATIONS};
+
+    #[test]
+    fn record_and_get() {
+        REGISTRATIONS.with(|regs| {
+            regs.borrow_mut().insert(
+                "id".into(),
+                Registration {
+                    name: Name::try_from("example.com").unwrap(),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::Available,
+                    key_type: None,
+                    alt_names: None,
+                },
+            )
+        });
+
+        let r = EventRecorder::new(&REGISTRATIONS, &HISTORIES);
+        let g = HistoryGetter::new(&REGISTRATIONS, &HISTORIES);
+
+        assert_eq!(g.get(&"id".into()).unwrap(), IssuanceHistory::default());
+
+        for t in 0..ISSUANCE_HISTORY_MAX_LEN as u64 + 2 {
+            r.record(&"id".into(), IssuanceEvent::Attempt(t)).unwrap();
+        }
+        r.record(&"id".into(), IssuanceEvent::Renewal(1)).unwrap();
+
+        let h = g.get(&"id".into()).unwrap();
+        assert_eq!(h.attempts.len(), ISSUANCE_HISTORY_MAX_LEN);
+        assert_eq!(h.attempts[0], 2);
+        assert_eq!(h.renewals, vec![1]);
+
+        // Unknown registrations
+        assert!(matches!(
+            r.record(&"other".into(), IssuanceEvent::Attempt(0)),
+            Err(HistoryError::NotFound)
+        ));
+        assert!(matches!(
+            g.get(&"other".into()),
+            Err(HistoryError::NotFound)
+        ));
+    }
+}
@@ -2,15 +2,23 @@ use std::{cell::RefCell, cmp::Reverse, collections::BTreeMap, thread::LocalKey,
 
 use candid::{candid_method, Principal};
 use certificate_orchestrator_interface::{
-    BoundedString, CreateRegistrationError, CreateRegistrationResponse, DispenseTaskError,
-    DispenseTaskResponse, EncryptedPair, ExportCertificatesCertifiedResponse,
-    ExportCertificatesError, ExportCertificatesResponse, ExportPackage, GetCertificateError,
-    GetCertificateResponse, GetRegistrationError, GetRegistrationResponse, HeaderField,
-    HttpRequest, HttpResponse, Id, InitArg, ListAllowedPrincipalsError,
-    ListAllowedPrincipalsResponse, ModifyAllowedPrincipalError, ModifyAllowedPrincipalResponse,
-    Name, PeekTaskError, PeekTaskResponse, QueueTaskError, QueueTaskResponse, Registration,
-    RemoveRegistrationError, RemoveRegistrationResponse, State, UpdateRegistrationError,
-    UpdateRegistrationResponse, UpdateType, UploadCertificateError, UploadCertificateResponse,
+    AuditEntry, BoundedString, CountTasksError, CountTasksResponse, CreateRegistrationError,
+    CreateRegistrationResponse, DispenseTaskError, DispenseTaskResponse, EncryptedPair,
+    ExportCertificatesCertifiedResponse, ExportCertificatesError,
+    ExportCertificatesFilteredResponse, ExportCertificatesResponse, ExportFilter, ExportPackage,
+    GetCertificateError, GetCertificateResponse, GetIssuanceHistoryError,
+    GetIssuanceHistoryResponse, GetRegistrationError, GetRegistrationResponse,
+    GetRegistrationTimestampsResponse, HeaderField, HttpRequest, HttpResponse, Id, InitArg,
+    IssuanceEvent, IssuanceHistory, KeyType, Lease, LeaseTaskResponse, ListAllowedPrincipalsError,
+    ListAllowedPrincipalsResponse, ListAuditEntriesError, ListAuditEntriesResponse,
+    ListRegistrationsError, ListRegistrationsResponse, ModifyAllowedPrincipalError,
+    ModifyAllowedPrincipalResponse, Name, PeekTaskError, PeekTaskResponse, QueueTaskError,
+    QueueTaskResponse, RecordAuditEntryError, RecordAuditEntryResponse, RecordIssuanceEventError,
+    RecordIssuanceEventResponse, Registration, RegistrationTimestamps, ReleaseLeaseError,
+    ReleaseLeaseResponse, RemoveRegistrationError, RemoveRegistrationResponse, RenewLeaseError,
+    RenewLeaseResponse, RevokeCertificateError, RevokeCertificateResponse, State, TaskPriority,
+    UpdateRegistrationError, UpdateRegistrationResponse, UpdateType, UploadCertificateError,
+    UploadCertificateResponse,
 };
 use ic_cdk::{
     api::{id, time},
@@ -23,32 +31,44 @@ use ic_stable_structures::{
     DefaultMemoryImpl, StableBTreeMap,
 };
 use priority_queue::PriorityQueue;
-use prometheus::{CounterVec, Encoder, Gauge, GaugeVec, Opts, Registry, TextEncoder};
+use prometheus::{
+    CounterVec, Encoder, Gauge, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry, TextEncoder,
+};
 use work::{Peek, PeekError};
 
 use crate::{
     acl::{Authorize, AuthorizeError, Authorizer, WithAuthorize},
+    audit::{AuditError, AuditLister, ListAudit, Record, Recorder},
     certificate::{
-        CertGetter, Export, ExportError, Exporter, GetCert, GetCertError, Upload, UploadError,
-        UploadWithIcCertification, Uploader,
+        CertGetter, Export, ExportError, Exporter, GetCert, GetCertError, Revoke, RevokeError,
+        Revoker, Upload, UploadError, UploadWithIcCertification, Uploader,
     },
+    history::{EventRecorder, GetHistory, HistoryError, HistoryGetter, RecordEvent},
     ic_certification::{add_cert, init_cert_tree, set_root_hash},
     id::{Generate, Generator},
     rate_limiter::WithRateLimit,
     registration::{
-        Create, CreateError, Creator, Expire, Expirer, Get, GetError, Getter, Remove, RemoveError,
-        Remover, Update, UpdateError, UpdateWithIcCertification, Updater,
+        Create, CreateError, Creator, Expire, Expirer, Get, GetError, Getter, List, ListError,
+        Lister, Remove, RemoveError, Remover, Update, UpdateError, UpdateWithIcCertification,
+        Updater,
+    },
+    timestamps::{GetTimestamps, TimestampsGetter, WithTimestamps},
+    work::{
+        Count, CountError, Dispense, DispenseError, Dispenser, LeaseError, Peeker, Queue,
+        QueueError, Queuer, Release, Releaser, Renew, Renewer, Retrier, Retry, TaskCounter,
     },
-    work::{Dispense, DispenseError, Dispenser, Peeker, Queue, QueueError, Queuer, Retrier, Retry},
 };
 
 mod acl;
+mod audit;
 mod certificate;
+mod history;
 mod ic_certification;
 mod id;
 mod persistence;
 mod rate_limiter;
 mod registration;
+mod timestamps;
 mod work;
 
 // Stable Memory
@@ -91,6 +111,12 @@ const MEMORY_ID_RETRIES: u8 = 9;
 const MEMORY_ID_REGISTRATION_EXPIRATION_TTL: u8 = 10;
 const MEMORY_ID_IN_PROGRESS_TTL: u8 = 11;
 const MEMORY_ID_MANAGEMENT_TASK_INTERVAL: u8 = 12;
+const MEMORY_ID_UPDATED_AT: u8 = 13;
+const MEMORY_ID_LEASES: u8 = 14;
+const MEMORY_ID_AUDIT_LOG: u8 = 15;
+const MEMORY_ID_ISSUANCE_HISTORIES: u8 = 16;
+const MEMORY_ID_TASK_PRIORITIES: u8 = 17;
+const MEMORY_ID_REGISTRATION_TIMESTAMPS: u8 = 18;
 
 const SUFFIX_LIST_STR: &str = include_str!("../public_suffix_list.dat");
 
@@ -127,6 +153,13 @@ thread_local! {
         ), &["status"]).unwrap()
     });
 
+    static COUNTER_REVOKE_CERTIFICATE_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_revoke_certificate_total"), // name
+            "number of times revoke_certificate was called", // help
+        ), &["status"]).unwrap()
+    });
+
     static COUNTER_QUEUE_TASK_TOTAL: RefCell<CounterVec> = RefCell::new({
         CounterVec::new(Opts::new(
             format!("{SERVICE_NAME}_queue_task_total"), // name
@@ -141,6 +174,13 @@ thread_local! {
         ), &["status"]).unwrap()
     });
 
+    static COUNTER_COUNT_TASKS_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_count_tasks_total"), // name
+            "number of times count_tasks was called", // help
+        ), &["status"]).unwrap()
+    });
+
     static COUNTER_DISPENSE_TASK_TOTAL: RefCell<CounterVec> = RefCell::new({
         CounterVec::new(Opts::new(
             format!("{SERVICE_NAME}_dispense_task_total"), // name
@@ -148,6 +188,34 @@ thread_local! {
         ), &["status"]).unwrap()
     });
 
+    static COUNTER_RENEW_LEASE_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_renew_lease_total"), // name
+            "number of times renew_lease was called", // help
+        ), &["status"]).unwrap()
+    });
+
+    static COUNTER_RELEASE_LEASE_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_release_lease_total"), // name
+            "number of times release_lease was called", // help
+        ), &["status"]).unwrap()
+    });
+
+    static COUNTER_RECORD_AUDIT_ENTRY_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_record_audit_entry_total"), // name
+            "number of times record_audit_entry was called", // help
+        ), &["status"]).unwrap()
+    });
+
+    static COUNTER_RECORD_ISSUANCE_EVENT_TOTAL: RefCell<CounterVec> = RefCell::new({
+        CounterVec::new(Opts::new(
+            format!("{SERVICE_NAME}_record_issuance_event_total"), // name
+            "number of times record_issuance_event was called", // help
+        ), &["status"]).unwrap()
+    });
+
     static GAUGE_REGISTRATIONS_TOTAL: RefCell<GaugeVec> = RefCell::new({
         GaugeVec::new(Opts::new(
             format!("{SERVICE_NAME}_registrations_total"), // name
@@ -162,6 +230,34 @@ thread_local! {
         ).unwrap()
     });
 
+    static GAUGE_TASKS_AVAILABLE_TOTAL: RefCell<Gauge> = RefCell::new({
+        Gauge::new(
+            format!("{SERVICE_NAME}_tasks_available_total"), // name
+            "number of tasks that are due and waiting to be dispensed", // help
+        ).unwrap()
+    });
+
+    static GAUGE_TASKS_IN_PROGRESS_TOTAL: RefCell<Gauge> = RefCell::new({
+        Gauge::new(
+            format!("{SERVICE_NAME}_tasks_in_progress_total"), // name
+            "number of dispensed tasks that have not been re-queued yet", // help
+        ).unwrap()
+    });
+
+    static GAUGE_OLDEST_TASK_AGE_SECONDS: RefCell<Gauge> = RefCell::new({
+        Gauge::new(
+            format!("{SERVICE_NAME}_oldest_task_age_seconds"), // name
+            "time since the oldest available task became due", // help
+        ).unwrap()
+    });
+
+    static HISTOGRAM_TASK_WAIT_SECONDS: RefCell<HistogramVec> = RefCell::new({
+        HistogramVec::new(HistogramOpts::new(
+            format!("{SERVICE_NAME}_task_wait_seconds"), // name
+            "time dispensed tasks waited after becoming due", // help
+        ).buckets(vec![1.0, 10.0, 60.0, 300.0, 900.0, 3600.0, 4.0 * 3600.0, 24.0 * 3600.0]), &["priority"]).unwrap()
+    });
+
     static GAUGE_ALLOWED_PRINCIPALS_TOTAL: RefCell<Gauge> = RefCell::new({
         Gauge::new(
             format!("{SERVICE_NAME}_allowed_principals_total"), // name
@@ -199,6 +295,11 @@ thread_local! {
             r.register(c).unwrap();
         });
 
+        COUNTER_REVOKE_CERTIFICATE_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
         COUNTER_QUEUE_TASK_TOTAL.with(|c| {
             let c = Box::new(c.borrow().to_owned());
             r.register(c).unwrap();
@@ -209,11 +310,36 @@ thread_local! {
             r.register(c).unwrap();
         });
 
+        COUNTER_COUNT_TASKS_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
         COUNTER_DISPENSE_TASK_TOTAL.with(|c| {
             let c = Box::new(c.borrow().to_owned());
             r.register(c).unwrap();
         });
 
+        COUNTER_RENEW_LEASE_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
+        COUNTER_RELEASE_LEASE_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
+        COUNTER_RECORD_AUDIT_ENTRY_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
+        COUNTER_RECORD_ISSUANCE_EVENT_TOTAL.with(|c| {
+            let c = Box::new(c.borrow().to_owned());
+            r.register(c).unwrap();
+        });
+
         GAUGE_REGISTRATIONS_TOTAL.with(|g| {
             let g = Box::new(g.borrow().to_owned());
             r.register(g).unwrap();
@@ -224,6 +350,26 @@ thread_local! {
             r.register(g).unwrap();
         });
 
+        GAUGE_TASKS_AVAILABLE_TOTAL.with(|g| {
+            let g = Box::new(g.borrow().to_owned());
+            r.register(g).unwrap();
+        });
+
+        GAUGE_TASKS_IN_PROGRESS_TOTAL.with(|g| {
+            let g = Box::new(g.borrow().to_owned());
+            r.register(g).unwrap();
+        });
+
+        GAUGE_OLDEST_TASK_AGE_SECONDS.with(|g| {
+            let g = Box::new(g.borrow().to_owned());
+            r.register(g).unwrap();
+        });
+
+        HISTOGRAM_TASK_WAIT_SECONDS.with(|h| {
+            let h = Box::new(h.borrow().to_owned());
+            r.register(h).unwrap();
+        });
+
         GAUGE_ALLOWED_PRINCIPALS_TOTAL.with(|g| {
             let g = Box::new(g.borrow().to_owned());
             r.register(g).unwrap();
@@ -307,17 +453,53 @@ thread_local! {
         )
     );
 
+    // Time at which each exported package last changed, used for incremental exports
+    static UPDATED_AT: RefCell<StableMap<StorableId, u64>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_UPDATED_AT))),
+        )
+    );
+
+    // Issuance history of each registration, recorded by the issuer
+    static HISTORIES: RefCell<StableMap<StorableId, IssuanceHistory>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_ISSUANCE_HISTORIES))),
+        )
+    );
+
+    // Lifecycle timestamps of each registration, see `RegistrationTimestamps`
+    static REGISTRATION_TIMESTAMPS: RefCell<StableMap<StorableId, RegistrationTimestamps>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_REGISTRATION_TIMESTAMPS))),
+        )
+    );
+
     static TASKS: RefCell<PriorityQueue<Id, Reverse<u64>>> = RefCell::new(PriorityQueue::new());
 
+    // Priorities of queued tasks other than the default one, see `TaskPriority`
+    static TASK_PRIORITIES: RefCell<StableMap<StorableId, TaskPriority>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_TASK_PRIORITIES))),
+        )
+    );
+
     static EXPIRATIONS: RefCell<PriorityQueue<Id, Reverse<u64>>> = RefCell::new(PriorityQueue::new());
 
     static RETRIES: RefCell<PriorityQueue<Id, Reverse<u64>>> = RefCell::new(PriorityQueue::new());
 
+    // Leases of dispensed tasks, so that a registration is processed by a single issuer at a time
+    static LEASES: RefCell<StableMap<StorableId, Lease>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_LEASES))),
+        )
+    );
+
     // Rate limiting for CREATOR
     static AVAILABLE_TOKENS: RefCell<BTreeMap<String, u32>> = RefCell::new(BTreeMap::new());
 
     static CREATOR: RefCell<Box<dyn Create>> = RefCell::new({
         let c = Creator::new(&ID_GENERATOR, &REGISTRATIONS, &NAMES, &EXPIRATIONS);
+        let c = WithTimestamps::new(c, &REGISTRATIONS, &REGISTRATION_TIMESTAMPS);
         let c = WithRateLimit::new(c, REGISTRATION_RATE_LIMIT_RATE, &AVAILABLE_TOKENS, SUFFIX_LIST_STR.parse().unwrap());
         let c = WithAuthorize(c, &MAIN_AUTHORIZER);
         let c = WithMetrics(c, &COUNTER_CREATE_REGISTRATION_TOTAL);
@@ -330,16 +512,30 @@ thread_local! {
         Box::new(g)
     });
 
+    static TIMESTAMPS_GETTER: RefCell<Box<dyn GetTimestamps>> = RefCell::new({
+        let g = TimestampsGetter::new(&REGISTRATIONS, &REGISTRATION_TIMESTAMPS, &TASKS);
+        let g = WithAuthorize(g, &MAIN_AUTHORIZER);
+        Box::new(g)
+    });
+
+    static LISTER: RefCell<Box<dyn List>> = RefCell::new({
+        let l = Lister::new(&REGISTRATIONS);
+        let l = WithAuthorize(l, &MAIN_AUTHORIZER);
+        Box::new(l)
+    });
+
     static UPDATER: RefCell<Box<dyn Update>> = RefCell::new({
         let u = Updater::new(&REGISTRATIONS, &EXPIRATIONS, &RETRIES);
-        let u = UpdateWithIcCertification::new(u, &ENCRYPTED_CERTIFICATES, &REGISTRATIONS);
+        let u = UpdateWithIcCertification::new(u, &ENCRYPTED_CERTIFICATES, &REGISTRATIONS, &UPDATED_AT);
+        let u = WithTimestamps::new(u, &REGISTRATIONS, &REGISTRATION_TIMESTAMPS);
         let u = WithAuthorize(u, &MAIN_AUTHORIZER);
         let u = WithMetrics(u, &COUNTER_UPDATE_REGISTRATION_TOTAL);
         Box::new(u)
     });
 
     static REMOVER: RefCell<Box<dyn Remove>> = RefCell::new({
-        let r = Remover::new(&REGISTRATIONS, &NAMES, &TASKS, &EXPIRATIONS, &RETRIES, &ENCRYPTED_CERTIFICATES);
+        let r = Remover::new(&REGISTRATIONS, &NAMES, &TASKS, &TASK_PRIORITIES, &EXPIRATIONS, &RETRIES, &LEASES, &ENCRYPTED_CERTIFICATES, &UPDATED_AT, &HISTORIES);
+        let r = WithTimestamps::new(r, &REGISTRATIONS, &REGISTRATION_TIMESTAMPS);
         let r = WithAuthorize(r, &MAIN_AUTHORIZER);
         let r = WithMetrics(r, &COUNTER_REMOVE_REGISTRATION_TOTAL);
         Box::new(r)
@@ -350,14 +546,21 @@ thread_local! {
 thread_local! {
     static UPLOADER: RefCell<Box<dyn Upload>> = RefCell::new({
         let u = Uploader::new(&ENCRYPTED_CERTIFICATES, &REGISTRATIONS);
-        let u = UploadWithIcCertification::new(u, &REGISTRATIONS);
+        let u = UploadWithIcCertification::new(u, &REGISTRATIONS, &UPDATED_AT);
         let u = WithAuthorize(u, &MAIN_AUTHORIZER);
         let u = WithMetrics(u, &COUNTER_UPLOAD_CERTIFICATE_TOTAL);
         Box::new(u)
     });
 
+    static REVOKER: RefCell<Box<dyn Revoke>> = RefCell::new({
+        let r = Revoker::new(&ENCRYPTED_CERTIFICATES, &UPDATED_AT);
+        let r = WithAuthorize(r, &MAIN_AUTHORIZER);
+        let r = WithMetrics(r, &COUNTER_REVOKE_CERTIFICATE_TOTAL);
+        Box::new(r)
+    });
+
     static EXPORTER: RefCell<Box<dyn Export>> = RefCell::new({
-        let e = Exporter::new(&ENCRYPTED_CERTIFICATES, &REGISTRATIONS);
+        let e = Exporter::new(&ENCRYPTED_CERTIFICATES, &REGISTRATIONS, &UPDATED_AT);
         let e = WithAuthorize(e, &MAIN_AUTHORIZER);
         Box::new(e)
     });
@@ -373,7 +576,7 @@ thread_local! {
 
 thread_local! {
     static QUEUER: RefCell<Box<dyn Queue>> = RefCell::new({
-        let q = Queuer::new(&TASKS, &REGISTRATIONS);
+        let q = Queuer::new(&TASKS, &TASK_PRIORITIES, &REGISTRATIONS);
         let q = WithAuthorize(q, &MAIN_AUTHORIZER);
         let q = WithMetrics(q, &COUNTER_QUEUE_TASK_TOTAL);
         Box::new(q)
@@ -386,12 +589,75 @@ thread_local! {
         Box::new(d)
     });
 
+    static TASK_COUNTER: RefCell<Box<dyn Count>> = RefCell::new({
+        let c = TaskCounter::new(&TASKS);
+        let c = WithAuthorize(c, &MAIN_AUTHORIZER);
+        let c = WithMetrics(c, &COUNTER_COUNT_TASKS_TOTAL);
+        Box::new(c)
+    });
+
     static DISPENSER: RefCell<Box<dyn Dispense>> = RefCell::new({
-        let d = Dispenser::new(&TASKS, &RETRIES);
+        let d = Dispenser::new(&TASKS, &TASK_PRIORITIES, &RETRIES, &LEASES, &ID_GENERATOR, &HISTOGRAM_TASK_WAIT_SECONDS);
+        let d = WithTimestamps::new(d, &REGISTRATIONS, &REGISTRATION_TIMESTAMPS);
         let d = WithAuthorize(d, &MAIN_AUTHORIZER);
         let d = WithMetrics(d, &COUNTER_DISPENSE_TASK_TOTAL);
         Box::new(d)
     });
+
+    static RENEWER: RefCell<Box<dyn Renew>> = RefCell::new({
+        let r = Renewer::new(&TASKS, &RETRIES, &LEASES);
+        let r = WithAuthorize(r, &MAIN_AUTHORIZER);
+        let r = WithMetrics(r, &COUNTER_RENEW_LEASE_TOTAL);
+        Box::new(r)
+    });
+
+    static RELEASER: RefCell<Box<dyn Release>> = RefCell::new({
+        let r = Releaser::new(&TASKS, &RETRIES, &LEASES);
+        let r = WithAuthorize(r, &MAIN_AUTHORIZER);
+        let r = WithMetrics(r, &COUNTER_RELEASE_LEASE_TOTAL);
+        Box::new(r)
+    });
+}
+
+// Audit
+
+thread_local! {
+    // Append-only log of registration mutations, keyed by sequence number
+    static AUDIT_LOG: RefCell<StableMap<u64, AuditEntry>> = RefCell::new(
+        StableMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(MEMORY_ID_AUDIT_LOG))),
+        )
+    );
+
+    static AUDIT_RECORDER: RefCell<Box<dyn Record>> = RefCell::new({
+        let r = Recorder::new(&AUDIT_LOG);
+        let r = WithAuthorize(r, &MAIN_AUTHORIZER);
+        let r = WithMetrics(r, &COUNTER_RECORD_AUDIT_ENTRY_TOTAL);
+        Box::new(r)
+    });
+
+    static AUDIT_LISTER: RefCell<Box<dyn ListAudit>> = RefCell::new({
+        let l = AuditLister::new(&AUDIT_LOG);
+        let l = WithAuthorize(l, &MAIN_AUTHORIZER);
+        Box::new(l)
+    });
+}
+
+// History
+
+thread_local! {
+    static HISTORY_RECORDER: RefCell<Box<dyn RecordEvent>> = RefCell::new({
+        let r = EventRecorder::new(&REGISTRATIONS, &HISTORIES);
+        let r = WithAuthorize(r, &MAIN_AUTHORIZER);
+        let r = WithMetrics(r, &COUNTER_RECORD_ISSUANCE_EVENT_TOTAL);
+        Box::new(r)
+    });
+
+    static HISTORY_GETTER: RefCell<Box<dyn GetHistory>> = RefCell::new({
+        let g = HistoryGetter::new(&REGISTRATIONS, &HISTORIES);
+        let g = WithAuthorize(g, &MAIN_AUTHORIZER);
+        Box::new(g)
+    });
 }
 
 // Expirations and retries
@@ -415,7 +681,7 @@ thread_local! {
     });
 
     static RETRIER: RefCell<Box<dyn Retry>> = RefCell::new({
-        let r = Retrier::new(&TASKS, &RETRIES);
+        let r = Retrier::new(&TASKS, &RETRIES, &LEASES);
         Box::new(r)
     });
 }
@@ -595,11 +861,20 @@ fn post_upgrade_fn() {
                     ExportPackage {
                         id: id.clone().into(),
                         name: reg.name,
+                        alt_names: reg.alt_names.unwrap_or_default(),
                         canister: reg.canister,
                         pair,
                     }
                 };
-                add_cert(id, &package_to_certify);
+                add_cert(id.clone(), &package_to_certify);
+
+                // packages certified before timestamps were tracked are considered updated now
+                UPDATED_AT.with(|ts| {
+                    let mut ts = ts.borrow_mut();
+                    if !ts.contains_key(&id) {
+                        ts.insert(id, time());
+                    }
+                });
             }
             set_root_hash();
         })
@@ -610,12 +885,20 @@ fn post_upgrade_fn() {
 
 #[update(name = "createRegistration")]
 #[candid_method(update, rename = "createRegistration")]
-fn create_registration(name: String, canister: Principal) -> CreateRegistrationResponse {
-    match CREATOR.with(|c| c.borrow().create(&name, &canister)) {
+fn create_registration(
+    name: String,
+    canister: Principal,
+    key_type: Option<KeyType>,
+    alt_names: Option<Vec<String>>,
+) -> CreateRegistrationResponse {
+    let alt_names = alt_names.unwrap_or_default();
+
+    match CREATOR.with(|c| c.borrow().create(&name, &canister, key_type, &alt_names)) {
         Ok(id) => CreateRegistrationResponse::Ok(id),
         Err(err) => CreateRegistrationResponse::Err(match err {
             CreateError::Duplicate(id) => CreateRegistrationError::Duplicate(id),
             CreateError::NameError(err) => CreateRegistrationError::NameError(err.to_string()),
+            CreateError::InvalidAltNames(err) => CreateRegistrationError::NameError(err),
             CreateError::RateLimited(domain) => CreateRegistrationError::RateLimited(domain),
             CreateError::Unauthorized => CreateRegistrationError::Unauthorized,
             CreateError::UnexpectedError(err) => {
@@ -640,6 +923,39 @@ fn get_registration(id: Id) -> GetRegistrationResponse {
     }
 }
 
+#[query(name = "getRegistrationTimestamps")]
+#[candid_method(query, rename = "getRegistrationTimestamps")]
+fn get_registration_timestamps(id: Id) -> GetRegistrationTimestampsResponse {
+    match TIMESTAMPS_GETTER.with(|g| g.borrow().get(&id)) {
+        Ok(timestamps) => GetRegistrationTimestampsResponse::Ok(timestamps),
+        Err(err) => GetRegistrationTimestampsResponse::Err(match err {
+            GetError::NotFound => GetRegistrationError::NotFound,
+            GetError::Unauthorized => GetRegistrationError::Unauthorized,
+            GetError::UnexpectedError(err) => {
+                GetRegistrationError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
+#[query(name = "listRegistrations")]
+#[candid_method(query, rename = "listRegistrations")]
+fn list_registrations(
+    key: Option<Id>,
+    limit: u64,
+    state: Option<State>,
+) -> ListRegistrationsResponse {
+    match LISTER.with(|l| l.borrow().list(key, limit, state)) {
+        Ok(regs) => ListRegistrationsResponse::Ok(regs),
+        Err(err) => ListRegistrationsResponse::Err(match err {
+            ListError::Unauthorized => ListRegistrationsError::Unauthorized,
+            ListError::UnexpectedError(err) => {
+                ListRegistrationsError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
 #[update(name = "updateRegistration")]
 #[candid_method(update, rename = "updateRegistration")]
 fn update_registration(id: Id, typ: UpdateType) -> UpdateRegistrationResponse {
@@ -705,6 +1021,21 @@ fn upload_certificate(id: Id, pair: EncryptedPair) -> UploadCertificateResponse
     }
 }
 
+#[update(name = "revokeCertificate")]
+#[candid_method(update, rename = "revokeCertificate")]
+fn revoke_certificate(id: Id) -> RevokeCertificateResponse {
+    match REVOKER.with(|r| r.borrow().revoke(&id)) {
+        Ok(()) => RevokeCertificateResponse::Ok(()),
+        Err(err) => RevokeCertificateResponse::Err(match err {
+            RevokeError::NotFound => RevokeCertificateError::NotFound,
+            RevokeError::Unauthorized => RevokeCertificateError::Unauthorized,
+            RevokeError::UnexpectedError(_) => {
+                RevokeCertificateError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
 #[query(name = "exportCertificatesPaginated")]
 #[candid_method(query, rename = "exportCertificatesPaginated")]
 fn export_certificates_paginated(key: Option<String>, limit: u64) -> ExportCertificatesResponse {
@@ -736,6 +1067,24 @@ fn export_certificates_certified(
     }
 }
 
+#[query(name = "exportCertificatesFiltered")]
+#[candid_method(query, rename = "exportCertificatesFiltered")]
+fn export_certificates_filtered(
+    key: Option<String>,
+    limit: u64,
+    filter: ExportFilter,
+) -> ExportCertificatesFilteredResponse {
+    match EXPORTER.with(|e| e.borrow().export_filtered(key, limit, &filter)) {
+        Ok(pkgs) => ExportCertificatesFilteredResponse::Ok(pkgs),
+        Err(err) => ExportCertificatesFilteredResponse::Err(match err {
+            ExportError::Unauthorized => ExportCertificatesError::Unauthorized,
+            ExportError::UnexpectedError(_) => {
+                ExportCertificatesError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
 #[query(name = "exportCertificates")]
 #[candid_method(query, rename = "exportCertificates")]
 fn export_certificates() -> ExportCertificatesResponse {
@@ -754,8 +1103,11 @@ fn export_certificates() -> ExportCertificatesResponse {
 
 #[update(name = "queueTask")]
 #[candid_method(update, rename = "queueTask")]
-fn queue_task(id: Id, timestamp: u64) -> QueueTaskResponse {
-    match QUEUER.with(|q| q.borrow().queue(id, timestamp)) {
+fn queue_task(id: Id, timestamp: u64, priority: Option<TaskPriority>) -> QueueTaskResponse {
+    match QUEUER.with(|q| {
+        q.borrow()
+            .queue(id, timestamp, priority.unwrap_or_default())
+    }) {
         Ok(()) => QueueTaskResponse::Ok(()),
         Err(err) => QueueTaskResponse::Err(match err {
             QueueError::NotFound => QueueTaskError::NotFound,
@@ -778,11 +1130,24 @@ fn peek_task() -> PeekTaskResponse {
     }
 }
 
+#[query(name = "countTasks")]
+#[candid_method(query, rename = "countTasks")]
+fn count_tasks() -> CountTasksResponse {
+    match TASK_COUNTER.with(|c| c.borrow().count()) {
+        Ok(n) => CountTasksResponse::Ok(n),
+        Err(err) => CountTasksResponse::Err(match err {
+            CountError::Unauthorized => CountTasksError::Unauthorized,
+            CountError::UnexpectedError(err) => CountTasksError::UnexpectedError(err.to_string()),
+        }),
+    }
+}
+
 #[update(name = "dispenseTask")]
 #[candid_method(update, rename = "dispenseTask")]
 fn dispense_task() -> DispenseTaskResponse {
-    match DISPENSER.with(|d| d.borrow().dispense()) {
-        Ok(id) => DispenseTaskResponse::Ok(id),
+    // Callers of the legacy endpoint never release leases, which would defer their follow-up tasks
+    match DISPENSER.with(|d| d.borrow().dispense(false)) {
+        Ok((id, _)) => DispenseTaskResponse::Ok(id),
         Err(err) => DispenseTaskResponse::Err(match err {
             DispenseError::NoTasksAvailable => DispenseTaskError::NoTasksAvailable,
             DispenseError::Unauthorized => DispenseTaskError::Unauthorized,
@@ -793,6 +1158,112 @@ fn dispense_task() -> DispenseTaskResponse {
     }
 }
 
+#[update(name = "leaseTask")]
+#[candid_method(update, rename = "leaseTask")]
+fn lease_task() -> LeaseTaskResponse {
+    match DISPENSER.with(|d| d.borrow().dispense(true)) {
+        Ok((id, Some(lease))) => LeaseTaskResponse::Ok((id, lease)),
+        Ok((id, None)) => LeaseTaskResponse::Err(DispenseTaskError::UnexpectedError(format!(
+            "task {id} was dispensed without a lease"
+        ))),
+        Err(err) => LeaseTaskResponse::Err(match err {
+            DispenseError::NoTasksAvailable => DispenseTaskError::NoTasksAvailable,
+            DispenseError::Unauthorized => DispenseTaskError::Unauthorized,
+            DispenseError::UnexpectedError(err) => {
+                DispenseTaskError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
+#[update(name = "renewLease")]
+#[candid_method(update, rename = "renewLease")]
+fn renew_lease(id: Id, lease_id: String) -> RenewLeaseResponse {
+    match RENEWER.with(|r| r.borrow().renew(&id, &lease_id)) {
+        Ok(lease) => RenewLeaseResponse::Ok(lease),
+        Err(err) => RenewLeaseResponse::Err(match err {
+            LeaseError::NotHeld => RenewLeaseError::NotHeld,
+            LeaseError::Unauthorized => RenewLeaseError::Unauthorized,
+            LeaseError::UnexpectedError(err) => RenewLeaseError::UnexpectedError(err.to_string()),
+        }),
+    }
+}
+
+#[update(name = "releaseLease")]
+#[candid_method(update, rename = "releaseLease")]
+fn release_lease(id: Id, lease_id: String) -> ReleaseLeaseResponse {
+    match RELEASER.with(|r| r.borrow().release(&id, &lease_id)) {
+        Ok(()) => ReleaseLeaseResponse::Ok(()),
+        Err(err) => ReleaseLeaseResponse::Err(match err {
+            LeaseError::NotHeld => ReleaseLeaseError::NotHeld,
+            LeaseError::Unauthorized => ReleaseLeaseError::Unauthorized,
+            LeaseError::UnexpectedError(err) => ReleaseLeaseError::UnexpectedError(err.to_string()),
+        }),
+    }
+}
+
+// Audit
+
+#[update(name = "recordAuditEntry")]
+#[candid_method(update, rename = "recordAuditEntry")]
+fn record_audit_entry(entry: AuditEntry) -> RecordAuditEntryResponse {
+    match AUDIT_RECORDER.with(|r| r.borrow().record(entry)) {
+        Ok(seq) => RecordAuditEntryResponse::Ok(seq),
+        Err(err) => RecordAuditEntryResponse::Err(match err {
+            AuditError::Unauthorized => RecordAuditEntryError::Unauthorized,
+            AuditError::UnexpectedError(err) => {
+                RecordAuditEntryError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
+#[query(name = "listAuditEntries")]
+#[candid_method(query, rename = "listAuditEntries")]
+fn list_audit_entries(id: Option<Id>, after: Option<u64>, limit: u64) -> ListAuditEntriesResponse {
+    match AUDIT_LISTER.with(|l| l.borrow().list(id, after, limit)) {
+        Ok(entries) => ListAuditEntriesResponse::Ok(entries),
+        Err(err) => ListAuditEntriesResponse::Err(match err {
+            AuditError::Unauthorized => ListAuditEntriesError::Unauthorized,
+            AuditError::UnexpectedError(err) => {
+                ListAuditEntriesError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
+// History
+
+#[update(name = "recordIssuanceEvent")]
+#[candid_method(update, rename = "recordIssuanceEvent")]
+fn record_issuance_event(id: Id, event: IssuanceEvent) -> RecordIssuanceEventResponse {
+    match HISTORY_RECORDER.with(|r| r.borrow().record(&id, event)) {
+        Ok(()) => RecordIssuanceEventResponse::Ok(()),
+        Err(err) => RecordIssuanceEventResponse::Err(match err {
+            HistoryError::NotFound => RecordIssuanceEventError::NotFound,
+            HistoryError::Unauthorized => RecordIssuanceEventError::Unauthorized,
+            HistoryError::UnexpectedError(err) => {
+                RecordIssuanceEventError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
+#[query(name = "getIssuanceHistory")]
+#[candid_method(query, rename = "getIssuanceHistory")]
+fn get_issuance_history(id: Id) -> GetIssuanceHistoryResponse {
+    match HISTORY_GETTER.with(|g| g.borrow().get(&id)) {
+        Ok(history) => GetIssuanceHistoryResponse::Ok(history),
+        Err(err) => GetIssuanceHistoryResponse::Err(match err {
+            HistoryError::NotFound => GetIssuanceHistoryError::NotFound,
+            HistoryError::Unauthorized => GetIssuanceHistoryError::Unauthorized,
+            HistoryError::UnexpectedError(err) => {
+                GetIssuanceHistoryError::UnexpectedError(err.to_string())
+            }
+        }),
+    }
+}
+
 // Metrics
 
 #[query(name = "http_request")]
@@ -817,6 +1288,9 @@ fn http_request(request: HttpRequest) -> HttpResponse {
     // Set Gauges
     REGISTRATIONS.with(|regs| {
         GAUGE_REGISTRATIONS_TOTAL.with(|g| {
+            // Gauges are recomputed on every scrape
+            g.borrow_mut().reset();
+
             regs.borrow().iter().for_each(|(_, reg)| {
                 g.borrow_mut()
                     .with_label_values(&[match reg.state {
@@ -825,6 +1299,7 @@ fn http_request(request: HttpRequest) -> HttpResponse {
                         State::PendingChallengeResponse => "pendingChallengeResponse",
                         State::PendingAcmeApproval => "pendingAcmeApproval",
                         State::Available => "available",
+                        State::Parked(_) => "parked",
                     }])
                     .inc()
             });
@@ -832,7 +1307,27 @@ fn http_request(request: HttpRequest) -> HttpResponse {
     });
 
     TASKS.with(|tasks| {
-        GAUGE_TASKS_TOTAL.with(|g| g.borrow_mut().set(tasks.borrow().len() as f64));
+        let tasks = tasks.borrow();
+        let now = time();
+
+        GAUGE_TASKS_TOTAL.with(|g| g.borrow_mut().set(tasks.len() as f64));
+
+        GAUGE_TASKS_AVAILABLE_TOTAL.with(|g| {
+            let n = tasks.iter().filter(|(_, Reverse(t))| *t <= now).count();
+            g.borrow_mut().set(n as f64)
+        });
+
+        // Tasks are ordered by the time they become due, so the first one is the oldest
+        GAUGE_OLDEST_TASK_AGE_SECONDS.with(|g| {
+            let age = tasks
+                .peek()
+                .map_or(0, |(_, Reverse(t))| now.saturating_sub(*t));
+            g.borrow_mut().set(Duration::from_nanos(age).as_secs_f64())
+        });
+    });
+
+    RETRIES.with(|retries| {
+        GAUGE_TASKS_IN_PROGRESS_TOTAL.with(|g| g.borrow_mut().set(retries.borrow().len() as f64));
     });
 
     ALLOWED_PRINCIPALS.with(|tasks| {
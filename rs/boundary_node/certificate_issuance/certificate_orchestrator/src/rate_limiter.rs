@@ -1,6 +1,6 @@
 use anyhow::{anyhow, Error};
 use candid::Principal;
-use certificate_orchestrator_interface::{Id, Name};
+use certificate_orchestrator_interface::{Id, KeyType, Name};
 use publicsuffix::{List, Psl};
 use std::collections::BTreeMap;
 
@@ -34,7 +34,13 @@ impl<T: Create> WithRateLimit<T> {
 }
 
 impl<T: Create> Create for WithRateLimit<T> {
-    fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError> {
+    fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
         let apex_domain = extract_apex_domain(name, &self.suffix_list)?; // the apex domain being rate-limited
         self.available_tokens.with(|at| {
             let mut at = at.borrow_mut();
@@ -42,7 +48,7 @@ impl<T: Create> Create for WithRateLimit<T> {
             if tokens < 1 {
                 return Err(CreateError::RateLimited(apex_domain));
             };
-            let create_result = self.limited.create(name, canister)?;
+            let create_result = self.limited.create(name, canister, key_type, alt_names)?;
             at.insert(apex_domain, tokens - 1);
             Ok(create_result)
         })
@@ -1,8 +1,9 @@
-use std::{cmp::Reverse, time::Duration};
+use std::{cmp::Reverse, iter::once, mem::discriminant, ops::Bound, time::Duration};
 
 use candid::Principal;
 use certificate_orchestrator_interface::{
-    EncryptedPair, ExportPackage, Id, Name, NameError, Registration, State, UpdateType,
+    EncryptedPair, ExportPackage, Id, IssuanceHistory, KeyType, Lease, Name, NameError,
+    Registration, RegistrationEntry, State, TaskPriority, UpdateType, ALT_NAMES_MAX_LEN,
 };
 use ic_cdk::caller;
 use mockall::automock;
@@ -30,6 +31,8 @@ use crate::{
 pub enum CreateError {
     #[error(transparent)]
     NameError(#[from] NameError),
+    #[error("invalid alternative names: {0}")]
+    InvalidAltNames(String),
     #[error("Registration '{0}' already exists")]
     Duplicate(Id),
     #[error("Rate limit exceeded for domain '{0}'")]
@@ -41,7 +44,13 @@ pub enum CreateError {
 }
 
 pub trait Create {
-    fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError>;
+    fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError>;
 }
 
 pub struct Creator {
@@ -68,12 +77,39 @@ impl Creator {
 }
 
 impl Create for Creator {
-    fn create(&self, name: &str, canister: &Principal) -> Result<Id, CreateError> {
+    fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
         let name: Name = name.try_into()?;
 
+        let alt_names = alt_names
+            .iter()
+            .map(|n| Name::try_from(n.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if alt_names.len() > ALT_NAMES_MAX_LEN {
+            return Err(CreateError::InvalidAltNames(format!(
+                "at most {ALT_NAMES_MAX_LEN} allowed"
+            )));
+        }
+
+        if alt_names
+            .iter()
+            .enumerate()
+            .any(|(i, n)| n == &name || alt_names[..i].contains(n))
+        {
+            return Err(CreateError::InvalidAltNames("names must be unique".into()));
+        }
+
         // Check for duplicate
-        if let Some(id) = self.names.with(|names| names.borrow().get(&name)) {
-            return Err(CreateError::Duplicate(id.into()));
+        for n in once(&name).chain(&alt_names) {
+            if let Some(id) = self.names.with(|names| names.borrow().get(n)) {
+                return Err(CreateError::Duplicate(id.into()));
+            }
         }
 
         // Generate ID
@@ -87,15 +123,19 @@ impl Create for Creator {
                     name: name.to_owned(),
                     canister: canister.to_owned(),
                     state: State::PendingOrder,
+                    key_type,
+                    alt_names: (!alt_names.is_empty()).then(|| alt_names.to_owned()),
                 },
             )
         });
 
         // Update name mapping
         self.names.with(|names| {
-            names
-                .borrow_mut()
-                .insert(name.to_owned(), id.to_owned().into())
+            let mut names = names.borrow_mut();
+
+            for n in once(name).chain(alt_names) {
+                names.insert(n, id.to_owned().into());
+            }
         });
 
         // Schedule expiration
@@ -115,7 +155,13 @@ impl Create for Creator {
 }
 
 impl<T: Create, A: Authorize> Create for WithAuthorize<T, A> {
-    fn create(&self, domain: &str, canister: &Principal) -> Result<Id, CreateError> {
+    fn create(
+        &self,
+        domain: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
         if let Err(err) = self.1.authorize(&caller()) {
             return Err(match err {
                 AuthorizeError::Unauthorized => CreateError::Unauthorized,
@@ -123,13 +169,19 @@ impl<T: Create, A: Authorize> Create for WithAuthorize<T, A> {
             });
         };
 
-        self.0.create(domain, canister)
+        self.0.create(domain, canister, key_type, alt_names)
     }
 }
 
 impl<T: Create> Create for WithMetrics<T> {
-    fn create(&self, domain: &str, canister: &Principal) -> Result<Id, CreateError> {
-        let out = self.0.create(domain, canister);
+    fn create(
+        &self,
+        domain: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        let out = self.0.create(domain, canister, key_type, alt_names);
 
         self.1.with(|c| {
             c.borrow()
@@ -138,6 +190,7 @@ impl<T: Create> Create for WithMetrics<T> {
                         Ok(_) => "ok",
                         Err(err) => match err {
                             CreateError::NameError(_) => "name-error",
+                            CreateError::InvalidAltNames(_) => "invalid-alt-names",
                             CreateError::Duplicate(_) => "duplicate",
                             CreateError::RateLimited(_) => "rate-limited",
                             CreateError::Unauthorized => "unauthorized",
@@ -196,6 +249,84 @@ impl<T: Get, A: Authorize> Get for WithAuthorize<T, A> {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum ListError {
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+pub trait List {
+    fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<RegistrationEntry>, ListError>;
+}
+
+pub struct Lister {
+    registrations: LocalRef<StableMap<StorableId, Registration>>,
+}
+
+impl Lister {
+    pub fn new(registrations: LocalRef<StableMap<StorableId, Registration>>) -> Self {
+        Self { registrations }
+    }
+}
+
+impl List for Lister {
+    fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<RegistrationEntry>, ListError> {
+        self.registrations.with(|regs| {
+            Ok(regs
+                .borrow()
+                .range((
+                    match key {
+                        Some(key) => Bound::Excluded(StorableId::from(key)),
+                        None => Bound::Unbounded,
+                    },
+                    Bound::Unbounded,
+                ))
+                // Only the variant of the state is compared, e.g. any parked registration
+                .filter(|(_, reg)| {
+                    state.as_ref().map_or(true, |state| {
+                        discriminant(state) == discriminant(&reg.state)
+                    })
+                })
+                .take(limit as usize)
+                .map(|(id, registration)| RegistrationEntry {
+                    id: id.into(),
+                    registration,
+                })
+                .collect())
+        })
+    }
+}
+
+impl<T: List, A: Authorize> List for WithAuthorize<T, A> {
+    fn list(
+        &self,
+        key: Option<Id>,
+        limit: u64,
+        state: Option<State>,
+    ) -> Result<Vec<RegistrationEntry>, ListError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => ListError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => ListError::UnexpectedError(err),
+            });
+        };
+
+        self.0.list(key, limit, state)
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum UpdateError {
     #[error("Not found")]
@@ -235,17 +366,10 @@ impl Update for Updater {
         match typ {
             // Update canister ID
             UpdateType::Canister(canister) => self.registrations.with(|regs| {
-                let Registration { name, state, .. } =
-                    regs.borrow().get(&id.into()).ok_or(UpdateError::NotFound)?;
+                let reg = regs.borrow().get(&id.into()).ok_or(UpdateError::NotFound)?;
 
-                regs.borrow_mut().insert(
-                    id.into(),
-                    Registration {
-                        name,
-                        canister,
-                        state,
-                    },
-                );
+                regs.borrow_mut()
+                    .insert(id.into(), Registration { canister, ..reg });
 
                 Ok(())
             }),
@@ -253,30 +377,29 @@ impl Update for Updater {
             // Update state
             UpdateType::State(state) => {
                 self.registrations.with(|regs| {
-                    let Registration { name, canister, .. } =
-                        regs.borrow().get(&id.into()).ok_or(UpdateError::NotFound)?;
+                    let reg = regs.borrow().get(&id.into()).ok_or(UpdateError::NotFound)?;
 
                     regs.borrow_mut().insert(
                         id.into(),
                         Registration {
-                            name,
-                            canister,
                             state: state.to_owned(),
+                            ..reg
                         },
                     );
 
                     Ok::<(), UpdateError>(())
                 })?;
 
-                // Successful registrations should not be expired or retried
-                if state == State::Available {
+                // Successful registrations should not be expired or retried, and neither should
+                // parked ones, which are kept around until an operator retries or removes them
+                if matches!(state, State::Available | State::Parked(_)) {
                     self.expirations.with(|exps| exps.borrow_mut().remove(id));
                     self.retries.with(|rets| rets.borrow_mut().remove(id));
                 }
 
                 // If a registration is being processed, but its expiration has not been scheduled,
                 // schedule it. This is needed, for example, for certificate renewals
-                if state != State::Available
+                if !matches!(state, State::Available | State::Parked(_))
                     && !self
                         .expirations
                         .with(|exps| exps.borrow().get(id).is_some())
@@ -304,6 +427,7 @@ pub struct UpdateWithIcCertification<T> {
     updater: T,
     pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
     registrations: LocalRef<StableMap<StorableId, Registration>>,
+    updated_at: LocalRef<StableMap<StorableId, u64>>,
 }
 
 impl<T: Update> UpdateWithIcCertification<T> {
@@ -311,11 +435,13 @@ impl<T: Update> UpdateWithIcCertification<T> {
         updater: T,
         pairs: LocalRef<StableMap<StorableId, EncryptedPair>>,
         registrations: LocalRef<StableMap<StorableId, Registration>>,
+        updated_at: LocalRef<StableMap<StorableId, u64>>,
     ) -> Self {
         Self {
             updater,
             pairs,
             registrations,
+            updated_at,
         }
     }
 }
@@ -325,7 +451,9 @@ impl<T: Update> Update for UpdateWithIcCertification<T> {
         if let UpdateType::Canister(canister) = typ {
             // If the encrypted pair has been uploaded, update the entry in certification tree
             if let Some(pair) = self.pairs.with(|pairs| pairs.borrow().get(&id.into())) {
-                let Registration { name, .. } = self
+                let Registration {
+                    name, alt_names, ..
+                } = self
                     .registrations
                     .with(|regs| regs.borrow().get(&id.into()))
                     .ok_or(UpdateError::NotFound)?;
@@ -333,11 +461,14 @@ impl<T: Update> Update for UpdateWithIcCertification<T> {
                 let package_to_certify = ExportPackage {
                     id: id.into(),
                     name,
+                    alt_names: alt_names.unwrap_or_default(),
                     canister,
                     pair,
                 };
                 add_cert(id.into(), &package_to_certify);
                 set_root_hash();
+                self.updated_at
+                    .with(|ts| ts.borrow_mut().insert(id.into(), time()));
             }
         }
         self.updater.update(id, typ)
@@ -399,9 +530,13 @@ pub struct Remover {
     registrations: LocalRef<StableMap<StorableId, Registration>>,
     names: LocalRef<StableMap<Name, StorableId>>,
     tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
     expirations: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
     retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    leases: LocalRef<StableMap<StorableId, Lease>>,
     encrypted_certificates: LocalRef<StableMap<StorableId, EncryptedPair>>,
+    updated_at: LocalRef<StableMap<StorableId, u64>>,
+    histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
 }
 
 impl Remover {
@@ -409,24 +544,34 @@ impl Remover {
         registrations: LocalRef<StableMap<StorableId, Registration>>,
         names: LocalRef<StableMap<Name, StorableId>>,
         tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
         expirations: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
         retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        leases: LocalRef<StableMap<StorableId, Lease>>,
         encrypted_certificates: LocalRef<StableMap<StorableId, EncryptedPair>>,
+        updated_at: LocalRef<StableMap<StorableId, u64>>,
+        histories: LocalRef<StableMap<StorableId, IssuanceHistory>>,
     ) -> Self {
         Self {
             registrations,
             names,
             tasks,
+            priorities,
             expirations,
             retries,
+            leases,
             encrypted_certificates,
+            updated_at,
+            histories,
         }
     }
 }
 
 impl Remove for Remover {
     fn remove(&self, id: &Id) -> Result<(), RemoveError> {
-        let Registration { name, .. } = self
+        let Registration {
+            name, alt_names, ..
+        } = self
             .registrations
             .with(|regs| regs.borrow().get(&id.into()).ok_or(RemoveError::NotFound))?;
 
@@ -434,17 +579,37 @@ impl Remove for Remover {
         self.registrations
             .with(|regs| regs.borrow_mut().remove(&id.into()));
 
-        // remove name mapping
-        self.names.with(|names| names.borrow_mut().remove(&name));
+        // remove name mappings
+        self.names.with(|names| {
+            let mut names = names.borrow_mut();
+
+            for n in once(name).chain(alt_names.unwrap_or_default()) {
+                names.remove(&n);
+            }
+        });
 
         // remove task/retry/expiry if present
         [self.tasks, self.retries, self.expirations]
             .map(|pq| pq.with(|pq| pq.borrow_mut().remove(id)));
 
+        // remove task priority if present
+        self.priorities
+            .with(|ps| ps.borrow_mut().remove(&id.into()));
+
+        // remove lease if present
+        self.leases.with(|ls| ls.borrow_mut().remove(&id.into()));
+
         // remove certificate
         self.encrypted_certificates
             .with(|certs| certs.borrow_mut().remove(&id.into()));
 
+        // remove the export timestamp
+        self.updated_at
+            .with(|ts| ts.borrow_mut().remove(&id.into()));
+
+        // remove the issuance history
+        self.histories.with(|hs| hs.borrow_mut().remove(&id.into()));
+
         // remove the IC certificate for the domain
         remove_cert(id.into());
 
@@ -554,7 +719,8 @@ mod tests {
 
     use super::*;
     use crate::{
-        ENCRYPTED_CERTIFICATES, EXPIRATIONS, ID_GENERATOR, NAMES, REGISTRATIONS, RETRIES, TASKS,
+        ENCRYPTED_CERTIFICATES, EXPIRATIONS, HISTORIES, ID_GENERATOR, LEASES, NAMES, REGISTRATIONS,
+        RETRIES, TASKS, TASK_PRIORITIES, UPDATED_AT,
     };
 
     pub fn time() -> u64 {
@@ -579,6 +745,8 @@ mod tests {
             name: Name::try_from("name.com")?,
             canister: Principal::from_text("aaaaa-aa")?,
             state: State::Available,
+            key_type: None,
+            alt_names: None,
         };
 
         REGISTRATIONS.with(|regs| {
@@ -598,6 +766,46 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn list_by_state() -> Result<(), Error> {
+        for (id, state) in [
+            ("id-1", State::Available),
+            ("id-2", State::Parked("error".into())),
+            ("id-3", State::PendingOrder),
+            ("id-4", State::Parked("other error".into())),
+        ] {
+            REGISTRATIONS.with(|regs| {
+                regs.borrow_mut().insert(
+                    id.to_string().into(),
+                    Registration {
+                        name: Name::try_from("name.com").unwrap(),
+                        canister: Principal::from_text("aaaaa-aa").unwrap(),
+                        state,
+                        key_type: None,
+                        alt_names: None,
+                    },
+                )
+            });
+        }
+
+        let lister = Lister::new(&REGISTRATIONS);
+        let parked = Some(State::Parked("".into()));
+
+        let ids = |out: Vec<RegistrationEntry>| out.into_iter().map(|e| e.id).collect::<Vec<_>>();
+
+        assert_eq!(
+            ids(lister.list(None, 10, parked.clone())?),
+            vec!["id-2", "id-4"]
+        );
+        assert_eq!(
+            ids(lister.list(Some("id-2".into()), 10, parked)?),
+            vec!["id-4"]
+        );
+        assert_eq!(ids(lister.list(None, 2, None)?), vec!["id-1", "id-2"]);
+
+        Ok(())
+    }
+
     #[test]
     fn create_ok() -> Result<(), Error> {
         crate::ID_SEED.with(|s| s.borrow_mut().insert((), 0));
@@ -612,6 +820,8 @@ mod tests {
         let id = creator.create(
             "name.com",                         // name
             &Principal::from_text("aaaaa-aa")?, // canister
+            Some(KeyType::Rsa2048),             // key_type
+            &[],                                // alt_names
         )?;
 
         // Check registration
@@ -625,6 +835,8 @@ mod tests {
                 name: Name::try_from("name.com")?,
                 canister: Principal::from_text("aaaaa-aa")?,
                 state: State::PendingOrder,
+                key_type: Some(KeyType::Rsa2048),
+                alt_names: None,
             }
         );
 
@@ -643,12 +855,76 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn create_alt_names() -> Result<(), Error> {
+        crate::ID_SEED.with(|s| s.borrow_mut().insert((), 0));
+
+        REGISTRATION_EXPIRATION_TTL.with(|s| {
+            let mut s = s.borrow_mut();
+            s.insert((), 60 * 60 * 24 * 3);
+        });
+
+        let creator = Creator::new(&ID_GENERATOR, &REGISTRATIONS, &NAMES, &EXPIRATIONS);
+
+        match creator.create(
+            "name.com",
+            &Principal::from_text("aaaaa-aa")?,
+            None,
+            &["name.com".into()],
+        ) {
+            Err(CreateError::InvalidAltNames(_)) => {}
+            other => panic!("expected InvalidAltNames but got {other:?}"),
+        };
+
+        match creator.create(
+            "name.com",
+            &Principal::from_text("aaaaa-aa")?,
+            None,
+            &["www.name.com".into(), "api.name.com".into()],
+        ) {
+            Err(CreateError::InvalidAltNames(_)) => {}
+            other => panic!("expected InvalidAltNames but got {other:?}"),
+        };
+
+        let id = creator.create(
+            "name.com",                         // name
+            &Principal::from_text("aaaaa-aa")?, // canister
+            None,                               // key_type
+            &["www.name.com".into()],           // alt_names
+        )?;
+
+        // Check both names map to the registration
+        for name in ["name.com", "www.name.com"] {
+            let iid: String = NAMES
+                .with(|names| names.borrow().get(&Name::try_from(name).unwrap()))
+                .expect("expected name mapping to exist but none found")
+                .into();
+
+            assert_eq!(id, iid, "expected ids to match");
+        }
+
+        // Alternative names cannot be registered again
+        match creator.create(
+            "www.name.com",
+            &Principal::from_text("aaaaa-aa")?,
+            None,
+            &[],
+        ) {
+            Err(CreateError::Duplicate(iid)) if iid == id => {}
+            other => panic!("expected Duplicate but got {other:?}"),
+        };
+
+        Ok(())
+    }
+
     #[test]
     fn update_canister_ok() -> Result<(), Error> {
         let reg = Registration {
             name: Name::try_from("name.com")?,
             canister: Principal::from_text("aaaaa-aa")?,
             state: State::PendingOrder,
+            key_type: Some(KeyType::Rsa4096),
+            alt_names: None,
         };
 
         REGISTRATIONS.with(|regs| regs.borrow_mut().insert("id".to_string().into(), reg));
@@ -669,6 +945,8 @@ mod tests {
                 name: Name::try_from("name.com")?,
                 canister: Principal::from_text("2ibo7-dia")?,
                 state: State::PendingOrder,
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: None,
             }
         );
 
@@ -681,6 +959,8 @@ mod tests {
             name: Name::try_from("name.com")?,
             canister: Principal::from_text("aaaaa-aa")?,
             state: State::PendingOrder,
+            key_type: None,
+            alt_names: None,
         };
 
         REGISTRATION_EXPIRATION_TTL.with(|s| {
@@ -706,21 +986,53 @@ mod tests {
                 name: Name::try_from("name.com")?,
                 canister: Principal::from_text("aaaaa-aa")?,
                 state: State::PendingChallengeResponse,
+                key_type: None,
+                alt_names: None,
             }
         );
 
         Ok(())
     }
 
+    #[test]
+    fn update_state_parked() -> Result<(), Error> {
+        let reg = Registration {
+            name: Name::try_from("name.com")?,
+            canister: Principal::from_text("aaaaa-aa")?,
+            state: State::PendingOrder,
+            key_type: None,
+            alt_names: None,
+        };
+
+        REGISTRATIONS.with(|regs| regs.borrow_mut().insert("id".to_string().into(), reg));
+        EXPIRATIONS.with(|exps| exps.borrow_mut().push("id".into(), Reverse(0)));
+        RETRIES.with(|rets| rets.borrow_mut().push("id".into(), Reverse(0)));
+
+        Updater::new(&REGISTRATIONS, &EXPIRATIONS, &RETRIES).update(
+            &Id::from("id"),
+            UpdateType::State(State::Parked("error".into())),
+        )?;
+
+        // Parked registrations are neither expired nor retried
+        assert!(EXPIRATIONS.with(|exps| exps.borrow().get("id").is_none()));
+        assert!(RETRIES.with(|rets| rets.borrow().get("id").is_none()));
+
+        Ok(())
+    }
+
     #[test]
     fn remove_not_found() -> Result<(), Error> {
         let r = Remover::new(
             &REGISTRATIONS,
             &NAMES,
             &TASKS,
+            &TASK_PRIORITIES,
             &EXPIRATIONS,
             &RETRIES,
+            &LEASES,
             &ENCRYPTED_CERTIFICATES,
+            &UPDATED_AT,
+            &HISTORIES,
         );
 
         match r.remove(&Id::from("id")) {
@@ -740,6 +1052,8 @@ mod tests {
                     name: Name::try_from("name.com").unwrap(),
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: None,
                 },
             )
         });
@@ -777,13 +1091,27 @@ mod tests {
                 .insert("id".to_string().into(), EncryptedPair(vec![], vec![]))
         });
 
+        HISTORIES.with(|hs| {
+            hs.borrow_mut()
+                .insert("id".to_string().into(), IssuanceHistory::default())
+        });
+
+        TASK_PRIORITIES.with(|ps| {
+            ps.borrow_mut()
+                .insert("id".to_string().into(), TaskPriority::High)
+        });
+
         let r = Remover::new(
             &REGISTRATIONS,
             &NAMES,
             &TASKS,
+            &TASK_PRIORITIES,
             &EXPIRATIONS,
             &RETRIES,
+            &LEASES,
             &ENCRYPTED_CERTIFICATES,
+            &UPDATED_AT,
+            &HISTORIES,
         );
 
         match r.remove(&Id::from("id")) {
@@ -821,6 +1149,16 @@ mod tests {
             Some(_) => panic!("expected certs to be removed, but they were not"),
         });
 
+        HISTORIES.with(|hs| match hs.borrow().get(&"id".to_string().into()) {
+            None => {}
+            Some(_) => panic!("expected issuance history to be removed, but it wasn't"),
+        });
+
+        TASK_PRIORITIES.with(|ps| match ps.borrow().get(&"id".to_string().into()) {
+            None => {}
+            Some(_) => panic!("expected task priority to be removed, but it wasn't"),
+        });
+
         Ok(())
     }
 
@@ -833,6 +1171,8 @@ mod tests {
                     name: Name::try_from("name.com").unwrap(),
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: None,
                 },
             )
         });
@@ -841,9 +1181,13 @@ mod tests {
             &REGISTRATIONS,
             &NAMES,
             &TASKS,
+            &TASK_PRIORITIES,
             &EXPIRATIONS,
             &RETRIES,
+            &LEASES,
             &ENCRYPTED_CERTIFICATES,
+            &UPDATED_AT,
+            &HISTORIES,
         );
 
         match r.remove(&Id::from("id")) {
@@ -0,0 +1,268 @@
+use std::cmp::Reverse;
+
+use candid::Principal;
+use certificate_orchestrator_interface::{
+    Id, KeyType, Lease, Registration, RegistrationTimestamps, UpdateType,
+};
+use ic_cdk::caller;
+use priority_queue::PriorityQueue;
+
+cfg_if::cfg_if! {
+    if #[cfg(test)] {
+        use tests::time;
+    } else {
+        use ic_cdk::api::time;
+    }
+}
+
+use crate::{
+    acl::{Authorize, AuthorizeError, WithAuthorize},
+    registration::{Create, CreateError, GetError, Remove, RemoveError, Update, UpdateError},
+    work::{Dispense, DispenseError},
+    LocalRef, StableMap, StorableId,
+};
+
+// Keeps track of when registrations are created, change state and have their tasks dispensed,
+// on top of the wrapped creator, updater, dispenser or remover
+pub struct WithTimestamps<T> {
+    inner: T,
+    registrations: LocalRef<StableMap<StorableId, Registration>>,
+    timestamps: LocalRef<StableMap<StorableId, RegistrationTimestamps>>,
+}
+
+impl<T> WithTimestamps<T> {
+    pub fn new(
+        inner: T,
+        registrations: LocalRef<StableMap<StorableId, Registration>>,
+        timestamps: LocalRef<StableMap<StorableId, RegistrationTimestamps>>,
+    ) -> Self {
+        Self {
+            inner,
+            registrations,
+            timestamps,
+        }
+    }
+
+    fn set(&self, id: &Id, f: impl FnOnce(&mut RegistrationTimestamps)) {
+        self.timestamps.with(|ts| {
+            let mut ts = ts.borrow_mut();
+
+            let mut t = ts.get(&id.into()).unwrap_or_default();
+            f(&mut t);
+            ts.insert(id.into(), t);
+        });
+    }
+}
+
+impl<T: Create> Create for WithTimestamps<T> {
+    fn create(
+        &self,
+        name: &str,
+        canister: &Principal,
+        key_type: Option<KeyType>,
+        alt_names: &[String],
+    ) -> Result<Id, CreateError> {
+        let id = self.inner.create(name, canister, key_type, alt_names)?;
+
+        let now = time();
+        self.set(&id, |t| {
+            t.created_at = Some(now);
+            t.last_state_change_at = Some(now);
+        });
+
+        Ok(id)
+    }
+}
+
+impl<T: Update> Update for WithTimestamps<T> {
+    fn update(&self, id: &Id, typ: UpdateType) -> Result<(), UpdateError> {
+        let state = self
+            .registrations
+            .with(|regs| regs.borrow().get(&id.into()))
+            .map(|reg| reg.state);
+
+        let is_state_change = matches!(&typ, UpdateType::State(s) if Some(s) != state.as_ref());
+
+        self.inner.update(id, typ)?;
+
+        if is_state_change {
+            let now = time();
+            self.set(id, |t| t.last_state_change_at = Some(now));
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: Dispense> Dispense for WithTimestamps<T> {
+    fn dispense(&self, leased: bool) -> Result<(Id, Option<Lease>), DispenseError> {
+        let (id, lease) = self.inner.dispense(leased)?;
+
+        let now = time();
+        self.set(&id, |t| t.last_attempt_at = Some(now));
+
+        Ok((id, lease))
+    }
+}
+
+impl<T: Remove> Remove for WithTimestamps<T> {
+    fn remove(&self, id: &Id) -> Result<(), RemoveError> {
+        self.inner.remove(id)?;
+
+        self.timestamps
+            .with(|ts| ts.borrow_mut().remove(&id.into()));
+
+        Ok(())
+    }
+}
+
+pub trait GetTimestamps {
+    fn get(&self, id: &Id) -> Result<RegistrationTimestamps, GetError>;
+}
+
+pub struct TimestampsGetter {
+    registrations: LocalRef<StableMap<StorableId, Registration>>,
+    timestamps: LocalRef<StableMap<StorableId, RegistrationTimestamps>>,
+    tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+}
+
+impl TimestampsGetter {
+    pub fn new(
+        registrations: LocalRef<StableMap<StorableId, Registration>>,
+        timestamps: LocalRef<StableMap<StorableId, RegistrationTimestamps>>,
+        tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    ) -> Self {
+        Self {
+            registrations,
+            timestamps,
+            tasks,
+        }
+    }
+}
+
+impl GetTimestamps for TimestampsGetter {
+    fn get(&self, id: &Id) -> Result<RegistrationTimestamps, GetError> {
+        if !self
+            .registrations
+            .with(|regs| regs.borrow().contains_key(&id.into()))
+        {
+            return Err(GetError::NotFound);
+        }
+
+        let t = self
+            .timestamps
+            .with(|ts| ts.borrow().get(&id.into()).unwrap_or_default());
+
+        // Tasks are not queued while being processed
+        let next_scheduled_at = self
+            .tasks
+            .with(|tasks| tasks.borrow().get_priority(id).map(|Reverse(t)| *t));
+
+        Ok(RegistrationTimestamps {
+            next_scheduled_at,
+            ..t
+        })
+    }
+}
+
+impl<T: GetTimestamps, A: Authorize> GetTimestamps for WithAuthorize<T, A> {
+    fn get(&self, id: &Id) -> Result<RegistrationTimestamps, GetError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => GetError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => GetError::UnexpectedError(err),
+            });
+        };
+
+        self.0.get(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use certificate_orchestrator_interface::{Name, State};
+
+    use crate::{REGISTRATIONS, REGISTRATION_TIMESTAMPS, TASKS};
+
+    pub fn time() -> u64 {
+        42
+    }
+
+    // Updates registrations in place, like the actual updater
+    struct TestUpdater;
+
+    impl Update for TestUpdater {
+        fn update(&self, id: &Id, typ: UpdateType) -> Result<(), UpdateError> {
+            REGISTRATIONS.with(|regs| {
+                let reg = regs.borrow().get(&id.into()).ok_or(UpdateError::NotFound)?;
+
+                let reg = match typ {
+                    UpdateType::Canister(canister) => Registration { canister, ..reg },
+                    UpdateType::State(state) => Registration { state, ..reg },
+                };
+
+                regs.borrow_mut().insert(id.into(), reg);
+
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn state_changes() {
+        REGISTRATIONS.with(|regs| {
+            regs.borrow_mut().insert(
+                "id".into(),
+                Registration {
+                    name: Name::try_from("example.com").unwrap(),
+                    canister: Principal::from_text("aaaaa-aa").unwrap(),
+                    state: State::PendingOrder,
+                    key_type: None,
+                    alt_names: None,
+                },
+            )
+        });
+
+        let u = WithTimestamps::new(TestUpdater, &REGISTRATIONS, &REGISTRATION_TIMESTAMPS);
+        let g = TimestampsGetter::new(&REGISTRATIONS, &REGISTRATION_TIMESTAMPS, &TASKS);
+
+        // Registrations that predate timestamps have none
+        assert_eq!(
+            g.get(&"id".into()).unwrap(),
+            RegistrationTimestamps::default()
+        );
+
+        // Updates to the same state or of the canister are not state changes
+        u.update(&"id".into(), UpdateType::State(State::PendingOrder))
+            .unwrap();
+        u.update(
+            &"id".into(),
+            UpdateType::Canister(Principal::from_text("2ibo7-dia").unwrap()),
+        )
+        .unwrap();
+
+        assert_eq!(g.get(&"id".into()).unwrap().last_state_change_at, None);
+
+        u.update(&"id".into(), UpdateType::State(State::Available))
+            .unwrap();
+
+        assert_eq!(g.get(&"id".into()).unwrap().last_state_change_at, Some(42));
+
+        // The next task is taken from the queue
+        TASKS.with(|tasks| tasks.borrow_mut().push("id".into(), Reverse(100)));
+
+        assert_eq!(
+            g.get(&"id".into()).unwrap(),
+            RegistrationTimestamps {
+                created_at: None,
+                last_attempt_at: None,
+                next_scheduled_at: Some(100),
+                last_state_change_at: Some(42),
+            }
+        );
+
+        assert!(matches!(g.get(&"other".into()), Err(GetError::NotFound)));
+    }
+}
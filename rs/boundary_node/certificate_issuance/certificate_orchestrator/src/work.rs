@@ -1,9 +1,9 @@
 use std::{cmp::Reverse, time::Duration};
 
-use certificate_orchestrator_interface::{Id, Registration};
+use certificate_orchestrator_interface::{Id, Lease, Registration, TaskPriority};
 use ic_cdk::caller;
 use priority_queue::PriorityQueue;
-use prometheus::labels;
+use prometheus::{labels, HistogramVec};
 
 cfg_if::cfg_if! {
     if #[cfg(test)] {
@@ -15,9 +15,15 @@ cfg_if::cfg_if! {
 
 use crate::{
     acl::{Authorize, AuthorizeError, WithAuthorize},
+    id::Generate,
     LocalRef, StableMap, StorableId, WithMetrics, IN_PROGRESS_TTL,
 };
 
+// Time until an unreleased lease expires, after which its task is reclaimed
+fn lease_ttl() -> Duration {
+    Duration::from_secs(IN_PROGRESS_TTL.with(|s| s.borrow().get(&()).unwrap()))
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum QueueError {
     #[error("Not found")]
@@ -28,34 +34,55 @@ pub enum QueueError {
     UnexpectedError(#[from] anyhow::Error),
 }
 
+fn priority_label(priority: TaskPriority) -> &'static str {
+    match priority {
+        TaskPriority::Low => "low",
+        TaskPriority::Normal => "normal",
+        TaskPriority::High => "high",
+    }
+}
+
 pub trait Queue {
-    fn queue(&self, id: Id, timestamp: u64) -> Result<(), QueueError>;
+    fn queue(&self, id: Id, timestamp: u64, priority: TaskPriority) -> Result<(), QueueError>;
 }
 
 pub struct Queuer {
     tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
     registrations: LocalRef<StableMap<StorableId, Registration>>,
 }
 
 impl Queuer {
     pub fn new(
         tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
         registrations: LocalRef<StableMap<StorableId, Registration>>,
     ) -> Self {
         Self {
             tasks,
+            priorities,
             registrations,
         }
     }
 }
 
 impl Queue for Queuer {
-    fn queue(&self, id: Id, timestamp: u64) -> Result<(), QueueError> {
+    fn queue(&self, id: Id, timestamp: u64, priority: TaskPriority) -> Result<(), QueueError> {
         self.registrations.with(|regs| {
             let regs = regs.borrow();
             regs.get(&id.to_owned().into()).ok_or(QueueError::NotFound)
         })?;
 
+        // Only non-default priorities are kept, so that dispensing stays cheap while there are none
+        self.priorities.with(|ps| {
+            let mut ps = ps.borrow_mut();
+
+            match priority {
+                TaskPriority::Normal => ps.remove(&id.to_owned().into()),
+                _ => ps.insert(id.to_owned().into(), priority),
+            }
+        });
+
         self.tasks.with(|tasks| {
             let mut tasks = tasks.borrow_mut();
             tasks.push(id, Reverse(timestamp));
@@ -66,7 +93,7 @@ impl Queue for Queuer {
 }
 
 impl<T: Queue, A: Authorize> Queue for WithAuthorize<T, A> {
-    fn queue(&self, id: Id, timestamp: u64) -> Result<(), QueueError> {
+    fn queue(&self, id: Id, timestamp: u64, priority: TaskPriority) -> Result<(), QueueError> {
         if let Err(err) = self.1.authorize(&caller()) {
             return Err(match err {
                 AuthorizeError::Unauthorized => QueueError::Unauthorized,
@@ -74,13 +101,13 @@ impl<T: Queue, A: Authorize> Queue for WithAuthorize<T, A> {
             });
         };
 
-        self.0.queue(id, timestamp)
+        self.0.queue(id, timestamp, priority)
     }
 }
 
 impl<T: Queue> Queue for WithMetrics<T> {
-    fn queue(&self, id: Id, timestamp: u64) -> Result<(), QueueError> {
-        let out = self.0.queue(id, timestamp);
+    fn queue(&self, id: Id, timestamp: u64, priority: TaskPriority) -> Result<(), QueueError> {
+        let out = self.0.queue(id, timestamp, priority);
 
         self.1.with(|c| {
             c.borrow()
@@ -178,6 +205,78 @@ impl<T: Peek> Peek for WithMetrics<T> {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum CountError {
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// Counts the tasks that are due, i.e. waiting to be dispensed
+pub trait Count {
+    fn count(&self) -> Result<u64, CountError>;
+}
+
+pub struct TaskCounter {
+    tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+}
+
+impl TaskCounter {
+    pub fn new(tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>) -> Self {
+        Self { tasks }
+    }
+}
+
+impl Count for TaskCounter {
+    fn count(&self) -> Result<u64, CountError> {
+        let now = time();
+
+        Ok(self.tasks.with(|tasks| {
+            tasks
+                .borrow()
+                .iter()
+                .filter(|(_, Reverse(t))| *t <= now)
+                .count() as u64
+        }))
+    }
+}
+
+impl<T: Count, A: Authorize> Count for WithAuthorize<T, A> {
+    fn count(&self) -> Result<u64, CountError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => CountError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => CountError::UnexpectedError(err),
+            });
+        };
+
+        self.0.count()
+    }
+}
+
+impl<T: Count> Count for WithMetrics<T> {
+    fn count(&self) -> Result<u64, CountError> {
+        let out = self.0.count();
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            CountError::Unauthorized => "unauthorized",
+                            CountError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum DispenseError {
     #[error("No tasks available")]
@@ -189,60 +288,135 @@ pub enum DispenseError {
 }
 
 pub trait Dispense {
-    fn dispense(&self) -> Result<Id, DispenseError>;
+    // Unleased tasks are only reclaimed by their retry, for callers that do not release leases
+    fn dispense(&self, leased: bool) -> Result<(Id, Option<Lease>), DispenseError>;
 }
 
 pub struct Dispenser {
     tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
     retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    leases: LocalRef<StableMap<StorableId, Lease>>,
+    generator: LocalRef<Box<dyn Generate>>,
+    wait_times: LocalRef<HistogramVec>,
 }
 
 impl Dispenser {
     pub fn new(
         tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        priorities: LocalRef<StableMap<StorableId, TaskPriority>>,
         retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        leases: LocalRef<StableMap<StorableId, Lease>>,
+        generator: LocalRef<Box<dyn Generate>>,
+        wait_times: LocalRef<HistogramVec>,
     ) -> Self {
-        Self { tasks, retries }
+        Self {
+            tasks,
+            priorities,
+            retries,
+            leases,
+            generator,
+            wait_times,
+        }
+    }
+
+    // The due task with the highest priority, the oldest one among equals
+    fn next(&self, tasks: &PriorityQueue<Id, Reverse<u64>>, now: u64) -> Option<Id> {
+        self.priorities.with(|ps| {
+            let ps = ps.borrow();
+
+            if ps.is_empty() {
+                return tasks.peek().map(|(id, _)| id.to_owned());
+            }
+
+            tasks
+                .iter()
+                .filter(|(_, Reverse(t))| *t <= now)
+                .max_by_key(|(id, Reverse(t))| {
+                    let p = ps.get(&id.to_owned().into()).unwrap_or_default();
+                    (p, Reverse(*t))
+                })
+                .map(|(id, _)| id.to_owned())
+        })
     }
 }
 
 impl Dispense for Dispenser {
-    fn dispense(&self) -> Result<Id, DispenseError> {
-        self.tasks.with(|tasks| {
-            // Check for available task
-            match tasks.borrow().peek() {
-                None => return Err(DispenseError::NoTasksAvailable),
-                Some((_, Reverse(timestamp))) => {
-                    if time().lt(timestamp) {
-                        return Err(DispenseError::NoTasksAvailable);
+    fn dispense(&self, leased: bool) -> Result<(Id, Option<Lease>), DispenseError> {
+        let (id, timestamp) = self.tasks.with(|tasks| {
+            let mut tasks = tasks.borrow_mut();
+
+            loop {
+                // Check for available task
+                match tasks.peek() {
+                    None => return Err(DispenseError::NoTasksAvailable),
+                    Some((_, Reverse(timestamp))) => {
+                        if time().lt(timestamp) {
+                            return Err(DispenseError::NoTasksAvailable);
+                        }
                     }
+                };
+
+                // Take task
+                let (id, Reverse(timestamp)) =
+                    match self.next(&tasks, time()).and_then(|id| tasks.remove(&id)) {
+                        None => return Err(DispenseError::NoTasksAvailable),
+                        Some(task) => task,
+                    };
+
+                // Tasks queued while their registration is leased wait for the lease to end
+                let lease = self
+                    .leases
+                    .with(|ls| ls.borrow().get(&id.to_owned().into()));
+
+                match lease {
+                    Some(lease) if lease.expires_at > time() => {
+                        tasks.push(id, Reverse(lease.expires_at));
+                    }
+                    _ => return Ok((id, timestamp)),
                 }
-            };
-
-            // Pop task
-            let id = match tasks.borrow_mut().pop() {
-                None => return Err(DispenseError::NoTasksAvailable),
-                Some((id, _)) => id,
-            };
-
-            // Schedule a retry in case the task failed and was not re-queued
-            let retry_delay =
-                Duration::from_secs(IN_PROGRESS_TTL.with(|s| s.borrow().get(&()).unwrap()));
-
-            self.retries.with(|retries| {
-                retries.borrow_mut().push(
-                    id.to_owned(),
-                    Reverse(time() + retry_delay.as_nanos() as u64),
-                )
+            }
+        })?;
+
+        let priority = self
+            .priorities
+            .with(|ps| ps.borrow().get(&id.to_owned().into()))
+            .unwrap_or_default();
+
+        self.wait_times.with(|h| {
+            h.borrow()
+                .with_label_values(&[priority_label(priority)])
+                .observe(Duration::from_nanos(time().saturating_sub(timestamp)).as_secs_f64())
+        });
+
+        let expires_at = time() + lease_ttl().as_nanos() as u64;
+
+        let lease = leased.then(|| Lease {
+            id: self.generator.with(|g| g.borrow().generate()),
+            expires_at,
+            priority: Some(priority),
+        });
+
+        if let Some(lease) = &lease {
+            self.leases.with(|ls| {
+                ls.borrow_mut()
+                    .insert(id.to_owned().into(), lease.to_owned())
             });
+        }
 
-            Ok(id)
-        })
+        // Schedule a retry in case the task failed and was not re-queued
+        self.retries.with(|retries| {
+            retries
+                .borrow_mut()
+                .push(id.to_owned(), Reverse(expires_at))
+        });
+
+        Ok((id, lease))
     }
 }
 
 impl<T: Dispense, A: Authorize> Dispense for WithAuthorize<T, A> {
-    fn dispense(&self) -> Result<Id, DispenseError> {
+    fn dispense(&self, leased: bool) -> Result<(Id, Option<Lease>), DispenseError> {
         if let Err(err) = self.1.authorize(&caller()) {
             return Err(match err {
                 AuthorizeError::Unauthorized => DispenseError::Unauthorized,
@@ -250,13 +424,13 @@ impl<T: Dispense, A: Authorize> Dispense for WithAuthorize<T, A> {
             });
         };
 
-        self.0.dispense()
+        self.0.dispense(leased)
     }
 }
 
 impl<T: Dispense> Dispense for WithMetrics<T> {
-    fn dispense(&self) -> Result<Id, DispenseError> {
-        let out = self.0.dispense();
+    fn dispense(&self, leased: bool) -> Result<(Id, Option<Lease>), DispenseError> {
+        let out = self.0.dispense(leased);
 
         self.1.with(|c| {
             c.borrow()
@@ -277,6 +451,207 @@ impl<T: Dispense> Dispense for WithMetrics<T> {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+pub enum LeaseError {
+    #[error("Lease not held")]
+    NotHeld,
+    #[error("Unauthorized")]
+    Unauthorized,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+// A lease is held as long as it has not been granted to anyone else, even past its expiry
+fn check_lease(
+    leases: LocalRef<StableMap<StorableId, Lease>>,
+    id: &Id,
+    lease_id: &str,
+) -> Result<Lease, LeaseError> {
+    match leases.with(|ls| ls.borrow().get(&id.to_owned().into())) {
+        Some(lease) if lease.id == lease_id => Ok(lease),
+        _ => Err(LeaseError::NotHeld),
+    }
+}
+
+// Tasks that were deferred until the end of a lease are rescheduled along with it
+fn reschedule_deferred(
+    tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    id: &Id,
+    from: u64,
+    to: u64,
+) {
+    tasks.with(|tasks| {
+        let mut tasks = tasks.borrow_mut();
+
+        if tasks.get_priority(id) == Some(&Reverse(from)) {
+            tasks.change_priority(id, Reverse(to));
+        }
+    });
+}
+
+pub trait Renew {
+    fn renew(&self, id: &Id, lease_id: &str) -> Result<Lease, LeaseError>;
+}
+
+pub struct Renewer {
+    tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    leases: LocalRef<StableMap<StorableId, Lease>>,
+}
+
+impl Renewer {
+    pub fn new(
+        tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        leases: LocalRef<StableMap<StorableId, Lease>>,
+    ) -> Self {
+        Self {
+            tasks,
+            retries,
+            leases,
+        }
+    }
+}
+
+impl Renew for Renewer {
+    fn renew(&self, id: &Id, lease_id: &str) -> Result<Lease, LeaseError> {
+        let prev = check_lease(self.leases, id, lease_id)?;
+
+        let lease = Lease {
+            expires_at: time() + lease_ttl().as_nanos() as u64,
+            ..prev.to_owned()
+        };
+
+        self.leases.with(|ls| {
+            ls.borrow_mut()
+                .insert(id.to_owned().into(), lease.to_owned())
+        });
+
+        // Push back the retry, so the task is not reclaimed while the lease is held
+        self.retries.with(|retries| {
+            retries
+                .borrow_mut()
+                .push(id.to_owned(), Reverse(lease.expires_at))
+        });
+
+        reschedule_deferred(self.tasks, id, prev.expires_at, lease.expires_at);
+
+        Ok(lease)
+    }
+}
+
+impl<T: Renew, A: Authorize> Renew for WithAuthorize<T, A> {
+    fn renew(&self, id: &Id, lease_id: &str) -> Result<Lease, LeaseError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => LeaseError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => LeaseError::UnexpectedError(err),
+            });
+        };
+
+        self.0.renew(id, lease_id)
+    }
+}
+
+impl<T: Renew> Renew for WithMetrics<T> {
+    fn renew(&self, id: &Id, lease_id: &str) -> Result<Lease, LeaseError> {
+        let out = self.0.renew(id, lease_id);
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            LeaseError::NotHeld => "not-held",
+                            LeaseError::Unauthorized => "unauthorized",
+                            LeaseError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
+pub trait Release {
+    fn release(&self, id: &Id, lease_id: &str) -> Result<(), LeaseError>;
+}
+
+pub struct Releaser {
+    tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    leases: LocalRef<StableMap<StorableId, Lease>>,
+}
+
+impl Releaser {
+    pub fn new(
+        tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        leases: LocalRef<StableMap<StorableId, Lease>>,
+    ) -> Self {
+        Self {
+            tasks,
+            retries,
+            leases,
+        }
+    }
+}
+
+impl Release for Releaser {
+    fn release(&self, id: &Id, lease_id: &str) -> Result<(), LeaseError> {
+        let lease = check_lease(self.leases, id, lease_id)?;
+
+        self.leases
+            .with(|ls| ls.borrow_mut().remove(&id.to_owned().into()));
+
+        // The task was re-queued by the holder, so it no longer needs to be reclaimed
+        self.retries.with(|retries| retries.borrow_mut().remove(id));
+
+        reschedule_deferred(self.tasks, id, lease.expires_at, time());
+
+        Ok(())
+    }
+}
+
+impl<T: Release, A: Authorize> Release for WithAuthorize<T, A> {
+    fn release(&self, id: &Id, lease_id: &str) -> Result<(), LeaseError> {
+        if let Err(err) = self.1.authorize(&caller()) {
+            return Err(match err {
+                AuthorizeError::Unauthorized => LeaseError::Unauthorized,
+                AuthorizeError::UnexpectedError(err) => LeaseError::UnexpectedError(err),
+            });
+        };
+
+        self.0.release(id, lease_id)
+    }
+}
+
+impl<T: Release> Release for WithMetrics<T> {
+    fn release(&self, id: &Id, lease_id: &str) -> Result<(), LeaseError> {
+        let out = self.0.release(id, lease_id);
+
+        self.1.with(|c| {
+            c.borrow()
+                .with(&labels! {
+                    "status" => match &out {
+                        Ok(_) => "ok",
+                        Err(err) => match err {
+                            LeaseError::NotHeld => "not-held",
+                            LeaseError::Unauthorized => "unauthorized",
+                            LeaseError::UnexpectedError(_) => "fail",
+                        },
+                    },
+                })
+                .inc()
+        });
+
+        out
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum RetryError {
     #[error(transparent)]
@@ -290,14 +665,20 @@ pub trait Retry {
 pub struct Retrier {
     tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
     retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+    leases: LocalRef<StableMap<StorableId, Lease>>,
 }
 
 impl Retrier {
     pub fn new(
         tasks: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
         retries: LocalRef<PriorityQueue<Id, Reverse<u64>>>,
+        leases: LocalRef<StableMap<StorableId, Lease>>,
     ) -> Self {
-        Self { tasks, retries }
+        Self {
+            tasks,
+            retries,
+            leases,
+        }
     }
 }
 
@@ -323,6 +704,10 @@ impl Retry for Retrier {
                     None => break,
                 };
 
+                // The lease expired without being renewed, so its holder is presumed gone
+                self.leases
+                    .with(|ls| ls.borrow_mut().remove(&id.to_owned().into()));
+
                 // Schedule a task for the ID
                 self.tasks.with(|tasks| {
                     let mut tasks = tasks.borrow_mut();
@@ -339,7 +724,9 @@ impl Retry for Retrier {
 mod tests {
     use super::*;
 
-    use crate::{RETRIES, TASKS};
+    use crate::{
+        HISTOGRAM_TASK_WAIT_SECONDS, ID_GENERATOR, ID_SEED, LEASES, RETRIES, TASKS, TASK_PRIORITIES,
+    };
 
     pub fn time() -> u64 {
         0
@@ -347,7 +734,16 @@ mod tests {
 
     #[test]
     fn dispense_empty() {
-        match Dispenser::new(&TASKS, &RETRIES).dispense() {
+        match Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        )
+        .dispense(true)
+        {
             Err(DispenseError::NoTasksAvailable) => {}
             _ => panic!("Not the error that was expected."),
         };
@@ -367,12 +763,35 @@ mod tests {
             )
         });
 
-        let id = match Dispenser::new(&TASKS, &RETRIES).dispense() {
-            Ok(id) => id,
+        ID_SEED.with(|s| s.borrow_mut().insert((), 0));
+
+        let d = Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        );
+
+        let (id, lease) = match d.dispense(true) {
+            Ok((id, Some(lease))) => (id, lease),
             other => panic!("expected id but got {other:?}"),
         };
 
         assert_eq!(id, "id");
+        assert_eq!(lease.expires_at, 10 * 60 * 1_000_000_000);
+    }
+
+    #[test]
+    fn count_due() {
+        TASKS.with(|t| {
+            let mut t = t.borrow_mut();
+            t.push("due".into(), Reverse(0));
+            t.push("scheduled".into(), Reverse(1));
+        });
+
+        assert_eq!(TaskCounter::new(&TASKS).count().unwrap(), 1);
     }
 
     #[test]
@@ -384,9 +803,128 @@ mod tests {
             )
         });
 
-        match Dispenser::new(&TASKS, &RETRIES).dispense() {
+        match Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        )
+        .dispense(true)
+        {
             Err(DispenseError::NoTasksAvailable) => {}
             other => panic!("expected NoTasksAvailable but got {other:?}"),
         };
     }
+
+    #[test]
+    fn dispense_leased() {
+        IN_PROGRESS_TTL.with(|s| s.borrow_mut().insert((), 10 * 60));
+        ID_SEED.with(|s| s.borrow_mut().insert((), 0));
+
+        let d = Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        );
+        let r = Releaser::new(&TASKS, &RETRIES, &LEASES);
+
+        TASKS.with(|t| t.borrow_mut().push("id".into(), Reverse(0)));
+
+        let (id, lease) = d.dispense(true).expect("failed to dispense task");
+        let lease = lease.expect("task was not leased");
+
+        // Queued again while leased
+        TASKS.with(|t| t.borrow_mut().push("id".into(), Reverse(0)));
+
+        match d.dispense(true) {
+            Err(DispenseError::NoTasksAvailable) => {}
+            other => panic!("expected NoTasksAvailable but got {other:?}"),
+        };
+
+        match r.release(&id, "other") {
+            Err(LeaseError::NotHeld) => {}
+            other => panic!("expected NotHeld but got {other:?}"),
+        };
+
+        r.release(&id, &lease.id).expect("failed to release lease");
+
+        let (other, next) = d.dispense(true).expect("failed to dispense task");
+        assert_eq!(other, id);
+        assert_ne!(next.expect("task was not leased").id, lease.id);
+    }
+
+    #[test]
+    fn dispense_unleased() {
+        IN_PROGRESS_TTL.with(|s| s.borrow_mut().insert((), 10 * 60));
+
+        let d = Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        );
+
+        TASKS.with(|t| t.borrow_mut().push("id".into(), Reverse(0)));
+
+        let (id, lease) = d.dispense(false).expect("failed to dispense task");
+        assert!(lease.is_none());
+
+        // Follow-up tasks are not deferred, but the task is still reclaimed if it is not
+        TASKS.with(|t| t.borrow_mut().push("id".into(), Reverse(0)));
+        assert_eq!(d.dispense(false).expect("failed to dispense task").0, id);
+
+        RETRIES.with(|rs| assert!(rs.borrow().get(&id).is_some()));
+    }
+
+    #[test]
+    fn dispense_by_priority() {
+        IN_PROGRESS_TTL.with(|s| s.borrow_mut().insert((), 10 * 60));
+        ID_SEED.with(|s| s.borrow_mut().insert((), 0));
+
+        TASK_PRIORITIES.with(|ps| {
+            let mut ps = ps.borrow_mut();
+            ps.insert("high".into(), TaskPriority::High);
+            ps.insert("low".into(), TaskPriority::Low);
+        });
+
+        TASKS.with(|t| {
+            let mut t = t.borrow_mut();
+            for id in ["low", "normal", "high"] {
+                t.push(id.into(), Reverse(0));
+            }
+        });
+
+        let d = Dispenser::new(
+            &TASKS,
+            &TASK_PRIORITIES,
+            &RETRIES,
+            &LEASES,
+            &ID_GENERATOR,
+            &HISTOGRAM_TASK_WAIT_SECONDS,
+        );
+
+        for (expected, priority) in [
+            ("high", TaskPriority::High),
+            ("normal", TaskPriority::Normal),
+            ("low", TaskPriority::Low),
+        ] {
+            let (id, lease) = d.dispense(true).expect("failed to dispense task");
+            assert_eq!(id, expected);
+            assert_eq!(lease.and_then(|lease| lease.priority), Some(priority));
+        }
+
+        HISTOGRAM_TASK_WAIT_SECONDS.with(|h| {
+            let h = h.borrow();
+            for p in ["low", "normal", "high"] {
+                assert_eq!(h.with_label_values(&[p]).get_sample_count(), 1);
+            }
+        });
+    }
 }
@@ -105,6 +105,10 @@ impl<const N: usize> Storable for BoundedString<N> {
 #[derive(CandidType, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
 pub struct Name(String);
 
+// ALT_NAMES_MAX_LEN is the maximum number of names a registration may cover in addition to its name
+// (e.g. `www.example.com` for `example.com`). It is bounded by the storage size of a registration.
+pub const ALT_NAMES_MAX_LEN: usize = 1;
+
 // NAME_MAX_LEN is the maximum length a name is allowed to have.
 // Based on https://en.wikipedia.org/wiki/Domain_name#Domain_name_syntax
 pub const NAME_MAX_LEN: u32 = 253;
@@ -131,6 +135,10 @@ impl TryFrom<&str> for Name {
     type Error = NameError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // Names are case-insensitive, so they are kept in lowercase
+        // to detect registrations of the same name in a different case
+        let value = value.to_lowercase();
+
         if value.len() > NAME_MAX_LEN as usize {
             return Err(NameError::InvalidSize(value.len()));
         }
@@ -140,7 +148,7 @@ impl TryFrom<&str> for Name {
         }
 
         // Ensure it's a valid domain name
-        let name = addr::parse_domain_name(value)
+        let name = addr::parse_domain_name(&value)
             .map_err(|err| NameError::InvalidDomain(err.to_string()))?;
 
         if name.as_str().matches('.').count() == 0 {
@@ -184,6 +192,22 @@ pub enum State {
 
     #[serde(rename = "available")]
     Available,
+
+    #[serde(rename = "parked")]
+    Parked(BoundedString<127>),
+}
+
+// Key algorithm of the certificates issued for a registration
+#[derive(Debug, CandidType, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum KeyType {
+    #[serde(rename = "ecdsaP256")]
+    EcdsaP256,
+
+    #[serde(rename = "rsa2048")]
+    Rsa2048,
+
+    #[serde(rename = "rsa4096")]
+    Rsa4096,
 }
 
 #[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
@@ -191,6 +215,14 @@ pub struct Registration {
     pub name: Name,
     pub canister: Principal,
     pub state: State,
+
+    // The issuer's default is used when unset
+    #[serde(rename = "keyType")]
+    pub key_type: Option<KeyType>,
+
+    // Additional names covered by the same certificate
+    #[serde(rename = "altNames")]
+    pub alt_names: Option<Vec<Name>>,
 }
 
 impl Storable for Registration {
@@ -216,6 +248,8 @@ impl Storable for Registration {
 pub struct ExportPackage {
     pub id: Id,
     pub name: Name,
+    #[serde(rename = "altNames")]
+    pub alt_names: Vec<Name>,
     pub canister: Principal,
     pub pair: EncryptedPair,
 }
@@ -248,6 +282,24 @@ pub enum GetRegistrationResponse {
     Err(GetRegistrationError),
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct RegistrationEntry {
+    pub id: Id,
+    pub registration: Registration,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ListRegistrationsError {
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ListRegistrationsResponse {
+    Ok(Vec<RegistrationEntry>),
+    Err(ListRegistrationsError),
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum UpdateType {
     Canister(Principal),
@@ -306,6 +358,19 @@ pub enum UploadCertificateResponse {
     Err(UploadCertificateError),
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RevokeCertificateError {
+    NotFound,
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RevokeCertificateResponse {
+    Ok(()),
+    Err(RevokeCertificateError),
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum ExportCertificatesError {
     Unauthorized,
@@ -326,12 +391,67 @@ pub struct IcCertificate {
     pub tree: Vec<u8>,
 }
 
+// Filters applied by the canister when exporting certificates.
+// A state filter matches on the variant only, i.e. any failure reason matches `Failed`.
+#[derive(Clone, Debug, Default, PartialEq, CandidType, Deserialize)]
+pub struct ExportFilter {
+    #[serde(rename = "nameSuffix")]
+    pub name_suffix: Option<String>,
+    pub state: Option<State>,
+    #[serde(rename = "updatedSince")]
+    pub updated_since: Option<u64>,
+}
+
+impl ExportFilter {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum ExportCertificatesCertifiedResponse {
     Ok((Vec<ExportPackage>, IcCertificate)),
     Err(ExportCertificatesError),
 }
 
+// Filtered pages additionally carry the key to continue the export from, since a page can end
+// before `limit` matches were found. It is absent once the end of the export was reached.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ExportCertificatesFilteredResponse {
+    Ok((Vec<ExportPackage>, IcCertificate, Option<Id>)),
+    Err(ExportCertificatesError),
+}
+
+// Order in which due tasks are dispensed, e.g. so that renewals of certificates about to expire
+// don't wait behind new registrations. Tasks of the same priority are dispensed oldest first.
+#[derive(Debug, CandidType, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum TaskPriority {
+    #[serde(rename = "low")]
+    Low,
+
+    #[default]
+    #[serde(rename = "normal")]
+    Normal,
+
+    #[serde(rename = "high")]
+    High,
+}
+
+impl Storable for TaskPriority {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 32,
+        is_fixed_size: false,
+    };
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum QueueTaskError {
     NotFound,
@@ -358,6 +478,18 @@ pub enum PeekTaskResponse {
     Err(PeekTaskError),
 }
 
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum CountTasksError {
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum CountTasksResponse {
+    Ok(u64),
+    Err(CountTasksError),
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum DispenseTaskError {
     NoTasksAvailable,
@@ -371,6 +503,271 @@ pub enum DispenseTaskResponse {
     Err(DispenseTaskError),
 }
 
+// Exclusive hold of a dispensed task by an issuer, until it is released or expires
+#[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
+pub struct Lease {
+    pub id: String,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: u64,
+
+    // Priority the task was queued with, so that its follow-up tasks can keep it
+    pub priority: Option<TaskPriority>,
+}
+
+impl Storable for Lease {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum LeaseTaskResponse {
+    Ok((Id, Lease)),
+    Err(DispenseTaskError),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RenewLeaseError {
+    NotHeld,
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RenewLeaseResponse {
+    Ok(Lease),
+    Err(RenewLeaseError),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ReleaseLeaseError {
+    NotHeld,
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ReleaseLeaseResponse {
+    Ok(()),
+    Err(ReleaseLeaseError),
+}
+
+#[derive(Debug, CandidType, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AuditAction {
+    #[serde(rename = "create")]
+    Create,
+
+    #[serde(rename = "update")]
+    Update,
+
+    #[serde(rename = "remove")]
+    Remove,
+}
+
+// A mutation of a registration, recorded by the issuer that made it
+#[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub actor: BoundedString<63>,
+    pub id: Id,
+    pub action: AuditAction,
+    #[serde(rename = "fromState")]
+    pub from_state: Option<State>,
+    #[serde(rename = "toState")]
+    pub to_state: Option<State>,
+    pub cause: BoundedString<127>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 1024,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RecordAuditEntryError {
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RecordAuditEntryResponse {
+    Ok(u64),
+    Err(RecordAuditEntryError),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ListAuditEntriesError {
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum ListAuditEntriesResponse {
+    Ok(Vec<(u64, AuditEntry)>),
+    Err(ListAuditEntriesError),
+}
+
+// Entries of each kind kept in the issuance history of a registration
+pub const ISSUANCE_HISTORY_MAX_LEN: usize = 10;
+
+#[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
+pub struct IssuedCertificate {
+    pub serial: BoundedString<64>, // hex-encoded
+    #[serde(rename = "notBefore")]
+    pub not_before: u64,
+    #[serde(rename = "notAfter")]
+    pub not_after: u64,
+    #[serde(rename = "issuedAt")]
+    pub issued_at: u64,
+}
+
+#[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
+pub struct IssuanceFailure {
+    pub timestamp: u64,
+    pub error: BoundedString<127>,
+}
+
+#[derive(Debug, CandidType, Clone, PartialEq, Deserialize)]
+pub enum IssuanceEvent {
+    #[serde(rename = "attempt")]
+    Attempt(u64),
+
+    #[serde(rename = "certificate")]
+    Certificate(IssuedCertificate),
+
+    #[serde(rename = "failure")]
+    Failure(IssuanceFailure),
+
+    #[serde(rename = "renewal")]
+    Renewal(u64),
+}
+
+// Issuance history of a registration, keeping the most recent entries of each kind, oldest first
+#[derive(Debug, CandidType, Clone, Default, PartialEq, Deserialize)]
+pub struct IssuanceHistory {
+    pub attempts: Vec<u64>,
+    pub certificates: Vec<IssuedCertificate>,
+    pub failures: Vec<IssuanceFailure>,
+    pub renewals: Vec<u64>,
+}
+
+impl IssuanceHistory {
+    pub fn record(&mut self, event: IssuanceEvent) {
+        fn push<T>(vs: &mut Vec<T>, v: T) {
+            vs.push(v);
+
+            if vs.len() > ISSUANCE_HISTORY_MAX_LEN {
+                vs.remove(0);
+            }
+        }
+
+        match event {
+            IssuanceEvent::Attempt(t) => push(&mut self.attempts, t),
+            IssuanceEvent::Certificate(c) => push(&mut self.certificates, c),
+            IssuanceEvent::Failure(f) => push(&mut self.failures, f),
+            IssuanceEvent::Renewal(t) => push(&mut self.renewals, t),
+        }
+    }
+}
+
+impl Storable for IssuanceHistory {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 4096,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RecordIssuanceEventError {
+    NotFound,
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum RecordIssuanceEventResponse {
+    Ok(()),
+    Err(RecordIssuanceEventError),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum GetIssuanceHistoryError {
+    NotFound,
+    Unauthorized,
+    UnexpectedError(String),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum GetIssuanceHistoryResponse {
+    Ok(IssuanceHistory),
+    Err(GetIssuanceHistoryError),
+}
+
+// Points in the lifecycle of a registration, in nanoseconds since the unix epoch. Timestamps are unset
+// for registrations that predate them, and the next scheduled task is not stored but taken from the queue
+#[derive(Debug, CandidType, Clone, Default, PartialEq, Deserialize)]
+pub struct RegistrationTimestamps {
+    #[serde(rename = "createdAt")]
+    pub created_at: Option<u64>,
+
+    #[serde(rename = "lastAttemptAt")]
+    pub last_attempt_at: Option<u64>,
+
+    #[serde(rename = "nextScheduledAt")]
+    pub next_scheduled_at: Option<u64>,
+
+    #[serde(rename = "lastStateChangeAt")]
+    pub last_state_change_at: Option<u64>,
+}
+
+impl Storable for RegistrationTimestamps {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(&bytes, Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 256,
+        is_fixed_size: false,
+    };
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum GetRegistrationTimestampsResponse {
+    Ok(RegistrationTimestamps),
+    Err(GetRegistrationError),
+}
+
 #[derive(Clone, Debug, CandidType, Deserialize)]
 pub enum ModifyAllowedPrincipalError {
     Unauthorized,
@@ -459,6 +856,15 @@ mod tests {
         assert_eq!(Name::try_from("rüdi.com"), Ok(Name("rüdi.com".to_string())),);
     }
 
+    #[test]
+    fn name_lowercase() {
+        assert_eq!(
+            Name::try_from("Www.Example.COM"),
+            Ok(Name("www.example.com".to_string())),
+        );
+        assert_eq!(Name::try_from("RÜDI.com"), Ok(Name("rüdi.com".to_string())),);
+    }
+
     #[test]
     fn name_invalid_size() {
         let n = (NAME_MAX_LEN + 1) as usize;
@@ -504,7 +910,7 @@ mod tests {
         assert_eq!(BoundedString::<4>::from("123").as_str(), "123");
     }
 
-    const MAX_REGISTRATION_SIZE: usize = 474;
+    const MAX_REGISTRATION_SIZE: usize = 777;
 
     #[test]
     fn max_registration_size() {
@@ -513,11 +919,21 @@ mod tests {
                 name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
                 canister: Principal::from_slice(&[0xFF; 29]),
                 state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
             },
             Registration {
                 name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
                 canister: Principal::from_slice(&[0xFF; 29]),
                 state: State::Failed(String::from_iter(vec!['a'; 128]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
             },
         ];
 
@@ -526,6 +942,84 @@ mod tests {
         }
     }
 
+    #[test]
+    fn max_audit_entry_size() {
+        let failed = || State::Failed(String::from_iter(vec!['a'; 127]).into());
+
+        let max = AuditEntry {
+            timestamp: u64::MAX,
+            actor: String::from_iter(vec!['a'; 63]).into(),
+            id: String::from_iter(vec!['a'; 64]),
+            action: AuditAction::Update,
+            from_state: Some(failed()),
+            to_state: Some(failed()),
+            cause: String::from_iter(vec!['a'; 127]).into(),
+        };
+
+        let Bound::Bounded { max_size, .. } = AuditEntry::BOUND else {
+            panic!("audit entries must be bounded");
+        };
+
+        assert!(max.to_bytes().len() <= max_size as usize);
+    }
+
+    #[test]
+    fn task_priority_order() {
+        assert!(TaskPriority::High > TaskPriority::Normal);
+        assert!(TaskPriority::Normal > TaskPriority::Low);
+        assert_eq!(TaskPriority::default(), TaskPriority::Normal);
+
+        let Bound::Bounded { max_size, .. } = TaskPriority::BOUND else {
+            panic!("task priorities must be bounded");
+        };
+
+        assert!(TaskPriority::High.to_bytes().len() <= max_size as usize);
+    }
+
+    #[test]
+    fn max_issuance_history_size() {
+        let mut max = IssuanceHistory::default();
+
+        for _ in 0..ISSUANCE_HISTORY_MAX_LEN + 1 {
+            max.record(IssuanceEvent::Attempt(u64::MAX));
+            max.record(IssuanceEvent::Renewal(u64::MAX));
+            max.record(IssuanceEvent::Certificate(IssuedCertificate {
+                serial: String::from_iter(vec!['a'; 64]).into(),
+                not_before: u64::MAX,
+                not_after: u64::MAX,
+                issued_at: u64::MAX,
+            }));
+            max.record(IssuanceEvent::Failure(IssuanceFailure {
+                timestamp: u64::MAX,
+                error: String::from_iter(vec!['a'; 127]).into(),
+            }));
+        }
+
+        assert_eq!(max.attempts.len(), ISSUANCE_HISTORY_MAX_LEN);
+
+        let Bound::Bounded { max_size, .. } = IssuanceHistory::BOUND else {
+            panic!("issuance histories must be bounded");
+        };
+
+        assert!(max.to_bytes().len() <= max_size as usize);
+    }
+
+    #[test]
+    fn max_registration_timestamps_size() {
+        let max = RegistrationTimestamps {
+            created_at: Some(u64::MAX),
+            last_attempt_at: Some(u64::MAX),
+            next_scheduled_at: Some(u64::MAX),
+            last_state_change_at: Some(u64::MAX),
+        };
+
+        let Bound::Bounded { max_size, .. } = RegistrationTimestamps::BOUND else {
+            panic!("registration timestamps must be bounded");
+        };
+
+        assert!(max.to_bytes().len() <= max_size as usize);
+    }
+
     #[test]
     fn non_max_registration_size() {
         let non_max = [
@@ -533,16 +1027,58 @@ mod tests {
                 name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize - 1])),
                 canister: Principal::from_slice(&[0xFF; 29]),
                 state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
             },
             Registration {
                 name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
                 canister: Principal::from_slice(&[0xFF; 28]),
                 state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
             },
             Registration {
                 name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
                 canister: Principal::from_slice(&[0xFF; 29]),
                 state: State::Failed(String::from_iter(vec!['a'; 126]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
+            },
+            Registration {
+                name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
+                canister: Principal::from_slice(&[0xFF; 29]),
+                state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: None,
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize
+                ]))]),
+            },
+            Registration {
+                name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
+                canister: Principal::from_slice(&[0xFF; 29]),
+                state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: Some(vec![Name(String::from_iter(vec![
+                    'a';
+                    NAME_MAX_LEN as usize - 1
+                ]))]),
+            },
+            Registration {
+                name: Name(String::from_iter(vec!['a'; NAME_MAX_LEN as usize])),
+                canister: Principal::from_slice(&[0xFF; 29]),
+                state: State::Failed(String::from_iter(vec!['a'; 127]).into()),
+                key_type: Some(KeyType::Rsa4096),
+                alt_names: None,
             },
         ];
 
@@ -3,7 +3,11 @@ use std::{sync::Arc, time::Instant};
 use anyhow::{anyhow, Context as AnyhowContext};
 use async_trait::async_trait;
 use candid::Principal;
-use hyper::{Body, Request, StatusCode, Uri};
+use hyper::{
+    body::HttpBody,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_TYPE},
+    Body, Request, StatusCode, Uri,
+};
 use mockall::automock;
 use opentelemetry::KeyValue;
 use serde::Deserialize;
@@ -33,10 +37,18 @@ pub struct Pair(
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Package {
     pub name: String,
+
+    // Additional names served with the same certificate
+    #[serde(default, rename = "altNames")]
+    pub alt_names: Vec<String>,
+
     pub canister: Principal,
     pub pair: Pair,
 }
 
+// Content type of the streamed export, one package per line
+const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
 #[automock]
 #[async_trait]
 pub trait Import: Sync + Send {
@@ -49,13 +61,19 @@ pub struct CertificatesImporter {
 
     // Configuration
     exporter_uri: Uri,
+    exporter_token: Option<String>,
 }
 
 impl CertificatesImporter {
-    pub fn new(http_client: Arc<dyn HttpClient>, exporter_uri: Uri) -> Self {
+    pub fn new(
+        http_client: Arc<dyn HttpClient>,
+        exporter_uri: Uri,
+        exporter_token: Option<String>,
+    ) -> Self {
         Self {
             http_client,
             exporter_uri,
+            exporter_token,
         }
     }
 }
@@ -63,9 +81,16 @@ impl CertificatesImporter {
 #[async_trait]
 impl Import for CertificatesImporter {
     async fn import(&self) -> Result<Vec<Package>, ImportError> {
-        let req = Request::builder()
+        let mut req = Request::builder()
             .method("GET")
             .uri(&self.exporter_uri)
+            .header(ACCEPT, NDJSON_CONTENT_TYPE);
+
+        if let Some(token) = &self.exporter_token {
+            req = req.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+
+        let req = req
             .body(Body::empty())
             .context("failed to create http request")?;
 
@@ -79,6 +104,33 @@ impl Import for CertificatesImporter {
             return Err(anyhow!(format!("request failed: {}", response.status())).into());
         }
 
+        let is_ndjson = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map_or(false, |v| v == NDJSON_CONTENT_TYPE);
+
+        // Parse packages as they arrive, rather than buffering the entire response first
+        if is_ndjson {
+            let mut pkgs = vec![];
+            let mut buf = vec![];
+
+            while let Some(bs) = response.body_mut().data().await {
+                buf.extend_from_slice(&bs.context("failed to consume response")?);
+
+                while let Some(idx) = buf.iter().position(|b| *b == b'\n') {
+                    let line: Vec<u8> = buf.drain(..=idx).collect();
+                    pkgs.push(serde_json::from_slice(&line).context("failed to parse json line")?);
+                }
+            }
+
+            // A trailing partial line indicates the export was interrupted
+            if !buf.is_empty() {
+                return Err(anyhow!("incomplete response").into());
+            }
+
+            return Ok(pkgs);
+        }
+
         let bs = hyper::body::to_bytes(response.body_mut())
             .await
             .context("failed to consume response")?
@@ -173,6 +225,7 @@ mod tests {
         let importer = CertificatesImporter::new(
             Arc::new(http_client),                 // http_client
             Uri::from_str("http://certificates")?, // exporter_uri
+            None,                                  // exporter_token
         );
 
         let out = importer.import().await?;
@@ -181,6 +234,7 @@ mod tests {
             out,
             vec![Package {
                 name: "name".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa")?,
                 pair: Pair(vec![1, 2, 3], vec![4, 5, 6]),
             }],
@@ -189,6 +243,78 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn import_ndjson_ok() -> Result<(), Error> {
+        let mut http_client = MockHttpClient::new();
+        http_client
+            .expect_request()
+            .times(1)
+            .with(predicate::function(|req: &Request<Body>| {
+                req.headers().get(ACCEPT).unwrap() == NDJSON_CONTENT_TYPE
+                    && req.headers().get(AUTHORIZATION).unwrap() == "Bearer token"
+            }))
+            .returning(|_| {
+                Ok(Response::builder()
+                    .header(CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                    .body(Body::from(concat!(
+                        r#"{"name":"name-1","canister":"aaaaa-aa","pair":[[1],[2]]}"#,
+                        "\n",
+                        r#"{"name":"name-2","canister":"aaaaa-aa","pair":[[3],[4]]}"#,
+                        "\n",
+                    )))
+                    .unwrap())
+            });
+
+        let importer = CertificatesImporter::new(
+            Arc::new(http_client),                 // http_client
+            Uri::from_str("http://certificates")?, // exporter_uri
+            Some("token".into()),                  // exporter_token
+        );
+
+        let out = importer.import().await?;
+
+        assert_eq!(
+            out,
+            vec![
+                Package {
+                    name: "name-1".into(),
+                    alt_names: vec![],
+                    canister: Principal::from_text("aaaaa-aa")?,
+                    pair: Pair(vec![1], vec![2]),
+                },
+                Package {
+                    name: "name-2".into(),
+                    alt_names: vec![],
+                    canister: Principal::from_text("aaaaa-aa")?,
+                    pair: Pair(vec![3], vec![4]),
+                },
+            ],
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn import_ndjson_incomplete() -> Result<(), Error> {
+        let mut http_client = MockHttpClient::new();
+        http_client.expect_request().times(1).returning(|_| {
+            Ok(Response::builder()
+                .header(CONTENT_TYPE, NDJSON_CONTENT_TYPE)
+                .body(Body::from(r#"{"name":"name-1","canister":"#))
+                .unwrap())
+        });
+
+        let importer = CertificatesImporter::new(
+            Arc::new(http_client),                 // http_client
+            Uri::from_str("http://certificates")?, // exporter_uri
+            None,                                  // exporter_token
+        );
+
+        assert!(importer.import().await.is_err());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn import_verify_multiple() {
         let mut verifier = MockVerify::new();
@@ -198,16 +324,19 @@ mod tests {
             .with(predicate::in_iter(vec![
                 Package {
                     name: "name-1".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
                 Package {
                     name: "name-2".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
                 Package {
                     name: "name-3".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
@@ -219,16 +348,19 @@ mod tests {
             Ok(vec![
                 Package {
                     name: "name-1".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
                 Package {
                     name: "name-2".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
                 Package {
                     name: "name-3".into(),
+                    alt_names: vec![],
                     canister: Principal::from_text("aaaaa-aa").unwrap(),
                     pair: Pair(vec![], vec![]),
                 },
@@ -251,6 +383,7 @@ mod tests {
             .times(1)
             .with(predicate::eq(Package {
                 name: "name-1".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa").unwrap(),
                 pair: Pair(vec![], vec![]),
             }))
@@ -266,6 +399,7 @@ mod tests {
         importer.expect_import().times(1).returning(|| {
             Ok(vec![Package {
                 name: "name-1".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa").unwrap(),
                 pair: Pair(vec![], vec![]),
             }])
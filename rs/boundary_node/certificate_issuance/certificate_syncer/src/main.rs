@@ -56,6 +56,10 @@ struct Cli {
     #[clap(long, default_value = "http://127.0.0.1:3000/certificates")]
     certificates_exporter_uri: Uri,
 
+    /// A file containing the bearer token used to authenticate with the certificates exporter
+    #[clap(long)]
+    certificates_exporter_token_path: Option<PathBuf>,
+
     #[clap(long, default_value = "certs")]
     local_certificates_path: PathBuf,
 
@@ -117,7 +121,16 @@ async fn main() -> Result<(), Error> {
     let http_client = Arc::new(http_client);
 
     // Certificates
-    let importer = CertificatesImporter::new(http_client, cli.certificates_exporter_uri);
+    let exporter_token = cli
+        .certificates_exporter_token_path
+        .as_ref()
+        .map(std::fs::read_to_string)
+        .transpose()
+        .context("failed to read certificates exporter token")?
+        .map(|token| token.trim().to_string());
+
+    let importer =
+        CertificatesImporter::new(http_client, cli.certificates_exporter_uri, exporter_token);
     let importer = WithVerify(importer, Verifier(CertificateParser));
     let importer = WithMetrics(importer, MetricParams::new(&meter, SERVICE_NAME, "import"));
     let importer = Arc::new(importer);
@@ -1,6 +1,7 @@
 use std::{
     collections::HashMap,
     io::{BufWriter, Write},
+    iter::once,
     path::{Component, Path, PathBuf},
     sync::{Arc, RwLock},
     time::Instant,
@@ -133,10 +134,15 @@ impl Persist for Persister {
             Ok::<_, Error>(())
         })?;
 
-        // Server blocks
+        // Server blocks, one per name, with alternative names sharing the certificate
         let cfgs = pkgs
             .iter()
-            .map(|pkg| {
+            .flat_map(|pkg| {
+                once(&pkg.name)
+                    .chain(&pkg.alt_names)
+                    .map(move |name| (pkg, name))
+            })
+            .map(|(pkg, name)| {
                 let ssl_certificate_path = normalize_path(
                     Path::new(&self.certificates_path.to_string_lossy().to_string())
                         .join(format!("{}.pem", &pkg.name)),
@@ -153,7 +159,7 @@ impl Persist for Persister {
 
                 self.renderer
                     .render(&Context {
-                        name: &pkg.name,
+                        name,
                         ssl_certificate_key_path: &ssl_certificate_key_path,
                         ssl_certificate_path: &ssl_certificate_path,
                     })
@@ -168,7 +174,9 @@ impl Persist for Persister {
         let mut domains: HashMap<String, String> = HashMap::new();
 
         pkgs.iter().for_each(|pkg| {
-            domains.insert(pkg.name.to_owned(), pkg.canister.to_string());
+            for name in once(&pkg.name).chain(&pkg.alt_names) {
+                domains.insert(name.to_owned(), pkg.canister.to_string());
+            }
         });
 
         let cntnt = (|| {
@@ -295,6 +303,7 @@ mod tests {
         let out = persister
             .persist(&[Package {
                 name: "test".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa")?,
                 pair: Pair(
                     "key".to_string().into_bytes(),
@@ -335,6 +344,54 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_persist_alt_names() -> Result<(), Error> {
+        use tempfile::tempdir;
+
+        let tmp_dir = tempdir()?;
+
+        let renderer = Renderer::new("{name}|{ssl_certificate_key_path}|{ssl_certificate_path}");
+
+        let persister = Persister::new(
+            Arc::new(renderer),              // renderer
+            tmp_dir.path().join("certs"),    // certificates_path
+            tmp_dir.path().join("conf"),     // configuration_path
+            tmp_dir.path().join("mappings"), // domain_mappings_path
+        );
+
+        persister
+            .persist(&[Package {
+                name: "test".into(),
+                alt_names: vec!["www.test".into()],
+                canister: Principal::from_text("aaaaa-aa")?,
+                pair: Pair(
+                    "key".to_string().into_bytes(),
+                    "cert".to_string().into_bytes(),
+                ),
+            }])
+            .await?;
+
+        // Alternative names are served with the certificate of the primary name
+        assert!(!tmp_dir.path().join("certs/www.test.pem").exists());
+
+        assert_eq!(
+            std::fs::read_to_string(tmp_dir.path().join("conf"))?,
+            ["test", "www.test"]
+                .map(|name| format!(
+                    "{name}|{}|{}",
+                    tmp_dir.path().join("certs/test-key.pem").display(),
+                    tmp_dir.path().join("certs/test.pem").display(),
+                ))
+                .join("\n")
+        );
+
+        let mappings = std::fs::read_to_string(tmp_dir.path().join("mappings"))?;
+        assert!(mappings.contains("\"test\":\"aaaaa-aa\""));
+        assert!(mappings.contains("\"www.test\":\"aaaaa-aa\""));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_dedup_empty() -> Result<(), Error> {
         let mut mock = MockPersist::new();
@@ -363,6 +420,7 @@ mod tests {
 
         let single_package: &[Package] = &[Package {
             name: "test1".into(),
+            alt_names: vec![],
             canister: Principal::from_text("aaaaa-aa")?,
             pair: Pair(
                 "key1".to_string().into_bytes(),
@@ -373,6 +431,7 @@ mod tests {
         let double_package: &[Package] = &[
             Package {
                 name: "test1".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa")?,
                 pair: Pair(
                     "key1".to_string().into_bytes(),
@@ -381,6 +440,7 @@ mod tests {
             },
             Package {
                 name: "test2".into(),
+                alt_names: vec![],
                 canister: Principal::from_text("aaaaa-aa")?,
                 pair: Pair(
                     "key2".to_string().into_bytes(),
@@ -1,3 +1,5 @@
+use std::iter::once;
+
 use anyhow::Context;
 use mockall::automock;
 use x509_parser::pem::parse_x509_pem;
@@ -66,12 +68,14 @@ pub struct Verifier<P>(pub P);
 
 impl<P: Parse> Verify for Verifier<P> {
     fn verify(&self, pkg: &Package) -> Result<(), VerifyError> {
-        if !pkg
-            .name
-            .chars()
-            .all(|c| c.is_alphanumeric() || c == '-' || c == '.')
-        {
-            return Err(VerifyError::InvalidDomainName(pkg.name.to_owned()));
+        // Names end up in the rendered configuration, so only allow plain domain names
+        for name in once(&pkg.name).chain(&pkg.alt_names) {
+            if !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '.')
+            {
+                return Err(VerifyError::InvalidDomainName(name.to_owned()));
+            }
         }
 
         // Parse common name from public certificate
@@ -111,6 +115,7 @@ mod tests {
 
         let out = verifier.verify(&Package {
             name: "name-0.com".into(),
+            alt_names: vec![],
             canister: Principal::from_text("aaaaa-aa").unwrap(),
             pair: Pair(vec![], vec![]),
         });
@@ -133,6 +138,7 @@ mod tests {
 
         let out = verifier.verify(&Package {
             name: "name-1".into(),
+            alt_names: vec![],
             canister: Principal::from_text("aaaaa-aa").unwrap(),
             pair: Pair(vec![], vec![]),
         });
@@ -151,6 +157,7 @@ mod tests {
 
         let out = verifier.verify(&Package {
             name: "bad_character".into(),
+            alt_names: vec![],
             canister: Principal::from_text("aaaaa-aa").unwrap(),
             pair: Pair(vec![], vec![]),
         });
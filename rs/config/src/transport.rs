@@ -14,6 +14,31 @@ pub struct TransportConfig {
     /// Transport creates 'max_streams' logical streams/channels between two peers.
     /// Channel ids should be within [0..max_streams).
     pub max_streams: usize,
+
+    /// Only one out of every `connection_log_sample_rate` outgoing connection attempts and
+    /// failed connection attempts is logged. Sampling restarts periodically, so the first such
+    /// event in each period is logged. Connections being established or closed are always
+    /// logged.
+    pub connection_log_sample_rate: u32,
+
+    /// Only one out of every `request_error_log_sample_rate` failures to read or write a
+    /// request on a stream is logged. Sampling restarts periodically, so the first such event
+    /// in each period is logged.
+    pub request_error_log_sample_rate: u32,
+
+    /// Upper bound on the encoded size of requests sent unreliably in a single datagram.
+    pub max_datagram_size: usize,
+
+    /// Number of pushes to a single peer that can be in progress at the same time. Further
+    /// pushes fail until earlier ones complete.
+    pub push_queue_capacity: usize,
+
+    /// If set, limits the bytes per second that are sent to a single peer.
+    pub peer_bandwidth_limit_bytes_per_second: Option<u64>,
+
+    /// Number of bytes that can be sent to a peer at once without being limited by
+    /// `peer_bandwidth_limit_bytes_per_second`.
+    pub peer_bandwidth_burst_bytes: u64,
 }
 
 impl Default for TransportConfig {
@@ -23,6 +48,12 @@ impl Default for TransportConfig {
             node_ip: String::default(),
             listening_port: u16::default(),
             max_streams: 1,
+            connection_log_sample_rate: 1,
+            request_error_log_sample_rate: 1,
+            max_datagram_size: 1024,
+            push_queue_capacity: 1_000,
+            peer_bandwidth_limit_bytes_per_second: None,
+            peer_bandwidth_burst_bytes: 10_000_000,
         }
     }
 }
@@ -0,0 +1,228 @@
+use candid::Decode;
+use cycles_minting_canister::{IcpXdrConversionRate, IcpXdrConversionRateCertifiedResponse};
+use ic_certification::verify_certified_data;
+use ic_crypto_tree_hash::{LookupStatus, MixedHashTree};
+use ic_nervous_system_runtime::Runtime;
+use ic_nns_constants::CYCLES_MINTING_CANISTER_ID;
+use ic_types::crypto::threshold_sig::ThresholdSigPublicKey;
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use std::{
+    fmt::{self, Display, Formatter},
+    marker::PhantomData,
+    time::{Duration, SystemTime},
+};
+
+/// The label under which the cycles minting canister (CMC) certifies the current rate.
+///
+/// This must be kept in sync with the CMC.
+const LABEL_ICP_XDR_CONVERSION_RATE: &[u8] = b"ICP_XDR_CONVERSION_RATE";
+
+const PERMYRIAD: u64 = 10_000;
+
+/// The value of 1 ICP in XDR.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct XdrsPerIcp(Decimal);
+
+impl XdrsPerIcp {
+    /// The CMC expresses rates in 10,000ths of an XDR per ICP.
+    pub fn from_xdr_permyriad_per_icp(xdr_permyriad_per_icp: u64) -> Self {
+        Self(Decimal::from(xdr_permyriad_per_icp) / Decimal::from(PERMYRIAD))
+    }
+
+    /// Inverse of from_xdr_permyriad_per_icp. Fractions of a permyriad are truncated.
+    pub fn xdr_permyriad_per_icp(&self) -> u64 {
+        (self.0 * Decimal::from(PERMYRIAD))
+            .trunc()
+            .to_u64()
+            .unwrap_or(u64::MAX)
+    }
+}
+
+impl From<XdrsPerIcp> for Decimal {
+    fn from(xdrs_per_icp: XdrsPerIcp) -> Self {
+        xdrs_per_icp.0
+    }
+}
+
+/// A rate, along with when the CMC obtained it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IcpXdrRate {
+    pub xdrs_per_icp: XdrsPerIcp,
+
+    /// Seconds since the UNIX epoch.
+    pub timestamp_seconds: u64,
+}
+
+impl From<&IcpXdrConversionRate> for IcpXdrRate {
+    fn from(rate: &IcpXdrConversionRate) -> Self {
+        Self {
+            xdrs_per_icp: XdrsPerIcp::from_xdr_permyriad_per_icp(rate.xdr_permyriad_per_icp),
+            timestamp_seconds: rate.timestamp_seconds,
+        }
+    }
+}
+
+/// Which of the rates served by the CMC to use.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RateKind {
+    /// The most recent rate.
+    Current,
+
+    /// The average of the rates of the last 30 days. This is what is generally used for
+    /// valuations, since it is much harder to manipulate.
+    ThirtyDayAverage,
+}
+
+impl RateKind {
+    pub fn method_name(&self) -> &'static str {
+        match self {
+            Self::Current => "get_icp_xdr_conversion_rate",
+            Self::ThirtyDayAverage => "get_average_icp_xdr_conversion_rate",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IcpXdrRateError {
+    /// Human-readable. Explains what could not be done and why.
+    pub message: String,
+}
+
+impl IcpXdrRateError {
+    fn new(message: String) -> Self {
+        Self { message }
+    }
+}
+
+impl Display for IcpXdrRateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for IcpXdrRateError {}
+
+/// Decodes the CMC's reply to a call to RateKind::method_name, without verifying its
+/// certificate.
+///
+/// This is appropriate for replies to calls made by canisters, which are replicated, i.e. go
+/// through consensus. Such replies do not even carry a certificate, since certificates are only
+/// available to queries.
+pub fn decode_uncertified(reply: &[u8]) -> Result<IcpXdrRate, IcpXdrRateError> {
+    let response = Decode!(reply, IcpXdrConversionRateCertifiedResponse).map_err(|err| {
+        IcpXdrRateError::new(format!(
+            "Unable to decode the reply of the cycles minting canister: {}",
+            err,
+        ))
+    })?;
+
+    Ok(IcpXdrRate::from(&response.data))
+}
+
+/// Fetches the rate from the CMC, without verifying its certificate (see decode_uncertified).
+pub async fn fetch_uncertified<MyRuntime: Runtime>(
+    kind: RateKind,
+) -> Result<IcpXdrRate, IcpXdrRateError> {
+    let (response,): (IcpXdrConversionRateCertifiedResponse,) =
+        MyRuntime::call_with_cleanup(CYCLES_MINTING_CANISTER_ID, kind.method_name(), ((),))
+            .await
+            .map_err(|err| {
+                IcpXdrRateError::new(format!(
+                    "Unable to determine XDRs per ICP, because the cycles minting canister \
+                     did not reply to a {} call: {:?}",
+                    kind.method_name(),
+                    err,
+                ))
+            })?;
+
+    Ok(IcpXdrRate::from(&response.data))
+}
+
+/// Verifies the CMC's reply to a query of the current rate (RateKind::Current), e.g. made by an
+/// agent outside of the IC, against the root key of the IC.
+///
+/// Only the current rate is certified.
+pub fn verify_certified(
+    response: &IcpXdrConversionRateCertifiedResponse,
+    root_key: &ThresholdSigPublicKey,
+) -> Result<IcpXdrRate, IcpXdrRateError> {
+    let tree: MixedHashTree = serde_cbor::from_slice(&response.hash_tree)
+        .map_err(|err| IcpXdrRateError::new(format!("Unable to decode the hash tree: {}", err)))?;
+
+    let leaf = match tree.lookup(&[LABEL_ICP_XDR_CONVERSION_RATE]) {
+        LookupStatus::Found(MixedHashTree::Leaf(leaf)) => leaf,
+        _ => {
+            return Err(IcpXdrRateError::new(
+                "The hash tree does not contain the current rate.".to_string(),
+            ))
+        }
+    };
+
+    // The rate is taken from the tree, rather than the (unauthenticated) data field.
+    let rate = Decode!(leaf, IcpXdrConversionRate).map_err(|err| {
+        IcpXdrRateError::new(format!("Unable to decode the certified rate: {}", err))
+    })?;
+    if rate != response.data {
+        return Err(IcpXdrRateError::new(format!(
+            "The certified rate ({:?}) does not match the returned one ({:?}).",
+            rate, response.data,
+        )));
+    }
+
+    verify_certified_data(
+        &response.certificate,
+        &CYCLES_MINTING_CANISTER_ID,
+        root_key,
+        &tree.digest().0,
+    )
+    .map_err(|err| IcpXdrRateError::new(format!("Unable to verify the certificate: {:?}", err)))?;
+
+    Ok(IcpXdrRate::from(&rate))
+}
+
+/// Holds on to the rate fetched from the CMC, only fetching it again once it is older than
+/// max_age, so that frequent callers do not each make a call to the CMC.
+pub struct CachedIcpXdrRate<MyRuntime: Runtime> {
+    kind: RateKind,
+    max_age: Duration,
+    now: fn() -> SystemTime,
+
+    /// When the rate was fetched, and the rate itself.
+    cached: Option<(SystemTime, IcpXdrRate)>,
+
+    _runtime: PhantomData<MyRuntime>,
+}
+
+impl<MyRuntime: Runtime> CachedIcpXdrRate<MyRuntime> {
+    pub fn new(kind: RateKind, max_age: Duration, now: fn() -> SystemTime) -> Self {
+        Self {
+            kind,
+            max_age,
+            now,
+            cached: None,
+            _runtime: Default::default(),
+        }
+    }
+
+    pub async fn get(&mut self) -> Result<IcpXdrRate, IcpXdrRateError> {
+        let now = (self.now)();
+
+        if let Some((fetched_at, rate)) = self.cached {
+            // A clock going backwards also invalidates the cache.
+            let is_fresh = now
+                .duration_since(fetched_at)
+                .map_or(false, |age| age <= self.max_age);
+            if is_fresh {
+                return Ok(rate);
+            }
+        }
+
+        let rate = fetch_uncertified::<MyRuntime>(self.kind).await?;
+        self.cached = Some((now, rate));
+
+        Ok(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests;
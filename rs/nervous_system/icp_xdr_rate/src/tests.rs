@@ -0,0 +1,175 @@
+use super::*;
+use async_trait::async_trait;
+use candid::{
+    de::IDLDeserialize,
+    utils::{ArgumentDecoder, ArgumentEncoder},
+    Encode,
+};
+use futures::executor::block_on;
+use ic_base_types::CanisterId;
+use ic_crypto_tree_hash::Label;
+use ic_types::crypto::threshold_sig::IcRootOfTrust;
+use std::{cell::RefCell, future::Future};
+
+thread_local! {
+    static CALL_COUNT: RefCell<u64> = RefCell::new(0);
+    static NOW: RefCell<SystemTime> = RefCell::new(SystemTime::UNIX_EPOCH);
+}
+
+fn now() -> SystemTime {
+    NOW.with(|now| *now.borrow())
+}
+
+fn advance_time(duration: Duration) {
+    NOW.with(|now| *now.borrow_mut() += duration);
+}
+
+struct MockRuntime {}
+
+#[async_trait]
+impl Runtime for MockRuntime {
+    async fn call_with_cleanup<In, Out>(
+        id: CanisterId,
+        method: &str,
+        _args: In,
+    ) -> Result<Out, (i32, String)>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        assert_eq!(id, CYCLES_MINTING_CANISTER_ID);
+        assert_eq!(method, "get_average_icp_xdr_conversion_rate");
+
+        let call_count = CALL_COUNT.with(|count| {
+            *count.borrow_mut() += 1;
+            *count.borrow()
+        });
+
+        // Pretend that the CMC returned, with a different rate on every call.
+        let result = Encode!(&IcpXdrConversionRateCertifiedResponse {
+            data: IcpXdrConversionRate {
+                timestamp_seconds: 42,
+                xdr_permyriad_per_icp: 32_100 + call_count,
+            },
+            hash_tree: vec![],
+            certificate: vec![],
+        })
+        .unwrap();
+        let mut idl_deserializer = IDLDeserialize::new(&result).unwrap();
+        Ok(Out::decode(&mut idl_deserializer).unwrap())
+    }
+
+    async fn call_without_cleanup<In, Out>(
+        _id: CanisterId,
+        _method: &str,
+        _args: In,
+    ) -> Result<Out, (i32, String)>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        unimplemented!()
+    }
+
+    async fn call_bytes_with_cleanup(
+        _id: CanisterId,
+        _method: &str,
+        _args: &[u8],
+    ) -> Result<Vec<u8>, (i32, String)> {
+        unimplemented!()
+    }
+
+    fn spawn_future<F: 'static + Future<Output = ()>>(_future: F) {
+        unimplemented!()
+    }
+
+    fn canister_id() -> CanisterId {
+        unimplemented!()
+    }
+
+    fn canister_version() -> u64 {
+        unimplemented!()
+    }
+}
+
+#[test]
+fn test_xdrs_per_icp() {
+    let xdrs_per_icp = XdrsPerIcp::from_xdr_permyriad_per_icp(32_100);
+
+    assert_eq!(Decimal::from(xdrs_per_icp), Decimal::new(321, 2));
+    assert_eq!(xdrs_per_icp.xdr_permyriad_per_icp(), 32_100);
+}
+
+#[test]
+fn test_decode_uncertified() {
+    let reply = Encode!(&IcpXdrConversionRateCertifiedResponse {
+        data: IcpXdrConversionRate {
+            timestamp_seconds: 42,
+            xdr_permyriad_per_icp: 32_100,
+        },
+        hash_tree: vec![],
+        certificate: vec![],
+    })
+    .unwrap();
+
+    assert_eq!(
+        decode_uncertified(&reply),
+        Ok(IcpXdrRate {
+            xdrs_per_icp: XdrsPerIcp::from_xdr_permyriad_per_icp(32_100),
+            timestamp_seconds: 42,
+        }),
+    );
+
+    assert!(decode_uncertified(b"garbage").is_err());
+}
+
+#[test]
+fn test_cached_rate_is_refetched_once_stale() {
+    let mut cached = CachedIcpXdrRate::<MockRuntime>::new(
+        RateKind::ThirtyDayAverage,
+        Duration::from_secs(60),
+        now,
+    );
+
+    let first = block_on(cached.get()).unwrap();
+    assert_eq!(first.xdrs_per_icp.xdr_permyriad_per_icp(), 32_101);
+
+    // Served from the cache.
+    advance_time(Duration::from_secs(60));
+    assert_eq!(block_on(cached.get()), Ok(first));
+    assert_eq!(CALL_COUNT.with(|count| *count.borrow()), 1);
+
+    // Fetched again.
+    advance_time(Duration::from_secs(1));
+    let second = block_on(cached.get()).unwrap();
+    assert_eq!(second.xdrs_per_icp.xdr_permyriad_per_icp(), 32_102);
+    assert_eq!(CALL_COUNT.with(|count| *count.borrow()), 2);
+}
+
+#[test]
+fn test_verify_certified_rejects_mismatched_rate() {
+    let data = IcpXdrConversionRate {
+        timestamp_seconds: 42,
+        xdr_permyriad_per_icp: 32_100,
+    };
+    let certified = IcpXdrConversionRate {
+        xdr_permyriad_per_icp: 1,
+        ..data.clone()
+    };
+
+    let tree = MixedHashTree::Labeled(
+        Label::from(LABEL_ICP_XDR_CONVERSION_RATE),
+        Box::new(MixedHashTree::Leaf(Encode!(&certified).unwrap())),
+    );
+    let response = IcpXdrConversionRateCertifiedResponse {
+        data,
+        hash_tree: serde_cbor::to_vec(&tree).unwrap(),
+        certificate: vec![],
+    };
+
+    // The key is never used, since verification fails before the certificate is looked at.
+    let root_of_trust = IcRootOfTrust::from([0; 96]);
+
+    let err = verify_certified(&response, root_of_trust.as_ref()).unwrap_err();
+    assert!(err.message.contains("does not match"), "{}", err.message);
+}
@@ -1,8 +1,13 @@
 use async_trait::async_trait;
 use candid::utils::{ArgumentDecoder, ArgumentEncoder};
 use ic_base_types::{CanisterId, PrincipalId};
+use ic_management_canister_types::{CanisterInfoRequest, CanisterInfoResponse};
 use std::future::Future;
 
+mod task;
+
+pub use task::{TaskHandle, TaskOutcome};
+
 // A trait to help parameterize the switch from dfn_core to ic_cdk. It should
 // no longer exist after the switch is completed for all NNS/SNS canisters.
 #[async_trait]
@@ -44,6 +49,46 @@ pub trait Runtime {
 
     // Spawns a future.
     fn spawn_future<F: 'static + Future<Output = ()>>(future: F);
+
+    // Spawns a future that can be cancelled, e.g. to stop a long-running
+    // background job before an upgrade. The returned handle can also be
+    // awaited for the future to complete or be cancelled.
+    fn spawn_cancellable<F: 'static + Future<Output = ()>>(future: F) -> TaskHandle {
+        let (future, handle) = task::cancellable(future);
+        Self::spawn_future(future);
+        handle
+    }
+
+    // Returns the ID of the canister itself.
+    fn canister_id() -> CanisterId;
+
+    // Returns the version of the canister, which is incremented on every
+    // change of its code or settings.
+    fn canister_version() -> u64;
+
+    // Asks the management canister for information about the canister itself.
+    // Unlike `canister_status`, this does not require the canister to be one
+    // of its own controllers.
+    async fn canister_info() -> Result<CanisterInfoResponse, (i32, String)> {
+        let (info,): (CanisterInfoResponse,) = Self::call_with_cleanup(
+            CanisterId::ic_00(),
+            "canister_info",
+            (CanisterInfoRequest::new(Self::canister_id(), None),),
+        )
+        .await?;
+
+        Ok(info)
+    }
+
+    // Returns the controllers of the canister.
+    async fn controllers() -> Result<Vec<PrincipalId>, (i32, String)> {
+        Self::canister_info().await.map(|info| info.controllers())
+    }
+
+    // Returns the SHA-256 hash of the canister's module, if one is installed.
+    async fn module_hash() -> Result<Option<Vec<u8>>, (i32, String)> {
+        Self::canister_info().await.map(|info| info.module_hash())
+    }
 }
 
 pub struct DfnRuntime;
@@ -94,6 +139,14 @@ impl Runtime for DfnRuntime {
     fn spawn_future<F: 'static + Future<Output = ()>>(future: F) {
         dfn_core::api::futures::spawn(future);
     }
+
+    fn canister_id() -> CanisterId {
+        dfn_core::api::id()
+    }
+
+    fn canister_version() -> u64 {
+        dfn_core::api::canister_version()
+    }
 }
 
 pub struct CdkRuntime;
@@ -142,4 +195,15 @@ impl Runtime for CdkRuntime {
     fn spawn_future<F: 'static + Future<Output = ()>>(future: F) {
         ic_cdk::spawn(future);
     }
+
+    fn canister_id() -> CanisterId {
+        CanisterId::unchecked_from_principal(PrincipalId::from(ic_cdk::id()))
+    }
+
+    fn canister_version() -> u64 {
+        ic_cdk::api::canister_version()
+    }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,205 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+};
+
+// How a task spawned with `Runtime::spawn_cancellable` ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TaskOutcome {
+    Completed,
+    Cancelled,
+}
+
+#[derive(Default)]
+struct TaskState {
+    cancelled: bool,
+    outcome: Option<TaskOutcome>,
+
+    // Waker of the spawned task, used to drop its future promptly once cancelled.
+    task_waker: Option<Waker>,
+
+    // Waker of whoever awaits the handle.
+    join_waker: Option<Waker>,
+}
+
+// A handle to a spawned task, which can be awaited for the task to end or
+// used to cancel it.
+//
+// Cancellation is cooperative: the task's future is dropped the next time it
+// is polled, i.e. at its next await point, instead of being resumed. In
+// canisters, that is when the call the task is waiting on returns, so a
+// cancelled task does not make any further calls. Natively, the task is woken
+// up right away so that the executor drops it.
+pub struct TaskHandle(Rc<RefCell<TaskState>>);
+
+impl TaskHandle {
+    // Requests the task to stop. Has no effect if the task already ended.
+    pub fn cancel(&self) {
+        let task_waker = {
+            let mut state = self.0.borrow_mut();
+            if state.outcome.is_some() {
+                return;
+            }
+
+            state.cancelled = true;
+            state.task_waker.take()
+        };
+
+        // Waking up the task from another one polls it in place in canisters,
+        // so it is left to be dropped on its next poll there.
+        if cfg!(not(target_arch = "wasm32")) {
+            if let Some(waker) = task_waker {
+                waker.wake();
+            }
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.borrow().cancelled
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.0.borrow().outcome.is_some()
+    }
+}
+
+impl Future for TaskHandle {
+    type Output = TaskOutcome;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.0.borrow_mut();
+
+        match state.outcome {
+            Some(outcome) => Poll::Ready(outcome),
+            None => {
+                state.join_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Wraps a future so that it stops being polled once its handle is cancelled.
+pub(crate) struct Cancellable<F> {
+    future: Option<Pin<Box<F>>>,
+    state: Rc<RefCell<TaskState>>,
+}
+
+impl<F> Cancellable<F> {
+    fn finish(&mut self, outcome: TaskOutcome) -> Poll<()> {
+        self.future = None;
+
+        let join_waker = {
+            let mut state = self.state.borrow_mut();
+            state.outcome = Some(outcome);
+            state.task_waker = None;
+            state.join_waker.take()
+        };
+
+        if let Some(waker) = join_waker {
+            waker.wake();
+        }
+
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future<Output = ()>> Future for Cancellable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+
+        if this.state.borrow().cancelled {
+            return this.finish(TaskOutcome::Cancelled);
+        }
+
+        let future = match this.future.as_mut() {
+            Some(future) => future,
+            None => return Poll::Ready(()),
+        };
+
+        // The state is not borrowed while polling, since the task may cancel itself.
+        match future.as_mut().poll(cx) {
+            Poll::Ready(()) => this.finish(TaskOutcome::Completed),
+            Poll::Pending => {
+                this.state.borrow_mut().task_waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub(crate) fn cancellable<F: Future<Output = ()>>(future: F) -> (Cancellable<F>, TaskHandle) {
+    let state = Rc::new(RefCell::new(TaskState::default()));
+
+    (
+        Cancellable {
+            future: Some(Box::pin(future)),
+            state: state.clone(),
+        },
+        TaskHandle(state),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::{channel::oneshot, executor::LocalPool, task::LocalSpawnExt};
+
+    #[test]
+    fn completed_task() {
+        let mut pool = LocalPool::new();
+        let (tx, rx) = oneshot::channel::<()>();
+
+        let (future, handle) = cancellable(async move {
+            rx.await.unwrap();
+        });
+        pool.spawner().spawn_local(future).unwrap();
+
+        pool.run_until_stalled();
+        assert!(!handle.is_finished());
+
+        tx.send(()).unwrap();
+        assert_eq!(pool.run_until(handle), TaskOutcome::Completed);
+    }
+
+    #[test]
+    fn cancelled_task() {
+        let mut pool = LocalPool::new();
+        let (tx, rx) = oneshot::channel::<()>();
+        let resumed = Rc::new(RefCell::new(false));
+
+        let (future, handle) = cancellable({
+            let resumed = resumed.clone();
+            async move {
+                let _ = rx.await;
+                *resumed.borrow_mut() = true;
+            }
+        });
+        pool.spawner().spawn_local(future).unwrap();
+
+        pool.run_until_stalled();
+        handle.cancel();
+        assert!(handle.is_cancelled());
+
+        assert_eq!(pool.run_until(handle), TaskOutcome::Cancelled);
+
+        // The future was dropped without being resumed
+        assert!(tx.send(()).is_err());
+        assert!(!*resumed.borrow());
+
+        // Cancelling a finished task has no effect
+        let (future, handle) = cancellable(async {});
+        pool.spawner().spawn_local(future).unwrap();
+        pool.run_until_stalled();
+
+        handle.cancel();
+        assert!(!handle.is_cancelled());
+        assert!(handle.is_finished());
+    }
+}
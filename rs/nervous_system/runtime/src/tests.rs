@@ -0,0 +1,88 @@
+use super::*;
+
+use candid::{de::IDLDeserialize, ser::IDLBuilder, Decode, Encode};
+use futures::executor::block_on;
+
+const CANISTER_ID: CanisterId = CanisterId::from_u64(42);
+
+struct MockRuntime;
+
+#[async_trait]
+impl Runtime for MockRuntime {
+    async fn call_without_cleanup<In, Out>(
+        _id: CanisterId,
+        _method: &str,
+        _args: In,
+    ) -> Result<Out, (i32, String)>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        unimplemented!()
+    }
+
+    async fn call_with_cleanup<In, Out>(
+        id: CanisterId,
+        method: &str,
+        args: In,
+    ) -> Result<Out, (i32, String)>
+    where
+        In: ArgumentEncoder + Send,
+        Out: for<'a> ArgumentDecoder<'a>,
+    {
+        // Inspect the request.
+        assert_eq!(id, CanisterId::ic_00());
+        assert_eq!(method, "canister_info");
+        let mut idl_builder = IDLBuilder::new();
+        args.encode(&mut idl_builder).unwrap();
+        let request = Decode!(
+            &idl_builder.serialize_to_vec().unwrap(),
+            CanisterInfoRequest
+        )
+        .unwrap();
+        assert_eq!(request.canister_id(), CANISTER_ID);
+
+        // Pretend that the management canister returned.
+        let response = Encode!(&CanisterInfoResponse::new(
+            3,
+            vec![],
+            Some(vec![1, 2, 3]),
+            vec![PrincipalId::new_user_test_id(1)],
+        ))
+        .unwrap();
+        let mut idl_deserializer = IDLDeserialize::new(&response).unwrap();
+        Ok(Out::decode(&mut idl_deserializer).unwrap())
+    }
+
+    async fn call_bytes_with_cleanup(
+        _id: CanisterId,
+        _method: &str,
+        _args: &[u8],
+    ) -> Result<Vec<u8>, (i32, String)> {
+        unimplemented!()
+    }
+
+    fn spawn_future<F: 'static + Future<Output = ()>>(_future: F) {
+        unimplemented!()
+    }
+
+    fn canister_id() -> CanisterId {
+        CANISTER_ID
+    }
+
+    fn canister_version() -> u64 {
+        7
+    }
+}
+
+#[test]
+fn test_introspection() {
+    assert_eq!(
+        block_on(MockRuntime::controllers()),
+        Ok(vec![PrincipalId::new_user_test_id(1)])
+    );
+    assert_eq!(
+        block_on(MockRuntime::module_hash()),
+        Ok(Some(vec![1, 2, 3]))
+    );
+}
@@ -53,7 +53,6 @@ use crate::{
 };
 use async_trait::async_trait;
 use candid::{Decode, Encode};
-use cycles_minting_canister::IcpXdrConversionRateCertifiedResponse;
 use dfn_core::api::spawn;
 #[cfg(target_arch = "wasm32")]
 use dfn_core::println;
@@ -64,6 +63,7 @@ use ic_nervous_system_common::{
     cmc::CMC, ledger, ledger::IcpLedger, NervousSystemError, SECONDS_PER_DAY,
 };
 use ic_nervous_system_governance::maturity_modulation::apply_maturity_modulation;
+use ic_nervous_system_icp_xdr_rate::{decode_uncertified, IcpXdrRate, RateKind};
 use ic_nervous_system_proto::pb::v1::GlobalTimeOfDay;
 use ic_nns_common::{
     pb::v1::{NeuronId, ProposalId},
@@ -7123,8 +7123,8 @@ impl Governance {
         let avg_xdr_permyriad_per_icp = self
             .get_average_icp_xdr_conversion_rate()
             .await?
-            .data
-            .xdr_permyriad_per_icp;
+            .xdrs_per_icp
+            .xdr_permyriad_per_icp();
 
         // Convert minimum_icp_xdr_rate to basis points for comparison with avg_xdr_permyriad_per_icp
         let minimum_xdr_permyriad_per_icp = self
@@ -7188,36 +7188,25 @@ impl Governance {
     }
 
     /// A helper for the CMC's get_average_icp_xdr_conversion_rate method
-    async fn get_average_icp_xdr_conversion_rate(
-        &mut self,
-    ) -> Result<IcpXdrConversionRateCertifiedResponse, GovernanceError> {
-        let cmc_response:
-            Vec<u8> = self
+    async fn get_average_icp_xdr_conversion_rate(&mut self) -> Result<IcpXdrRate, GovernanceError> {
+        let method_name = RateKind::ThirtyDayAverage.method_name();
+
+        let cmc_response: Vec<u8> = self
             .env
-            .call_canister_method(
-                CYCLES_MINTING_CANISTER_ID,
-                "get_average_icp_xdr_conversion_rate",
-                Encode!().unwrap(),
-            )
+            .call_canister_method(CYCLES_MINTING_CANISTER_ID, method_name, Encode!().unwrap())
             .await
             .map_err(|(code, msg)| {
                 GovernanceError::new_with_message(
                     ErrorType::External,
                     format!(
-                        "Error calling 'get_average_icp_xdr_conversion_rate': code: {:?}, message: {}",
-                        code, msg
+                        "Error calling '{}': code: {:?}, message: {}",
+                        method_name, code, msg
                     ),
                 )
             })?;
 
-        Decode!(&cmc_response, IcpXdrConversionRateCertifiedResponse)
-            .map_err(|err| GovernanceError::new_with_message(
-                ErrorType::External,
-                format!(
-                    "Cannot decode return type from get_average_icp_xdr_conversion_rate'. Error: {}",
-                    err,
-                ),
-            ))
+        decode_uncertified(&cmc_response)
+            .map_err(|err| GovernanceError::new_with_message(ErrorType::External, err.message))
     }
 
     /// Return the cached governance metrics.
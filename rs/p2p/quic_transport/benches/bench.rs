@@ -19,7 +19,9 @@ use ic_metrics::MetricsRegistry;
 use ic_p2p_test_utils::{
     create_registry_handle, temp_crypto_component_with_tls_keys, RegistryConsensusHandle,
 };
-use ic_quic_transport::{DummyUdpSocket, QuicTransport, SubnetTopology, Transport};
+use ic_quic_transport::{
+    DummyUdpSocket, NoCertRotation, QuicTransport, QuicTransportConfig, SubnetTopology, Transport,
+};
 use ic_types_test_utils::ids::{node_test_id, SUBNET_1};
 use tokio::{
     runtime::{Handle, Runtime},
@@ -63,11 +65,13 @@ fn spawn_transport(
         &rt,
         tls,
         registry_handle.registry_client.clone(),
+        Arc::new(NoCertRotation),
         sev,
         node_id,
         watch_rx,
         Either::<_, DummyUdpSocket>::Left(node_addr),
         Router::new().route("/", any(pong)),
+        QuicTransportConfig::default(),
     ));
     (transport, node_id, node_addr)
 }
@@ -0,0 +1,87 @@
+//! Quic Transport acknowledged pushes.
+//!
+//! `push` succeeds once the request was written to the stream, so the sender can not tell
+//! whether the handler of the peer processed it. `push_acked` sends the request like an rpc
+//! instead, marked with the `ACK_HEADER` header:
+//!  - The peer calls the handler as usual, but only sends the status of its response back.
+//!    The body is dropped, so an ack costs a few bytes irrespective of the handler.
+//!  - The push succeeds if the status is successful, and fails with `SendError::HandlerError`
+//!    otherwise.
+//!  - The push fails with `SendError::Timeout` if the ack does not arrive in time.
+//! Peers that do not know the header send the whole response, which is dropped by the sender.
+//!
+use std::time::Duration;
+
+use axum::{
+    body::Body,
+    http::{HeaderValue, Request, Response},
+};
+
+use crate::{deadline::Deadline, SendError};
+
+/// Header of requests that are only acknowledged.
+pub(crate) const ACK_HEADER: &str = "x-ic-ack";
+
+/// Marks the request as only acknowledged, and sets its deadline to `timeout`.
+pub(crate) fn request_ack<B>(request: &mut Request<B>, timeout: Duration) {
+    request
+        .headers_mut()
+        .insert(ACK_HEADER, HeaderValue::from_static("1"));
+    request.extensions_mut().insert(Deadline::after(timeout));
+}
+
+/// Whether the sender of the incoming request only waits for an ack. Removes the header.
+pub(crate) fn is_ack_requested<B>(request: &mut Request<B>) -> bool {
+    request.headers_mut().remove(ACK_HEADER).is_some()
+}
+
+/// Turns the response of a handler into an ack, i.e. drops its body.
+pub(crate) fn into_ack(response: Response<Body>) -> Response<Body> {
+    let (parts, _) = response.into_parts();
+    Response::from_parts(parts, Body::empty())
+}
+
+/// Interprets the response to an acknowledged push.
+pub(crate) fn check_ack<B>(response: Response<B>) -> Result<(), SendError> {
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(SendError::HandlerError(response.status()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn ack_only_carries_the_status() {
+        let mut request = Request::new(());
+        assert!(!is_ack_requested(&mut request));
+        request_ack(&mut request, Duration::from_secs(1));
+        assert!(request.extensions().get::<Deadline>().is_some());
+        assert!(is_ack_requested(&mut request));
+        // The header is removed, so the handler does not see it.
+        assert!(request.headers().get(ACK_HEADER).is_none());
+
+        let response = Response::builder()
+            .status(StatusCode::ACCEPTED)
+            .body(Body::from("large body"))
+            .unwrap();
+        let ack = into_ack(response);
+        assert_eq!(ack.status(), StatusCode::ACCEPTED);
+        assert!(axum::body::to_bytes(ack.into_body(), usize::MAX)
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(check_ack(Response::new(())).is_ok());
+        let mut rejected = Response::new(());
+        *rejected.status_mut() = StatusCode::BAD_REQUEST;
+        assert!(matches!(
+            check_ack(rejected),
+            Err(SendError::HandlerError(StatusCode::BAD_REQUEST))
+        ));
+    }
+}
@@ -0,0 +1,204 @@
+//! Quic Transport peer attestation.
+//!
+//! After the TLS handshake, peers attest each other over a dedicated stream with a
+//! `ValidateAttestedStream`, e.g. the SEV handshake. `AttestationMode` decides what happens
+//! with the result:
+//!  - `Required`: Peers that fail the attestation are disconnected. Meant for production subnets.
+//!  - `Optional`: Peers that fail the attestation are logged and counted, but stay connected.
+//!    Meant for rolling out attestation to a subnet.
+//!  - `Disabled`: The attestation is skipped, e.g. in tests or deployments without SEV hardware.
+//!    `NoAttestation` can be passed as handshake in this case.
+//! The attestation stream is opened in all modes, so that peers with different modes can
+//! still connect as long as their handshakes are compatible.
+//!
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use ic_base_types::{NodeId, RegistryVersion};
+use ic_crypto_tls_interfaces::TlsStream;
+use ic_icos_sev::{ValidateAttestationError, ValidateAttestedStream};
+use ic_logger::{info, ReplicaLogger};
+use prometheus::IntCounter;
+
+/// What happens with peers that fail the attestation.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum AttestationMode {
+    /// The attestation is not performed.
+    Disabled,
+    /// Peers that fail the attestation are accepted.
+    Optional,
+    /// Peers that fail the attestation are rejected.
+    #[default]
+    Required,
+}
+
+/// Handshake that accepts every peer without exchanging anything.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoAttestation;
+
+#[async_trait]
+impl<S: Send + 'static> ValidateAttestedStream<S> for NoAttestation {
+    async fn perform_attestation_validation(
+        &self,
+        stream: S,
+        _peer: NodeId,
+        _registry_version: RegistryVersion,
+    ) -> Result<S, ValidateAttestationError> {
+        Ok(stream)
+    }
+}
+
+/// Attests peers with the handshake, according to the mode.
+#[derive(Clone)]
+pub(crate) struct Attestation {
+    log: ReplicaLogger,
+    mode: AttestationMode,
+    handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+    tolerated_failures: IntCounter,
+}
+
+impl Attestation {
+    pub(crate) fn new(
+        log: ReplicaLogger,
+        mode: AttestationMode,
+        handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+        tolerated_failures: IntCounter,
+    ) -> Self {
+        Self {
+            log,
+            mode,
+            handshake,
+            tolerated_failures,
+        }
+    }
+
+    /// Fails if the peer must be rejected.
+    pub(crate) async fn attest(
+        &self,
+        stream: Box<dyn TlsStream>,
+        peer_id: NodeId,
+        registry_version: RegistryVersion,
+    ) -> Result<(), ValidateAttestationError> {
+        if self.mode == AttestationMode::Disabled {
+            return Ok(());
+        }
+        let result = self
+            .handshake
+            .perform_attestation_validation(stream, peer_id, registry_version)
+            .await;
+        match (result, self.mode) {
+            (Ok(_), _) => Ok(()),
+            (Err(err), AttestationMode::Optional) => {
+                info!(
+                    self.log,
+                    "Accepting peer {} although its attestation failed: {}", peer_id, err
+                );
+                self.tolerated_failures.inc();
+                Ok(())
+            }
+            (Err(err), _) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_base_types::PrincipalId;
+    use ic_logger::replica_logger::no_op_logger;
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+
+    struct TestStream(DuplexStream);
+
+    impl AsyncRead for TestStream {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for TestStream {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx, buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_shutdown(cx)
+        }
+    }
+
+    impl TlsStream for TestStream {}
+
+    /// Handshake that rejects every peer.
+    struct Rejecting;
+
+    #[async_trait]
+    impl<S: Send + 'static> ValidateAttestedStream<S> for Rejecting {
+        async fn perform_attestation_validation(
+            &self,
+            _stream: S,
+            _peer: NodeId,
+            _registry_version: RegistryVersion,
+        ) -> Result<S, ValidateAttestationError> {
+            Err(ValidateAttestationError::HandshakeError {
+                description: "Peer rejected".to_string(),
+            })
+        }
+    }
+
+    async fn attest(
+        mode: AttestationMode,
+        handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+    ) -> (bool, u64) {
+        let tolerated_failures = IntCounter::new("tolerated", "tolerated").unwrap();
+        let attestation =
+            Attestation::new(no_op_logger(), mode, handshake, tolerated_failures.clone());
+        let (stream, _) = tokio::io::duplex(64);
+        let result = attestation
+            .attest(
+                Box::new(TestStream(stream)),
+                NodeId::from(PrincipalId::new_node_test_id(1)),
+                RegistryVersion::from(1),
+            )
+            .await;
+        (result.is_ok(), tolerated_failures.get())
+    }
+
+    #[tokio::test]
+    async fn mode_decides_about_failed_attestations() {
+        assert_eq!(
+            attest(AttestationMode::Required, Arc::new(Rejecting)).await,
+            (false, 0)
+        );
+        assert_eq!(
+            attest(AttestationMode::Optional, Arc::new(Rejecting)).await,
+            (true, 1)
+        );
+        assert_eq!(
+            attest(AttestationMode::Disabled, Arc::new(Rejecting)).await,
+            (true, 0)
+        );
+        assert_eq!(
+            attest(AttestationMode::Required, Arc::new(NoAttestation)).await,
+            (true, 0)
+        );
+    }
+}
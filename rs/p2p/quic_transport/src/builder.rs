@@ -0,0 +1,154 @@
+//! Quic Transport builder.
+//!
+//! Alternative to `QuicTransport::start` that does not require callers to pass every argument
+//! positionally. The identity of the node and the topology are passed to the constructor, all
+//! other arguments are set with setters.
+//!
+//! Required:
+//!     - `tls_config` and `registry_client` authenticate peers.
+//!     - `sev_handshake` attests peers, unless `QuicTransportConfig::attestation` is
+//!       `AttestationMode::Disabled`.
+//!     - `socket_addr` or `custom_socket` define where transport listens.
+//! Optional:
+//!     - `tls_certificates` defaults to `NoCertRotation`, i.e. connections with rotated
+//!       certificates are not cycled.
+//!     - `router` defaults to a router without routes.
+//!     - `config` defaults to `QuicTransportConfig::default()`.
+//!
+use std::{net::SocketAddr, sync::Arc};
+
+use axum::Router;
+use either::Either;
+use ic_base_types::NodeId;
+use ic_crypto_tls_interfaces::{TlsConfig, TlsStream};
+use ic_icos_sev::ValidateAttestedStream;
+use ic_interfaces_registry::RegistryClient;
+use ic_logger::ReplicaLogger;
+use ic_metrics::MetricsRegistry;
+use quinn::AsyncUdpSocket;
+use tokio::sync::watch;
+
+use crate::{
+    AttestationMode, DummyUdpSocket, NoAttestation, NoCertRotation, QuicTransport,
+    QuicTransportConfig, SubnetTopology, TlsCertificates,
+};
+
+pub struct QuicTransportBuilder<S = DummyUdpSocket> {
+    log: ReplicaLogger,
+    metrics_registry: MetricsRegistry,
+    node_id: NodeId,
+    topology_watcher: watch::Receiver<SubnetTopology>,
+    tls_config: Option<Arc<dyn TlsConfig + Send + Sync>>,
+    registry_client: Option<Arc<dyn RegistryClient>>,
+    tls_certificates: Arc<dyn TlsCertificates>,
+    sev_handshake: Option<Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>>,
+    udp_socket: Option<Either<SocketAddr, S>>,
+    router: Router,
+    config: QuicTransportConfig,
+}
+
+impl QuicTransportBuilder {
+    pub fn new(
+        log: &ReplicaLogger,
+        metrics_registry: &MetricsRegistry,
+        node_id: NodeId,
+        topology_watcher: watch::Receiver<SubnetTopology>,
+    ) -> Self {
+        Self {
+            log: log.clone(),
+            metrics_registry: metrics_registry.clone(),
+            node_id,
+            topology_watcher,
+            tls_config: None,
+            registry_client: None,
+            tls_certificates: Arc::new(NoCertRotation),
+            sev_handshake: None,
+            udp_socket: None,
+            router: Router::new(),
+            config: QuicTransportConfig::default(),
+        }
+    }
+}
+
+impl<S: AsyncUdpSocket> QuicTransportBuilder<S> {
+    pub fn tls_config(mut self, tls_config: Arc<dyn TlsConfig + Send + Sync>) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    pub fn registry_client(mut self, registry_client: Arc<dyn RegistryClient>) -> Self {
+        self.registry_client = Some(registry_client);
+        self
+    }
+
+    pub fn tls_certificates(mut self, tls_certificates: Arc<dyn TlsCertificates>) -> Self {
+        self.tls_certificates = tls_certificates;
+        self
+    }
+
+    pub fn sev_handshake(
+        mut self,
+        sev_handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+    ) -> Self {
+        self.sev_handshake = Some(sev_handshake);
+        self
+    }
+
+    /// Binds a UDP socket to `addr`.
+    pub fn socket_addr(mut self, addr: SocketAddr) -> Self {
+        self.udp_socket = Some(Either::Left(addr));
+        self
+    }
+
+    /// Uses the given socket instead of binding one, e.g. a simulated socket in tests.
+    pub fn custom_socket<T: AsyncUdpSocket>(self, socket: T) -> QuicTransportBuilder<T> {
+        QuicTransportBuilder {
+            log: self.log,
+            metrics_registry: self.metrics_registry,
+            node_id: self.node_id,
+            topology_watcher: self.topology_watcher,
+            tls_config: self.tls_config,
+            registry_client: self.registry_client,
+            tls_certificates: self.tls_certificates,
+            sev_handshake: self.sev_handshake,
+            udp_socket: Some(Either::Right(socket)),
+            router: self.router,
+            config: self.config,
+        }
+    }
+
+    pub fn router(mut self, router: Router) -> Self {
+        self.router = router;
+        self
+    }
+
+    pub fn config(mut self, config: QuicTransportConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Starts transport on the given runtime, see `QuicTransport::start`.
+    /// Panics if a required argument was not set.
+    pub fn build_and_start(self, rt: &tokio::runtime::Handle) -> QuicTransport {
+        let sev_handshake = match (self.sev_handshake, self.config.attestation) {
+            (Some(sev_handshake), _) => sev_handshake,
+            (None, AttestationMode::Disabled) => Arc::new(NoAttestation),
+            (None, _) => panic!("The sev handshake is required unless attestation is disabled"),
+        };
+        QuicTransport::start(
+            &self.log,
+            &self.metrics_registry,
+            rt,
+            self.tls_config.expect("The tls config is required"),
+            self.registry_client
+                .expect("The registry client is required"),
+            self.tls_certificates,
+            sev_handshake,
+            self.node_id,
+            self.topology_watcher,
+            self.udp_socket.expect("A socket is required"),
+            self.router,
+            self.config,
+        )
+    }
+}
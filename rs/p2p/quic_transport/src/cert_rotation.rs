@@ -0,0 +1,147 @@
+//! Quic Transport TLS certificate rotation.
+//!
+//! Connections keep the certificates they were established with, also after a node rotated its
+//! TLS certificate in the registry. Transport picks up rotated certificates without a restart:
+//!  - New connections use the certificates of the latest registry version, both when dialing
+//!    and when accepting peers, since the TLS configs are fetched for that version.
+//!  - On each new registry version, connections established with outdated certificates are
+//!    cycled. If the certificate of this node changed, that is all connections. Otherwise it
+//!    is the connections whose peer presented a certificate that differs from the registry.
+//!  - Outdated connections are queued and cycled at most `MAX_CYCLED_CONNECTIONS_PER_INTERVAL`
+//!    at a time, once every `CYCLE_INTERVAL`, so that a rotation of the local certificate does
+//!    not disconnect all peers at once.
+//!  - Cycled connections are drained like connections to peers that left the topology, i.e.
+//!    requests in flight get the drain grace period to complete. The peers reconnect once the
+//!    connection is closed.
+//! Certificates that can not be read from the registry are treated as unchanged.
+//! The certificates are supplied by the caller with `TlsCertificates`, `NoCertRotation` never
+//! cycles connections.
+//!
+use std::{collections::BTreeMap, sync::Arc, time::Duration};
+
+use ic_base_types::{NodeId, RegistryVersion};
+use quinn::Connection;
+use tokio_rustls::rustls::Certificate;
+
+/// Interval at which queued outdated connections are cycled.
+pub(crate) const CYCLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Upper bound on the connections that are cycled at once.
+pub(crate) const MAX_CYCLED_CONNECTIONS_PER_INTERVAL: usize = 3;
+
+/// TLS certificates of nodes as of a registry version, e.g. read from the registry.
+pub trait TlsCertificates: Send + Sync {
+    /// DER encoded certificate of the node, `None` if it can not be read.
+    fn tls_certificate(
+        &self,
+        node_id: NodeId,
+        registry_version: RegistryVersion,
+    ) -> Option<Vec<u8>>;
+}
+
+/// Certificates that are never known, so that connections are not cycled.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct NoCertRotation;
+
+impl TlsCertificates for NoCertRotation {
+    fn tls_certificate(
+        &self,
+        _node_id: NodeId,
+        _registry_version: RegistryVersion,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Detects connections that were established with certificates that were rotated since.
+pub(crate) struct CertRotation {
+    tls_certificates: Arc<dyn TlsCertificates>,
+    node_id: NodeId,
+    /// Certificate of this node, as of the last registry version that was checked.
+    local_cert: Option<Vec<u8>>,
+    /// Outdated connections that were not cycled yet, by peer. Holds the stable id of the
+    /// connection, so that the peer is skipped if it reconnected in the meantime.
+    outdated: BTreeMap<NodeId, usize>,
+}
+
+impl CertRotation {
+    pub(crate) fn new(
+        tls_certificates: Arc<dyn TlsCertificates>,
+        node_id: NodeId,
+        registry_version: RegistryVersion,
+    ) -> Self {
+        let local_cert = tls_certificates.tls_certificate(node_id, registry_version);
+        Self {
+            tls_certificates,
+            node_id,
+            local_cert,
+            outdated: BTreeMap::new(),
+        }
+    }
+
+    /// Queues the connections that use a certificate that is outdated at `registry_version`.
+    pub(crate) fn check_connections<'a>(
+        &mut self,
+        registry_version: RegistryVersion,
+        connections: impl Iterator<Item = (&'a NodeId, &'a Connection)>,
+    ) {
+        let local_cert = self
+            .tls_certificates
+            .tls_certificate(self.node_id, registry_version);
+        let local_rotated = is_rotated(self.local_cert.as_deref(), local_cert.as_deref());
+        if local_cert.is_some() {
+            self.local_cert = local_cert;
+        }
+
+        let outdated: Vec<_> = connections
+            .filter(|(peer_id, connection)| {
+                local_rotated
+                    || is_rotated(
+                        presented_cert(connection).as_deref(),
+                        self.tls_certificates
+                            .tls_certificate(**peer_id, registry_version)
+                            .as_deref(),
+                    )
+            })
+            .map(|(peer_id, connection)| (*peer_id, connection.stable_id()))
+            .collect();
+        self.outdated.extend(outdated);
+    }
+
+    pub(crate) fn has_outdated(&self) -> bool {
+        !self.outdated.is_empty()
+    }
+
+    /// Takes the next queued connection, as the peer and the stable id of the connection.
+    pub(crate) fn pop_outdated(&mut self) -> Option<(NodeId, usize)> {
+        self.outdated.pop_first()
+    }
+}
+
+/// Whether the certificate changed. Unknown certificates are treated as unchanged.
+fn is_rotated(previous: Option<&[u8]>, current: Option<&[u8]>) -> bool {
+    matches!((previous, current), (Some(previous), Some(current)) if previous != current)
+}
+
+/// Certificate the peer presented in the TLS handshake of the connection.
+fn presented_cert(connection: &Connection) -> Option<Vec<u8>> {
+    let certs = connection
+        .peer_identity()?
+        .downcast::<Vec<Certificate>>()
+        .ok()?;
+    certs.first().map(|cert| cert.0.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_known_certificates_are_rotated() {
+        assert!(is_rotated(Some(b"old"), Some(b"new")));
+        assert!(!is_rotated(Some(b"old"), Some(b"old")));
+        // Failing to read a certificate does not cycle connections.
+        assert!(!is_rotated(None, Some(b"new")));
+        assert!(!is_rotated(Some(b"old"), None));
+    }
+}
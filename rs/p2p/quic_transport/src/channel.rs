@@ -0,0 +1,91 @@
+//! Quic Transport channels.
+//!
+//! A channel is a long-lived bidirectional exchange of messages with a peer over a single
+//! QUIC stream, as opposed to the one-shot `rpc` and `push`.
+//!
+//! Opening a channel:
+//!     - The opener writes `CHANNEL_MAGIC` to a new bidi stream. Since requests start with
+//!       the length of their URI, this never happens for `rpc` requests.
+//!     - All further data on the stream is length delimited frames. The first frame sent by
+//!       the opener is an encoded request, carrying the URI the channel is routed by.
+//!     - The request is subject to the size limit of its URI, which is checked before the rest
+//!       of the frame is read, see message_size.rs. Further frames from the opener are limited
+//!       to the same size.
+//!     - The accepting side calls the router with the request. Handlers of channels extract the
+//!       `ChannelUpgrade` extension and call `accept` on it.
+//!     - The accepting side answers with an encoded response, as a single frame. Only after
+//!       that frame is written the channel is handed to the handler, so that it is always the
+//!       first frame the opener receives.
+//!     - If the response is not successful, or the handler did not accept the channel, the
+//!       stream is closed.
+//!
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+use futures::{stream::BoxStream, Sink, SinkExt, StreamExt, TryStreamExt};
+use quinn::{RecvStream, SendStream};
+use tokio::sync::oneshot;
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
+
+use crate::{utils::MAX_MESSAGE_SIZE_BYTES, SendError};
+
+pub(crate) const CHANNEL_MAGIC: [u8; 8] = u64::MAX.to_le_bytes();
+
+/// Size of the big endian length that precedes each frame.
+pub(crate) const FRAME_HEADER_SIZE: usize = 4;
+
+pub(crate) fn channel_codec() -> LengthDelimitedCodec {
+    limited_channel_codec(MAX_MESSAGE_SIZE_BYTES)
+}
+
+/// Codec of frames received from a peer, which are rejected if they exceed `limit` bytes.
+pub(crate) fn limited_channel_codec(limit: usize) -> LengthDelimitedCodec {
+    LengthDelimitedCodec::builder()
+        .length_field_length(FRAME_HEADER_SIZE)
+        .big_endian()
+        .max_frame_length(limit)
+        .new_codec()
+}
+
+/// A framed bidirectional stream to a peer. Each message sent on the sink is received as a
+/// single message on the stream of the peer.
+pub struct Channel {
+    pub sink: Pin<Box<dyn Sink<Bytes, Error = SendError> + Send>>,
+    pub stream: BoxStream<'static, Result<Bytes, SendError>>,
+}
+
+impl Channel {
+    pub(crate) fn new(
+        sink: FramedWrite<SendStream, LengthDelimitedCodec>,
+        stream: FramedRead<RecvStream, LengthDelimitedCodec>,
+    ) -> Self {
+        Self {
+            sink: Box::pin(sink.sink_map_err(|err| SendError::Internal(err.to_string()))),
+            stream: stream
+                .map_ok(|frame| frame.freeze())
+                .map_err(|err| SendError::Internal(err.to_string()))
+                .boxed(),
+        }
+    }
+}
+
+/// Extension of requests that open a channel.
+#[derive(Clone, Default)]
+pub struct ChannelUpgrade(Arc<Mutex<Option<oneshot::Sender<Channel>>>>);
+
+impl ChannelUpgrade {
+    /// Accepts the channel. It is received once the handler returned a successful response
+    /// and that response was sent to the peer.
+    pub fn accept(&self) -> oneshot::Receiver<Channel> {
+        let (tx, rx) = oneshot::channel();
+        *self.0.lock().unwrap() = Some(tx);
+        rx
+    }
+
+    pub(crate) fn take(&self) -> Option<oneshot::Sender<Channel>> {
+        self.0.lock().unwrap().take()
+    }
+}
@@ -0,0 +1,113 @@
+//! Quic Transport payload compression.
+//!
+//! Bodies of requests and responses can be compressed with zstd.
+//!  - Compression is negotiated per connection. Peers that have compression enabled announce
+//!    it in the gruezi handshake if `announce_features` is set, or if the peer announced its
+//!    features, and bodies are only compressed if both peers announced it.
+//!  - Only bodies of at least `min_size` bytes are compressed. Compressed requests are marked
+//!    with a `content-encoding: zstd` header, compressed responses with a flag in the response
+//!    header of the wire format.
+//!  - Bodies are decompressed before they reach handlers or callers, so compression is not
+//!    visible outside of transport. Decompressed request bodies are bounded by the size limit
+//!    of their URI, decompressed response bodies by the maximum size of the wire format.
+//!  - Compressed responses are written and read as a whole, i.e. they are not streamed.
+//!
+use axum::http::{header::CONTENT_ENCODING, HeaderValue, Request};
+use bytes::Bytes;
+
+use crate::SendError;
+
+/// Encoding announced in the handshake and used as value of the `content-encoding` header.
+pub(crate) const ZSTD_ENCODING: &str = "zstd";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CompressionConfig {
+    /// Bodies smaller than this are sent uncompressed.
+    pub min_size: usize,
+    /// zstd compression level.
+    pub level: i32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            min_size: 1024,
+            level: zstd::DEFAULT_COMPRESSION_LEVEL,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Returns the compressed body, or `None` if the body should be sent as is.
+    pub(crate) fn compress(&self, body: &[u8]) -> Option<Bytes> {
+        if body.len() < self.min_size {
+            return None;
+        }
+        zstd::bulk::compress(body, self.level).ok().map(Bytes::from)
+    }
+
+    /// Compresses the body of an outgoing request, if it is large enough.
+    pub(crate) fn compress_request(&self, request: Request<Bytes>) -> Request<Bytes> {
+        let (mut parts, body) = request.into_parts();
+        match self.compress(&body) {
+            Some(compressed) => {
+                parts
+                    .headers
+                    .insert(CONTENT_ENCODING, HeaderValue::from_static(ZSTD_ENCODING));
+                Request::from_parts(parts, compressed)
+            }
+            None => Request::from_parts(parts, body),
+        }
+    }
+}
+
+/// Decompresses a body, which can be at most `limit` bytes large when decompressed. Only `limit`
+/// bytes are allocated, regardless of the size the compressed body claims.
+pub(crate) fn decompress(body: &[u8], limit: usize) -> Result<Bytes, SendError> {
+    zstd::bulk::decompress(body, limit)
+        .map(Bytes::from)
+        .map_err(|err| {
+            SendError::Internal(format!(
+                "Decompressing body into at most {} bytes failed: {}",
+                limit, err
+            ))
+        })
+}
+
+/// Whether the content encoding header marks a body as compressed.
+pub(crate) fn is_compressed(content_encoding: Option<&HeaderValue>) -> bool {
+    content_encoding.is_some_and(|value| value == ZSTD_ENCODING)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_large_bodies_are_compressed() {
+        let config = CompressionConfig {
+            min_size: 100,
+            ..Default::default()
+        };
+
+        let request = config.compress_request(Request::new(Bytes::from(vec![1; 99])));
+        assert!(!is_compressed(request.headers().get(CONTENT_ENCODING)));
+        assert_eq!(request.body().len(), 99);
+
+        let request = config.compress_request(Request::new(Bytes::from(vec![1; 10_000])));
+        assert!(is_compressed(request.headers().get(CONTENT_ENCODING)));
+        assert!(request.body().len() < 10_000);
+        assert_eq!(decompress(request.body(), 10_000).unwrap(), vec![1; 10_000]);
+    }
+
+    #[test]
+    fn decompression_is_bounded_by_limit() {
+        let body = CompressionConfig::default()
+            .compress(&[0; 1_000_000])
+            .unwrap();
+        assert!(body.len() < 1_000);
+
+        assert!(decompress(&body, 999_999).is_err());
+        assert_eq!(decompress(&body, 1_000_000).unwrap().len(), 1_000_000);
+    }
+}
@@ -0,0 +1,130 @@
+//! Quic Transport configuration.
+//!
+//! The defaults are the values used by replicas and should only be changed with care.
+//! Both sides of a connection use their own configuration, so e.g. the effective idle timeout
+//! is the lower one of the two peers.
+//!
+use std::time::Duration;
+
+use crate::{
+    AttestationMode, CompressionConfig, FailoverConfig, HealthCheckConfig, LogSamplingConfig,
+    MessageSizeLimits, OrderedLanes, OutgoingLayers, ProtocolVersion, RateLimitConfig,
+    StreamPriorities, TrafficClasses,
+};
+
+/// Default upper bound on the encoded size of requests sent with `push_unreliable`. Datagrams
+/// are additionally limited by the path MTU, which is at least 1200 bytes for QUIC.
+pub const DEFAULT_MAX_DATAGRAM_SIZE: usize = 1024;
+
+/// Default number of pushes to a single peer that can be in progress at the same time.
+pub const DEFAULT_PUSH_QUEUE_CAPACITY: usize = 1_000;
+
+/// Default number of rpcs to a single peer that can be in flight at the same time.
+pub const DEFAULT_MAX_INFLIGHT_RPCS_PER_PEER: usize = 1_000;
+
+/// Default number of datagrams from a single peer that can be handled at the same time.
+pub const DEFAULT_MAX_INFLIGHT_DATAGRAMS_PER_PEER: usize = 1_000;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuicTransportConfig {
+    /// Interval of quic heartbeats. They are only sent if the connection is idle for longer
+    /// than this interval.
+    pub keep_alive_interval: Duration,
+    /// Timeout after which quic marks connections as broken. This timeout is used to detect
+    /// connections that were not explicitly closed. I.e replica crash.
+    pub idle_timeout: Duration,
+    /// Bytes that can be in flight to a peer, over all streams of the connection.
+    pub send_window: u64,
+    /// Upper bound on receive memory consumption of a connection.
+    pub receive_window: u32,
+    /// Upper bound on receive memory consumption of a single stream.
+    pub stream_receive_window: u32,
+    /// Number of bidi streams the peer can have open at the same time.
+    pub max_concurrent_bidi_streams: u32,
+    /// Number of uni streams the peer can have open at the same time.
+    pub max_concurrent_uni_streams: u32,
+    pub log_sampling: LogSamplingConfig,
+    /// Upper bound on the encoded size of requests sent with `push_unreliable`.
+    pub max_datagram_size: usize,
+    /// Number of datagrams from a single peer that can be handled at the same time. Further
+    /// datagrams are dropped until earlier ones are handled.
+    pub max_inflight_datagrams_per_peer: usize,
+    pub stream_priorities: StreamPriorities,
+    /// Number of pushes to a single peer that can be in progress at the same time.
+    pub push_queue_capacity: usize,
+    /// Number of rpcs to a single peer that can be in flight at the same time. Further rpcs
+    /// fail with `SendError::Overloaded`.
+    pub max_inflight_rpcs_per_peer: usize,
+    /// Priorities and in-flight limits of requests tagged with a `TrafficClass`.
+    pub traffic_classes: TrafficClasses,
+    /// Bandwidth limit of the connection to each peer. `None` disables the limit.
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Layers applied to requests sent with `rpc` and `push`.
+    pub outgoing_layers: OutgoingLayers,
+    /// URIs whose pushes are delivered to each peer in the order they were sent.
+    pub ordered_lanes: OrderedLanes,
+    /// Compression of bodies. Only used for peers that enabled compression as well. `None`
+    /// disables compression.
+    pub compression: Option<CompressionConfig>,
+    /// Size limits of requests received from peers.
+    pub message_size_limits: MessageSizeLimits,
+    /// Whether peers can move connections this node accepted to another address, e.g. on
+    /// failover between NICs. Otherwise such connections break and are re-dialed.
+    pub connection_migration: bool,
+    /// Whether sockets bound to an IPv6 address also handle IPv4 traffic, so that peers of
+    /// both address families can be reached. Only has an effect if transport binds the socket.
+    pub dual_stack: bool,
+    /// Other local addresses to move to if no peer is reachable. `None` disables failover.
+    pub failover: Option<FailoverConfig>,
+    /// Time requests in flight to a peer that left the topology get to complete before the
+    /// connection is closed. Zero closes such connections right away.
+    pub drain_grace_period: Duration,
+    /// Pings of connected peers to detect half-dead connections. `None` disables them.
+    pub health_check: Option<HealthCheckConfig>,
+    /// Application protocol announced to peers in the handshake.
+    pub protocol: ProtocolVersion,
+    /// Whether compression and the protocol are announced in the handshake. Peers that announce
+    /// them are answered in kind, and only with peers that announced anything are requests sent
+    /// with headers and responses compressed. Transport versions without announcements reject
+    /// the handshake of peers that announce, so this must only be enabled once no such peers
+    /// remain in the subnet.
+    pub announce_features: bool,
+    /// Whether peers that fail the attestation handshake are rejected.
+    pub attestation: AttestationMode,
+}
+
+impl Default for QuicTransportConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive_interval: Duration::from_millis(200),
+            idle_timeout: Duration::from_secs(5),
+            // The quinn defaults are a stream receive window of 1_250_000 and a send window
+            // of 8 times that.
+            send_window: 100_000_000,
+            receive_window: 200_000_000,
+            stream_receive_window: 4_000_000,
+            max_concurrent_bidi_streams: 1_000,
+            max_concurrent_uni_streams: 1_000,
+            log_sampling: LogSamplingConfig::default(),
+            max_datagram_size: DEFAULT_MAX_DATAGRAM_SIZE,
+            max_inflight_datagrams_per_peer: DEFAULT_MAX_INFLIGHT_DATAGRAMS_PER_PEER,
+            stream_priorities: StreamPriorities::default(),
+            push_queue_capacity: DEFAULT_PUSH_QUEUE_CAPACITY,
+            max_inflight_rpcs_per_peer: DEFAULT_MAX_INFLIGHT_RPCS_PER_PEER,
+            traffic_classes: TrafficClasses::default(),
+            rate_limit: None,
+            outgoing_layers: OutgoingLayers::default(),
+            ordered_lanes: OrderedLanes::default(),
+            compression: None,
+            message_size_limits: MessageSizeLimits::default(),
+            connection_migration: true,
+            dual_stack: true,
+            failover: None,
+            drain_grace_period: Duration::from_secs(5),
+            health_check: Some(HealthCheckConfig::default()),
+            protocol: ProtocolVersion::default(),
+            announce_features: false,
+            attestation: AttestationMode::default(),
+        }
+    }
+}
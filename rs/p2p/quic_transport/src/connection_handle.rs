@@ -1,21 +1,59 @@
 //! Quic Transport connection handle.
 //!
 //! Contains a wrapper, called `ConnectionHandle`, around quinn's Connection.
-//! The `ConnectionHandle` implements `rpc` and `push` methods for the given
-//! connection.
+//! The `ConnectionHandle` implements `rpc`, `rpc_stream`, `push`, `push_acked`,
+//! `push_unreliable` and `open_channel` methods for the given connection. Requests sent with
+//! `rpc`, `push` and `push_acked` pass through the outgoing layers first, see middleware.rs.
+//! Pushes to URIs with an ordered lane are written to the lane instead of a new stream, see
+//! lane.rs.
+//! Rpcs tagged with a traffic class are admitted by the in-flight limit of the class, see
+//! traffic_class.rs.
 //!
-use axum::http::{Request, Response};
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::http::{Request, Response, Uri};
 use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
 use ic_base_types::NodeId;
-use quinn::Connection;
+use prometheus::IntGauge;
+use quinn::{Connection, SendDatagramError, SendStream, VarInt};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::{service_fn, util::BoxCloneService, ServiceExt};
 
 use crate::{
+    ack::{check_ack, request_ack},
+    compression::CompressionConfig,
+    deadline::{encode_deadline, Deadline},
+    drain::InflightRequests,
+    health::HEALTH_CHECK_URI,
+    lane::{LaneSenders, OrderedLanes},
     metrics::{
-        QuicTransportMetrics, ERROR_TYPE_FINISH, ERROR_TYPE_OPEN, ERROR_TYPE_READ,
-        ERROR_TYPE_WRITE, REQUEST_TYPE_PUSH, REQUEST_TYPE_RPC,
+        QuicTransportMetrics, DIRECTION_OUTBOUND, DROP_REASON_CONNECTION_LOST,
+        DROP_REASON_TOO_LARGE, DROP_REASON_UNSUPPORTED, ERROR_TYPE_BACKPRESSURE, ERROR_TYPE_FINISH,
+        ERROR_TYPE_OPEN, ERROR_TYPE_OVERLOADED, ERROR_TYPE_READ, ERROR_TYPE_TIMEOUT,
+        ERROR_TYPE_WRITE, REQUEST_TYPE_CHANNEL, REQUEST_TYPE_PUSH, REQUEST_TYPE_RPC,
+        REQUEST_TYPE_RPC_STREAM,
+    },
+    middleware::OutgoingLayers,
+    priority::StreamPriorities,
+    protocol::ProtocolVersion,
+    rate_limit::RateLimiter,
+    trace_context::encode_trace_context,
+    traffic_class::{
+        encode_traffic_class, traffic_class, traffic_class_label, TrafficClass, TrafficClasses,
+        TRAFFIC_CLASS_NONE,
     },
-    utils::{read_response, write_request},
-    ConnId, SendError,
+    utils::{
+        encode_request, open_channel, read_response, read_response_stream, write_request,
+        WireFormat,
+    },
+    Channel, ConnId, PeerStats, ResponseStream, SendError,
 };
 
 #[derive(Clone, Debug)]
@@ -24,89 +62,296 @@ pub(crate) struct ConnectionHandle {
     pub connection: Connection,
     pub metrics: QuicTransportMetrics,
     conn_id: ConnId,
+    max_datagram_size: usize,
+    stream_priorities: Arc<StreamPriorities>,
+    /// Limits the number of pushes to the peer that are in progress at the same time.
+    push_permits: Arc<Semaphore>,
+    /// Limits the number of rpcs to the peer that are in flight at the same time.
+    rpc_permits: Arc<Semaphore>,
+    /// Limits the number of rpcs of each traffic class to the peer that are in flight at the
+    /// same time. Rpcs without class are limited by `rpc_permits` instead.
+    class_rpc_permits: Arc<HashMap<TrafficClass, Arc<Semaphore>>>,
+    inflight_rpcs: IntGauge,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    outgoing_layers: Arc<OutgoingLayers>,
+    ordered_lanes: Arc<OrderedLanes>,
+    /// Lanes to the peer opened so far.
+    lanes: Arc<LaneSenders>,
+    /// Set if both peers enabled compression.
+    compression: Option<CompressionConfig>,
+    /// Protocol negotiated in the handshake.
+    protocol: ProtocolVersion,
+    /// Layout of requests and responses understood by the peer.
+    wire_format: WireFormat,
+    /// Requests in progress, waited for when the connection is drained.
+    inflight: InflightRequests,
 }
 
 impl ConnectionHandle {
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn new(
         peer_id: NodeId,
         connection: Connection,
         metrics: QuicTransportMetrics,
         conn_id: ConnId,
+        max_datagram_size: usize,
+        stream_priorities: Arc<StreamPriorities>,
+        push_queue_capacity: usize,
+        max_inflight_rpcs: usize,
+        traffic_classes: &TrafficClasses,
+        rate_limiter: Option<Arc<RateLimiter>>,
+        outgoing_layers: Arc<OutgoingLayers>,
+        ordered_lanes: Arc<OrderedLanes>,
+        compression: Option<CompressionConfig>,
+        protocol: ProtocolVersion,
+        wire_format: WireFormat,
     ) -> Self {
+        let inflight_rpcs = metrics
+            .connection_handle_inflight_rpcs
+            .with_label_values(&[&peer_id.to_string()]);
         Self {
             peer_id,
             connection,
             metrics,
             conn_id,
+            max_datagram_size,
+            stream_priorities,
+            push_permits: Arc::new(Semaphore::new(push_queue_capacity)),
+            rpc_permits: Arc::new(Semaphore::new(max_inflight_rpcs)),
+            class_rpc_permits: Arc::new(traffic_classes.rpc_permits()),
+            inflight_rpcs,
+            rate_limiter,
+            outgoing_layers,
+            ordered_lanes,
+            lanes: Arc::new(LaneSenders::default()),
+            compression,
+            protocol,
+            wire_format,
+            inflight: InflightRequests::default(),
+        }
+    }
+
+    fn compress(&self, request: Request<Bytes>) -> Request<Bytes> {
+        match &self.compression {
+            Some(compression) => compression.compress_request(request),
+            None => request,
+        }
+    }
+
+    /// Waits until the request can be sent without exceeding the bandwidth limit.
+    async fn throttle(&self, request: &Request<Bytes>) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire(request.body().len() as u64).await;
         }
     }
 
+    /// Admits an rpc if not too many rpcs to the peer, of the same traffic class if any, are in
+    /// flight already.
+    fn admit_rpc(
+        &self,
+        request_type: &str,
+        class: Option<TrafficClass>,
+    ) -> Result<RpcPermit, SendError> {
+        let class_label = class.map_or(TRAFFIC_CLASS_NONE, |class| class.as_str());
+        self.metrics
+            .connection_handle_traffic_class_requests_total
+            .with_label_values(&[request_type, class_label])
+            .inc();
+        let permits = match class {
+            Some(class) => &self.class_rpc_permits[&class],
+            None => &self.rpc_permits,
+        };
+        let permit = permits.clone().try_acquire_owned().map_err(|_| {
+            self.metrics
+                .connection_handle_errors_total
+                .with_label_values(&[request_type, ERROR_TYPE_OVERLOADED])
+                .inc();
+            self.metrics
+                .connection_handle_traffic_class_overloaded_total
+                .with_label_values(&[class_label])
+                .inc();
+            SendError::Overloaded
+        })?;
+        let class_inflight_rpcs = self
+            .metrics
+            .connection_handle_traffic_class_inflight_rpcs
+            .with_label_values(&[class_label]);
+        self.inflight_rpcs.inc();
+        class_inflight_rpcs.inc();
+        Ok(RpcPermit {
+            _permit: permit,
+            inflight_rpcs: self.inflight_rpcs.clone(),
+            class_inflight_rpcs,
+        })
+    }
+
+    /// Fails if the request did not complete before its deadline. The stream is aborted by
+    /// dropping it, see `ResetOnDrop`.
+    fn abort_on_timeout<T>(
+        &self,
+        request_type: &str,
+        result: Option<Result<T, SendError>>,
+    ) -> Result<T, SendError> {
+        result.unwrap_or_else(|| {
+            self.metrics
+                .connection_handle_errors_total
+                .with_label_values(&[request_type, ERROR_TYPE_TIMEOUT])
+                .inc();
+            Err(SendError::Timeout)
+        })
+    }
+
     pub(crate) fn conn_id(&self) -> ConnId {
         self.conn_id
     }
 
-    pub(crate) async fn rpc(
-        &self,
-        mut request: Request<Bytes>,
-    ) -> Result<Response<Bytes>, SendError> {
+    pub(crate) fn protocol(&self) -> ProtocolVersion {
+        self.protocol
+    }
+
+    /// Number of rpcs to the peer that are in flight.
+    pub(crate) fn inflight_rpcs(&self) -> usize {
+        self.inflight_rpcs.get().max(0) as usize
+    }
+
+    pub(crate) fn inflight(&self) -> InflightRequests {
+        self.inflight.clone()
+    }
+
+    pub(crate) fn peer_stats(&self) -> PeerStats {
+        let path_stats = self.connection.stats().path;
+        PeerStats {
+            rtt: path_stats.rtt,
+            congestion_window: path_stats.cwnd,
+            sent_packets: path_stats.sent_packets,
+            lost_packets: path_stats.lost_packets,
+            loss_rate: if path_stats.sent_packets == 0 {
+                0.0
+            } else {
+                path_stats.lost_packets as f64 / path_stats.sent_packets as f64
+            },
+        }
+    }
+
+    /// Fails with `SendError::Overloaded` if too many rpcs to the peer are in flight.
+    pub(crate) async fn rpc(&self, request: Request<Bytes>) -> Result<Response<Bytes>, SendError> {
+        let _permit = self.admit_rpc(REQUEST_TYPE_RPC, traffic_class(&request))?;
+        let _inflight = self.inflight.start();
+        if self.outgoing_layers.is_empty() {
+            return self.send_rpc(request).await;
+        }
+        let handle = self.clone();
+        let send = BoxCloneService::new(service_fn(move |request: Request<Bytes>| {
+            let handle = handle.clone();
+            async move { handle.send_rpc(request).await }
+        }));
+        self.outgoing_layers.wrap(send).oneshot(request).await
+    }
+
+    async fn send_rpc(&self, mut request: Request<Bytes>) -> Result<Response<Bytes>, SendError> {
         let _timer = self
             .metrics
             .connection_handle_duration_seconds
             .with_label_values(&[request.uri().path()])
             .start_timer();
+        let response_size = self
+            .metrics
+            .response_size_bytes
+            .with_label_values(&[request.uri().path(), DIRECTION_OUTBOUND]);
         self.metrics
             .connection_handle_bytes_sent_total
             .with_label_values(&[request.uri().path()])
             .inc_by(request.body().len() as u64);
+        self.metrics
+            .request_size_bytes
+            .with_label_values(&[request.uri().path(), DIRECTION_OUTBOUND])
+            .observe(request.body().len() as f64);
         let in_counter = self
             .metrics
             .connection_handle_bytes_received_total
             .with_label_values(&[request.uri().path()]);
 
+        let priority = self.stream_priorities.for_request(&request);
+        let deadline = encode_deadline(&mut request);
+        encode_trace_context(&mut request);
+        encode_traffic_class(&mut request);
+        let mut request = self.compress(request);
+
         // Propagate PeerId from this connection to lower layers.
         request.extensions_mut().insert(self.peer_id);
 
-        let (mut send_stream, recv_stream) = self.connection.open_bi().await.map_err(|err| {
-            self.metrics
-                .connection_handle_errors_total
-                .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_OPEN]);
-            err
-        })?;
+        // The deadline also bounds the time spent waiting for the bandwidth limit.
+        let exchange = async {
+            self.throttle(&request).await;
 
-        write_request(&mut send_stream, request)
-            .await
-            .map_err(|err| {
+            let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(|err| {
                 self.metrics
                     .connection_handle_errors_total
-                    .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_WRITE])
-                    .inc();
+                    .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_OPEN]);
                 err
             })?;
+            let _ = send_stream.set_priority(priority.0);
+            let mut send_stream = ResetOnDrop::new(send_stream);
 
-        send_stream.finish().await.map_err(|err| {
-            self.metrics
-                .connection_handle_errors_total
-                .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_FINISH])
-                .inc();
-            err
-        })?;
+            write_request(&mut send_stream, request, self.wire_format)
+                .await
+                .map_err(|err| {
+                    self.metrics
+                        .connection_handle_errors_total
+                        .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_WRITE])
+                        .inc();
+                    err
+                })?;
 
-        let mut response = read_response(recv_stream).await.map_err(|err| {
-            self.metrics
-                .connection_handle_errors_total
-                .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_READ])
-                .inc();
-            err
-        })?;
+            send_stream.finish().await.map_err(|err| {
+                self.metrics
+                    .connection_handle_errors_total
+                    .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_FINISH])
+                    .inc();
+                err
+            })?;
+
+            let response = read_response(recv_stream, self.wire_format)
+                .await
+                .map_err(|err| {
+                    self.metrics
+                        .connection_handle_errors_total
+                        .with_label_values(&[REQUEST_TYPE_RPC, ERROR_TYPE_READ])
+                        .inc();
+                    err
+                })?;
+            send_stream.disarm();
+            Ok::<_, SendError>(response)
+        };
+        let response = with_deadline(deadline, exchange).await;
+        let mut response = self.abort_on_timeout(REQUEST_TYPE_RPC, response)?;
 
         // Propagate PeerId from this request to upper layers.
         response.extensions_mut().insert(self.peer_id);
 
         in_counter.inc_by(response.body().len() as u64);
+        response_size.observe(response.body().len() as f64);
         Ok(response)
     }
 
-    pub(crate) async fn push(&self, mut request: Request<Bytes>) -> Result<(), SendError> {
+    /// Sends an empty rpc to the health check URI of the peer, bypassing the outgoing layers.
+    /// Pings are control traffic, so that they are not delayed by other streams.
+    pub(crate) async fn ping(&self, timeout: Duration) -> Result<(), SendError> {
+        let mut request = Request::new(Bytes::new());
+        *request.uri_mut() = Uri::from_static(HEALTH_CHECK_URI);
+        request.extensions_mut().insert(Deadline::after(timeout));
+        request.extensions_mut().insert(TrafficClass::Control);
+        self.send_rpc(request).await.map(|_| ())
+    }
+
+    pub(crate) async fn rpc_stream(
+        &self,
+        mut request: Request<Bytes>,
+    ) -> Result<Response<ResponseStream>, SendError> {
+        // Admission and draining only cover the time until the response starts arriving.
+        let _permit = self.admit_rpc(REQUEST_TYPE_RPC_STREAM, traffic_class(&request))?;
+        let _inflight = self.inflight.start();
+        // Only measures the time until the response starts arriving.
         let _timer = self
             .metrics
             .connection_handle_duration_seconds
@@ -116,18 +361,171 @@ impl ConnectionHandle {
             .connection_handle_bytes_sent_total
             .with_label_values(&[request.uri().path()])
             .inc_by(request.body().len() as u64);
+        self.metrics
+            .request_size_bytes
+            .with_label_values(&[request.uri().path(), DIRECTION_OUTBOUND])
+            .observe(request.body().len() as f64);
+        let in_counter = self
+            .metrics
+            .connection_handle_bytes_received_total
+            .with_label_values(&[request.uri().path()]);
+        let read_error_counter = self
+            .metrics
+            .connection_handle_errors_total
+            .with_label_values(&[REQUEST_TYPE_RPC_STREAM, ERROR_TYPE_READ]);
+
+        let priority = self.stream_priorities.for_request(&request);
+        let deadline = encode_deadline(&mut request);
+        encode_trace_context(&mut request);
+        encode_traffic_class(&mut request);
+        let mut request = self.compress(request);
 
         // Propagate PeerId from this connection to lower layers.
         request.extensions_mut().insert(self.peer_id);
 
+        // The deadline applies from waiting for the bandwidth limit until the response starts
+        // arriving. Afterwards the caller can drop the body stream to abort.
+        let exchange = async {
+            self.throttle(&request).await;
+
+            let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(|err| {
+                self.metrics
+                    .connection_handle_errors_total
+                    .with_label_values(&[REQUEST_TYPE_RPC_STREAM, ERROR_TYPE_OPEN])
+                    .inc();
+                err
+            })?;
+            let _ = send_stream.set_priority(priority.0);
+            let mut send_stream = ResetOnDrop::new(send_stream);
+
+            write_request(&mut send_stream, request, self.wire_format)
+                .await
+                .map_err(|err| {
+                    self.metrics
+                        .connection_handle_errors_total
+                        .with_label_values(&[REQUEST_TYPE_RPC_STREAM, ERROR_TYPE_WRITE])
+                        .inc();
+                    err
+                })?;
+
+            send_stream.finish().await.map_err(|err| {
+                self.metrics
+                    .connection_handle_errors_total
+                    .with_label_values(&[REQUEST_TYPE_RPC_STREAM, ERROR_TYPE_FINISH])
+                    .inc();
+                err
+            })?;
+
+            let response = read_response_stream(recv_stream, self.wire_format)
+                .await
+                .map_err(|err| {
+                    read_error_counter.inc();
+                    err
+                })?;
+            send_stream.disarm();
+            Ok::<_, SendError>(response)
+        };
+        let response = with_deadline(deadline, exchange).await;
+        let response = self.abort_on_timeout(REQUEST_TYPE_RPC_STREAM, response)?;
+
+        // Propagate PeerId from this request to upper layers.
+        let (mut parts, body) = response.into_parts();
+        parts.extensions.insert(self.peer_id);
+
+        let body = body
+            .inspect_ok(move |chunk| in_counter.inc_by(chunk.len() as u64))
+            .map_err(move |err| {
+                read_error_counter.inc();
+                err
+            })
+            .boxed();
+
+        Ok(Response::from_parts(parts, body))
+    }
+
+    /// Fails with `SendError::Backpressure` if the peer does not keep up with the pushes that
+    /// are already in progress.
+    pub(crate) async fn push(&self, request: Request<Bytes>) -> Result<(), SendError> {
+        let _inflight = self.inflight.start();
+        if self.outgoing_layers.is_empty() {
+            return self.send_push(request).await;
+        }
+        let handle = self.clone();
+        let send = BoxCloneService::new(service_fn(move |request: Request<Bytes>| {
+            let handle = handle.clone();
+            async move {
+                handle.send_push(request).await?;
+                Ok(Response::new(Bytes::new()))
+            }
+        }));
+        self.outgoing_layers
+            .wrap(send)
+            .oneshot(request)
+            .await
+            .map(|_| ())
+    }
+
+    async fn send_push(&self, mut request: Request<Bytes>) -> Result<(), SendError> {
+        let _permit = self.push_permits.try_acquire().map_err(|_| {
+            self.metrics
+                .connection_handle_errors_total
+                .with_label_values(&[REQUEST_TYPE_PUSH, ERROR_TYPE_BACKPRESSURE])
+                .inc();
+            SendError::Backpressure
+        })?;
+
+        let _timer = self
+            .metrics
+            .connection_handle_duration_seconds
+            .with_label_values(&[request.uri().path()])
+            .start_timer();
+        self.metrics
+            .connection_handle_bytes_sent_total
+            .with_label_values(&[request.uri().path()])
+            .inc_by(request.body().len() as u64);
+        self.metrics
+            .request_size_bytes
+            .with_label_values(&[request.uri().path(), DIRECTION_OUTBOUND])
+            .observe(request.body().len() as f64);
+
+        self.metrics
+            .connection_handle_traffic_class_requests_total
+            .with_label_values(&[REQUEST_TYPE_PUSH, traffic_class_label(&request)])
+            .inc();
+        let priority = self.stream_priorities.for_request(&request);
+        encode_trace_context(&mut request);
+        encode_traffic_class(&mut request);
+        let mut request = self.compress(request);
+        self.throttle(&request).await;
+
+        // Propagate PeerId from this connection to lower layers.
+        request.extensions_mut().insert(self.peer_id);
+
+        // Peers that did not announce features do not understand lanes.
+        if self.wire_format == WireFormat::Extended && self.ordered_lanes.contains(request.uri()) {
+            return self
+                .lanes
+                .send(&self.connection, priority, request, self.wire_format)
+                .await
+                .map_err(|err| {
+                    self.metrics
+                        .connection_handle_errors_total
+                        .with_label_values(&[REQUEST_TYPE_PUSH, ERROR_TYPE_WRITE])
+                        .inc();
+                    err
+                });
+        }
+
         let mut send_stream = self.connection.open_uni().await.map_err(|err| {
             self.metrics
                 .connection_handle_errors_total
                 .with_label_values(&[REQUEST_TYPE_PUSH, ERROR_TYPE_OPEN]);
             err
         })?;
+        let _ = send_stream.set_priority(priority.0);
+        let mut send_stream = ResetOnDrop::new(send_stream);
 
-        write_request(&mut send_stream, request)
+        write_request(&mut send_stream, request, self.wire_format)
             .await
             .map_err(|err| {
                 self.metrics
@@ -144,7 +542,163 @@ impl ConnectionHandle {
                 .inc();
             err
         })?;
+        send_stream.disarm();
+
+        Ok(())
+    }
+
+    /// Sends the request like an rpc, but the peer only answers with the status of the handler.
+    /// Fails with `SendError::Timeout` if the ack does not arrive within `timeout`.
+    pub(crate) async fn push_acked(
+        &self,
+        mut request: Request<Bytes>,
+        timeout: Duration,
+    ) -> Result<(), SendError> {
+        request_ack(&mut request, timeout);
+        check_ack(self.rpc(request).await?)
+    }
+
+    /// Sends the request in a single datagram. Succeeding only means that the datagram was
+    /// queued, it can still be lost or dropped by the peer.
+    pub(crate) fn push_unreliable(&self, request: Request<Bytes>) -> Result<(), SendError> {
+        let path = request.uri().path().to_string();
+        let body_len = request.body().len();
+        let datagram = encode_request(request, self.wire_format)?;
+
+        // `None` if datagrams are not supported, which is reported by `send_datagram` below.
+        let max_datagram_size = self
+            .connection
+            .max_datagram_size()
+            .map(|size| size.min(self.max_datagram_size));
+        if let Some(max_datagram_size) = max_datagram_size {
+            if datagram.len() > max_datagram_size {
+                self.metrics
+                    .connection_handle_datagrams_dropped_total
+                    .with_label_values(&[DROP_REASON_TOO_LARGE])
+                    .inc();
+                return Err(SendError::MessageTooLarge);
+            }
+        }
+
+        self.connection.send_datagram(datagram).map_err(|err| {
+            let reason = match err {
+                SendDatagramError::ConnectionLost(_) => DROP_REASON_CONNECTION_LOST,
+                SendDatagramError::TooLarge => DROP_REASON_TOO_LARGE,
+                SendDatagramError::UnsupportedByPeer | SendDatagramError::Disabled => {
+                    DROP_REASON_UNSUPPORTED
+                }
+            };
+            self.metrics
+                .connection_handle_datagrams_dropped_total
+                .with_label_values(&[reason])
+                .inc();
+            match err {
+                SendDatagramError::ConnectionLost(conn_err) => conn_err.into(),
+                SendDatagramError::TooLarge => SendError::MessageTooLarge,
+                SendDatagramError::UnsupportedByPeer | SendDatagramError::Disabled => {
+                    SendError::Unsupported(err.to_string())
+                }
+            }
+        })?;
 
+        self.metrics
+            .connection_handle_bytes_sent_total
+            .with_label_values(&[&path])
+            .inc_by(body_len as u64);
+        self.metrics
+            .request_size_bytes
+            .with_label_values(&[&path, DIRECTION_OUTBOUND])
+            .observe(body_len as f64);
         Ok(())
     }
+
+    pub(crate) async fn open_channel(&self, uri: Uri) -> Result<Channel, SendError> {
+        let priority = self.stream_priorities.for_uri(&uri);
+
+        let (send_stream, recv_stream) = self.connection.open_bi().await.map_err(|err| {
+            self.metrics
+                .connection_handle_errors_total
+                .with_label_values(&[REQUEST_TYPE_CHANNEL, ERROR_TYPE_OPEN])
+                .inc();
+            err
+        })?;
+        let _ = send_stream.set_priority(priority.0);
+
+        open_channel(send_stream, recv_stream, uri, self.wire_format)
+            .await
+            .map_err(|err| {
+                self.metrics
+                    .connection_handle_errors_total
+                    .with_label_values(&[REQUEST_TYPE_CHANNEL, ERROR_TYPE_WRITE])
+                    .inc();
+                err
+            })
+    }
+}
+
+/// Runs `fut` to completion, or returns `None` if the deadline expires first.
+async fn with_deadline<F: Future>(deadline: Option<Deadline>, fut: F) -> Option<F::Output> {
+    match deadline {
+        Some(deadline) => tokio::time::timeout_at(deadline.0, fut).await.ok(),
+        None => Some(fut.await),
+    }
+}
+
+/// Admission of an rpc. Keeps the gauges of in-flight rpcs up to date until dropped.
+struct RpcPermit {
+    _permit: OwnedSemaphorePermit,
+    inflight_rpcs: IntGauge,
+    class_inflight_rpcs: IntGauge,
+}
+
+impl Drop for RpcPermit {
+    fn drop(&mut self) {
+        self.inflight_rpcs.dec();
+        self.class_inflight_rpcs.dec();
+    }
+}
+
+/// Resets the send side of a stream if it is dropped before `disarm` is called, e.g. because
+/// the caller dropped the future of a request. Otherwise quinn finishes streams on drop, and
+/// the peer would handle a truncated request. The receive side needs no guard, since quinn
+/// asks the peer to stop sending if it is dropped before the response was read. This cancels
+/// the handler of the peer.
+struct ResetOnDrop {
+    stream: SendStream,
+    armed: bool,
+}
+
+impl ResetOnDrop {
+    fn new(stream: SendStream) -> Self {
+        Self {
+            stream,
+            armed: true,
+        }
+    }
+
+    fn disarm(&mut self) {
+        self.armed = false;
+    }
+}
+
+impl Deref for ResetOnDrop {
+    type Target = SendStream;
+
+    fn deref(&self) -> &SendStream {
+        &self.stream
+    }
+}
+
+impl DerefMut for ResetOnDrop {
+    fn deref_mut(&mut self) -> &mut SendStream {
+        &mut self.stream
+    }
+}
+
+impl Drop for ResetOnDrop {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.stream.reset(VarInt::from_u32(0));
+        }
+    }
 }
@@ -28,15 +28,33 @@
 //!       it needs to repair broken connections.
 //!     - Currently there is a periodic check that checks the status of the connection
 //!       and reconnects if necessary.
+//!     - Connections to peers that left the topology are drained instead of closed right
+//!       away, see drain.rs.
+//!
+//! Connection migration:
+//!     - If the local address of the node changes, e.g. on failover to another NIC, the
+//!       endpoint can be moved to a new socket with `LocalEndpoint::rebind`. Existing
+//!       connections migrate to the new address instead of being closed and re-dialed.
+//!     - Peers accept migrations of connections they did not dial, unless migration is
+//!       disabled in the config.
+//!     - With a failover config the connection manager rebinds the endpoint itself if the
+//!       node is disconnected from all peers, see failover.rs.
+//!
+//! Address families:
+//!     - Sockets bound to an IPv6 address are dual-stack unless disabled in the config, i.e.
+//!       they also send and receive IPv4 traffic if bound to the unspecified address `[::]`.
+//!     - Peers are dialed with the address family of the local socket. IPv4 peers are dialed
+//!       through IPv4-mapped IPv6 addresses from dual-stack sockets. Peers whose address
+//!       family is not supported by the local socket are not dialed.
 use std::{
     collections::{BTreeSet, HashMap},
-    net::SocketAddr,
+    net::{SocketAddr, UdpSocket},
     pin::Pin,
     sync::{Arc, RwLock},
     time::Duration,
 };
 
-use axum::{middleware::from_fn_with_state, Router};
+use axum::{middleware::from_fn_with_state, routing::any, Router};
 use either::Either;
 use futures::StreamExt;
 use ic_async_utils::JoinMap;
@@ -61,25 +79,42 @@ use tokio::{
     runtime::Handle,
     select,
     task::JoinSet,
+    time::MissedTickBehavior,
 };
 use tokio_util::{sync::CancellationToken, task::task_tracker::TaskTracker, time::DelayQueue};
 
 use crate::{
+    attestation::Attestation,
+    cert_rotation::{
+        CertRotation, TlsCertificates, CYCLE_INTERVAL, MAX_CYCLED_CONNECTIONS_PER_INTERVAL,
+    },
+    compression::{CompressionConfig, ZSTD_ENCODING},
     connection_handle::ConnectionHandle,
+    drain::{drain_connection, DrainReason},
+    failover::Failover,
+    health::{run_health_check, HealthCheckConfig, HEALTH_CHECK_URI},
+    lane::OrderedLanes,
+    log_sampling::{info_sampled, EventClass, LogSampler, LogSamplingConfig},
+    message_size::MessageSizeLimits,
     metrics::{CONNECTION_RESULT_FAILED_LABEL, CONNECTION_RESULT_SUCCESS_LABEL},
-    utils::collect_metrics,
-    ConnId, SubnetTopology,
+    middleware::OutgoingLayers,
+    priority::StreamPriorities,
+    protocol::ProtocolVersion,
+    pubsub::{PubSub, ANNOUNCEMENT_TIMEOUT},
+    quarantine::{Quarantine, QUARANTINED_ERROR_CODE},
+    rate_limit::{RateLimitConfig, RateLimiter},
+    traffic_class::TrafficClasses,
+    utils::{collect_metrics, WireFormat},
+    ConnId, QuicTransportConfig, SubnetTopology,
 };
 use crate::{metrics::QuicTransportMetrics, request_handler::run_stream_acceptor};
 
-/// Interval of quic heartbeats. They are only sent if the connection is idle for more than 200ms.
-const KEEP_ALIVE_INTERVAL: Duration = Duration::from_millis(200);
-/// Timeout after which quic marks connections as broken. This timeout is used to detect connections
-/// that were not explicitly closed. I.e replica crash
-const IDLE_TIMEOUT: Duration = Duration::from_secs(5);
 const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 const CONNECT_RETRY_BACKOFF: Duration = Duration::from_secs(3);
+const FAILOVER_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 const GRUEZI_HANDSHAKE: &str = "gruezi";
+/// Upper bound on the length of the greeting including the announced features.
+const MAX_GRUEZI_HANDSHAKE_LEN: usize = 256;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Direction {
@@ -95,6 +130,40 @@ struct ConnectionManager {
     node_id: NodeId,
     rt: Handle,
     metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
+    /// Upper bound on the size of datagrams sent and accepted by connections.
+    max_datagram_size: usize,
+    max_inflight_datagrams_per_peer: usize,
+    stream_priorities: Arc<StreamPriorities>,
+    push_queue_capacity: usize,
+    max_inflight_rpcs_per_peer: usize,
+    traffic_classes: TrafficClasses,
+    /// Bandwidth limit of each connection, if any.
+    rate_limit: Option<RateLimitConfig>,
+    outgoing_layers: Arc<OutgoingLayers>,
+    ordered_lanes: Arc<OrderedLanes>,
+    /// Compression of bodies, if enabled. Only used for peers that enabled it too.
+    compression: Option<CompressionConfig>,
+    message_size_limits: Arc<MessageSizeLimits>,
+    /// Whether peers can migrate connections to another address.
+    connection_migration: bool,
+    /// Whether IPv6 sockets also handle IPv4 traffic.
+    dual_stack: bool,
+    /// Protocol announced to peers.
+    protocol: ProtocolVersion,
+    /// Whether features are announced to peers that did not announce theirs first.
+    announce_features: bool,
+    /// Failover to other local addresses, if configured.
+    failover: Option<Failover>,
+    /// Grace period of in-flight requests to peers that left the topology.
+    drain_grace_period: Duration,
+    /// Pings of connected peers, if enabled.
+    health_check: Option<HealthCheckConfig>,
+    /// Peers that are neither dialed nor accepted for now.
+    quarantine: Quarantine,
+    /// Subscriptions to topics, announced to each peer that connects.
+    pubsub: PubSub,
+    local_endpoint: LocalEndpoint,
 
     /// Current topology
     topology: SubnetTopology,
@@ -106,7 +175,8 @@ struct ConnectionManager {
 
     // Authentication
     tls_config: Arc<dyn TlsConfig + Send + Sync>,
-    sev_handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+    attestation: Attestation,
+    cert_rotation: CertRotation,
 
     // Shared state
     watcher: tokio::sync::watch::Receiver<SubnetTopology>,
@@ -122,6 +192,8 @@ struct ConnectionManager {
     inbound_connecting: JoinSet<Result<ConnectionWithPeerId, ConnectionEstablishError>>,
     /// JoinMap that stores active connection handlers keyed by peer id.
     active_connections: JoinMap<NodeId, ()>,
+    /// Task joinset on which connections to peers that left the topology are drained.
+    draining_connections: JoinSet<()>,
 
     /// Endpoint config
     endpoint: Endpoint,
@@ -157,6 +229,10 @@ enum ConnectionEstablishError {
 struct ConnectionWithPeerId {
     peer_id: NodeId,
     connection: Connection,
+    peer_supports_compression: bool,
+    /// Protocol negotiated with the peer.
+    protocol: ProtocolVersion,
+    wire_format: WireFormat,
 }
 
 impl std::fmt::Display for ConnectionEstablishError {
@@ -189,6 +265,113 @@ impl std::fmt::Display for ConnectionEstablishError {
     }
 }
 
+/// Handle to the endpoint of the connection manager, used to move it to another local address.
+#[derive(Clone)]
+pub(crate) struct LocalEndpoint {
+    log: ReplicaLogger,
+    rt: Handle,
+    metrics: QuicTransportMetrics,
+    endpoint: Endpoint,
+    dual_stack: bool,
+}
+
+impl LocalEndpoint {
+    pub(crate) fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Binds a new socket to `addr` and switches the endpoint to it. Connections keep their
+    /// state and migrate to the new address.
+    pub(crate) fn rebind(&self, addr: SocketAddr) -> std::io::Result<()> {
+        let result = bind_udp_socket(&self.log, addr, self.dual_stack).and_then(|socket| {
+            let _enter_guard = self.rt.enter();
+            self.endpoint.rebind(socket)
+        });
+        match &result {
+            Ok(()) => {
+                info!(self.log, "Rebound quic endpoint to {}", addr);
+                self.metrics
+                    .endpoint_rebinds_total
+                    .with_label_values(&[CONNECTION_RESULT_SUCCESS_LABEL])
+                    .inc();
+            }
+            Err(e) => {
+                error!(
+                    self.log,
+                    "Failed to rebind quic endpoint to {}: {}", addr, e
+                );
+                self.metrics
+                    .endpoint_rebinds_total
+                    .with_label_values(&[CONNECTION_RESULT_FAILED_LABEL])
+                    .inc();
+            }
+        }
+        result
+    }
+}
+
+fn bind_udp_socket(
+    log: &ReplicaLogger,
+    addr: SocketAddr,
+    dual_stack: bool,
+) -> std::io::Result<UdpSocket> {
+    let socket2 = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket2.set_only_v6(!dual_stack)?;
+    }
+
+    // Set socket send/recv buffer size. Setting these explicitly makes sure that a
+    // sufficiently large value is used. Increasing these buffers can help with high packetloss.
+    // The value of 25MB isch chosen from experiments and the BDP product shown below to support
+    // around 2Gb/s.
+    // Bandwidth-Delay Product
+    // 2Gb/s * 100ms ~ 200M bits = 25MB
+    // To this only on to avoid unecessary error in dfx on MacOS
+    #[cfg(target_os = "linux")]
+    if let Err(e) = socket2.set_recv_buffer_size(25_000_000) {
+        info!(log, "Failed to set receive udp buffer. {}", e)
+    }
+    #[cfg(target_os = "linux")]
+    if let Err(e) = socket2.set_send_buffer_size(25_000_000) {
+        info!(log, "Failed to set send udp buffer. {}", e)
+    }
+    info!(
+        log,
+        "Udp receive buffer size: {:?}",
+        socket2.recv_buffer_size()
+    );
+    info!(
+        log,
+        "Udp send buffer size: {:?}",
+        socket2.send_buffer_size()
+    );
+    socket2.bind(&SockAddr::from(addr))?;
+    Ok(socket2.into())
+}
+
+/// Address used to dial a peer from a socket bound to `local_addr`, or `None` if the peer is
+/// not reachable with the address family of the socket.
+fn dial_addr(
+    local_addr: SocketAddr,
+    dual_stack: bool,
+    peer_addr: SocketAddr,
+) -> Option<SocketAddr> {
+    match (local_addr, peer_addr) {
+        (SocketAddr::V4(_), SocketAddr::V4(_)) | (SocketAddr::V6(_), SocketAddr::V6(_)) => {
+            Some(peer_addr)
+        }
+        (SocketAddr::V6(_), SocketAddr::V4(peer_addr)) if dual_stack => Some(SocketAddr::new(
+            peer_addr.ip().to_ipv6_mapped().into(),
+            peer_addr.port(),
+        )),
+        (SocketAddr::V4(_), SocketAddr::V6(peer_addr)) => peer_addr
+            .ip()
+            .to_ipv4_mapped()
+            .map(|ip| SocketAddr::new(ip.into(), peer_addr.port())),
+        _ => None,
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn start_connection_manager(
     log: &ReplicaLogger,
@@ -196,6 +379,7 @@ pub(crate) fn start_connection_manager(
     rt: &Handle,
     tls_config: Arc<dyn TlsConfig + Send + Sync>,
     registry_client: Arc<dyn RegistryClient>,
+    tls_certificates: Arc<dyn TlsCertificates>,
     sev_handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
     node_id: NodeId,
     peer_map: Arc<RwLock<HashMap<NodeId, ConnectionHandle>>>,
@@ -204,12 +388,20 @@ pub(crate) fn start_connection_manager(
     task_tracker: TaskTracker,
     socket: Either<SocketAddr, impl AsyncUdpSocket>,
     router: Router,
-) {
+    config: QuicTransportConfig,
+    quarantine: Quarantine,
+    pubsub: PubSub,
+) -> LocalEndpoint {
     let topology = watcher.borrow().clone();
 
     let metrics = QuicTransportMetrics::new(metrics_registry);
 
-    let router = router.route_layer(from_fn_with_state(metrics.clone(), collect_metrics));
+    // The health check and pub/sub routes are added after the metrics layer, so that pings and
+    // announcements do not show up as requests of handlers.
+    let router = router
+        .route_layer(from_fn_with_state(metrics.clone(), collect_metrics))
+        .route(HEALTH_CHECK_URI, any(|| async {}))
+        .merge(pubsub.router());
 
     // We use a random reset key here. The downside of this is that
     // during a crash and restart the peer will not recognize our
@@ -229,62 +421,41 @@ pub(crate) fn start_connection_manager(
 
     let mut transport_config = quinn::TransportConfig::default();
 
-    transport_config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
-    transport_config.max_idle_timeout(Some(IDLE_TIMEOUT.try_into().unwrap()));
-    // defaults:
-    // STREAM_RWN 1_250_000
-    // stream_receive_window: STREAM_RWND.into(),
-    // send_window: (8 * STREAM_RWND).into()
-    transport_config.send_window(100_000_000);
-    // Upper bound on receive memory consumption.
-    transport_config.receive_window(VarInt::from_u32(200_000_000));
-    transport_config.stream_receive_window(VarInt::from_u32(4_000_000));
-    transport_config.max_concurrent_bidi_streams(VarInt::from_u32(1_000));
-    transport_config.max_concurrent_uni_streams(VarInt::from_u32(1_000));
+    transport_config.keep_alive_interval(Some(config.keep_alive_interval));
+    transport_config.max_idle_timeout(Some(
+        config
+            .idle_timeout
+            .try_into()
+            .expect("Idle timeout is too large"),
+    ));
+    transport_config.send_window(config.send_window);
+    transport_config.receive_window(VarInt::from_u32(config.receive_window));
+    transport_config.stream_receive_window(VarInt::from_u32(config.stream_receive_window));
+    transport_config
+        .max_concurrent_bidi_streams(VarInt::from_u32(config.max_concurrent_bidi_streams));
+    transport_config
+        .max_concurrent_uni_streams(VarInt::from_u32(config.max_concurrent_uni_streams));
     let transport_config = Arc::new(transport_config);
     let mut server_config = quinn::ServerConfig::with_crypto(Arc::new(rustls_server_config));
     server_config.transport_config(transport_config.clone());
+    server_config.migration(config.connection_migration);
+
+    let primary_addr = socket.as_ref().left().copied();
+    let failover = config
+        .failover
+        .map(|failover| Failover::new(failover, primary_addr));
 
     // Start endpoint
     let endpoint = match socket {
         Either::Left(addr) => {
-            let socket2 = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))
-                .expect("Failed to create udp socket");
-
-            // Set socket send/recv buffer size. Setting these explicitly makes sure that a
-            // sufficiently large value is used. Increasing these buffers can help with high packetloss.
-            // The value of 25MB isch chosen from experiments and the BDP product shown below to support
-            // around 2Gb/s.
-            // Bandwidth-Delay Product
-            // 2Gb/s * 100ms ~ 200M bits = 25MB
-            // To this only on to avoid unecessary error in dfx on MacOS
-            #[cfg(target_os = "linux")]
-            if let Err(e) = socket2.set_recv_buffer_size(25_000_000) {
-                info!(log, "Failed to set receive udp buffer. {}", e)
-            }
-            #[cfg(target_os = "linux")]
-            if let Err(e) = socket2.set_send_buffer_size(25_000_000) {
-                info!(log, "Failed to set send udp buffer. {}", e)
-            }
-            info!(
-                log,
-                "Udp receive buffer size: {:?}",
-                socket2.recv_buffer_size()
-            );
-            info!(
-                log,
-                "Udp send buffer size: {:?}",
-                socket2.send_buffer_size()
-            );
-            socket2
-                .bind(&SockAddr::from(addr))
+            let socket = bind_udp_socket(log, addr, config.dual_stack)
                 .expect("Failed to bind to UDP socket");
 
             let _enter_guard = rt.enter();
             Endpoint::new(
                 endpoint_config,
                 Some(server_config),
-                socket2.into(),
+                socket,
                 Arc::new(quinn::TokioRuntime),
             )
             .expect("Failed to create endpoint")
@@ -297,13 +468,58 @@ pub(crate) fn start_connection_manager(
         )
         .expect("Failed to create endpoint"),
     };
+    let attestation = Attestation::new(
+        log.clone(),
+        config.attestation,
+        sev_handshake,
+        metrics.attestation_failures_tolerated_total.clone(),
+    );
+    let cert_rotation = CertRotation::new(
+        tls_certificates,
+        node_id,
+        registry_client.get_latest_version(),
+    );
+    let local_endpoint = LocalEndpoint {
+        log: log.clone(),
+        rt: rt.clone(),
+        metrics: metrics.clone(),
+        endpoint: endpoint.clone(),
+        dual_stack: config.dual_stack,
+    };
 
     let manager = ConnectionManager {
         log: log.clone(),
         rt: rt.clone(),
         tls_config,
         metrics,
-        sev_handshake,
+        log_sampler: LogSampler::new(config.log_sampling),
+        max_datagram_size: config.max_datagram_size,
+        max_inflight_datagrams_per_peer: config.max_inflight_datagrams_per_peer,
+        stream_priorities: Arc::new(
+            config
+                .stream_priorities
+                .with_traffic_classes(&config.traffic_classes),
+        ),
+        push_queue_capacity: config.push_queue_capacity,
+        max_inflight_rpcs_per_peer: config.max_inflight_rpcs_per_peer,
+        traffic_classes: config.traffic_classes,
+        rate_limit: config.rate_limit,
+        outgoing_layers: Arc::new(config.outgoing_layers),
+        ordered_lanes: Arc::new(config.ordered_lanes),
+        compression: config.compression,
+        message_size_limits: Arc::new(config.message_size_limits),
+        connection_migration: config.connection_migration,
+        dual_stack: config.dual_stack,
+        protocol: config.protocol,
+        announce_features: config.announce_features,
+        failover,
+        drain_grace_period: config.drain_grace_period,
+        health_check: config.health_check,
+        quarantine,
+        pubsub,
+        local_endpoint: local_endpoint.clone(),
+        attestation,
+        cert_rotation,
         node_id,
         topology,
         connect_queue: DelayQueue::new(),
@@ -316,9 +532,11 @@ pub(crate) fn start_connection_manager(
         outbound_connecting: JoinMap::new(),
         inbound_connecting: JoinSet::new(),
         active_connections: JoinMap::new(),
+        draining_connections: JoinSet::new(),
         router,
     };
     task_tracker.spawn_on(manager.run(), rt);
+    local_endpoint
 }
 
 impl ConnectionManager {
@@ -327,11 +545,21 @@ impl ConnectionManager {
     }
 
     pub async fn run(mut self) {
+        let mut failover_check = tokio::time::interval(FAILOVER_CHECK_INTERVAL);
+        let mut cert_rotation_cycle = tokio::time::interval(CYCLE_INTERVAL);
+        // Ticks missed while no connections were queued must not cycle several batches at once.
+        cert_rotation_cycle.set_missed_tick_behavior(MissedTickBehavior::Delay);
         loop {
             select! {
                 () = self.cancellation.cancelled() => {
                     break;
                 },
+                _ = failover_check.tick(), if self.failover.is_some() => {
+                    self.check_failover();
+                },
+                _ = cert_rotation_cycle.tick(), if self.cert_rotation.has_outdated() => {
+                    self.cycle_outdated_connections();
+                },
                 Some(reconnect) = self.connect_queue.next() => {
                     self.handle_dial(reconnect.into_inner())
                 },
@@ -371,6 +599,14 @@ impl ConnectionManager {
                         }
                     }
                 },
+                Some(drain_result) = self.draining_connections.join_next() => {
+                    // Cancelling tasks is ok. Panicking tasks are not.
+                    if let Err(err) = drain_result {
+                        if err.is_panic() {
+                            std::panic::resume_unwind(err.into_panic());
+                        }
+                    }
+                },
                 Some(active_result) = self.active_connections.join_next() => {
                     match active_result {
                         Ok((_, peer_id)) => self.handled_closed_conn(peer_id),
@@ -405,10 +641,34 @@ impl ConnectionManager {
         self.connect_queue.clear();
         self.inbound_connecting.shutdown().await;
         self.outbound_connecting.shutdown().await;
+        self.draining_connections.shutdown().await;
         self.active_connections.shutdown().await;
         self.endpoint.wait_idle().await;
     }
 
+    /// Rebinds the endpoint to the next local address if no peer is reachable from the current one.
+    fn check_failover(&mut self) {
+        let has_peers = self.topology.is_member(&self.node_id)
+            && self
+                .topology
+                .iter()
+                .any(|(peer_id, _)| *peer_id != self.node_id);
+        let connected = !has_peers || !self.active_connections.is_empty();
+        let Some(addr) = self
+            .failover
+            .as_mut()
+            .and_then(|failover| failover.poll(connected, tokio::time::Instant::now()))
+        else {
+            return;
+        };
+        info!(
+            self.log,
+            "Not connected to any peer, failing over to local address {}", addr
+        );
+        // Errors are logged and recorded by `rebind`. The next address is tried after the timeout.
+        let _ = self.local_endpoint.rebind(addr);
+    }
+
     // Removes connection and sets peer status to disconnected
     fn handled_closed_conn(&mut self, peer_id: NodeId) {
         self.peer_map.write().unwrap().remove(&peer_id);
@@ -419,6 +679,7 @@ impl ConnectionManager {
 
     fn handle_topology_change(&mut self) {
         self.metrics.topology_changes_total.inc();
+        let previous_registry_version = self.topology.latest_registry_version();
         self.topology = self.watcher.borrow_and_update().clone();
 
         let subnet_node_set = self.topology.get_subnet_nodes();
@@ -434,6 +695,7 @@ impl ConnectionManager {
                 let mut server_config =
                     quinn::ServerConfig::with_crypto(Arc::new(rustls_server_config));
                 server_config.transport_config(self.transport_config.clone());
+                server_config.migration(self.connection_migration);
                 self.endpoint.set_server_config(Some(server_config));
             }
             Err(e) => {
@@ -457,8 +719,8 @@ impl ConnectionManager {
             }
         }
 
-        // Remove peer connections that are not part of subnet anymore.
-        // Also remove peer connections that have closed connections.
+        // Remove peer connections that are not part of subnet anymore. Their removal from the
+        // peer map stops new requests, the connections are closed once drained.
         let mut peer_map = self.peer_map.write().unwrap();
         peer_map.retain(|peer_id, conn_handle| {
             let peer_left_topology = !self.topology.is_member(peer_id);
@@ -467,16 +729,77 @@ impl ConnectionManager {
             let should_close_connection = peer_left_topology || node_left_topology;
 
             if should_close_connection {
+                info!(
+                    self.log,
+                    "Draining connection to peer {} that is not part of the subnet anymore",
+                    peer_id
+                );
                 self.metrics.peers_removed_total.inc();
-                conn_handle
-                    .connection
-                    .close(VarInt::from_u32(0), b"node not part of subnet anymore");
+                self.draining_connections.spawn_on(
+                    drain_connection(
+                        conn_handle.connection.clone(),
+                        conn_handle.inflight(),
+                        self.drain_grace_period,
+                        self.metrics.clone(),
+                        DrainReason::LeftSubnet,
+                    ),
+                    &self.rt,
+                );
                 false
             } else {
                 true
             }
         });
         self.metrics.peer_map_size.set(peer_map.len() as i64);
+
+        // Connections established with rotated certificates are drained the same way, the
+        // peers reconnect with the current certificates once the connections are closed. They
+        // are queued here and cycled a few at a time, see `cycle_outdated_connections`.
+        if self.topology.latest_registry_version() > previous_registry_version {
+            self.cert_rotation.check_connections(
+                self.topology.latest_registry_version(),
+                peer_map
+                    .iter()
+                    .map(|(peer_id, conn_handle)| (peer_id, &conn_handle.connection)),
+            );
+        }
+    }
+
+    /// Drains up to `MAX_CYCLED_CONNECTIONS_PER_INTERVAL` of the connections that were
+    /// established with rotated certificates.
+    fn cycle_outdated_connections(&mut self) {
+        let mut peer_map = self.peer_map.write().unwrap();
+        let mut cycled = 0;
+        while cycled < MAX_CYCLED_CONNECTIONS_PER_INTERVAL {
+            let Some((peer_id, stable_id)) = self.cert_rotation.pop_outdated() else {
+                break;
+            };
+            // The peer reconnected or left the subnet since the connection was queued.
+            let is_queued_connection = peer_map
+                .get(&peer_id)
+                .is_some_and(|conn_handle| conn_handle.connection.stable_id() == stable_id);
+            if !is_queued_connection {
+                continue;
+            }
+            let conn_handle = peer_map.remove(&peer_id).expect("Peer is in the peer map");
+            info!(
+                self.log,
+                "Cycling connection to peer {} whose TLS certificate rotated", peer_id
+            );
+            self.metrics.cert_rotation_cycled_connections_total.inc();
+            self.draining_connections.spawn_on(
+                drain_connection(
+                    conn_handle.connection.clone(),
+                    conn_handle.inflight(),
+                    self.drain_grace_period,
+                    self.metrics.clone(),
+                    DrainReason::CertRotated,
+                ),
+                &self.rt,
+            );
+            cycled += 1;
+        }
+        self.metrics.peer_map_size.set(peer_map.len() as i64);
     }
 
     fn handle_dial(&mut self, peer_id: NodeId) {
@@ -500,14 +823,53 @@ impl ConnectionManager {
         {
             return;
         }
+        // Quarantined peers are dialed once the quarantine ended.
+        if let Some(remaining) = self
+            .quarantine
+            .remaining(&peer_id, tokio::time::Instant::now())
+        {
+            self.connect_queue.insert(peer_id, remaining);
+            return;
+        }
 
-        info!(self.log, "Connecting to node {}", peer_id);
+        info_sampled!(
+            self.log_sampler,
+            EventClass::ConnectionLifecycle,
+            self.log,
+            "Connecting to node {}",
+            peer_id
+        );
         self.metrics.outbound_connection_total.inc();
-        let addr = self
+        let peer_addr = self
             .topology
             .get_addr(&peer_id)
             .expect("Just checked this conditions");
-        let handshaker = self.sev_handshake.clone();
+        // The local address can change on rebind, so the address family is checked on every dial.
+        let Some(addr) = self
+            .endpoint
+            .local_addr()
+            .ok()
+            .and_then(|local_addr| dial_addr(local_addr, self.dual_stack, peer_addr))
+        else {
+            self.metrics
+                .connection_results_total
+                .with_label_values(&[CONNECTION_RESULT_FAILED_LABEL])
+                .inc();
+            self.connect_queue.insert(peer_id, CONNECT_RETRY_BACKOFF);
+            info_sampled!(
+                self.log_sampler,
+                EventClass::ConnectionLifecycle,
+                self.log,
+                "Address {} of peer {} is not reachable from the local socket",
+                peer_addr,
+                peer_id
+            );
+            return;
+        };
+        let attestation = self.attestation.clone();
+        let announce_features = self.announce_features;
+        let compression_enabled = self.compression.is_some();
+        let protocol = self.protocol;
         let endpoint = self.endpoint.clone();
         let client_config = self
             .tls_config
@@ -529,18 +891,28 @@ impl ConnectionManager {
 
             // Authentication handshakes
             let connection = Self::attestation_handshake(
-                handshaker,
+                attestation,
                 peer_id,
                 latest_registry_version,
                 established,
                 Direction::Outbound,
             )
             .await?;
-            let connection = Self::gruezi(connection, Direction::Outbound).await?;
+            let (connection, peer_supports_compression, protocol, wire_format) = Self::gruezi(
+                connection,
+                Direction::Outbound,
+                announce_features,
+                compression_enabled,
+                protocol,
+            )
+            .await?;
 
             Ok::<_, ConnectionEstablishError>(ConnectionWithPeerId {
                 peer_id,
                 connection,
+                peer_supports_compression,
+                protocol,
+                wire_format,
             })
         };
 
@@ -568,7 +940,25 @@ impl ConnectionManager {
             Ok(ConnectionWithPeerId {
                 peer_id,
                 connection,
+                peer_supports_compression,
+                protocol,
+                wire_format,
             }) => {
+                // The identity of the peer is only known after the handshake, so connections
+                // of quarantined peers are closed only now.
+                if let Some(remaining) = self
+                    .quarantine
+                    .remaining(&peer_id, tokio::time::Instant::now())
+                {
+                    info!(
+                        self.log,
+                        "Closing connection to quarantined peer {}", peer_id
+                    );
+                    self.metrics.quarantine_refused_connections_total.inc();
+                    connection.close(QUARANTINED_ERROR_CODE, b"peer is quarantined");
+                    self.connect_queue.insert(peer_id, remaining);
+                    return;
+                }
                 self.metrics
                     .connection_results_total
                     .with_label_values(&[CONNECTION_RESULT_SUCCESS_LABEL])
@@ -581,9 +971,47 @@ impl ConnectionManager {
                 self.conn_id_counter.inc_assign();
                 let conn_id = self.conn_id_counter;
 
-                let connection_handle =
-                    ConnectionHandle::new(peer_id, connection, self.metrics.clone(), conn_id);
+                // Requests and responses to the peer share the limit.
+                let rate_limiter = self.rate_limit.map(|config| {
+                    Arc::new(RateLimiter::new(
+                        config,
+                        self.metrics.rate_limit_throttled_bytes_total.clone(),
+                    ))
+                });
+                let compression = self.compression.filter(|_| peer_supports_compression);
+                let connection_handle = ConnectionHandle::new(
+                    peer_id,
+                    connection,
+                    self.metrics.clone(),
+                    conn_id,
+                    self.max_datagram_size,
+                    self.stream_priorities.clone(),
+                    self.push_queue_capacity,
+                    self.max_inflight_rpcs_per_peer,
+                    &self.traffic_classes,
+                    rate_limiter.clone(),
+                    self.outgoing_layers.clone(),
+                    self.ordered_lanes.clone(),
+                    compression,
+                    protocol,
+                    wire_format,
+                );
                 let req_handler_connection_handle = connection_handle.clone();
+                let inflight = connection_handle.inflight();
+                let health_check = self.health_check.map(|config| {
+                    run_health_check(self.log.clone(), connection_handle.clone(), config)
+                });
+
+                // The peer learns the subscriptions of this node again on each new connection.
+                let announcement = self.pubsub.announcement();
+                let announcing_connection_handle = connection_handle.clone();
+                self.rt.spawn(async move {
+                    let _ = tokio::time::timeout(
+                        ANNOUNCEMENT_TIMEOUT,
+                        announcing_connection_handle.push(announcement),
+                    )
+                    .await;
+                });
 
                 // dropping the old connection will result in closing it
                 if let Some(old_conn) = peer_map_mut.insert(peer_id, connection_handle) {
@@ -602,16 +1030,38 @@ impl ConnectionManager {
                     self.log,
                     "Spawning request handler for peer : {:?}", peer_id
                 );
+                let stream_acceptor = run_stream_acceptor(
+                    self.log.clone(),
+                    req_handler_connection_handle.peer_id,
+                    req_handler_connection_handle.conn_id(),
+                    req_handler_connection_handle.connection,
+                    self.metrics.clone(),
+                    self.log_sampler.clone(),
+                    self.router.clone(),
+                    self.max_datagram_size,
+                    self.max_inflight_datagrams_per_peer,
+                    self.stream_priorities.clone(),
+                    rate_limiter,
+                    compression,
+                    wire_format,
+                    self.message_size_limits.clone(),
+                    inflight,
+                );
+                // The connection is closed if the health check fails, which also stops the
+                // stream acceptor.
                 self.active_connections.spawn_on(
                     peer_id,
-                    run_stream_acceptor(
-                        self.log.clone(),
-                        req_handler_connection_handle.peer_id,
-                        req_handler_connection_handle.conn_id(),
-                        req_handler_connection_handle.connection,
-                        self.metrics.clone(),
-                        self.router.clone(),
-                    ),
+                    async move {
+                        match health_check {
+                            Some(health_check) => {
+                                select! {
+                                    () = stream_acceptor => {},
+                                    () = health_check => {},
+                                }
+                            }
+                            None => stream_acceptor.await,
+                        }
+                    },
                     &self.rt,
                 );
             }
@@ -624,14 +1074,23 @@ impl ConnectionManager {
                 if let Some(peer_id) = peer_id {
                     self.connect_queue.insert(peer_id, CONNECT_RETRY_BACKOFF);
                 }
-                info!(self.log, "Failed to connect {}", err);
+                info_sampled!(
+                    self.log_sampler,
+                    EventClass::ConnectionLifecycle,
+                    self.log,
+                    "Failed to connect {}",
+                    err
+                );
             }
         };
     }
 
     fn handle_inbound(&mut self, connecting: Connecting) {
         self.metrics.inbound_connection_total.inc();
-        let handshaker = self.sev_handshake.clone();
+        let attestation = self.attestation.clone();
+        let announce_features = self.announce_features;
+        let compression_enabled = self.compression.is_some();
+        let protocol = self.protocol;
         let node_id = self.node_id;
         let last_registry_version = self.topology.latest_registry_version();
         let conn_fut = async move {
@@ -668,18 +1127,28 @@ impl ConnectionManager {
 
             // Authentication handshakes
             let connection = Self::attestation_handshake(
-                handshaker,
+                attestation,
                 peer_id,
                 last_registry_version,
                 established,
                 Direction::Inbound,
             )
             .await?;
-            let connection = Self::gruezi(connection, Direction::Inbound).await?;
+            let (connection, peer_supports_compression, protocol, wire_format) = Self::gruezi(
+                connection,
+                Direction::Inbound,
+                announce_features,
+                compression_enabled,
+                protocol,
+            )
+            .await?;
 
             Ok::<_, ConnectionEstablishError>(ConnectionWithPeerId {
                 peer_id,
                 connection,
+                peer_supports_compression,
+                protocol,
+                wire_format,
             })
         };
 
@@ -694,7 +1163,7 @@ impl ConnectionManager {
     }
 
     async fn attestation_handshake(
-        sev_handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
+        attestation: Attestation,
         peer_id: NodeId,
         registry_version: RegistryVersion,
         conn: Connection,
@@ -713,8 +1182,8 @@ impl ConnectionManager {
             ),
         };
 
-        sev_handshake
-            .perform_attestation_validation(Box::new(read_write), peer_id, registry_version)
+        attestation
+            .attest(Box::new(read_write), peer_id, registry_version)
             .await
             .map_err(|e| ConnectionEstablishError::SevAttestation(e.to_string()))?;
         Ok(conn)
@@ -724,32 +1193,35 @@ impl ConnectionManager {
     // of the other peer. It can can happen that one side assumes that the connection
     // is fully established when the other peer may still reject the connection. This
     // handshake makes sure that connection is fully functional.
+    // Peers also announce optional features after the greeting, separated by spaces. Returns
+    // whether the peer supports compression, the protocol negotiated with the peer and the
+    // layout of requests the peer understands.
+    // Transport versions without announcements expect the plain greeting. The accepting peer
+    // greets first and only announces its features if `announce_features` is set, and the
+    // dialing peer only announces its features if the accepting peer did.
     async fn gruezi(
         conn: Connection,
         direction: Direction,
-    ) -> Result<Connection, ConnectionEstablishError> {
-        match direction {
+        announce_features: bool,
+        compression_enabled: bool,
+        protocol: ProtocolVersion,
+    ) -> Result<(Connection, bool, ProtocolVersion, WireFormat), ConnectionEstablishError> {
+        let data = match direction {
             Direction::Inbound => {
                 let (mut send, mut recv) = conn
                     .open_bi()
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
-                send.write_all(GRUEZI_HANDSHAKE.as_bytes())
+                let greeting = gruezi_greeting(announce_features, compression_enabled, protocol);
+                send.write_all(greeting.as_bytes())
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
                 send.finish()
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
-                let data = recv
-                    .read_to_end(GRUEZI_HANDSHAKE.len())
+                recv.read_to_end(MAX_GRUEZI_HANDSHAKE_LEN)
                     .await
-                    .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
-                if data != GRUEZI_HANDSHAKE.as_bytes() {
-                    return Err(ConnectionEstablishError::Gruezi(format!(
-                        "Handshake failed unexpected response: {}",
-                        String::from_utf8_lossy(&data)
-                    )));
-                }
+                    .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?
             }
             Direction::Outbound => {
                 let (mut send, mut recv) = conn
@@ -757,25 +1229,68 @@ impl ConnectionManager {
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
                 let data = recv
-                    .read_to_end(GRUEZI_HANDSHAKE.len())
+                    .read_to_end(MAX_GRUEZI_HANDSHAKE_LEN)
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
-                if data != GRUEZI_HANDSHAKE.as_bytes() {
-                    return Err(ConnectionEstablishError::Gruezi(format!(
-                        "Handshake failed unexpected response: {}",
-                        String::from_utf8_lossy(&data)
-                    )));
-                }
-                send.write_all(GRUEZI_HANDSHAKE.as_bytes())
+                let peer_announced = !gruezi_features(&data)?.is_empty();
+                let greeting = gruezi_greeting(peer_announced, compression_enabled, protocol);
+                send.write_all(greeting.as_bytes())
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
                 send.finish()
                     .await
                     .map_err(|e| ConnectionEstablishError::Gruezi(e.to_string()))?;
+                data
             }
         };
-        Ok(conn)
+
+        let features = gruezi_features(&data)?;
+        let peer_supports_compression = features
+            .split_whitespace()
+            .any(|feature| feature == ZSTD_ENCODING);
+        let peer_protocol = ProtocolVersion::decode(features.split_whitespace());
+        // Peers that announce nothing predate the announcements, and with them request headers.
+        let wire_format = if features.is_empty() {
+            WireFormat::Legacy
+        } else {
+            WireFormat::Extended
+        };
+        Ok((
+            conn,
+            peer_supports_compression,
+            protocol.negotiate(&peer_protocol),
+            wire_format,
+        ))
+    }
+}
+
+/// Greeting of the gruezi handshake, followed by the features of this node if they are announced.
+fn gruezi_greeting(announce: bool, compression_enabled: bool, protocol: ProtocolVersion) -> String {
+    let mut greeting = GRUEZI_HANDSHAKE.to_string();
+    if !announce {
+        return greeting;
+    }
+    if compression_enabled {
+        greeting.push(' ');
+        greeting.push_str(ZSTD_ENCODING);
     }
+    greeting.push(' ');
+    greeting.push_str(&protocol.encode());
+    greeting
+}
+
+/// Features announced after the greeting, empty if the peer did not announce any.
+fn gruezi_features(data: &[u8]) -> Result<&str, ConnectionEstablishError> {
+    data.strip_prefix(GRUEZI_HANDSHAKE.as_bytes())
+        .and_then(|features| std::str::from_utf8(features).ok())
+        .filter(|features| features.is_empty() || features.starts_with(' '))
+        .map(str::trim)
+        .ok_or_else(|| {
+            ConnectionEstablishError::Gruezi(format!(
+                "Handshake failed unexpected response: {}",
+                String::from_utf8_lossy(data)
+            ))
+        })
 }
 
 struct HandshakeReadWrite {
@@ -825,3 +1340,47 @@ impl AsyncWrite for HandshakeReadWrite {
         AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dial_addr_matches_local_address_family() {
+        let v4_local: SocketAddr = "10.0.0.1:4100".parse().unwrap();
+        let v6_local: SocketAddr = "[::]:4100".parse().unwrap();
+        let v4_peer: SocketAddr = "10.0.0.2:4100".parse().unwrap();
+        let v6_peer: SocketAddr = "[2001:db8::2]:4100".parse().unwrap();
+        let mapped_peer: SocketAddr = "[::ffff:10.0.0.2]:4100".parse().unwrap();
+
+        assert_eq!(dial_addr(v4_local, true, v4_peer), Some(v4_peer));
+        assert_eq!(dial_addr(v6_local, true, v6_peer), Some(v6_peer));
+        assert_eq!(dial_addr(v6_local, true, v4_peer), Some(mapped_peer));
+        assert_eq!(dial_addr(v6_local, false, v4_peer), None);
+        assert_eq!(dial_addr(v4_local, true, v6_peer), None);
+        assert_eq!(dial_addr(v4_local, true, mapped_peer), Some(v4_peer));
+    }
+
+    #[test]
+    fn features_are_only_announced_if_enabled() {
+        let protocol = ProtocolVersion {
+            version: 1,
+            features: 0b1,
+        };
+        // Transport versions without announcements expect exactly this greeting.
+        assert_eq!(gruezi_greeting(false, true, protocol), GRUEZI_HANDSHAKE);
+        assert_eq!(gruezi_features(GRUEZI_HANDSHAKE.as_bytes()).unwrap(), "");
+
+        let greeting = gruezi_greeting(true, true, protocol);
+        let features = gruezi_features(greeting.as_bytes()).unwrap();
+        assert!(features
+            .split_whitespace()
+            .any(|feature| feature == ZSTD_ENCODING));
+        assert_eq!(
+            ProtocolVersion::decode(features.split_whitespace()),
+            protocol
+        );
+
+        assert!(gruezi_features(b"grueziX").is_err());
+    }
+}
@@ -0,0 +1,87 @@
+//! Quic Transport request deadlines.
+//!
+//! Callers can attach a `Deadline` to a request as an extension. Transport then
+//!  - aborts the stream on the sending side if the deadline expires before the response
+//!    arrived, in which case the request fails with `SendError::Timeout`.
+//!  - sends the remaining time in the `TIMEOUT_HEADER` header. Clocks of peers are not
+//!    synchronized, so the peer converts the remaining time back into a local deadline when
+//!    it receives the request.
+//!  - surfaces the deadline to the handler on the receiving side as a `Deadline` extension,
+//!    so that it can stop working on requests the caller has given up on.
+//!
+use std::time::Duration;
+
+use axum::http::{HeaderValue, Request};
+use tokio::time::Instant;
+
+/// Header that carries the remaining time of a request in milliseconds.
+pub(crate) const TIMEOUT_HEADER: &str = "x-ic-timeout-ms";
+
+/// Point in time after which the caller of a request is no longer interested in the response.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    /// Time until the deadline expires, zero if it already expired.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.0 <= Instant::now()
+    }
+}
+
+/// Encodes the deadline extension of an outgoing request, if any, as a header.
+pub(crate) fn encode_deadline<B>(request: &mut Request<B>) -> Option<Deadline> {
+    let deadline = request.extensions().get::<Deadline>().copied()?;
+    request.headers_mut().insert(
+        TIMEOUT_HEADER,
+        HeaderValue::from(deadline.remaining().as_millis() as u64),
+    );
+    Some(deadline)
+}
+
+/// Turns the header of an incoming request, if any, into a deadline extension.
+pub(crate) fn decode_deadline<B>(request: &mut Request<B>) {
+    let Some(timeout_ms) = request
+        .headers_mut()
+        .remove(TIMEOUT_HEADER)
+        .and_then(|value| value.to_str().ok()?.parse::<u64>().ok())
+    else {
+        return;
+    };
+    request
+        .extensions_mut()
+        .insert(Deadline::after(Duration::from_millis(timeout_ms)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn deadline_survives_encoding() {
+        let mut request = Request::new(());
+        assert_eq!(encode_deadline(&mut request), None);
+
+        let deadline = Deadline::after(Duration::from_secs(10));
+        request.extensions_mut().insert(deadline);
+        assert_eq!(encode_deadline(&mut request), Some(deadline));
+
+        // The receiving side only sees the header.
+        let (parts, body) = request.into_parts();
+        let mut received = Request::new(body);
+        *received.headers_mut() = parts.headers;
+        decode_deadline(&mut received);
+
+        let received_deadline = received.extensions().get::<Deadline>().unwrap();
+        assert!(received_deadline.remaining() <= Duration::from_secs(10));
+        assert!(received_deadline.remaining() > Duration::from_secs(9));
+        assert!(received.headers().get(TIMEOUT_HEADER).is_none());
+    }
+}
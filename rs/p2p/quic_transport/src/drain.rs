@@ -0,0 +1,146 @@
+//! Quic Transport draining of connections to peers that left the topology.
+//!
+//! Closing a connection aborts all of its streams, so requests that are in flight when a peer
+//! leaves the subnet would fail mid-way. Instead transport drains such connections:
+//!  - The connection is removed from the peer map, so no new requests are sent to the peer.
+//!  - Requests this node sent, and requests of the peer this node is still handling, get
+//!    `drain_grace_period` to complete. Channels stay open until the grace period elapsed.
+//!  - Afterwards the connection is closed with the error code of the `DrainReason`, so the peer
+//!    can tell draining apart from other reasons of closing.
+//! Connections established with rotated TLS certificates are drained the same way, see
+//! cert_rotation.rs.
+//!
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use quinn::{Connection, VarInt};
+use tokio::sync::Notify;
+
+use crate::metrics::{QuicTransportMetrics, DRAIN_RESULT_COMPLETED, DRAIN_RESULT_TIMED_OUT};
+
+/// Application error code of connections that were closed after draining.
+pub(crate) const DRAINED_ERROR_CODE: VarInt = VarInt::from_u32(1);
+
+/// Application error code of drained connections that were established with a rotated TLS
+/// certificate.
+pub(crate) const CERT_ROTATED_ERROR_CODE: VarInt = VarInt::from_u32(2);
+
+/// Why a connection is drained.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum DrainReason {
+    /// The peer or this node is not part of the subnet anymore.
+    LeftSubnet,
+    /// The connection was established with a TLS certificate that was rotated since. The peers
+    /// reconnect with the current certificates.
+    CertRotated,
+}
+
+impl DrainReason {
+    /// Error code and reason the connection is closed with.
+    fn close_reason(self) -> (VarInt, &'static [u8]) {
+        match self {
+            DrainReason::LeftSubnet => (DRAINED_ERROR_CODE, b"node not part of subnet anymore"),
+            DrainReason::CertRotated => (CERT_ROTATED_ERROR_CODE, b"tls certificate rotated"),
+        }
+    }
+}
+
+/// Counts the requests in progress on a connection.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct InflightRequests {
+    count: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+}
+
+/// Marks a request as in progress until dropped.
+pub(crate) struct InflightGuard(InflightRequests);
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+impl InflightRequests {
+    pub(crate) fn start(&self) -> InflightGuard {
+        self.count.fetch_add(1, Ordering::AcqRel);
+        InflightGuard(self.clone())
+    }
+
+    /// Marks the request as in progress until the future completes.
+    pub(crate) fn track<F: Future>(&self, request: F) -> impl Future<Output = F::Output> {
+        let guard = self.start();
+        async move {
+            let _guard = guard;
+            request.await
+        }
+    }
+
+    /// Waits until no request is in progress.
+    pub(crate) async fn wait_idle(&self) {
+        loop {
+            let notified = self.idle.notified();
+            tokio::pin!(notified);
+            // Registers for notifications before checking the count, so none is missed.
+            notified.as_mut().enable();
+            if self.count.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Closes the connection once the in-flight requests completed or the grace period elapsed.
+pub(crate) async fn drain_connection(
+    connection: Connection,
+    inflight: InflightRequests,
+    grace_period: Duration,
+    metrics: QuicTransportMetrics,
+    reason: DrainReason,
+) {
+    let result = match tokio::time::timeout(grace_period, inflight.wait_idle()).await {
+        Ok(()) => DRAIN_RESULT_COMPLETED,
+        Err(_) => DRAIN_RESULT_TIMED_OUT,
+    };
+    metrics
+        .drained_connections_total
+        .with_label_values(&[result])
+        .inc();
+    let (error_code, reason) = reason.close_reason();
+    connection.close(error_code, reason);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_inflight_requests() {
+        let inflight = InflightRequests::default();
+        inflight.wait_idle().await;
+
+        let first = inflight.start();
+        let second = inflight.start();
+        let waiter = tokio::spawn({
+            let inflight = inflight.clone();
+            async move { inflight.wait_idle().await }
+        });
+        drop(first);
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+        drop(second);
+        tokio::time::timeout(Duration::from_secs(5), waiter)
+            .await
+            .expect("Drain did not complete")
+            .unwrap();
+    }
+}
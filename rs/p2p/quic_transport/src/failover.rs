@@ -0,0 +1,101 @@
+//! Quic Transport failover between local addresses.
+//!
+//! Replicas with redundant uplinks can configure the addresses of their other NICs. QUIC
+//! connections use a single path at a time, so transport fails over instead of using the
+//! paths concurrently:
+//!  - If the node is disconnected from all peers for longer than `timeout`, the endpoint is
+//!    rebound to the next address, see `QuicTransport::rebind`. Established connections
+//!    migrate to it and new connections are dialed from it.
+//!  - Addresses are tried in order, starting after the address transport was started with,
+//!    and wrap around. Each address gets `timeout` to connect to a peer.
+//!  - A node without peers in the topology is not considered disconnected.
+//!
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::time::Instant;
+
+/// Local addresses to fail over to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FailoverConfig {
+    /// Addresses of other interfaces of the node.
+    pub addrs: Vec<SocketAddr>,
+    /// Time without any connected peer after which transport moves to the next address.
+    pub timeout: Duration,
+}
+
+#[derive(Debug)]
+pub(crate) struct Failover {
+    /// Addresses in the order they are tried.
+    addrs: Vec<SocketAddr>,
+    next: usize,
+    timeout: Duration,
+    disconnected_since: Option<Instant>,
+}
+
+impl Failover {
+    /// `primary` is the address transport was started with, if it bound the socket itself.
+    pub(crate) fn new(config: FailoverConfig, primary: Option<SocketAddr>) -> Self {
+        let addrs: Vec<_> = primary.into_iter().chain(config.addrs).collect();
+        Self {
+            // The primary address is in use already.
+            next: usize::from(primary.is_some()),
+            addrs,
+            timeout: config.timeout,
+            disconnected_since: None,
+        }
+    }
+
+    /// Called periodically with whether the node is connected. Returns the address to rebind
+    /// to, if it is time to fail over.
+    pub(crate) fn poll(&mut self, connected: bool, now: Instant) -> Option<SocketAddr> {
+        if connected || self.addrs.is_empty() {
+            self.disconnected_since = None;
+            return None;
+        }
+        let disconnected_since = *self.disconnected_since.get_or_insert(now);
+        if now.duration_since(disconnected_since) < self.timeout {
+            return None;
+        }
+        // The next address gets the full timeout as well.
+        self.disconnected_since = Some(now);
+        let addr = self.addrs[self.next % self.addrs.len()];
+        self.next = self.next.wrapping_add(1);
+        Some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fails_over_in_order_after_timeout() {
+        let primary: SocketAddr = "10.0.0.1:4100".parse().unwrap();
+        let backup: SocketAddr = "10.1.0.1:4100".parse().unwrap();
+        let timeout = Duration::from_secs(10);
+        let mut failover = Failover::new(
+            FailoverConfig {
+                addrs: vec![backup],
+                timeout,
+            },
+            Some(primary),
+        );
+
+        let start = Instant::now();
+        assert_eq!(failover.poll(false, start), None);
+        assert_eq!(failover.poll(false, start + timeout / 2), None);
+        // Reconnecting resets the timeout.
+        assert_eq!(failover.poll(true, start + timeout / 2), None);
+        assert_eq!(failover.poll(false, start + timeout), None);
+        assert_eq!(
+            failover.poll(false, start + timeout + timeout / 2),
+            Some(backup)
+        );
+        assert_eq!(failover.poll(false, start + 2 * timeout), None);
+        // Wraps around to the primary address.
+        assert_eq!(
+            failover.poll(false, start + 2 * timeout + timeout / 2),
+            Some(primary)
+        );
+    }
+}
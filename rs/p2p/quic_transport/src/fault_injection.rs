@@ -0,0 +1,237 @@
+//! Fault injection for tests of transport users.
+//!
+//! `FaultyTransport` wraps any `Transport` and injects failures into `rpc` and `push` according
+//! to a `FaultPolicy`, e.g. to test retry logic. Each fault is injected independently with its
+//! probability. The random numbers are drawn from an RNG seeded with `FaultPolicy::seed`, so
+//! runs with the same seed and the same sequence of requests inject the same faults.
+//!  - Dropped requests do not reach the peer. Rpcs fail with `SendError::Timeout`, pushes
+//!    succeed as if the request was lost on the way.
+//!  - Dropped responses: the request reaches the peer, but the rpc fails with
+//!    `SendError::Timeout`.
+//!  - Delayed requests are sent after a random delay of at most `max_delay`.
+//!  - Duplicated requests reach the peer twice. The caller sees the result of the first one.
+//!  - Corrupted requests and responses have a random byte of their body flipped.
+//!
+use std::{sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use axum::http::{Request, Response};
+use bytes::Bytes;
+use ic_base_types::NodeId;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{ConnId, PeerStats, ProtocolVersion, SendError, Transport};
+
+/// Probabilities of the faults `FaultyTransport` injects, between 0 and 1.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FaultPolicy {
+    pub drop_request: f64,
+    pub drop_response: f64,
+    pub delay: f64,
+    /// Upper bound on the delay of delayed requests.
+    pub max_delay: Duration,
+    pub duplicate: f64,
+    pub corrupt_request: f64,
+    pub corrupt_response: f64,
+    pub seed: u64,
+}
+
+/// Faults injected into a single request.
+#[derive(Debug, Default)]
+struct Faults {
+    drop_request: bool,
+    drop_response: bool,
+    delay: Option<Duration>,
+    duplicate: bool,
+    corrupt_request: Option<usize>,
+    corrupt_response: bool,
+}
+
+pub struct FaultyTransport<T> {
+    inner: T,
+    policy: FaultPolicy,
+    rng: Mutex<StdRng>,
+}
+
+impl<T: Transport> FaultyTransport<T> {
+    pub fn new(inner: T, policy: FaultPolicy) -> Self {
+        Self {
+            inner,
+            rng: Mutex::new(StdRng::seed_from_u64(policy.seed)),
+            policy,
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Draws the faults of a request with the given body length.
+    fn draw(&self, body_len: usize) -> Faults {
+        let mut rng = self.rng.lock().unwrap();
+        let mut happens = |probability: f64| rng.gen_bool(probability.clamp(0.0, 1.0));
+        let mut faults = Faults {
+            drop_request: happens(self.policy.drop_request),
+            drop_response: happens(self.policy.drop_response),
+            duplicate: happens(self.policy.duplicate),
+            corrupt_response: happens(self.policy.corrupt_response),
+            ..Default::default()
+        };
+        let delay = happens(self.policy.delay);
+        let corrupt_request = happens(self.policy.corrupt_request) && body_len > 0;
+        if delay {
+            faults.delay = Some(self.policy.max_delay.mul_f64(rng.gen()));
+        }
+        if corrupt_request {
+            faults.corrupt_request = Some(rng.gen_range(0..body_len));
+        }
+        faults
+    }
+
+    fn corrupt_response(&self, response: &mut Response<Bytes>) {
+        if response.body().is_empty() {
+            return;
+        }
+        let index = self.rng.lock().unwrap().gen_range(0..response.body().len());
+        *response.body_mut() = flip_byte(response.body(), index);
+    }
+
+    /// Applies the faults that happen before the request reaches the peer. Returns `None` if
+    /// the request is dropped.
+    async fn prepare(
+        &self,
+        faults: &Faults,
+        mut request: Request<Bytes>,
+    ) -> Option<Request<Bytes>> {
+        if let Some(delay) = faults.delay {
+            tokio::time::sleep(delay).await;
+        }
+        if faults.drop_request {
+            return None;
+        }
+        if let Some(index) = faults.corrupt_request {
+            *request.body_mut() = flip_byte(request.body(), index);
+        }
+        Some(request)
+    }
+}
+
+fn flip_byte(body: &Bytes, index: usize) -> Bytes {
+    let mut body = body.to_vec();
+    body[index] ^= 0xff;
+    Bytes::from(body)
+}
+
+#[async_trait]
+impl<T: Transport> Transport for FaultyTransport<T> {
+    async fn rpc(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<Response<Bytes>, SendError> {
+        let faults = self.draw(request.body().len());
+        let Some(request) = self.prepare(&faults, request).await else {
+            return Err(SendError::Timeout);
+        };
+
+        let duplicate = faults.duplicate.then(|| request.clone());
+        let result = self.inner.rpc(peer_id, request).await;
+        if let Some(duplicate) = duplicate {
+            let _ = self.inner.rpc(peer_id, duplicate).await;
+        }
+
+        let mut response = result?;
+        if faults.drop_response {
+            return Err(SendError::Timeout);
+        }
+        if faults.corrupt_response {
+            self.corrupt_response(&mut response);
+        }
+        Ok(response)
+    }
+
+    async fn push(&self, peer_id: &NodeId, request: Request<Bytes>) -> Result<(), SendError> {
+        let faults = self.draw(request.body().len());
+        let Some(request) = self.prepare(&faults, request).await else {
+            return Ok(());
+        };
+
+        let duplicate = faults.duplicate.then(|| request.clone());
+        let result = self.inner.push(peer_id, request).await;
+        if let Some(duplicate) = duplicate {
+            let _ = self.inner.push(peer_id, duplicate).await;
+        }
+        result
+    }
+
+    fn peers(&self) -> Vec<(NodeId, ConnId)> {
+        self.inner.peers()
+    }
+
+    fn peer_stats(&self, peer_id: &NodeId) -> Option<PeerStats> {
+        self.inner.peer_stats(peer_id)
+    }
+
+    fn peer_protocol(&self, peer_id: &NodeId) -> Option<ProtocolVersion> {
+        self.inner.peer_protocol(peer_id)
+    }
+
+    fn peer_inflight_rpcs(&self, peer_id: &NodeId) -> Option<usize> {
+        self.inner.peer_inflight_rpcs(peer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemoryTransportPair;
+    use axum::{routing::any, Router};
+    use ic_base_types::PrincipalId;
+
+    fn node(id: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(id))
+    }
+
+    fn echo_pair() -> InMemoryTransportPair {
+        let echo = Router::new().route("/", any(|body: Bytes| async move { body }));
+        InMemoryTransportPair::new((node(1), echo.clone()), (node(2), echo))
+    }
+
+    #[tokio::test]
+    async fn faults_are_injected_according_to_policy() {
+        let transport = FaultyTransport::new(echo_pair().a, FaultPolicy::default());
+        let response = transport
+            .rpc(&node(2), Request::new(Bytes::from_static(b"ping")))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "ping");
+
+        let transport = FaultyTransport::new(
+            echo_pair().a,
+            FaultPolicy {
+                drop_request: 1.0,
+                ..Default::default()
+            },
+        );
+        assert!(matches!(
+            transport
+                .rpc(&node(2), Request::new(Bytes::from_static(b"ping")))
+                .await,
+            Err(SendError::Timeout)
+        ));
+
+        let transport = FaultyTransport::new(
+            echo_pair().a,
+            FaultPolicy {
+                corrupt_request: 1.0,
+                ..Default::default()
+            },
+        );
+        let response = transport
+            .rpc(&node(2), Request::new(Bytes::from_static(b"ping")))
+            .await
+            .unwrap();
+        assert_ne!(response.body(), "ping");
+        assert_eq!(response.body().len(), 4);
+    }
+}
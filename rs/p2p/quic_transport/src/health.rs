@@ -0,0 +1,102 @@
+//! Quic Transport health checks of connections.
+//!
+//! QUIC keep-alives only show that the peer's endpoint acknowledges packets. A connection can
+//! still be half-dead, e.g. if a middlebox silently drops streams, and would then linger until
+//! the idle timeout. Transport therefore pings each peer with an rpc to `HEALTH_CHECK_URI`:
+//!  - Every connected peer is pinged each `interval`. Pings bypass the outgoing layers.
+//!  - Any response counts as healthy, so peers that do not serve the URI yet are not evicted.
+//!  - After `max_failures` consecutive pings that failed or took longer than `timeout`, the
+//!    connection is closed with `HEALTH_CHECK_FAILED_ERROR_CODE` and re-dialed like any other
+//!    broken connection.
+//!  - The consecutive failures and the latency of the last ping are exported per peer.
+//!
+use std::time::Duration;
+
+use ic_logger::{info, ReplicaLogger};
+use quinn::VarInt;
+use tokio::time::{Instant, MissedTickBehavior};
+
+use crate::connection_handle::ConnectionHandle;
+
+/// URI served by transport itself to answer pings of peers.
+pub(crate) const HEALTH_CHECK_URI: &str = "/_quic_transport/health";
+
+/// Application error code of connections that were closed because of failed health checks.
+pub(crate) const HEALTH_CHECK_FAILED_ERROR_CODE: VarInt = VarInt::from_u32(2);
+
+/// Pinging of connected peers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HealthCheckConfig {
+    /// Time between two pings of the same peer.
+    pub interval: Duration,
+    /// Time after which a ping counts as failed.
+    pub timeout: Duration,
+    /// Consecutive failed pings after which the connection is evicted.
+    pub max_failures: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(2),
+            max_failures: 3,
+        }
+    }
+}
+
+/// Pings the peer until the health check fails, in which case the connection is closed.
+pub(crate) async fn run_health_check(
+    log: ReplicaLogger,
+    conn_handle: ConnectionHandle,
+    config: HealthCheckConfig,
+) {
+    let peer_id_label: [&str; 1] = [&conn_handle.peer_id.to_string()];
+    let failures_gauge = conn_handle
+        .metrics
+        .health_check_consecutive_failures
+        .with_label_values(&peer_id_label);
+    let latency_gauge = conn_handle
+        .metrics
+        .health_check_latency_seconds
+        .with_label_values(&peer_id_label);
+
+    let mut ping_interval = tokio::time::interval(config.interval);
+    ping_interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut failures = 0;
+    loop {
+        ping_interval.tick().await;
+        let start = Instant::now();
+        match conn_handle.ping(config.timeout).await {
+            Ok(()) => {
+                failures = 0;
+                latency_gauge.set(start.elapsed().as_secs_f64());
+            }
+            Err(err) => {
+                failures += 1;
+                info!(
+                    log,
+                    "Health check {}/{} of peer {} failed: {}",
+                    failures,
+                    config.max_failures,
+                    conn_handle.peer_id,
+                    err
+                );
+            }
+        }
+        failures_gauge.set(i64::from(failures));
+
+        if failures >= config.max_failures {
+            info!(
+                log,
+                "Evicting connection to peer {} after failed health checks", conn_handle.peer_id
+            );
+            conn_handle.metrics.health_check_evictions_total.inc();
+            failures_gauge.set(0);
+            conn_handle
+                .connection
+                .close(HEALTH_CHECK_FAILED_ERROR_CODE, b"health check failed");
+            return;
+        }
+    }
+}
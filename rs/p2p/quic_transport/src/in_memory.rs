@@ -0,0 +1,233 @@
+//! In-memory transport for unit tests of transport users.
+//!
+//! `InMemoryNetwork` routes requests between in-process routers without sockets. Its transports
+//! implement `Transport` with the semantics of `QuicTransport` where it matters to handlers:
+//!  - Only the URI, headers and body of requests reach the peer. Handlers see the `NodeId`
+//!    of the sender and a `ConnId` as extensions, like with `QuicTransport`.
+//!  - Only the status and body of responses reach the caller. Responses carry the `NodeId`
+//!    of the peer as extension.
+//!  - `Deadline` and `TraceContext` extensions are propagated to the peer, and rpcs fail with
+//!    `SendError::Timeout` if their deadline expires.
+//!  - Requests to nodes that are not part of the network fail with
+//!    `SendError::PeerNotInTopology`, requests to nodes that are disconnected from the sender
+//!    with `SendError::ConnectionUnavailable`.
+//! Retries, compression, size limits, rate limits and channels are not modelled.
+//!
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, RwLock},
+};
+
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, Response},
+    Router,
+};
+use bytes::Bytes;
+use ic_base_types::NodeId;
+use tower::ServiceExt;
+
+use crate::{
+    deadline::{decode_deadline, encode_deadline},
+    trace_context::{decode_trace_context, encode_trace_context},
+    utils::MAX_MESSAGE_SIZE_BYTES,
+    ConnId, SendError, Transport,
+};
+
+#[derive(Default)]
+struct NetworkState {
+    routers: HashMap<NodeId, Router>,
+    /// Unordered pairs of nodes that cannot reach each other.
+    partitions: BTreeSet<(NodeId, NodeId)>,
+}
+
+impl NetworkState {
+    fn is_connected(&self, from: NodeId, to: NodeId) -> bool {
+        from != to
+            && self.routers.contains_key(&from)
+            && self.routers.contains_key(&to)
+            && !self.partitions.contains(&(from.min(to), from.max(to)))
+    }
+}
+
+/// Set of nodes that are connected to each other in memory.
+#[derive(Clone, Default)]
+pub struct InMemoryNetwork {
+    state: Arc<RwLock<NetworkState>>,
+}
+
+impl InMemoryNetwork {
+    /// Adds a node whose requests are handled by `router`, replacing any previous router of
+    /// the node, and returns the transport of the node.
+    pub fn add_node(&self, node_id: NodeId, router: Router) -> InMemoryTransport {
+        self.state.write().unwrap().routers.insert(node_id, router);
+        InMemoryTransport {
+            node_id,
+            network: self.clone(),
+        }
+    }
+
+    /// Removes the node. Requests to and from it fail afterwards.
+    pub fn remove_node(&self, node_id: &NodeId) {
+        self.state.write().unwrap().routers.remove(node_id);
+    }
+
+    /// Disconnects the two nodes from each other.
+    pub fn disconnect(&self, a: NodeId, b: NodeId) {
+        self.state
+            .write()
+            .unwrap()
+            .partitions
+            .insert((a.min(b), a.max(b)));
+    }
+
+    /// Reconnects two nodes that were disconnected with `disconnect`.
+    pub fn reconnect(&self, a: NodeId, b: NodeId) {
+        self.state
+            .write()
+            .unwrap()
+            .partitions
+            .remove(&(a.min(b), a.max(b)));
+    }
+
+    fn router(&self, from: NodeId, to: NodeId) -> Result<Router, SendError> {
+        let state = self.state.read().unwrap();
+        let router = state.routers.get(&to).ok_or(SendError::PeerNotInTopology)?;
+        if !state.is_connected(from, to) {
+            return Err(SendError::ConnectionUnavailable(
+                "Currently not connected to this peer".to_string(),
+            ));
+        }
+        Ok(router.clone())
+    }
+}
+
+/// Two transports that are connected to each other, for tests with only two nodes.
+pub struct InMemoryTransportPair {
+    pub network: InMemoryNetwork,
+    pub a: InMemoryTransport,
+    pub b: InMemoryTransport,
+}
+
+impl InMemoryTransportPair {
+    pub fn new(a: (NodeId, Router), b: (NodeId, Router)) -> Self {
+        let network = InMemoryNetwork::default();
+        Self {
+            a: network.add_node(a.0, a.1),
+            b: network.add_node(b.0, b.1),
+            network,
+        }
+    }
+}
+
+/// Transport of a single node of an `InMemoryNetwork`.
+#[derive(Clone)]
+pub struct InMemoryTransport {
+    node_id: NodeId,
+    network: InMemoryNetwork,
+}
+
+impl InMemoryTransport {
+    /// Sends the request to the router of the peer, as if it was sent over the wire.
+    async fn send(
+        &self,
+        peer_id: &NodeId,
+        mut request: Request<Bytes>,
+    ) -> Result<Response<Bytes>, SendError> {
+        let router = self.network.router(self.node_id, *peer_id)?;
+
+        let deadline = encode_deadline(&mut request);
+        encode_trace_context(&mut request);
+        let (parts, body) = request.into_parts();
+        let mut wire_request = Request::new(Body::from(body));
+        *wire_request.uri_mut() = parts.uri;
+        *wire_request.headers_mut() = parts.headers;
+        wire_request.extensions_mut().insert(self.node_id);
+        wire_request.extensions_mut().insert(ConnId::from(0));
+        decode_deadline(&mut wire_request);
+        decode_trace_context(&mut wire_request);
+
+        let exchange = async move {
+            let response = router.oneshot(wire_request).await.expect("Infallible");
+            let (parts, body) = response.into_parts();
+            axum::body::to_bytes(body, MAX_MESSAGE_SIZE_BYTES)
+                .await
+                .map(|body| (parts.status, body))
+                .map_err(|err| SendError::Internal(err.to_string()))
+        };
+        let (status, body) = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.0, exchange)
+                .await
+                .map_err(|_| SendError::Timeout)??,
+            None => exchange.await?,
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        response.extensions_mut().insert(*peer_id);
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Transport for InMemoryTransport {
+    async fn rpc(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<Response<Bytes>, SendError> {
+        self.send(peer_id, request).await
+    }
+
+    async fn push(&self, peer_id: &NodeId, request: Request<Bytes>) -> Result<(), SendError> {
+        self.send(peer_id, request).await.map(|_| ())
+    }
+
+    fn peers(&self) -> Vec<(NodeId, ConnId)> {
+        let state = self.network.state.read().unwrap();
+        state
+            .routers
+            .keys()
+            .filter(|peer_id| state.is_connected(self.node_id, **peer_id))
+            .map(|peer_id| (*peer_id, ConnId::from(0)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::any, Extension};
+    use ic_base_types::PrincipalId;
+
+    fn node(id: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(id))
+    }
+
+    #[tokio::test]
+    async fn requests_reach_the_router_of_the_peer() {
+        let echo_sender = Router::new().route(
+            "/",
+            any(|Extension(peer): Extension<NodeId>| async move { peer.to_string() }),
+        );
+        let pair =
+            InMemoryTransportPair::new((node(1), echo_sender.clone()), (node(2), echo_sender));
+
+        let response = pair
+            .a
+            .rpc(&node(2), Request::new(Bytes::new()))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), &node(1).to_string());
+        assert_eq!(response.extensions().get::<NodeId>(), Some(&node(2)));
+        assert_eq!(pair.a.peers(), vec![(node(2), ConnId::from(0))]);
+
+        pair.network.disconnect(node(2), node(1));
+        assert!(matches!(
+            pair.a.rpc(&node(2), Request::new(Bytes::new())).await,
+            Err(SendError::ConnectionUnavailable(_))
+        ));
+        assert!(pair.b.peers().is_empty());
+    }
+}
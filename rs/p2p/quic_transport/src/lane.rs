@@ -0,0 +1,117 @@
+//! Quic Transport ordered lanes.
+//!
+//! Each push is sent on its own stream, so pushes can overtake each other. Pushes to URIs that
+//! are registered as ordered lanes are instead delivered in the order they were sent:
+//!  - For each peer and URI, the first push opens a uni stream and writes `LANE_MAGIC` to it.
+//!    Since requests start with the length of their URI, this never happens for other pushes.
+//!  - All pushes to the URI are written to that stream as length delimited frames, in the
+//!    order in which `push` was called. Each frame is an encoded request.
+//!  - The peer calls the router with one request after the other, i.e. the handler of a
+//!    request completed before the next request is handled.
+//!  - Each request is subject to the size limit of its URI, which the peer checks before it
+//!    reads the rest of the frame. An oversized request stops the whole lane, see
+//!    message_size.rs.
+//!  - If writing to the stream fails, or the push is dropped while writing, the next push
+//!    opens a new stream. Requests that were written but not yet handled by the peer can be lost in this case.
+//! Only the sender decides which URIs are ordered, the peer handles any lane it accepts.
+//! Lanes are part of the extended wire format, so pushes to peers that did not announce
+//! features in the gruezi handshake are sent on their own streams, without ordering.
+//!
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex},
+};
+
+use axum::http::{Request, Uri};
+use bytes::Bytes;
+use futures::SinkExt;
+use quinn::{Connection, SendStream};
+use tokio_util::codec::{FramedWrite, LengthDelimitedCodec};
+
+use crate::{
+    channel::channel_codec,
+    priority::StreamPriority,
+    utils::{encode_request, WireFormat},
+    SendError,
+};
+
+pub(crate) const LANE_MAGIC: [u8; 8] = (u64::MAX - 1).to_le_bytes();
+
+/// URI paths of pushes that are delivered in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct OrderedLanes {
+    paths: BTreeSet<String>,
+}
+
+impl OrderedLanes {
+    /// Delivers pushes to `path` in order.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.paths.insert(path.into());
+        self
+    }
+
+    pub(crate) fn contains(&self, uri: &Uri) -> bool {
+        self.paths.contains(uri.path())
+    }
+}
+
+type LaneSink = FramedWrite<SendStream, LengthDelimitedCodec>;
+
+/// Open lanes of a connection, by URI path.
+#[derive(Debug, Default)]
+pub(crate) struct LaneSenders {
+    lanes: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<LaneSink>>>>>,
+}
+
+impl LaneSenders {
+    /// Writes the request to the lane of its URI, opening the lane if necessary. Concurrent
+    /// requests are written in the order they called `send`.
+    pub(crate) async fn send(
+        &self,
+        connection: &Connection,
+        priority: StreamPriority,
+        request: Request<Bytes>,
+        wire_format: WireFormat,
+    ) -> Result<(), SendError> {
+        let lane = self
+            .lanes
+            .lock()
+            .unwrap()
+            .entry(request.uri().path().to_string())
+            .or_default()
+            .clone();
+        // The lock of tokio is fair, so waiting requests acquire it in order.
+        let mut lane = lane.lock().await;
+
+        let frame = encode_request(request, wire_format)?;
+        // The sink is taken out while a frame is written, so that the lane is reopened if the
+        // caller drops the push before the frame was written completely.
+        let mut sink = match lane.take() {
+            Some(sink) => sink,
+            None => {
+                let mut send_stream = connection.open_uni().await?;
+                let _ = send_stream.set_priority(priority.0);
+                send_stream.write_all(&LANE_MAGIC).await?;
+                FramedWrite::new(send_stream, channel_codec())
+            }
+        };
+        sink.send(frame)
+            .await
+            .map_err(|err| SendError::ConnectionLost(err.to_string()))?;
+        *lane = Some(sink);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lanes_match_exact_paths() {
+        let lanes = OrderedLanes::default().with_path("/consensus/advert");
+        assert!(lanes.contains(&Uri::from_static("/consensus/advert")));
+        assert!(!lanes.contains(&Uri::from_static("/consensus/advert/other")));
+        assert!(!lanes.contains(&Uri::from_static("/consensus")));
+    }
+}
@@ -6,24 +6,88 @@
 //! each other.
 //!
 //! COMPONENTS:
+//!  - Ack (ack.rs): Pushes that complete once the handler of the peer processed them.
+//!  - Attestation (attestation.rs): Rejects or tolerates peers that fail the attestation
+//!    handshake, e.g. SEV, depending on `QuicTransportConfig::attestation`.
 //!  - Connection Manager (connection_manager.rs): Keeps peers connected.
+//!  - Cert Rotation (cert_rotation.rs): Cycles connections whose TLS certificates rotated in
+//!    the registry, as reported by the caller.
+//!  - Config (config.rs): Tunable parameters of transport.
 //!  - Request Handler (request_handler.rs): Accepts streams on an active connection.
 //!    Spawned by the connection manager for each connection.
 //!  - Connection Handle (connection_handle.rs): Provides rpc and push interfaces to a peer.
+//!  - Channel (channel.rs): Framed bidirectional streams between peers.
+//!  - Compression (compression.rs): Optional compression of bodies, negotiated per connection.
+//!  - Log Sampling (log_sampling.rs): Limits the log lines of high rate events.
+//!  - Message Size (message_size.rs): Per-URI size limits of incoming requests.
+//!  - Priority (priority.rs): Priorities of streams, based on the URI of their request.
+//!  - Peer Selector (peer_selector.rs): Order in which `rpc_any` tries the connected peers.
+//!  - Protocol (protocol.rs): Negotiates the application protocol version with each peer.
+//!  - Pub/Sub (pubsub.rs): Topics that peers subscribe to, and messages published to them.
+//!  - Quarantine (quarantine.rs): Keeps misbehaving peers disconnected for a while.
+//!  - Retry (retry.rs): Retries of rpcs that are marked as idempotent.
+//!  - Rate Limit (rate_limit.rs): Optional bandwidth limit of the connection to each peer.
+//!  - Builder (builder.rs): Constructs transport with setters instead of positional arguments.
+//!  - Deadline (deadline.rs): Propagates the deadline of rpc requests to the peer.
+//!  - Drain (drain.rs): Lets requests to peers that left the topology complete before closing.
+//!  - Failover (failover.rs): Moves transport to another local address if no peer is reachable.
+//!  - Health (health.rs): Pings peers and evicts connections that stopped responding.
+//!  - Lane (lane.rs): Delivers pushes to a URI in order over a single stream.
+//!  - In Memory (in_memory.rs): Transport without sockets for unit tests, behind the
+//!    `test-util` feature.
+//!  - Fault Injection (fault_injection.rs): Transport decorator that injects failures, behind
+//!    the `test-util` feature.
+//!  - Middleware (middleware.rs): Tower layers applied to outgoing requests.
+//!  - Trace Context (trace_context.rs): Propagates the trace context of requests to the peer.
+//!  - Traffic Class (traffic_class.rs): Priorities, in-flight limits and metrics of classes of
+//!    requests, e.g. control or bulk traffic.
+//!  - Typed (typed.rs): Rpcs with typed requests and responses, encoded by a pluggable codec.
 //!
 //! API:
 //!  - Constructor takes a topology watcher. The topology defines the
 //!    set of peers, to which transport tries to keep active connections.
 //!  - Constructor also takes a Router. Incoming requests are routed to a handler
 //!    based on the URI specified in the request.
+//!  - Constructor also takes the attestation handshake of peers, e.g. SEV. Peers that fail it
+//!    are rejected, tolerated or not attested at all, see `QuicTransportConfig::attestation`.
 //!  - `get_conn_handle`: Can be used to get a `ConnectionHandle` to a peer.
 //!     The connection handle is small wrapper around the actual quic connection
 //!     with an rpc/push interface. Passed in requests need to specify an URI to get
 //!     routed to the correct handler.
+//!  - `rpc_stream`: Like `rpc`, but yields the response body in chunks as they arrive instead
+//!     of buffering it. Uses the same wire format as `rpc`.
+//!  - `open_channel`: Opens a long-lived bidirectional stream of messages to a peer. Channels
+//!     are routed like requests, see `ChannelUpgrade`.
+//!  - `push_acked`: Like `push`, but completes once the handler of the peer processed the
+//!     request. Only the status of the handler is sent back.
+//!  - `push` requests to URIs in `QuicTransportConfig::ordered_lanes` are delivered in order.
+//!  - `push_unreliable`: Like `push`, but sends the request in a single QUIC datagram.
+//!     Meant for small, loss-tolerant messages, e.g. adverts.
+//!  - `broadcast`/`broadcast_rpc`: Sends a request to all connected peers concurrently.
+//!  - `rpc_any`: Sends an rpc to a connected peer chosen by a `PeerSelector`, e.g. the closest
+//!     one. Fails over to the next peer if the request could not be sent.
+//!  - `peer_stats`/`peer_rtt`: Quality of the path to a peer, e.g. to prefer close peers.
+//!  - `peer_protocol`: Application protocol version and features negotiated with a peer.
+//!  - `rebind`: Moves transport to another local address. Connections migrate to it.
+//!  - `quarantine_peer`: Disconnects from a misbehaving peer for a while.
+//!  - `subscribe`/`unsubscribe`/`publish`: Sends messages of a topic to the peers that
+//!     subscribed to it.
+//!  - `TypedTransport`/`TypedRouter`: Send and handle rpcs declared with `typed_rpc!`,
+//!     encoded with a `Codec`, e.g. `ProstCodec`.
+//!  - `rpc` requests with a `RetryPolicy` extension are retried on transient errors.
+//!  - `rpc`/`rpc_stream` fail with `SendError::Overloaded` if too many rpcs to the peer are
+//!     in flight, see `QuicTransportConfig::max_inflight_rpcs_per_peer`.
+//!  - `rpc`/`rpc_stream` requests with a `Deadline` extension fail with `SendError::Timeout`
+//!     once it expires. Handlers of the peer see the deadline as an extension of the request.
+//!  - `rpc`/`rpc_stream`/`push` requests with a `TrafficClass` extension are sent with the
+//!     priority of the class, and rpcs are admitted by the in-flight limit of the class, see
+//!     `QuicTransportConfig::traffic_classes`. Handlers of the peer see the class as well.
+//!  - `rpc`/`rpc_stream`/`push` requests with a `TraceContext` extension propagate it to the
+//!     peer. Handlers of the peer see a child of the context as an extension of the request.
 //!
 //! GUARANTEES:
-//!  - If a peer is reachable, part of the topology and well-behaving transport will eventually
-//!    open a connection.
+//!  - If a peer is reachable, part of the topology, well-behaving and not quarantined
+//!    transport will eventually open a connection.
 //!  - The connection handle returned by `get_conn_handle` can be broken.
 //!    It is responsibility of the transport user to have an adequate retry logic.
 //!
@@ -31,17 +95,24 @@
 use std::{
     collections::{BTreeSet, HashMap},
     fmt::Debug,
+    future::Future,
     net::SocketAddr,
     sync::{Arc, RwLock},
+    time::Duration,
 };
 
 use async_trait::async_trait;
 use axum::{
-    http::{Request, Response},
+    http::{Request, Response, StatusCode, Uri},
     Router,
 };
 use bytes::Bytes;
 use either::Either;
+use futures::{
+    future::join_all,
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use ic_base_types::{NodeId, RegistryVersion};
 use ic_crypto_tls_interfaces::{TlsConfig, TlsStream};
 use ic_icos_sev::ValidateAttestedStream;
@@ -51,16 +122,85 @@ use ic_metrics::MetricsRegistry;
 use phantom_newtype::AmountOf;
 use quinn::{AsyncUdpSocket, ConnectionError, WriteError};
 use thiserror::Error;
-use tokio::sync::watch;
+use tokio::sync::{mpsc, watch};
 use tokio_util::{sync::CancellationToken, task::task_tracker::TaskTracker};
+use tracing::Instrument;
 
+use crate::ack::check_ack;
 use crate::connection_handle::ConnectionHandle;
-use crate::connection_manager::start_connection_manager;
+use crate::connection_manager::{start_connection_manager, LocalEndpoint};
+use crate::message_size::STREAM_ERROR_MESSAGE_TOO_LARGE;
+use crate::metrics::{
+    REQUEST_TYPE_PUSH, REQUEST_TYPE_PUSH_ACKED, REQUEST_TYPE_RPC, REQUEST_TYPE_RPC_STREAM,
+};
+use crate::peer_selector::is_unsent;
+use crate::pubsub::{publication, PubSub, ANNOUNCEMENT_TIMEOUT};
+use crate::quarantine::{Quarantine, QUARANTINED_ERROR_CODE};
+use crate::retry::with_retries;
+use crate::trace_context::outgoing_span;
+
+pub use crate::attestation::{AttestationMode, NoAttestation};
+pub use crate::builder::QuicTransportBuilder;
+pub use crate::cert_rotation::{NoCertRotation, TlsCertificates};
+pub use crate::channel::{Channel, ChannelUpgrade};
+pub use crate::compression::CompressionConfig;
+pub use crate::config::{
+    QuicTransportConfig, DEFAULT_MAX_DATAGRAM_SIZE, DEFAULT_MAX_INFLIGHT_DATAGRAMS_PER_PEER,
+    DEFAULT_MAX_INFLIGHT_RPCS_PER_PEER, DEFAULT_PUSH_QUEUE_CAPACITY,
+};
+pub use crate::deadline::Deadline;
+pub use crate::failover::FailoverConfig;
+#[cfg(feature = "test-util")]
+pub use crate::fault_injection::{FaultPolicy, FaultyTransport};
+pub use crate::health::HealthCheckConfig;
+#[cfg(feature = "test-util")]
+pub use crate::in_memory::{InMemoryNetwork, InMemoryTransport, InMemoryTransportPair};
+pub use crate::lane::OrderedLanes;
+pub use crate::log_sampling::LogSamplingConfig;
+pub use crate::message_size::MessageSizeLimits;
+pub use crate::middleware::{OutgoingLayers, OutgoingService};
+pub use crate::peer_selector::PeerSelector;
+pub use crate::priority::{StreamPriorities, StreamPriority};
+pub use crate::protocol::ProtocolVersion;
+pub use crate::rate_limit::RateLimitConfig;
+pub use crate::retry::RetryPolicy;
+pub use crate::trace_context::TraceContext;
+pub use crate::traffic_class::{TrafficClass, TrafficClassConfig, TrafficClasses};
+pub use crate::typed::{BincodeCodec, Codec, ProstCodec, TypedRouter, TypedRpc, TypedTransport};
 
+mod ack;
+mod attestation;
+mod builder;
+mod cert_rotation;
+mod channel;
+mod compression;
+mod config;
 mod connection_handle;
 mod connection_manager;
+mod deadline;
+mod drain;
+mod failover;
+#[cfg(feature = "test-util")]
+mod fault_injection;
+mod health;
+#[cfg(feature = "test-util")]
+mod in_memory;
+mod lane;
+mod log_sampling;
+mod message_size;
 mod metrics;
+mod middleware;
+mod peer_selector;
+mod priority;
+mod protocol;
+mod pubsub;
+mod quarantine;
+mod rate_limit;
 mod request_handler;
+mod retry;
+mod trace_context;
+mod traffic_class;
+mod typed;
 mod utils;
 
 #[derive(Clone)]
@@ -68,6 +208,11 @@ pub struct QuicTransport {
     conn_handles: Arc<RwLock<HashMap<NodeId, ConnectionHandle>>>,
     cancellation: CancellationToken,
     conn_manager_task_tracker: TaskTracker,
+    local_endpoint: LocalEndpoint,
+    quarantine: Quarantine,
+    pubsub: PubSub,
+    /// Distinguishes peers that are not connected from peers that are not in the topology.
+    topology_watcher: watch::Receiver<SubnetTopology>,
 }
 
 /// This is the main transport handle used for communication between peers.
@@ -91,6 +236,10 @@ impl QuicTransport {
         rt: &tokio::runtime::Handle,
         tls_config: Arc<dyn TlsConfig + Send + Sync>,
         registry_client: Arc<dyn RegistryClient>,
+        // Certificates that connections are cycled to once they rotate. `NoCertRotation` keeps
+        // connections with rotated certificates.
+        tls_certificates: Arc<dyn TlsCertificates>,
+        // Not called if attestation is disabled, in which case `NoAttestation` can be passed.
         sev_handshake: Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>,
         node_id: NodeId,
         // The receiver is passed here mainly to be consistent with other managers that also
@@ -99,34 +248,121 @@ impl QuicTransport {
         udp_socket: Either<SocketAddr, impl AsyncUdpSocket>,
         // Make sure this is respected https://docs.rs/axum/latest/axum/struct.Router.html#a-note-about-performance
         router: Router,
+        config: QuicTransportConfig,
     ) -> QuicTransport {
         info!(log, "Starting Quic transport.");
 
         let cancellation = CancellationToken::new();
         let conn_handles = Arc::new(RwLock::new(HashMap::new()));
         let conn_manager_task_tracker = TaskTracker::new();
+        let quarantine = Quarantine::default();
+        let pubsub = PubSub::default();
 
-        start_connection_manager(
+        let local_endpoint = start_connection_manager(
             log,
             metrics_registry,
             rt,
             tls_config.clone(),
             registry_client,
+            tls_certificates,
             sev_handshake,
             node_id,
             conn_handles.clone(),
-            topology_watcher,
+            topology_watcher.clone(),
             cancellation.clone(),
             conn_manager_task_tracker.clone(),
             udp_socket,
             router,
+            config,
+            quarantine.clone(),
+            pubsub.clone(),
         );
 
         QuicTransport {
             conn_handles,
             cancellation,
             conn_manager_task_tracker,
+            local_endpoint,
+            quarantine,
+            pubsub,
+            topology_watcher,
+        }
+    }
+
+    /// Address of the socket transport currently listens on.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.local_endpoint.local_addr()
+    }
+
+    /// Moves transport to a socket bound to `addr`, e.g. after failover to another NIC.
+    /// Existing connections migrate to the new address instead of being re-dialed. On error,
+    /// transport keeps using the current socket.
+    pub fn rebind(&self, addr: SocketAddr) -> std::io::Result<()> {
+        self.local_endpoint.rebind(addr)
+    }
+
+    /// Closes the connection to the peer and neither dials nor accepts connections of the peer
+    /// for the given period, even if the peer remains in the topology. Meant for peers that
+    /// misbehave on the protocol level.
+    pub fn quarantine_peer(&self, peer_id: NodeId, duration: Duration) {
+        self.quarantine
+            .insert(peer_id, tokio::time::Instant::now() + duration);
+        if let Some(peer) = self.conn_handles.write().unwrap().remove(&peer_id) {
+            peer.connection
+                .close(QUARANTINED_ERROR_CODE, b"peer is quarantined");
+        }
+    }
+
+    /// Subscribes this node to the topic. Messages that peers publish to the topic are received
+    /// on the returned channel, together with the peer that published them.
+    pub async fn subscribe(&self, topic: impl Into<String>) -> mpsc::Receiver<(NodeId, Bytes)> {
+        let (subscription, announcement) = self.pubsub.subscribe(topic.into());
+        if let Some(announcement) = announcement {
+            self.announce(announcement).await;
         }
+        subscription
+    }
+
+    /// Unsubscribes all local subscribers from the topic.
+    pub async fn unsubscribe(&self, topic: &str) {
+        if let Some(announcement) = self.pubsub.unsubscribe(topic) {
+            self.announce(announcement).await;
+        }
+    }
+
+    /// Pushes the payload to all connected peers that subscribed to the topic. Returns the
+    /// result of each peer. Peers that do not complete within `timeout` fail with
+    /// `SendError::Timeout`.
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: Bytes,
+        timeout: Duration,
+    ) -> Vec<(NodeId, Result<(), SendError>)> {
+        let subscribers = self
+            .peers()
+            .into_iter()
+            .filter(|(peer_id, _)| self.pubsub.is_subscribed(peer_id, topic))
+            .collect();
+        fan_out(
+            subscribers,
+            &publication(topic, &payload),
+            timeout,
+            |peer_id, request| async move { self.push(&peer_id, request).await },
+        )
+        .await
+    }
+
+    /// Sends the subscriptions of this node to all connected peers. Peers that miss them
+    /// receive them again once they reconnect.
+    async fn announce(&self, announcement: Request<Bytes>) {
+        fan_out(
+            self.peers(),
+            &announcement,
+            ANNOUNCEMENT_TIMEOUT,
+            |peer_id, request| async move { self.push(&peer_id, request).await },
+        )
+        .await;
     }
 
     /// Graceful shutdown of transport.
@@ -138,16 +374,14 @@ impl QuicTransport {
     }
 
     pub(crate) fn get_conn_handle(&self, peer_id: &NodeId) -> Result<ConnectionHandle, SendError> {
-        let conn = self
-            .conn_handles
-            .read()
-            .unwrap()
-            .get(peer_id)
-            .ok_or(SendError::ConnectionUnavailable(
-                "Currently not connected to this peer".to_string(),
-            ))?
-            .clone();
-        Ok(conn)
+        let conn = self.conn_handles.read().unwrap().get(peer_id).cloned();
+        conn.ok_or_else(|| {
+            if self.topology_watcher.borrow().is_member(peer_id) {
+                SendError::ConnectionUnavailable("Currently not connected to this peer".to_string())
+            } else {
+                SendError::PeerNotInTopology
+            }
+        })
     }
 }
 
@@ -158,13 +392,54 @@ impl Transport for QuicTransport {
         peer_id: &NodeId,
         request: Request<Bytes>,
     ) -> Result<Response<Bytes>, SendError> {
+        let span = outgoing_span(REQUEST_TYPE_RPC, peer_id, &request);
+        with_retries(request, |request| async move {
+            let peer = self.get_conn_handle(peer_id)?;
+            peer.rpc(request).await
+        })
+        .instrument(span)
+        .await
+    }
+
+    async fn rpc_stream(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<Response<ResponseStream>, SendError> {
+        let span = outgoing_span(REQUEST_TYPE_RPC_STREAM, peer_id, &request);
         let peer = self.get_conn_handle(peer_id)?;
-        peer.rpc(request).await
+        peer.rpc_stream(request).instrument(span).await
     }
 
     async fn push(&self, peer_id: &NodeId, request: Request<Bytes>) -> Result<(), SendError> {
+        let span = outgoing_span(REQUEST_TYPE_PUSH, peer_id, &request);
+        let peer = self.get_conn_handle(peer_id)?;
+        peer.push(request).instrument(span).await
+    }
+
+    async fn push_acked(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+        timeout: Duration,
+    ) -> Result<(), SendError> {
+        let span = outgoing_span(REQUEST_TYPE_PUSH_ACKED, peer_id, &request);
         let peer = self.get_conn_handle(peer_id)?;
-        peer.push(request).await
+        peer.push_acked(request, timeout).instrument(span).await
+    }
+
+    async fn push_unreliable(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<(), SendError> {
+        let peer = self.get_conn_handle(peer_id)?;
+        peer.push_unreliable(request)
+    }
+
+    async fn open_channel(&self, peer_id: &NodeId, uri: Uri) -> Result<Channel, SendError> {
+        let peer = self.get_conn_handle(peer_id)?;
+        peer.open_channel(uri).await
     }
 
     fn peers(&self) -> Vec<(NodeId, ConnId)> {
@@ -175,21 +450,123 @@ impl Transport for QuicTransport {
             .map(|(n, c)| (*n, c.conn_id()))
             .collect()
     }
+
+    fn peer_stats(&self, peer_id: &NodeId) -> Option<PeerStats> {
+        self.get_conn_handle(peer_id)
+            .ok()
+            .map(|peer| peer.peer_stats())
+    }
+
+    fn peer_protocol(&self, peer_id: &NodeId) -> Option<ProtocolVersion> {
+        self.get_conn_handle(peer_id)
+            .ok()
+            .map(|peer| peer.protocol())
+    }
+
+    fn peer_inflight_rpcs(&self, peer_id: &NodeId) -> Option<usize> {
+        self.get_conn_handle(peer_id)
+            .ok()
+            .map(|peer| peer.inflight_rpcs())
+    }
 }
 
+/// Errors of sending requests. Transient errors can succeed if the request is sent again
+/// later, see `is_retryable`. Permanent errors fail again.
 #[derive(Debug, Error)]
 pub enum SendError {
+    // Transient errors.
+    // The peer is part of the topology, but currently not connected.
     #[error("the connection to peer `{0}` is unavailable")]
     ConnectionUnavailable(String),
+    // The connection was closed or reset while the request was in progress.
+    #[error("the connection to the peer was lost `{0}`")]
+    ConnectionLost(String),
+    #[error("the peer did not complete the request in time")]
+    Timeout,
+    // Too many requests to the peer are in progress. The caller should retry later.
+    #[error("too many requests to the peer are in progress")]
+    Backpressure,
+    // Too many rpcs to the peer are in flight. The caller should retry later.
+    #[error("too many rpcs to the peer are in flight")]
+    Overloaded,
+    // Permanent errors.
+    #[error("the peer is not part of the topology")]
+    PeerNotInTopology,
+    // The request exceeds the size limit of the peer for its URI, or of a datagram.
+    #[error("the request is too large for the peer")]
+    MessageTooLarge,
+    // The handler of the peer rejected the request. Rpcs return the response of such handlers
+    // instead, only channels fail with this error.
+    #[error("the handler of the peer rejected the request with status {0}")]
+    HandlerError(StatusCode),
+    // The transport or the peer does not support the kind of request, e.g. channels.
+    #[error("the request is not supported `{0}`")]
+    Unsupported(String),
     // This serves as catch-all error for invariant breaking errors.
-    // E.g. failing to serialize, peer closing connections unexpectedly, etc.
+    // E.g. failing to serialize, malformed responses of the peer, etc.
     #[error("internal error `{0}`")]
     Internal(String),
 }
 
+/// Classes of `SendError`s, e.g. to select the errors that are retried.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum SendErrorKind {
+    ConnectionUnavailable,
+    ConnectionLost,
+    Timeout,
+    Backpressure,
+    Overloaded,
+    PeerNotInTopology,
+    MessageTooLarge,
+    HandlerError,
+    Unsupported,
+    Internal,
+}
+
+impl SendErrorKind {
+    /// Whether errors of this kind are transient, i.e. sending the request again later can
+    /// succeed.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            SendErrorKind::ConnectionUnavailable
+            | SendErrorKind::ConnectionLost
+            | SendErrorKind::Timeout
+            | SendErrorKind::Backpressure
+            | SendErrorKind::Overloaded => true,
+            SendErrorKind::PeerNotInTopology
+            | SendErrorKind::MessageTooLarge
+            | SendErrorKind::HandlerError
+            | SendErrorKind::Unsupported
+            | SendErrorKind::Internal => false,
+        }
+    }
+}
+
+impl SendError {
+    pub fn kind(&self) -> SendErrorKind {
+        match self {
+            SendError::ConnectionUnavailable(_) => SendErrorKind::ConnectionUnavailable,
+            SendError::ConnectionLost(_) => SendErrorKind::ConnectionLost,
+            SendError::Timeout => SendErrorKind::Timeout,
+            SendError::Backpressure => SendErrorKind::Backpressure,
+            SendError::Overloaded => SendErrorKind::Overloaded,
+            SendError::PeerNotInTopology => SendErrorKind::PeerNotInTopology,
+            SendError::MessageTooLarge => SendErrorKind::MessageTooLarge,
+            SendError::HandlerError(_) => SendErrorKind::HandlerError,
+            SendError::Unsupported(_) => SendErrorKind::Unsupported,
+            SendError::Internal(_) => SendErrorKind::Internal,
+        }
+    }
+
+    /// Whether the error is transient, see `SendErrorKind::is_retryable`.
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+}
+
 impl From<ConnectionError> for SendError {
     fn from(conn_err: ConnectionError) -> Self {
-        SendError::Internal(conn_err.to_string())
+        SendError::ConnectionLost(conn_err.to_string())
     }
 }
 
@@ -197,6 +574,9 @@ impl From<WriteError> for SendError {
     fn from(write_err: WriteError) -> Self {
         match write_err {
             WriteError::ConnectionLost(conn_err) => conn_err.into(),
+            WriteError::Stopped(code) if code == STREAM_ERROR_MESSAGE_TOO_LARGE => {
+                SendError::MessageTooLarge
+            }
             _ => SendError::Internal(write_err.to_string()),
         }
     }
@@ -204,17 +584,188 @@ impl From<WriteError> for SendError {
 
 #[async_trait]
 pub trait Transport: Send + Sync {
+    /// May fail with `SendError::Overloaded` if too many rpcs to the peer are in flight.
     async fn rpc(
         &self,
         peer_id: &NodeId,
         request: Request<Bytes>,
     ) -> Result<Response<Bytes>, SendError>;
 
+    /// Same as `rpc`, but the body of the response is yielded in chunks as they arrive.
+    /// The default implementation yields the whole body of the `rpc` response as a single chunk.
+    async fn rpc_stream(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<Response<ResponseStream>, SendError> {
+        let (parts, body) = self.rpc(peer_id, request).await?.into_parts();
+        Ok(Response::from_parts(
+            parts,
+            stream::once(async move { Ok(body) }).boxed(),
+        ))
+    }
+
+    /// May fail with `SendError::Backpressure` if too many pushes to the peer are in progress.
     async fn push(&self, peer_id: &NodeId, request: Request<Bytes>) -> Result<(), SendError>;
 
+    /// Same as `push`, but completes only once the handler of the peer processed the request.
+    /// Fails with `SendError::HandlerError` if the handler did not succeed, and with
+    /// `SendError::Timeout` if the peer does not acknowledge the request within `timeout`.
+    /// The default implementation sends an `rpc` and drops the response.
+    async fn push_acked(
+        &self,
+        peer_id: &NodeId,
+        mut request: Request<Bytes>,
+        timeout: Duration,
+    ) -> Result<(), SendError> {
+        request.extensions_mut().insert(Deadline::after(timeout));
+        check_ack(self.rpc(peer_id, request).await?)
+    }
+
+    /// Same as `push`, but the request may be lost. Only small requests can be sent this way,
+    /// larger ones are rejected. The default implementation falls back to `push`.
+    async fn push_unreliable(
+        &self,
+        peer_id: &NodeId,
+        request: Request<Bytes>,
+    ) -> Result<(), SendError> {
+        self.push(peer_id, request).await
+    }
+
+    /// Opens a channel to the handler of `uri` on the peer. Peers that do not support channels
+    /// never answer, so callers should apply a timeout.
+    async fn open_channel(&self, _peer_id: &NodeId, _uri: Uri) -> Result<Channel, SendError> {
+        Err(SendError::Unsupported(
+            "Channels are not supported by this transport".to_string(),
+        ))
+    }
+
+    /// Pushes the request to all currently connected peers concurrently. Returns the result
+    /// of each peer. Peers that do not complete within `timeout` fail with `SendError::Timeout`.
+    async fn broadcast(
+        &self,
+        request: Request<Bytes>,
+        timeout: Duration,
+    ) -> Vec<(NodeId, Result<(), SendError>)> {
+        fan_out(
+            self.peers(),
+            &request,
+            timeout,
+            |peer_id, request| async move { self.push(&peer_id, request).await },
+        )
+        .await
+    }
+
+    /// Same as `broadcast`, but sends an `rpc` to each peer.
+    async fn broadcast_rpc(
+        &self,
+        request: Request<Bytes>,
+        timeout: Duration,
+    ) -> Vec<(NodeId, Result<Response<Bytes>, SendError>)> {
+        fan_out(
+            self.peers(),
+            &request,
+            timeout,
+            |peer_id, request| async move { self.rpc(&peer_id, request).await },
+        )
+        .await
+    }
+
+    /// Sends the rpc to a connected peer, trying the peers in the order of `selector`. Peers
+    /// the request could not be sent to, i.e. that are unavailable or overloaded, are skipped.
+    /// Fails with `SendError::ConnectionUnavailable` if no peer is connected.
+    async fn rpc_any(
+        &self,
+        request: Request<Bytes>,
+        selector: &PeerSelector,
+    ) -> Result<Response<Bytes>, SendError> {
+        let peers = self
+            .peers()
+            .into_iter()
+            .map(|(peer_id, _)| peer_id)
+            .collect();
+        let mut last_err =
+            SendError::ConnectionUnavailable("Currently not connected to any peer".to_string());
+        for peer_id in selector.order(self, peers) {
+            match self.rpc(&peer_id, request.clone()).await {
+                Err(err) if is_unsent(&err) => last_err = err,
+                result => return result,
+            }
+        }
+        Err(last_err)
+    }
+
     fn peers(&self) -> Vec<(NodeId, ConnId)>;
+
+    /// Path statistics of the connection to the peer. `None` if the peer is not connected or
+    /// the transport has no notion of paths.
+    fn peer_stats(&self, _peer_id: &NodeId) -> Option<PeerStats> {
+        None
+    }
+
+    /// Smoothed round trip time to the peer, see `peer_stats`.
+    fn peer_rtt(&self, peer_id: &NodeId) -> Option<Duration> {
+        self.peer_stats(peer_id).map(|stats| stats.rtt)
+    }
+
+    /// Application protocol negotiated with the peer. `None` if the peer is not connected or
+    /// the transport does not negotiate protocols.
+    fn peer_protocol(&self, _peer_id: &NodeId) -> Option<ProtocolVersion> {
+        None
+    }
+
+    /// Number of rpcs to the peer that are in flight. `None` if the peer is not connected or
+    /// the transport does not track rpcs.
+    fn peer_inflight_rpcs(&self, _peer_id: &NodeId) -> Option<usize> {
+        None
+    }
 }
 
+/// Statistics of the network path to a peer, as estimated by quinn.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PeerStats {
+    /// Smoothed round trip time.
+    pub rtt: Duration,
+    /// Congestion window in bytes.
+    pub congestion_window: u64,
+    pub sent_packets: u64,
+    pub lost_packets: u64,
+    /// Fraction of sent packets that were lost, between 0 and 1.
+    pub loss_rate: f64,
+}
+
+/// Calls `send` for each peer with a copy of the request. Extensions are not copied.
+async fn fan_out<T, F, Fut>(
+    peers: Vec<(NodeId, ConnId)>,
+    request: &Request<Bytes>,
+    timeout: Duration,
+    send: F,
+) -> Vec<(NodeId, Result<T, SendError>)>
+where
+    F: Fn(NodeId, Request<Bytes>) -> Fut,
+    Fut: Future<Output = Result<T, SendError>>,
+{
+    join_all(peers.into_iter().map(|(peer_id, _)| {
+        let mut peer_request = Request::new(request.body().clone());
+        *peer_request.method_mut() = request.method().clone();
+        *peer_request.uri_mut() = request.uri().clone();
+        *peer_request.version_mut() = request.version();
+        *peer_request.headers_mut() = request.headers().clone();
+
+        let send = send(peer_id, peer_request);
+        async move {
+            let result = tokio::time::timeout(timeout, send)
+                .await
+                .unwrap_or(Err(SendError::Timeout));
+            (peer_id, result)
+        }
+    }))
+    .await
+}
+
+/// Body of a response returned by `Transport::rpc_stream`.
+pub type ResponseStream = BoxStream<'static, Result<Bytes, SendError>>;
+
 pub struct ConnIdTag {}
 pub type ConnId = AmountOf<ConnIdTag, u64>;
 
@@ -280,6 +831,8 @@ impl SubnetTopology {
         self.subnet_nodes.contains_key(node)
     }
 
+    /// Address of the node. Transport dials it with the address family of its local socket,
+    /// see `QuicTransportConfig::dual_stack`.
     pub fn get_addr(&self, node: &NodeId) -> Option<SocketAddr> {
         self.subnet_nodes.get(node).copied()
     }
@@ -0,0 +1,191 @@
+//! Quic Transport log sampling.
+//!
+//! Connection churn on large subnets can produce a flood of log lines. Events that can occur
+//! at a high rate are therefore grouped into classes, and only one out of every `n` events
+//! of a class is logged, where `n` is the configured sample rate of that class.
+//!
+//!  - Sampling restarts every [`SAMPLING_WINDOW`]: the first event of a class in each window
+//!    is always logged, so a failure that is rare but keeps recurring is logged at least once
+//!    per window, even after a burst of other events of the same class.
+//!  - Each logged line reports how many events of its class were not logged since the
+//!    previous one.
+//!  - Events that change the state of transport, e.g. a connection being added to or removed
+//!    from the peer map, are not sampled and always logged.
+//!
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Length of the window after which sampling of a class restarts.
+pub(crate) const SAMPLING_WINDOW: Duration = Duration::from_secs(10);
+
+/// Sample rates of the event classes. A rate of `n` logs one out of every `n` events of
+/// the class. Rates of 0 and 1 log every event.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LogSamplingConfig {
+    /// Outgoing connection attempts and failed connection attempts.
+    pub connection_lifecycle: u32,
+    /// Failures to read or write a request or response on a stream.
+    pub request_errors: u32,
+}
+
+impl Default for LogSamplingConfig {
+    fn default() -> Self {
+        Self {
+            connection_lifecycle: 1,
+            request_errors: 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum EventClass {
+    ConnectionLifecycle,
+    RequestError,
+}
+
+#[derive(Debug, Default)]
+struct ClassState {
+    window_start: Option<Instant>,
+    seen_in_window: u64,
+    not_logged: u64,
+}
+
+/// Decides which events get logged. Clones share their counters, so that events are
+/// sampled across all connections.
+#[derive(Clone, Debug)]
+pub(crate) struct LogSampler {
+    config: LogSamplingConfig,
+    connection_lifecycle: Arc<Mutex<ClassState>>,
+    request_errors: Arc<Mutex<ClassState>>,
+}
+
+impl LogSampler {
+    pub(crate) fn new(config: LogSamplingConfig) -> Self {
+        Self {
+            config,
+            connection_lifecycle: Arc::default(),
+            request_errors: Arc::default(),
+        }
+    }
+
+    /// Records an event. Returns the number of events of the same class that were not
+    /// logged since the last logged one if this event should be logged, `None` otherwise.
+    pub(crate) fn sample(&self, class: EventClass) -> Option<u64> {
+        self.sample_at(class, Instant::now())
+    }
+
+    fn sample_at(&self, class: EventClass, now: Instant) -> Option<u64> {
+        let (rate, state) = match class {
+            EventClass::ConnectionLifecycle => {
+                (self.config.connection_lifecycle, &self.connection_lifecycle)
+            }
+            EventClass::RequestError => (self.config.request_errors, &self.request_errors),
+        };
+        let rate = u64::from(rate.max(1));
+
+        let mut state = state.lock().unwrap();
+        let window_elapsed = state.window_start.map_or(true, |start| {
+            now.saturating_duration_since(start) >= SAMPLING_WINDOW
+        });
+        if window_elapsed {
+            state.window_start = Some(now);
+            state.seen_in_window = 0;
+        }
+
+        let seen = state.seen_in_window;
+        state.seen_in_window += 1;
+        if seen % rate != 0 {
+            state.not_logged += 1;
+            return None;
+        }
+
+        Some(std::mem::take(&mut state.not_logged))
+    }
+}
+
+pub(crate) fn not_logged_suffix(not_logged: u64) -> String {
+    if not_logged == 0 {
+        String::new()
+    } else {
+        format!(" ({} similar events not logged)", not_logged)
+    }
+}
+
+/// Logs at INFO level iff the sampler selects the event.
+macro_rules! info_sampled {
+    ($sampler:expr, $class:expr, $log:expr, $($arg:tt)+) => {
+        if let Some(not_logged) = $sampler.sample($class) {
+            ic_logger::info!(
+                $log,
+                "{}{}",
+                format!($($arg)+),
+                $crate::log_sampling::not_logged_suffix(not_logged)
+            );
+        }
+    };
+}
+
+pub(crate) use info_sampled;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_sampled_per_class() {
+        let sampler = LogSampler::new(LogSamplingConfig {
+            connection_lifecycle: 3,
+            request_errors: 0,
+        });
+        let shared = sampler.clone();
+        let now = Instant::now();
+
+        let lifecycle: Vec<_> = (0..7)
+            .map(|i| {
+                // Clones share their counters.
+                let sampler = if i % 2 == 0 { &sampler } else { &shared };
+                sampler.sample_at(EventClass::ConnectionLifecycle, now)
+            })
+            .collect();
+        assert_eq!(
+            lifecycle,
+            vec![Some(0), None, None, Some(2), None, None, Some(2)]
+        );
+
+        // A rate of 0 logs everything, independently of other classes.
+        for _ in 0..5 {
+            assert_eq!(sampler.sample_at(EventClass::RequestError, now), Some(0));
+        }
+    }
+
+    #[test]
+    fn first_event_of_each_window_is_logged() {
+        let sampler = LogSampler::new(LogSamplingConfig {
+            connection_lifecycle: 0,
+            request_errors: 100,
+        });
+        let start = Instant::now();
+
+        assert_eq!(sampler.sample_at(EventClass::RequestError, start), Some(0));
+        for _ in 0..4 {
+            assert_eq!(sampler.sample_at(EventClass::RequestError, start), None);
+        }
+
+        // Still within the window.
+        let later = start + SAMPLING_WINDOW / 2;
+        assert_eq!(sampler.sample_at(EventClass::RequestError, later), None);
+
+        // The next window logs its first event and reports what was suppressed before.
+        let next_window = start + SAMPLING_WINDOW;
+        assert_eq!(
+            sampler.sample_at(EventClass::RequestError, next_window),
+            Some(5)
+        );
+        assert_eq!(
+            sampler.sample_at(EventClass::RequestError, next_window),
+            None
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Quic Transport message size limits.
+//!
+//! Requests are buffered before they are passed to handlers. To bound the memory a peer can
+//! make this node allocate, the request handler enforces size limits on incoming requests.
+//!
+//!  - The limit of a request is determined by the URI of the request. It is read before the
+//!    rest of the request, so oversized requests are rejected without buffering them.
+//!  - The limit applies to the encoded request after the URI, i.e. its headers and body.
+//!    Compressed bodies are in addition limited to the same size once decompressed.
+//!  - Oversized requests are rejected by stopping the stream with
+//!    `STREAM_ERROR_MESSAGE_TOO_LARGE`, which fails the request with
+//!    `SendError::MessageTooLarge` on the sender. Rpcs that were already sent completely
+//!    receive a `413 Payload Too Large` response instead.
+//!  - Requests sent in frames of lanes and channels are limited the same way, using the length
+//!    of the frame. Since the stream is stopped, the rest of the lane is lost, and the next
+//!    push to the URI opens a new lane.
+//!
+use axum::http::Uri;
+use quinn::VarInt;
+
+use crate::utils::MAX_MESSAGE_SIZE_BYTES;
+
+/// Error code of streams that were stopped because the request exceeds the size limit.
+pub(crate) const STREAM_ERROR_MESSAGE_TOO_LARGE: VarInt = VarInt::from_u32(413);
+
+/// Mapping from URI path prefixes to upper bounds on the size of requests in bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MessageSizeLimits {
+    default_limit: usize,
+    prefixes: Vec<(String, usize)>,
+}
+
+impl Default for MessageSizeLimits {
+    fn default() -> Self {
+        Self::new(MAX_MESSAGE_SIZE_BYTES)
+    }
+}
+
+impl MessageSizeLimits {
+    /// Limits all requests to `default_limit` bytes. Limits above the maximum size supported by
+    /// the wire format are lowered to it.
+    pub fn new(default_limit: usize) -> Self {
+        Self {
+            default_limit: default_limit.min(MAX_MESSAGE_SIZE_BYTES),
+            prefixes: Vec::new(),
+        }
+    }
+
+    /// Limits all requests whose URI path starts with `prefix` to `limit` bytes. If several
+    /// prefixes match, the longest one wins.
+    pub fn with_prefix(mut self, prefix: impl Into<String>, limit: usize) -> Self {
+        self.prefixes
+            .push((prefix.into(), limit.min(MAX_MESSAGE_SIZE_BYTES)));
+        self
+    }
+
+    pub(crate) fn for_uri(&self, uri: &Uri) -> usize {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| uri.path().starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, limit)| *limit)
+            .unwrap_or(self.default_limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix_wins() {
+        let limits = MessageSizeLimits::new(1_000)
+            .with_prefix("/consensus", 100)
+            .with_prefix("/consensus/artifact", usize::MAX);
+
+        let limit = |uri: &'static str| limits.for_uri(&Uri::from_static(uri));
+        assert_eq!(limit("/state-sync/chunk"), 1_000);
+        assert_eq!(limit("/consensus/advert"), 100);
+        // Limits are capped by the wire format.
+        assert_eq!(limit("/consensus/artifact"), MAX_MESSAGE_SIZE_BYTES);
+    }
+}
@@ -13,18 +13,42 @@ const STREAM_TYPE_LABEL: &str = "stream";
 const HANDLER_LABEL: &str = "handler";
 const ERROR_TYPE_LABEL: &str = "error";
 const REQUEST_TYPE_LABEL: &str = "request";
+const DROP_REASON_LABEL: &str = "reason";
+const DIRECTION_LABEL: &str = "direction";
+const TRAFFIC_CLASS_LABEL: &str = "traffic_class";
 pub(crate) const CONNECTION_RESULT_SUCCESS_LABEL: &str = "success";
 pub(crate) const CONNECTION_RESULT_FAILED_LABEL: &str = "failed";
 pub(crate) const ERROR_TYPE_ACCEPT: &str = "accept";
+pub(crate) const ERROR_TYPE_BACKPRESSURE: &str = "backpressure";
+pub(crate) const ERROR_TYPE_OVERLOADED: &str = "overloaded";
 pub(crate) const ERROR_TYPE_OPEN: &str = "open";
 pub(crate) const ERROR_TYPE_APP: &str = "app";
 pub(crate) const ERROR_TYPE_FINISH: &str = "finish";
 pub(crate) const ERROR_TYPE_READ: &str = "read";
 pub(crate) const ERROR_TYPE_WRITE: &str = "write";
+pub(crate) const ERROR_TYPE_TIMEOUT: &str = "timeout";
+pub(crate) const ERROR_TYPE_TOO_LARGE: &str = "too_large";
 pub(crate) const STREAM_TYPE_BIDI: &str = "bidi";
+pub(crate) const STREAM_TYPE_CHANNEL: &str = "channel";
+pub(crate) const STREAM_TYPE_DATAGRAM: &str = "datagram";
+pub(crate) const STREAM_TYPE_LANE: &str = "lane";
 pub(crate) const STREAM_TYPE_UNI: &str = "uni";
 pub(crate) const REQUEST_TYPE_PUSH: &str = "push";
+pub(crate) const REQUEST_TYPE_PUSH_ACKED: &str = "push_acked";
 pub(crate) const REQUEST_TYPE_RPC: &str = "rpc";
+pub(crate) const REQUEST_TYPE_RPC_STREAM: &str = "rpc_stream";
+pub(crate) const REQUEST_TYPE_CHANNEL: &str = "channel";
+pub(crate) const DROP_REASON_TOO_LARGE: &str = "too_large";
+pub(crate) const DROP_REASON_UNSUPPORTED: &str = "unsupported";
+pub(crate) const DROP_REASON_CONNECTION_LOST: &str = "connection_lost";
+pub(crate) const DROP_REASON_MALFORMED: &str = "malformed";
+pub(crate) const DROP_REASON_OVERLOADED: &str = "overloaded";
+/// Requests received from peers.
+pub(crate) const DIRECTION_INBOUND: &str = "inbound";
+/// Requests sent to peers.
+pub(crate) const DIRECTION_OUTBOUND: &str = "outbound";
+pub(crate) const DRAIN_RESULT_COMPLETED: &str = "completed";
+pub(crate) const DRAIN_RESULT_TIMED_OUT: &str = "timed_out";
 
 #[derive(Debug, Clone)]
 pub struct QuicTransportMetrics {
@@ -40,17 +64,38 @@ pub struct QuicTransportMetrics {
     pub connecting_connections: IntGauge,
     pub delay_queue_size: IntGauge,
     pub closed_request_handlers_total: IntCounter,
+    pub endpoint_rebinds_total: IntCounterVec,
+    pub connection_migrations_total: IntCounter,
+    pub drained_connections_total: IntCounterVec,
+    pub health_check_consecutive_failures: IntGaugeVec,
+    pub health_check_latency_seconds: GaugeVec,
+    pub health_check_evictions_total: IntCounter,
+    pub quarantine_refused_connections_total: IntCounter,
+    pub attestation_failures_tolerated_total: IntCounter,
+    pub cert_rotation_cycled_connections_total: IntCounter,
     // Request handler
     pub request_task_monitor: TaskMonitor,
     pub request_handle_errors_total: IntCounterVec,
     pub request_handle_bytes_received_total: IntCounterVec,
     pub request_handle_bytes_sent_total: IntCounterVec,
     pub request_handle_duration_seconds: HistogramVec,
+    pub request_handle_datagrams_dropped_total: IntCounterVec,
+    pub request_handle_cancelled_total: IntCounterVec,
     // Connection handle
     pub connection_handle_bytes_received_total: IntCounterVec,
     pub connection_handle_bytes_sent_total: IntCounterVec,
     pub connection_handle_duration_seconds: HistogramVec,
     pub connection_handle_errors_total: IntCounterVec,
+    pub connection_handle_datagrams_dropped_total: IntCounterVec,
+    pub rate_limit_throttled_bytes_total: IntCounter,
+    pub connection_handle_inflight_rpcs: IntGaugeVec,
+    pub connection_handle_traffic_class_requests_total: IntCounterVec,
+    pub connection_handle_traffic_class_inflight_rpcs: IntGaugeVec,
+    pub connection_handle_traffic_class_overloaded_total: IntCounterVec,
+    // Both directions. The duration of requests is recorded by the request handler and the
+    // connection handle histograms above.
+    pub request_size_bytes: HistogramVec,
+    pub response_size_bytes: HistogramVec,
     // Quinn
     quinn_path_rtt_seconds: GaugeVec,
     quinn_path_congestion_window: IntGaugeVec,
@@ -110,6 +155,46 @@ impl QuicTransportMetrics {
                 "quic_transport_closed_request_handler_total",
                 "Number of closed request handlers.",
             ),
+            endpoint_rebinds_total: metrics_registry.int_counter_vec(
+                "quic_transport_endpoint_rebinds_total",
+                "Moves of the endpoint to a new local address.",
+                &[CONNECTION_RESULT_LABEL],
+            ),
+            connection_migrations_total: metrics_registry.int_counter(
+                "quic_transport_connection_migrations_total",
+                "Number of observed changes of the remote address of connections.",
+            ),
+            drained_connections_total: metrics_registry.int_counter_vec(
+                "quic_transport_drained_connections_total",
+                "Connections to removed peers closed after draining, by whether their requests completed.",
+                &[CONNECTION_RESULT_LABEL],
+            ),
+            health_check_consecutive_failures: metrics_registry.int_gauge_vec(
+                "quic_transport_health_check_consecutive_failures",
+                "Health checks of the peer that failed since the last successful one.",
+                &[PEER_ID_LABEL],
+            ),
+            health_check_latency_seconds: metrics_registry.gauge_vec(
+                "quic_transport_health_check_latency_seconds",
+                "Latency of the last successful health check of the peer.",
+                &[PEER_ID_LABEL],
+            ),
+            health_check_evictions_total: metrics_registry.int_counter(
+                "quic_transport_health_check_evictions_total",
+                "Connections closed because of failed health checks.",
+            ),
+            quarantine_refused_connections_total: metrics_registry.int_counter(
+                "quic_transport_quarantine_refused_connections_total",
+                "Connections closed after the handshake because the peer is quarantined.",
+            ),
+            attestation_failures_tolerated_total: metrics_registry.int_counter(
+                "quic_transport_attestation_failures_tolerated_total",
+                "Peers that failed the attestation, but were accepted since it is optional.",
+            ),
+            cert_rotation_cycled_connections_total: metrics_registry.int_counter(
+                "quic_transport_cert_rotation_cycled_connections_total",
+                "Connections closed because a TLS certificate rotated in the registry.",
+            ),
             // Request handler
             request_task_monitor,
             request_handle_errors_total: metrics_registry.int_counter_vec(
@@ -133,6 +218,16 @@ impl QuicTransportMetrics {
                 decimal_buckets(-2, 0),
                 &[HANDLER_LABEL],
             ),
+            request_handle_datagrams_dropped_total: metrics_registry.int_counter_vec(
+                "quic_transport_request_handle_datagrams_dropped_total",
+                "Received datagrams that were dropped before reaching a handler, by reason.",
+                &[DROP_REASON_LABEL],
+            ),
+            request_handle_cancelled_total: metrics_registry.int_counter_vec(
+                "quic_transport_request_handle_cancelled_total",
+                "Requests cancelled by the peer before they were handled, by stream type.",
+                &[STREAM_TYPE_LABEL],
+            ),
             // Connection handler
             connection_handle_bytes_received_total: metrics_registry.int_counter_vec(
                 "quic_transport_connection_handle_bytes_received_total",
@@ -155,6 +250,50 @@ impl QuicTransportMetrics {
                 "Request handler errors by stream type and error type.",
                 &[REQUEST_TYPE_LABEL, ERROR_TYPE_LABEL],
             ),
+            connection_handle_datagrams_dropped_total: metrics_registry.int_counter_vec(
+                "quic_transport_connection_handle_datagrams_dropped_total",
+                "Unreliable pushes that were dropped before being sent, by reason.",
+                &[DROP_REASON_LABEL],
+            ),
+            rate_limit_throttled_bytes_total: metrics_registry.int_counter(
+                "quic_transport_rate_limit_throttled_bytes_total",
+                "Bytes sent to peers that were delayed by the bandwidth limit.",
+            ),
+            connection_handle_inflight_rpcs: metrics_registry.int_gauge_vec(
+                "quic_transport_connection_handle_inflight_rpcs",
+                "Rpcs to the peer that are in flight.",
+                &[PEER_ID_LABEL],
+            ),
+            connection_handle_traffic_class_requests_total: metrics_registry.int_counter_vec(
+                "quic_transport_connection_handle_traffic_class_requests_total",
+                "Requests sent to peers by request type and traffic class.",
+                &[REQUEST_TYPE_LABEL, TRAFFIC_CLASS_LABEL],
+            ),
+            connection_handle_traffic_class_inflight_rpcs: metrics_registry.int_gauge_vec(
+                "quic_transport_connection_handle_traffic_class_inflight_rpcs",
+                "Rpcs to all peers that are in flight, by traffic class.",
+                &[TRAFFIC_CLASS_LABEL],
+            ),
+            connection_handle_traffic_class_overloaded_total: metrics_registry.int_counter_vec(
+                "quic_transport_connection_handle_traffic_class_overloaded_total",
+                "Rpcs rejected because too many rpcs of the traffic class were in flight.",
+                &[TRAFFIC_CLASS_LABEL],
+            ),
+            // Both directions
+            request_size_bytes: metrics_registry.histogram_vec(
+                "quic_transport_request_size_bytes",
+                "Size of request bodies by handler and direction.",
+                // 100B - 500MB
+                decimal_buckets(2, 8),
+                &[HANDLER_LABEL, DIRECTION_LABEL],
+            ),
+            response_size_bytes: metrics_registry.histogram_vec(
+                "quic_transport_response_size_bytes",
+                "Size of response bodies by handler and direction.",
+                // 100B - 500MB
+                decimal_buckets(2, 8),
+                &[HANDLER_LABEL, DIRECTION_LABEL],
+            ),
 
             // Quinn stats
             quinn_path_rtt_seconds: metrics_registry.gauge_vec(
@@ -0,0 +1,121 @@
+//! Quic Transport outgoing request middleware.
+//!
+//! Incoming requests are handled by an axum Router, which can be extended with any tower
+//! layer. Outgoing requests can be extended the same way:
+//!  - Sending a request to a peer is a tower service, `OutgoingService`, that returns the
+//!    response of the peer. For pushes it returns an empty response once the request was sent.
+//!  - Layers added to `OutgoingLayers` wrap this service for every `rpc` and `push`, e.g. to add
+//!    headers, compress bodies or log requests. The layer that was added first is the
+//!    outermost one.
+//!  - `rpc_stream`, `push_unreliable` and channels bypass the layers.
+//!
+use std::{fmt::Debug, sync::Arc};
+
+use axum::http::{Request, Response};
+use bytes::Bytes;
+use tower::{util::BoxCloneService, Layer, Service};
+
+use crate::SendError;
+
+/// Service that sends a request to a peer.
+pub type OutgoingService = BoxCloneService<Request<Bytes>, Response<Bytes>, SendError>;
+
+type BoxLayer = Arc<dyn Fn(OutgoingService) -> OutgoingService + Send + Sync>;
+
+/// Layers applied to outgoing requests.
+#[derive(Clone, Default)]
+pub struct OutgoingLayers {
+    layers: Vec<BoxLayer>,
+}
+
+impl OutgoingLayers {
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<OutgoingService> + Send + Sync + 'static,
+        L::Service: Service<Request<Bytes>, Response = Response<Bytes>, Error = SendError>
+            + Clone
+            + Send
+            + 'static,
+        <L::Service as Service<Request<Bytes>>>::Future: Send + 'static,
+    {
+        self.layers
+            .push(Arc::new(move |svc| BoxCloneService::new(layer.layer(svc))));
+        self
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.layers.is_empty()
+    }
+
+    pub(crate) fn wrap(&self, svc: OutgoingService) -> OutgoingService {
+        self.layers.iter().rev().fold(svc, |svc, layer| layer(svc))
+    }
+}
+
+impl Debug for OutgoingLayers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OutgoingLayers")
+            .field("len", &self.layers.len())
+            .finish()
+    }
+}
+
+/// Layers are equal if they are the same instances.
+impl PartialEq for OutgoingLayers {
+    fn eq(&self, other: &Self) -> bool {
+        self.layers.len() == other.layers.len()
+            && self
+                .layers
+                .iter()
+                .zip(&other.layers)
+                .all(|(a, b)| Arc::as_ptr(a) as *const () == Arc::as_ptr(b) as *const ())
+    }
+}
+
+impl Eq for OutgoingLayers {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn first_layer_is_outermost() {
+        let layers = OutgoingLayers::default()
+            .layer(tower::util::MapRequestLayer::new(
+                |mut request: Request<Bytes>| {
+                    request
+                        .headers_mut()
+                        .append("x-layer", HeaderValue::from_static("first"));
+                    request
+                },
+            ))
+            .layer(tower::util::MapRequestLayer::new(
+                |mut request: Request<Bytes>| {
+                    request
+                        .headers_mut()
+                        .append("x-layer", HeaderValue::from_static("second"));
+                    request
+                },
+            ));
+
+        // Echoes the headers added by the layers.
+        let send = BoxCloneService::new(service_fn(|request: Request<Bytes>| async move {
+            let layers: Vec<_> = request
+                .headers()
+                .get_all("x-layer")
+                .iter()
+                .map(|value| value.to_str().unwrap().to_string())
+                .collect();
+            Ok::<_, SendError>(Response::new(Bytes::from(layers.join(","))))
+        }));
+
+        let response = layers
+            .wrap(send)
+            .oneshot(Request::new(Bytes::new()))
+            .await
+            .unwrap();
+        assert_eq!(response.body(), "first,second");
+    }
+}
@@ -0,0 +1,172 @@
+//! Quic Transport peer selection.
+//!
+//! `Transport::rpc_any` sends a request to any connected peer, e.g. to fetch an artifact that
+//! all peers have. The `PeerSelector` decides the order in which the peers are tried:
+//!  - Round robin spreads requests evenly over the peers. Clones of a selector share their
+//!    position.
+//!  - Lowest RTT prefers close peers, see `Transport::peer_rtt`.
+//!  - Least in-flight prefers the peers with the fewest rpcs in flight, see
+//!    `Transport::peer_inflight_rpcs`.
+//! Peers without RTT or in-flight rpcs are tried last. If the request could not be sent to a
+//! peer, i.e. it failed with `SendError::ConnectionUnavailable` or `SendError::Overloaded`, the
+//! next peer is tried. Other errors, and responses with any status code, are returned.
+//!
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use ic_base_types::NodeId;
+
+use crate::{SendError, Transport};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Strategy {
+    RoundRobin,
+    LowestRtt,
+    LeastInflight,
+}
+
+/// Order in which `Transport::rpc_any` tries the connected peers.
+#[derive(Clone, Debug)]
+pub struct PeerSelector {
+    strategy: Strategy,
+    /// Position of round robin selection.
+    next: Arc<AtomicUsize>,
+}
+
+impl PeerSelector {
+    fn new(strategy: Strategy) -> Self {
+        Self {
+            strategy,
+            next: Arc::default(),
+        }
+    }
+
+    pub fn round_robin() -> Self {
+        Self::new(Strategy::RoundRobin)
+    }
+
+    pub fn lowest_rtt() -> Self {
+        Self::new(Strategy::LowestRtt)
+    }
+
+    pub fn least_inflight() -> Self {
+        Self::new(Strategy::LeastInflight)
+    }
+
+    /// Orders the peers, the preferred peer first.
+    pub(crate) fn order<T: Transport + ?Sized>(
+        &self,
+        transport: &T,
+        mut peers: Vec<NodeId>,
+    ) -> Vec<NodeId> {
+        match self.strategy {
+            Strategy::RoundRobin => {
+                peers.sort();
+                if !peers.is_empty() {
+                    let start = self.next.fetch_add(1, Ordering::Relaxed) % peers.len();
+                    peers.rotate_left(start);
+                }
+            }
+            Strategy::LowestRtt => peers
+                .sort_by_cached_key(|peer_id| transport.peer_rtt(peer_id).unwrap_or(Duration::MAX)),
+            Strategy::LeastInflight => peers.sort_by_cached_key(|peer_id| {
+                transport.peer_inflight_rpcs(peer_id).unwrap_or(usize::MAX)
+            }),
+        }
+        peers
+    }
+}
+
+/// Whether the request was not sent to the peer, so that another peer can be tried.
+pub(crate) fn is_unsent(err: &SendError) -> bool {
+    matches!(
+        err,
+        SendError::ConnectionUnavailable(_) | SendError::Overloaded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::ConnId;
+    use async_trait::async_trait;
+    use axum::http::{Request, Response};
+    use bytes::Bytes;
+    use ic_base_types::PrincipalId;
+
+    fn node(id: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(id))
+    }
+
+    /// Transport that only knows the RTT and in-flight rpcs of its peers.
+    struct PeerInfo {
+        rtts: HashMap<NodeId, Duration>,
+        inflight_rpcs: HashMap<NodeId, usize>,
+    }
+
+    #[async_trait]
+    impl Transport for PeerInfo {
+        async fn rpc(
+            &self,
+            _peer_id: &NodeId,
+            _request: Request<Bytes>,
+        ) -> Result<Response<Bytes>, SendError> {
+            unimplemented!()
+        }
+
+        async fn push(&self, _peer_id: &NodeId, _request: Request<Bytes>) -> Result<(), SendError> {
+            unimplemented!()
+        }
+
+        fn peers(&self) -> Vec<(NodeId, ConnId)> {
+            unimplemented!()
+        }
+
+        fn peer_rtt(&self, peer_id: &NodeId) -> Option<Duration> {
+            self.rtts.get(peer_id).copied()
+        }
+
+        fn peer_inflight_rpcs(&self, peer_id: &NodeId) -> Option<usize> {
+            self.inflight_rpcs.get(peer_id).copied()
+        }
+    }
+
+    #[test]
+    fn peers_are_ordered_by_strategy() {
+        let transport = PeerInfo {
+            rtts: HashMap::from([
+                (node(1), Duration::from_millis(30)),
+                (node(2), Duration::from_millis(10)),
+            ]),
+            inflight_rpcs: HashMap::from([(node(1), 0), (node(3), 5)]),
+        };
+        let peers = vec![node(1), node(2), node(3)];
+
+        let round_robin = PeerSelector::round_robin();
+        let shared = round_robin.clone();
+        assert_eq!(
+            round_robin.order(&transport, peers.clone()),
+            vec![node(1), node(2), node(3)]
+        );
+        assert_eq!(
+            shared.order(&transport, peers.clone()),
+            vec![node(2), node(3), node(1)]
+        );
+
+        assert_eq!(
+            PeerSelector::lowest_rtt().order(&transport, peers.clone()),
+            vec![node(2), node(1), node(3)]
+        );
+        assert_eq!(
+            PeerSelector::least_inflight().order(&transport, peers),
+            vec![node(1), node(3), node(2)]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Quic Transport stream priorities.
+//!
+//! All streams of a connection share its congestion window. Quinn sends data of streams with
+//! a higher priority first, so that e.g. latency-sensitive requests are not starved by bulk
+//! transfers on the same connection.
+//!
+//!  - The priority of a stream is determined by the URI of the request it carries. Mappings
+//!    from URI prefixes to priorities are registered when transport is started.
+//!  - Outgoing requests can override the mapping with the `StreamPriority` extension, or with
+//!    the priority of their `TrafficClass` extension, see traffic_class.rs.
+//!  - The same priority is used for the request and for its response, since the peer looks
+//!    it up with the same URI and traffic class. Streams that match no prefix have priority 0.
+//!
+use std::collections::BTreeMap;
+
+use axum::http::{Request, Uri};
+
+use crate::traffic_class::{traffic_class, TrafficClass, TrafficClasses};
+
+/// Priority of a stream. Streams with a higher priority are sent first.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct StreamPriority(pub i32);
+
+/// Mapping from URI path prefixes to stream priorities.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StreamPriorities {
+    prefixes: Vec<(String, StreamPriority)>,
+    classes: BTreeMap<TrafficClass, StreamPriority>,
+}
+
+impl StreamPriorities {
+    /// Assigns `priority` to all requests whose URI path starts with `prefix`. If several
+    /// prefixes match, the longest one wins.
+    pub fn with_prefix(mut self, prefix: impl Into<String>, priority: StreamPriority) -> Self {
+        self.prefixes.push((prefix.into(), priority));
+        self
+    }
+
+    /// Assigns the priorities of the traffic classes to requests tagged with them.
+    pub(crate) fn with_traffic_classes(mut self, classes: &TrafficClasses) -> Self {
+        self.classes = TrafficClass::ALL
+            .into_iter()
+            .map(|class| (class, classes.get(class).priority))
+            .collect();
+        self
+    }
+
+    pub(crate) fn for_uri(&self, uri: &Uri) -> StreamPriority {
+        self.prefixes
+            .iter()
+            .filter(|(prefix, _)| uri.path().starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, priority)| *priority)
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn for_request<B>(&self, request: &Request<B>) -> StreamPriority {
+        request
+            .extensions()
+            .get::<StreamPriority>()
+            .copied()
+            .or_else(|| self.classes.get(&traffic_class(request)?).copied())
+            .unwrap_or_else(|| self.for_uri(request.uri()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn longest_prefix_wins() {
+        let priorities = StreamPriorities::default()
+            .with_prefix("/state-sync", StreamPriority(-1))
+            .with_prefix("/state-sync/advert", StreamPriority(1));
+
+        let priority = |uri: &'static str| priorities.for_uri(&Uri::from_static(uri));
+        assert_eq!(priority("/state-sync/chunk"), StreamPriority(-1));
+        assert_eq!(priority("/state-sync/advert"), StreamPriority(1));
+        assert_eq!(priority("/consensus/update"), StreamPriority(0));
+
+        // Annotations of requests take precedence.
+        let mut request = Request::builder()
+            .uri("/state-sync/chunk")
+            .body(())
+            .unwrap();
+        request.extensions_mut().insert(StreamPriority(5));
+        assert_eq!(priorities.for_request(&request), StreamPriority(5));
+    }
+
+    #[test]
+    fn traffic_class_overrides_prefix() {
+        let priorities = StreamPriorities::default()
+            .with_prefix("/state-sync", StreamPriority(-1))
+            .with_traffic_classes(&TrafficClasses::default());
+
+        let mut request = Request::builder()
+            .uri("/state-sync/chunk")
+            .body(())
+            .unwrap();
+        assert_eq!(priorities.for_request(&request), StreamPriority(-1));
+        request.extensions_mut().insert(TrafficClass::Control);
+        assert_eq!(
+            priorities.for_request(&request),
+            TrafficClasses::default().control.priority
+        );
+        request.extensions_mut().insert(StreamPriority(5));
+        assert_eq!(priorities.for_request(&request), StreamPriority(5));
+    }
+}
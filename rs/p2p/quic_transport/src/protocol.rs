@@ -0,0 +1,89 @@
+//! Quic Transport application protocol negotiation.
+//!
+//! Peers announce the version of the application protocol they speak, and a bitmap of the
+//! optional features they support, in the gruezi handshake. This lets P2P protocols roll out
+//! wire format changes without out-of-band coordination:
+//!  - The negotiated version of a connection is the lower version of the two peers, the
+//!    negotiated features are those that both peers support.
+//!  - Peers that do not announce a version are assumed to speak version 0 without features.
+//!    That includes all connections if `announce_features` is not set, unless the peer
+//!    announced its features.
+//!  - The negotiated protocol is available with `Transport::peer_protocol`.
+//!
+/// Prefixes of the handshake tokens that announce the version and the features.
+const VERSION_PREFIX: &str = "version=";
+const FEATURES_PREFIX: &str = "features=";
+
+/// Application protocol version and optional features, defined by the users of transport.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ProtocolVersion {
+    pub version: u32,
+    /// Bit `i` is set if feature `i` is supported.
+    pub features: u64,
+}
+
+impl ProtocolVersion {
+    /// Whether feature `bit` is supported.
+    pub fn supports(&self, bit: u32) -> bool {
+        bit < u64::BITS && self.features & (1 << bit) != 0
+    }
+
+    /// Protocol both peers support.
+    pub(crate) fn negotiate(&self, peer: &Self) -> Self {
+        Self {
+            version: self.version.min(peer.version),
+            features: self.features & peer.features,
+        }
+    }
+
+    /// Tokens announced in the handshake.
+    pub(crate) fn encode(&self) -> String {
+        format!(
+            "{}{} {}{:x}",
+            VERSION_PREFIX, self.version, FEATURES_PREFIX, self.features
+        )
+    }
+
+    /// Protocol announced with the given handshake tokens. Malformed tokens are ignored.
+    pub(crate) fn decode<'a>(tokens: impl Iterator<Item = &'a str>) -> Self {
+        let mut protocol = Self::default();
+        for token in tokens {
+            if let Some(version) = token.strip_prefix(VERSION_PREFIX) {
+                protocol.version = version.parse().unwrap_or_default();
+            } else if let Some(features) = token.strip_prefix(FEATURES_PREFIX) {
+                protocol.features = u64::from_str_radix(features, 16).unwrap_or_default();
+            }
+        }
+        protocol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_common_protocol() {
+        let local = ProtocolVersion {
+            version: 3,
+            features: 0b1011,
+        };
+        let announced = local.encode();
+        let peer = ProtocolVersion::decode(format!("zstd {}", announced).split_whitespace());
+        assert_eq!(peer, local);
+
+        let peer = ProtocolVersion {
+            version: 2,
+            features: 0b0110,
+        };
+        let negotiated = local.negotiate(&peer);
+        assert_eq!(negotiated.version, 2);
+        assert!(negotiated.supports(1));
+        assert!(!negotiated.supports(0));
+        assert!(!negotiated.supports(64));
+
+        // Peers that do not announce a protocol.
+        let legacy = ProtocolVersion::decode("zstd".split_whitespace());
+        assert_eq!(local.negotiate(&legacy), ProtocolVersion::default());
+    }
+}
@@ -0,0 +1,268 @@
+//! Quic Transport publish/subscribe.
+//!
+//! Protocols that send the same kind of message to all interested peers, e.g. adverts, can use
+//! topics instead of tracking the interested peers themselves:
+//!  - `QuicTransport::subscribe` subscribes this node to a topic. Transport announces the set of
+//!    topics this node subscribed to with a push to `SUBSCRIPTIONS_URI` of all connected peers.
+//!  - The announcement is repeated on each new connection, so peers learn the subscriptions of
+//!    nodes that reconnected or restarted. Announcements carry a version, so that a delayed
+//!    announcement does not override a newer one.
+//!  - `QuicTransport::publish` pushes the message to `PUBLISH_URI` of all connected peers that
+//!    subscribed to the topic.
+//!  - Received messages are delivered to all local subscribers of the topic. Messages for
+//!    subscribers that do not keep up are dropped.
+//!
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use axum::{
+    http::{Request, StatusCode},
+    routing::any,
+    Extension, Router,
+};
+use bytes::Bytes;
+use ic_base_types::NodeId;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+pub(crate) const SUBSCRIPTIONS_URI: &str = "/_quic_transport/pubsub/subscriptions";
+pub(crate) const PUBLISH_URI: &str = "/_quic_transport/pubsub/publish";
+
+/// Time after which announcing the subscriptions to a peer is given up. The peer receives
+/// them again once it reconnects.
+pub(crate) const ANNOUNCEMENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of received messages buffered for each local subscriber.
+const SUBSCRIBER_CAPACITY: usize = 1_000;
+
+/// Topics a node subscribed to.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Announcement {
+    /// Random per process, so that the versions of a restarted node are not compared with the
+    /// versions of its previous process.
+    epoch: u64,
+    version: u64,
+    topics: BTreeSet<String>,
+}
+
+/// A message published to a topic.
+#[derive(Serialize, Deserialize)]
+struct Publication<'a> {
+    topic: &'a str,
+    #[serde(with = "serde_bytes")]
+    payload: &'a [u8],
+}
+
+impl Announcement {
+    fn supersedes(&self, other: &Announcement) -> bool {
+        self.epoch != other.epoch || self.version > other.version
+    }
+}
+
+#[derive(Default)]
+struct LocalSubscriptions {
+    version: u64,
+    subscribers: HashMap<String, Vec<mpsc::Sender<(NodeId, Bytes)>>>,
+}
+
+/// Subscriptions of this node and of its peers, shared between transport and the connection
+/// manager.
+#[derive(Clone)]
+pub(crate) struct PubSub {
+    epoch: u64,
+    local: Arc<Mutex<LocalSubscriptions>>,
+    peers: Arc<RwLock<HashMap<NodeId, Announcement>>>,
+}
+
+impl Default for PubSub {
+    fn default() -> Self {
+        Self {
+            epoch: rand::random(),
+            local: Arc::default(),
+            peers: Arc::default(),
+        }
+    }
+}
+
+impl PubSub {
+    /// Adds a local subscriber of the topic. Also returns the announcement to send to peers if
+    /// the topic is new.
+    pub(crate) fn subscribe(
+        &self,
+        topic: String,
+    ) -> (mpsc::Receiver<(NodeId, Bytes)>, Option<Request<Bytes>>) {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY);
+        let mut local = self.local.lock().unwrap();
+        let subscribers = local.subscribers.entry(topic).or_default();
+        let is_new = subscribers.is_empty();
+        subscribers.push(tx);
+        let announcement = is_new.then(|| self.next_announcement(&mut local));
+        (rx, announcement)
+    }
+
+    /// Removes all local subscribers of the topic. Returns the announcement to send to peers if
+    /// this node was subscribed to the topic.
+    pub(crate) fn unsubscribe(&self, topic: &str) -> Option<Request<Bytes>> {
+        let mut local = self.local.lock().unwrap();
+        local.subscribers.remove(topic)?;
+        Some(self.next_announcement(&mut local))
+    }
+
+    /// Announcement of the current subscriptions, for peers that just connected.
+    pub(crate) fn announcement(&self) -> Request<Bytes> {
+        let local = self.local.lock().unwrap();
+        self.encode_announcement(&local)
+    }
+
+    fn next_announcement(&self, local: &mut LocalSubscriptions) -> Request<Bytes> {
+        local.version += 1;
+        self.encode_announcement(local)
+    }
+
+    fn encode_announcement(&self, local: &LocalSubscriptions) -> Request<Bytes> {
+        let announcement = Announcement {
+            epoch: self.epoch,
+            version: local.version,
+            topics: local.subscribers.keys().cloned().collect(),
+        };
+        let body = bincode::serialize(&announcement).expect("Announcements can be serialized");
+        Request::builder()
+            .uri(SUBSCRIPTIONS_URI)
+            .body(Bytes::from(body))
+            .expect("Request is valid")
+    }
+
+    /// Whether the peer subscribed to the topic, according to its latest announcement.
+    pub(crate) fn is_subscribed(&self, peer_id: &NodeId, topic: &str) -> bool {
+        self.peers
+            .read()
+            .unwrap()
+            .get(peer_id)
+            .is_some_and(|announcement| announcement.topics.contains(topic))
+    }
+
+    /// Routes announcements and published messages of peers.
+    pub(crate) fn router(&self) -> Router {
+        let pubsub = self.clone();
+        Router::new()
+            .route(
+                SUBSCRIPTIONS_URI,
+                any({
+                    let pubsub = pubsub.clone();
+                    move |Extension(peer_id): Extension<NodeId>, body: Bytes| async move {
+                        pubsub.handle_announcement(peer_id, &body)
+                    }
+                }),
+            )
+            .route(
+                PUBLISH_URI,
+                any(
+                    move |Extension(peer_id): Extension<NodeId>, body: Bytes| async move {
+                        pubsub.deliver(peer_id, &body)
+                    },
+                ),
+            )
+    }
+
+    fn handle_announcement(&self, peer_id: NodeId, body: &[u8]) -> StatusCode {
+        let Ok(announcement) = bincode::deserialize::<Announcement>(body) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        let mut peers = self.peers.write().unwrap();
+        match peers.get(&peer_id) {
+            Some(current) if !announcement.supersedes(current) => {}
+            _ => {
+                peers.insert(peer_id, announcement);
+            }
+        }
+        StatusCode::OK
+    }
+
+    /// Delivers a message published by the peer to the local subscribers of its topic.
+    fn deliver(&self, peer_id: NodeId, body: &[u8]) -> StatusCode {
+        let Ok(publication) = bincode::deserialize::<Publication>(body) else {
+            return StatusCode::BAD_REQUEST;
+        };
+        let mut local = self.local.lock().unwrap();
+        let Some(subscribers) = local.subscribers.get_mut(publication.topic) else {
+            return StatusCode::NOT_FOUND;
+        };
+        let payload = Bytes::copy_from_slice(publication.payload);
+        subscribers.retain(
+            |subscriber| match subscriber.try_send((peer_id, payload.clone())) {
+                Ok(()) | Err(TrySendError::Full(_)) => true,
+                Err(TrySendError::Closed(_)) => false,
+            },
+        );
+        StatusCode::OK
+    }
+}
+
+/// Request that publishes the payload to the topic.
+pub(crate) fn publication(topic: &str, payload: &[u8]) -> Request<Bytes> {
+    let body = bincode::serialize(&Publication { topic, payload })
+        .expect("Publications can be serialized");
+    Request::builder()
+        .uri(PUBLISH_URI)
+        .body(Bytes::from(body))
+        .expect("Request is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_base_types::PrincipalId;
+    use tower::ServiceExt;
+
+    fn node(id: u64) -> NodeId {
+        NodeId::from(PrincipalId::new_node_test_id(id))
+    }
+
+    /// Sends the request to the router, as if it was received from `from`.
+    async fn receive(router: &Router, from: NodeId, mut request: Request<Bytes>) -> StatusCode {
+        request.extensions_mut().insert(from);
+        let (parts, body) = request.into_parts();
+        let request = Request::from_parts(parts, axum::body::Body::from(body));
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn published_messages_reach_subscribers() {
+        let publisher = PubSub::default();
+        let subscriber = PubSub::default();
+
+        let (mut rx, first) = subscriber.subscribe("adverts".to_string());
+        let (_other_rx, none) = subscriber.subscribe("adverts".to_string());
+        assert!(none.is_none());
+        assert!(subscriber.unsubscribe("tips").is_none());
+        let (_tips_rx, second) = subscriber.subscribe("tips".to_string());
+
+        let router = publisher.router();
+        assert_eq!(
+            receive(&router, node(2), second.unwrap()).await,
+            StatusCode::OK
+        );
+        // The delayed first announcement does not override the second one.
+        receive(&router, node(2), first.unwrap()).await;
+        assert!(publisher.is_subscribed(&node(2), "adverts"));
+        assert!(publisher.is_subscribed(&node(2), "tips"));
+        assert!(!publisher.is_subscribed(&node(3), "tips"));
+
+        // The announcement of a restarted node replaces the one of its previous process.
+        receive(&router, node(2), PubSub::default().announcement()).await;
+        assert!(!publisher.is_subscribed(&node(2), "adverts"));
+
+        let payload = Bytes::from_static(b"advert");
+        let status = receive(
+            &subscriber.router(),
+            node(1),
+            publication("adverts", &payload),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(rx.recv().await, Some((node(1), payload)));
+    }
+}
@@ -0,0 +1,77 @@
+//! Quic Transport quarantine of misbehaving peers.
+//!
+//! P2P protocols that detect misbehavior of a peer, e.g. invalid artifacts, can quarantine
+//! the peer with `QuicTransport::quarantine_peer`. For the given period, and even if the peer
+//! remains in the topology:
+//!  - The connection to the peer is closed with `QUARANTINED_ERROR_CODE`.
+//!  - The peer is not dialed. The dial is retried once the quarantine expired.
+//!  - Connections of the peer are closed right after the handshake, since the identity of the
+//!    peer is only known after TLS.
+//! Quarantining a peer again extends the quarantine if it ends later.
+//!
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use ic_base_types::NodeId;
+use quinn::VarInt;
+use tokio::time::Instant;
+
+/// Application error code of connections that were closed because the peer is quarantined.
+pub(crate) const QUARANTINED_ERROR_CODE: VarInt = VarInt::from_u32(3);
+
+/// Peers that are quarantined, shared between transport and the connection manager.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Quarantine {
+    /// End of the quarantine of each peer.
+    peers: Arc<RwLock<HashMap<NodeId, Instant>>>,
+}
+
+impl Quarantine {
+    /// Quarantines the peer until `until`.
+    pub(crate) fn insert(&self, peer_id: NodeId, until: Instant) {
+        let mut peers = self.peers.write().unwrap();
+        let end = peers.entry(peer_id).or_insert(until);
+        *end = (*end).max(until);
+    }
+
+    /// Time until the quarantine of the peer ends, `None` if the peer is not quarantined.
+    pub(crate) fn remaining(&self, peer_id: &NodeId, now: Instant) -> Option<Duration> {
+        let mut peers = self.peers.write().unwrap();
+        let remaining = peers.get(peer_id)?.saturating_duration_since(now);
+        if remaining.is_zero() {
+            peers.remove(peer_id);
+            return None;
+        }
+        Some(remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_base_types::PrincipalId;
+
+    #[test]
+    fn quarantine_expires() {
+        let peer = NodeId::from(PrincipalId::new_node_test_id(1));
+        let quarantine = Quarantine::default();
+        let start = Instant::now();
+        assert_eq!(quarantine.remaining(&peer, start), None);
+
+        quarantine.insert(peer, start + Duration::from_secs(10));
+        // Shorter quarantines do not shorten the current one.
+        quarantine.insert(peer, start + Duration::from_secs(1));
+        assert_eq!(
+            quarantine.remaining(&peer, start),
+            Some(Duration::from_secs(10))
+        );
+
+        assert_eq!(
+            quarantine.remaining(&peer, start + Duration::from_secs(10)),
+            None
+        );
+    }
+}
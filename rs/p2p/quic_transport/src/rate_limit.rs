@@ -0,0 +1,111 @@
+//! Quic Transport bandwidth rate limiting.
+//!
+//! Each connection can have a token bucket that limits the bytes sent to the peer, i.e. the
+//! requests sent by the connection handle and the responses written by the request handler.
+//!
+//!  - The bucket holds up to `burst_bytes` tokens and is refilled with `bytes_per_second`
+//!    tokens per second.
+//!  - Sending takes as many tokens as bytes are sent. If there are not enough tokens the
+//!    sender waits until the bucket has been refilled. Messages larger than the burst are
+//!    therefore delayed instead of being rejected.
+//!  - The limit is applied to whole messages before they are written, so data of streamed
+//!    responses, whose length is not known upfront, is not limited.
+//!
+use std::{sync::Mutex, time::Duration};
+
+use prometheus::IntCounter;
+use tokio::time::Instant;
+
+/// Bandwidth limit of the connection to a single peer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimitConfig {
+    pub bytes_per_second: u64,
+    pub burst_bytes: u64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative if senders are waiting for the bucket to be refilled.
+    tokens: f64,
+    last_refill: Instant,
+}
+
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    config: RateLimitConfig,
+    bucket: Mutex<Bucket>,
+    throttled_bytes_total: IntCounter,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: RateLimitConfig, throttled_bytes_total: IntCounter) -> Self {
+        Self {
+            config,
+            bucket: Mutex::new(Bucket {
+                tokens: config.burst_bytes as f64,
+                last_refill: Instant::now(),
+            }),
+            throttled_bytes_total,
+        }
+    }
+
+    /// Takes `bytes` tokens and returns how long the caller has to wait before sending.
+    fn reserve(&self, bytes: u64, now: Instant) -> Duration {
+        let rate = self.config.bytes_per_second.max(1) as f64;
+        let mut bucket = self.bucket.lock().unwrap();
+
+        let refill = now.duration_since(bucket.last_refill).as_secs_f64() * rate;
+        bucket.tokens = (bucket.tokens + refill).min(self.config.burst_bytes as f64);
+        bucket.last_refill = now;
+
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        self.throttled_bytes_total.inc_by(bytes);
+        Duration::from_secs_f64(-bucket.tokens / rate)
+    }
+
+    /// Waits until `bytes` can be sent without exceeding the limit.
+    pub(crate) async fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes, Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sending_beyond_the_burst_waits_for_refill() {
+        let throttled = IntCounter::new("throttled", "throttled").unwrap();
+        let limiter = RateLimiter::new(
+            RateLimitConfig {
+                bytes_per_second: 1_000,
+                burst_bytes: 500,
+            },
+            throttled.clone(),
+        );
+        let start = Instant::now();
+
+        // The burst is available immediately.
+        assert_eq!(limiter.reserve(500, start), Duration::ZERO);
+        // Senders queue up behind each other.
+        assert_eq!(limiter.reserve(1_000, start), Duration::from_secs(1));
+        assert_eq!(limiter.reserve(500, start), Duration::from_millis(1_500));
+        assert_eq!(throttled.get(), 1_500);
+
+        // Refilled tokens first pay off the debt.
+        let now = start + Duration::from_secs(1);
+        assert_eq!(limiter.reserve(500, now), Duration::from_secs(1));
+
+        // The bucket never holds more than the burst.
+        let now = now + Duration::from_secs(10);
+        assert_eq!(limiter.reserve(500, now), Duration::ZERO);
+        assert_eq!(limiter.reserve(250, now), Duration::from_millis(250));
+        assert_eq!(throttled.get(), 2_250);
+    }
+}
@@ -3,42 +3,90 @@
 //! The handler is an event loop that accepts streams and spawns a tokio task for each stream
 //! Each task does the following:
 //!     - Reads a request from the stream. (A single stream carries a single request.)
+//!       Requests that exceed the size limit of their URI are rejected, see message_size.rs.
 //!     - Adds metadata to the request based on the underlying connection.
 //!       E.g. adds the NodeId of the peer as an extension.
-//!     - Calls the router.
-//!     - Writes the response to the wire.
+//!       The deadline of rpc requests, if any, is added as a `Deadline` extension.
+//!       The trace context of the sender, if any, is added as a `TraceContext` extension.
+//!     - Calls the router within a tracing span of the request. The handler is cancelled
+//!       if the peer cancels the request.
+//!     - Writes the response to the wire. Only the status is written if the sender only
+//!       waits for an ack, see ack.rs.
+//! Bidi streams can also open a channel instead, see channel.rs. Uni streams can also open an
+//! ordered lane, whose requests are handled one after the other, see lane.rs.
+//! Datagrams are handled like requests on uni streams, except that each datagram contains
+//! the whole request. Datagrams that arrive while too many others are still being handled
+//! are dropped.
 //!
 //! Please note that the connection manager is responsible for closing connections.
 //!
-use std::time::Duration;
+use std::{sync::Arc, time::Duration};
 
-use axum::Router;
+use axum::{
+    body::{Body, HttpBody},
+    http::{Request, Response, StatusCode},
+    Router,
+};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
 use ic_base_types::NodeId;
 use ic_logger::{info, ReplicaLogger};
 use quinn::{Connection, RecvStream, SendStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
 use tower::ServiceExt;
+use tracing::Instrument;
 
 use crate::{
+    ack::{into_ack, is_ack_requested},
+    channel::{Channel, ChannelUpgrade},
+    compression::CompressionConfig,
+    deadline::decode_deadline,
+    drain::InflightRequests,
+    log_sampling::{info_sampled, EventClass, LogSampler},
+    message_size::MessageSizeLimits,
     metrics::{
-        QuicTransportMetrics, ERROR_TYPE_ACCEPT, ERROR_TYPE_APP, ERROR_TYPE_FINISH,
-        ERROR_TYPE_READ, ERROR_TYPE_WRITE, STREAM_TYPE_BIDI, STREAM_TYPE_UNI,
+        QuicTransportMetrics, DROP_REASON_MALFORMED, DROP_REASON_OVERLOADED, DROP_REASON_TOO_LARGE,
+        ERROR_TYPE_ACCEPT, ERROR_TYPE_APP, ERROR_TYPE_FINISH, ERROR_TYPE_READ,
+        ERROR_TYPE_TOO_LARGE, ERROR_TYPE_WRITE, STREAM_TYPE_BIDI, STREAM_TYPE_CHANNEL,
+        STREAM_TYPE_DATAGRAM, STREAM_TYPE_LANE, STREAM_TYPE_UNI,
+    },
+    priority::StreamPriorities,
+    rate_limit::RateLimiter,
+    trace_context::{decode_trace_context, incoming_span},
+    traffic_class::decode_traffic_class,
+    utils::{
+        decode_request, read_bidi_request, read_limited_frame, read_uni_request,
+        write_channel_response, write_response, BidiRequest, RecvError, UniRequest, WireFormat,
     },
-    utils::{read_request, write_response},
     ConnId,
 };
 
 const QUIC_METRIC_SCRAPE_INTERVAL: Duration = Duration::from_secs(5);
 
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn run_stream_acceptor(
     log: ReplicaLogger,
     peer_id: NodeId,
     conn_id: ConnId,
     connection: Connection,
     metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
     router: Router,
+    max_datagram_size: usize,
+    max_inflight_datagrams: usize,
+    stream_priorities: Arc<StreamPriorities>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    compression: Option<CompressionConfig>,
+    wire_format: WireFormat,
+    size_limits: Arc<MessageSizeLimits>,
+    inflight: InflightRequests,
 ) {
     let mut inflight_requests = tokio::task::JoinSet::new();
+    // Unlike streams, datagrams are not limited by quic, so their handlers are limited here.
+    let datagram_permits = Arc::new(Semaphore::new(max_inflight_datagrams));
     let mut quic_metrics_scrape = tokio::time::interval(QUIC_METRIC_SCRAPE_INTERVAL);
+    let mut remote_address = connection.remote_address();
     // The extreme result of a slow handler is that the stream limit will be reach, hence
     // having buffered up to the stream limit number of messages/requests.
     // A better approach will be to use a router implemented as a tower service and accept
@@ -48,10 +96,23 @@ pub(crate) async fn run_stream_acceptor(
         tokio::select! {
              _ = quic_metrics_scrape.tick() => {
                 metrics.collect_quic_connection_stats(&connection, &peer_id);
+                // The peer migrated the connection, e.g. because its local address changed.
+                if connection.remote_address() != remote_address {
+                    info!(
+                        log,
+                        "Connection to peer {} migrated from {} to {}",
+                        peer_id,
+                        remote_address,
+                        connection.remote_address()
+                    );
+                    metrics.connection_migrations_total.inc();
+                    remote_address = connection.remote_address();
+                }
             }
             uni = connection.accept_uni() => {
                 match uni {
                     Ok(uni_rx) => {
+                        // Tracks in-flight requests itself, since lanes are long-lived.
                         inflight_requests.spawn(
                             metrics.request_task_monitor.instrument(
                                 handle_uni_stream(
@@ -59,7 +120,11 @@ pub(crate) async fn run_stream_acceptor(
                                     peer_id,
                                     conn_id,
                                     metrics.clone(),
+                                    log_sampler.clone(),
                                     router.clone(),
+                                    wire_format,
+                                    size_limits.clone(),
+                                    inflight.clone(),
                                     uni_rx,
                                 )
                             )
@@ -82,17 +147,23 @@ pub(crate) async fn run_stream_acceptor(
                 match bi {
                     Ok((bi_tx, bi_rx)) => {
                         inflight_requests.spawn(
-                            metrics.request_task_monitor.instrument(
+                            metrics.request_task_monitor.instrument(inflight.track(
                                 handle_bi_stream(
                                     log.clone(),
                                     peer_id,
                                     conn_id,
                                     metrics.clone(),
+                                    log_sampler.clone(),
                                     router.clone(),
+                                    stream_priorities.clone(),
+                                    rate_limiter.clone(),
+                                    compression,
+                                    wire_format,
+                                    size_limits.clone(),
                                     bi_tx,
                                     bi_rx
                                 )
-                            )
+                            ))
                         );
                     }
                     Err(e) => {
@@ -108,7 +179,47 @@ pub(crate) async fn run_stream_acceptor(
                     }
                 }
             },
-            _ = connection.read_datagram() => {},
+            datagram = connection.read_datagram() => {
+                match datagram {
+                    Ok(datagram) => {
+                        let Ok(permit) = datagram_permits.clone().try_acquire_owned() else {
+                            metrics
+                                .request_handle_datagrams_dropped_total
+                                .with_label_values(&[DROP_REASON_OVERLOADED])
+                                .inc();
+                            continue;
+                        };
+                        inflight_requests.spawn(
+                            metrics.request_task_monitor.instrument(
+                                handle_datagram(
+                                    log.clone(),
+                                    peer_id,
+                                    conn_id,
+                                    metrics.clone(),
+                                    log_sampler.clone(),
+                                    router.clone(),
+                                    max_datagram_size,
+                                    size_limits.clone(),
+                                    wire_format,
+                                    datagram,
+                                    permit,
+                                )
+                            )
+                        );
+                    }
+                    Err(e) => {
+                        info!(log, "Error reading datagram {}", e.to_string());
+                        metrics
+                            .request_handle_errors_total
+                            .with_label_values(&[
+                                STREAM_TYPE_DATAGRAM,
+                                ERROR_TYPE_ACCEPT,
+                            ])
+                            .inc();
+                        break;
+                    }
+                }
+            },
             Some(completed_request) = inflight_requests.join_next() => {
                 if let Err(err) = completed_request {
                     // Cancelling tasks is ok. Panicking tasks are not.
@@ -124,19 +235,76 @@ pub(crate) async fn run_stream_acceptor(
     inflight_requests.shutdown().await;
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_bi_stream(
     log: ReplicaLogger,
     peer_id: NodeId,
     conn_id: ConnId,
     metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
     router: Router,
+    stream_priorities: Arc<StreamPriorities>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    compression: Option<CompressionConfig>,
+    wire_format: WireFormat,
+    size_limits: Arc<MessageSizeLimits>,
     mut bi_tx: SendStream,
     bi_rx: RecvStream,
 ) {
-    let mut request = match read_request(bi_rx).await {
-        Ok(request) => request,
+    let mut request = match read_bidi_request(bi_rx, &size_limits, wire_format).await {
+        Ok(BidiRequest::Rpc(request)) => request,
+        Ok(BidiRequest::Channel(request, frames)) => {
+            let _ = bi_tx.set_priority(stream_priorities.for_uri(request.uri()).0);
+            handle_channel(
+                log,
+                peer_id,
+                conn_id,
+                metrics,
+                log_sampler,
+                router,
+                wire_format,
+                bi_tx,
+                request,
+                frames,
+            )
+            .await;
+            return;
+        }
+        Err(RecvError::Cancelled) => {
+            metrics
+                .request_handle_cancelled_total
+                .with_label_values(&[STREAM_TYPE_BIDI])
+                .inc();
+            return;
+        }
+        Err(e @ RecvError::TooLarge { .. }) => {
+            info_sampled!(log_sampler, EventClass::RequestError, log, "{}", e);
+            metrics
+                .request_handle_errors_total
+                .with_label_values(&[STREAM_TYPE_BIDI, ERROR_TYPE_TOO_LARGE])
+                .inc();
+            // Reaches the peer only if it already sent the whole request, otherwise its write
+            // fails because the stream was stopped.
+            let response = Response::builder()
+                .status(StatusCode::PAYLOAD_TOO_LARGE)
+                .body(Body::from(e.to_string()))
+                .expect("Response is valid");
+            if write_response(&mut bi_tx, response, None, wire_format)
+                .await
+                .is_ok()
+            {
+                let _ = bi_tx.finish().await;
+            }
+            return;
+        }
         Err(e) => {
-            info!(log, "Failed to read request from bidi stream: {}", e);
+            info_sampled!(
+                log_sampler,
+                EventClass::RequestError,
+                log,
+                "Failed to read request from bidi stream: {}",
+                e
+            );
             metrics
                 .request_handle_errors_total
                 .with_label_values(&[STREAM_TYPE_BIDI, ERROR_TYPE_READ])
@@ -147,15 +315,34 @@ async fn handle_bi_stream(
 
     request.extensions_mut().insert::<NodeId>(peer_id);
     request.extensions_mut().insert::<ConnId>(conn_id);
+    decode_deadline(&mut request);
+    decode_trace_context(&mut request);
+    decode_traffic_class(&mut request);
+
+    // The response is sent with the priority of the request.
+    let _ = bi_tx.set_priority(stream_priorities.for_request(&request).0);
+    let ack_requested = is_ack_requested(&mut request);
+    let span = incoming_span(STREAM_TYPE_BIDI, &peer_id, &request);
 
-    let svc = router.oneshot(request);
+    // The peer stops the stream if the caller is no longer interested in the response, e.g.
+    // because it dropped the rpc future. Dropping the handler future cancels the handler.
+    let svc = router.oneshot(request).instrument(span);
     let stopped = bi_tx.stopped();
     let response = tokio::select! {
         response = svc => response.expect("Infallible"),
         _ = stopped => {
+            metrics
+                .request_handle_cancelled_total
+                .with_label_values(&[STREAM_TYPE_BIDI])
+                .inc();
             return;
         }
     };
+    let response = if ack_requested {
+        into_ack(response)
+    } else {
+        response
+    };
 
     // Record application level errors.
     if !response.status().is_success() {
@@ -165,18 +352,36 @@ async fn handle_bi_stream(
             .inc();
     }
 
+    if let (Some(rate_limiter), Some(body_len)) =
+        (&rate_limiter, response.body().size_hint().exact())
+    {
+        rate_limiter.acquire(body_len).await;
+    }
+
     // We can ignore the errors because if both peers follow the protocol an errors will only occur
     // if the other peer has closed the connection. In this case `accept_bi` in the peer event
     // loop will close this connection.
-    if let Err(e) = write_response(&mut bi_tx, response).await {
-        info!(log, "Failed to write response to stream: {}", e);
+    if let Err(e) = write_response(&mut bi_tx, response, compression.as_ref(), wire_format).await {
+        info_sampled!(
+            log_sampler,
+            EventClass::RequestError,
+            log,
+            "Failed to write response to stream: {}",
+            e
+        );
         metrics
             .request_handle_errors_total
             .with_label_values(&[STREAM_TYPE_BIDI, ERROR_TYPE_WRITE])
             .inc();
     }
     if let Err(e) = bi_tx.finish().await {
-        info!(log, "Failed to finish stream: {}", e.to_string());
+        info_sampled!(
+            log_sampler,
+            EventClass::RequestError,
+            log,
+            "Failed to finish stream: {}",
+            e.to_string()
+        );
         metrics
             .request_handle_errors_total
             .with_label_values(&[STREAM_TYPE_BIDI, ERROR_TYPE_FINISH])
@@ -184,18 +389,120 @@ async fn handle_bi_stream(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+async fn handle_channel(
+    log: ReplicaLogger,
+    peer_id: NodeId,
+    conn_id: ConnId,
+    metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
+    router: Router,
+    wire_format: WireFormat,
+    bi_tx: SendStream,
+    mut request: Request<Body>,
+    frames: FramedRead<RecvStream, LengthDelimitedCodec>,
+) {
+    let upgrade = ChannelUpgrade::default();
+    request.extensions_mut().insert::<NodeId>(peer_id);
+    request.extensions_mut().insert::<ConnId>(conn_id);
+    request.extensions_mut().insert(upgrade.clone());
+
+    let status = router.oneshot(request).await.expect("Infallible").status();
+
+    // Record application level errors.
+    if !status.is_success() {
+        metrics
+            .request_handle_errors_total
+            .with_label_values(&[STREAM_TYPE_CHANNEL, ERROR_TYPE_APP])
+            .inc();
+    }
+
+    let mut sink = match write_channel_response(bi_tx, status, wire_format).await {
+        Ok(sink) => sink,
+        Err(e) => {
+            info_sampled!(
+                log_sampler,
+                EventClass::RequestError,
+                log,
+                "Failed to write channel response to stream: {}",
+                e
+            );
+            metrics
+                .request_handle_errors_total
+                .with_label_values(&[STREAM_TYPE_CHANNEL, ERROR_TYPE_WRITE])
+                .inc();
+            return;
+        }
+    };
+
+    match upgrade.take() {
+        // The handler might have given up on the channel, in which case it is dropped.
+        Some(channel_tx) if status.is_success() => {
+            let _ = channel_tx.send(Channel::new(sink, frames));
+        }
+        _ => {
+            let _ = sink.close().await;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 async fn handle_uni_stream(
     log: ReplicaLogger,
     peer_id: NodeId,
     conn_id: ConnId,
     metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
     router: Router,
+    wire_format: WireFormat,
+    size_limits: Arc<MessageSizeLimits>,
+    inflight: InflightRequests,
     uni_rx: RecvStream,
 ) {
-    let mut request = match read_request(uni_rx).await {
-        Ok(request) => request,
+    let inflight_guard = inflight.start();
+    let request = match read_uni_request(uni_rx, &size_limits, wire_format).await {
+        Ok(UniRequest::Push(request)) => request,
+        Ok(UniRequest::Lane(lane_rx)) => {
+            // Lanes stay open as long as the connection, so only their requests are in flight.
+            drop(inflight_guard);
+            handle_lane(
+                log,
+                peer_id,
+                conn_id,
+                metrics,
+                log_sampler,
+                router,
+                wire_format,
+                size_limits,
+                inflight,
+                lane_rx,
+            )
+            .await;
+            return;
+        }
+        Err(RecvError::Cancelled) => {
+            metrics
+                .request_handle_cancelled_total
+                .with_label_values(&[STREAM_TYPE_UNI])
+                .inc();
+            return;
+        }
+        Err(e @ RecvError::TooLarge { .. }) => {
+            info_sampled!(log_sampler, EventClass::RequestError, log, "{}", e);
+            metrics
+                .request_handle_errors_total
+                .with_label_values(&[STREAM_TYPE_UNI, ERROR_TYPE_TOO_LARGE])
+                .inc();
+            return;
+        }
         Err(e) => {
-            info!(log, "Failed to read request from uni stream: {}", e);
+            info_sampled!(
+                log_sampler,
+                EventClass::RequestError,
+                log,
+                "Failed to read request from uni stream: {}",
+                e
+            );
             metrics
                 .request_handle_errors_total
                 .with_label_values(&[STREAM_TYPE_UNI, ERROR_TYPE_READ])
@@ -204,6 +511,153 @@ async fn handle_uni_stream(
         }
     };
 
+    route_push(peer_id, conn_id, &metrics, router, STREAM_TYPE_UNI, request).await;
+}
+
+/// Handles the requests of an ordered lane one after the other, until the peer closes it.
+#[allow(clippy::too_many_arguments)]
+async fn handle_lane(
+    log: ReplicaLogger,
+    peer_id: NodeId,
+    conn_id: ConnId,
+    metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
+    router: Router,
+    wire_format: WireFormat,
+    size_limits: Arc<MessageSizeLimits>,
+    inflight: InflightRequests,
+    mut lane_rx: RecvStream,
+) {
+    loop {
+        let raw_msg = match read_limited_frame(&mut lane_rx, &size_limits).await {
+            Ok(Some(raw_msg)) => raw_msg,
+            Ok(None) => return,
+            Err(e @ RecvError::TooLarge { .. }) => {
+                info_sampled!(log_sampler, EventClass::RequestError, log, "{}", e);
+                metrics
+                    .request_handle_errors_total
+                    .with_label_values(&[STREAM_TYPE_LANE, ERROR_TYPE_TOO_LARGE])
+                    .inc();
+                return;
+            }
+            Err(e) => {
+                info_sampled!(
+                    log_sampler,
+                    EventClass::RequestError,
+                    log,
+                    "Failed to read request from lane: {}",
+                    e
+                );
+                metrics
+                    .request_handle_errors_total
+                    .with_label_values(&[STREAM_TYPE_LANE, ERROR_TYPE_READ])
+                    .inc();
+                return;
+            }
+        };
+        let _inflight = inflight.start();
+
+        // The framing is intact, so the lane continues with the next request.
+        let request = match decode_request(&raw_msg, &size_limits, wire_format) {
+            Ok(request) => request,
+            Err(e) => {
+                info_sampled!(
+                    log_sampler,
+                    EventClass::RequestError,
+                    log,
+                    "Failed to decode request from lane: {}",
+                    e
+                );
+                metrics
+                    .request_handle_errors_total
+                    .with_label_values(&[STREAM_TYPE_LANE, ERROR_TYPE_READ])
+                    .inc();
+                continue;
+            }
+        };
+
+        route_push(
+            peer_id,
+            conn_id,
+            &metrics,
+            router.clone(),
+            STREAM_TYPE_LANE,
+            request,
+        )
+        .await;
+    }
+}
+
+/// Calls the router with a request that is not answered.
+async fn route_push(
+    peer_id: NodeId,
+    conn_id: ConnId,
+    metrics: &QuicTransportMetrics,
+    router: Router,
+    stream_type: &str,
+    mut request: Request<Body>,
+) {
+    request.extensions_mut().insert::<NodeId>(peer_id);
+    request.extensions_mut().insert::<ConnId>(conn_id);
+    decode_trace_context(&mut request);
+    decode_traffic_class(&mut request);
+    let span = incoming_span(stream_type, &peer_id, &request);
+
+    // Record application level errors.
+    if !router
+        .oneshot(request)
+        .instrument(span)
+        .await
+        .expect("Infallible")
+        .status()
+        .is_success()
+    {
+        metrics
+            .request_handle_errors_total
+            .with_label_values(&[stream_type, ERROR_TYPE_APP])
+            .inc();
+    }
+}
+
+async fn handle_datagram(
+    log: ReplicaLogger,
+    peer_id: NodeId,
+    conn_id: ConnId,
+    metrics: QuicTransportMetrics,
+    log_sampler: LogSampler,
+    router: Router,
+    max_datagram_size: usize,
+    size_limits: Arc<MessageSizeLimits>,
+    wire_format: WireFormat,
+    datagram: Bytes,
+    _permit: OwnedSemaphorePermit,
+) {
+    if datagram.len() > max_datagram_size {
+        metrics
+            .request_handle_datagrams_dropped_total
+            .with_label_values(&[DROP_REASON_TOO_LARGE])
+            .inc();
+        return;
+    }
+
+    let mut request = match decode_request(&datagram, &size_limits, wire_format) {
+        Ok(request) => request,
+        Err(e) => {
+            info_sampled!(
+                log_sampler,
+                EventClass::RequestError,
+                log,
+                "Failed to read request from datagram: {}",
+                e
+            );
+            metrics
+                .request_handle_datagrams_dropped_total
+                .with_label_values(&[DROP_REASON_MALFORMED])
+                .inc();
+            return;
+        }
+    };
+
     request.extensions_mut().insert::<NodeId>(peer_id);
     request.extensions_mut().insert::<ConnId>(conn_id);
 
@@ -217,7 +671,7 @@ async fn handle_uni_stream(
     {
         metrics
             .request_handle_errors_total
-            .with_label_values(&[STREAM_TYPE_UNI, ERROR_TYPE_APP])
+            .with_label_values(&[STREAM_TYPE_DATAGRAM, ERROR_TYPE_APP])
             .inc();
     }
 }
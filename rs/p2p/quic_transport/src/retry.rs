@@ -0,0 +1,152 @@
+//! Quic Transport retries of idempotent rpcs.
+//!
+//! Callers that can safely send a request more than once attach a `RetryPolicy` to it as an
+//! extension. `rpc` then retries failed attempts:
+//!  - Only errors whose kind is in `retry_on` are retried. Other errors, and responses with
+//!    any status code, are returned to the caller.
+//!  - Attempts are separated by an exponential backoff, starting at `initial_backoff` and
+//!    capped at `max_backoff`.
+//!  - Each attempt looks up the connection to the peer again, so that requests that failed
+//!    with `SendError::ConnectionUnavailable` succeed once the peer is reconnected.
+//!  - The `Deadline` of the request, if any, bounds all attempts together.
+//!
+use std::{future::Future, time::Duration};
+
+use axum::http::Request;
+use bytes::Bytes;
+
+use crate::{Deadline, SendError, SendErrorKind};
+
+/// Retry policy of an rpc. Attaching it to a request marks the request as idempotent.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Upper bound on the number of attempts, including the first one.
+    pub max_attempts: usize,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Kinds of errors after which the request is sent again. Defaults to the transient
+    /// kinds, see `SendErrorKind::is_retryable`.
+    pub retry_on: Vec<SendErrorKind>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            retry_on: vec![
+                SendErrorKind::ConnectionUnavailable,
+                SendErrorKind::ConnectionLost,
+                SendErrorKind::Timeout,
+                SendErrorKind::Backpressure,
+                SendErrorKind::Overloaded,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Time to wait after the given failed attempt, counting from 1.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let factor = 2_u32.saturating_pow(attempt.saturating_sub(1) as u32);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+
+    fn should_retry(&self, attempt: usize, err: &SendError) -> bool {
+        attempt < self.max_attempts && self.retry_on.contains(&err.kind())
+    }
+}
+
+/// Sends the request with `send`, and retries according to the policy of the request.
+pub(crate) async fn with_retries<T, F, Fut>(
+    request: Request<Bytes>,
+    send: F,
+) -> Result<T, SendError>
+where
+    F: Fn(Request<Bytes>) -> Fut,
+    Fut: Future<Output = Result<T, SendError>>,
+{
+    let Some(policy) = request.extensions().get::<RetryPolicy>().cloned() else {
+        return send(request).await;
+    };
+    let deadline = request.extensions().get::<Deadline>().copied();
+
+    let mut attempt = 1;
+    loop {
+        match send(request.clone()).await {
+            Err(err) if policy.should_retry(attempt, &err) => {
+                let backoff = policy.backoff(attempt);
+                if deadline.is_some_and(|deadline| deadline.remaining() <= backoff) {
+                    return Err(err);
+                }
+                tokio::time::sleep(backoff).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn backoff_is_exponential_and_capped() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..Default::default()
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(300));
+        assert_eq!(policy.backoff(100), Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn only_retryable_errors_are_retried() {
+        let attempts = AtomicUsize::new(0);
+        let send = |_request: Request<Bytes>| {
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                match attempt {
+                    1 => Err::<(), _>(SendError::ConnectionUnavailable("".to_string())),
+                    2 => Err(SendError::ConnectionLost("".to_string())),
+                    3 => Err(SendError::Timeout),
+                    _ => Err(SendError::Internal("".to_string())),
+                }
+            }
+        };
+
+        // Without a policy the request is only sent once.
+        let result = with_retries(Request::new(Bytes::new()), send).await;
+        assert!(matches!(result, Err(SendError::ConnectionUnavailable(_))));
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 1);
+
+        let mut request = Request::new(Bytes::new());
+        request.extensions_mut().insert(RetryPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(1),
+            ..Default::default()
+        });
+        let result = with_retries(request, send).await;
+        // Internal errors are not retried by default.
+        assert!(matches!(result, Err(SendError::Internal(_))));
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn default_policy_retries_transient_errors() {
+        let policy = RetryPolicy::default();
+        assert!(policy.retry_on.iter().all(SendErrorKind::is_retryable));
+        assert!(!SendError::PeerNotInTopology.is_retryable());
+        assert!(!SendError::MessageTooLarge.is_retryable());
+        assert!(SendError::Overloaded.is_retryable());
+    }
+}
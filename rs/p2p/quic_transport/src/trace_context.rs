@@ -0,0 +1,221 @@
+//! Quic Transport trace context propagation.
+//!
+//! Callers can attach a `TraceContext` to a request as an extension, e.g. to correlate the
+//! handling of a consensus artifact across replicas. Transport then
+//!  - sends the context to the peer in the W3C `traceparent` header, for `rpc`, `rpc_stream`
+//!    and `push`.
+//!  - runs the send within a `tracing` span that carries the trace and span id of the caller.
+//!  - surfaces a child of the context to the handler on the receiving side as a `TraceContext`
+//!    extension. The handler runs within a `tracing` span that carries the ids of the child,
+//!    and can attach the child to requests it sends in turn.
+//! Requests without a context are sent without the header, but still get a span on both sides.
+//!
+use axum::http::{HeaderValue, Request};
+use ic_base_types::NodeId;
+use tracing::{field::Empty, Span};
+
+/// Header that carries the trace context, see https://www.w3.org/TR/trace-context/.
+pub(crate) const TRACEPARENT_HEADER: &str = "traceparent";
+
+/// Only version of the `traceparent` header defined so far.
+const TRACEPARENT_VERSION: &str = "00";
+
+/// Marks the trace as sampled.
+const TRACEPARENT_FLAGS: &str = "01";
+
+/// Identifies an operation within a trace that can span several nodes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct TraceContext {
+    /// Shared by all operations of the trace. Never zero.
+    pub trace_id: u128,
+    /// Identifies this operation. Never zero.
+    pub span_id: u64,
+    /// Span id of the operation that caused this one, if any.
+    pub parent_span_id: Option<u64>,
+}
+
+impl TraceContext {
+    /// Starts a new trace.
+    pub fn new_root() -> Self {
+        Self {
+            trace_id: non_zero_random(),
+            span_id: non_zero_random(),
+            parent_span_id: None,
+        }
+    }
+
+    /// Context of an operation that is caused by this one.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id,
+            span_id: non_zero_random(),
+            parent_span_id: Some(self.span_id),
+        }
+    }
+
+    fn to_header(self) -> HeaderValue {
+        HeaderValue::try_from(format!(
+            "{}-{:032x}-{:016x}-{}",
+            TRACEPARENT_VERSION, self.trace_id, self.span_id, TRACEPARENT_FLAGS
+        ))
+        .expect("Hex digits and dashes are valid header characters")
+    }
+
+    /// Parses the header sent by the parent. Malformed headers are ignored.
+    fn from_header(value: &HeaderValue) -> Option<Self> {
+        let mut parts = value.to_str().ok()?.split('-');
+        let (version, trace_id, span_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if parts.next().is_some()
+            || version != TRACEPARENT_VERSION
+            || trace_id.len() != 32
+            || span_id.len() != 16
+            || flags.len() != 2
+        {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16).ok()?;
+        let span_id = u64::from_str_radix(span_id, 16).ok()?;
+        if trace_id == 0 || span_id == 0 {
+            return None;
+        }
+        Some(Self {
+            trace_id,
+            span_id,
+            parent_span_id: None,
+        })
+    }
+
+    fn record(&self, span: &Span) {
+        span.record("trace_id", format!("{:032x}", self.trace_id));
+        span.record("span_id", format!("{:016x}", self.span_id));
+        if let Some(parent_span_id) = self.parent_span_id {
+            span.record("parent_span_id", format!("{:016x}", parent_span_id));
+        }
+    }
+}
+
+fn non_zero_random<T: PartialEq + Default>() -> T
+where
+    rand::distributions::Standard: rand::distributions::Distribution<T>,
+{
+    loop {
+        let id = rand::random();
+        if id != T::default() {
+            return id;
+        }
+    }
+}
+
+/// Encodes the trace context extension of an outgoing request, if any, as a header.
+pub(crate) fn encode_trace_context<B>(request: &mut Request<B>) {
+    if let Some(context) = request.extensions().get::<TraceContext>().copied() {
+        request
+            .headers_mut()
+            .insert(TRACEPARENT_HEADER, context.to_header());
+    }
+}
+
+/// Turns the header of an incoming request, if any, into a trace context extension that is a
+/// child of the context of the sender.
+pub(crate) fn decode_trace_context<B>(request: &mut Request<B>) -> Option<TraceContext> {
+    let context = request
+        .headers_mut()
+        .remove(TRACEPARENT_HEADER)
+        .and_then(|value| TraceContext::from_header(&value))?
+        .child();
+    request.extensions_mut().insert(context);
+    Some(context)
+}
+
+/// Span of sending a request to a peer.
+pub(crate) fn outgoing_span<B>(request_type: &str, peer_id: &NodeId, request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "quic_transport_send",
+        request = request_type,
+        peer = %peer_id,
+        uri = %request.uri().path(),
+        trace_id = Empty,
+        span_id = Empty,
+        parent_span_id = Empty,
+    );
+    if let Some(context) = request.extensions().get::<TraceContext>() {
+        context.record(&span);
+    }
+    span
+}
+
+/// Span of handling a request of a peer. Expects the trace context to be decoded already.
+pub(crate) fn incoming_span<B>(stream_type: &str, peer_id: &NodeId, request: &Request<B>) -> Span {
+    let span = tracing::info_span!(
+        "quic_transport_handle",
+        stream = stream_type,
+        peer = %peer_id,
+        uri = %request.uri().path(),
+        trace_id = Empty,
+        span_id = Empty,
+        parent_span_id = Empty,
+    );
+    if let Some(context) = request.extensions().get::<TraceContext>() {
+        context.record(&span);
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trace_context_survives_encoding() {
+        let mut request = Request::new(());
+        encode_trace_context(&mut request);
+        assert!(request.headers().get(TRACEPARENT_HEADER).is_none());
+
+        let context = TraceContext::new_root();
+        request.extensions_mut().insert(context);
+        encode_trace_context(&mut request);
+
+        // The receiving side only sees the header.
+        let (parts, body) = request.into_parts();
+        let mut received = Request::new(body);
+        *received.headers_mut() = parts.headers;
+        let received_context = decode_trace_context(&mut received).unwrap();
+
+        assert_eq!(received_context.trace_id, context.trace_id);
+        assert_eq!(received_context.parent_span_id, Some(context.span_id));
+        assert_ne!(received_context.span_id, context.span_id);
+        assert_eq!(
+            received.extensions().get::<TraceContext>(),
+            Some(&received_context)
+        );
+        assert!(received.headers().get(TRACEPARENT_HEADER).is_none());
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        for header in [
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473x-00f067aa0ba902b7-01",
+        ] {
+            let mut request = Request::new(());
+            request
+                .headers_mut()
+                .insert(TRACEPARENT_HEADER, HeaderValue::from_static(header));
+            assert_eq!(decode_trace_context(&mut request), None, "{}", header);
+        }
+
+        let mut request = Request::new(());
+        request.headers_mut().insert(
+            TRACEPARENT_HEADER,
+            HeaderValue::from_static("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+        );
+        let context = decode_trace_context(&mut request).unwrap();
+        assert_eq!(context.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(context.parent_span_id, Some(0x00f067aa0ba902b7));
+    }
+}
@@ -0,0 +1,179 @@
+//! Quic Transport traffic classes.
+//!
+//! Callers can tag a request with a `TrafficClass` extension instead of tuning priorities and
+//! limits per URI. The policy of each class is configured in `TrafficClasses`:
+//!  - Streams of the request, and of its response, are sent with the priority of the class.
+//!    A `StreamPriority` extension of the request still takes precedence.
+//!  - Rpcs of a class are admitted by their own in-flight limit per peer, so that e.g. bulk
+//!    transfers can not exhaust the rpcs available to control traffic. Untagged rpcs share
+//!    `QuicTransportConfig::max_inflight_rpcs_per_peer`.
+//!  - Metrics of outgoing requests are labelled with the class, untagged requests with
+//!    `TRAFFIC_CLASS_NONE`.
+//!  - The class is sent in the `TRAFFIC_CLASS_HEADER` header, so that handlers of the peer see
+//!    it as an extension of the request.
+//!
+use std::{collections::HashMap, sync::Arc};
+
+use axum::http::{HeaderValue, Request};
+use tokio::sync::Semaphore;
+
+use crate::{config::DEFAULT_MAX_INFLIGHT_RPCS_PER_PEER, priority::StreamPriority};
+
+/// Header that carries the traffic class of a request.
+pub(crate) const TRAFFIC_CLASS_HEADER: &str = "x-ic-traffic-class";
+
+/// Metrics label of requests without traffic class.
+pub(crate) const TRAFFIC_CLASS_NONE: &str = "none";
+
+/// Kind of traffic a request belongs to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TrafficClass {
+    /// Small, latency-sensitive requests, e.g. health checks.
+    Control,
+    /// Requests of the consensus protocol, e.g. adverts and artifacts.
+    Consensus,
+    /// Large transfers that can wait, e.g. state sync chunks.
+    Bulk,
+}
+
+impl TrafficClass {
+    pub const ALL: [TrafficClass; 3] = [
+        TrafficClass::Control,
+        TrafficClass::Consensus,
+        TrafficClass::Bulk,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TrafficClass::Control => "control",
+            TrafficClass::Consensus => "consensus",
+            TrafficClass::Bulk => "bulk",
+        }
+    }
+
+    fn parse(class: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| c.as_str() == class)
+    }
+}
+
+/// Policy of a traffic class.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct TrafficClassConfig {
+    pub priority: StreamPriority,
+    /// Number of rpcs of the class to a single peer that can be in flight at the same time.
+    /// Further rpcs fail with `SendError::Overloaded`.
+    pub max_inflight_rpcs_per_peer: usize,
+}
+
+/// Policies of all traffic classes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TrafficClasses {
+    pub control: TrafficClassConfig,
+    pub consensus: TrafficClassConfig,
+    pub bulk: TrafficClassConfig,
+}
+
+impl Default for TrafficClasses {
+    fn default() -> Self {
+        Self {
+            control: TrafficClassConfig {
+                priority: StreamPriority(2),
+                max_inflight_rpcs_per_peer: 100,
+            },
+            consensus: TrafficClassConfig {
+                priority: StreamPriority(1),
+                max_inflight_rpcs_per_peer: DEFAULT_MAX_INFLIGHT_RPCS_PER_PEER,
+            },
+            bulk: TrafficClassConfig {
+                priority: StreamPriority(-1),
+                max_inflight_rpcs_per_peer: 100,
+            },
+        }
+    }
+}
+
+impl TrafficClasses {
+    pub fn get(&self, class: TrafficClass) -> &TrafficClassConfig {
+        match class {
+            TrafficClass::Control => &self.control,
+            TrafficClass::Consensus => &self.consensus,
+            TrafficClass::Bulk => &self.bulk,
+        }
+    }
+
+    /// In-flight limits of a connection, one per class.
+    pub(crate) fn rpc_permits(&self) -> HashMap<TrafficClass, Arc<Semaphore>> {
+        TrafficClass::ALL
+            .into_iter()
+            .map(|class| {
+                let limit = self.get(class).max_inflight_rpcs_per_peer;
+                (class, Arc::new(Semaphore::new(limit)))
+            })
+            .collect()
+    }
+}
+
+/// Traffic class of the request, if it was tagged with one.
+pub(crate) fn traffic_class<B>(request: &Request<B>) -> Option<TrafficClass> {
+    request.extensions().get::<TrafficClass>().copied()
+}
+
+/// Metrics label of the traffic class of the request.
+pub(crate) fn traffic_class_label<B>(request: &Request<B>) -> &'static str {
+    traffic_class(request).map_or(TRAFFIC_CLASS_NONE, |class| class.as_str())
+}
+
+/// Encodes the traffic class extension of an outgoing request, if any, as a header.
+pub(crate) fn encode_traffic_class<B>(request: &mut Request<B>) {
+    if let Some(class) = traffic_class(request) {
+        request.headers_mut().insert(
+            TRAFFIC_CLASS_HEADER,
+            HeaderValue::from_static(class.as_str()),
+        );
+    }
+}
+
+/// Turns the header of an incoming request, if any, into a traffic class extension. Unknown
+/// classes are ignored.
+pub(crate) fn decode_traffic_class<B>(request: &mut Request<B>) {
+    let Some(class) = request
+        .headers_mut()
+        .remove(TRAFFIC_CLASS_HEADER)
+        .and_then(|value| TrafficClass::parse(value.to_str().ok()?))
+    else {
+        return;
+    };
+    request.extensions_mut().insert(class);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traffic_class_survives_encoding() {
+        let mut request = Request::new(());
+        encode_traffic_class(&mut request);
+        assert!(request.headers().get(TRAFFIC_CLASS_HEADER).is_none());
+        assert_eq!(traffic_class_label(&request), TRAFFIC_CLASS_NONE);
+
+        request.extensions_mut().insert(TrafficClass::Bulk);
+        encode_traffic_class(&mut request);
+        assert_eq!(traffic_class_label(&request), "bulk");
+
+        // The receiving side only sees the header.
+        let (parts, body) = request.into_parts();
+        let mut received = Request::new(body);
+        *received.headers_mut() = parts.headers;
+        decode_traffic_class(&mut received);
+        assert_eq!(traffic_class(&received), Some(TrafficClass::Bulk));
+        assert!(received.headers().get(TRAFFIC_CLASS_HEADER).is_none());
+
+        let mut unknown = Request::new(());
+        unknown
+            .headers_mut()
+            .insert(TRAFFIC_CLASS_HEADER, HeaderValue::from_static("premium"));
+        decode_traffic_class(&mut unknown);
+        assert_eq!(traffic_class(&unknown), None);
+    }
+}
@@ -0,0 +1,222 @@
+//! Quic Transport typed rpcs.
+//!
+//! Protocols describe each rpc with a `TypedRpc`, i.e. the URI and the types of the request and
+//! the response, instead of encoding `Request<Bytes>` by hand:
+//!  - `typed_rpc!` declares a `TypedRpc`.
+//!  - The sender calls `TypedTransport::rpc`, which encodes the request with a `Codec`, sends
+//!    it to the URI of the rpc and decodes the response.
+//!  - The receiver registers the handler with `TypedRouter::typed_route`, which decodes the
+//!    request and encodes the response with the same `Codec`.
+//! Sender and receiver are both bound to the types of the rpc, so sending a request of the
+//! wrong type, or to the wrong URI, does not compile.
+//!
+//! Requests the receiver can not decode are answered with `StatusCode::BAD_REQUEST`. Responses
+//! that are not successful fail with `SendError::HandlerError`.
+//!
+use std::{future::Future, sync::Arc};
+
+use axum::{
+    http::{Request, StatusCode},
+    routing::any,
+    Extension, Router,
+};
+use bytes::Bytes;
+use ic_base_types::NodeId;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{SendError, Transport};
+
+/// An rpc between peers, i.e. the URI it is routed by and the types it exchanges.
+pub trait TypedRpc: Send + Sync + 'static {
+    const URI: &'static str;
+    type Request: Send + 'static;
+    type Response: Send + 'static;
+}
+
+/// Declares a `TypedRpc`.
+///
+/// ```ignore
+/// typed_rpc!(pub GetChunk: "/state-sync/chunk", pb::ChunkRequest => pb::ChunkResponse);
+/// ```
+#[macro_export]
+macro_rules! typed_rpc {
+    ($(#[$meta:meta])* $vis:vis $name:ident: $uri:literal, $request:ty => $response:ty) => {
+        $(#[$meta])*
+        #[derive(Copy, Clone, Debug)]
+        $vis struct $name;
+
+        impl $crate::TypedRpc for $name {
+            const URI: &'static str = $uri;
+            type Request = $request;
+            type Response = $response;
+        }
+    };
+}
+
+/// Serialization of the messages of typed rpcs.
+pub trait Codec<T>: Clone + Send + Sync + 'static {
+    fn encode(&self, message: &T) -> Result<Bytes, String>;
+    fn decode(&self, bytes: Bytes) -> Result<T, String>;
+}
+
+/// Codec of protobuf messages.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ProstCodec;
+
+impl<T: prost::Message + Default> Codec<T> for ProstCodec {
+    fn encode(&self, message: &T) -> Result<Bytes, String> {
+        Ok(message.encode_to_vec().into())
+    }
+
+    fn decode(&self, bytes: Bytes) -> Result<T, String> {
+        T::decode(bytes).map_err(|err| err.to_string())
+    }
+}
+
+/// Codec of serde types, using bincode.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct BincodeCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, message: &T) -> Result<Bytes, String> {
+        bincode::serialize(message)
+            .map(Bytes::from)
+            .map_err(|err| err.to_string())
+    }
+
+    fn decode(&self, bytes: Bytes) -> Result<T, String> {
+        bincode::deserialize(&bytes).map_err(|err| err.to_string())
+    }
+}
+
+/// Sends typed rpcs over any `Transport`.
+pub struct TypedTransport<C> {
+    transport: Arc<dyn Transport>,
+    codec: C,
+}
+
+impl<C: Clone> Clone for TypedTransport<C> {
+    fn clone(&self) -> Self {
+        Self {
+            transport: self.transport.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+impl<C> TypedTransport<C> {
+    pub fn new(transport: Arc<dyn Transport>, codec: C) -> Self {
+        Self { transport, codec }
+    }
+
+    pub fn transport(&self) -> &Arc<dyn Transport> {
+        &self.transport
+    }
+
+    pub async fn rpc<R>(
+        &self,
+        peer_id: &NodeId,
+        request: R::Request,
+    ) -> Result<R::Response, SendError>
+    where
+        R: TypedRpc,
+        C: Codec<R::Request> + Codec<R::Response>,
+    {
+        let request = self.encode_request::<R>(&request)?;
+        let response = self.transport.rpc(peer_id, request).await?;
+        if !response.status().is_success() {
+            return Err(SendError::HandlerError(response.status()));
+        }
+        Codec::<R::Response>::decode(&self.codec, response.into_body()).map_err(|err| {
+            SendError::Internal(format!("Failed to decode response of {}: {}", R::URI, err))
+        })
+    }
+
+    pub async fn push<R>(&self, peer_id: &NodeId, request: R::Request) -> Result<(), SendError>
+    where
+        R: TypedRpc,
+        C: Codec<R::Request>,
+    {
+        let request = self.encode_request::<R>(&request)?;
+        self.transport.push(peer_id, request).await
+    }
+
+    fn encode_request<R>(&self, request: &R::Request) -> Result<Request<Bytes>, SendError>
+    where
+        R: TypedRpc,
+        C: Codec<R::Request>,
+    {
+        let body = self.codec.encode(request).map_err(|err| {
+            SendError::Internal(format!("Failed to encode request of {}: {}", R::URI, err))
+        })?;
+        Request::builder()
+            .uri(R::URI)
+            .body(body)
+            .map_err(|err| SendError::Internal(err.to_string()))
+    }
+}
+
+/// Registers handlers of typed rpcs.
+pub trait TypedRouter {
+    /// Routes requests of the rpc `R` to `handler`, which is called with the peer that sent the
+    /// request.
+    fn typed_route<R, C, H, Fut>(self, codec: C, handler: H) -> Self
+    where
+        R: TypedRpc,
+        C: Codec<R::Request> + Codec<R::Response>,
+        H: Fn(NodeId, R::Request) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Response, StatusCode>> + Send + 'static;
+}
+
+impl TypedRouter for Router {
+    fn typed_route<R, C, H, Fut>(self, codec: C, handler: H) -> Self
+    where
+        R: TypedRpc,
+        C: Codec<R::Request> + Codec<R::Response>,
+        H: Fn(NodeId, R::Request) -> Fut + Clone + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Response, StatusCode>> + Send + 'static,
+    {
+        self.route(
+            R::URI,
+            any(
+                move |Extension(peer_id): Extension<NodeId>, body: Bytes| async move {
+                    let request = Codec::<R::Request>::decode(&codec, body)
+                        .map_err(|_| StatusCode::BAD_REQUEST)?;
+                    let response = handler(peer_id, request).await?;
+                    Codec::<R::Response>::encode(&codec, &response)
+                        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+                },
+            ),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Message {
+        id: u64,
+        payload: Vec<u8>,
+    }
+
+    #[test]
+    fn codecs_roundtrip() {
+        let message = Message {
+            id: 7,
+            payload: vec![1, 2, 3],
+        };
+        let encoded = BincodeCodec.encode(&message).unwrap();
+        assert_eq!(
+            Codec::<Message>::decode(&BincodeCodec, encoded),
+            Ok(message)
+        );
+
+        let message = "hello".to_string();
+        let encoded = ProstCodec.encode(&message).unwrap();
+        assert_eq!(Codec::<String>::decode(&ProstCodec, encoded), Ok(message));
+        assert!(Codec::<String>::decode(&ProstCodec, Bytes::from_static(&[0xff])).is_err());
+    }
+}
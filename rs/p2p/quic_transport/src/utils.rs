@@ -3,7 +3,9 @@
 //! Contains the actual wire format used for messages.
 //! Request encoding Request<Bytes>:
 //!     - Split into header and body.
-//!     - Header contains a HeaderMap and the URI
+//!     - Header contains the URI, and a HeaderMap if the peer announced support for it in the
+//!       gruezi handshake, see `WireFormat`. Peers that do not announce anything receive
+//!       requests without headers, as sent by transport versions without the handshake features.
 //!     - Body is just the byte vector.
 //!     - Both the header and body are encoded with bincode
 //!     - At this point both header and body are just a vector of bytes.
@@ -11,29 +13,61 @@
 //!     - Reading a request involves doing two reads from the wire for the
 //!       encoded header and body and reconstructing it into a typed request.
 //! Response encoding Response<Bytes>:
-//!     - Same as request expect that the header contains a HeaderMap and a Statuscode.
+//!     - Same as request expect that the header contains a Statuscode, and whether the body is
+//!       compressed if the peer announced features in the gruezi handshake, see compression.rs.
+//!     - Since the body comes last, responses whose body length is known upfront are written
+//!       as the body is produced, and streamed responses are read chunk by chunk.
+//! Datagrams:
+//!     - A datagram contains a single encoded request, without any framing.
+//! Ordered lanes:
+//!     - Uni streams that start with `LANE_MAGIC` carry length delimited frames, each of which
+//!       contains an encoded request, see lane.rs.
 use axum::{
     body::{Body, HttpBody},
     extract::State,
-    http::{Request, Response, StatusCode, Uri},
+    http::{header::CONTENT_ENCODING, HeaderMap, Request, Response, StatusCode, Uri},
     middleware::Next,
 };
 use bincode::Options;
 use bytes::Bytes;
-use quinn::{ReadError, ReadToEndError, RecvStream, SendStream};
+use futures::{stream, SinkExt, StreamExt};
+use quinn::{ReadError, ReadExactError, ReadToEndError, RecvStream, SendStream};
 use serde::{Deserialize, Serialize};
+use tokio_util::codec::{FramedRead, FramedWrite, LengthDelimitedCodec};
 
-use crate::{metrics::QuicTransportMetrics, SendError};
+use crate::{
+    channel::{channel_codec, limited_channel_codec, Channel, CHANNEL_MAGIC, FRAME_HEADER_SIZE},
+    compression::{decompress, is_compressed, CompressionConfig},
+    lane::LANE_MAGIC,
+    message_size::{MessageSizeLimits, STREAM_ERROR_MESSAGE_TOO_LARGE},
+    metrics::{QuicTransportMetrics, DIRECTION_INBOUND},
+    ResponseStream, SendError,
+};
 
 #[derive(Debug)]
 pub(crate) enum RecvError {
-    RecvRequestFailed { reason: String },
-    SendResponseFailed { reason: String },
+    /// The peer reset the stream before the whole request was received.
+    Cancelled,
+    /// The request exceeds the size limit of its URI, see message_size.rs.
+    TooLarge {
+        uri: Uri,
+        limit: usize,
+    },
+    RecvRequestFailed {
+        reason: String,
+    },
+    SendResponseFailed {
+        reason: String,
+    },
 }
 
 impl std::fmt::Display for RecvError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Self::Cancelled => write!(f, "The request was cancelled by the peer"),
+            Self::TooLarge { uri, limit } => {
+                write!(f, "Request to {} exceeds the limit of {} bytes", uri, limit)
+            }
             Self::RecvRequestFailed { reason: e } => {
                 write!(f, "Receiving a request failed: {}", e)
             }
@@ -46,7 +80,7 @@ impl std::fmt::Display for RecvError {
 
 /// On purpose the value is big, otherwise there is risk of not processing important consensus messages.
 /// E.g. summary blocks generated by the consensus protocol for 40 node subnet can be bigger than 5MB.
-const MAX_MESSAGE_SIZE_BYTES: usize = 128 * 1024 * 1024;
+pub(crate) const MAX_MESSAGE_SIZE_BYTES: usize = 128 * 1024 * 1024;
 
 fn bincode_config() -> impl Options {
     bincode::DefaultOptions::new()
@@ -54,30 +88,249 @@ fn bincode_config() -> impl Options {
         .with_limit(MAX_MESSAGE_SIZE_BYTES as u64)
 }
 
-pub(crate) async fn read_request(mut recv_stream: RecvStream) -> Result<Request<Body>, RecvError> {
-    let raw_msg = recv_stream
-        .read_to_end(MAX_MESSAGE_SIZE_BYTES)
+/// Upper bound on the length of the URI of a request.
+const MAX_URI_SIZE_BYTES: u64 = 8 * 1024;
+
+/// Layout of the requests and responses of a connection. Peers that announce features in the
+/// gruezi handshake understand the extended layout, other peers only understand the legacy layout.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum WireFormat {
+    /// Requests consist of the URI and the body, responses of the status and the body. Headers
+    /// are not sent and bodies are not compressed.
+    Legacy,
+    /// Requests consist of the URI, the headers and the body, responses of the status, whether
+    /// the body is compressed and the body.
+    Extended,
+}
+
+impl WireFormat {
+    /// Size of an encoded `WireResponseHeader`, i.e. a u16 status code, a bool in the extended
+    /// format, and a u64 body length.
+    fn response_header_size(self) -> usize {
+        match self {
+            WireFormat::Legacy => 10,
+            WireFormat::Extended => 11,
+        }
+    }
+}
+
+/// Requests received on uni streams are either a single push or open an ordered lane.
+pub(crate) enum UniRequest {
+    Push(Request<Body>),
+    /// The frames of the lane are read with `read_limited_frame`.
+    Lane(RecvStream),
+}
+
+pub(crate) async fn read_uni_request(
+    mut recv_stream: RecvStream,
+    size_limits: &MessageSizeLimits,
+    wire_format: WireFormat,
+) -> Result<UniRequest, RecvError> {
+    // The magic has the same size as the length of the URI that requests start with.
+    let mut prefix = [0; LANE_MAGIC.len()];
+    recv_stream
+        .read_exact(&mut prefix)
         .await
-        .map_err(|_| RecvError::RecvRequestFailed {
-            reason: format!(
-                "Recv stream for request contains more than {} bytes",
-                MAX_MESSAGE_SIZE_BYTES
-            ),
+        .map_err(request_read_exact_error)?;
+
+    // Like transport versions without lanes, the legacy wire format reads the magic as the
+    // length of a URI and rejects it.
+    if wire_format == WireFormat::Extended && prefix == LANE_MAGIC {
+        return Ok(UniRequest::Lane(recv_stream));
+    }
+
+    Ok(UniRequest::Push(
+        read_limited_request(&mut recv_stream, prefix, size_limits, wire_format).await?,
+    ))
+}
+
+/// Reads the rest of a request, after the length of its URI was read. The URI is read first to
+/// look up the size limit of the request. If the request exceeds the limit, the stream is
+/// stopped without reading the rest of it.
+async fn read_limited_request(
+    recv_stream: &mut RecvStream,
+    uri_len: [u8; 8],
+    size_limits: &MessageSizeLimits,
+    wire_format: WireFormat,
+) -> Result<Request<Body>, RecvError> {
+    let (raw_uri, uri) = read_uri(recv_stream, uri_len).await?;
+
+    let limit = size_limits.for_uri(&uri);
+    let rest = match recv_stream.read_to_end(limit).await {
+        Ok(rest) => rest,
+        Err(ReadToEndError::TooLong) => {
+            let _ = recv_stream.stop(STREAM_ERROR_MESSAGE_TOO_LARGE);
+            return Err(RecvError::TooLarge { uri, limit });
+        }
+        Err(ReadToEndError::Read(ReadError::Reset(_))) => return Err(RecvError::Cancelled),
+        Err(err) => {
+            return Err(RecvError::RecvRequestFailed {
+                reason: format!("Reading request failed: {}", err),
+            })
+        }
+    };
+    decode_request(
+        &[&uri_len[..], &raw_uri, &rest].concat(),
+        size_limits,
+        wire_format,
+    )
+}
+
+/// Reads the next frame of a lane, or the frame of the request that opens a channel. The frame
+/// contains an encoded request, whose URI is read first to look up the size limit of the
+/// request. If the request exceeds the limit, the stream is stopped without reading the rest
+/// of the frame. Returns `None` if the peer finished the stream instead of sending a frame.
+pub(crate) async fn read_limited_frame(
+    recv_stream: &mut RecvStream,
+    size_limits: &MessageSizeLimits,
+) -> Result<Option<Vec<u8>>, RecvError> {
+    let mut frame_len = [0; FRAME_HEADER_SIZE];
+    match recv_stream.read_exact(&mut frame_len).await {
+        Ok(()) => {}
+        Err(ReadExactError::FinishedEarly) => return Ok(None),
+        Err(err) => return Err(request_read_exact_error(err)),
+    }
+    let frame_len = u32::from_be_bytes(frame_len) as usize;
+
+    let mut uri_len = [0; 8];
+    recv_stream
+        .read_exact(&mut uri_len)
+        .await
+        .map_err(request_read_exact_error)?;
+    let (raw_uri, uri) = read_uri(recv_stream, uri_len).await?;
+
+    let rest_len = frame_len
+        .checked_sub(uri_len.len() + raw_uri.len())
+        .ok_or_else(|| RecvError::RecvRequestFailed {
+            reason: "Frame is shorter than the URI of its request".to_string(),
         })?;
-    let msg: WireRequest =
-        bincode_config()
-            .deserialize(&raw_msg)
-            .map_err(|err| RecvError::RecvRequestFailed {
-                reason: format!("Deserializing request failed: {}", err),
+    let limit = size_limits.for_uri(&uri);
+    if rest_len > limit {
+        let _ = recv_stream.stop(STREAM_ERROR_MESSAGE_TOO_LARGE);
+        return Err(RecvError::TooLarge { uri, limit });
+    }
+    let mut rest = vec![0; rest_len];
+    recv_stream
+        .read_exact(&mut rest)
+        .await
+        .map_err(request_read_exact_error)?;
+    Ok(Some([&uri_len[..], &raw_uri, &rest].concat()))
+}
+
+/// Reads the URI of a request, after the length of the URI was read.
+async fn read_uri(
+    recv_stream: &mut RecvStream,
+    uri_len: [u8; 8],
+) -> Result<(Vec<u8>, Uri), RecvError> {
+    let len = u64::from_le_bytes(uri_len);
+    if len > MAX_URI_SIZE_BYTES {
+        return Err(RecvError::RecvRequestFailed {
+            reason: format!("URI of {} bytes is too long", len),
+        });
+    }
+    let mut raw_uri = vec![0; len as usize];
+    recv_stream
+        .read_exact(&mut raw_uri)
+        .await
+        .map_err(request_read_exact_error)?;
+    let uri = std::str::from_utf8(&raw_uri)
+        .ok()
+        .and_then(|uri| uri.parse::<Uri>().ok())
+        .ok_or_else(|| RecvError::RecvRequestFailed {
+            reason: "Request contains an invalid URI".to_string(),
+        })?;
+    Ok((raw_uri, uri))
+}
+
+fn request_read_exact_error(err: ReadExactError) -> RecvError {
+    match err {
+        ReadExactError::ReadError(ReadError::Reset(_)) => RecvError::Cancelled,
+        _ => RecvError::RecvRequestFailed {
+            reason: format!("Reading request failed: {}", err),
+        },
+    }
+}
+
+/// Requests received on bidi streams either expect a single response or open a channel.
+pub(crate) enum BidiRequest {
+    Rpc(Request<Body>),
+    Channel(Request<Body>, FramedRead<RecvStream, LengthDelimitedCodec>),
+}
+
+pub(crate) async fn read_bidi_request(
+    mut recv_stream: RecvStream,
+    size_limits: &MessageSizeLimits,
+    wire_format: WireFormat,
+) -> Result<BidiRequest, RecvError> {
+    // Any request is longer than the magic, since it contains at least two lengths.
+    // The magic has the same size as the length of the URI that requests start with.
+    let mut prefix = [0; CHANNEL_MAGIC.len()];
+    recv_stream
+        .read_exact(&mut prefix)
+        .await
+        .map_err(request_read_exact_error)?;
+
+    if prefix == CHANNEL_MAGIC {
+        let raw_msg = read_limited_frame(&mut recv_stream, size_limits)
+            .await?
+            .ok_or_else(|| RecvError::RecvRequestFailed {
+                reason: "Channel closed before its request was received".to_string(),
             })?;
+        let request = decode_request(&raw_msg, size_limits, wire_format)?;
+        // Further frames of the channel are limited like its request.
+        let frames = FramedRead::new(
+            recv_stream,
+            limited_channel_codec(size_limits.for_uri(request.uri())),
+        );
+        return Ok(BidiRequest::Channel(request, frames));
+    }
+
+    Ok(BidiRequest::Rpc(
+        read_limited_request(&mut recv_stream, prefix, size_limits, wire_format).await?,
+    ))
+}
+
+/// Decodes a request. Compressed bodies are decompressed up to the size limit of the URI.
+pub(crate) fn decode_request(
+    raw_msg: &[u8],
+    size_limits: &MessageSizeLimits,
+    wire_format: WireFormat,
+) -> Result<Request<Body>, RecvError> {
+    let msg: WireRequest = match wire_format {
+        WireFormat::Legacy => bincode_config()
+            .deserialize::<LegacyWireRequest>(raw_msg)
+            .map(|msg| WireRequest {
+                uri: msg.uri,
+                headers: HeaderMap::new(),
+                body: msg.body,
+            }),
+        WireFormat::Extended => bincode_config().deserialize(raw_msg),
+    }
+    .map_err(|err| RecvError::RecvRequestFailed {
+        reason: format!("Deserializing request failed: {}", err),
+    })?;
+
+    let mut headers = msg.headers;
+    let body = if is_compressed(headers.get(CONTENT_ENCODING)) {
+        headers.remove(CONTENT_ENCODING);
+        decompress(msg.body, size_limits.for_uri(&msg.uri)).map_err(|err| {
+            RecvError::RecvRequestFailed {
+                reason: err.to_string(),
+            }
+        })?
+    } else {
+        Bytes::copy_from_slice(msg.body)
+    };
 
-    let mut request = Request::new(Body::from(Bytes::copy_from_slice(msg.body)));
+    let mut request = Request::new(Body::from(body));
     let _ = std::mem::replace(request.uri_mut(), msg.uri);
+    let _ = std::mem::replace(request.headers_mut(), headers);
     Ok(request)
 }
 
 pub(crate) async fn read_response(
     mut recv_stream: RecvStream,
+    wire_format: WireFormat,
 ) -> Result<Response<Bytes>, SendError> {
     let raw_msg = recv_stream
         .read_to_end(MAX_MESSAGE_SIZE_BYTES)
@@ -90,37 +343,155 @@ pub(crate) async fn read_response(
             )),
             _ => SendError::Internal(err.to_string()),
         })?;
-    let msg: WireResponse = bincode_config()
-        .deserialize(&raw_msg)
-        .map_err(|err| SendError::Internal(format!("Deserializing response failed: {}", err)))?;
+    let msg = decode_response(&raw_msg, wire_format)?;
+
+    let body = if msg.compressed {
+        decompress(msg.body, MAX_MESSAGE_SIZE_BYTES)?
+    } else {
+        Bytes::copy_from_slice(msg.body)
+    };
 
-    let mut response = Response::new(Bytes::copy_from_slice(msg.body));
+    let mut response = Response::new(body);
     let _ = std::mem::replace(response.status_mut(), msg.status);
     Ok(response)
 }
 
+/// Reads a response like `read_response`, but yields the body in chunks as they arrive.
+pub(crate) async fn read_response_stream(
+    mut recv_stream: RecvStream,
+    wire_format: WireFormat,
+) -> Result<Response<ResponseStream>, SendError> {
+    let mut raw_header = vec![0; wire_format.response_header_size()];
+    recv_stream
+        .read_exact(&mut raw_header)
+        .await
+        .map_err(|err| match err {
+            ReadExactError::ReadError(ReadError::ConnectionLost(conn_err)) => conn_err.into(),
+            _ => SendError::Internal(err.to_string()),
+        })?;
+    let header: WireResponseHeader = match wire_format {
+        WireFormat::Legacy => bincode_config()
+            .deserialize::<LegacyWireResponseHeader>(&raw_header)
+            .map(|header| WireResponseHeader {
+                status: header.status,
+                compressed: false,
+                body_len: header.body_len,
+            }),
+        WireFormat::Extended => bincode_config().deserialize(&raw_header),
+    }
+    .map_err(|err| SendError::Internal(format!("Deserializing response failed: {}", err)))?;
+    if header.body_len > MAX_MESSAGE_SIZE_BYTES as u64 {
+        return Err(SendError::Internal(format!(
+            "Recv stream for response contains more than {} bytes",
+            MAX_MESSAGE_SIZE_BYTES
+        )));
+    }
+
+    if header.compressed {
+        let raw_body = recv_stream
+            .read_to_end(header.body_len as usize)
+            .await
+            .map_err(|err| match err {
+                ReadToEndError::Read(ReadError::ConnectionLost(conn_err)) => conn_err.into(),
+                _ => SendError::Internal(err.to_string()),
+            })?;
+        let body = decompress(&raw_body, MAX_MESSAGE_SIZE_BYTES)?;
+        let mut response = Response::new(stream::once(async move { Ok(body) }).boxed());
+        let _ = std::mem::replace(response.status_mut(), header.status);
+        return Ok(response);
+    }
+
+    let body = stream::try_unfold(
+        (recv_stream, header.body_len),
+        |(mut recv_stream, remaining)| async move {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            let chunk = recv_stream
+                .read_chunk(remaining as usize, true)
+                .await
+                .map_err(|err| match err {
+                    ReadError::ConnectionLost(conn_err) => conn_err.into(),
+                    _ => SendError::Internal(err.to_string()),
+                })?
+                .ok_or_else(|| {
+                    SendError::Internal(format!(
+                        "Recv stream for response finished {} bytes early",
+                        remaining
+                    ))
+                })?;
+            let remaining = remaining - chunk.bytes.len() as u64;
+            Ok(Some((chunk.bytes, (recv_stream, remaining))))
+        },
+    )
+    .boxed();
+
+    let mut response = Response::new(body);
+    let _ = std::mem::replace(response.status_mut(), header.status);
+    Ok(response)
+}
+
 pub(crate) async fn write_request(
     send_stream: &mut SendStream,
     request: Request<Bytes>,
+    wire_format: WireFormat,
 ) -> Result<(), SendError> {
-    let (parts, body) = request.into_parts();
+    let res = encode_request(request, wire_format)?;
+    Ok(send_stream.write_all(&res).await?)
+}
 
-    let msg = WireRequest {
-        uri: parts.uri,
-        body: &body,
-    };
+/// Encodes a request in the wire format. Also used as is for the content of datagrams.
+/// Headers are dropped in the legacy format.
+pub(crate) fn encode_request(
+    request: Request<Bytes>,
+    wire_format: WireFormat,
+) -> Result<Bytes, SendError> {
+    let (parts, body) = request.into_parts();
 
-    let res = bincode_config()
-        .serialize(&msg)
-        .map_err(|err| SendError::Internal(err.to_string()))?;
-    Ok(send_stream.write_all(&res).await?)
+    match wire_format {
+        WireFormat::Legacy => bincode_config().serialize(&LegacyWireRequest {
+            uri: parts.uri,
+            body: &body,
+        }),
+        WireFormat::Extended => bincode_config().serialize(&WireRequest {
+            uri: parts.uri,
+            headers: parts.headers,
+            body: &body,
+        }),
+    }
+    .map(Bytes::from)
+    .map_err(|err| SendError::Internal(err.to_string()))
 }
 
+/// Writes the response, with a compressed body if `compression` is set, the body is large
+/// enough and the wire format can mark it as compressed.
 pub(crate) async fn write_response(
     send_stream: &mut SendStream,
     response: Response<Body>,
+    compression: Option<&CompressionConfig>,
+    wire_format: WireFormat,
 ) -> Result<(), RecvError> {
     let (parts, body) = response.into_parts();
+
+    // Bodies of known length are not buffered, unless they are compressed.
+    let exact_len = body.size_hint().exact();
+    let compression = compression
+        .filter(|_| wire_format == WireFormat::Extended)
+        .filter(|compression| exact_len.map_or(true, |len| len >= compression.min_size as u64));
+    if let Some(body_len) = exact_len
+        .filter(|len| *len <= MAX_MESSAGE_SIZE_BYTES as u64)
+        .filter(|_| compression.is_none())
+    {
+        return write_response_incrementally(
+            send_stream,
+            parts.status,
+            body_len,
+            body,
+            wire_format,
+        )
+        .await;
+    }
+
     // Check for axum error in body
     // TODO: Think about this. What is the error that can happen here?
     let b = axum::body::to_bytes(body, MAX_MESSAGE_SIZE_BYTES)
@@ -128,16 +499,14 @@ pub(crate) async fn write_response(
         .map_err(|err| RecvError::SendResponseFailed {
             reason: err.to_string(),
         })?;
+    let compressed = compression.and_then(|compression| compression.compress(&b));
     let msg = WireResponse {
         status: parts.status,
-        body: &b,
+        compressed: compressed.is_some(),
+        body: compressed.as_deref().unwrap_or(&b),
     };
 
-    let res = bincode_config()
-        .serialize(&msg)
-        .map_err(|err| RecvError::SendResponseFailed {
-            reason: err.to_string(),
-        })?;
+    let res = encode_response(&msg, wire_format)?;
     send_stream
         .write_all(&res)
         .await
@@ -146,8 +515,172 @@ pub(crate) async fn write_response(
         })
 }
 
+/// Writes the same bytes as `write_response`, but writes each chunk of the body as soon
+/// as it is produced.
+async fn write_response_incrementally(
+    send_stream: &mut SendStream,
+    status: StatusCode,
+    body_len: u64,
+    body: Body,
+    wire_format: WireFormat,
+) -> Result<(), RecvError> {
+    let header = match wire_format {
+        WireFormat::Legacy => {
+            bincode_config().serialize(&LegacyWireResponseHeader { status, body_len })
+        }
+        WireFormat::Extended => bincode_config().serialize(&WireResponseHeader {
+            status,
+            compressed: false,
+            body_len,
+        }),
+    }
+    .map_err(|err| RecvError::SendResponseFailed {
+        reason: err.to_string(),
+    })?;
+    send_stream
+        .write_all(&header)
+        .await
+        .map_err(|err| RecvError::SendResponseFailed {
+            reason: err.to_string(),
+        })?;
+
+    let mut written = 0;
+    let mut chunks = body.into_data_stream();
+    while let Some(chunk) = chunks.next().await {
+        let chunk = chunk.map_err(|err| RecvError::SendResponseFailed {
+            reason: err.to_string(),
+        })?;
+        written += chunk.len() as u64;
+        if written > body_len {
+            return Err(RecvError::SendResponseFailed {
+                reason: format!("Response body is longer than the announced {body_len} bytes"),
+            });
+        }
+        send_stream
+            .write_chunk(chunk)
+            .await
+            .map_err(|err| RecvError::SendResponseFailed {
+                reason: err.to_string(),
+            })?;
+    }
+
+    if written != body_len {
+        return Err(RecvError::SendResponseFailed {
+            reason: format!("Response body is shorter than the announced {body_len} bytes"),
+        });
+    }
+    Ok(())
+}
+
+/// Prefix of an encoded `WireResponse`, up to the content of the body.
+#[derive(Serialize, Deserialize)]
+struct WireResponseHeader {
+    #[serde(with = "http_serde::status_code")]
+    status: StatusCode,
+    compressed: bool,
+    body_len: u64,
+}
+
+/// Prefix of an encoded `LegacyWireResponse`, up to the content of the body.
+#[derive(Serialize, Deserialize)]
+struct LegacyWireResponseHeader {
+    #[serde(with = "http_serde::status_code")]
+    status: StatusCode,
+    body_len: u64,
+}
+
+/// Opens a channel on a new bidi stream. Fails if the peer does not accept it.
+pub(crate) async fn open_channel(
+    mut send_stream: SendStream,
+    recv_stream: RecvStream,
+    uri: Uri,
+    wire_format: WireFormat,
+) -> Result<Channel, SendError> {
+    send_stream.write_all(&CHANNEL_MAGIC).await?;
+
+    let mut request = Request::new(Bytes::new());
+    *request.uri_mut() = uri;
+    let raw_msg = encode_request(request, wire_format)?;
+    let mut sink = FramedWrite::new(send_stream, channel_codec());
+    sink.send(raw_msg)
+        .await
+        .map_err(|err| SendError::Internal(err.to_string()))?;
+
+    let mut stream = FramedRead::new(recv_stream, channel_codec());
+    let raw_msg = stream
+        .next()
+        .await
+        .ok_or_else(|| SendError::Internal("Channel closed before it was accepted".to_string()))?
+        .map_err(|err| SendError::Internal(err.to_string()))?;
+    let msg = decode_response(&raw_msg, wire_format)?;
+    if !msg.status.is_success() {
+        return Err(SendError::HandlerError(msg.status));
+    }
+
+    Ok(Channel::new(sink, stream))
+}
+
+/// Answers a request to open a channel. The body of the response is not sent.
+pub(crate) async fn write_channel_response(
+    send_stream: SendStream,
+    status: StatusCode,
+    wire_format: WireFormat,
+) -> Result<FramedWrite<SendStream, LengthDelimitedCodec>, RecvError> {
+    let msg = WireResponse {
+        status,
+        compressed: false,
+        body: &[],
+    };
+    let raw_msg = encode_response(&msg, wire_format)?;
+
+    let mut sink = FramedWrite::new(send_stream, channel_codec());
+    sink.send(Bytes::from(raw_msg))
+        .await
+        .map_err(|err| RecvError::SendResponseFailed {
+            reason: err.to_string(),
+        })?;
+    Ok(sink)
+}
+
+fn encode_response(msg: &WireResponse, wire_format: WireFormat) -> Result<Vec<u8>, RecvError> {
+    match wire_format {
+        WireFormat::Legacy => bincode_config().serialize(&LegacyWireResponse {
+            status: msg.status,
+            body: msg.body,
+        }),
+        WireFormat::Extended => bincode_config().serialize(msg),
+    }
+    .map_err(|err| RecvError::SendResponseFailed {
+        reason: err.to_string(),
+    })
+}
+
+fn decode_response(raw_msg: &[u8], wire_format: WireFormat) -> Result<WireResponse, SendError> {
+    match wire_format {
+        WireFormat::Legacy => bincode_config()
+            .deserialize::<LegacyWireResponse>(raw_msg)
+            .map(|msg| WireResponse {
+                status: msg.status,
+                compressed: false,
+                body: msg.body,
+            }),
+        WireFormat::Extended => bincode_config().deserialize(raw_msg),
+    }
+    .map_err(|err| SendError::Internal(format!("Deserializing response failed: {}", err)))
+}
+
 #[derive(Serialize, Deserialize)]
 struct WireResponse<'a> {
+    #[serde(with = "http_serde::status_code")]
+    status: StatusCode,
+    compressed: bool,
+    #[serde(with = "serde_bytes")]
+    body: &'a [u8],
+}
+
+/// Response in `WireFormat::Legacy`.
+#[derive(Serialize, Deserialize)]
+struct LegacyWireResponse<'a> {
     #[serde(with = "http_serde::status_code")]
     status: StatusCode,
     #[serde(with = "serde_bytes")]
@@ -156,6 +689,17 @@ struct WireResponse<'a> {
 
 #[derive(Serialize, Deserialize)]
 struct WireRequest<'a> {
+    #[serde(with = "http_serde::uri")]
+    uri: Uri,
+    #[serde(with = "http_serde::header_map")]
+    headers: HeaderMap,
+    #[serde(with = "serde_bytes")]
+    body: &'a [u8],
+}
+
+/// Request in `WireFormat::Legacy`.
+#[derive(Serialize, Deserialize)]
+struct LegacyWireRequest<'a> {
     #[serde(with = "http_serde::uri")]
     uri: Uri,
     #[serde(with = "serde_bytes")]
@@ -172,6 +716,13 @@ pub(crate) async fn collect_metrics(
         .request_handle_bytes_received_total
         .with_label_values(&[request.uri().path()])
         .inc_by(request.body().size_hint().lower());
+    state
+        .request_size_bytes
+        .with_label_values(&[request.uri().path(), DIRECTION_INBOUND])
+        .observe(request.body().size_hint().lower() as f64);
+    let response_size = state
+        .response_size_bytes
+        .with_label_values(&[request.uri().path(), DIRECTION_INBOUND]);
     let _timer = state
         .request_handle_duration_seconds
         .with_label_values(&[request.uri().path()])
@@ -181,5 +732,147 @@ pub(crate) async fn collect_metrics(
         .with_label_values(&[request.uri().path()]);
     let response = next.run(request).await;
     out_counter.inc_by(response.body().size_hint().lower());
+    // The size of streamed responses is not known upfront.
+    if let Some(len) = response.body().size_hint().exact() {
+        response_size.observe(len as f64);
+    }
     response
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_response_header_is_prefix_of_wire_response() {
+        let body = [1, 2, 3];
+
+        let response = bincode_config()
+            .serialize(&WireResponse {
+                status: StatusCode::IM_A_TEAPOT,
+                compressed: false,
+                body: &body,
+            })
+            .unwrap();
+        let header = bincode_config()
+            .serialize(&WireResponseHeader {
+                status: StatusCode::IM_A_TEAPOT,
+                compressed: false,
+                body_len: body.len() as u64,
+            })
+            .unwrap();
+
+        assert_eq!(header.len(), WireFormat::Extended.response_header_size());
+        assert_eq!([header.as_slice(), &body].concat(), response);
+
+        let response = bincode_config()
+            .serialize(&LegacyWireResponse {
+                status: StatusCode::IM_A_TEAPOT,
+                body: &body,
+            })
+            .unwrap();
+        let header = bincode_config()
+            .serialize(&LegacyWireResponseHeader {
+                status: StatusCode::IM_A_TEAPOT,
+                body_len: body.len() as u64,
+            })
+            .unwrap();
+
+        assert_eq!(header.len(), WireFormat::Legacy.response_header_size());
+        assert_eq!([header.as_slice(), &body].concat(), response);
+    }
+
+    #[test]
+    fn legacy_responses_have_no_compression_flag() {
+        let body = [1, 2, 3];
+        // Layout of responses sent by transport versions without handshake features.
+        let raw_msg = [
+            &StatusCode::IM_A_TEAPOT.as_u16().to_le_bytes()[..],
+            &(body.len() as u64).to_le_bytes(),
+            &body,
+        ]
+        .concat();
+
+        let msg = WireResponse {
+            status: StatusCode::IM_A_TEAPOT,
+            compressed: false,
+            body: &body,
+        };
+        assert_eq!(encode_response(&msg, WireFormat::Legacy).unwrap(), raw_msg);
+
+        let msg = decode_response(&raw_msg, WireFormat::Legacy).unwrap();
+        assert_eq!(msg.status, StatusCode::IM_A_TEAPOT);
+        assert!(!msg.compressed);
+        assert_eq!(msg.body, body);
+    }
+
+    #[test]
+    fn legacy_requests_have_no_headers() {
+        let uri = b"/consensus/advert";
+        let body = [1, 2, 3];
+        // Layout of requests sent by transport versions without handshake features.
+        let raw_msg = [
+            &(uri.len() as u64).to_le_bytes()[..],
+            uri,
+            &(body.len() as u64).to_le_bytes(),
+            &body,
+        ]
+        .concat();
+
+        let request =
+            decode_request(&raw_msg, &MessageSizeLimits::default(), WireFormat::Legacy).unwrap();
+        assert_eq!(request.uri(), "/consensus/advert");
+        assert!(request.headers().is_empty());
+        assert_eq!(request.body().size_hint().exact(), Some(body.len() as u64));
+
+        // Headers are dropped for such peers.
+        let request = || {
+            Request::builder()
+                .uri("/consensus/advert")
+                .header("x-ic-ack", "1")
+                .body(Bytes::copy_from_slice(&body))
+                .unwrap()
+        };
+        assert_eq!(
+            encode_request(request(), WireFormat::Legacy).unwrap(),
+            raw_msg
+        );
+        assert!(decode_request(
+            &raw_msg,
+            &MessageSizeLimits::default(),
+            WireFormat::Extended
+        )
+        .is_err());
+
+        let raw_msg = encode_request(request(), WireFormat::Extended).unwrap();
+        let request = decode_request(
+            &raw_msg,
+            &MessageSizeLimits::default(),
+            WireFormat::Extended,
+        )
+        .unwrap();
+        assert_eq!(request.headers().get("x-ic-ack").unwrap(), "1");
+    }
+
+    #[test]
+    fn compressed_requests_are_bounded_by_size_limit() {
+        let request = CompressionConfig::default().compress_request(
+            Request::builder()
+                .uri("/state-sync/chunk")
+                .body(Bytes::from(vec![0; 1_000_000]))
+                .unwrap(),
+        );
+        let raw_msg = encode_request(request, WireFormat::Extended).unwrap();
+        let size_limits = MessageSizeLimits::default().with_prefix("/state-sync", 10_000);
+        assert!(raw_msg.len() < 10_000);
+
+        assert!(decode_request(&raw_msg, &size_limits, WireFormat::Extended).is_err());
+        let request = decode_request(
+            &raw_msg,
+            &MessageSizeLimits::default(),
+            WireFormat::Extended,
+        )
+        .unwrap();
+        assert_eq!(request.body().size_hint().exact(), Some(1_000_000));
+    }
+}
@@ -1,10 +1,21 @@
-use std::{net::SocketAddr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 
 use crate::common::{PeerRestrictedSevHandshake, PeerRestrictedTlsConfig};
-use axum::http::Request;
+use axum::{
+    http::{Request, StatusCode, Uri},
+    Extension, Router,
+};
 use bytes::Bytes;
 use either::Either;
-use futures::FutureExt;
+use futures::{FutureExt, SinkExt, StreamExt, TryStreamExt};
 use ic_base_types::{NodeId, RegistryVersion};
 use ic_icos_sev::Sev;
 use ic_logger::info;
@@ -13,13 +24,21 @@ use ic_metrics::MetricsRegistry;
 use ic_p2p_test_utils::{
     create_peer_manager_and_registry_handle, temp_crypto_component_with_tls_keys,
     turmoil::{
-        add_peer_manager_to_sim, add_transport_to_sim, wait_for, wait_for_timeout,
+        add_nodes_to_topology, add_peer_manager_to_sim, add_transport_to_sim,
+        add_transport_to_sim_with_config, wait_for, wait_for_timeout, waiter_fut,
         PeerManagerAction,
     },
     ConnectivityChecker,
 };
-use ic_quic_transport::SendError;
-use ic_quic_transport::{DummyUdpSocket, QuicTransport, Transport};
+use ic_quic_transport::{
+    typed_rpc, AttestationMode, BincodeCodec, Deadline, DummyUdpSocket, HealthCheckConfig,
+    MessageSizeLimits, OrderedLanes, PeerSelector, ProtocolVersion, QuicTransport,
+    QuicTransportConfig, TraceContext, TrafficClass, Transport, TypedRouter, TypedTransport,
+    DEFAULT_MAX_DATAGRAM_SIZE,
+};
+use ic_quic_transport::{
+    ChannelUpgrade, CompressionConfig, NoCertRotation, RateLimitConfig, SendError,
+};
 use ic_test_utilities_logger::with_test_replica_logger;
 use ic_types_test_utils::ids::{NODE_1, NODE_2, NODE_3, NODE_4, NODE_5, SUBNET_1};
 use tokio::{sync::Notify, time::timeout};
@@ -123,11 +142,13 @@ fn test_graceful_shutdown() {
             rt.handle(),
             node_crypto_1,
             registry_handler.registry_client.clone(),
+            Arc::new(NoCertRotation),
             sev_handshake_1,
             NODE_1,
             topology_watcher.clone(),
             Either::Left::<_, DummyUdpSocket>(socket_1),
             ConnectivityChecker::router(),
+            QuicTransportConfig::default(),
         ));
 
         let mut transport_2 = Arc::new(QuicTransport::start(
@@ -136,11 +157,13 @@ fn test_graceful_shutdown() {
             rt.handle(),
             node_crypto_2,
             registry_handler.registry_client.clone(),
+            Arc::new(NoCertRotation),
             sev_handshake_2,
             NODE_2,
             topology_watcher,
             Either::Left::<_, DummyUdpSocket>(socket_2),
             ConnectivityChecker::router(),
+            QuicTransportConfig::default(),
         ));
 
         registry_handler.add_node(
@@ -184,97 +207,2320 @@ fn test_graceful_shutdown() {
 }
 
 #[test]
-fn test_real_socket() {
+fn test_real_socket() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let (_jh, topology_watcher, mut registry_handler) =
+            create_peer_manager_and_registry_handle(rt.handle(), log.clone());
+
+        let node_crypto_1 = temp_crypto_component_with_tls_keys(&registry_handler, NODE_1);
+        let sev_handshake_1 = Arc::new(Sev::new(
+            NODE_1,
+            SUBNET_1,
+            registry_handler.registry_client.clone(),
+            log.clone(),
+        ));
+        let node_crypto_2 = temp_crypto_component_with_tls_keys(&registry_handler, NODE_2);
+        let sev_handshake_2 = Arc::new(Sev::new(
+            NODE_2,
+            SUBNET_1,
+            registry_handler.registry_client.clone(),
+            log.clone(),
+        ));
+        registry_handler.registry_client.update_to_latest_version();
+
+        let socket_1: SocketAddr = "127.0.1.1:4100".parse().unwrap();
+        let socket_2: SocketAddr = "127.0.2.1:4100".parse().unwrap();
+
+        let transport_1 = Arc::new(QuicTransport::start(
+            &log,
+            &MetricsRegistry::default(),
+            rt.handle(),
+            node_crypto_1,
+            registry_handler.registry_client.clone(),
+            Arc::new(NoCertRotation),
+            sev_handshake_1,
+            NODE_1,
+            topology_watcher.clone(),
+            Either::Left::<_, DummyUdpSocket>(socket_1),
+            ConnectivityChecker::router(),
+            QuicTransportConfig::default(),
+        ));
+
+        let transport_2 = Arc::new(QuicTransport::start(
+            &log,
+            &MetricsRegistry::default(),
+            rt.handle(),
+            node_crypto_2,
+            registry_handler.registry_client.clone(),
+            Arc::new(NoCertRotation),
+            sev_handshake_2,
+            NODE_2,
+            topology_watcher,
+            Either::Left::<_, DummyUdpSocket>(socket_2),
+            ConnectivityChecker::router(),
+            QuicTransportConfig::default(),
+        ));
+
+        registry_handler.add_node(
+            RegistryVersion::from(2),
+            NODE_1,
+            Some(&socket_1.ip().to_string()),
+        );
+        registry_handler.add_node(
+            RegistryVersion::from(3),
+            NODE_2,
+            Some(&socket_2.ip().to_string()),
+        );
+        registry_handler.registry_client.reload();
+        registry_handler.registry_client.update_to_latest_version();
+
+        rt.block_on(async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(250)).await;
+
+                let request = Request::builder().uri("/Ping").body(Bytes::new()).unwrap();
+                let node_1_reachable_from_node_2 = transport_2.push(&NODE_1, request).await.is_ok();
+                let request = Request::builder().uri("/Ping").body(Bytes::new()).unwrap();
+                let node_2_reachable_from_node_1 = transport_1.push(&NODE_2, request).await.is_ok();
+                if node_2_reachable_from_node_1 && node_1_reachable_from_node_2 {
+                    break;
+                }
+            }
+        });
+    })
+}
+
+/// Test sending large message that is above our message limit. This should be rejected during the serialization step.
+#[test]
+fn test_sending_large_message() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+
+        // Send large message that should be reject and verify connectivity.
+        let send_large_msg_to_node_2 = |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                loop {
+                    transport
+                        .push(&NODE_2, Request::new(Bytes::from(vec![0; 50_000_000])))
+                        .await
+                        .unwrap_err();
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            send_large_msg_to_node_2,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for_timeout(&mut sim, || false, Duration::from_secs(5))
+            .expect("The network did not reach a fully connected state after startup");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test compressed rpcs over a network with varying latency, which reorders packets.
+#[test]
+fn test_compressed_rpc_with_latency() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .min_message_latency(Duration::from_millis(10))
+            .max_message_latency(Duration::from_millis(100))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let config = QuicTransportConfig {
+            compression: Some(CompressionConfig::default()),
+            announce_features: true,
+            ..Default::default()
+        };
+        let echoed = Arc::new(AtomicBool::new(false));
+
+        let echoed_clone = echoed.clone();
+        let rpc_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let echoed = echoed_clone.clone();
+            async move {
+                let body = Bytes::from(vec![7; 1_000_000]);
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder().uri("/Echo").body(body.clone()).unwrap();
+                    if let Ok(response) = transport.rpc(&NODE_2, request).await {
+                        assert_eq!(response.body(), &body);
+                        echoed.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            rpc_to_node_2,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Echo",
+                axum::routing::any(|body: Bytes| async move { body }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || echoed.load(Ordering::SeqCst)).expect("The rpc was not echoed");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that peers with different protocol versions agree on the common protocol.
+#[test]
+fn test_protocol_negotiation() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let negotiated = Arc::new(AtomicBool::new(false));
+
+        let negotiated_clone = negotiated.clone();
+        let check_protocol = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let negotiated = negotiated_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if let Some(protocol) = transport.peer_protocol(&NODE_2) {
+                        assert_eq!(
+                            protocol,
+                            ProtocolVersion {
+                                version: 2,
+                                features: 0b100,
+                            }
+                        );
+                        negotiated.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                protocol: ProtocolVersion {
+                    version: 3,
+                    features: 0b101,
+                },
+                announce_features: true,
+                ..Default::default()
+            },
+            check_protocol,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            None,
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                protocol: ProtocolVersion {
+                    version: 2,
+                    features: 0b110,
+                },
+                announce_features: true,
+                ..Default::default()
+            },
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || negotiated.load(Ordering::SeqCst))
+            .expect("The protocol was not negotiated");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that peers only announce their features if configured, so that they can connect to
+/// transport versions without announcements.
+#[test]
+fn test_features_not_announced_by_default() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let config = QuicTransportConfig {
+            compression: Some(CompressionConfig::default()),
+            protocol: ProtocolVersion {
+                version: 2,
+                features: 0b110,
+            },
+            ..Default::default()
+        };
+        let echoed = Arc::new(AtomicBool::new(false));
+
+        let echoed_clone = echoed.clone();
+        let rpc_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let echoed = echoed_clone.clone();
+            async move {
+                while transport.peer_protocol(&NODE_2).is_none() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                assert_eq!(
+                    transport.peer_protocol(&NODE_2),
+                    Some(ProtocolVersion::default())
+                );
+                let body = Bytes::from(vec![0; 100_000]);
+                let request = Request::builder().uri("/Echo").body(body.clone()).unwrap();
+                let response = transport.rpc(&NODE_2, request).await.unwrap();
+                assert_eq!(response.into_body(), body);
+                echoed.store(true, Ordering::SeqCst);
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            rpc_to_node_2,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Echo",
+                axum::routing::any(|body: Bytes| async move { body }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || echoed.load(Ordering::SeqCst)).expect("The rpc was not answered");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that rpcs in flight to a peer that leaves the topology complete.
+#[test]
+fn test_drain_removed_peer() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, mut registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let received = Arc::new(AtomicBool::new(false));
+        let completed = Arc::new(AtomicBool::new(false));
+
+        let completed_clone = completed.clone();
+        let slow_rpc_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let completed = completed_clone.clone();
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let request = Request::builder()
+                    .uri("/Slow")
+                    .body(Bytes::from_static(b"slow"))
+                    .unwrap();
+                let response = transport.rpc(&NODE_2, request).await.unwrap();
+                assert_eq!(response.body(), "slow");
+                completed.store(true, Ordering::SeqCst);
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        let received_clone = received.clone();
+        let slow_router = Router::new().route(
+            "/Slow",
+            axum::routing::any(move |body: Bytes| {
+                let received = received_clone.clone();
+                async move {
+                    received.store(true, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_secs(2)).await;
+                    body
+                }
+            }),
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            slow_rpc_to_node_2,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(slow_router),
+            None,
+            None,
+            None,
+            None,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || received.load(Ordering::SeqCst)).expect("The rpc was not received");
+
+        // Remove node 2 while its handler is still running.
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Remove((
+                NODE_2,
+                RegistryVersion::from(4),
+            )))
+            .unwrap();
+        registry_handle.set_oldest_consensus_registry_version(RegistryVersion::from(4));
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || completed.load(Ordering::SeqCst)).expect("The rpc did not complete");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that rpcs beyond the in-flight limit of a peer are rejected.
+#[test]
+fn test_inflight_rpc_limit() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let config = QuicTransportConfig {
+            max_inflight_rpcs_per_peer: 1,
+            ..Default::default()
+        };
+        let rejected = Arc::new(AtomicBool::new(false));
+
+        let rejected_clone = rejected.clone();
+        let concurrent_rpcs_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let rejected = rejected_clone.clone();
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let slow_request = Request::builder().uri("/Slow").body(Bytes::new()).unwrap();
+                let request = Request::builder().uri("/Slow").body(Bytes::new()).unwrap();
+                let (slow_response, response) = futures::join!(
+                    transport.rpc(&NODE_2, slow_request),
+                    transport.rpc(&NODE_2, request)
+                );
+                assert!(slow_response.is_ok());
+                assert!(matches!(response, Err(SendError::Overloaded)));
+                // The slot is free again once the first rpc completed.
+                let request = Request::builder().uri("/Slow").body(Bytes::new()).unwrap();
+                assert!(transport.rpc(&NODE_2, request).await.is_ok());
+                rejected.store(true, Ordering::SeqCst);
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            concurrent_rpcs_to_node_2,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Slow",
+                axum::routing::any(|| async {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || rejected.load(Ordering::SeqCst))
+            .expect("The rpc beyond the limit was not rejected");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that rpcs of a traffic class are limited separately, and that the handler sees the class.
+#[test]
+fn test_traffic_class_inflight_limit() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let mut config = QuicTransportConfig {
+            announce_features: true,
+            ..Default::default()
+        };
+        config.traffic_classes.bulk.max_inflight_rpcs_per_peer = 1;
+        let rejected = Arc::new(AtomicBool::new(false));
+
+        let class_request = |class: TrafficClass| {
+            let mut request = Request::builder().uri("/Class").body(Bytes::new()).unwrap();
+            request.extensions_mut().insert(class);
+            request
+        };
+        let rejected_clone = rejected.clone();
+        let concurrent_rpcs_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let rejected = rejected_clone.clone();
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let (slow_response, response, control_response) = futures::join!(
+                    transport.rpc(&NODE_2, class_request(TrafficClass::Bulk)),
+                    transport.rpc(&NODE_2, class_request(TrafficClass::Bulk)),
+                    transport.rpc(&NODE_2, class_request(TrafficClass::Control))
+                );
+                assert_eq!(slow_response.unwrap().body(), "bulk");
+                assert!(matches!(response, Err(SendError::Overloaded)));
+                // Other classes are not affected by the limit of bulk rpcs.
+                assert_eq!(control_response.unwrap().body(), "control");
+                rejected.store(true, Ordering::SeqCst);
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            concurrent_rpcs_to_node_2,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Class",
+                axum::routing::any(|Extension(class): Extension<TrafficClass>| async move {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    class.as_str()
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || rejected.load(Ordering::SeqCst))
+            .expect("The bulk rpc beyond the limit was not rejected");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+#[test]
+fn test_rpc_stream() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        // Larger than the stream receive window, so that the body arrives in several chunks.
+        const BODY_SIZE: usize = 10_000_000;
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let received = Arc::new(AtomicBool::new(false));
+
+        let received_clone = received.clone();
+        let stream_from_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let received = received_clone.clone();
+            async move {
+                loop {
+                    let request = Request::builder()
+                        .uri("/Stream")
+                        .body(Bytes::new())
+                        .unwrap();
+                    if let Ok(response) = transport.rpc_stream(&NODE_2, request).await {
+                        let chunks: Vec<Bytes> = response.into_body().try_collect().await.unwrap();
+                        assert!(chunks.len() > 1);
+                        assert_eq!(chunks.concat(), vec![7; BODY_SIZE]);
+                        received.store(true, Ordering::SeqCst);
+                    }
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            stream_from_node_2,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route(
+                "/Stream",
+                axum::routing::any(|| async { Bytes::from(vec![7; BODY_SIZE]) }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || received.load(Ordering::SeqCst))
+            .expect("The streamed response was not received");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+async fn echo_channel(Extension(upgrade): Extension<ChannelUpgrade>) -> StatusCode {
+    let channel = upgrade.accept();
+    tokio::spawn(async move {
+        if let Ok(mut channel) = channel.await {
+            while let Some(Ok(msg)) = channel.stream.next().await {
+                if channel.sink.send(msg).await.is_err() {
+                    break;
+                }
+            }
+        }
+    });
+    StatusCode::OK
+}
+
+#[test]
+fn test_channel() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let echoed = Arc::new(AtomicBool::new(false));
+
+        let echoed_clone = echoed.clone();
+        let talk_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let echoed = echoed_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let Ok(mut channel) = transport
+                        .open_channel(&NODE_2, Uri::from_static("/Echo"))
+                        .await
+                    else {
+                        continue;
+                    };
+
+                    // Several messages are exchanged over the same channel.
+                    for i in 0..3u8 {
+                        let msg = Bytes::from(vec![i; 1000]);
+                        channel.sink.send(msg.clone()).await.unwrap();
+                        assert_eq!(channel.stream.next().await.unwrap().unwrap(), msg);
+                    }
+
+                    // Channels to routes that do not exist are rejected.
+                    assert!(matches!(
+                        transport
+                            .open_channel(&NODE_2, Uri::from_static("/Unknown"))
+                            .await,
+                        Err(SendError::HandlerError(_))
+                    ));
+
+                    echoed.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            talk_to_node_2,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route("/Echo", axum::routing::any(echo_channel))),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || echoed.load(Ordering::SeqCst))
+            .expect("Messages were not echoed over the channel");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+#[test]
+fn test_push_unreliable() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let received = Arc::new(AtomicBool::new(false));
+
+        let push_to_node_2 = |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder()
+                        .uri("/Advert")
+                        .body(Bytes::from(vec![1; 100]))
+                        .unwrap();
+                    let _ = transport.push_unreliable(&NODE_2, request).await;
+
+                    // Requests that do not fit into a datagram are rejected.
+                    let request = Request::builder()
+                        .uri("/Advert")
+                        .body(Bytes::from(vec![1; DEFAULT_MAX_DATAGRAM_SIZE]))
+                        .unwrap();
+                    assert!(transport.push_unreliable(&NODE_2, request).await.is_err());
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            push_to_node_2,
+        );
+
+        let received_clone = received.clone();
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route(
+                "/Advert",
+                axum::routing::any(move |body: Bytes| {
+                    let received = received_clone.clone();
+                    async move {
+                        assert_eq!(body, vec![1; 100]);
+                        received.store(true, Ordering::SeqCst);
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || received.load(Ordering::SeqCst))
+            .expect("The datagram was not received");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that datagrams arriving while too many others are being handled are dropped.
+#[test]
+fn test_inflight_datagram_limit() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let config = QuicTransportConfig {
+            max_inflight_datagrams_per_peer: 2,
+            ..Default::default()
+        };
+        let handled = Arc::new(AtomicUsize::new(0));
+
+        let push_to_node_2 = |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder()
+                        .uri("/Stuck")
+                        .body(Bytes::from(vec![1; 100]))
+                        .unwrap();
+                    let _ = transport.push_unreliable(&NODE_2, request).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            push_to_node_2,
+        );
+
+        let handled_clone = handled.clone();
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Stuck",
+                axum::routing::any(move || {
+                    let handled = handled_clone.clone();
+                    async move {
+                        handled.fetch_add(1, Ordering::SeqCst);
+                        // Never completes, so the datagram stays in flight.
+                        std::future::pending::<()>().await;
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || handled.load(Ordering::SeqCst) == 2)
+            .expect("The datagrams were not received");
+        wait_for_timeout(
+            &mut sim,
+            || handled.load(Ordering::SeqCst) > 2,
+            Duration::from_secs(3),
+        )
+        .expect("Datagrams beyond the limit were handled");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+#[test]
+fn test_peer_stats() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let got_stats = Arc::new(AtomicBool::new(false));
+
+        let got_stats_clone = got_stats.clone();
+        let check_stats = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let got_stats = got_stats_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    // Peers that are not connected have no stats.
+                    assert!(transport.peer_stats(&NODE_3).is_none());
+                    if let Some(stats) = transport.peer_stats(&NODE_2) {
+                        assert!(stats.sent_packets > 0);
+                        assert!((0.0..=1.0).contains(&stats.loss_rate));
+                        assert_eq!(transport.peer_rtt(&NODE_2), Some(stats.rtt));
+                        got_stats.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            check_stats,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || got_stats.load(Ordering::SeqCst))
+            .expect("No stats for the connected peer");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+#[test]
+fn test_broadcast_rpc() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2, NODE_3]);
+        let broadcasted = Arc::new(AtomicBool::new(false));
+
+        let broadcasted_clone = broadcasted.clone();
+        let broadcast_to_peers = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let broadcasted = broadcasted_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if transport.peers().len() != 2 {
+                        continue;
+                    }
+
+                    let request = Request::builder()
+                        .uri("/Broadcast")
+                        .body(Bytes::from("ping"))
+                        .unwrap();
+                    let results: HashMap<_, _> = transport
+                        .broadcast_rpc(request, Duration::from_secs(1))
+                        .await
+                        .into_iter()
+                        .collect();
+
+                    // Node 3 responds too slowly.
+                    assert_eq!(results.len(), 2);
+                    assert_eq!(results[&NODE_2].as_ref().unwrap().body(), "pong");
+                    assert!(matches!(results[&NODE_3], Err(SendError::Timeout)));
+
+                    broadcasted.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            broadcast_to_peers,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(
+                ConnectivityChecker::router()
+                    .route("/Broadcast", axum::routing::any(|| async { "pong" })),
+            ),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_3,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route(
+                "/Broadcast",
+                axum::routing::any(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    "pong"
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_3, RegistryVersion::from(4))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || broadcasted.load(Ordering::SeqCst))
+            .expect("The broadcast did not complete");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that `rpc_any` with a round robin selector alternates between the connected peers.
+#[test]
+fn test_rpc_any() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2, NODE_3]);
+        let selected = Arc::new(AtomicBool::new(false));
+
+        let selected_clone = selected.clone();
+        let rpc_any = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let selected = selected_clone.clone();
+            async move {
+                let selector = PeerSelector::round_robin();
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    if transport.peers().len() != 2 {
+                        continue;
+                    }
+
+                    let mut bodies = Vec::new();
+                    for _ in 0..2 {
+                        let request = Request::builder().uri("/Any").body(Bytes::new()).unwrap();
+                        let response = transport.rpc_any(request, &selector).await.unwrap();
+                        bodies.push(response.into_body());
+                    }
+                    bodies.sort();
+                    assert_eq!(bodies, vec!["node 2", "node 3"]);
+
+                    selected.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            rpc_any,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(
+                ConnectivityChecker::router()
+                    .route("/Any", axum::routing::any(|| async { "node 2" })),
+            ),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_3,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(
+                ConnectivityChecker::router()
+                    .route("/Any", axum::routing::any(|| async { "node 3" })),
+            ),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_3, RegistryVersion::from(4))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || selected.load(Ordering::SeqCst))
+            .expect("The rpcs were not sent to both peers");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that rpcs with a deadline time out and that the handler sees the deadline.
+#[test]
+fn test_rpc_deadline() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let handler_saw_deadline = Arc::new(AtomicBool::new(false));
+
+        let timed_out_clone = timed_out.clone();
+        let rpc_with_deadline = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let timed_out = timed_out_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let mut request = Request::builder().uri("/Slow").body(Bytes::new()).unwrap();
+                    request
+                        .extensions_mut()
+                        .insert(Deadline::after(Duration::from_secs(1)));
+                    if let Err(SendError::Timeout) = transport.rpc(&NODE_2, request).await {
+                        timed_out.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            rpc_with_deadline,
+        );
+
+        let handler_saw_deadline_clone = handler_saw_deadline.clone();
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route(
+                "/Slow",
+                axum::routing::any(move |Extension(deadline): Extension<Deadline>| {
+                    let handler_saw_deadline = handler_saw_deadline_clone.clone();
+                    async move {
+                        assert!(deadline.remaining() <= Duration::from_secs(1));
+                        handler_saw_deadline.store(true, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || {
+            timed_out.load(Ordering::SeqCst) && handler_saw_deadline.load(Ordering::SeqCst)
+        })
+        .expect("The rpc did not time out");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that the deadline of an rpc also bounds the time it waits for the bandwidth limit.
+#[test]
+fn test_rpc_deadline_bounds_throttling() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(20))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let config = QuicTransportConfig {
+            announce_features: true,
+            rate_limit: Some(RateLimitConfig {
+                bytes_per_second: 1_000,
+                burst_bytes: 1_000,
+            }),
+            ..Default::default()
+        };
+        let timed_out = Arc::new(AtomicBool::new(false));
+
+        let timed_out_clone = timed_out.clone();
+        let throttled_rpc_to_node_2 = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let timed_out = timed_out_clone.clone();
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                // Sending the body takes several seconds at the configured bandwidth.
+                let mut request = Request::builder()
+                    .uri("/Throttled")
+                    .body(Bytes::from(vec![1; 5_000]))
+                    .unwrap();
+                request
+                    .extensions_mut()
+                    .insert(Deadline::after(Duration::from_secs(1)));
+                let start = tokio::time::Instant::now();
+                let response = transport.rpc(&NODE_2, request).await;
+                assert!(matches!(response, Err(SendError::Timeout)));
+                assert!(start.elapsed() < Duration::from_secs(2));
+                timed_out.store(true, Ordering::SeqCst);
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            throttled_rpc_to_node_2,
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route("/Throttled", axum::routing::any(|| async {}))),
+            None,
+            None,
+            None,
+            None,
+            config,
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || timed_out.load(Ordering::SeqCst))
+            .expect("The throttled rpc did not time out");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that acked pushes complete once the handler of the peer processed them, and fail if
+/// the handler rejects them.
+#[test]
+fn test_push_acked() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let processed = Arc::new(AtomicBool::new(false));
+        let acked = Arc::new(AtomicBool::new(false));
+
+        let processed_clone = processed.clone();
+        let acked_clone = acked.clone();
+        let push_acked = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let processed = processed_clone.clone();
+            let acked = acked_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder().uri("/Ack").body(Bytes::new()).unwrap();
+                    if transport
+                        .push_acked(&NODE_2, request, Duration::from_secs(1))
+                        .await
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    // The ack is only sent after the handler completed.
+                    assert!(processed.load(Ordering::SeqCst));
+
+                    let request = Request::builder()
+                        .uri("/Reject")
+                        .body(Bytes::new())
+                        .unwrap();
+                    assert!(matches!(
+                        transport
+                            .push_acked(&NODE_2, request, Duration::from_secs(1))
+                            .await,
+                        Err(SendError::HandlerError(StatusCode::BAD_REQUEST))
+                    ));
+                    acked.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            push_acked,
+        );
+
+        let processed_clone = processed.clone();
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(
+                ConnectivityChecker::router()
+                    .route(
+                        "/Ack",
+                        axum::routing::any(move || {
+                            let processed = processed_clone.clone();
+                            async move {
+                                tokio::time::sleep(Duration::from_millis(200)).await;
+                                processed.store(true, Ordering::SeqCst);
+                                "response body that is not sent back"
+                            }
+                        }),
+                    )
+                    .route(
+                        "/Reject",
+                        axum::routing::any(|| async { StatusCode::BAD_REQUEST }),
+                    ),
+            ),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || acked.load(Ordering::SeqCst)).expect("The push was not acknowledged");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that pushes to a URI with an ordered lane are handled in the order they were sent, even
+/// if handlers of earlier pushes take longer.
+#[test]
+fn test_ordered_lane() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        const NUM_PUSHES: u64 = 20;
+        let config = QuicTransportConfig {
+            announce_features: true,
+            ordered_lanes: OrderedLanes::default().with_path("/Ordered"),
+            ..Default::default()
+        };
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let ordered_pushes = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                for seq in 0..NUM_PUSHES {
+                    let request = Request::builder()
+                        .uri("/Ordered")
+                        .body(Bytes::from(seq.to_le_bytes().to_vec()))
+                        .unwrap();
+                    transport.push(&NODE_2, request).await.unwrap();
+                }
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            config,
+            ordered_pushes,
+        );
+
+        let received_clone = received.clone();
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Ordered",
+                axum::routing::any(move |body: Bytes| {
+                    let received = received_clone.clone();
+                    async move {
+                        let seq = u64::from_le_bytes(body.as_ref().try_into().unwrap());
+                        // Earlier pushes take longer, so they would be overtaken without the lane.
+                        tokio::time::sleep(Duration::from_millis(10 * (NUM_PUSHES - seq))).await;
+                        received.lock().unwrap().push(seq);
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                announce_features: true,
+                ..Default::default()
+            },
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || {
+            received.lock().unwrap().len() == NUM_PUSHES as usize
+        })
+        .expect("Not all pushes were received");
+        assert_eq!(
+            *received.lock().unwrap(),
+            (0..NUM_PUSHES).collect::<Vec<_>>()
+        );
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that pushes to ordered URIs still arrive at peers that do not announce features, and
+/// therefore do not understand lanes.
+#[test]
+fn test_ordered_lane_legacy_peer() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        const NUM_PUSHES: u64 = 20;
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let ordered_pushes = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                for seq in 0..NUM_PUSHES {
+                    let request = Request::builder()
+                        .uri("/Ordered")
+                        .body(Bytes::from(seq.to_le_bytes().to_vec()))
+                        .unwrap();
+                    transport.push(&NODE_2, request).await.unwrap();
+                }
+                loop {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+            .boxed()
+        };
+
+        // Neither node announces features, so the connection uses the legacy wire format.
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                ordered_lanes: OrderedLanes::default().with_path("/Ordered"),
+                ..Default::default()
+            },
+            ordered_pushes,
+        );
+
+        let received_clone = received.clone();
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Ordered",
+                axum::routing::any(move |body: Bytes| {
+                    let received = received_clone.clone();
+                    async move {
+                        let seq = u64::from_le_bytes(body.as_ref().try_into().unwrap());
+                        received.lock().unwrap().push(seq);
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig::default(),
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || {
+            received.lock().unwrap().len() == NUM_PUSHES as usize
+        })
+        .expect("Not all pushes were received");
+        // Without the lane, pushes can overtake each other.
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        assert_eq!(received, (0..NUM_PUSHES).collect::<Vec<_>>());
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that requests on a lane that exceed the size limit of their URI are not handled, and
+/// that later pushes are delivered on a new lane.
+#[test]
+fn test_ordered_lane_size_limit() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+
+        let oversized_push = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            async move {
+                while transport.peers().is_empty() {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                let request = Request::builder()
+                    .uri("/Ordered")
+                    .body(Bytes::from(vec![1; 1_000]))
+                    .unwrap();
+                let _ = transport.push(&NODE_2, request).await;
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder()
+                        .uri("/Ordered")
+                        .body(Bytes::from(vec![2; 10]))
+                        .unwrap();
+                    let _ = transport.push(&NODE_2, request).await;
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            None,
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                announce_features: true,
+                ordered_lanes: OrderedLanes::default().with_path("/Ordered"),
+                ..Default::default()
+            },
+            oversized_push,
+        );
+
+        let received_clone = received.clone();
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(Router::new().route(
+                "/Ordered",
+                axum::routing::any(move |body: Bytes| {
+                    let received = received_clone.clone();
+                    async move {
+                        received.lock().unwrap().push(body);
+                    }
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            QuicTransportConfig {
+                announce_features: true,
+                message_size_limits: MessageSizeLimits::default().with_prefix("/Ordered", 100),
+                ..Default::default()
+            },
+            waiter_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || !received.lock().unwrap().is_empty())
+            .expect("No push was received after the oversized one");
+        assert!(received
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|body| *body == vec![2; 10]));
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+typed_rpc!(Echo: "/typed/echo", String => String);
+// Same URI as `Echo`, but a request type the handler can not decode.
+typed_rpc!(MismatchedEcho: "/typed/echo", u8 => String);
+
+/// Test that typed rpcs are encoded, routed and decoded, and that requests of the wrong type
+/// are rejected by the peer.
+#[test]
+fn test_typed_rpc() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let done = Arc::new(AtomicBool::new(false));
+
+        let done_clone = done.clone();
+        let typed_rpcs = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let done = done_clone.clone();
+            async move {
+                let transport = TypedTransport::new(transport, BincodeCodec);
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let Ok(response) = transport.rpc::<Echo>(&NODE_2, "ping".to_string()).await
+                    else {
+                        continue;
+                    };
+                    assert_eq!(response, format!("ping from {}", NODE_1));
+                    assert!(matches!(
+                        transport.rpc::<MismatchedEcho>(&NODE_2, 0).await,
+                        Err(SendError::HandlerError(StatusCode::BAD_REQUEST))
+                    ));
+                    done.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
+
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            typed_rpcs,
+        );
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().typed_route::<Echo, _, _, _>(
+                BincodeCodec,
+                |peer_id: NodeId, request: String| async move {
+                    Ok(format!("{} from {}", request, peer_id))
+                },
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
+        );
+
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
+
+        wait_for(&mut sim, || done.load(Ordering::SeqCst)).expect("The typed rpc did not complete");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
+/// Test that the trace context of an rpc reaches the handler of the peer.
+#[test]
+fn test_trace_context() {
     with_test_replica_logger(|log| {
         info!(log, "Starting test");
-        let rt = tokio::runtime::Runtime::new().unwrap();
 
-        let (_jh, topology_watcher, mut registry_handler) =
-            create_peer_manager_and_registry_handle(rt.handle(), log.clone());
+        let mut sim = Builder::new()
+            .tick_duration(Duration::from_millis(100))
+            .simulation_duration(Duration::from_secs(10))
+            .build();
 
-        let node_crypto_1 = temp_crypto_component_with_tls_keys(&registry_handler, NODE_1);
-        let sev_handshake_1 = Arc::new(Sev::new(
-            NODE_1,
-            SUBNET_1,
-            registry_handler.registry_client.clone(),
-            log.clone(),
-        ));
-        let node_crypto_2 = temp_crypto_component_with_tls_keys(&registry_handler, NODE_2);
-        let sev_handshake_2 = Arc::new(Sev::new(
-            NODE_2,
-            SUBNET_1,
-            registry_handler.registry_client.clone(),
-            log.clone(),
-        ));
-        registry_handler.registry_client.update_to_latest_version();
+        let exit_notify = Arc::new(Notify::new());
 
-        let socket_1: SocketAddr = "127.0.1.1:4100".parse().unwrap();
-        let socket_2: SocketAddr = "127.0.2.1:4100".parse().unwrap();
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
 
-        let transport_1 = Arc::new(QuicTransport::start(
-            &log,
-            &MetricsRegistry::default(),
-            rt.handle(),
-            node_crypto_1,
-            registry_handler.registry_client.clone(),
-            sev_handshake_1,
-            NODE_1,
-            topology_watcher.clone(),
-            Either::Left::<_, DummyUdpSocket>(socket_1),
-            ConnectivityChecker::router(),
-        ));
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let traced = Arc::new(AtomicBool::new(false));
 
-        let transport_2 = Arc::new(QuicTransport::start(
-            &log,
-            &MetricsRegistry::default(),
-            rt.handle(),
-            node_crypto_2,
-            registry_handler.registry_client.clone(),
-            sev_handshake_2,
-            NODE_2,
-            topology_watcher,
-            Either::Left::<_, DummyUdpSocket>(socket_2),
-            ConnectivityChecker::router(),
-        ));
+        let traced_clone = traced.clone();
+        let rpc_with_trace_context = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let traced = traced_clone.clone();
+            async move {
+                loop {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    let context = TraceContext::new_root();
+                    let mut request = Request::builder().uri("/Trace").body(Bytes::new()).unwrap();
+                    request.extensions_mut().insert(context);
+                    let Ok(response) = transport.rpc(&NODE_2, request).await else {
+                        continue;
+                    };
+                    // The handler echoes the context it received.
+                    let expected = format!("{:x}/{:x}", context.trace_id, context.span_id);
+                    assert_eq!(response.body(), &expected);
+                    traced.store(true, Ordering::SeqCst);
+                }
+            }
+            .boxed()
+        };
 
-        registry_handler.add_node(
-            RegistryVersion::from(2),
+        add_transport_to_sim(
+            &mut sim,
+            log.clone(),
             NODE_1,
-            Some(&socket_1.ip().to_string()),
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            rpc_with_trace_context,
         );
-        registry_handler.add_node(
-            RegistryVersion::from(3),
+
+        add_transport_to_sim(
+            &mut sim,
+            log,
             NODE_2,
-            Some(&socket_2.ip().to_string()),
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router().route(
+                "/Trace",
+                axum::routing::any(|Extension(context): Extension<TraceContext>| async move {
+                    format!(
+                        "{:x}/{:x}",
+                        context.trace_id,
+                        context.parent_span_id.unwrap()
+                    )
+                }),
+            )),
+            None,
+            None,
+            None,
+            None,
+            conn_checker.check_fut(),
         );
-        registry_handler.registry_client.reload();
-        registry_handler.registry_client.update_to_latest_version();
 
-        rt.block_on(async move {
-            loop {
-                tokio::time::sleep(Duration::from_millis(250)).await;
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_1, RegistryVersion::from(2))))
+            .unwrap();
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add((NODE_2, RegistryVersion::from(3))))
+            .unwrap();
+        registry_handle.registry_client.reload();
+        registry_handle.registry_client.update_to_latest_version();
 
-                let request = Request::builder().uri("/Ping").body(Bytes::new()).unwrap();
-                let node_1_reachable_from_node_2 = transport_2.push(&NODE_1, request).await.is_ok();
-                let request = Request::builder().uri("/Ping").body(Bytes::new()).unwrap();
-                let node_2_reachable_from_node_1 = transport_1.push(&NODE_2, request).await.is_ok();
-                if node_2_reachable_from_node_1 && node_1_reachable_from_node_2 {
-                    break;
-                }
-            }
-        });
+        wait_for(&mut sim, || traced.load(Ordering::SeqCst))
+            .expect("The trace context did not reach the handler");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
     })
 }
 
-/// Test sending large message that is above our message limit. This should be rejected during the serialization step.
+/// Test that dropping an rpc future cancels the handler of the peer.
 #[test]
-fn test_sending_large_message() {
+fn test_rpc_cancellation() {
     with_test_replica_logger(|log| {
         info!(log, "Starting test");
 
         let mut sim = Builder::new()
             .tick_duration(Duration::from_millis(100))
-            .simulation_duration(Duration::from_secs(10))
+            .simulation_duration(Duration::from_secs(20))
             .build();
 
         let exit_notify = Arc::new(Notify::new());
@@ -283,16 +2529,25 @@ fn test_sending_large_message() {
             add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
 
         let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+        let gave_up = Arc::new(AtomicBool::new(false));
+        let handler_started = Arc::new(AtomicBool::new(false));
+        let handler_completed = Arc::new(AtomicBool::new(false));
 
-        // Send large message that should be reject and verify connectivity.
-        let send_large_msg_to_node_2 = |_node_id: NodeId, transport: Arc<dyn Transport>| {
+        let gave_up_clone = gave_up.clone();
+        let impatient_rpc = move |_node_id: NodeId, transport: Arc<dyn Transport>| {
+            let gave_up = gave_up_clone.clone();
             async move {
                 loop {
-                    transport
-                        .push(&NODE_2, Request::new(Bytes::from(vec![0; 50_000_000])))
-                        .await
-                        .unwrap_err();
                     tokio::time::sleep(Duration::from_millis(100)).await;
+                    let request = Request::builder().uri("/Slow").body(Bytes::new()).unwrap();
+                    // Only a timeout means that the request reached the peer.
+                    if timeout(Duration::from_secs(1), transport.rpc(&NODE_2, request))
+                        .await
+                        .is_err()
+                    {
+                        gave_up.store(true, Ordering::SeqCst);
+                        break;
+                    }
                 }
             }
             .boxed()
@@ -309,16 +2564,29 @@ fn test_sending_large_message() {
             None,
             None,
             None,
-            send_large_msg_to_node_2,
+            impatient_rpc,
         );
 
+        let handler_started_clone = handler_started.clone();
+        let handler_completed_clone = handler_completed.clone();
         add_transport_to_sim(
             &mut sim,
             log,
             NODE_2,
             registry_handle.clone(),
             topology_watcher,
-            Some(ConnectivityChecker::router()),
+            Some(ConnectivityChecker::router().route(
+                "/Slow",
+                axum::routing::any(move || {
+                    let handler_started = handler_started_clone.clone();
+                    let handler_completed = handler_completed_clone.clone();
+                    async move {
+                        handler_started.store(true, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        handler_completed.store(true, Ordering::SeqCst);
+                    }
+                }),
+            )),
             None,
             None,
             None,
@@ -335,8 +2603,18 @@ fn test_sending_large_message() {
         registry_handle.registry_client.reload();
         registry_handle.registry_client.update_to_latest_version();
 
-        wait_for_timeout(&mut sim, || false, Duration::from_secs(5))
-            .expect("The network did not reach a fully connected state after startup");
+        wait_for(&mut sim, || {
+            gave_up.load(Ordering::SeqCst) && handler_started.load(Ordering::SeqCst)
+        })
+        .expect("The rpc did not reach the peer");
+
+        // The handler would complete within 5 seconds if it was not cancelled.
+        wait_for_timeout(
+            &mut sim,
+            || handler_completed.load(Ordering::SeqCst),
+            Duration::from_secs(6),
+        )
+        .expect("The handler was not cancelled");
 
         exit_notify.notify_waiters();
         sim.run().unwrap();
@@ -661,6 +2939,105 @@ fn test_changing_subnet_membership() {
     })
 }
 
+/// Test that health checks evict connections that stopped working long before the idle
+/// timeout.
+#[test]
+fn test_health_check_evicts_dead_connection() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_secs(30))
+            .tick_duration(Duration::from_millis(100))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+
+        // Without health checks the connection would only be replaced after the idle timeout.
+        let config = QuicTransportConfig {
+            idle_timeout: Duration::from_secs(60),
+            health_check: Some(HealthCheckConfig {
+                interval: Duration::from_millis(500),
+                timeout: Duration::from_millis(500),
+                max_failures: 2,
+            }),
+            ..Default::default()
+        };
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            config.clone(),
+            conn_checker.check_fut(),
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router()),
+            None,
+            None,
+            None,
+            None,
+            config,
+            conn_checker.check_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || conn_checker.fully_connected()).unwrap();
+
+        sim.partition(NODE_1.to_string(), NODE_2.to_string());
+        info!(log, "Partitioned nodes");
+        wait_for(&mut sim, || {
+            conn_checker.disconnected_from(&NODE_1, &NODE_2)
+                && conn_checker.disconnected_from(&NODE_2, &NODE_1)
+        })
+        .expect("Nodes should be disconnected due to partitioning.");
+        // Give the health checks time to evict the connection.
+        wait_for_timeout(
+            &mut sim,
+            || conn_checker.fully_connected(),
+            Duration::from_secs(3),
+        )
+        .expect("Nodes are connected but they should be partitioned.");
+
+        info!(log, "Releasing nodes");
+        sim.release(NODE_1.to_string(), NODE_2.to_string());
+        wait_for(&mut sim, || {
+            conn_checker.connected_with_min_id(&NODE_1, &NODE_2, 1)
+                && conn_checker.connected_with_min_id(&NODE_2, &NODE_1, 1)
+        })
+        .expect("The dead connection was not replaced.");
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
 /// Test that we reconnect after AMD SEV-SNP handshake failures.
 #[test]
 fn test_transient_failing_sev() {
@@ -777,6 +3154,79 @@ fn test_transient_failing_sev() {
     })
 }
 
+/// Test that peers failing the attestation stay connected if it is optional or disabled.
+#[test]
+fn test_optional_attestation() {
+    with_test_replica_logger(|log| {
+        info!(log, "Starting test");
+
+        let mut sim = Builder::new()
+            .simulation_duration(Duration::from_secs(20))
+            .tick_duration(Duration::from_millis(100))
+            .build();
+
+        let exit_notify = Arc::new(Notify::new());
+
+        let (peer_manager_cmd_sender, topology_watcher, registry_handle) =
+            add_peer_manager_to_sim(&mut sim, exit_notify.clone(), log.clone());
+
+        let conn_checker = ConnectivityChecker::new(&[NODE_1, NODE_2]);
+
+        // Rejects all peers.
+        let sev = Arc::new(PeerRestrictedSevHandshake::new());
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log.clone(),
+            NODE_1,
+            registry_handle.clone(),
+            topology_watcher.clone(),
+            Some(ConnectivityChecker::router()),
+            None,
+            Some(sev.clone()),
+            None,
+            None,
+            QuicTransportConfig {
+                attestation: AttestationMode::Optional,
+                ..Default::default()
+            },
+            conn_checker.check_fut(),
+        );
+
+        add_transport_to_sim_with_config(
+            &mut sim,
+            log,
+            NODE_2,
+            registry_handle.clone(),
+            topology_watcher,
+            Some(ConnectivityChecker::router()),
+            None,
+            Some(sev),
+            None,
+            None,
+            QuicTransportConfig {
+                attestation: AttestationMode::Disabled,
+                ..Default::default()
+            },
+            conn_checker.check_fut(),
+        );
+
+        add_nodes_to_topology(
+            &peer_manager_cmd_sender,
+            &registry_handle,
+            &[
+                (NODE_1, RegistryVersion::from(2)),
+                (NODE_2, RegistryVersion::from(3)),
+            ],
+        );
+
+        wait_for(&mut sim, || conn_checker.fully_connected()).unwrap();
+
+        exit_notify.notify_waiters();
+        sim.run().unwrap();
+    })
+}
+
 /// Test that we reconnect after TLS handshake failures.
 #[test]
 fn test_transient_failing_tls() {
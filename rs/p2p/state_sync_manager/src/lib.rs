@@ -41,6 +41,8 @@ use tokio_util::sync::CancellationToken;
 
 use crate::ongoing::start_ongoing_state_sync;
 
+pub use routes::STATE_SYNC_CHUNK_PATH;
+
 mod metrics;
 mod ongoing;
 mod routes;
@@ -224,18 +226,14 @@ impl<T: 'static + Send> StateSyncManager<T> {
 
         for state_id in available_states {
             // Unreliable broadcast of adverts to all current peers.
-            for (peer_id, _) in transport.peers() {
-                let request = build_advert_handler_request(state_id.clone());
-                let transport_c = transport.clone();
+            let request = build_advert_handler_request(state_id);
+            let transport_c = transport.clone();
 
-                rt.spawn(async move {
-                    tokio::time::timeout(
-                        ADVERT_BROADCAST_TIMEOUT,
-                        transport_c.push(&peer_id, request),
-                    )
+            rt.spawn(async move {
+                transport_c
+                    .broadcast(request, ADVERT_BROADCAST_TIMEOUT)
                     .await
-                });
-            }
+            });
         }
     }
 }
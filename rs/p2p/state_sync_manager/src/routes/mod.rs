@@ -5,7 +5,8 @@ pub(crate) use advert::{
     build_advert_handler_request, state_sync_advert_handler, StateSyncAdvertHandler,
     STATE_SYNC_ADVERT_PATH,
 };
+pub use chunk::STATE_SYNC_CHUNK_PATH;
 pub(crate) use chunk::{
     build_chunk_handler_request, parse_chunk_handler_response, state_sync_chunk_handler,
-    StateSyncChunkHandler, STATE_SYNC_CHUNK_PATH,
+    StateSyncChunkHandler,
 };
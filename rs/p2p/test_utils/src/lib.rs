@@ -1,6 +1,5 @@
 use axum::{http::Request, Router};
 use bytes::Bytes;
-use either::Either;
 use futures::{
     future::{join_all, BoxFuture},
     FutureExt,
@@ -17,7 +16,7 @@ use ic_protobuf::registry::{
     node::v1::{ConnectionEndpoint, NodeRecord},
     subnet::v1::SubnetRecord,
 };
-use ic_quic_transport::{ConnId, DummyUdpSocket, QuicTransport, SubnetTopology, Transport};
+use ic_quic_transport::{ConnId, QuicTransportBuilder, SubnetTopology, Transport};
 use ic_registry_client_fake::FakeRegistryClient;
 use ic_registry_keys::make_node_record_key;
 use ic_registry_local_registry::LocalRegistry;
@@ -207,18 +206,20 @@ pub fn fully_connected_localhost_subnet(
 
         let socket: SocketAddr = format!("127.1.{id}.{}:4100", i + 1).parse().unwrap();
 
-        let transport = Arc::new(QuicTransport::start(
-            &log,
-            &MetricsRegistry::default(),
-            rt,
-            node_crypto,
-            registry_handler.registry_client.clone(),
-            sev_handshake,
-            node,
-            topology_watcher.clone(),
-            Either::Left::<_, DummyUdpSocket>(socket),
-            router,
-        )) as Arc<_>;
+        let transport = Arc::new(
+            QuicTransportBuilder::new(
+                &log,
+                &MetricsRegistry::default(),
+                node,
+                topology_watcher.clone(),
+            )
+            .tls_config(node_crypto)
+            .registry_client(registry_handler.registry_client.clone())
+            .sev_handshake(sev_handshake)
+            .socket_addr(socket)
+            .router(router)
+            .build_and_start(rt),
+        ) as Arc<_>;
         registry_handler.add_node(
             RegistryVersion::from(i as u64 + 1),
             node,
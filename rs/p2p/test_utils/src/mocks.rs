@@ -1,12 +1,16 @@
+use std::time::Duration;
+
 use crate::consensus::U64Artifact;
 use async_trait::async_trait;
-use axum::http::{Request, Response};
+use axum::http::{Request, Response, Uri};
 use bytes::Bytes;
 use ic_interfaces::p2p::{
     consensus::{PriorityFnAndFilterProducer, ValidatedPoolReader},
     state_sync::{AddChunkError, Chunk, ChunkId, Chunkable, StateSyncArtifactId, StateSyncClient},
 };
-use ic_quic_transport::{ConnId, SendError, Transport};
+use ic_quic_transport::{
+    Channel, ConnId, PeerSelector, PeerStats, ProtocolVersion, ResponseStream, SendError, Transport,
+};
 use ic_types::artifact::PriorityFn;
 use ic_types::NodeId;
 use mockall::mock;
@@ -43,13 +47,60 @@ mock! {
             request: Request<Bytes>,
         ) -> Result<Response<Bytes>, SendError>;
 
+        async fn rpc_stream(
+            &self,
+            peer_id: &NodeId,
+            request: Request<Bytes>,
+        ) -> Result<Response<ResponseStream>, SendError>;
+
         async fn push(
             &self,
             peer_id: &NodeId,
             request: Request<Bytes>,
         ) -> Result<(), SendError>;
 
+        async fn push_acked(
+            &self,
+            peer_id: &NodeId,
+            request: Request<Bytes>,
+            timeout: Duration,
+        ) -> Result<(), SendError>;
+
+        async fn push_unreliable(
+            &self,
+            peer_id: &NodeId,
+            request: Request<Bytes>,
+        ) -> Result<(), SendError>;
+
+        async fn open_channel(&self, peer_id: &NodeId, uri: Uri) -> Result<Channel, SendError>;
+
+        async fn broadcast(
+            &self,
+            request: Request<Bytes>,
+            timeout: Duration,
+        ) -> Vec<(NodeId, Result<(), SendError>)>;
+
+        async fn broadcast_rpc(
+            &self,
+            request: Request<Bytes>,
+            timeout: Duration,
+        ) -> Vec<(NodeId, Result<Response<Bytes>, SendError>)>;
+
+        async fn rpc_any(
+            &self,
+            request: Request<Bytes>,
+            selector: &PeerSelector,
+        ) -> Result<Response<Bytes>, SendError>;
+
         fn peers(&self) -> Vec<(NodeId, ConnId)>;
+
+        fn peer_stats(&self, peer_id: &NodeId) -> Option<PeerStats>;
+
+        fn peer_protocol(&self, peer_id: &NodeId) -> Option<ProtocolVersion>;
+
+        fn peer_rtt(&self, peer_id: &NodeId) -> Option<Duration>;
+
+        fn peer_inflight_rpcs(&self, peer_id: &NodeId) -> Option<usize>;
     }
 }
 
@@ -1,3 +1,15 @@
+//! Deterministic simulation of transport with turmoil.
+//!
+//! Turmoil runs all hosts of a simulation on a single thread with a simulated clock and
+//! network, so tests with partitions, packet loss, reordering and delays are reproducible.
+//!  - `TurmoilUdpSocket` connects `QuicTransport` to the simulated network.
+//!  - `add_peer_manager_to_sim` drives the topology watcher of all transports of the
+//!    simulation. Nodes join and leave the topology with `PeerManagerAction`s, e.g. with
+//!    `add_nodes_to_topology`.
+//!  - `add_transport_to_sim` and `add_transport_to_sim_with_config` add a node with a running
+//!    transport to the simulation.
+//!  - `wait_for`, `wait_for_timeout` and `run_simulation_for` step the simulation.
+//!
 use std::{
     future::Future,
     io::{self, IoSliceMut},
@@ -13,7 +25,6 @@ use crate::{
     RegistryConsensusHandle,
 };
 use axum::Router;
-use either::Either;
 use futures::{future::BoxFuture, FutureExt};
 use ic_artifact_manager::run_artifact_processor;
 use ic_crypto_tls_interfaces::{TlsConfig, TlsStream};
@@ -26,7 +37,7 @@ use ic_interfaces::{
 use ic_logger::ReplicaLogger;
 use ic_metrics::MetricsRegistry;
 use ic_quic_transport::SubnetTopology;
-use ic_quic_transport::{QuicTransport, Transport};
+use ic_quic_transport::{QuicTransportBuilder, QuicTransportConfig, Transport};
 use ic_state_manager::state_sync::types::StateSyncMessage;
 use ic_types::{artifact::UnvalidatedArtifactMutation, NodeId, RegistryVersion};
 use ic_types_test_utils::ids::SUBNET_1;
@@ -41,26 +52,38 @@ use tokio::{
 };
 use turmoil::Sim;
 
-struct CustomUdp {
+/// Port transport listens on in simulations.
+pub const SIM_TRANSPORT_PORT: u16 = 4100;
+
+/// UDP socket of a simulated host. Must be created and used within a turmoil host.
+pub struct TurmoilUdpSocket {
     ip: IpAddr,
     inner: turmoil::net::UdpSocket,
 }
 
-impl CustomUdp {
+impl TurmoilUdpSocket {
     const ECN: EcnCodepoint = EcnCodepoint::Ect0;
 
     pub fn new(ip: IpAddr, inner: turmoil::net::UdpSocket) -> Self {
         Self { ip, inner }
     }
+
+    /// Binds the transport port of the host that is named after `node`, as done by
+    /// `add_transport_to_sim`.
+    pub async fn bind(node: NodeId) -> io::Result<Self> {
+        let addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, SIM_TRANSPORT_PORT).into();
+        let inner = turmoil::net::UdpSocket::bind(addr).await?;
+        Ok(Self::new(turmoil::lookup(node.to_string()), inner))
+    }
 }
 
-impl std::fmt::Debug for CustomUdp {
+impl std::fmt::Debug for TurmoilUdpSocket {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CustomUdp")
+        write!(f, "TurmoilUdpSocket")
     }
 }
 
-impl AsyncUdpSocket for CustomUdp {
+impl AsyncUdpSocket for TurmoilUdpSocket {
     fn poll_send(
         &self,
         _state: &quinn::udp::UdpState,
@@ -207,6 +230,22 @@ pub enum PeerManagerAction {
     Remove((NodeId, RegistryVersion)),
 }
 
+/// Adds the nodes to the registry of the simulation and makes the registry client see the
+/// change. The topology watcher is updated once the peer manager of the simulation runs.
+pub fn add_nodes_to_topology(
+    peer_manager_cmd_sender: &mpsc::UnboundedSender<PeerManagerAction>,
+    registry_handle: &RegistryConsensusHandle,
+    nodes: &[(NodeId, RegistryVersion)],
+) {
+    for node in nodes {
+        peer_manager_cmd_sender
+            .send(PeerManagerAction::Add(*node))
+            .unwrap();
+    }
+    registry_handle.registry_client.reload();
+    registry_handle.registry_client.update_to_latest_version();
+}
+
 pub fn add_peer_manager_to_sim(
     sim: &mut Sim,
     stop_notify: Arc<Notify>,
@@ -278,7 +317,44 @@ pub fn add_transport_to_sim<F>(
 ) where
     F: Fn(NodeId, Arc<dyn Transport>) -> BoxFuture<'static, ()> + Clone + 'static,
 {
-    let node_addr: SocketAddr = (Ipv4Addr::UNSPECIFIED, 4100).into();
+    add_transport_to_sim_with_config(
+        sim,
+        log,
+        peer,
+        registry_handler,
+        topology_watcher,
+        conn_checker,
+        crypto,
+        sev,
+        state_sync_client,
+        consensus_manager,
+        // Simulated nodes all run the same transport version.
+        QuicTransportConfig {
+            announce_features: true,
+            ..Default::default()
+        },
+        post_setup_future,
+    )
+}
+
+/// Same as `add_transport_to_sim`, but starts transport with the given config.
+#[allow(clippy::type_complexity)]
+pub fn add_transport_to_sim_with_config<F>(
+    sim: &mut Sim,
+    log: ReplicaLogger,
+    peer: NodeId,
+    registry_handler: RegistryConsensusHandle,
+    topology_watcher: watch::Receiver<SubnetTopology>,
+    conn_checker: Option<Router>,
+    crypto: Option<Arc<dyn TlsConfig + Send + Sync>>,
+    sev: Option<Arc<dyn ValidateAttestedStream<Box<dyn TlsStream>> + Send + Sync>>,
+    state_sync_client: Option<Arc<dyn StateSyncClient<Message = StateSyncMessage>>>,
+    consensus_manager: Option<TestConsensus<U64Artifact>>,
+    config: QuicTransportConfig,
+    post_setup_future: F,
+) where
+    F: Fn(NodeId, Arc<dyn Transport>) -> BoxFuture<'static, ()> + Clone + 'static,
+{
     let consensus_manager = consensus_manager.map(|m| Arc::new(RwLock::new(m.clone())));
 
     let node_crypto =
@@ -303,6 +379,7 @@ pub fn add_transport_to_sim<F>(
         let post_setup_future_clone = post_setup_future.clone();
         let state_sync_client_clone = state_sync_client.clone();
         let consensus_manager_clone = consensus_manager.clone();
+        let config_clone = config.clone();
 
         async move {
             let metrics_registry = MetricsRegistry::default();
@@ -313,9 +390,7 @@ pub fn add_transport_to_sim<F>(
             );
 
             let mut router = conn_checker_clone;
-            let udp_listener = turmoil::net::UdpSocket::bind(node_addr).await.unwrap();
-            let this_ip = turmoil::lookup(peer.to_string());
-            let custom_udp = CustomUdp::new(this_ip, udp_listener);
+            let custom_udp = TurmoilUdpSocket::bind(peer).await.unwrap();
 
             let state_sync_rx = if let Some(ref state_sync) = state_sync_client_clone {
                 let (state_sync_router, state_sync_rx) = ic_state_sync_manager::build_axum_router(
@@ -349,18 +424,21 @@ pub fn add_transport_to_sim<F>(
                 None
             };
 
-            let transport = Arc::new(QuicTransport::start(
-                &log,
-                &MetricsRegistry::default(),
-                &tokio::runtime::Handle::current(),
-                node_crypto_clone,
-                registry_client,
-                sev_handshake_clone,
-                peer,
-                topology_watcher_clone.clone(),
-                Either::Right(custom_udp),
-                router.unwrap_or_default(),
-            ));
+            let transport = Arc::new(
+                QuicTransportBuilder::new(
+                    &log,
+                    &MetricsRegistry::default(),
+                    peer,
+                    topology_watcher_clone.clone(),
+                )
+                .tls_config(node_crypto_clone)
+                .registry_client(registry_client)
+                .sev_handshake(sev_handshake_clone)
+                .custom_socket(custom_udp)
+                .router(router.unwrap_or_default())
+                .config(config_clone)
+                .build_and_start(&tokio::runtime::Handle::current()),
+            );
 
             consensus_builder.run(transport.clone(), topology_watcher_clone.clone());
 
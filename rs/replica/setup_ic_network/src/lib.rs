@@ -49,8 +49,11 @@ use ic_interfaces_transport::Transport;
 use ic_logger::{info, replica_logger::ReplicaLogger};
 use ic_metrics::MetricsRegistry;
 use ic_p2p::{start_p2p, MAX_ADVERT_BUFFER};
-use ic_quic_transport::DummyUdpSocket;
-use ic_registry_client_helpers::subnet::SubnetRegistry;
+use ic_quic_transport::{
+    DummyUdpSocket, LogSamplingConfig, QuicTransportConfig, RateLimitConfig, StreamPriorities,
+    StreamPriority, TlsCertificates,
+};
+use ic_registry_client_helpers::{crypto::CryptoRegistry, subnet::SubnetRegistry};
 use ic_replicated_state::ReplicatedState;
 use ic_state_manager::state_sync::types::StateSyncMessage;
 use ic_transport::transport::create_transport;
@@ -66,7 +69,7 @@ use ic_types::{
     malicious_flags::MaliciousFlags,
     p2p::GossipAdvert,
     replica_config::ReplicaConfig,
-    NodeId, SubnetId,
+    NodeId, RegistryVersion, SubnetId,
 };
 use std::{
     collections::HashMap,
@@ -119,6 +122,24 @@ struct P2PClients {
     https_outcalls: P2PClientAndPrioFn<CanisterHttpArtifact, CanisterHttpPoolImpl>,
 }
 
+/// TLS certificates of nodes as stored in the registry, so that transport cycles connections
+/// whose certificates rotated.
+struct RegistryTlsCertificates(Arc<dyn RegistryClient>);
+
+impl TlsCertificates for RegistryTlsCertificates {
+    fn tls_certificate(
+        &self,
+        node_id: NodeId,
+        registry_version: RegistryVersion,
+    ) -> Option<Vec<u8>> {
+        self.0
+            .get_tls_certificate(node_id, registry_version)
+            .ok()
+            .flatten()
+            .map(|cert| cert.certificate_der)
+    }
+}
+
 pub type CanisterHttpAdapterClient =
     Box<dyn NonBlockingChannel<CanisterHttpRequest, Response = CanisterHttpResponse> + Send>;
 
@@ -383,11 +404,32 @@ pub fn setup_consensus_and_p2p(
         rt_handle,
         tls_config,
         registry_client.clone(),
+        Arc::new(RegistryTlsCertificates(registry_client.clone())),
         sev_handshake.clone(),
         node_id,
         topology_watcher.clone(),
         Either::<_, DummyUdpSocket>::Left(transport_addr),
         p2p_router.unwrap_or_default(),
+        QuicTransportConfig {
+            log_sampling: LogSamplingConfig {
+                connection_lifecycle: transport_config.connection_log_sample_rate,
+                request_errors: transport_config.request_error_log_sample_rate,
+            },
+            max_datagram_size: transport_config.max_datagram_size,
+            // Bulk state sync transfers should not delay consensus traffic.
+            stream_priorities: StreamPriorities::default().with_prefix(
+                ic_state_sync_manager::STATE_SYNC_CHUNK_PATH,
+                StreamPriority(-1),
+            ),
+            push_queue_capacity: transport_config.push_queue_capacity,
+            rate_limit: transport_config.peer_bandwidth_limit_bytes_per_second.map(
+                |bytes_per_second| RateLimitConfig {
+                    bytes_per_second,
+                    burst_bytes: transport_config.peer_bandwidth_burst_bytes,
+                },
+            ),
+            ..Default::default()
+        },
     ));
 
     let _state_sync_manager = ic_state_sync_manager::start_state_sync_manager(
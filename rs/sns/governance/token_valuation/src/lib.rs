@@ -1,15 +1,15 @@
 use async_trait::async_trait;
 use candid::CandidType;
-use cycles_minting_canister::IcpXdrConversionRateCertifiedResponse;
 use futures::join;
 use ic_base_types::CanisterId;
 use ic_nervous_system_common::{
     ledger::{ICRC1Ledger, IcpLedgerCanister as LedgerCanister},
-    E8, UNITS_PER_PERMYRIAD,
+    E8,
 };
+use ic_nervous_system_icp_xdr_rate::{fetch_uncertified, RateKind};
 use ic_nervous_system_runtime::{CdkRuntime, Runtime};
 use ic_nervous_system_string::clamp_debug_len;
-use ic_nns_constants::{CYCLES_MINTING_CANISTER_ID, LEDGER_CANISTER_ID as ICP_LEDGER_CANISTER_ID};
+use ic_nns_constants::LEDGER_CANISTER_ID as ICP_LEDGER_CANISTER_ID;
 use ic_sns_swap::pb::v1::{
     GetDerivedStateRequest, GetDerivedStateResponse, GetInitRequest, GetInitResponse,
 };
@@ -348,30 +348,14 @@ fn new_standard_xdrs_per_icp_client<MyRuntime: Runtime + Send + Sync>() -> impl
         for CmcBased30DayMovingAverageXdrsPerIcpClient<MyRuntime>
     {
         async fn get(&mut self) -> Result<Decimal, ValuationError> {
-            let (response,): (IcpXdrConversionRateCertifiedResponse,) =
-                MyRuntime::call_with_cleanup(
-                    CYCLES_MINTING_CANISTER_ID,
-                    // This is not in the cmc.did file (yet).
-                    "get_average_icp_xdr_conversion_rate",
-                    ((),),
-                )
+            // No need to validate a certificate, because query is not used in this case
+            // (specifically, canister A in subnet X is calling (another) canister B in (another)
+            // subnet Y).
+            let rate = fetch_uncertified::<MyRuntime>(RateKind::ThirtyDayAverage)
                 .await
-                .map_err(|err| {
-                    ValuationError::new_external(format!(
-                        "Unable to determine XDRs per ICP, because the cycles minting canister \
-                         did not reply to a get_average_icp_xdr_conversion_rate call: {:?}",
-                        err,
-                    ))
-                })?;
-
-            // No need to validate the cerificate in response, because query is not used in this
-            // case (specifically, canister A in subnet X is calling (another) canister B in
-            // (another) subnet Y).
-
-            let xdr_per_icp =
-                Decimal::from(response.data.xdr_permyriad_per_icp) * *UNITS_PER_PERMYRIAD;
+                .map_err(|err| ValuationError::new_external(err.message))?;
 
-            Ok(xdr_per_icp)
+            Ok(rate.xdrs_per_icp.into())
         }
     }
 
@@ -13,10 +13,11 @@ use candid::{
     utils::{ArgumentDecoder, ArgumentEncoder},
     Encode,
 };
-use cycles_minting_canister::IcpXdrConversionRate;
+use cycles_minting_canister::{IcpXdrConversionRate, IcpXdrConversionRateCertifiedResponse};
 use ic_base_types::PrincipalId;
 use ic_ledger_core::Tokens;
 use ic_nervous_system_common::ledger::MockICRC1Ledger;
+use ic_nns_constants::CYCLES_MINTING_CANISTER_ID;
 use ic_sns_swap::pb::v1::Init as SwapInit;
 use lazy_static::lazy_static;
 use maplit::hashmap;
@@ -184,6 +185,14 @@ async fn test_icps_per_sns_token_client() {
         fn spawn_future<F: 'static + Future<Output = ()>>(_future: F) {
             unimplemented!()
         }
+
+        fn canister_id() -> CanisterId {
+            unimplemented!()
+        }
+
+        fn canister_version() -> u64 {
+            unimplemented!()
+        }
     }
 
     let observed_icps_per_sns_token = IcpsPerSnsTokenClient::<MockRuntime>::new(*SWAP_CANISTER_ID)
@@ -289,6 +298,14 @@ async fn test_new_standard_xdrs_per_icp_client() {
         fn spawn_future<F: 'static + Future<Output = ()>>(_future: F) {
             unimplemented!()
         }
+
+        fn canister_id() -> CanisterId {
+            unimplemented!()
+        }
+
+        fn canister_version() -> u64 {
+            unimplemented!()
+        }
     }
 
     let observed_xdrs_per_icp = new_standard_xdrs_per_icp_client::<MockRuntime>()